@@ -0,0 +1,195 @@
+// Measures the time and the number of heap allocations per QoS 0 publish.
+//
+// Example:
+//
+//     cargo bench --features client --bench publish
+//
+// - `encode` encodes PUBLISH packets into a reused buffer. This should not allocate at all.
+//
+// - `client` publishes through `Client::publish` to an in-memory connection. This makes one allocation per publish,
+//   for the oneshot channel backing the future returned by `Client::publish`, and none for the packet itself.
+//
+// Payloads and topics are allocated up front as `Vec`-backed `Bytes`, so any clone of them in the publish path
+// would show up as an allocation (the first clone of such a `Bytes` promotes it to a shared allocation).
+
+use std::str::FromStr;
+
+use futures_util::{FutureExt, StreamExt};
+
+const WARM_UP: usize = 1_000;
+const ITERATIONS: usize = 100_000;
+
+static ALLOCATIONS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        std::alloc::System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn main() {
+    encode();
+    client();
+}
+
+fn encode() {
+    let mut publications = publications().map(|publication| mqtt3::proto::Packet::Publish(mqtt3::proto::Publish {
+        packet_identifier_dup_qos: mqtt3::proto::PacketIdentifierDupQoS::AtMostOnce,
+        retain: publication.retain,
+        topic_name: publication.topic_name,
+        payload: publication.payload,
+        user_properties: vec![],
+        subscription_identifiers: vec![],
+        response_topic: None,
+        correlation_data: None,
+        message_expiry: None,
+        content_type: None,
+    }));
+
+    let mut buf = bytes::BytesMut::with_capacity(1024);
+
+    measure("encode", &mut || {
+        buf.clear();
+        let packet = publications.next().expect("enough publications were generated");
+        mqtt3::proto::encode(packet, &mut buf).expect("couldn't encode packet");
+    });
+}
+
+fn client() {
+    let runtime =
+        tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build().expect("could not create runtime");
+
+    runtime.block_on(async {
+        let mut client = mqtt3::Client::new(
+            None,
+            None,
+            None,
+            || futures_util::future::ok::<_, std::convert::Infallible>((
+                futures_util::stream::iter(std::iter::once(Ok(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
+                    session_present: false,
+                    return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
+                }))))
+                .chain(futures_util::stream::pending()),
+                DiscardSink(bytes::BytesMut::with_capacity(1024)),
+                None,
+            )),
+            std::time::Duration::from_secs(1),
+            std::time::Duration::from_secs(60),
+        );
+
+        let event = client.next().await.expect("client stopped").expect("client failed");
+        assert_eq!(event, mqtt3::Event::NewConnection { reset_session: true });
+
+        let mut publications = publications();
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        measure("client", &mut || {
+            let publication = publications.next().expect("enough publications were generated");
+            let published = client.publish(publication);
+
+            assert!(client.poll_next_unpin(&mut cx).is_pending());
+
+            published
+                .now_or_never().expect("QoS 0 publication was not sent")
+                .expect("couldn't publish");
+        });
+    });
+}
+
+fn publications() -> impl Iterator<Item = mqtt3::proto::Publication> {
+    let publications: Vec<_> =
+        (0..(WARM_UP + ITERATIONS))
+        .map(|_| mqtt3::proto::Publication {
+            topic_name: mqtt3::proto::ByteStr::from_str("devices/bench/telemetry").expect("topic is not too long"),
+            qos: mqtt3::proto::QoS::AtMostOnce,
+            retain: false,
+            payload: vec![0x5A; 64].into(),
+            user_properties: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        })
+        .collect();
+    publications.into_iter()
+}
+
+fn measure(name: &str, f: &mut dyn FnMut()) {
+    for _ in 0..WARM_UP {
+        f();
+    }
+
+    let allocations = ALLOCATIONS.load(std::sync::atomic::Ordering::SeqCst);
+    let start = std::time::Instant::now();
+
+    for _ in 0..ITERATIONS {
+        f();
+    }
+
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(std::sync::atomic::Ordering::SeqCst) - allocations;
+
+    #[allow(clippy::cast_precision_loss)]
+    {
+        println!(
+            "{:<8} {:>10.1} ns/publish {:>8.2} allocations/publish",
+            name,
+            elapsed.as_nanos() as f64 / ITERATIONS as f64,
+            allocations as f64 / ITERATIONS as f64,
+        );
+    }
+}
+
+/// A `PacketSink` that encodes every packet into a reused buffer and then discards it.
+struct DiscardSink(bytes::BytesMut);
+
+impl futures_sink::Sink<mqtt3::proto::Packet> for DiscardSink {
+    type Error = mqtt3::proto::EncodeError;
+
+    fn poll_ready(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: std::pin::Pin<&mut Self>, item: mqtt3::proto::Packet) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        this.0.clear();
+        mqtt3::proto::encode(item, &mut this.0)
+    }
+
+    fn poll_flush(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        std::task::Poll::Ready(Ok(()))
+    }
+}
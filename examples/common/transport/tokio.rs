@@ -1,27 +1,5 @@
 use bytes::BufMut;
 
-#[cfg(feature = "client")]
-pub(crate) async fn connect(addr: impl tokio::net::ToSocketAddrs) -> std::io::Result<(impl mqtt3::io::PacketStream, impl mqtt3::io::PacketSink)> {
-    let stream = tokio::net::TcpStream::connect(addr).await?;
-
-    let stream = stream.into_std()?;
-    let sink = stream.try_clone()?;
-
-    let stream = IoStream {
-        io: tokio::net::TcpStream::from_std(stream)?,
-        decoder: Default::default(),
-        read_state: super::ReadState::WaitingForMore(bytes::BytesMut::with_capacity(1024)),
-    };
-
-    let sink = IoSink {
-        io: tokio::net::TcpStream::from_std(sink)?,
-        write_state: Default::default(),
-        buffer_timeout: Box::pin(tokio::time::sleep(super::BUFFER_TIME)),
-    };
-
-    Ok(mqtt3::io::logging(stream, sink))
-}
-
 #[cfg(feature = "server")]
 pub(crate) struct Listener(tokio::net::TcpListener);
 
@@ -31,6 +31,11 @@ fn main() {
         keep_alive: std::time::Duration::from_secs(0),
         protocol_name: mqtt3::PROTOCOL_NAME,
         protocol_level: mqtt3::PROTOCOL_LEVEL,
+        session_expiry: None,
+        will_delay: None,
+        maximum_packet_size: None,
+        authentication_method: None,
+        authentication_data: None,
     }));
     let () = stream.write_all(&*connect).unwrap();
 
@@ -43,6 +48,12 @@ fn main() {
         retain: false,
         topic_name: "foo".to_owned().try_into().unwrap(),
         payload,
+        user_properties: vec![],
+        subscription_identifiers: vec![],
+        response_topic: None,
+        correlation_data: None,
+        message_expiry: None,
+        content_type: None,
     }));
     let mut publish_buf = Vec::with_capacity(8192);
 
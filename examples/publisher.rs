@@ -33,7 +33,7 @@ struct Options {
 		help = "Maximum back-off time between reconnections to the server, in seconds.",
 		long = "max-reconnect-back-off",
 		default_value = "30",
-		parse(try_from_str = common::duration_from_secs_str),
+		parse(try_from_str = mqtt3::util::duration_from_secs_str),
 	)]
     max_reconnect_back_off: std::time::Duration,
 
@@ -41,7 +41,7 @@ struct Options {
 		help = "Keep-alive time advertised to the server, in seconds.",
 		long = "keep-alive",
 		default_value = "5",
-		parse(try_from_str = common::duration_from_secs_str),
+		parse(try_from_str = mqtt3::util::duration_from_secs_str),
 	)]
     keep_alive: std::time::Duration,
 
@@ -49,14 +49,14 @@ struct Options {
 		help = "How often to publish to the server, in milliseconds.",
 		long = "publish-frequency",
 		default_value = "1000",
-		parse(try_from_str = duration_from_millis_str),
+		parse(try_from_str = mqtt3::util::duration_from_millis_str),
 	)]
     publish_frequency: std::time::Duration,
 
     #[structopt(help = "The topic of the publications.", long = "topic")]
     topic: mqtt3::proto::ByteStr,
 
-    #[structopt(help = "The QoS of the publications.", long = "qos", parse(try_from_str = common::qos_from_str))]
+    #[structopt(help = "The QoS of the publications.", long = "qos", parse(try_from_str = mqtt3::util::qos_from_str))]
     qos: mqtt3::proto::QoS,
 
     #[structopt(help = "The payload of the publications.", long = "payload")]
@@ -85,7 +85,7 @@ async fn main() {
         move || {
             let password = password.clone();
             Box::pin(async move {
-                let (stream, sink) = common::transport::tokio::connect(server).await?;
+                let (stream, sink) = mqtt3::util::connect(server).await?;
                 Ok::<_, std::io::Error>((stream, sink, password))
             })
         },
@@ -135,6 +135,11 @@ async fn main() {
                         qos,
                         retain: false,
                         payload,
+                        user_properties: vec![],
+                        response_topic: None,
+                        correlation_data: None,
+                        message_expiry: None,
+                        content_type: None,
                     })
                     .await;
                 let () = result.expect("couldn't publish");
@@ -154,9 +159,3 @@ async fn main() {
         let _ = event.unwrap();
     }
 }
-
-fn duration_from_millis_str(
-    s: &str,
-) -> Result<std::time::Duration, <u64 as std::str::FromStr>::Err> {
-    Ok(std::time::Duration::from_millis(s.parse()?))
-}
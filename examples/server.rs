@@ -5,21 +5,39 @@
 //
 // tokio transport:
 //     cargo run --features server,transport-tokio --example server -- --bind '[::]:1883'
+//
+// tokio transport, listening on a Unix socket:
+//     cargo run --features server,transport-tokio --example server -- --bind-unix /tmp/mqtt3.sock
+//
+// tokio transport, listening on two TCP addresses and a Unix socket at once:
+//     cargo run --features server,transport-tokio --example server -- --bind '0.0.0.0:1883' --bind '[::1]:1884' --bind-unix /tmp/mqtt3.sock
 
 mod common;
 
 #[derive(Debug, structopt::StructOpt)]
 struct Options {
-    #[structopt(help = "Address of the MQTT server.", long)]
-    bind: std::net::SocketAddr,
+    #[structopt(help = "Address of the MQTT server. Can be given more than once to listen on several addresses.", long, required_unless = "bind-unix")]
+    bind: Vec<std::net::SocketAddr>,
+
+    #[structopt(help = "Path of a Unix socket to listen on, as well as any TCP addresses. Only supported with the tokio transport.", long)]
+    bind_unix: Option<std::path::PathBuf>,
 }
 
 #[cfg(feature = "transport-smol")]
 fn main() {
-    let bind = init();
-    let listener = smol::block_on(common::transport::smol::Listener::bind(bind)).expect("bind failed");
+    let Options {
+        bind,
+        bind_unix,
+    } = common::init("server");
+    assert!(bind_unix.is_none(), "--bind-unix is only supported with the tokio transport");
+
+    let mut listeners = mqtt3::io::Listeners::default();
+    for bind in bind {
+        let listener = smol::block_on(common::transport::smol::Listener::bind(bind)).expect("bind failed");
+        listeners = listeners.with_listener(listener);
+    }
 
-    let () = smol::block_on(mqtt3::server::run(listener)).expect("server failed");
+    let () = smol::block_on(mqtt3::server::run(listeners)).expect("server failed");
 }
 
 #[cfg(feature = "transport-tokio")]
@@ -31,15 +49,27 @@ fn main() {
         .build().expect("could not create runtime");
     let local_set = tokio::task::LocalSet::new();
 
-    let bind = init();
-    let listener = local_set.block_on(&runtime, common::transport::tokio::Listener::bind(bind)).expect("bind failed");
-
-    let () = local_set.block_on(&runtime, tokio::task::unconstrained(mqtt3::server::run(listener))).expect("server failed");
-}
-
-fn init() -> std::net::SocketAddr {
     let Options {
         bind,
+        bind_unix,
     } = common::init("server");
-    bind
+
+    let mut listeners = mqtt3::io::Listeners::default();
+
+    for bind in bind {
+        let listener = local_set.block_on(&runtime, common::transport::tokio::Listener::bind(bind)).expect("bind failed");
+        listeners = listeners.with_listener(listener);
+    }
+
+    #[cfg(unix)]
+    {
+        if let Some(bind_unix) = bind_unix {
+            let listener = local_set.block_on(&runtime, async { mqtt3::transport::UnixListener::bind(bind_unix) }).expect("bind failed");
+            listeners = listeners.with_listener(listener);
+        }
+    }
+    #[cfg(not(unix))]
+    assert!(bind_unix.is_none(), "--bind-unix is only supported on Unix");
+
+    let () = local_set.block_on(&runtime, tokio::task::unconstrained(mqtt3::server::run(listeners))).expect("server failed");
 }
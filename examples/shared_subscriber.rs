@@ -0,0 +1,148 @@
+// Example:
+//
+//     cargo run --features client --example shared_subscriber -- --server 127.0.0.1:1883 --group 'workers' --topic-filter 'jobs/#' --qos 1 --consumers 3
+//
+// Starts several consumers that subscribe to the same topic filter as a shared subscription, so that the server load-balances
+// the publications between them. Each publication is logged by the one consumer that received it.
+
+use futures_util::StreamExt;
+
+mod common;
+
+#[derive(Debug, structopt::StructOpt)]
+struct Options {
+    #[structopt(help = "Address of the MQTT server.", long = "server")]
+    server: std::net::SocketAddr,
+
+    #[structopt(
+        help = "Username used to authenticate with the server, if any.",
+        long = "username"
+    )]
+    username: Option<mqtt3::proto::ByteStr>,
+
+    #[structopt(
+        help = "Password used to authenticate with the server, if any.",
+        long = "password"
+    )]
+    password: Option<mqtt3::proto::ByteStr>,
+
+    #[structopt(
+		help = "Maximum back-off time between reconnections to the server, in seconds.",
+		long = "max-reconnect-back-off",
+		default_value = "30",
+		parse(try_from_str = mqtt3::util::duration_from_secs_str),
+	)]
+    max_reconnect_back_off: std::time::Duration,
+
+    #[structopt(
+		help = "Keep-alive time advertised to the server, in seconds.",
+		long = "keep-alive",
+		default_value = "5",
+		parse(try_from_str = mqtt3::util::duration_from_secs_str),
+	)]
+    keep_alive: std::time::Duration,
+
+    #[structopt(help = "The share name of the shared subscription.", long = "group")]
+    group: String,
+
+    #[structopt(help = "The topic filter to subscribe to.", long = "topic-filter")]
+    topic_filter: String,
+
+    #[structopt(help = "The QoS with which to subscribe to the topic.", long = "qos", parse(try_from_str = mqtt3::util::qos_from_str))]
+    qos: mqtt3::proto::QoS,
+
+    #[structopt(help = "The number of consumers that share the subscription.", long = "consumers", default_value = "2")]
+    consumers: usize,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let Options {
+        server,
+        username,
+        password,
+        max_reconnect_back_off,
+        keep_alive,
+        group,
+        topic_filter,
+        qos,
+        consumers,
+    } = common::init("shared_subscriber");
+
+    let topic_filter: mqtt3::proto::ByteStr = format!("$share/{}/{}", group, topic_filter)
+        .parse()
+        .expect("shared subscription topic filter is too long");
+
+    let mut consumer_tasks = vec![];
+
+    for consumer in 0..consumers {
+        let password = password.clone();
+
+        let mut client = mqtt3::Client::new(
+            None,
+            username.clone(),
+            None,
+            move || {
+                let password = password.clone();
+                Box::pin(async move {
+                    let (stream, sink) = mqtt3::util::connect(server).await?;
+                    Ok::<_, std::io::Error>((stream, sink, password))
+                })
+            },
+            max_reconnect_back_off,
+            keep_alive,
+        );
+
+        let mut shutdown_handle = client
+            .shutdown_handle()
+            .expect("couldn't get shutdown handle");
+        tokio::spawn(async move {
+            let () = tokio::signal::ctrl_c()
+                .await
+                .expect("couldn't get Ctrl-C notification");
+            let result = shutdown_handle.shutdown().await;
+            let () = result.expect("couldn't send shutdown notification");
+        });
+
+        let mut update_subscription_handle = client
+            .update_subscription_handle()
+            .expect("couldn't get subscription update handle");
+        let subscribe_to = mqtt3::proto::SubscribeTo {
+            topic_filter: topic_filter.clone(),
+            qos,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        };
+        tokio::spawn(async move {
+            let result = update_subscription_handle
+                .subscribe(subscribe_to)
+                .await;
+            if let Err(err) = result {
+                panic!("couldn't update subscription: {}", err);
+            }
+        });
+
+        consumer_tasks.push(tokio::spawn(async move {
+            while let Some(event) = client.next().await {
+                let event = event.unwrap();
+
+                if let mqtt3::Event::Publication(publication) = event {
+                    log::info!(
+                        "Consumer {} received publication: {:?} {:?} {:?}",
+                        consumer,
+                        publication.topic_name,
+                        publication.payload,
+                        publication.qos,
+                    );
+                }
+            }
+        }));
+    }
+
+    for consumer_task in consumer_tasks {
+        consumer_task.await.expect("consumer task panicked");
+    }
+}
@@ -33,7 +33,7 @@ struct Options {
 		help = "Maximum back-off time between reconnections to the server, in seconds.",
 		long = "max-reconnect-back-off",
 		default_value = "30",
-		parse(try_from_str = common::duration_from_secs_str),
+		parse(try_from_str = mqtt3::util::duration_from_secs_str),
 	)]
     max_reconnect_back_off: std::time::Duration,
 
@@ -41,14 +41,14 @@ struct Options {
 		help = "Keep-alive time advertised to the server, in seconds.",
 		long = "keep-alive",
 		default_value = "5",
-		parse(try_from_str = common::duration_from_secs_str),
+		parse(try_from_str = mqtt3::util::duration_from_secs_str),
 	)]
     keep_alive: std::time::Duration,
 
     #[structopt(help = "The topic filter to subscribe to.", long = "topic-filter")]
     topic_filter: mqtt3::proto::ByteStr,
 
-    #[structopt(help = "The QoS with which to subscribe to the topic.", long = "qos", parse(try_from_str = common::qos_from_str))]
+    #[structopt(help = "The QoS with which to subscribe to the topic.", long = "qos", parse(try_from_str = mqtt3::util::qos_from_str))]
     qos: mqtt3::proto::QoS,
 }
 
@@ -72,7 +72,7 @@ async fn main() {
         move || {
             let password = password.clone();
             Box::pin(async move {
-                let (stream, sink) = common::transport::tokio::connect(server).await?;
+                let (stream, sink) = mqtt3::util::connect(server).await?;
                 Ok::<_, std::io::Error>((stream, sink, password))
             })
         },
@@ -96,7 +96,15 @@ async fn main() {
         .expect("couldn't get subscription update handle");
     tokio::spawn(async move {
         let result = update_subscription_handle
-            .subscribe(mqtt3::proto::SubscribeTo { topic_filter, qos })
+            .subscribe(mqtt3::proto::SubscribeTo {
+                topic_filter,
+                qos,
+                user_properties: vec![],
+                subscription_identifier: None,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: Default::default(),
+            })
             .await;
         if let Err(err) = result {
             panic!("couldn't update subscription: {}", err);
@@ -109,20 +117,12 @@ async fn main() {
         let event = event.unwrap();
 
         if let mqtt3::Event::Publication(publication) = event {
-            match std::str::from_utf8(&publication.payload) {
-                Ok(s) => log::debug!(
-                    "Received publication: {:?} {:?} {:?}",
-                    publication.topic_name,
-                    s,
-                    publication.qos,
-                ),
-                Err(_) => log::debug!(
-                    "Received publication: {:?} {:?} {:?}",
-                    publication.topic_name,
-                    publication.payload,
-                    publication.qos,
-                ),
-            }
+            log::debug!(
+                "Received publication: {} {} {:?}",
+                mqtt3::proto::EscapedStr(publication.topic_name.as_ref()),
+                mqtt3::proto::EscapedBytes(&publication.payload),
+                publication.qos,
+            );
 
             packet_stats.count(1);
         }
@@ -43,7 +43,7 @@ struct Options {
 		help = "Maximum back-off time between reconnections to the server, in seconds.",
 		long = "max-reconnect-back-off",
 		default_value = "30",
-		parse(try_from_str = common::duration_from_secs_str),
+		parse(try_from_str = mqtt3::util::duration_from_secs_str),
 	)]
     max_reconnect_back_off: std::time::Duration,
 
@@ -51,14 +51,14 @@ struct Options {
 		help = "Keep-alive time advertised to the server, in seconds.",
 		long = "keep-alive",
 		default_value = "5",
-		parse(try_from_str = common::duration_from_secs_str),
+		parse(try_from_str = mqtt3::util::duration_from_secs_str),
 	)]
     keep_alive: std::time::Duration,
 
     #[structopt(help = "The topic of the will.", long = "topic")]
     topic: mqtt3::proto::ByteStr,
 
-    #[structopt(help = "The QoS of the will.", long = "qos", parse(try_from_str = common::qos_from_str))]
+    #[structopt(help = "The QoS of the will.", long = "qos", parse(try_from_str = mqtt3::util::qos_from_str))]
     qos: mqtt3::proto::QoS,
 
     #[structopt(help = "The payload of the will.", long = "payload")]
@@ -84,6 +84,11 @@ async fn main() {
         qos,
         retain: false,
         payload: payload.into(),
+        user_properties: vec![],
+        response_topic: None,
+        correlation_data: None,
+        message_expiry: None,
+        content_type: None,
     };
 
     let mut client = mqtt3::Client::new(
@@ -93,7 +98,7 @@ async fn main() {
         move || {
             let password = password.clone();
             Box::pin(async move {
-                let (stream, sink) = common::transport::tokio::connect(server).await?;
+                let (stream, sink) = mqtt3::util::connect(server).await?;
                 Ok::<_, std::io::Error>((stream, sink, password))
             })
         },
@@ -109,6 +114,11 @@ async fn main() {
             .subscribe(mqtt3::proto::SubscribeTo {
                 topic_filter: topic,
                 qos,
+                user_properties: vec![],
+                subscription_identifier: None,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: Default::default(),
             })
             .await;
         if let Err(err) = result {
@@ -120,20 +130,12 @@ async fn main() {
         let event = event.unwrap();
 
         if let mqtt3::Event::Publication(publication) = event {
-            match std::str::from_utf8(&publication.payload) {
-                Ok(s) => log::info!(
-                    "Received publication: {:?} {:?} {:?}",
-                    publication.topic_name,
-                    s,
-                    publication.qos,
-                ),
-                Err(_) => log::info!(
-                    "Received publication: {:?} {:?} {:?}",
-                    publication.topic_name,
-                    publication.payload,
-                    publication.qos,
-                ),
-            }
+            log::info!(
+                "Received publication: {} {} {:?}",
+                mqtt3::proto::EscapedStr(publication.topic_name.as_ref()),
+                mqtt3::proto::EscapedBytes(&publication.payload),
+                publication.qos,
+            );
         }
     }
 }
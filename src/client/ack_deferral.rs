@@ -0,0 +1,122 @@
+/// How long a [`Client`](super::Client) may hold back the PUBACKs of received QoS 1 publications, so that the PUBACKs of many publications
+/// are written to the connection with a single flush.
+///
+/// The PUBACKs are flushed once `max_deferred_acks` of them are waiting, or `window` after the first of them, whichever comes first.
+/// Any other packet, such as a publication or a ping, flushes the waiting PUBACKs along with it.
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AckDeferral {
+    /// How long the first waiting PUBACK may wait
+    pub window: std::time::Duration,
+
+    /// The number of waiting PUBACKs that are flushed right away
+    pub max_deferred_acks: usize,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct State {
+    ack_deferral: Option<AckDeferral>,
+
+    /// The number of PUBACKs written to the connection since the last flush
+    deferred_acks: usize,
+
+    /// Whether any other packet was written to the connection since the last flush
+    must_flush: bool,
+
+    timer: Option<super::clock::Timer>,
+    clock: super::clock::SharedClock,
+}
+
+impl State {
+    pub(super) fn set_ack_deferral(&mut self, ack_deferral: Option<AckDeferral>) {
+        self.ack_deferral = ack_deferral;
+        self.new_connection();
+    }
+
+    pub(super) fn set_clock(&mut self, clock: super::clock::SharedClock) {
+        self.clock = clock;
+    }
+
+    pub(super) fn packet_sent(&mut self, packet: &crate::proto::Packet) {
+        if let crate::proto::Packet::PubAck(_) = packet {
+            self.deferred_acks += 1;
+        }
+        else {
+            self.must_flush = true;
+        }
+    }
+
+    /// Returns whether the connection should be flushed now, or else waits for the window of the waiting PUBACKs to end
+    pub(super) fn poll_flush(&mut self, cx: &mut std::task::Context<'_>) -> bool {
+        let ack_deferral = match self.ack_deferral {
+            Some(ack_deferral) if !self.must_flush && self.deferred_acks > 0 && self.deferred_acks < ack_deferral.max_deferred_acks => ack_deferral,
+            _ => {
+                self.flushed();
+                return true;
+            },
+        };
+
+        let clock = &self.clock;
+        let timer = self.timer.get_or_insert_with(|| super::clock::Timer(clock.sleep(ack_deferral.window)));
+        match futures_util::FutureExt::poll_unpin(&mut timer.0, cx) {
+            std::task::Poll::Ready(()) => {
+                log::trace!("flushing {} deferred PUBACKs", self.deferred_acks);
+                self.flushed();
+                true
+            },
+
+            std::task::Poll::Pending => false,
+        }
+    }
+
+    pub(super) fn new_connection(&mut self) {
+        self.flushed();
+    }
+
+    fn flushed(&mut self) {
+        self.deferred_acks = 0;
+        self.must_flush = false;
+        self.timer = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn puback() -> crate::proto::Packet {
+        crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap() })
+    }
+
+    #[test]
+    fn defers_acks() {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let mut state = super::State::default();
+        state.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+        state.set_ack_deferral(Some(super::AckDeferral { window: std::time::Duration::from_secs(1), max_deferred_acks: 3 }));
+
+        // Nothing to flush
+        assert!(state.poll_flush(&mut cx));
+
+        // PUBACKs wait for the window to end
+        state.packet_sent(&puback());
+        state.packet_sent(&puback());
+        assert!(!state.poll_flush(&mut cx));
+        clock.advance_to(1);
+        assert!(state.poll_flush(&mut cx));
+
+        // ... or for enough of them
+        state.packet_sent(&puback());
+        state.packet_sent(&puback());
+        assert!(!state.poll_flush(&mut cx));
+        state.packet_sent(&puback());
+        assert!(state.poll_flush(&mut cx));
+
+        // ... or for another packet
+        state.packet_sent(&puback());
+        assert!(!state.poll_flush(&mut cx));
+        state.packet_sent(&crate::proto::Packet::PingReq(crate::proto::PingReq));
+        assert!(state.poll_flush(&mut cx));
+    }
+}
@@ -0,0 +1,202 @@
+/// Performs the client's side of the enhanced authentication of MQTT 5.0, such as SCRAM or Kerberos, which can take several
+/// rounds of challenges and responses. Added with [`Client::with_authenticator`](crate::Client::with_authenticator).
+///
+/// Each exchange starts with `step(None)`, whose data is sent in the CONNECT packet, or in the AUTH packet that starts a re-authentication.
+/// Each challenge of the server is then passed to `step` in turn, until the server accepts or refuses the client. If the server sends data
+/// along with its acceptance, such as the server's proof of SCRAM, that data is passed to `step` as well, so that the authenticator can verify it.
+///
+/// The authenticator is called from inside the client's `poll_next`, so it should not block.
+#[allow(clippy::doc_markdown)]
+pub trait Authenticator {
+    /// The authentication method, such as `SCRAM-SHA-256`
+    fn method(&self) -> crate::proto::ByteStr;
+
+    /// Takes the next step of the exchange, given the data that the server sent, or `None` to start a new exchange
+    fn step(&mut self, server_data: Option<bytes::Bytes>) -> AuthStep;
+}
+
+/// The result of a step of an [`Authenticator`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AuthStep {
+    /// Send the given data, if any, to the server, and wait for its response
+    Continue(Option<bytes::Bytes>),
+
+    /// The exchange is complete as far as the client is concerned, so nothing needs to be sent
+    Done,
+
+    /// The server could not be authenticated, or the exchange cannot continue. The client drops the connection and connects again.
+    Fail,
+}
+
+/// The enhanced authentication of the connections of a client
+#[derive(Default)]
+pub(super) struct State {
+    authenticator: Option<Box<dyn Authenticator + Send>>,
+
+    /// Whether the current connection uses MQTT 5.0, and so can exchange AUTH packets
+    enabled: bool,
+
+    /// Whether the application asked for a re-authentication that has not been started yet
+    reauthenticate: bool,
+}
+
+impl State {
+    #[cfg(feature = "v5")]
+    pub(super) fn set_authenticator(&mut self, authenticator: Box<dyn Authenticator + Send>) {
+        self.authenticator = Some(authenticator);
+    }
+
+    #[cfg(feature = "v5")]
+    pub(super) fn reauthenticate(&mut self) {
+        self.reauthenticate = true;
+    }
+
+    /// Starts the exchange of a new connection. Returns the authentication method and data to send with the CONNECT.
+    pub(super) fn start(&mut self) -> Result<(Option<crate::proto::ByteStr>, Option<bytes::Bytes>), super::Error> {
+        self.enabled = false;
+        self.reauthenticate = false;
+
+        match &mut self.authenticator {
+            Some(authenticator) => match authenticator.step(None) {
+                AuthStep::Continue(data) => Ok((Some(authenticator.method()), data)),
+                AuthStep::Done => Ok((Some(authenticator.method()), None)),
+                AuthStep::Fail => Err(super::Error::AuthenticationFailed),
+            },
+            None => Ok((None, None)),
+        }
+    }
+
+    /// Handles an AUTH packet that the server sent in the middle of an exchange. Returns the response to send to the server, if any.
+    pub(super) fn challenge(&mut self, auth: crate::proto::Auth) -> Result<Option<crate::proto::Packet>, super::Error> {
+        let authenticator = match &mut self.authenticator {
+            Some(authenticator) if auth.authentication_method == authenticator.method() => authenticator,
+            _ => return Err(super::Error::AuthenticationFailed),
+        };
+
+        match (auth.reason, authenticator.step(auth.authentication_data)) {
+            (_, AuthStep::Fail) | (crate::proto::AuthReason::ReAuthenticate, _) => Err(super::Error::AuthenticationFailed),
+
+            (crate::proto::AuthReason::ContinueAuthentication, AuthStep::Continue(authentication_data)) =>
+                Ok(Some(crate::proto::Packet::Auth(crate::proto::Auth {
+                    reason: crate::proto::AuthReason::ContinueAuthentication,
+                    authentication_method: authenticator.method(),
+                    authentication_data,
+                }))),
+
+            (crate::proto::AuthReason::ContinueAuthentication, AuthStep::Done) | (crate::proto::AuthReason::Success, _) => Ok(None),
+        }
+    }
+
+    /// Handles the data that the server sent along with the CONNACK that accepted the connection, if any
+    pub(super) fn accepted(&mut self, authentication_data: Option<bytes::Bytes>, enabled: bool) -> Result<(), super::Error> {
+        self.enabled = enabled;
+
+        match (&mut self.authenticator, authentication_data) {
+            (Some(authenticator), Some(authentication_data)) => match authenticator.step(Some(authentication_data)) {
+                AuthStep::Fail => Err(super::Error::AuthenticationFailed),
+                AuthStep::Continue(_) | AuthStep::Done => Ok(()),
+            },
+            (_, _) => Ok(()),
+        }
+    }
+
+    pub(super) fn poll(
+        &mut self,
+        packet: &mut Option<crate::proto::Packet>,
+        packets_waiting_to_be_sent: &mut std::collections::VecDeque<crate::proto::Packet>,
+    ) -> Result<(), super::Error> {
+        if let Some(crate::proto::Packet::Auth(_)) = packet {
+            if let Some(crate::proto::Packet::Auth(auth)) = packet.take() {
+                packets_waiting_to_be_sent.extend(self.challenge(auth)?);
+            }
+        }
+
+        if self.reauthenticate && self.enabled {
+            self.reauthenticate = false;
+
+            if let Some(authenticator) = &mut self.authenticator {
+                log::debug!("Re-authenticating");
+
+                let authentication_data = match authenticator.step(None) {
+                    AuthStep::Continue(authentication_data) => authentication_data,
+                    AuthStep::Done => None,
+                    AuthStep::Fail => return Err(super::Error::AuthenticationFailed),
+                };
+
+                packets_waiting_to_be_sent.push_back(crate::proto::Packet::Auth(crate::proto::Auth {
+                    reason: crate::proto::AuthReason::ReAuthenticate,
+                    authentication_method: authenticator.method(),
+                    authentication_data,
+                }));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("enabled", &self.enabled)
+            .field("reauthenticate", &self.reauthenticate)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "v5"))]
+mod tests {
+    /// Sends "client-first", then "client-final" in response to "server-first", and expects "server-final" with the CONNACK
+    struct TestAuthenticator;
+
+    impl super::Authenticator for TestAuthenticator {
+        fn method(&self) -> crate::proto::ByteStr {
+            "TEST".parse().unwrap()
+        }
+
+        fn step(&mut self, server_data: Option<bytes::Bytes>) -> super::AuthStep {
+            match server_data.as_deref() {
+                None => super::AuthStep::Continue(Some(bytes::Bytes::from_static(b"client-first"))),
+                Some(b"server-first") => super::AuthStep::Continue(Some(bytes::Bytes::from_static(b"client-final"))),
+                Some(b"server-final") => super::AuthStep::Done,
+                Some(_) => super::AuthStep::Fail,
+            }
+        }
+    }
+
+    fn auth(reason: crate::proto::AuthReason, authentication_data: &'static [u8]) -> crate::proto::Auth {
+        crate::proto::Auth {
+            reason,
+            authentication_method: "TEST".parse().unwrap(),
+            authentication_data: Some(bytes::Bytes::from_static(authentication_data)),
+        }
+    }
+
+    #[test]
+    fn exchange() {
+        let mut state = super::State::default();
+        assert_eq!(state.start().unwrap(), (None, None));
+
+        state.set_authenticator(Box::new(TestAuthenticator));
+        assert_eq!(state.start().unwrap(), (Some("TEST".parse().unwrap()), Some(bytes::Bytes::from_static(b"client-first"))));
+
+        let response = state.challenge(auth(crate::proto::AuthReason::ContinueAuthentication, b"server-first")).unwrap();
+        assert_eq!(response, Some(crate::proto::Packet::Auth(auth(crate::proto::AuthReason::ContinueAuthentication, b"client-final"))));
+
+        assert!(state.accepted(Some(bytes::Bytes::from_static(b"forged")), true).is_err());
+        state.accepted(Some(bytes::Bytes::from_static(b"server-final")), true).unwrap();
+
+        // A re-authentication is started once the connection is up
+        let mut packets_waiting_to_be_sent = Default::default();
+        state.reauthenticate();
+        state.poll(&mut None, &mut packets_waiting_to_be_sent).unwrap();
+        assert_eq!(
+            packets_waiting_to_be_sent.into_iter().collect::<Vec<_>>(),
+            [crate::proto::Packet::Auth(auth(crate::proto::AuthReason::ReAuthenticate, b"client-first"))],
+        );
+
+        let mut packet = Some(crate::proto::Packet::Auth(auth(crate::proto::AuthReason::ContinueAuthentication, b"unexpected")));
+        assert!(state.poll(&mut packet, &mut Default::default()).is_err());
+        assert!(packet.is_none());
+    }
+}
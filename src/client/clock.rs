@@ -0,0 +1,186 @@
+/// A source of time for a [`Client`](crate::Client), set with [`Client::with_clock`](crate::Client::with_clock).
+///
+/// The client reads the time from its clock for keep-alive pings, reconnection back-offs, subscription lease refreshes, the CONNACK timeout,
+/// the maximum age and pacing of queued publications, and slow consumer detection. A [`WeightedConnector`](crate::WeightedConnector)
+/// has its own clock, set with [`WeightedConnector::with_clock`](crate::WeightedConnector::with_clock), for the penalties of failing endpoints,
+/// session affinity and the latency of probes. Embedded targets without a monotonic OS clock, simulations and tests can supply their own clock.
+/// Defaults to [`TokioClock`].
+///
+/// Times are the time elapsed since a fixed point chosen by the clock, such as when it was created.
+#[allow(clippy::doc_markdown)]
+pub trait Clock: Send + Sync {
+    /// The current time. This must never go backwards.
+    fn now(&self) -> std::time::Duration;
+
+    /// Returns a future that completes once [`Clock::now`] reaches the given time
+    fn sleep_until(&self, deadline: std::time::Duration) -> ClockSleep;
+
+    /// Returns a future that completes after the given time has passed
+    fn sleep(&self, duration: std::time::Duration) -> ClockSleep {
+        self.sleep_until(self.now() + duration)
+    }
+}
+
+/// The future returned by [`Clock::sleep_until`]
+pub type ClockSleep = std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>;
+
+/// A [`ClockSleep`] held by a type that derives `Debug`
+pub(super) struct Timer(pub(super) ClockSleep);
+
+impl std::fmt::Debug for Timer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Timer")
+    }
+}
+
+/// A [`Clock`] that uses the time and timers of the tokio runtime. Times are measured from when the clock was created.
+#[derive(Clone, Copy, Debug)]
+pub struct TokioClock {
+    start: tokio::time::Instant,
+}
+
+impl Default for TokioClock {
+    fn default() -> Self {
+        TokioClock {
+            start: tokio::time::Instant::now(),
+        }
+    }
+}
+
+impl Clock for TokioClock {
+    fn now(&self) -> std::time::Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: std::time::Duration) -> ClockSleep {
+        Box::pin(tokio::time::sleep_until(self.start + deadline))
+    }
+}
+
+/// The clock of a client, shared between its parts
+#[derive(Clone)]
+pub(super) struct SharedClock(std::sync::Arc<dyn Clock>);
+
+impl SharedClock {
+    pub(super) fn new(clock: impl Clock + 'static) -> Self {
+        SharedClock(std::sync::Arc::new(clock))
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock::new(TokioClock::default())
+    }
+}
+
+impl std::ops::Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl std::fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedClock").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    /// A clock that only moves when the test advances it
+    #[derive(Clone, Default)]
+    pub(in crate::client) struct ManualClock(std::sync::Arc<std::sync::Mutex<std::time::Duration>>);
+
+    impl ManualClock {
+        pub(in crate::client) fn advance_to(&self, now: u64) {
+            *self.0.lock().unwrap() = std::time::Duration::from_secs(now);
+        }
+    }
+
+    impl super::Clock for ManualClock {
+        fn now(&self) -> std::time::Duration {
+            *self.0.lock().unwrap()
+        }
+
+        fn sleep_until(&self, deadline: std::time::Duration) -> super::ClockSleep {
+            let clock = self.clone();
+            Box::pin(futures_util::future::poll_fn(move |_| {
+                if super::Clock::now(&clock) >= deadline {
+                    std::task::Poll::Ready(())
+                }
+                else {
+                    std::task::Poll::Pending
+                }
+            }))
+        }
+    }
+
+    #[test]
+    fn keep_alive_follows_clock() {
+        fn poll(ping: &mut crate::client::ping::State, clock: &ManualClock, now: u64) -> bool {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            clock.advance_to(now);
+            ping.poll(&mut cx, &mut None, std::time::Duration::from_secs(10)).is_some()
+        }
+
+        let clock = ManualClock::default();
+        let mut ping = crate::client::ping::State::default();
+        ping.set_clock(super::SharedClock::new(clock.clone()));
+
+        assert!(!poll(&mut ping, &clock, 0));
+        assert!(!poll(&mut ping, &clock, 9));
+        assert!(poll(&mut ping, &clock, 10));
+        assert!(!poll(&mut ping, &clock, 14));
+        assert!(poll(&mut ping, &clock, 15));
+
+        // A packet sent in the meantime postpones the next ping
+        clock.advance_to(18);
+        ping.packet_sent();
+        assert!(!poll(&mut ping, &clock, 20));
+        assert!(poll(&mut ping, &clock, 23));
+    }
+
+    #[test]
+    fn keep_alive_needs_inbound_activity() {
+        fn poll(ping: &mut crate::client::ping::State, clock: &ManualClock, now: u64) -> bool {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            clock.advance_to(now);
+            ping.poll(&mut cx, &mut None, std::time::Duration::from_secs(10)).is_some()
+        }
+
+        let clock = ManualClock::default();
+        let mut ping = crate::client::ping::State::default();
+        ping.set_clock(super::SharedClock::new(clock.clone()));
+        ping.new_connection();
+
+        // Packets sent every few seconds take the place of pings as long as the server sends something back
+        for now in (2..=16).step_by(2) {
+            clock.advance_to(now);
+            ping.packet_sent();
+            if now <= 8 {
+                ping.packet_received();
+            }
+            assert!(!poll(&mut ping, &clock, now), "pinged at {}", now);
+        }
+
+        // Nothing was received since 8, so the client pings once a whole keep-alive interval has passed,
+        // and keeps pinging until the server responds
+        assert!(poll(&mut ping, &clock, 18));
+        clock.advance_to(20);
+        ping.packet_sent();
+        assert!(!poll(&mut ping, &clock, 22));
+        assert!(poll(&mut ping, &clock, 23));
+
+        clock.advance_to(24);
+        ping.packet_received();
+        clock.advance_to(26);
+        ping.packet_sent();
+        assert!(!poll(&mut ping, &clock, 28));
+    }
+}
@@ -0,0 +1,234 @@
+/// Converts between values of an application type and the payloads of publications.
+///
+/// This lets applications publish and receive values in formats such as protobuf, Avro or a custom binary format,
+/// without this crate depending on the libraries for them. Use [`PayloadCodec::publication`] to create a publication from a value,
+/// and [`crate::ReceivedPublication::decode`] to get the value back out of a received publication.
+pub trait PayloadCodec {
+    /// The type of the values that are encoded in payloads
+    type Value;
+
+    /// The error returned when a value can't be encoded or a payload can't be decoded
+    type Error: std::error::Error;
+
+    /// Encodes the given value into a payload
+    fn encode(&self, value: &Self::Value) -> Result<bytes::Bytes, Self::Error>;
+
+    /// Decodes a value from the given payload
+    fn decode(&self, payload: &bytes::Bytes) -> Result<Self::Value, Self::Error>;
+
+    /// Creates a publication whose payload is the given value
+    fn publication(
+        &self,
+        topic_name: crate::proto::ByteStr,
+        qos: crate::proto::QoS,
+        retain: bool,
+        value: &Self::Value,
+    ) -> Result<crate::proto::Publication, Self::Error> {
+        Ok(crate::proto::Publication {
+            topic_name,
+            qos,
+            retain,
+            payload: self.encode(value)?,
+            user_properties: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        })
+    }
+
+    /// Returns a stream that decodes the payloads of the publications of the given stream with this codec.
+    ///
+    /// This can be used to get a stream of typed values for a subscription, from the publications that the application
+    /// has picked out of the [`crate::Client`]'s events.
+    fn decode_publications<S>(self, publications: S) -> DecodedPublications<S, Self>
+    where
+        Self: Sized,
+        S: futures_core::Stream<Item = crate::ReceivedPublication> + Unpin,
+    {
+        DecodedPublications { publications, codec: self }
+    }
+}
+
+/// A stream of publications and the values decoded from their payloads. Returned by [`PayloadCodec::decode_publications`].
+///
+/// The publication is returned along with the result of decoding it, so that it can still be acked in manual ack mode
+/// if its payload is invalid.
+#[derive(Debug)]
+pub struct DecodedPublications<S, P> {
+    publications: S,
+    codec: P,
+}
+
+impl<S, P> futures_core::Stream for DecodedPublications<S, P>
+where
+    S: futures_core::Stream<Item = crate::ReceivedPublication> + Unpin,
+    P: PayloadCodec + Unpin,
+{
+    type Item = (crate::ReceivedPublication, Result<P::Value, P::Error>);
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        match std::pin::Pin::new(&mut this.publications).poll_next(cx) {
+            std::task::Poll::Ready(Some(publication)) => {
+                let value = publication.decode(&this.codec);
+                std::task::Poll::Ready(Some((publication, value)))
+            }
+            std::task::Poll::Ready(None) => std::task::Poll::Ready(None),
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+}
+
+/// A [`PayloadCodec`] for protobuf messages generated by `prost`. Requires the `prost` feature.
+#[cfg(feature = "prost")]
+pub struct ProstCodec<M>(std::marker::PhantomData<fn() -> M>);
+
+#[cfg(feature = "prost")]
+impl<M> ProstCodec<M> {
+    pub fn new() -> Self {
+        ProstCodec(std::marker::PhantomData)
+    }
+}
+
+#[cfg(feature = "prost")]
+impl<M> Clone for ProstCodec<M> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+#[cfg(feature = "prost")]
+impl<M> Copy for ProstCodec<M> {}
+
+#[cfg(feature = "prost")]
+impl<M> std::fmt::Debug for ProstCodec<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProstCodec")
+    }
+}
+
+#[cfg(feature = "prost")]
+impl<M> Default for ProstCodec<M> {
+    fn default() -> Self {
+        ProstCodec::new()
+    }
+}
+
+#[cfg(feature = "prost")]
+impl<M> PayloadCodec for ProstCodec<M> where M: prost::Message + Default {
+    type Value = M;
+    type Error = prost::DecodeError;
+
+    fn encode(&self, value: &M) -> Result<bytes::Bytes, Self::Error> {
+        Ok(value.encode_to_vec().into())
+    }
+
+    fn decode(&self, payload: &bytes::Bytes) -> Result<M, Self::Error> {
+        M::decode(payload.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::PayloadCodec;
+
+    struct U32Codec;
+
+    #[derive(Debug)]
+    struct WrongLength(usize);
+
+    impl std::fmt::Display for WrongLength {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "expected 4 bytes but got {}", self.0)
+        }
+    }
+
+    impl std::error::Error for WrongLength {}
+
+    impl PayloadCodec for U32Codec {
+        type Value = u32;
+        type Error = WrongLength;
+
+        fn encode(&self, value: &u32) -> Result<bytes::Bytes, Self::Error> {
+            Ok(bytes::Bytes::copy_from_slice(&value.to_be_bytes()))
+        }
+
+        fn decode(&self, payload: &bytes::Bytes) -> Result<u32, Self::Error> {
+            let payload: [u8; 4] = payload[..].try_into().map_err(|_| WrongLength(payload.len()))?;
+            Ok(u32::from_be_bytes(payload))
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let publication = U32Codec.publication("counter".parse().unwrap(), crate::proto::QoS::AtLeastOnce, false, &0x0102_0304).unwrap();
+        assert_eq!(&publication.payload[..], [0x01, 0x02, 0x03, 0x04]);
+
+        let mut received = crate::ReceivedPublication {
+            topic_name: publication.topic_name,
+            dup: false,
+            qos: publication.qos,
+            packet_identifier: None,
+            retain: publication.retain,
+            payload: publication.payload,
+            user_properties: vec![],
+            manual_ack: None,
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+        };
+        assert_eq!(received.decode(&U32Codec).unwrap(), 0x0102_0304);
+
+        received.payload = bytes::Bytes::from_static(b"abc");
+        assert_eq!(received.decode(&U32Codec).unwrap_err().to_string(), "expected 4 bytes but got 3");
+    }
+
+    #[cfg(feature = "prost")]
+    #[test]
+    fn prost_decode_publications() {
+        use futures_util::{FutureExt, StreamExt};
+
+        let codec: super::ProstCodec<String> = super::ProstCodec::new();
+
+        let publication = codec.publication("greeting".parse().unwrap(), crate::proto::QoS::AtMostOnce, false, &"hello".to_owned()).unwrap();
+        let publications = futures_util::stream::iter(vec![
+            crate::ReceivedPublication {
+                topic_name: publication.topic_name,
+                dup: false,
+                qos: publication.qos,
+                packet_identifier: None,
+                retain: publication.retain,
+                payload: publication.payload,
+                user_properties: vec![],
+                manual_ack: None,
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+            },
+            crate::ReceivedPublication {
+                topic_name: "greeting".parse().unwrap(),
+                dup: false,
+                qos: crate::proto::QoS::AtMostOnce,
+                packet_identifier: None,
+                retain: false,
+                payload: bytes::Bytes::from_static(&[0xFF]),
+                user_properties: vec![],
+                manual_ack: None,
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+            },
+        ]);
+
+        let values: Vec<_> =
+            codec.decode_publications(publications)
+            .map(|(_, value)| value.ok())
+            .collect()
+            .now_or_never().unwrap();
+        assert_eq!(values, [Some("hello".to_owned()), None]);
+    }
+}
@@ -0,0 +1,509 @@
+/// Compresses and decompresses the payloads of publications. Used by [`TopicCompression`].
+pub trait CompressionCodec {
+    /// The name of the codec. It is sent in the marker of compressed payloads, so that receivers know how to decompress them.
+    /// It must be at most 255 bytes long.
+    fn name(&self) -> &str;
+
+    /// Compresses the given payload
+    fn compress(&self, payload: &[u8]) -> std::io::Result<bytes::Bytes>;
+
+    /// Decompresses the given payload. Fails if the decompressed payload would be larger than `max_size` bytes.
+    fn decompress(&self, payload: &[u8], max_size: usize) -> std::io::Result<bytes::Bytes>;
+}
+
+/// A [`CompressionCodec`] for the deflate format. Requires the `flate2` feature.
+#[cfg(feature = "flate2")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Deflate(flate2::Compression);
+
+#[cfg(feature = "flate2")]
+impl Deflate {
+    /// Creates a codec that compresses with the given level, from 0 (no compression) to 9 (best compression)
+    pub fn new(level: u32) -> Self {
+        Deflate(flate2::Compression::new(level))
+    }
+}
+
+#[cfg(feature = "flate2")]
+impl CompressionCodec for Deflate {
+    fn name(&self) -> &'static str {
+        "deflate"
+    }
+
+    fn compress(&self, payload: &[u8]) -> std::io::Result<bytes::Bytes> {
+        use std::io::Write;
+
+        let mut encoder = flate2::write::DeflateEncoder::new(vec![], self.0);
+        encoder.write_all(payload)?;
+        Ok(encoder.finish()?.into())
+    }
+
+    fn decompress(&self, payload: &[u8], max_size: usize) -> std::io::Result<bytes::Bytes> {
+        use std::io::Read;
+
+        // Read one byte more than allowed to tell a payload of exactly max_size bytes from a larger one
+        let mut decompressed = vec![];
+        flate2::read::DeflateDecoder::new(payload).take(max_size as u64 + 1).read_to_end(&mut decompressed)?;
+        if decompressed.len() > max_size {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "decompressed payload is too large"));
+        }
+
+        Ok(decompressed.into())
+    }
+}
+
+/// Compresses the payloads of publications to some topics, such as verbose JSON topics, and decompresses received ones.
+///
+/// It is a packet interceptor pair around the connections of a [`crate::io::Connector`], created with [`TopicCompression::connector`].
+/// The sink of each connection compresses the payloads of the PUBLISH packets sent to the topics that match the topic filter of one of its codecs,
+/// and the stream decompresses the payloads of the PUBLISH packets received from them.
+///
+/// Compression is negotiated with every publication, so it works with MQTT 3.1.1 and with servers that know nothing of it.
+/// A compressed payload starts with the [`TopicCompression::MARKER`], followed by the length and name of its codec, and then the compressed payload.
+/// A receiver only decompresses a payload whose topic matches the topic filter of one of its codecs, and whose marker names that codec.
+/// Every other payload is given to the application as it was received. So publications from publishers that don't compress are understood
+/// by every receiver, and receivers that don't compress a topic, or use another codec for it, get the payloads that they can't decompress
+/// with their marker. Payloads that have the marker but can't be decompressed are given to the application as they were received too,
+/// and counted in [`TopicCompression::decompression_failures`].
+///
+/// The client only sees uncompressed publications, so its [`crate::PacketTap`] shows them uncompressed.
+///
+/// Clones share the same counts.
+#[derive(Clone)]
+pub struct TopicCompression {
+    codecs: Vec<(String, std::sync::Arc<dyn CompressionCodec + Send + Sync>)>,
+    min_size: usize,
+    max_decompressed_size: usize,
+    counts: std::sync::Arc<TopicCompressionCounts>,
+}
+
+#[derive(Debug, Default)]
+struct TopicCompressionCounts {
+    compressed: std::sync::atomic::AtomicU64,
+    decompressed: std::sync::atomic::AtomicU64,
+    decompression_failures: std::sync::atomic::AtomicU64,
+}
+
+impl TopicCompression {
+    /// The bytes that compressed payloads start with
+    pub const MARKER: &'static [u8] = b"\x00mqtt3-compression\x00";
+
+    /// Compresses the payloads of publications to topics that match the given topic filter with the given codec,
+    /// and decompresses the payloads of received publications to them that are marked with the name of the codec.
+    ///
+    /// If several topic filters match a topic, the codec that was added first is used.
+    ///
+    /// Fails if the topic filter is not valid, or if the name of the codec is longer than 255 bytes.
+    pub fn topic(
+        mut self,
+        topic_filter: impl Into<String>,
+        codec: impl CompressionCodec + Send + Sync + 'static,
+    ) -> Result<Self, TopicCompressionError> {
+        let topic_filter = topic_filter.into();
+        if !is_valid_topic_filter(&topic_filter) {
+            return Err(TopicCompressionError::InvalidTopicFilter(topic_filter));
+        }
+
+        let name_len = codec.name().len();
+        if name_len > usize::from(u8::MAX) {
+            return Err(TopicCompressionError::CodecNameTooLong(name_len));
+        }
+
+        self.codecs.push((topic_filter, std::sync::Arc::new(codec)));
+        Ok(self)
+    }
+
+    /// Sets the smallest payload that is compressed, in bytes. Defaults to 128.
+    ///
+    /// Compressing small payloads usually makes them larger.
+    #[must_use]
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the largest payload that received payloads are decompressed to, in bytes. Payloads that are larger once decompressed
+    /// are given to the application as they were received. Defaults to 268,435,455, the largest payload of an MQTT packet.
+    #[must_use]
+    pub fn with_max_decompressed_size(mut self, max_decompressed_size: usize) -> Self {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    /// Wraps the given connector, so that the payloads of the publications sent and received on its connections are compressed and decompressed
+    pub fn connector<C>(&self, inner: C) -> CompressionConnector<C> {
+        CompressionConnector {
+            inner,
+            compression: self.clone(),
+        }
+    }
+
+    /// The number of publications that were compressed
+    pub fn compressed(&self) -> u64 {
+        self.counts.compressed.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// The number of received publications that were decompressed
+    pub fn decompressed(&self) -> u64 {
+        self.counts.decompressed.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// The number of received publications that were marked with the name of the codec of their topic, but could not be decompressed
+    pub fn decompression_failures(&self) -> u64 {
+        self.counts.decompression_failures.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn codec(&self, topic_name: &crate::proto::ByteStr) -> Option<&(dyn CompressionCodec + Send + Sync)> {
+        let topic_name: &str = topic_name.as_ref();
+        self.codecs.iter()
+            .find(|(topic_filter, _)| crate::proto::topic_filter_matches(topic_filter, topic_name))
+            .map(|(_, codec)| &**codec)
+    }
+
+    fn compress(&self, publish: &mut crate::proto::Publish) {
+        let codec = if publish.payload.len() < self.min_size { None } else { self.codec(&publish.topic_name) };
+
+        match codec.map(|codec| (codec.name(), codec.compress(&publish.payload))) {
+            Some((name, Ok(compressed))) => {
+                let name = name.as_bytes();
+                let mut payload = bytes::BytesMut::with_capacity(Self::MARKER.len() + 1 + name.len() + compressed.len());
+                payload.extend_from_slice(Self::MARKER);
+                #[allow(clippy::cast_possible_truncation)] // The length of the name was checked when the codec was added
+                payload.extend_from_slice(&[name.len() as u8]);
+                payload.extend_from_slice(name);
+                payload.extend_from_slice(&compressed);
+                publish.payload = payload.freeze();
+                self.counts.compressed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            },
+
+            Some((_, Err(err))) =>
+                log::warn!("publishing uncompressed payload to {:?} because it could not be compressed: {}", publish.topic_name, err),
+
+            None => (),
+        }
+    }
+
+    fn decompress(&self, publish: &mut crate::proto::Publish) {
+        match self.marked_codec(publish).map(|(codec, compressed)| codec.decompress(compressed, self.max_decompressed_size)) {
+            Some(Ok(payload)) => {
+                publish.payload = payload;
+                self.counts.decompressed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            },
+
+            Some(Err(err)) => {
+                log::warn!("receiving compressed payload from {:?} as it is because it could not be decompressed: {}", publish.topic_name, err);
+                self.counts.decompression_failures.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            },
+
+            None => (),
+        }
+    }
+
+    /// Returns the codec of the topic of the received publication and its compressed payload, if its payload is marked with the name of the codec
+    fn marked_codec<'a>(&'a self, publish: &'a crate::proto::Publish) -> Option<(&'a (dyn CompressionCodec + Send + Sync), &'a [u8])> {
+        let codec = self.codec(&publish.topic_name)?;
+        let (&name_len, rest) = publish.payload.strip_prefix(Self::MARKER)?.split_first()?;
+        if rest.len() < usize::from(name_len) {
+            return None;
+        }
+
+        let (name, compressed) = rest.split_at(usize::from(name_len));
+        (name == codec.name().as_bytes()).then_some((codec, compressed))
+    }
+}
+
+impl Default for TopicCompression {
+    fn default() -> Self {
+        TopicCompression {
+            codecs: vec![],
+            min_size: 128,
+            max_decompressed_size: 268_435_455,
+            counts: Default::default(),
+        }
+    }
+}
+
+impl std::fmt::Debug for TopicCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TopicCompression")
+            .field("codecs", &self.codecs.iter().map(|(topic_filter, codec)| (topic_filter, codec.name())).collect::<Vec<_>>())
+            .field("min_size", &self.min_size)
+            .field("max_decompressed_size", &self.max_decompressed_size)
+            .field("counts", &self.counts)
+            .finish()
+    }
+}
+
+/// Whether the topic filter is valid, that is, its `+` and `#` wildcards take up whole levels, and `#` is only the last level
+fn is_valid_topic_filter(topic_filter: &str) -> bool {
+    let num_levels = topic_filter.split('/').count();
+    !topic_filter.is_empty() &&
+        topic_filter.split('/').enumerate().all(|(i, level)| match level {
+            "#" => i == num_levels - 1,
+            "+" => true,
+            level => !level.contains(['+', '#']),
+        })
+}
+
+#[derive(Debug)]
+pub enum TopicCompressionError {
+    CodecNameTooLong(usize),
+    InvalidTopicFilter(String),
+}
+
+impl std::fmt::Display for TopicCompressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TopicCompressionError::CodecNameTooLong(len) => write!(f, "name of compression codec is {} bytes long, more than 255 bytes", len),
+            TopicCompressionError::InvalidTopicFilter(topic_filter) => write!(f, "invalid topic filter {:?}", topic_filter),
+        }
+    }
+}
+
+impl std::error::Error for TopicCompressionError {}
+
+/// A [`crate::io::Connector`] whose connections compress and decompress the payloads of publications. Created with [`TopicCompression::connector`].
+#[derive(Debug)]
+pub struct CompressionConnector<C> {
+    inner: C,
+    compression: TopicCompression,
+}
+
+impl<C> crate::io::Connector for CompressionConnector<C> where C: crate::io::Connector {
+    type PacketStream = CompressionStream<<C as crate::io::Connector>::PacketStream>;
+    type PacketSink = CompressionSink<<C as crate::io::Connector>::PacketSink>;
+    type Error = <C as crate::io::Connector>::Error;
+    type Future = CompressionConnectorFuture<<C as crate::io::Connector>::Future>;
+
+    fn connect(&mut self) -> Self::Future {
+        CompressionConnectorFuture {
+            inner: self.inner.connect(),
+            compression: Some(self.compression.clone()),
+        }
+    }
+
+    fn redirect(&mut self, server_reference: Option<&str>) -> bool {
+        self.inner.redirect(server_reference)
+    }
+}
+
+/// The connection future of a [`CompressionConnector`]
+#[pin_project::pin_project]
+#[derive(Debug)]
+pub struct CompressionConnectorFuture<F> {
+    #[pin]
+    inner: F,
+    compression: Option<TopicCompression>,
+}
+
+impl<F, St, Si, E> std::future::Future for CompressionConnectorFuture<F>
+where
+    F: std::future::Future<Output = Result<(St, Si, Option<crate::proto::ByteStr>), E>>,
+{
+    type Output = Result<(CompressionStream<St>, CompressionSink<Si>, Option<crate::proto::ByteStr>), E>;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+        let this = self.project();
+
+        let (stream, sink, password) = match this.inner.poll(cx) {
+            std::task::Poll::Ready(Ok(connection)) => connection,
+            std::task::Poll::Ready(Err(err)) => return std::task::Poll::Ready(Err(err)),
+            std::task::Poll::Pending => return std::task::Poll::Pending,
+        };
+
+        let compression = this.compression.take().expect("polled after completion");
+        std::task::Poll::Ready(Ok((
+            CompressionStream { inner: stream, compression: compression.clone() },
+            CompressionSink { inner: sink, compression },
+            password,
+        )))
+    }
+}
+
+/// The packets received on a connection of a [`CompressionConnector`], with the payloads of publications decompressed
+#[pin_project::pin_project]
+#[derive(Debug)]
+pub struct CompressionStream<S> {
+    #[pin]
+    inner: S,
+    compression: TopicCompression,
+}
+
+impl<S> futures_core::Stream for CompressionStream<S> where S: crate::io::PacketStream {
+    type Item = Result<crate::proto::Packet, crate::proto::DecodeError>;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.project();
+
+        let mut result = this.inner.poll_next(cx);
+        if let std::task::Poll::Ready(Some(Ok(crate::proto::Packet::Publish(publish)))) = &mut result {
+            this.compression.decompress(publish);
+        }
+        result
+    }
+}
+
+/// The packets sent on a connection of a [`CompressionConnector`], with the payloads of publications compressed
+#[pin_project::pin_project]
+#[derive(Debug)]
+pub struct CompressionSink<S> {
+    #[pin]
+    inner: S,
+    compression: TopicCompression,
+}
+
+impl<S> futures_sink::Sink<crate::proto::Packet> for CompressionSink<S> where S: crate::io::PacketSink {
+    type Error = crate::proto::EncodeError;
+
+    fn poll_ready(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_ready(cx)
+    }
+
+    fn start_send(self: std::pin::Pin<&mut Self>, mut packet: crate::proto::Packet) -> Result<(), Self::Error> {
+        let this = self.project();
+
+        if let crate::proto::Packet::Publish(publish) = &mut packet {
+            this.compression.compress(publish);
+        }
+        this.inner.start_send(packet)
+    }
+
+    fn poll_flush(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        self.project().inner.poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// A codec that doesn't change the payload, with any name
+    struct Identity(&'static str);
+
+    impl super::CompressionCodec for Identity {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn compress(&self, payload: &[u8]) -> std::io::Result<bytes::Bytes> {
+            Ok(bytes::Bytes::copy_from_slice(payload))
+        }
+
+        fn decompress(&self, payload: &[u8], _max_size: usize) -> std::io::Result<bytes::Bytes> {
+            Ok(bytes::Bytes::copy_from_slice(payload))
+        }
+    }
+
+    fn publish(topic_name: &str, payload: &[u8]) -> crate::proto::Publish {
+        crate::proto::Publish {
+            packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+            retain: false,
+            topic_name: topic_name.parse().unwrap(),
+            payload: bytes::Bytes::copy_from_slice(payload),
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        }
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn topic_compression() {
+        let compression = super::TopicCompression::default().topic("json/#", super::Deflate::default()).unwrap();
+        let payload = br#"{"temperature":21.5,"humidity":40}"#.repeat(10);
+
+        let mut compressed = publish("json/a", &payload);
+        compression.compress(&mut compressed);
+        assert!(compressed.payload.starts_with(super::TopicCompression::MARKER));
+        assert!(compressed.payload.len() < payload.len());
+
+        // Topics that match no topic filter and small payloads are not compressed
+        let mut uncompressed = publish("jsonl/a", &payload);
+        compression.compress(&mut uncompressed);
+        assert_eq!(uncompressed.payload, payload);
+        let mut small = publish("json/a", b"{}");
+        compression.compress(&mut small);
+        assert_eq!(small.payload, &b"{}"[..]);
+        assert_eq!(compression.compressed(), 1);
+
+        // Receivers that don't compress the topic, or use another codec for it, get the compressed payload
+        let mut received = compressed.clone();
+        super::TopicCompression::default().topic("other/#", super::Deflate::default()).unwrap().decompress(&mut received);
+        assert_eq!(received.payload, compressed.payload);
+        super::TopicCompression::default().topic("json/#", Identity("identity")).unwrap().decompress(&mut received);
+        assert_eq!(received.payload, compressed.payload);
+
+        // Receivers that do get the original payload
+        let subscriber = super::TopicCompression::default().topic("json/+", super::Deflate::default()).unwrap();
+        subscriber.decompress(&mut received);
+        assert_eq!(received.payload, payload);
+        assert_eq!(subscriber.decompressed(), 1);
+
+        // Payloads without the marker are received as they are
+        let mut received = publish("json/a", &payload);
+        subscriber.decompress(&mut received);
+        assert_eq!(received.payload, payload);
+        assert_eq!(subscriber.decompressed(), 1);
+
+        // Payloads that are too large once decompressed are received as they are
+        let subscriber = subscriber.with_max_decompressed_size(payload.len() - 1);
+        let mut received = compressed.clone();
+        subscriber.decompress(&mut received);
+        assert_eq!(received.payload, compressed.payload);
+        assert_eq!(subscriber.decompression_failures(), 1);
+    }
+
+    #[test]
+    fn invalid_topic() {
+        for topic_filter in ["", "a/#/b", "a/b#", "a+/b"] {
+            match super::TopicCompression::default().topic(topic_filter, Identity("identity")) {
+                Err(super::TopicCompressionError::InvalidTopicFilter(_)) => (),
+                result => panic!("expected topic filter {:?} to be invalid, got {:?}", topic_filter, result),
+            }
+        }
+
+        let name = Box::leak("a".repeat(256).into_boxed_str());
+        match super::TopicCompression::default().topic("a", Identity(name)) {
+            Err(super::TopicCompressionError::CodecNameTooLong(256)) => (),
+            result => panic!("expected the name of the codec to be too long, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn connector() {
+        let compression = super::TopicCompression::default().with_min_size(0).topic("a", Identity("identity")).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let (server_send, client_recv) = futures_channel::mpsc::unbounded();
+            let (client_send, mut server_recv) = futures_channel::mpsc::unbounded();
+            let client_sink = futures_util::SinkExt::sink_map_err(client_send, |err| crate::proto::EncodeError::Io(std::io::Error::other(err)));
+            let mut connection = Some((client_recv, client_sink));
+            let mut connector = compression.connector(move || futures_util::future::ready(Ok::<_, std::io::Error>({
+                let (stream, sink) = connection.take().unwrap();
+                (stream, sink, None)
+            })));
+
+            let (mut stream, mut sink, _) = crate::io::Connector::connect(&mut connector).await.unwrap();
+
+            // The publications sent by the client are compressed
+            futures_util::SinkExt::send(&mut sink, crate::proto::Packet::Publish(publish("a", b"1"))).await.unwrap();
+            futures_util::SinkExt::send(&mut sink, crate::proto::Packet::Publish(publish("b", b"2"))).await.unwrap();
+            let compressed = [super::TopicCompression::MARKER, b"\x08identity1"].concat();
+            let sent = futures_util::StreamExt::next(&mut server_recv).await.unwrap();
+            assert_eq!(sent, crate::proto::Packet::Publish(publish("a", &compressed)));
+            let sent = futures_util::StreamExt::next(&mut server_recv).await.unwrap();
+            assert_eq!(sent, crate::proto::Packet::Publish(publish("b", b"2")));
+
+            // The publications received by the client are decompressed
+            server_send.unbounded_send(Ok(crate::proto::Packet::Publish(publish("a", &compressed)))).unwrap();
+            let received = futures_util::StreamExt::next(&mut stream).await.unwrap().unwrap();
+            assert_eq!(received, crate::proto::Packet::Publish(publish("a", b"1")));
+        });
+    }
+}
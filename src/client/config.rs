@@ -0,0 +1,143 @@
+/// Settings of a [`Client`](crate::Client) to change while it runs, sent with a [`ConfigHandle`].
+///
+/// Settings that are not set keep their current values. The client applies the update the next time it is polled,
+/// and each setting takes effect where it is next used:
+///
+/// - The keep-alive time is sent with the next CONNECT, so the current connection keeps the keep-alive it negotiated.
+/// - The maximum reconnection back-off and the reconnection jitter apply to the next back-off.
+/// - The maximum message age applies to the publications that are queued when the client next sends publications.
+/// - The publish pacing applies to the backlog of the next connection.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigUpdate(Vec<Setting>);
+
+#[derive(Clone, Debug)]
+enum Setting {
+    KeepAlive(std::time::Duration),
+    MaxReconnectBackOff(std::time::Duration),
+    ReconnectJitter(super::ReconnectJitter),
+    MaxMessageAge(Option<std::time::Duration>),
+    PublishPacing(super::PublishPacing),
+}
+
+impl ConfigUpdate {
+    /// Sets the keep-alive time advertised to the server. See [`Client::new`](crate::Client::new).
+    #[must_use]
+    pub fn with_keep_alive(mut self, keep_alive: std::time::Duration) -> Self {
+        self.0.push(Setting::KeepAlive(keep_alive));
+        self
+    }
+
+    /// Sets the maximum back-off time between reconnections to the server. See [`Client::new`](crate::Client::new).
+    #[must_use]
+    pub fn with_max_reconnect_back_off(mut self, max_reconnect_back_off: std::time::Duration) -> Self {
+        self.0.push(Setting::MaxReconnectBackOff(max_reconnect_back_off));
+        self
+    }
+
+    /// Sets how the back-off periods between reconnection attempts are randomized. See [`Client::with_reconnect_jitter`](crate::Client::with_reconnect_jitter).
+    #[must_use]
+    pub fn with_reconnect_jitter(mut self, reconnect_jitter: super::ReconnectJitter) -> Self {
+        self.0.push(Setting::ReconnectJitter(reconnect_jitter));
+        self
+    }
+
+    /// Sets the maximum time that a publication can be queued before it is sent. See [`Client::with_max_message_age`](crate::Client::with_max_message_age).
+    #[must_use]
+    pub fn with_max_message_age(mut self, max_message_age: Option<std::time::Duration>) -> Self {
+        self.0.push(Setting::MaxMessageAge(max_message_age));
+        self
+    }
+
+    /// Sets how the publications queued while the client was disconnected are sent. See [`Client::with_publish_pacing`](crate::Client::with_publish_pacing).
+    #[must_use]
+    pub fn with_publish_pacing(mut self, publish_pacing: super::PublishPacing) -> Self {
+        self.0.push(Setting::PublishPacing(publish_pacing));
+        self
+    }
+
+    pub(super) fn apply<C>(
+        self,
+        keep_alive: &mut std::time::Duration,
+        connect: &mut super::connect::Connect<C>,
+        publish: &mut super::publish::State,
+    )
+    where
+        C: crate::io::Connector,
+    {
+        for setting in self.0 {
+            log::debug!("applying configuration update {:?}", setting);
+
+            match setting {
+                Setting::KeepAlive(new_keep_alive) => *keep_alive = new_keep_alive,
+                Setting::MaxReconnectBackOff(max_reconnect_back_off) => connect.set_max_back_off(max_reconnect_back_off),
+                Setting::ReconnectJitter(reconnect_jitter) => connect.set_reconnect_jitter(reconnect_jitter),
+                Setting::MaxMessageAge(max_message_age) => publish.set_max_message_age(max_message_age),
+                Setting::PublishPacing(publish_pacing) => publish.set_publish_pacing(publish_pacing),
+            }
+        }
+    }
+}
+
+/// Used to change the settings of a [`Client`](crate::Client) while it runs. Returned by [`Client::config_handle`](crate::Client::config_handle).
+#[derive(Clone, Debug)]
+pub struct ConfigHandle(pub(super) futures_channel::mpsc::UnboundedSender<ConfigUpdate>);
+
+impl ConfigHandle {
+    /// Sends the given settings to the client, which applies them the next time it is polled
+    pub fn update(&self, update: ConfigUpdate) -> Result<(), ConfigUpdateError> {
+        self.0.unbounded_send(update).map_err(|_| ConfigUpdateError::ClientDoesNotExist)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigUpdateError {
+    ClientDoesNotExist,
+}
+
+impl std::fmt::Display for ConfigUpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigUpdateError::ClientDoesNotExist => write!(f, "client does not exist"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigUpdateError {}
+
+#[cfg(test)]
+mod tests {
+    type Connection = (
+        std::pin::Pin<Box<dyn crate::io::PacketStream + Send>>,
+        std::pin::Pin<Box<dyn crate::io::PacketSink + Send>>,
+        Option<crate::proto::ByteStr>,
+    );
+
+    #[test]
+    fn keep_alive_applies_to_next_connection() {
+        use futures_core::Stream;
+
+        // The connector never connects, so the client stays between connections
+        let mut client = crate::Client::new(
+            None,
+            None,
+            None,
+            || futures_util::future::pending::<Result<Connection, std::io::Error>>(),
+            std::time::Duration::from_secs(30),
+            std::time::Duration::from_secs(60),
+        );
+        let config_handle = client.config_handle().unwrap();
+        config_handle.update(super::ConfigUpdate::default().with_keep_alive(std::time::Duration::from_secs(10))).unwrap();
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        assert!(std::pin::Pin::new(&mut client).poll_next(&mut cx).is_pending());
+
+        match &client.0 {
+            crate::client::ClientState::Up { keep_alive, .. } => assert_eq!(*keep_alive, std::time::Duration::from_secs(10)),
+            crate::client::ClientState::ShuttingDown { .. } | crate::client::ClientState::ShutDown { .. } => panic!("client is not up"),
+        }
+
+        drop(client);
+        assert!(config_handle.update(super::ConfigUpdate::default()).is_err());
+    }
+}
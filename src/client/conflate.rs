@@ -0,0 +1,234 @@
+/// Keeps only the latest publication to each topic when the application falls behind, for feeds such as market data
+/// where only the freshest value matters.
+///
+/// [`Conflation::conflate`] wraps the events of a [`Client`](crate::Client). Whenever the application polls it, it reads all the events
+/// that are ready without waiting, up to [`Conflation::with_max_read_ahead`] of them. Of the publications read this way, only the latest one
+/// to each topic that matches one of the topic filters is kept. So an application that keeps up with the server sees every publication,
+/// and one that falls behind skips the stale ones.
+///
+/// Other events are never dropped, and publications are never conflated across them, so that the order of events is kept.
+/// Publications that are dropped are acked in manual ack mode.
+#[derive(Clone, Debug)]
+pub struct Conflation {
+    topic_filters: Vec<String>,
+    max_read_ahead: usize,
+}
+
+impl Default for Conflation {
+    fn default() -> Self {
+        Conflation {
+            topic_filters: vec![],
+            max_read_ahead: 1024,
+        }
+    }
+}
+
+impl Conflation {
+    /// Conflates publications to topics that match the given topic filter, such as `prices/#` or `prices/+/bid`
+    #[must_use]
+    pub fn topic_filter(mut self, topic_filter: impl Into<String>) -> Self {
+        self.topic_filters.push(topic_filter.into());
+        self
+    }
+
+    /// Sets the maximum number of events that are read ahead of the application. Defaults to 1024.
+    #[must_use]
+    pub fn with_max_read_ahead(mut self, max_read_ahead: usize) -> Self {
+        self.max_read_ahead = max_read_ahead;
+        self
+    }
+
+    /// Returns a stream of the given events with publications conflated
+    pub fn conflate<S, E>(self, events: S) -> Conflated<S, E> {
+        Conflated {
+            events,
+            conflation: self,
+            read_ahead: Default::default(),
+            popped: 0,
+            latest: Default::default(),
+            ended: false,
+            dropped: 0,
+        }
+    }
+
+    fn applies_to(&self, topic_name: &crate::proto::ByteStr) -> bool {
+        let topic_name: &str = topic_name.as_ref();
+        self.topic_filters.iter().any(|topic_filter| crate::proto::topic_filter_matches(topic_filter, topic_name))
+    }
+}
+
+/// A stream of events with publications conflated. Returned by [`Conflation::conflate`].
+#[derive(Debug)]
+pub struct Conflated<S, E> {
+    events: S,
+    conflation: Conflation,
+
+    /// Events that were read ahead of the application. Dropped publications are replaced with `None`.
+    read_ahead: std::collections::VecDeque<Option<Result<super::Event, E>>>,
+
+    /// The number of events popped from the front of `read_ahead` so far, so that the positions in `latest` stay valid
+    popped: usize,
+
+    /// The position of the latest publication to each conflated topic that was read ahead, counting from the first event ever read
+    latest: std::collections::HashMap<crate::proto::ByteStr, usize>,
+
+    ended: bool,
+    dropped: u64,
+}
+
+impl<S, E> Conflated<S, E> {
+    /// The number of publications dropped because a later one to the same topic was received
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Adds an event read from the inner stream to the events read ahead
+    fn read(&mut self, event: Result<super::Event, E>) {
+        let position = self.popped + self.read_ahead.len();
+
+        match &event {
+            Ok(super::Event::Publication(publication)) if self.conflation.applies_to(&publication.topic_name) => {
+                if let Some(previous) = self.latest.insert(publication.topic_name.clone(), position) {
+                    if let Some(Ok(super::Event::Publication(mut previous))) = self.read_ahead[previous - self.popped].take() {
+                        previous.ack();
+                        self.dropped += 1;
+                    }
+                }
+            },
+
+            Ok(super::Event::Publication(_)) => (),
+
+            // Don't conflate publications across other events
+            _ => self.latest.clear(),
+        }
+
+        self.read_ahead.push_back(Some(event));
+    }
+
+    /// Returns the next event that was read ahead and not dropped
+    fn pop(&mut self) -> Option<Result<super::Event, E>> {
+        while let Some(event) = self.read_ahead.pop_front() {
+            let position = self.popped;
+            self.popped += 1;
+
+            if let Some(event) = event {
+                if let Ok(super::Event::Publication(publication)) = &event {
+                    if self.latest.get(&publication.topic_name) == Some(&position) {
+                        self.latest.remove(&publication.topic_name);
+                    }
+                }
+
+                return Some(event);
+            }
+        }
+
+        None
+    }
+}
+
+impl<S, E> futures_core::Stream for Conflated<S, E>
+where
+    S: futures_core::Stream<Item = Result<super::Event, E>> + Unpin,
+    E: Unpin,
+{
+    type Item = Result<super::Event, E>;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        while !this.ended && this.read_ahead.len() < this.conflation.max_read_ahead {
+            match std::pin::Pin::new(&mut this.events).poll_next(cx) {
+                std::task::Poll::Ready(Some(event)) => this.read(event),
+                std::task::Poll::Ready(None) => this.ended = true,
+                std::task::Poll::Pending => break,
+            }
+        }
+
+        match this.pop() {
+            Some(event) => std::task::Poll::Ready(Some(event)),
+            None if this.ended => std::task::Poll::Ready(None),
+            None => std::task::Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn publication(topic_name: &str, payload: &'static [u8]) -> Result<crate::client::Event, ()> {
+        Ok(crate::client::Event::Publication(crate::ReceivedPublication {
+            topic_name: topic_name.parse().unwrap(),
+            dup: false,
+            qos: crate::proto::QoS::AtMostOnce,
+            packet_identifier: None,
+            retain: false,
+            payload: bytes::Bytes::from_static(payload),
+            user_properties: vec![],
+            manual_ack: None,
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+        }))
+    }
+
+    #[test]
+    fn keeps_latest_publication_per_topic() {
+        let events = futures_util::stream::iter(vec![
+            publication("prices/a", b"1"),
+            publication("prices/b", b"1"),
+            publication("orders", b"1"),
+            publication("prices/a", b"2"),
+            publication("orders", b"2"),
+            Ok(crate::client::Event::NewConnection { reset_session: false }),
+            publication("prices/a", b"3"),
+            Err(()),
+            publication("prices/a", b"4"),
+            publication("prices/a", b"5"),
+        ]);
+
+        let mut conflated = super::Conflation::default().topic_filter("prices/#").conflate(events);
+        let received: Vec<_> = futures_util::FutureExt::now_or_never(futures_util::StreamExt::collect(&mut conflated)).unwrap();
+
+        let expected = vec![
+            publication("prices/b", b"1"),
+            publication("orders", b"1"),
+            publication("prices/a", b"2"),
+            publication("orders", b"2"),
+            Ok(crate::client::Event::NewConnection { reset_session: false }),
+            publication("prices/a", b"3"),
+            Err(()),
+            publication("prices/a", b"5"),
+        ];
+        assert_eq!(received, expected);
+        assert_eq!(conflated.dropped(), 2);
+    }
+
+    #[test]
+    fn matches_topic_filters() {
+        let events = futures_util::stream::iter(vec![
+            publication("prices/a/bid", b"1"),
+            publication("prices/a/ask", b"1"),
+            publication("prices/a/bid/depth", b"1"),
+            publication("prices/abid", b"1"),
+            publication("prices/a/bid", b"2"),
+            publication("prices/a/ask", b"2"),
+            publication("prices/a/bid/depth", b"2"),
+            publication("prices/abid", b"2"),
+        ]);
+
+        // The wildcard matches exactly one whole topic level, so the other topics are not conflated
+        let mut conflated = super::Conflation::default().topic_filter("prices/+/bid").conflate(events);
+        let received: Vec<_> = futures_util::FutureExt::now_or_never(futures_util::StreamExt::collect(&mut conflated)).unwrap();
+
+        let expected = vec![
+            publication("prices/a/ask", b"1"),
+            publication("prices/a/bid/depth", b"1"),
+            publication("prices/abid", b"1"),
+            publication("prices/a/bid", b"2"),
+            publication("prices/a/ask", b"2"),
+            publication("prices/a/bid/depth", b"2"),
+            publication("prices/abid", b"2"),
+        ];
+        assert_eq!(received, expected);
+        assert_eq!(conflated.dropped(), 1);
+    }
+}
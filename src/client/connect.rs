@@ -1,5 +1,6 @@
 use std::future::Future;
 
+#[allow(clippy::struct_field_names)]
 #[derive(Debug)]
 pub(super) struct Connect<C>
 where
@@ -8,7 +9,48 @@ where
     connector: C,
     max_back_off: std::time::Duration,
     current_back_off: std::time::Duration,
+    reconnect_jitter: ReconnectJitter,
+    redirect: Redirect,
+
+    conn_ack_timeout: Option<std::time::Duration>,
+    conn_ack_timer: Option<super::clock::Timer>,
+
+    /// How long the transport can take to send the CONNECT, the first packet of every connection, after it connected
+    connect_send_timeout: Option<std::time::Duration>,
+    connect_send_timer: Option<super::clock::Timer>,
+
+    /// When the transport of the current connection attempt connected, to log how long it took to send the CONNECT
+    io_connected_at: std::time::Duration,
+
+    clock: super::clock::SharedClock,
+
+    /// The protocol level of the next CONNECT, which is MQTT 3.1.1 after falling back from the requested one
+    protocol_level: u8,
+    requested_protocol_level: u8,
+    protocol_fallback: ProtocolFallback,
+
+    /// How long the server keeps the session after the connection is lost, or `None` if it never expires
+    session_expiry: Option<std::time::Duration>,
+
+    /// How long the server waits before it publishes the will after the connection is lost, or `None` to publish it right away
+    will_delay: Option<std::time::Duration>,
+
+    /// The size in bytes of the largest packet that the client accepts, if it limits them
+    maximum_packet_size: Option<u32>,
+
+    /// Whether new connections are reported with [`super::Event::Connected`] instead of [`super::Event::NewConnection`]
+    connected_events: bool,
+
+    /// The client ID that is sent instead of an empty one after the server rejected the empty one
+    generated_client_id: Option<crate::proto::ByteStr>,
+
     state: State<C>,
+    tap: super::tap::Tap,
+    events: super::event_sink::Events,
+    auth: super::auth::State,
+
+    /// The response to an AUTH challenge of the server that is waiting to be sent before the CONNACK
+    auth_response: Option<crate::proto::Packet>,
 }
 
 enum State<C>
@@ -16,7 +58,7 @@ where
     C: crate::io::Connector,
 {
     BeginBackOff,
-    EndBackOff(std::pin::Pin<Box<tokio::time::Sleep>>),
+    EndBackOff(super::ClockSleep),
     BeginConnecting,
     WaitingForIoToConnect(<C as crate::io::Connector>::Future),
     Framed {
@@ -27,14 +69,20 @@ where
     },
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 enum FramedState {
     BeginSendingConnect,
     EndSendingConnect,
     WaitingForConnAck,
+    BeginSendingAuth,
+    EndSendingAuth,
     Connected {
         new_connection: bool,
         reset_session: bool,
+        connection_parameters: super::ConnectionParameters,
+
+        /// The event to report the new connection with, if connected events are enabled and it has not been reported yet
+        connected_event: Option<super::ConnectedEvent>,
     },
 }
 
@@ -65,13 +113,135 @@ where
             connector,
             max_back_off,
             current_back_off: std::time::Duration::from_secs(0),
+            reconnect_jitter: Default::default(),
+            redirect: Default::default(),
+            conn_ack_timeout: None,
+            conn_ack_timer: None,
+            connect_send_timeout: None,
+            connect_send_timer: None,
+            io_connected_at: std::time::Duration::ZERO,
+            clock: Default::default(),
+            protocol_level: crate::PROTOCOL_LEVEL,
+            requested_protocol_level: crate::PROTOCOL_LEVEL,
+            protocol_fallback: Default::default(),
+            session_expiry: None,
+            will_delay: None,
+            maximum_packet_size: None,
+            connected_events: false,
+            generated_client_id: None,
             state: State::BeginConnecting,
+            tap: Default::default(),
+            events: Default::default(),
+            auth: Default::default(),
+            auth_response: None,
         }
     }
 
+    pub(super) fn set_max_back_off(&mut self, max_back_off: std::time::Duration) {
+        self.max_back_off = max_back_off;
+        self.current_back_off = std::cmp::min(self.max_back_off, self.current_back_off);
+    }
+
+    pub(super) fn set_reconnect_jitter(&mut self, reconnect_jitter: ReconnectJitter) {
+        self.reconnect_jitter = reconnect_jitter;
+    }
+
+    pub(super) fn set_redirect_policy(&mut self, redirect_policy: RedirectPolicy) {
+        self.redirect.policy = redirect_policy;
+    }
+
+    /// Makes the next connection go to the given server that the server redirected the client to, if the redirect policy allows it
+    pub(super) fn redirect(&mut self, server_reference: &crate::proto::ByteStr) {
+        if self.redirect.follow(&mut self.connector, server_reference) {
+            // Connect to the new server right away
+            self.current_back_off = std::time::Duration::from_secs(0);
+        }
+    }
+
+    pub(super) fn set_conn_ack_timeout(&mut self, conn_ack_timeout: Option<std::time::Duration>) {
+        self.conn_ack_timeout = conn_ack_timeout;
+    }
+
+    pub(super) fn set_connect_send_timeout(&mut self, connect_send_timeout: Option<std::time::Duration>) {
+        self.connect_send_timeout = connect_send_timeout;
+    }
+
+    pub(super) fn set_clock(&mut self, clock: super::clock::SharedClock) {
+        self.clock = clock;
+    }
+
+    #[cfg(feature = "v5")]
+    pub(super) fn set_protocol_level(&mut self, protocol_level: u8) {
+        self.protocol_level = protocol_level;
+        self.requested_protocol_level = protocol_level;
+    }
+
+    #[cfg(feature = "v5")]
+    pub(super) fn set_protocol_fallback(&mut self, protocol_fallback: ProtocolFallback) {
+        self.protocol_fallback = protocol_fallback;
+    }
+
+    pub(super) fn set_session_expiry(&mut self, session_expiry: Option<std::time::Duration>) {
+        self.session_expiry = session_expiry;
+    }
+
+    pub(super) fn set_will_delay(&mut self, will_delay: Option<std::time::Duration>) {
+        self.will_delay = will_delay;
+    }
+
+    pub(super) fn set_maximum_packet_size(&mut self, maximum_packet_size: Option<u32>) {
+        self.maximum_packet_size = maximum_packet_size;
+    }
+
+    pub(super) fn set_connected_events(&mut self, connected_events: bool) {
+        self.connected_events = connected_events;
+    }
+
     pub(super) fn reconnect(&mut self) {
         self.state = State::BeginBackOff;
     }
+
+    /// Drops the current connection, if any, and connects again immediately
+    pub(super) fn restart(&mut self) {
+        self.current_back_off = std::time::Duration::from_secs(0);
+        self.conn_ack_timer = None;
+        self.connect_send_timer = None;
+        self.state = State::BeginBackOff;
+    }
+
+    pub(super) fn set_event_sink(&mut self, sink: Box<dyn super::EventSink + Send>) {
+        self.events.set(sink);
+    }
+
+    /// Records the given event with the event sink, if any
+    pub(super) fn record_event(&mut self, record: &super::EventRecord<'_>) {
+        self.events.record(record);
+    }
+
+    #[cfg(feature = "v5")]
+    pub(super) fn set_authenticator(&mut self, authenticator: Box<dyn super::Authenticator + Send>) {
+        self.auth.set_authenticator(authenticator);
+    }
+
+    #[cfg(feature = "v5")]
+    pub(super) fn reauthenticate(&mut self) {
+        self.auth.reauthenticate();
+    }
+
+    pub(super) fn packet_tap(&mut self, capacity: usize) -> super::PacketTap {
+        self.tap.new_tap(capacity)
+    }
+
+    /// The parameters negotiated with the server, if currently connected
+    pub(super) fn connection_parameters(&self) -> Option<super::ConnectionParameters> {
+        match &self.state {
+            State::Framed {
+                framed_state: FramedState::Connected { connection_parameters, .. },
+                ..
+            } => Some(*connection_parameters),
+            _ => None,
+        }
+    }
 }
 
 impl<C> Connect<C>
@@ -107,10 +277,12 @@ where
                     }
 
                     back_off => {
+                        let back_off = self.reconnect_jitter.apply(back_off);
                         log::debug!("Backing off for {:?}", back_off);
+                        self.events.record(&super::EventRecord::BackOff(back_off));
                         self.current_back_off =
                             std::cmp::min(self.max_back_off, self.current_back_off * 2);
-                        *state = State::EndBackOff(Box::pin(tokio::time::sleep(back_off)));
+                        *state = State::EndBackOff(self.clock.sleep(back_off));
                     }
                 },
 
@@ -124,12 +296,20 @@ where
                 }
 
                 State::BeginConnecting => {
+                    self.events.record(&super::EventRecord::ConnectAttempt);
                     let io = self.connector.connect();
                     *state = State::WaitingForIoToConnect(io);
                 }
 
                 State::WaitingForIoToConnect(io) => match std::pin::Pin::new(io).poll(cx) {
                     std::task::Poll::Ready(Ok((stream, sink, password))) => {
+                        // The CONNECT is sent right away, in this same poll, since some servers close connections
+                        // that don't send it soon enough.
+                        self.io_connected_at = self.clock.now();
+                        self.connect_send_timer = match self.connect_send_timeout {
+                            Some(connect_send_timeout) => Some(super::clock::Timer(self.clock.sleep(connect_send_timeout))),
+                            None => None,
+                        };
                         *state = State::Framed {
                             stream,
                             sink,
@@ -140,6 +320,8 @@ where
 
                     std::task::Poll::Ready(Err(err)) => {
                         log::warn!("could not connect to server: {}", err);
+                        self.events.record(&super::EventRecord::ConnectFailed(&err));
+                        self.redirect.end(&mut self.connector);
                         *state = State::BeginBackOff;
                     }
 
@@ -153,20 +335,47 @@ where
                     password,
                 } => match std::pin::Pin::new(&mut *sink).poll_ready(cx) {
                     std::task::Poll::Ready(Ok(())) => {
+                        let (authentication_method, authentication_data) = match self.auth.start() {
+                            Ok(authentication) => authentication,
+                            Err(err) => {
+                                log::warn!("could not connect to server: {}", err);
+                                self.events.record(&super::EventRecord::ConnectFailed(&err));
+                                *state = State::BeginBackOff;
+                                continue;
+                            }
+                        };
+
+                        // A generated client ID still starts a clean session on every connection, like a server-generated one does,
+                        // so the server does not need to keep it
+                        let (client_id, session_expiry) = match (&*client_id, &self.generated_client_id) {
+                            (crate::proto::ClientId::ServerGenerated, Some(generated_client_id)) =>
+                                (crate::proto::ClientId::IdWithCleanSession(generated_client_id.clone()), Some(std::time::Duration::from_secs(0))),
+                            (crate::proto::ClientId::ServerGenerated, None) => (crate::proto::ClientId::ServerGenerated, Some(std::time::Duration::from_secs(0))),
+                            (client_id, _) => (client_id.clone(), self.session_expiry),
+                        };
+
                         let packet = crate::proto::Packet::Connect(crate::proto::Connect {
                             username: username.cloned(),
                             password: password.clone(),
                             will: will.cloned(),
-                            client_id: client_id.clone(),
+                            client_id,
                             keep_alive,
                             protocol_name: crate::PROTOCOL_NAME,
-                            protocol_level: crate::PROTOCOL_LEVEL,
+                            protocol_level: self.protocol_level,
+                            session_expiry,
+                            will_delay: self.will_delay,
+                            maximum_packet_size: self.maximum_packet_size,
+                            authentication_method,
+                            authentication_data,
                         });
 
+                        self.tap.sent(&packet);
+
                         match std::pin::Pin::new(&mut *sink).start_send(packet) {
                             Ok(()) => *framed_state = FramedState::EndSendingConnect,
                             Err(err) => {
                                 log::warn!("could not connect to server: {}", err);
+                                self.events.record(&super::EventRecord::ConnectFailed(&err));
                                 *state = State::BeginBackOff;
                             }
                         }
@@ -174,10 +383,19 @@ where
 
                     std::task::Poll::Ready(Err(err)) => {
                         log::warn!("could not connect to server: {}", err);
+                        self.events.record(&super::EventRecord::ConnectFailed(&err));
                         *state = State::BeginBackOff;
                     }
 
-                    std::task::Poll::Pending => return std::task::Poll::Pending,
+                    std::task::Poll::Pending => {
+                        if !timed_out(&mut self.connect_send_timer, cx) {
+                            return std::task::Poll::Pending;
+                        }
+
+                        log::warn!("could not connect to server: timed out sending CONNECT");
+                        self.events.record(&super::EventRecord::ConnectSendTimedOut);
+                        *state = State::BeginBackOff;
+                    }
                 },
 
                 State::Framed {
@@ -187,13 +405,28 @@ where
                     ..
                 } => match std::pin::Pin::new(sink).poll_flush(cx) {
                     std::task::Poll::Ready(Ok(())) => {
+                        log::debug!("sent CONNECT {:?} after the transport connected", self.clock.now().saturating_sub(self.io_connected_at));
+                        self.connect_send_timer = None;
+                        self.conn_ack_timer = match self.conn_ack_timeout {
+                            Some(conn_ack_timeout) => Some(super::clock::Timer(self.clock.sleep(conn_ack_timeout))),
+                            None => None,
+                        };
                         *framed_state = FramedState::WaitingForConnAck
                     }
                     std::task::Poll::Ready(Err(err)) => {
                         log::warn!("could not connect to server: {}", err);
+                        self.events.record(&super::EventRecord::ConnectFailed(&err));
+                        *state = State::BeginBackOff;
+                    }
+                    std::task::Poll::Pending => {
+                        if !timed_out(&mut self.connect_send_timer, cx) {
+                            return std::task::Poll::Pending;
+                        }
+
+                        log::warn!("could not connect to server: timed out sending CONNECT");
+                        self.events.record(&super::EventRecord::ConnectSendTimedOut);
                         *state = State::BeginBackOff;
                     }
-                    std::task::Poll::Pending => return std::task::Poll::Pending,
                 },
 
                 State::Framed {
@@ -201,16 +434,44 @@ where
                     sink: _,
                     framed_state: framed_state @ FramedState::WaitingForConnAck,
                     ..
-                } => match std::pin::Pin::new(stream).poll_next(cx) {
+                } => match self.tap.poll_received(std::pin::Pin::new(stream).poll_next(cx)) {
                     std::task::Poll::Ready(Some(Ok(packet))) => match packet {
                         crate::proto::Packet::ConnAck(crate::proto::ConnAck {
                             session_present,
                             return_code: crate::proto::ConnectReturnCode::Accepted,
+                            authentication_data,
+                            receive_maximum,
+                            maximum_packet_size,
+                            server_reference: _,
+                            assigned_client_id,
+                            server_keep_alive,
+                            maximum_qos,
+                            retain_available,
+                            reason_string,
+                            user_properties,
                         }) => {
+                            if let Err(err) = self.auth.accepted(authentication_data, self.protocol_level != crate::PROTOCOL_LEVEL) {
+                                log::warn!("could not connect to server: {}", err);
+                                self.events.record(&super::EventRecord::ConnectFailed(&err));
+                                self.conn_ack_timer = None;
+                                *state = State::BeginBackOff;
+                                continue;
+                            }
+
                             self.current_back_off = std::time::Duration::from_secs(0);
+                            self.conn_ack_timer = None;
+
+                            let protocol_level = self.protocol_level;
+                            if self.protocol_fallback == ProtocolFallback::PerConnection {
+                                // Ask for the requested protocol level again on the next connection
+                                self.protocol_level = self.requested_protocol_level;
+                            }
 
                             let reset_session = match client_id {
                                 crate::proto::ClientId::ServerGenerated => true,
+                                // A session that the server does not keep can't be resumed, so every connection starts a clean one.
+                                // This is also how a session expiry of zero is expressed in MQTT 3.1.1.
+                                crate::proto::ClientId::IdWithCleanSession(_) if !session_is_kept(self.session_expiry) => true,
                                 crate::proto::ClientId::IdWithCleanSession(id) => {
                                     *client_id = crate::proto::ClientId::IdWithExistingSession(
                                         std::mem::take(id),
@@ -225,39 +486,171 @@ where
                                 }
                             };
 
+                            // MQTT 3.1.1 servers cannot advertise any restrictions in CONNACK,
+                            // so the effective parameters are the ones we asked for and the protocol maximums,
+                            // unless MQTT 5.0 servers override them.
+                            let connection_parameters = super::ConnectionParameters {
+                                keep_alive: server_keep_alive.unwrap_or(keep_alive),
+                                maximum_qos: maximum_qos.unwrap_or(crate::proto::QoS::ExactlyOnce),
+                                retain_available: retain_available.unwrap_or(true),
+                                receive_maximum: receive_maximum.unwrap_or(u16::MAX),
+                                maximum_packet_size,
+                                protocol_level,
+                            };
+
+                            self.events.record(&super::EventRecord::Connected { reset_session });
+
+                            let connected_event = if self.connected_events {
+                                Some(super::ConnectedEvent {
+                                    reset_session,
+                                    session_present,
+                                    assigned_client_id,
+                                    server_keep_alive,
+                                    connection_parameters,
+                                    reason_string,
+                                    user_properties,
+                                })
+                            }
+                            else {
+                                None
+                            };
+
                             *framed_state = FramedState::Connected {
                                 new_connection: true,
                                 reset_session,
+                                connection_parameters,
+                                connected_event,
                             };
                         }
 
                         crate::proto::Packet::ConnAck(crate::proto::ConnAck {
                             return_code: crate::proto::ConnectReturnCode::Refused(return_code),
+                            server_reference,
                             ..
                         }) => {
                             log::warn!(
                                 "could not connect to server: connection refused: {:?}",
                                 return_code
                             );
+                            self.events.record(&super::EventRecord::ConnectRefused(return_code));
+
+                            // The server asks the client to use another server instead
+                            match &server_reference {
+                                Some(server_reference) if self.redirect.follow(&mut self.connector, server_reference) =>
+                                    self.current_back_off = std::time::Duration::from_secs(0),
+                                _ => self.redirect.end(&mut self.connector),
+                            }
+
+                            // The server does not support the protocol level we asked for, so fall back to MQTT 3.1.1 right away
+                            if return_code == crate::proto::ConnectionRefusedReason::UnacceptableProtocolVersion &&
+                                self.protocol_level != crate::PROTOCOL_LEVEL &&
+                                self.protocol_fallback != ProtocolFallback::Disabled
+                            {
+                                log::info!("server does not support protocol level {}, falling back to {}", self.protocol_level, crate::PROTOCOL_LEVEL);
+                                self.protocol_level = crate::PROTOCOL_LEVEL;
+                                self.current_back_off = std::time::Duration::from_secs(0);
+                            }
+
+                            // The server does not assign client IDs, so generate one that every server must accept
+                            if return_code == crate::proto::ConnectionRefusedReason::IdentifierRejected &&
+                                *client_id == crate::proto::ClientId::ServerGenerated
+                            {
+                                let generated_client_id = generate_client_id();
+                                log::info!("server rejected empty client ID, connecting with generated client ID {} instead", generated_client_id);
+                                self.generated_client_id = Some(generated_client_id);
+                            }
+
                             *state = State::BeginBackOff;
                         }
 
+                        crate::proto::Packet::Auth(auth) => match self.auth.challenge(auth) {
+                            Ok(Some(response)) => {
+                                self.auth_response = Some(response);
+                                *framed_state = FramedState::BeginSendingAuth;
+                            }
+
+                            Ok(None) => (),
+
+                            Err(err) => {
+                                log::warn!("could not connect to server: {}", err);
+                                self.events.record(&super::EventRecord::ConnectFailed(&err));
+                                self.conn_ack_timer = None;
+                                *state = State::BeginBackOff;
+                            }
+                        },
+
                         packet => {
                             log::warn!("could not connect to server: expected to receive ConnAck but received {:?}", packet);
+                            self.events.record(&super::EventRecord::ConnectFailed(&format_args!("expected to receive ConnAck but received {:?}", packet)));
                             *state = State::BeginBackOff;
                         }
                     },
 
                     std::task::Poll::Ready(Some(Err(err))) => {
                         log::warn!("could not connect to server: {}", err);
+                        self.events.record(&super::EventRecord::ConnectFailed(&err));
                         *state = State::BeginBackOff;
                     }
 
                     std::task::Poll::Ready(None) => {
                         log::warn!("could not connect to server: connection closed by server");
+                        self.events.record(&super::EventRecord::ConnectFailed(&"connection closed by server"));
+                        *state = State::BeginBackOff;
+                    }
+
+                    std::task::Poll::Pending => {
+                        if !timed_out(&mut self.conn_ack_timer, cx) {
+                            return std::task::Poll::Pending;
+                        }
+
+                        log::warn!("could not connect to server: timed out waiting for ConnAck");
+                        self.events.record(&super::EventRecord::ConnAckTimedOut);
+                        self.conn_ack_timer = None;
                         *state = State::BeginBackOff;
                     }
+                },
 
+                State::Framed {
+                    stream: _,
+                    sink,
+                    framed_state: framed_state @ FramedState::BeginSendingAuth,
+                    ..
+                } => match std::pin::Pin::new(&mut *sink).poll_ready(cx) {
+                    std::task::Poll::Ready(Ok(())) => {
+                        let packet = self.auth_response.take().expect("AUTH response is set before sending it");
+                        self.tap.sent(&packet);
+
+                        match std::pin::Pin::new(&mut *sink).start_send(packet) {
+                            Ok(()) => *framed_state = FramedState::EndSendingAuth,
+                            Err(err) => {
+                                log::warn!("could not connect to server: {}", err);
+                                self.events.record(&super::EventRecord::ConnectFailed(&err));
+                                *state = State::BeginBackOff;
+                            }
+                        }
+                    }
+
+                    std::task::Poll::Ready(Err(err)) => {
+                        log::warn!("could not connect to server: {}", err);
+                        self.events.record(&super::EventRecord::ConnectFailed(&err));
+                        *state = State::BeginBackOff;
+                    }
+
+                    std::task::Poll::Pending => return std::task::Poll::Pending,
+                },
+
+                State::Framed {
+                    stream: _,
+                    sink,
+                    framed_state: framed_state @ FramedState::EndSendingAuth,
+                    ..
+                } => match std::pin::Pin::new(sink).poll_flush(cx) {
+                    std::task::Poll::Ready(Ok(())) => *framed_state = FramedState::WaitingForConnAck,
+                    std::task::Poll::Ready(Err(err)) => {
+                        log::warn!("could not connect to server: {}", err);
+                        self.events.record(&super::EventRecord::ConnectFailed(&err));
+                        *state = State::BeginBackOff;
+                    }
                     std::task::Poll::Pending => return std::task::Poll::Pending,
                 },
 
@@ -268,14 +661,20 @@ where
                         FramedState::Connected {
                             new_connection,
                             reset_session,
+                            connection_parameters,
+                            connected_event,
                         },
                     ..
                 } => {
                     let result = Connected {
                         stream,
                         sink,
+                        tap: &mut self.tap,
+                        auth: &mut self.auth,
                         new_connection: *new_connection,
                         reset_session: *reset_session,
+                        connection_parameters: *connection_parameters,
+                        event: connected_event.take(),
                     };
                     *new_connection = false;
                     *reset_session = false;
@@ -286,12 +685,378 @@ where
     }
 }
 
+/// Randomizes the back-off periods between reconnection attempts, so that clients that lost their connections at the same time,
+/// such as because the server restarted, don't all try to reconnect at the same time.
+///
+/// The first reconnection attempt after a connection is lost is always immediate. Jitter applies to the back-off periods after that.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReconnectJitter {
+    /// Back off for exactly the back-off period.
+    None,
+
+    /// Back off for a random duration between zero and the back-off period.
+    Full,
+
+    /// Back off for a random duration between half the back-off period and the back-off period.
+    #[default]
+    Equal,
+}
+
+impl ReconnectJitter {
+    fn apply(self, back_off: std::time::Duration) -> std::time::Duration {
+        match self {
+            ReconnectJitter::None => back_off,
+            ReconnectJitter::Full => random_duration(back_off),
+            ReconnectJitter::Equal => back_off / 2 + random_duration(back_off / 2),
+        }
+    }
+}
+
+/// Whether the [`Client`](crate::Client) connects to the other server that an MQTT 5.0 server redirects it to,
+/// with a Server Reference in a CONNACK that refuses the connection or in a DISCONNECT.
+///
+/// Redirects are followed by telling the connector to connect to the other server with [`crate::io::Connector::redirect`],
+/// such as with a [`crate::RedirectableConnector`]. The client reconnects right away. If it cannot connect to the server
+/// it was redirected to, or that server refuses the connection, the client goes back to the usual server.
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RedirectPolicy {
+    /// Reconnect to the same server
+    #[default]
+    Ignore,
+
+    /// Follow a redirect, but not a redirect of the server that the client was redirected to, so that servers cannot redirect the client in a loop
+    FollowOnce,
+
+    /// Follow every redirect
+    FollowAlways,
+}
+
+/// What the [`Client`](crate::Client) does when the server refuses the protocol level set with
+/// [`Client::with_protocol_level`](crate::Client::with_protocol_level) as unacceptable, such as an MQTT 3.1.1 server refusing MQTT 5.0.
+///
+/// When it falls back, the client reconnects right away with MQTT 3.1.1. [`crate::ConnectionParameters::protocol_level`]
+/// tells which protocol level each connection ended up with.
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ProtocolFallback {
+    /// Keep asking for the protocol level after the usual back-off, such as for applications that depend on MQTT 5.0 features
+    Disabled,
+
+    /// Fall back to MQTT 3.1.1 for the rest of the life of the client
+    #[default]
+    Sticky,
+
+    /// Fall back to MQTT 3.1.1 for one connection, and ask for the protocol level again on the next one,
+    /// such as for connectors that can connect to servers that do not all support the same protocol levels
+    PerConnection,
+}
+
+/// Which server the connector connects to, according to the [`RedirectPolicy`]
+#[derive(Debug, Default)]
+struct Redirect {
+    policy: RedirectPolicy,
+
+    /// Whether the connector was redirected to another server by the server it connected to
+    redirected: bool,
+}
+
+impl Redirect {
+    /// Makes the connector connect to the given server, if the redirect policy allows it. Returns whether the redirect is followed.
+    fn follow<C>(&mut self, connector: &mut C, server_reference: &crate::proto::ByteStr) -> bool where C: crate::io::Connector {
+        let follow = match self.policy {
+            RedirectPolicy::Ignore => false,
+            RedirectPolicy::FollowOnce => !self.redirected,
+            RedirectPolicy::FollowAlways => true,
+        };
+        if !follow {
+            log::info!("not following redirect to {} because of the redirect policy", server_reference);
+            return false;
+        }
+
+        if !connector.redirect(Some(server_reference.as_ref())) {
+            log::warn!("not following redirect to {} because the connector does not support redirects", server_reference);
+            return false;
+        }
+
+        log::info!("server redirected the client to {}", server_reference);
+        self.redirected = true;
+        true
+    }
+
+    /// Makes the connector connect to the usual server again, if it was redirected to another one
+    fn end<C>(&mut self, connector: &mut C) where C: crate::io::Connector {
+        if self.redirected {
+            log::info!("could not connect to the server that the client was redirected to, going back to the usual server");
+            self.redirected = false;
+            connector.redirect(None);
+        }
+    }
+}
+
+/// Returns a random duration between zero and `max`, inclusive
+fn random_duration(max: std::time::Duration) -> std::time::Duration {
+    use std::convert::TryFrom;
+
+    let max = u64::try_from(max.as_nanos()).unwrap_or(u64::MAX);
+    std::time::Duration::from_nanos(random() % max.saturating_add(1))
+}
+
+/// Whether the given timer, if any, has fired
+fn timed_out(timer: &mut Option<super::clock::Timer>, cx: &mut std::task::Context<'_>) -> bool {
+    use futures_util::FutureExt;
+
+    match timer {
+        Some(timer) => timer.0.poll_unpin(cx).is_ready(),
+        None => false,
+    }
+}
+
+/// Whether the server keeps the session after the connection is lost, so that the client can resume it when it reconnects.
+/// The Session Expiry Interval is in whole seconds.
+fn session_is_kept(session_expiry: Option<std::time::Duration>) -> bool {
+    session_expiry.map(|session_expiry| session_expiry.as_secs()) != Some(0)
+}
+
+/// Returns a random client ID of 21 alphanumeric characters.
+///
+/// Ref: 3.1.3.1 Client Identifier: servers must allow IDs of 1 to 23 characters from "0-9a-zA-Z".
+fn generate_client_id() -> crate::proto::ByteStr {
+    format!("mqtt3{:016x}", random()).parse().expect("generated client ID is a valid string")
+}
+
+/// Returns a random number. Not suitable for cryptography.
+pub(super) fn random() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+
+    // Every `RandomState` is seeded differently, which is all the randomness that jitter and endpoint selection need.
+    std::collections::hash_map::RandomState::new().build_hasher().finish()
+}
+
 pub(super) struct Connected<'a, C>
 where
     C: crate::io::Connector,
 {
     pub(super) stream: &'a mut <C as crate::io::Connector>::PacketStream,
     pub(super) sink: &'a mut <C as crate::io::Connector>::PacketSink,
+    pub(super) tap: &'a mut super::tap::Tap,
+    pub(super) auth: &'a mut super::auth::State,
     pub(super) new_connection: bool,
     pub(super) reset_session: bool,
+    pub(super) connection_parameters: super::ConnectionParameters,
+    pub(super) event: Option<super::ConnectedEvent>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::test_server::{conn_ack, Stall, TestServer};
+
+    #[test]
+    fn follow_redirect_once() {
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let server_references = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+
+        // The usual server redirects to "b", which redirects to "c"
+        let connector = crate::client::RedirectableConnector::new({
+            let server_references = server_references.clone();
+            move |server_reference: Option<&str>| {
+                server_references.lock().unwrap().push(server_reference.map(ToOwned::to_owned));
+                let conn_ack = crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+                    server_reference: Some(if server_reference.is_none() { "b" } else { "c" }.parse().unwrap()),
+                    ..conn_ack(crate::proto::ConnectReturnCode::Refused(crate::proto::ConnectionRefusedReason::Other(0x9C)))
+                });
+                futures_util::future::ready(Ok::<_, std::io::Error>((
+                    futures_util::stream::iter(vec![Ok(conn_ack)]),
+                    futures_util::SinkExt::sink_map_err(futures_util::sink::drain(), |err| match err {}),
+                    None,
+                )))
+            }
+        });
+
+        let mut connect = super::Connect::new(connector, std::time::Duration::from_secs(60));
+        connect.set_clock(crate::client::clock::SharedClock::new(clock));
+        connect.set_redirect_policy(super::RedirectPolicy::FollowOnce);
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut client_id = crate::proto::ClientId::ServerGenerated;
+        assert!(connect.poll(&mut cx, None, None, &mut client_id, std::time::Duration::from_secs(30)).is_pending());
+
+        // The redirect of "b" is not followed, so the client goes back to the usual server after backing off
+        assert_eq!(*server_references.lock().unwrap(), [None, Some("b".to_owned())]);
+        assert_eq!(connect.connector.server_reference(), None);
+    }
+
+    #[test]
+    fn connected_event() {
+        let server = TestServer::new(|packet| match packet {
+            crate::proto::Packet::Connect(_) => vec![crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+                assigned_client_id: Some("assigned".parse().unwrap()),
+                server_keep_alive: Some(std::time::Duration::from_secs(10)),
+                maximum_qos: Some(crate::proto::QoS::AtLeastOnce),
+                ..conn_ack(crate::proto::ConnectReturnCode::Accepted)
+            })],
+            _ => vec![],
+        });
+
+        let mut connect = super::Connect::new(server, std::time::Duration::from_secs(60));
+        connect.set_connected_events(true);
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut client_id = crate::proto::ClientId::ServerGenerated;
+        let connected = match connect.poll(&mut cx, None, None, &mut client_id, std::time::Duration::from_secs(30)) {
+            std::task::Poll::Ready(connected) => connected,
+            std::task::Poll::Pending => panic!("client did not connect"),
+        };
+
+        // The keep-alive of the server overrides the one the client asked for
+        let expected_connection_parameters = crate::client::ConnectionParameters {
+            keep_alive: std::time::Duration::from_secs(10),
+            maximum_qos: crate::proto::QoS::AtLeastOnce,
+            retain_available: true,
+            receive_maximum: u16::MAX,
+            maximum_packet_size: None,
+            protocol_level: crate::PROTOCOL_LEVEL,
+        };
+        assert_eq!(connected.connection_parameters, expected_connection_parameters);
+        assert_eq!(connected.event, Some(crate::client::ConnectedEvent {
+            reset_session: true,
+            session_present: false,
+            assigned_client_id: Some("assigned".parse().unwrap()),
+            server_keep_alive: Some(std::time::Duration::from_secs(10)),
+            connection_parameters: expected_connection_parameters,
+            reason_string: None,
+            user_properties: vec![],
+        }));
+
+        // The event is only reported once per connection
+        match connect.poll(&mut cx, None, None, &mut client_id, std::time::Duration::from_secs(30)) {
+            std::task::Poll::Ready(connected) => assert!(!connected.new_connection && connected.event.is_none()),
+            std::task::Poll::Pending => panic!("client is not connected"),
+        }
+    }
+
+    #[test]
+    fn conn_ack_timeout() {
+        // The server never responds to the CONNECT
+        let server = TestServer::new(|_| vec![]);
+
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+
+        let mut connect = super::Connect::new(server.clone(), std::time::Duration::from_secs(60));
+        connect.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+        connect.set_reconnect_jitter(super::ReconnectJitter::None);
+        connect.set_conn_ack_timeout(Some(std::time::Duration::from_secs(10)));
+        connect.set_event_sink(Box::new({
+            let events = events.clone();
+            move |record: &crate::client::EventRecord<'_>| events.lock().unwrap().push(format!("{:?}", record))
+        }));
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut client_id = crate::proto::ClientId::ServerGenerated;
+        let mut poll = |now| {
+            clock.advance_to(now);
+            assert!(connect.poll(&mut cx, None, None, &mut client_id, std::time::Duration::from_secs(30)).is_pending());
+            server.sent().iter().filter(|packet| matches!(packet, crate::proto::Packet::Connect(_))).count()
+        };
+
+        assert_eq!(poll(0), 1);
+        assert_eq!(poll(9), 1);
+        assert_eq!(*events.lock().unwrap(), ["ConnectAttempt"]);
+
+        // The client gives up on the connection once the timeout passes, and connects again
+        assert_eq!(poll(10), 2);
+        assert_eq!(*events.lock().unwrap(), ["ConnectAttempt", "ConnAckTimedOut", "ConnectAttempt"]);
+
+        // ... and backs off before the attempt after that
+        assert_eq!(poll(20), 2);
+        assert_eq!(poll(21), 3);
+        assert_eq!(*events.lock().unwrap(), ["ConnectAttempt", "ConnAckTimedOut", "ConnectAttempt", "ConnAckTimedOut", "BackOff(1s)", "ConnectAttempt"]);
+    }
+
+    #[test]
+    fn connect_send_timeout() {
+        // Returns the number of connection attempts and the events after the given times
+        fn connect(stall: Stall, times: &[u64]) -> Vec<(usize, Vec<String>)> {
+            let server = TestServer::stalled(stall);
+
+            let clock = crate::client::clock::tests::ManualClock::default();
+            let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+
+            let mut connect = super::Connect::new(server, std::time::Duration::from_secs(60));
+            connect.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+            connect.set_connect_send_timeout(Some(std::time::Duration::from_secs(1)));
+            connect.set_conn_ack_timeout(Some(std::time::Duration::from_secs(10)));
+            connect.set_event_sink(Box::new({
+                let events = events.clone();
+                move |record: &crate::client::EventRecord<'_>| if let crate::client::EventRecord::ConnectAttempt | crate::client::EventRecord::ConnectSendTimedOut = record {
+                    events.lock().unwrap().push(format!("{:?}", record));
+                }
+            }));
+
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+            let mut client_id = crate::proto::ClientId::ServerGenerated;
+            times.iter()
+                .map(|&now| {
+                    clock.advance_to(now);
+                    assert!(connect.poll(&mut cx, None, None, &mut client_id, std::time::Duration::from_secs(30)).is_pending());
+                    let events = events.lock().unwrap().clone();
+                    (events.iter().filter(|event| *event == "ConnectAttempt").count(), events)
+                })
+                .collect()
+        }
+
+        // Whether the transport never gets ready to send the CONNECT, or never finishes sending it, the client gives up on the connection
+        // once the timeout passes, well before the CONNACK timeout, and connects again
+        for &stall in &[Stall::Ready, Stall::Flush] {
+            let attempts = connect(stall, &[0, 1]);
+            assert_eq!(attempts[0], (1, vec!["ConnectAttempt".to_owned()]), "{:?}", stall);
+            assert_eq!(attempts[1], (2, vec!["ConnectAttempt".to_owned(), "ConnectSendTimedOut".to_owned(), "ConnectAttempt".to_owned()]), "{:?}", stall);
+        }
+    }
+
+    #[cfg(feature = "v5")]
+    #[test]
+    fn protocol_fallback() {
+        // Returns the protocol level of the connection if the client connected without backing off,
+        // and the protocol level of the next CONNECT
+        fn connect(protocol_fallback: super::ProtocolFallback) -> (Option<u8>, u8) {
+            // The server only supports MQTT 3.1.1
+            let server = TestServer::new(|packet| match packet {
+                crate::proto::Packet::Connect(connect) => {
+                    let return_code =
+                        if connect.protocol_level == crate::PROTOCOL_LEVEL {
+                            crate::proto::ConnectReturnCode::Accepted
+                        }
+                        else {
+                            crate::proto::ConnectReturnCode::Refused(crate::proto::ConnectionRefusedReason::UnacceptableProtocolVersion)
+                        };
+                    vec![crate::proto::Packet::ConnAck(conn_ack(return_code))]
+                },
+                _ => vec![],
+            });
+
+            let mut connect = super::Connect::new(server, std::time::Duration::from_secs(60));
+            connect.set_clock(crate::client::clock::SharedClock::new(crate::client::clock::tests::ManualClock::default()));
+            connect.set_protocol_level(crate::proto::v5::PROTOCOL_LEVEL);
+            connect.set_protocol_fallback(protocol_fallback);
+
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+            let mut client_id = crate::proto::ClientId::ServerGenerated;
+            let protocol_level = match connect.poll(&mut cx, None, None, &mut client_id, std::time::Duration::from_secs(30)) {
+                std::task::Poll::Ready(connected) => Some(connected.connection_parameters.protocol_level),
+                std::task::Poll::Pending => None,
+            };
+            (protocol_level, connect.protocol_level)
+        }
+
+        assert_eq!(connect(super::ProtocolFallback::Disabled), (None, crate::proto::v5::PROTOCOL_LEVEL));
+        assert_eq!(connect(super::ProtocolFallback::Sticky), (Some(crate::PROTOCOL_LEVEL), crate::PROTOCOL_LEVEL));
+        assert_eq!(connect(super::ProtocolFallback::PerConnection), (Some(crate::PROTOCOL_LEVEL), crate::proto::v5::PROTOCOL_LEVEL));
+    }
 }
@@ -0,0 +1,180 @@
+/// How long it takes for a reconnect, or a period of being connected or disconnected, to count half as much in the
+/// [`ConnectionStabilityStats`] by default
+const DEFAULT_HALF_LIFE: std::time::Duration = std::time::Duration::from_secs(SECONDS_PER_HOUR);
+
+const SECONDS_PER_HOUR: u64 = 60 * 60;
+
+/// Tracks how stable the connection of a [`Client`](crate::Client) is, returned by
+/// [`Client::connection_stability`](crate::Client::connection_stability).
+///
+/// The reconnects and the time spent connected are exponentially decayed, so that recent history counts more than old history,
+/// and a device that has been stable for a while recovers its score. Clones share the same statistics.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionStability(std::sync::Arc<std::sync::Mutex<Inner>>);
+
+#[derive(Debug)]
+struct Inner {
+    half_life: std::time::Duration,
+    clock: super::clock::SharedClock,
+
+    /// When the statistics were last decayed, or `None` before the first connection
+    updated_at: Option<std::time::Duration>,
+    is_connected: bool,
+    reconnects: u64,
+
+    /// The decayed number of reconnects
+    decayed_reconnects: f64,
+
+    /// The decayed number of seconds spent connected
+    decayed_connected: f64,
+
+    /// The decayed number of seconds observed
+    decayed_observed: f64,
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Inner {
+            half_life: DEFAULT_HALF_LIFE,
+            clock: Default::default(),
+
+            updated_at: None,
+            is_connected: false,
+            reconnects: 0,
+
+            decayed_reconnects: 0.,
+            decayed_connected: 0.,
+            decayed_observed: 0.,
+        }
+    }
+}
+
+impl ConnectionStability {
+    /// Returns the statistics as of now
+    pub fn stats(&self) -> ConnectionStabilityStats {
+        let mut inner = self.0.lock().expect("connection stability mutex is poisoned");
+        inner.decay();
+
+        let mean_life = inner.half_life.as_secs_f64() / std::f64::consts::LN_2;
+        ConnectionStabilityStats {
+            reconnects: inner.reconnects,
+            #[allow(clippy::cast_precision_loss)]
+            reconnects_per_hour: inner.decayed_reconnects / mean_life * SECONDS_PER_HOUR as f64,
+            connected_ratio: if inner.decayed_observed > 0. { inner.decayed_connected / inner.decayed_observed } else { 0. },
+        }
+    }
+
+    pub(super) fn set_half_life(&self, half_life: std::time::Duration) {
+        let mut inner = self.0.lock().expect("connection stability mutex is poisoned");
+        inner.half_life = half_life;
+    }
+
+    pub(super) fn set_clock(&self, clock: super::clock::SharedClock) {
+        let mut inner = self.0.lock().expect("connection stability mutex is poisoned");
+        inner.clock = clock;
+    }
+
+    pub(super) fn new_connection(&self) {
+        let mut inner = self.0.lock().expect("connection stability mutex is poisoned");
+        if inner.updated_at.is_some() {
+            inner.decay();
+            inner.reconnects += 1;
+            inner.decayed_reconnects += 1.;
+        }
+        else {
+            inner.updated_at = Some(inner.clock.now());
+        }
+        inner.is_connected = true;
+    }
+
+    pub(super) fn connection_lost(&self) {
+        let mut inner = self.0.lock().expect("connection stability mutex is poisoned");
+        inner.decay();
+
+        inner.is_connected = false;
+    }
+}
+
+impl Inner {
+    /// Decays the statistics up to now, counting the time since they were last decayed as connected or disconnected
+    fn decay(&mut self) {
+        if let Some(updated_at) = self.updated_at {
+            let now = self.clock.now();
+            let elapsed = now.saturating_sub(updated_at).as_secs_f64();
+            self.updated_at = Some(now);
+
+            let mean_life = self.half_life.as_secs_f64() / std::f64::consts::LN_2;
+            let decay = (-elapsed / mean_life).exp();
+
+            // The decayed length of the elapsed time, ie the integral of the decay over it
+            let decayed_elapsed = mean_life * (1. - decay);
+
+            self.decayed_reconnects *= decay;
+            self.decayed_connected = self.decayed_connected * decay + if self.is_connected { decayed_elapsed } else { 0. };
+            self.decayed_observed = self.decayed_observed * decay + decayed_elapsed;
+        }
+    }
+}
+
+/// A snapshot of a [`ConnectionStability`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectionStabilityStats {
+    /// The number of times the client connected again after its first connection
+    pub reconnects: u64,
+
+    /// The recent rate of reconnects, decayed exponentially
+    pub reconnects_per_hour: f64,
+
+    /// The recent fraction of time that the client was connected since its first connection, from 0 to 1, decayed exponentially
+    pub connected_ratio: f64,
+}
+
+impl ConnectionStabilityStats {
+    /// A single score to rank clients by, from 0 for a client that is not connected to 1 for a client that stays connected
+    /// without reconnecting. Each recent reconnect per hour halves the score of a client that is otherwise always connected.
+    pub fn score(&self) -> f64 {
+        self.connected_ratio / (1. + self.reconnects_per_hour)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decays() {
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let stability = super::ConnectionStability::default();
+        stability.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+        stability.set_half_life(std::time::Duration::from_secs(3600));
+
+        // Nothing is observed before the first connection
+        clock.advance_to(3600);
+        assert_eq!(stability.stats(), super::ConnectionStabilityStats { reconnects: 0, reconnects_per_hour: 0., connected_ratio: 0. });
+
+        // Connected for an hour, then disconnected for an hour
+        stability.new_connection();
+        clock.advance_to(7200);
+        stability.connection_lost();
+        clock.advance_to(10800);
+        let stats = stability.stats();
+        assert_eq!(stats.reconnects, 0);
+        assert!(stats.reconnects_per_hour.abs() < f64::EPSILON);
+        // The disconnected hour counts twice as much as the older connected one
+        assert!((stats.connected_ratio - 1. / 3.).abs() < 1e-9, "{:?}", stats);
+
+        // Reconnects lower the score, until they decay
+        stability.new_connection();
+        stability.connection_lost();
+        stability.new_connection();
+        let stats = stability.stats();
+        assert_eq!(stats.reconnects, 2);
+        assert!((stats.reconnects_per_hour - 2. * std::f64::consts::LN_2).abs() < 1e-9, "{:?}", stats);
+        let unstable_score = stats.score();
+
+        clock.advance_to(10800 + 10 * 3600);
+        let stats = stability.stats();
+        assert_eq!(stats.reconnects, 2);
+        assert!(stats.reconnects_per_hour < 0.01, "{:?}", stats);
+        assert!(stats.connected_ratio > 0.99, "{:?}", stats);
+        assert!(stats.score() > unstable_score);
+    }
+}
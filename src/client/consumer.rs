@@ -0,0 +1,140 @@
+/// Processes publications received in manual ack mode at least once, without processing redelivered publications again after a restart.
+///
+/// For every topic, the consumer records a marker of the last publication that was processed in a [`ProcessedStore`].
+/// When a publication that may have been delivered before (because it has the DUP or retain flag set) has the same marker
+/// as the last processed publication of its topic, it is acked without being processed again.
+/// This means a restarted consumer does not reprocess the retained publications and unacked redeliveries that it had already processed.
+///
+/// The marker of a publication is computed by a function that can be set with [`Consumer::with_marker`]. The default function
+/// is a hash of the payload.
+pub struct Consumer<S> {
+    store: S,
+    marker: Box<dyn Fn(&crate::ReceivedPublication) -> u64 + Send>,
+}
+
+impl<S> Consumer<S> where S: ProcessedStore {
+    pub fn new(store: S) -> Self {
+        Consumer {
+            store,
+            marker: Box::new(|publication| fnv1a(&publication.payload)),
+        }
+    }
+
+    /// Sets the function that computes the marker of a publication, such as by extracting a sequence number from its payload.
+    #[must_use]
+    pub fn with_marker(mut self, marker: impl Fn(&crate::ReceivedPublication) -> u64 + Send + 'static) -> Self {
+        self.marker = Box::new(marker);
+        self
+    }
+
+    /// Returns whether the given publication needs to be processed.
+    ///
+    /// If it does not, it has already been acked and should be dropped. Otherwise, call [`Consumer::complete`] once it has been processed.
+    pub fn begin(&mut self, publication: &mut crate::ReceivedPublication) -> Result<bool, S::Error> {
+        if !publication.dup && !publication.retain {
+            return Ok(true);
+        }
+
+        let marker = (self.marker)(publication);
+        if self.store.last_processed(publication.topic_name.as_ref())? == Some(marker) {
+            log::debug!("skipping publication on {} that was already processed", publication.topic_name);
+            publication.ack();
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Records that the given publication has been processed, then acks it.
+    pub fn complete(&mut self, mut publication: crate::ReceivedPublication) -> Result<(), S::Error> {
+        let marker = (self.marker)(&publication);
+        self.store.set_last_processed(publication.topic_name.as_ref(), marker)?;
+        publication.ack();
+        Ok(())
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+}
+
+impl<S> std::fmt::Debug for Consumer<S> where S: std::fmt::Debug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Consumer")
+            .field("store", &self.store)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stores the marker of the last processed publication of every topic for a [`Consumer`]
+///
+/// Implementations should persist the markers, such as to a file or database, so that they are available after a restart.
+pub trait ProcessedStore {
+    type Error;
+
+    /// Returns the marker of the last processed publication of the given topic, if any.
+    fn last_processed(&mut self, topic_name: &str) -> Result<Option<u64>, Self::Error>;
+
+    /// Sets the marker of the last processed publication of the given topic.
+    fn set_last_processed(&mut self, topic_name: &str, marker: u64) -> Result<(), Self::Error>;
+}
+
+/// A [`ProcessedStore`] that keeps the markers in memory, so only deduplicates publications for the lifetime of the process.
+#[derive(Debug, Default)]
+pub struct MemoryProcessedStore(std::collections::HashMap<String, u64>);
+
+impl ProcessedStore for MemoryProcessedStore {
+    type Error = std::convert::Infallible;
+
+    fn last_processed(&mut self, topic_name: &str) -> Result<Option<u64>, Self::Error> {
+        Ok(self.0.get(topic_name).copied())
+    }
+
+    fn set_last_processed(&mut self, topic_name: &str, marker: u64) -> Result<(), Self::Error> {
+        self.0.insert(topic_name.to_owned(), marker);
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a. Unlike `std::collections::hash_map::DefaultHasher`, its output is stable across Rust versions, so it can be persisted.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn skips_processed_redeliveries() {
+        fn publication(payload: &'static [u8], dup: bool, retain: bool) -> crate::ReceivedPublication {
+            crate::ReceivedPublication {
+                topic_name: "devices/foo/config".parse().unwrap(),
+                dup,
+                qos: crate::proto::QoS::AtLeastOnce,
+                packet_identifier: None,
+                retain,
+                payload: bytes::Bytes::from_static(payload),
+                user_properties: vec![],
+                manual_ack: None,
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+            }
+        }
+
+        let mut consumer = super::Consumer::new(super::MemoryProcessedStore::default());
+
+        let mut first = publication(b"a", false, false);
+        assert!(consumer.begin(&mut first).unwrap());
+        consumer.complete(first).unwrap();
+
+        // Redelivery of a processed publication is skipped
+        assert!(!consumer.begin(&mut publication(b"a", true, false)).unwrap());
+        assert!(!consumer.begin(&mut publication(b"a", false, true)).unwrap());
+
+        // A new publication with the same payload is not
+        assert!(consumer.begin(&mut publication(b"a", false, false)).unwrap());
+
+        // Nor is a redelivery of a different publication
+        assert!(consumer.begin(&mut publication(b"b", false, true)).unwrap());
+    }
+}
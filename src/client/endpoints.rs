@@ -0,0 +1,471 @@
+/// A [`crate::io::Connector`] that connects to one of several servers, chosen at random in proportion to their weights.
+///
+/// Endpoints that recently failed to connect are penalized: every failure halves the chance that the endpoint is chosen,
+/// until it connects successfully or has not failed for the time set with [`WeightedConnector::with_failure_memory`].
+/// So the client mostly reconnects to healthy servers, but still tries the others now and then to notice when they recover.
+///
+/// Only failures to establish the I/O connection are seen by the connector. Servers that accept the connection
+/// but refuse the CONNECT are not penalized.
+///
+/// If the endpoints are probed with [`WeightedConnector::prober`], the connector instead connects to the fastest endpoint
+/// that answered the last probe and has not failed recently, if there is one.
+pub struct WeightedConnector<A, F> {
+    endpoints: Vec<(A, u32)>,
+    connect: F,
+    failure_memory: std::time::Duration,
+    session_affinity: Option<std::time::Duration>,
+    clock: super::clock::SharedClock,
+    state: std::sync::Arc<std::sync::Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    health: Vec<Health>,
+
+    /// The endpoint that the connector last connected to successfully
+    sticky: Option<Sticky>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+
+    /// When the endpoint last failed, as read from the clock of the connector
+    last_failure: Option<std::time::Duration>,
+
+    /// How long the last probe of the endpoint took, or `None` if it has not been probed or the probe failed
+    latency: Option<std::time::Duration>,
+}
+
+#[derive(Debug)]
+struct Sticky {
+    index: usize,
+
+    /// When the connector was first asked to reconnect after connecting to this endpoint, as read from the clock of the connector
+    disconnected_at: Option<std::time::Duration>,
+}
+
+/// Each failure halves the weight of an endpoint, up to this many times
+const MAX_PENALTY: u32 = 16;
+
+impl<A, F> WeightedConnector<A, F> {
+    /// Creates a connector for the given endpoints and their weights. `connect` connects to the given endpoint.
+    ///
+    /// Endpoints with zero weight are only chosen if all endpoints have zero weight.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `endpoints` is empty.
+    pub fn new(endpoints: impl IntoIterator<Item = (A, u32)>, connect: F) -> Self {
+        let endpoints: Vec<_> = endpoints.into_iter().collect();
+        assert!(!endpoints.is_empty(), "WeightedConnector needs at least one endpoint");
+
+        let state = State {
+            health: endpoints.iter().map(|_| Health::default()).collect(),
+            sticky: None,
+        };
+
+        WeightedConnector {
+            endpoints,
+            connect,
+            failure_memory: std::time::Duration::from_mins(5),
+            session_affinity: None,
+            clock: Default::default(),
+            state: std::sync::Arc::new(std::sync::Mutex::new(state)),
+        }
+    }
+
+    /// Sets how long failures count against an endpoint. Defaults to five minutes.
+    #[must_use]
+    pub fn with_failure_memory(mut self, failure_memory: std::time::Duration) -> Self {
+        self.failure_memory = failure_memory;
+        self
+    }
+
+    /// Makes the connector reconnect to the endpoint it last connected to successfully, for up to the given time after the connection
+    /// to it was lost, so that the client can resume its persistent session on that server. Defaults to `None`.
+    ///
+    /// Failures to connect to that endpoint within that time do not make the connector choose another one.
+    #[must_use]
+    pub fn with_session_affinity(mut self, session_affinity: Option<std::time::Duration>) -> Self {
+        self.session_affinity = session_affinity;
+        self
+    }
+
+    /// Sets the [`Clock`](super::Clock) that the connector reads the time from to penalize failing endpoints, for session affinity,
+    /// and to time probes. Defaults to [`TokioClock`](super::TokioClock).
+    ///
+    /// Probers returned before this was called keep using the previous clock.
+    #[must_use]
+    pub fn with_clock(mut self, clock: impl super::Clock + 'static) -> Self {
+        self.clock = super::clock::SharedClock::new(clock);
+        self
+    }
+
+    /// Returns a future that probes every endpoint with `probe` every `interval`, and records how long the probes take
+    /// so that the connector can connect to the fastest endpoint.
+    ///
+    /// `probe` should do what connecting does up to the MQTT CONNECT, such as connecting over TCP and doing the TLS handshake.
+    /// Probes that take longer than `interval` fail. The future should be spawned on the runtime,
+    /// and completes when the connector is dropped.
+    pub fn prober<P, PFut, E>(&self, mut probe: P, interval: std::time::Duration) -> impl std::future::Future<Output = ()>
+    where
+        A: Clone + std::fmt::Debug,
+        P: FnMut(&A) -> PFut,
+        PFut: std::future::Future<Output = Result<(), E>>,
+        E: std::fmt::Display,
+    {
+        let addresses: Vec<_> = self.endpoints.iter().map(|(address, _)| address.clone()).collect();
+        let clock = self.clock.clone();
+        let state = std::sync::Arc::downgrade(&self.state);
+
+        async move {
+            loop {
+                for (index, address) in addresses.iter().enumerate() {
+                    let start = clock.now();
+                    let probe = probe(address);
+                    futures_util::pin_mut!(probe);
+                    let latency = match futures_util::future::select(probe, clock.sleep(interval)).await {
+                        futures_util::future::Either::Left((Ok(()), _)) => Some(clock.now().saturating_sub(start)),
+
+                        futures_util::future::Either::Left((Err(err), _)) => {
+                            log::debug!("probe of endpoint {:?} failed: {}", address, err);
+                            None
+                        },
+
+                        futures_util::future::Either::Right(((), _)) => {
+                            log::debug!("probe of endpoint {:?} timed out", address);
+                            None
+                        },
+                    };
+
+                    if let Some(state) = state.upgrade() {
+                        state.lock().expect("endpoints state lock is poisoned").health[index].latency = latency;
+                    }
+                    else {
+                        return;
+                    }
+                }
+
+                clock.sleep(interval).await;
+            }
+        }
+    }
+
+    /// The endpoints and the number of recent failures to connect to them
+    pub fn failures(&self) -> impl Iterator<Item = (&A, u32)> + '_ {
+        let now = self.clock.now();
+        let state = self.state.lock().expect("endpoints state lock is poisoned");
+        let failures: Vec<_> = state.health.iter().map(|health| self.recent_failures(health, now)).collect();
+        self.endpoints.iter().map(|(address, _)| address).zip(failures)
+    }
+
+    fn recent_failures(&self, health: &Health, now: std::time::Duration) -> u32 {
+        match health.last_failure {
+            Some(last_failure) if now.saturating_sub(last_failure) < self.failure_memory => health.failures,
+            _ => 0,
+        }
+    }
+
+    /// Returns the index of the endpoint to connect to
+    fn choose(&self) -> usize {
+        use std::convert::TryFrom;
+
+        let now = self.clock.now();
+        let mut state = self.state.lock().expect("endpoints state lock is poisoned");
+
+        if let (Some(sticky), Some(session_affinity)) = (&mut state.sticky, self.session_affinity) {
+            let disconnected_at = *sticky.disconnected_at.get_or_insert(now);
+            if now.saturating_sub(disconnected_at) < session_affinity {
+                return sticky.index;
+            }
+        }
+        state.sticky = None;
+
+        let fastest = state.health.iter().enumerate()
+            .filter(|(_, health)| self.recent_failures(health, now) == 0)
+            .filter_map(|(index, health)| health.latency.map(|latency| (index, latency)))
+            .min_by_key(|&(_, latency)| latency);
+        if let Some((index, _)) = fastest {
+            return index;
+        }
+
+        // Weights are scaled up so that halving them for failures keeps them distinct from zero
+        let weights: Vec<u64> = self.endpoints.iter().zip(&state.health)
+            .map(|((_, weight), health)| (u64::from(*weight) << MAX_PENALTY) >> self.recent_failures(health, now).min(MAX_PENALTY))
+            .collect();
+
+        let total: u64 = weights.iter().sum();
+        if total == 0 {
+            let len = u64::try_from(self.endpoints.len()).expect("usize fits in u64");
+            return usize::try_from(super::connect::random() % len).expect("index is less than the number of endpoints");
+        }
+
+        let mut random = super::connect::random() % total;
+        for (index, weight) in weights.into_iter().enumerate() {
+            if random < weight {
+                return index;
+            }
+            random -= weight;
+        }
+
+        unreachable!("random number is less than the total weight")
+    }
+}
+
+impl<A, F, Fut, St, Si, E> crate::io::Connector for WeightedConnector<A, F>
+where
+    A: std::fmt::Debug,
+    F: FnMut(&A) -> Fut,
+    Fut: std::future::Future<Output = Result<(St, Si, Option<crate::proto::ByteStr>), E>>,
+    St: crate::io::PacketStream,
+    Si: crate::io::PacketSink,
+{
+    type PacketStream = St;
+    type PacketSink = Si;
+    type Error = E;
+    type Future = EndpointFuture<Fut>;
+
+    fn connect(&mut self) -> Self::Future {
+        let index = self.choose();
+        let (address, _) = &self.endpoints[index];
+        log::debug!("connecting to endpoint {:?}", address);
+
+        let inner = (self.connect)(address);
+        EndpointFuture { inner, index, clock: self.clock.clone(), state: self.state.clone() }
+    }
+}
+
+impl<A, F> std::fmt::Debug for WeightedConnector<A, F> where A: std::fmt::Debug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WeightedConnector")
+            .field("endpoints", &self.endpoints)
+            .field("failure_memory", &self.failure_memory)
+            .field("session_affinity", &self.session_affinity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The connection future of a [`WeightedConnector`]. Records whether the connection to the endpoint succeeded.
+#[pin_project::pin_project]
+pub struct EndpointFuture<F> {
+    #[pin]
+    inner: F,
+    index: usize,
+    clock: super::clock::SharedClock,
+    state: std::sync::Arc<std::sync::Mutex<State>>,
+}
+
+impl<F, T, E> std::future::Future for EndpointFuture<F> where F: std::future::Future<Output = Result<T, E>> {
+    type Output = Result<T, E>;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+        let this = self.project();
+
+        let result = match this.inner.poll(cx) {
+            std::task::Poll::Ready(result) => result,
+            std::task::Poll::Pending => return std::task::Poll::Pending,
+        };
+
+        let mut state = this.state.lock().expect("endpoints state lock is poisoned");
+        let health = &mut state.health[*this.index];
+        if result.is_ok() {
+            health.failures = 0;
+            health.last_failure = None;
+            state.sticky = Some(Sticky { index: *this.index, disconnected_at: None });
+        }
+        else {
+            health.failures = health.failures.saturating_add(1);
+            health.last_failure = Some(this.clock.now());
+        }
+
+        std::task::Poll::Ready(result)
+    }
+}
+
+impl<F> std::fmt::Debug for EndpointFuture<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EndpointFuture").finish_non_exhaustive()
+    }
+}
+
+/// A [`crate::io::Connector`] that can follow the redirects of servers, with [`crate::Client::with_redirect_policy`].
+///
+/// `connect` connects to the server reference that a server redirected the client to, or to the usual server if it is given `None`.
+pub struct RedirectableConnector<F> {
+    connect: F,
+    server_reference: Option<String>,
+}
+
+impl<F> RedirectableConnector<F> {
+    pub fn new(connect: F) -> Self {
+        RedirectableConnector {
+            connect,
+            server_reference: None,
+        }
+    }
+
+    /// The server that the client was redirected to, if it is not connecting to the usual server
+    pub fn server_reference(&self) -> Option<&str> {
+        self.server_reference.as_deref()
+    }
+}
+
+impl<F, Fut, St, Si, E> crate::io::Connector for RedirectableConnector<F>
+where
+    F: FnMut(Option<&str>) -> Fut,
+    Fut: std::future::Future<Output = Result<(St, Si, Option<crate::proto::ByteStr>), E>>,
+    St: crate::io::PacketStream,
+    Si: crate::io::PacketSink,
+{
+    type PacketStream = St;
+    type PacketSink = Si;
+    type Error = E;
+    type Future = Fut;
+
+    fn connect(&mut self) -> Self::Future {
+        (self.connect)(self.server_reference.as_deref())
+    }
+
+    fn redirect(&mut self, server_reference: Option<&str>) -> bool {
+        self.server_reference = server_reference.map(ToOwned::to_owned);
+        true
+    }
+}
+
+impl<F> std::fmt::Debug for RedirectableConnector<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedirectableConnector")
+            .field("server_reference", &self.server_reference)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use crate::io::Connector;
+
+    #[test]
+    fn penalizes_failing_endpoints() {
+        let mut connector = super::WeightedConnector::new(vec![("healthy", 1), ("broken", 1), ("disabled", 0)], |&address: &&str| {
+            futures_util::future::ready(if address == "healthy" {
+                Ok((futures_util::stream::empty::<Result<crate::proto::Packet, crate::proto::DecodeError>>(), futures_util::SinkExt::sink_map_err(futures_util::sink::drain(), |err| match err {}), None))
+            }
+            else {
+                Err(address.to_owned())
+            })
+        });
+
+        let mut connected = 0;
+        let mut failed = vec![];
+        for _ in 0..200 {
+            match futures_util::FutureExt::now_or_never(connector.connect()).unwrap() {
+                Ok(_) => connected += 1,
+                Err(address) => failed.push(address),
+            }
+        }
+
+        // "broken" is chosen about half the time at first, but becomes very unlikely after a few failures
+        assert!(connected > 150, "{}", connected);
+        assert!(failed.iter().all(|address| address == "broken"));
+        assert_eq!(connector.failures().collect::<Vec<_>>(), [(&"healthy", 0), (&"broken", u32::try_from(failed.len()).unwrap()), (&"disabled", 0)]);
+    }
+
+    #[test]
+    fn session_affinity() {
+        let connected = std::cell::RefCell::new(vec![]);
+        let mut connector = super::WeightedConnector::new(vec![("a", 1), ("b", 1)], |&address: &&str| {
+            connected.borrow_mut().push(address.to_owned());
+            futures_util::future::ok::<_, std::convert::Infallible>((
+                futures_util::stream::empty::<Result<crate::proto::Packet, crate::proto::DecodeError>>(),
+                futures_util::SinkExt::sink_map_err(futures_util::sink::drain(), |err| match err {}),
+                None,
+            ))
+        })
+        .with_session_affinity(Some(std::time::Duration::from_secs(60)));
+
+        for _ in 0..20 {
+            let _connection = futures_util::FutureExt::now_or_never(connector.connect()).unwrap().unwrap();
+        }
+
+        let connected = connected.borrow();
+        assert!(connected.iter().all(|address| *address == connected[0]));
+    }
+
+    #[test]
+    fn prefers_fastest_probed_endpoint() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+
+        let connected = std::cell::RefCell::new(vec![]);
+        let mut connector = super::WeightedConnector::new(vec![("slow", 100), ("fast", 1), ("down", 100)], |&address: &&str| {
+            connected.borrow_mut().push(address.to_owned());
+            futures_util::future::ok::<_, std::convert::Infallible>((
+                futures_util::stream::empty::<Result<crate::proto::Packet, crate::proto::DecodeError>>(),
+                futures_util::SinkExt::sink_map_err(futures_util::sink::drain(), |err| match err {}),
+                None,
+            ))
+        });
+
+        let prober = connector.prober(|&address: &&str| async move {
+            match address {
+                "slow" => { tokio::time::sleep(std::time::Duration::from_millis(50)).await; Ok(()) },
+                "fast" => Ok(()),
+                _ => Err("connection refused"),
+            }
+        }, std::time::Duration::from_secs(1));
+        runtime.block_on(async {
+            // The prober never completes while the connector exists, so stop it after the first round of probes
+            let _ = tokio::time::timeout(std::time::Duration::from_millis(200), prober).await;
+        });
+
+        for _ in 0..10 {
+            let _connection = futures_util::FutureExt::now_or_never(connector.connect()).unwrap().unwrap();
+        }
+
+        assert!(connected.borrow().iter().all(|address| address == "fast"));
+    }
+
+    #[test]
+    fn times_with_clock() {
+        type Stream = futures_util::stream::Empty<Result<crate::proto::Packet, crate::proto::DecodeError>>;
+        type Sink = futures_util::sink::SinkMapErr<futures_util::sink::Drain<crate::proto::Packet>, fn(std::convert::Infallible) -> crate::proto::EncodeError>;
+
+        let clock = crate::client::clock::tests::ManualClock::default();
+
+        let mut connector = super::WeightedConnector::new(vec![("a", 1), ("b", 1)], |&address: &&str| {
+            futures_util::future::err::<(Stream, Sink, Option<crate::proto::ByteStr>), _>(address.to_owned())
+        })
+        .with_failure_memory(std::time::Duration::from_secs(60))
+        .with_clock(clock.clone());
+
+        let _ = futures_util::FutureExt::now_or_never(connector.connect()).unwrap();
+        assert_eq!(connector.failures().map(|(_, failures)| failures).sum::<u32>(), 1);
+
+        // Failures are forgotten once the clock of the connector, not the OS clock, has moved past the failure memory
+        clock.advance_to(59);
+        assert_eq!(connector.failures().map(|(_, failures)| failures).sum::<u32>(), 1);
+        clock.advance_to(60);
+        assert_eq!(connector.failures().map(|(_, failures)| failures).sum::<u32>(), 0);
+
+        // Probe latencies are the time that passes on the clock of the connector during each probe
+        let probe_clock = clock.clone();
+        let mut prober = Box::pin(connector.prober(move |&address: &&str| {
+            let clock = probe_clock.clone();
+            async move {
+                match address {
+                    "a" => clock.advance_to(62),
+                    _ => clock.advance_to(63),
+                }
+                Ok::<_, std::convert::Infallible>(())
+            }
+        }, std::time::Duration::from_secs(10)));
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        assert!(futures_util::FutureExt::poll_unpin(&mut prober, &mut cx).is_pending());
+
+        let state = connector.state.lock().unwrap();
+        assert_eq!(state.health.iter().map(|health| health.latency).collect::<Vec<_>>(), [Some(std::time::Duration::from_secs(2)), Some(std::time::Duration::from_secs(1))]);
+    }
+}
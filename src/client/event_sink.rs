@@ -0,0 +1,88 @@
+/// Receives the [`EventRecord`]s of a [`Client`](crate::Client), added with [`Client::with_event_sink`](crate::Client::with_event_sink).
+///
+/// This lets applications send the client's diagnostics to their own logging or alerting system,
+/// regardless of how the `log` crate is set up. It is also implemented for closures.
+///
+/// The sink is called from inside the client's `poll_next`, so it should not block.
+pub trait EventSink {
+    fn record(&mut self, record: &EventRecord<'_>);
+}
+
+impl<F> EventSink for F where F: FnMut(&EventRecord<'_>) {
+    fn record(&mut self, record: &EventRecord<'_>) {
+        self(record);
+    }
+}
+
+/// Something that happened to the connection of a [`Client`](crate::Client)
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Copy)]
+pub enum EventRecord<'a> {
+    /// The client started to connect to the server
+    ConnectAttempt,
+
+    /// The client could not connect to the server, such as because the connector failed or the connection was closed
+    /// before the server sent a CONNACK
+    ConnectFailed(&'a dyn std::fmt::Display),
+
+    /// The server refused the connection
+    ConnectRefused(crate::proto::ConnectionRefusedReason),
+
+    /// The transport did not send the CONNECT within the timeout set with
+    /// [`Client::with_connect_send_timeout`](crate::Client::with_connect_send_timeout)
+    ConnectSendTimedOut,
+
+    /// The server did not send a CONNACK within the timeout set with [`Client::with_conn_ack_timeout`](crate::Client::with_conn_ack_timeout)
+    ConnAckTimedOut,
+
+    /// The server accepted the connection
+    Connected {
+        /// Whether the session was reset as part of this new connection or not
+        reset_session: bool,
+    },
+
+    /// The client will wait for the given time before it connects again
+    BackOff(std::time::Duration),
+
+    /// The client lost its connection because of the given error, such as a packet that could not be decoded, and will reconnect
+    ConnectionLost(&'a super::Error),
+}
+
+impl std::fmt::Debug for EventRecord<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventRecord::ConnectAttempt => f.write_str("ConnectAttempt"),
+            EventRecord::ConnectFailed(err) => f.debug_tuple("ConnectFailed").field(&format_args!("{}", err)).finish(),
+            EventRecord::ConnectRefused(reason) => f.debug_tuple("ConnectRefused").field(reason).finish(),
+            EventRecord::ConnectSendTimedOut => f.write_str("ConnectSendTimedOut"),
+            EventRecord::ConnAckTimedOut => f.write_str("ConnAckTimedOut"),
+            EventRecord::Connected { reset_session } => f.debug_struct("Connected").field("reset_session", reset_session).finish(),
+            EventRecord::BackOff(back_off) => f.debug_tuple("BackOff").field(back_off).finish(),
+            EventRecord::ConnectionLost(err) => f.debug_tuple("ConnectionLost").field(err).finish(),
+        }
+    }
+}
+
+/// The event sink of a client, if any
+#[derive(Default)]
+pub(super) struct Events(Option<Box<dyn EventSink + Send>>);
+
+impl Events {
+    pub(super) fn set(&mut self, sink: Box<dyn EventSink + Send>) {
+        self.0 = Some(sink);
+    }
+
+    pub(super) fn record(&mut self, record: &EventRecord<'_>) {
+        if let Some(sink) = &mut self.0 {
+            sink.record(record);
+        }
+    }
+}
+
+impl std::fmt::Debug for Events {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Events")
+            .field("has_sink", &self.0.is_some())
+            .finish()
+    }
+}
@@ -0,0 +1,401 @@
+/// Two [`Client`](crate::Client)s connected to different servers, one active and one on standby, such as to keep publishing
+/// through a second broker while the first one is down.
+///
+/// The primary client is active at first. If the active client loses its connection while the other one is connected,
+/// the other one becomes active, and the primary client becomes active again as soon as it reconnects. Publications go to the active client,
+/// or to both with [`FailoverPublishMode::Mirror`].
+///
+/// Subscriptions are made with both clients, and a publication received by both, such as from bridged brokers, is only returned once.
+/// A publication counts as a duplicate of one that the other client received with the same topic and payload among its last
+/// [`Failover::with_dedup_window`] publications.
+#[derive(Debug)]
+pub struct Failover<P, S>
+where
+    P: crate::io::Connector,
+    S: crate::io::Connector,
+{
+    primary: super::Client<P>,
+    standby: super::Client<S>,
+    publish_mode: FailoverPublishMode,
+    state: State,
+
+    /// Which client to poll first, alternated so that a busy client does not starve the other
+    poll_standby_first: bool,
+    primary_ended: bool,
+    standby_ended: bool,
+}
+
+/// One of the two clients of a [`Failover`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FailoverSide {
+    Primary,
+    Standby,
+}
+
+impl FailoverSide {
+    fn other(self) -> Self {
+        match self {
+            FailoverSide::Primary => FailoverSide::Standby,
+            FailoverSide::Standby => FailoverSide::Primary,
+        }
+    }
+}
+
+/// Where a [`Failover`] publishes
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FailoverPublishMode {
+    /// Publish with the active client only
+    #[default]
+    Switch,
+
+    /// Publish with both clients. Publishing succeeds if either client publishes.
+    Mirror,
+}
+
+/// An event generated by a [`Failover`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum FailoverEvent {
+    /// An event of one of the clients
+    Client(FailoverSide, super::Event),
+
+    /// The given client became the active one
+    Switched(FailoverSide),
+}
+
+/// An error of one of the clients of a [`Failover`]
+#[derive(Debug)]
+pub struct FailoverError {
+    pub side: FailoverSide,
+    pub error: super::Error,
+}
+
+impl<P, S> Failover<P, S>
+where
+    P: crate::io::Connector,
+    S: crate::io::Connector,
+{
+    pub fn new(primary: super::Client<P>, standby: super::Client<S>) -> Self {
+        Failover {
+            primary,
+            standby,
+            publish_mode: Default::default(),
+            state: Default::default(),
+            poll_standby_first: false,
+            primary_ended: false,
+            standby_ended: false,
+        }
+    }
+
+    /// Sets where publications go. Defaults to [`FailoverPublishMode::Switch`].
+    #[must_use]
+    pub fn with_publish_mode(mut self, publish_mode: FailoverPublishMode) -> Self {
+        self.publish_mode = publish_mode;
+        self
+    }
+
+    /// Sets how many of the latest publications of each client are remembered to recognize the duplicates received by the other client.
+    /// Defaults to 1024.
+    #[must_use]
+    pub fn with_dedup_window(mut self, dedup_window: usize) -> Self {
+        self.state.dedup_window = dedup_window;
+        self
+    }
+
+    /// The client that is currently active
+    pub fn active(&self) -> FailoverSide {
+        self.state.active
+    }
+
+    pub fn primary_mut(&mut self) -> &mut super::Client<P> {
+        &mut self.primary
+    }
+
+    pub fn standby_mut(&mut self) -> &mut super::Client<S> {
+        &mut self.standby
+    }
+
+    /// Queues a message to be published by the active client, or by both clients with [`FailoverPublishMode::Mirror`]
+    pub fn publish(&mut self, publication: crate::proto::Publication) -> impl std::future::Future<Output = Result<(), super::PublishError>> {
+        use futures_util::FutureExt;
+
+        let (active, mirror) = match (self.publish_mode, self.state.active) {
+            (FailoverPublishMode::Switch, FailoverSide::Primary) => (self.primary.publish(publication).left_future(), None),
+            (FailoverPublishMode::Switch, FailoverSide::Standby) => (self.standby.publish(publication).right_future(), None),
+            (FailoverPublishMode::Mirror, FailoverSide::Primary) =>
+                (self.primary.publish(publication.clone()).left_future(), Some(self.standby.publish(publication).right_future())),
+            (FailoverPublishMode::Mirror, FailoverSide::Standby) =>
+                (self.standby.publish(publication.clone()).right_future(), Some(self.primary.publish(publication).left_future())),
+        };
+
+        async move {
+            match mirror {
+                Some(mirror) => match futures_util::future::join(active, mirror).await {
+                    (Ok(()), _) | (_, Ok(())) => Ok(()),
+                    (Err(err), Err(_)) => Err(err),
+                },
+                None => active.await,
+            }
+        }
+    }
+
+    /// Subscribes to a topic with both clients
+    pub fn subscribe(&mut self, subscribe_to: crate::proto::SubscribeTo) -> Result<(), super::UpdateSubscriptionError> {
+        self.primary.subscribe(subscribe_to.clone())?;
+        self.standby.subscribe(subscribe_to)
+    }
+
+    /// Unsubscribes from a topic with both clients
+    pub fn unsubscribe(&mut self, unsubscribe_from: crate::proto::ByteStr) -> Result<(), super::UpdateSubscriptionError> {
+        self.primary.unsubscribe(unsubscribe_from.clone())?;
+        self.standby.unsubscribe(unsubscribe_from)
+    }
+}
+
+impl<P, S> futures_core::Stream for Failover<P, S>
+where
+    P: crate::io::Connector,
+    super::Client<P>: futures_core::Stream<Item = Result<super::Event, super::Error>> + Unpin,
+    S: crate::io::Connector,
+    super::Client<S>: futures_core::Stream<Item = Result<super::Event, super::Error>> + Unpin,
+{
+    type Item = Result<FailoverEvent, FailoverError>;
+
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        if let Some(active) = this.state.switched.take() {
+            return std::task::Poll::Ready(Some(Ok(FailoverEvent::Switched(active))));
+        }
+
+        this.poll_standby_first = !this.poll_standby_first;
+        let sides =
+            if this.poll_standby_first { [FailoverSide::Standby, FailoverSide::Primary] }
+            else { [FailoverSide::Primary, FailoverSide::Standby] };
+
+        for &side in &sides {
+            loop {
+                let (item, ended) = match side {
+                    FailoverSide::Primary => (std::pin::Pin::new(&mut this.primary).poll_next(cx), &mut this.primary_ended),
+                    FailoverSide::Standby => (std::pin::Pin::new(&mut this.standby).poll_next(cx), &mut this.standby_ended),
+                };
+
+                match item {
+                    // Duplicate publications are dropped, so keep polling the same client
+                    std::task::Poll::Ready(Some(Ok(event))) => if let Some(event) = this.state.event(side, event) {
+                        return std::task::Poll::Ready(Some(Ok(FailoverEvent::Client(side, event))));
+                    },
+
+                    std::task::Poll::Ready(Some(Err(error))) => return std::task::Poll::Ready(Some(Err(FailoverError { side, error }))),
+
+                    std::task::Poll::Ready(None) => {
+                        *ended = true;
+                        break;
+                    },
+
+                    std::task::Poll::Pending => break,
+                }
+            }
+        }
+
+        if this.primary_ended && this.standby_ended {
+            std::task::Poll::Ready(None)
+        }
+        else {
+            std::task::Poll::Pending
+        }
+    }
+}
+
+impl std::fmt::Display for FailoverError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} client failed: {}", self.side, self.error)
+    }
+}
+
+impl std::error::Error for FailoverError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Which client is active, and the latest publications of each client
+#[derive(Debug)]
+struct State {
+    active: FailoverSide,
+    primary_connected: bool,
+    standby_connected: bool,
+
+    /// The client that became active, to be returned before the next event
+    switched: Option<FailoverSide>,
+
+    dedup_window: usize,
+    primary_seen: std::collections::VecDeque<u64>,
+    standby_seen: std::collections::VecDeque<u64>,
+}
+
+impl State {
+    /// Handles an event of the given client. Returns the event, or `None` if it is a publication that the other client already returned.
+    fn event(&mut self, side: FailoverSide, event: super::Event) -> Option<super::Event> {
+        match event {
+            super::Event::NewConnection { .. } | super::Event::Connected(_) => {
+                *self.connected(side) = true;
+
+                // Fail back to the primary client as soon as it is connected again
+                if side == FailoverSide::Primary || !*self.connected(self.active) {
+                    self.switch(side);
+                }
+            },
+
+            super::Event::Disconnected(_) => {
+                *self.connected(side) = false;
+
+                if side == self.active && *self.connected(side.other()) {
+                    self.switch(side.other());
+                }
+            },
+
+            super::Event::Publication(mut publication) => {
+                if self.is_duplicate(side, &publication) {
+                    log::debug!("dropping publication to {:?} from the {:?} client that the other client already received", publication.topic_name, side);
+                    publication.ack();
+                    return None;
+                }
+
+                return Some(super::Event::Publication(publication));
+            },
+
+            _ => (),
+        }
+
+        Some(event)
+    }
+
+    fn connected(&mut self, side: FailoverSide) -> &mut bool {
+        match side {
+            FailoverSide::Primary => &mut self.primary_connected,
+            FailoverSide::Standby => &mut self.standby_connected,
+        }
+    }
+
+    fn switch(&mut self, active: FailoverSide) {
+        if self.active != active {
+            log::info!("switching to the {:?} client", active);
+            self.active = active;
+            self.switched = Some(active);
+        }
+    }
+
+    /// Returns whether the other client received the same publication among its latest ones, and forgets it if so,
+    /// or else remembers the publication in case the other client receives it later
+    fn is_duplicate(&mut self, side: FailoverSide, publication: &super::ReceivedPublication) -> bool {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        AsRef::<str>::as_ref(&publication.topic_name).hash(&mut hasher);
+        publication.payload.hash(&mut hasher);
+        let fingerprint = hasher.finish();
+
+        let (seen, other_seen) = match side {
+            FailoverSide::Primary => (&mut self.primary_seen, &mut self.standby_seen),
+            FailoverSide::Standby => (&mut self.standby_seen, &mut self.primary_seen),
+        };
+
+        if let Some(i) = other_seen.iter().position(|&seen| seen == fingerprint) {
+            other_seen.remove(i);
+            return true;
+        }
+
+        if self.dedup_window > 0 {
+            if seen.len() == self.dedup_window {
+                seen.pop_front();
+            }
+            seen.push_back(fingerprint);
+        }
+
+        false
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            active: FailoverSide::Primary,
+            primary_connected: false,
+            standby_connected: false,
+            switched: None,
+            dedup_window: 1024,
+            primary_seen: Default::default(),
+            standby_seen: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FailoverSide::{Primary, Standby};
+
+    fn publication(payload: &'static [u8]) -> crate::Event {
+        crate::Event::Publication(crate::ReceivedPublication {
+            topic_name: "topic".parse().unwrap(),
+            dup: false,
+            qos: crate::proto::QoS::AtMostOnce,
+            packet_identifier: None,
+            retain: false,
+            payload: bytes::Bytes::from_static(payload),
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+            manual_ack: None,
+        })
+    }
+
+    #[test]
+    fn switches() {
+        let mut state = super::State::default();
+        let disconnected = || crate::Event::Disconnected(crate::ConnectionError::ServerClosedConnection);
+
+        // The standby client does not take over while the primary client is connected
+        assert!(state.event(Primary, crate::Event::NewConnection { reset_session: true }).is_some());
+        assert!(state.event(Standby, crate::Event::NewConnection { reset_session: true }).is_some());
+        assert_eq!((state.active, state.switched.take()), (Primary, None));
+
+        assert!(state.event(Primary, disconnected()).is_some());
+        assert_eq!((state.active, state.switched.take()), (Standby, Some(Standby)));
+
+        assert!(state.event(Primary, crate::Event::NewConnection { reset_session: false }).is_some());
+        assert_eq!((state.active, state.switched.take()), (Primary, Some(Primary)));
+
+        // With neither client connected, the first one to connect takes over
+        assert!(state.event(Standby, disconnected()).is_some());
+        assert!(state.event(Primary, disconnected()).is_some());
+        assert_eq!((state.active, state.switched.take()), (Primary, None));
+        assert!(state.event(Standby, crate::Event::NewConnection { reset_session: false }).is_some());
+        assert_eq!((state.active, state.switched.take()), (Standby, Some(Standby)));
+    }
+
+    #[test]
+    fn dedup() {
+        let mut state = super::State::default().with_window(2);
+
+        assert!(state.event(Primary, publication(b"1")).is_some());
+        assert!(state.event(Primary, publication(b"1")).is_some());
+        assert!(state.event(Standby, publication(b"1")).is_none());
+        assert!(state.event(Standby, publication(b"1")).is_none());
+        assert!(state.event(Standby, publication(b"1")).is_some());
+
+        // Only the latest publications of each client are remembered
+        for &payload in &[&b"2"[..], b"3", b"4"] {
+            assert!(state.event(Primary, publication(payload)).is_some());
+        }
+        assert!(state.event(Standby, publication(b"2")).is_some());
+        assert!(state.event(Standby, publication(b"4")).is_none());
+    }
+
+    impl super::State {
+        fn with_window(mut self, dedup_window: usize) -> Self {
+            self.dedup_window = dedup_window;
+            self
+        }
+    }
+}
@@ -0,0 +1,141 @@
+/// When a [`Client`](super::Client) flushes the packets that it wrote to the connection, set with
+/// [`Client::with_flush_strategy`](super::Client::with_flush_strategy).
+///
+/// Flushing less often coalesces small packets into fewer writes, which raises throughput at the cost of latency.
+/// The PUBACKs held back by an [`AckDeferral`](super::AckDeferral) are still held back with every strategy.
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlushStrategy {
+    /// Flushes every packet as soon as it is written, for the lowest latency
+    PerPacket,
+
+    /// Flushes once all the packets that are ready to be sent have been written. This is the default.
+    #[default]
+    Batch,
+
+    /// Lets written packets wait until `max_bytes` of them are waiting, or until `max_delay` after the first of them,
+    /// whichever comes first, for the highest throughput.
+    ///
+    /// Sinks that buffer a limited number of bytes, such as the ones of [`crate::transport`], flush by themselves once
+    /// their buffer is full regardless of `max_bytes`.
+    Coalesce {
+        max_bytes: usize,
+        max_delay: std::time::Duration,
+    },
+}
+
+#[derive(Debug, Default)]
+pub(super) struct State {
+    strategy: FlushStrategy,
+
+    /// The number of bytes written to the connection since the last flush, only counted with [`FlushStrategy::Coalesce`]
+    unflushed_bytes: usize,
+
+    /// Whether any packet was written to the connection since the last flush
+    unflushed: bool,
+
+    timer: Option<super::clock::Timer>,
+    clock: super::clock::SharedClock,
+}
+
+impl State {
+    pub(super) fn set_strategy(&mut self, strategy: FlushStrategy) {
+        self.strategy = strategy;
+        self.flushed();
+    }
+
+    pub(super) fn set_clock(&mut self, clock: super::clock::SharedClock) {
+        self.clock = clock;
+    }
+
+    /// Returns whether the connection should be flushed right after every packet that is written to it
+    pub(super) fn per_packet(&self) -> bool {
+        self.strategy == FlushStrategy::PerPacket
+    }
+
+    pub(super) fn packet_sent(&mut self, packet: &crate::proto::Packet) {
+        if let FlushStrategy::Coalesce { .. } = self.strategy {
+            // A packet that cannot be encoded fails to be written anyway
+            self.unflushed_bytes += crate::proto::encoded_len(packet.clone()).unwrap_or_default();
+        }
+        self.unflushed = true;
+    }
+
+    /// Returns whether the connection should be flushed now, or else waits for the written packets to be coalesced with more of them.
+    ///
+    /// This does not consider the connection to be flushed, since an [`AckDeferral`](super::AckDeferral) may still hold it back.
+    /// [`State::flushed`] must be called once it is.
+    pub(super) fn poll_flush(&mut self, cx: &mut std::task::Context<'_>) -> bool {
+        let max_delay = match self.strategy {
+            FlushStrategy::Coalesce { max_bytes, max_delay } if self.unflushed && self.unflushed_bytes < max_bytes => max_delay,
+            _ => return true,
+        };
+
+        let clock = &self.clock;
+        let timer = self.timer.get_or_insert_with(|| super::clock::Timer(clock.sleep(max_delay)));
+        futures_util::FutureExt::poll_unpin(&mut timer.0, cx).is_ready()
+    }
+
+    pub(super) fn new_connection(&mut self) {
+        self.flushed();
+    }
+
+    pub(super) fn flushed(&mut self) {
+        self.unflushed_bytes = 0;
+        self.unflushed = false;
+        self.timer = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn ping() -> crate::proto::Packet {
+        crate::proto::Packet::PingReq(crate::proto::PingReq)
+    }
+
+    #[test]
+    fn coalesces() {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let mut state = super::State::default();
+        state.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+
+        // Batches are flushed right away by default
+        state.packet_sent(&ping());
+        assert!(!state.per_packet());
+        assert!(state.poll_flush(&mut cx));
+        state.flushed();
+
+        // PINGREQs are two bytes each
+        state.set_strategy(super::FlushStrategy::Coalesce { max_bytes: 6, max_delay: std::time::Duration::from_secs(1) });
+
+        // Nothing to flush
+        assert!(state.poll_flush(&mut cx));
+
+        // Packets wait for the delay to end
+        state.packet_sent(&ping());
+        assert!(!state.poll_flush(&mut cx));
+        clock.advance_to(1);
+        assert!(state.poll_flush(&mut cx));
+        state.flushed();
+
+        // ... or for enough bytes
+        state.packet_sent(&ping());
+        state.packet_sent(&ping());
+        assert!(!state.poll_flush(&mut cx));
+        state.packet_sent(&ping());
+        assert!(state.poll_flush(&mut cx));
+        state.flushed();
+
+        // A new connection starts over
+        state.packet_sent(&ping());
+        assert!(!state.poll_flush(&mut cx));
+        state.new_connection();
+        assert!(state.poll_flush(&mut cx));
+
+        state.set_strategy(super::FlushStrategy::PerPacket);
+        assert!(state.per_packet());
+    }
+}
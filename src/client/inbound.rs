@@ -0,0 +1,233 @@
+/// A step of the path that publications received by the [`Client`](crate::Client) take to the application,
+/// added with [`Client::with_inbound_middleware`](crate::Client::with_inbound_middleware).
+///
+/// Middleware can change publications, such as to decrypt their payloads, or drop them, such as to filter or deduplicate them.
+/// It is also implemented for closures.
+pub trait InboundMiddleware {
+    /// Handles the given publication. Returns `false` to drop it.
+    ///
+    /// Dropped publications are not returned to the application. In manual ack mode, the client acks them.
+    fn handle(&mut self, publication: &mut crate::ReceivedPublication) -> bool;
+}
+
+impl<F> InboundMiddleware for F where F: FnMut(&mut crate::ReceivedPublication) -> bool {
+    fn handle(&mut self, publication: &mut crate::ReceivedPublication) -> bool {
+        self(publication)
+    }
+}
+
+/// An [`InboundMiddleware`] that counts the publications received by the client.
+///
+/// Clones share the same counts, so a clone can be added to the client and the original used to read them.
+#[derive(Clone, Debug, Default)]
+pub struct InboundCounters(std::sync::Arc<InboundCountersInner>);
+
+#[derive(Debug, Default)]
+struct InboundCountersInner {
+    publications: std::sync::atomic::AtomicU64,
+    payload_bytes: std::sync::atomic::AtomicU64,
+}
+
+impl InboundCounters {
+    /// The number of publications received
+    pub fn publications(&self) -> u64 {
+        self.0.publications.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// The total size of the payloads of the publications received
+    pub fn payload_bytes(&self) -> u64 {
+        self.0.payload_bytes.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+impl InboundMiddleware for InboundCounters {
+    fn handle(&mut self, publication: &mut crate::ReceivedPublication) -> bool {
+        self.0.publications.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.0.payload_bytes.fetch_add(publication.payload.len() as u64, std::sync::atomic::Ordering::Relaxed);
+        true
+    }
+}
+
+/// An [`InboundMiddleware`] that drops publications whose payloads do not satisfy its rules,
+/// such as to keep large or unwanted publications on noisy topics from reaching a slow application.
+///
+/// Each rule applies to the topics that start with its topic prefix. An empty prefix applies the rule to all topics.
+/// Clones share the same count of dropped publications.
+#[derive(Clone, Debug, Default)]
+pub struct PayloadFilter {
+    rules: Vec<(String, PayloadRule)>,
+    dropped: std::sync::Arc<std::sync::atomic::AtomicU64>,
+}
+
+#[derive(Clone)]
+enum PayloadRule {
+    MaxSize(usize),
+    Prefix(bytes::Bytes),
+    Predicate(PayloadPredicate),
+}
+
+type PayloadPredicate = std::sync::Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+impl PayloadFilter {
+    /// Drops publications to topics starting with `topic_prefix` whose payloads are larger than `max_size` bytes
+    #[must_use]
+    pub fn max_size(mut self, topic_prefix: impl Into<String>, max_size: usize) -> Self {
+        self.rules.push((topic_prefix.into(), PayloadRule::MaxSize(max_size)));
+        self
+    }
+
+    /// Drops publications to topics starting with `topic_prefix` whose payloads do not start with `prefix`
+    #[must_use]
+    pub fn payload_prefix(mut self, topic_prefix: impl Into<String>, prefix: impl Into<bytes::Bytes>) -> Self {
+        self.rules.push((topic_prefix.into(), PayloadRule::Prefix(prefix.into())));
+        self
+    }
+
+    /// Drops publications to topics starting with `topic_prefix` whose payloads do not satisfy `predicate`
+    #[must_use]
+    pub fn predicate(mut self, topic_prefix: impl Into<String>, predicate: impl Fn(&[u8]) -> bool + Send + Sync + 'static) -> Self {
+        self.rules.push((topic_prefix.into(), PayloadRule::Predicate(std::sync::Arc::new(predicate))));
+        self
+    }
+
+    /// The number of publications dropped by the filter
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+impl InboundMiddleware for PayloadFilter {
+    fn handle(&mut self, publication: &mut crate::ReceivedPublication) -> bool {
+        let topic_name: &str = publication.topic_name.as_ref();
+
+        let accepted = self.rules.iter()
+            .filter(|(topic_prefix, _)| topic_name.starts_with(topic_prefix.as_str()))
+            .all(|(_, rule)| match rule {
+                PayloadRule::MaxSize(max_size) => publication.payload.len() <= *max_size,
+                PayloadRule::Prefix(prefix) => publication.payload.starts_with(prefix),
+                PayloadRule::Predicate(predicate) => predicate(&publication.payload),
+            });
+
+        if !accepted {
+            self.dropped.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        accepted
+    }
+}
+
+impl std::fmt::Debug for PayloadRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadRule::MaxSize(max_size) => f.debug_tuple("MaxSize").field(max_size).finish(),
+            PayloadRule::Prefix(prefix) => f.debug_tuple("Prefix").field(prefix).finish(),
+            PayloadRule::Predicate(_) => f.write_str("Predicate"),
+        }
+    }
+}
+
+/// The inbound middleware of a client, in the order it was added
+#[derive(Default)]
+pub(super) struct Pipeline(Vec<Box<dyn InboundMiddleware + Send>>);
+
+impl Pipeline {
+    pub(super) fn push(&mut self, middleware: Box<dyn InboundMiddleware + Send>) {
+        self.0.push(middleware);
+    }
+
+    /// Passes the given publication through the middleware. Returns `None` if any of them dropped it.
+    pub(super) fn handle(&mut self, mut publication: crate::ReceivedPublication) -> Option<crate::ReceivedPublication> {
+        for middleware in &mut self.0 {
+            if !middleware.handle(&mut publication) {
+                log::debug!("publication to {:?} was dropped by inbound middleware", publication.topic_name);
+                publication.ack();
+                return None;
+            }
+        }
+
+        Some(publication)
+    }
+}
+
+impl std::fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("len", &self.0.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn pipeline() {
+        fn publication(topic_name: &str, payload: &'static [u8]) -> crate::ReceivedPublication {
+            crate::ReceivedPublication {
+                topic_name: topic_name.parse().unwrap(),
+                dup: false,
+                qos: crate::proto::QoS::AtMostOnce,
+                packet_identifier: None,
+                retain: false,
+                payload: bytes::Bytes::from_static(payload),
+                user_properties: vec![],
+                manual_ack: None,
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+            }
+        }
+
+        let counters = super::InboundCounters::default();
+
+        let mut pipeline = super::Pipeline::default();
+        pipeline.push(Box::new(counters.clone()));
+        pipeline.push(Box::new(|publication: &mut crate::ReceivedPublication| !AsRef::<str>::as_ref(&publication.topic_name).starts_with("internal/")));
+        pipeline.push(Box::new(|publication: &mut crate::ReceivedPublication| {
+            publication.payload = publication.payload.iter().rev().copied().collect::<Vec<_>>().into();
+            true
+        }));
+
+        assert_eq!(pipeline.handle(publication("telemetry", b"abc")), Some(publication("telemetry", b"cba")));
+        assert_eq!(pipeline.handle(publication("internal/debug", b"de")), None);
+
+        assert_eq!(counters.publications(), 2);
+        assert_eq!(counters.payload_bytes(), 5);
+    }
+
+    #[test]
+    fn payload_filter() {
+        fn publication(topic_name: &str, payload: &'static [u8]) -> crate::ReceivedPublication {
+            crate::ReceivedPublication {
+                topic_name: topic_name.parse().unwrap(),
+                dup: false,
+                qos: crate::proto::QoS::AtMostOnce,
+                packet_identifier: None,
+                retain: false,
+                payload: bytes::Bytes::from_static(payload),
+                user_properties: vec![],
+                manual_ack: None,
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+            }
+        }
+
+        let filter =
+            super::PayloadFilter::default()
+            .max_size("", 8)
+            .payload_prefix("json/", &b"{"[..])
+            .predicate("text/", |payload| std::str::from_utf8(payload).is_ok());
+
+        let mut pipeline = super::Pipeline::default();
+        pipeline.push(Box::new(filter.clone()));
+
+        assert!(pipeline.handle(publication("json/a", b"{}")).is_some());
+        assert!(pipeline.handle(publication("json/a", b"[]")).is_none());
+        assert!(pipeline.handle(publication("text/a", b"hello")).is_some());
+        assert!(pipeline.handle(publication("text/a", b"\xff")).is_none());
+        assert!(pipeline.handle(publication("raw", b"\xff")).is_some());
+        assert!(pipeline.handle(publication("raw", b"too large!")).is_none());
+
+        assert_eq!(filter.dropped(), 3);
+    }
+}
@@ -0,0 +1,178 @@
+/// The user property that [`LoopGuard`] records hops in, unless changed with [`LoopGuard::with_property_name`]
+const DEFAULT_PROPERTY_NAME: &str = "mqtt3-bridge-hop";
+
+/// Keeps publications from looping forever between bridges that forward publications from one server to another,
+/// such as in a mesh of servers bridged both ways.
+///
+/// As a [`super::PublishLayer`] on the publisher that forwards publications, it stamps every publication with a hop:
+/// a user property whose value is the identifier of the bridge. Hops accumulate as publications are forwarded from bridge to bridge.
+/// As an [`super::InboundMiddleware`] on the client that receives the publications to forward, it drops the publications
+/// that already went through this bridge, and optionally the ones that went through too many bridges.
+///
+/// Hops are user properties, so they are only kept by MQTT 5.0 servers. When both sides of a bridge are the same server,
+/// subscribing with [`crate::proto::SubscribeTo::no_local`] also keeps the bridge from receiving its own publications.
+/// Clones share the same count of dropped publications.
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Debug)]
+pub struct LoopGuard {
+    id: crate::proto::ByteStr,
+    property_name: crate::proto::ByteStr,
+    max_hops: Option<usize>,
+    dropped: std::sync::Arc<std::sync::atomic::AtomicU64>,
+}
+
+impl LoopGuard {
+    /// Creates a guard for the bridge with the given identifier, which must be unique among the bridges that publications can go through
+    pub fn new(id: crate::proto::ByteStr) -> Self {
+        LoopGuard {
+            id,
+            property_name: DEFAULT_PROPERTY_NAME.parse().expect("default property name is valid"),
+            max_hops: None,
+            dropped: Default::default(),
+        }
+    }
+
+    /// Sets the name of the user property that hops are recorded in. Defaults to `mqtt3-bridge-hop`.
+    #[must_use]
+    pub fn with_property_name(mut self, property_name: crate::proto::ByteStr) -> Self {
+        self.property_name = property_name;
+        self
+    }
+
+    /// Also drops the received publications that already went through more than `max_hops` bridges. Not set by default.
+    #[must_use]
+    pub fn with_max_hops(mut self, max_hops: usize) -> Self {
+        self.max_hops = Some(max_hops);
+        self
+    }
+
+    /// The number of received publications that were dropped
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// The identifiers of the bridges that the given publication went through, in order
+    pub fn hops<'a>(&'a self, user_properties: &'a [(crate::proto::ByteStr, crate::proto::ByteStr)]) -> impl Iterator<Item = &'a crate::proto::ByteStr> {
+        user_properties.iter().filter(move |(name, _)| *name == self.property_name).map(|(_, value)| value)
+    }
+}
+
+impl<S> super::PublishLayer<S> for LoopGuard where S: super::PublishService {
+    type Service = LoopGuardService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LoopGuardService { inner, guard: self.clone() }
+    }
+}
+
+/// The [`super::PublishService`] created by [`LoopGuard`]
+#[derive(Clone, Debug)]
+pub struct LoopGuardService<S> {
+    inner: S,
+    guard: LoopGuard,
+}
+
+impl<S> super::PublishService for LoopGuardService<S> where S: super::PublishService {
+    fn publish(&mut self, mut publication: crate::proto::Publication) -> super::PublishFuture {
+        publication.user_properties.push((self.guard.property_name.clone(), self.guard.id.clone()));
+        self.inner.publish(publication)
+    }
+}
+
+impl super::InboundMiddleware for LoopGuard {
+    fn handle(&mut self, publication: &mut crate::ReceivedPublication) -> bool {
+        let mut hops = 0;
+        for hop in self.hops(&publication.user_properties) {
+            if *hop == self.id {
+                log::debug!("dropping publication to {:?} that already went through this bridge", publication.topic_name);
+                self.dropped.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                return false;
+            }
+
+            hops += 1;
+        }
+
+        if matches!(self.max_hops, Some(max_hops) if hops > max_hops) {
+            log::debug!("dropping publication to {:?} that went through {} bridges", publication.topic_name, hops);
+            self.dropped.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            return false;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{InboundMiddleware, PublishService};
+
+    /// Records the user properties of all publications
+    #[derive(Clone, Default)]
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<Vec<(crate::proto::ByteStr, crate::proto::ByteStr)>>>>);
+
+    impl PublishService for Recorder {
+        fn publish(&mut self, publication: crate::proto::Publication) -> crate::client::PublishFuture {
+            self.0.lock().unwrap().push(publication.user_properties);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn forward(guard: &super::LoopGuard, user_properties: Vec<(crate::proto::ByteStr, crate::proto::ByteStr)>) -> Vec<(crate::proto::ByteStr, crate::proto::ByteStr)> {
+        let recorder = Recorder::default();
+        let mut publisher = crate::client::PublishServiceBuilder::new().layer(guard.clone()).service(recorder.clone());
+        let _ = publisher.publish(crate::proto::Publication {
+            topic_name: "topic".parse().unwrap(),
+            qos: crate::proto::QoS::AtMostOnce,
+            retain: false,
+            payload: bytes::Bytes::from_static(b"payload"),
+            user_properties,
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        });
+        let mut recorded = recorder.0.lock().unwrap();
+        recorded.pop().unwrap()
+    }
+
+    fn receive(guard: &mut super::LoopGuard, user_properties: Vec<(crate::proto::ByteStr, crate::proto::ByteStr)>) -> bool {
+        guard.handle(&mut crate::ReceivedPublication {
+            topic_name: "topic".parse().unwrap(),
+            dup: false,
+            qos: crate::proto::QoS::AtMostOnce,
+            packet_identifier: None,
+            retain: false,
+            payload: bytes::Bytes::from_static(b"payload"),
+            user_properties,
+            manual_ack: None,
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+        })
+    }
+
+    #[test]
+    fn drops_loops() {
+        let mut a = super::LoopGuard::new("a".parse().unwrap());
+        let mut b = super::LoopGuard::new("b".parse().unwrap());
+        let mut c = super::LoopGuard::new("c".parse().unwrap());
+
+        // a -> b -> c -> a
+        let user_properties = vec![("tenant".parse().unwrap(), "1".parse().unwrap())];
+        assert!(receive(&mut a, user_properties.clone()));
+        let user_properties = forward(&a, user_properties);
+        assert!(receive(&mut b, user_properties.clone()));
+        let user_properties = forward(&b, user_properties);
+        assert!(receive(&mut c, user_properties.clone()));
+        let user_properties = forward(&c, user_properties);
+        assert!(!receive(&mut a, user_properties.clone()));
+        assert_eq!(a.dropped(), 1);
+
+        assert_eq!(a.hops(&user_properties).map(AsRef::as_ref).collect::<Vec<&str>>(), ["a", "b", "c"]);
+        assert_eq!(AsRef::<str>::as_ref(&user_properties[0].0), "tenant");
+
+        let mut d = super::LoopGuard::new("d".parse().unwrap()).with_max_hops(2);
+        assert!(!receive(&mut d, user_properties));
+        assert_eq!(d.dropped(), 1);
+    }
+}
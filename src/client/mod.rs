@@ -1,14 +1,109 @@
 use std::future::Future;
 
+mod ack_deferral;
+pub use ack_deferral::AckDeferral;
+
+mod auth;
+pub use auth::{AuthStep, Authenticator};
+
+mod codec;
+pub use codec::{DecodedPublications, PayloadCodec};
+#[cfg(feature = "prost")]
+pub use codec::ProstCodec;
+
+mod clock;
+pub use clock::{Clock, ClockSleep, TokioClock};
+
+mod config;
+pub use config::{ConfigHandle, ConfigUpdate, ConfigUpdateError};
+
+mod conflate;
+pub use conflate::{Conflated, Conflation};
+
+mod compression;
+pub use compression::{
+    CompressionCodec, CompressionConnector, CompressionConnectorFuture, CompressionSink, CompressionStream, TopicCompression, TopicCompressionError,
+};
+#[cfg(feature = "flate2")]
+pub use compression::Deflate;
+
 mod connect;
+pub use connect::{ProtocolFallback, ReconnectJitter, RedirectPolicy};
+
+mod connection_stability;
+pub use connection_stability::{ConnectionStability, ConnectionStabilityStats};
+
+mod consumer;
+pub use consumer::{Consumer, MemoryProcessedStore, ProcessedStore};
+
+mod endpoints;
+pub use endpoints::{EndpointFuture, RedirectableConnector, WeightedConnector};
+
+mod failover;
+pub use failover::{Failover, FailoverError, FailoverEvent, FailoverPublishMode, FailoverSide};
+
+mod event_sink;
+pub use event_sink::{EventRecord, EventSink};
+
+mod flush;
+pub use flush::FlushStrategy;
+
+mod inbound;
+pub use inbound::{InboundCounters, InboundMiddleware, PayloadFilter};
+
+mod payload_sizes;
+pub use payload_sizes::{PayloadSizeHistogram, PayloadSizes};
+
+mod loop_guard;
+pub use loop_guard::{LoopGuard, LoopGuardService};
 
 mod ping;
 
+mod presence;
+pub use presence::Presence;
+
 mod publish;
-pub use publish::{PublishError, PublishHandle};
+pub use publish::{
+    DeliveryMode, DuplicatePolicy, ManualAck, PublishBeforeFirstConnection, PublishError, PublishHandle, PublishPacing, PublishPriority, ReplayOrdering, RetransmitPolicy,
+};
+
+mod publish_service;
+pub use publish_service::{
+    DeadlineLayer, DeadlineService, IdentityLayer, LayerStack, LogLayer, LogService, MapPublicationLayer, MapPublicationService,
+    PublishFuture, PublishLayer, PublishService, PublishServiceBuilder, RetryLayer, RetryService,
+};
+
+mod queued;
+
+pub mod rpc;
+
+#[cfg(feature = "schema-registry")]
+mod schema_registry;
+#[cfg(feature = "schema-registry")]
+pub use schema_registry::{SchemaCodec, SchemaRegistry, SchemaRegistryCodec, SchemaRegistryCodecError};
+#[cfg(feature = "avro")]
+pub use schema_registry::{AvroCodec, AvroCodecError, AvroValue};
+
+mod sequence;
+pub use sequence::{GapDetector, Sequence, SequenceLayer, SequenceService};
+
+mod slow_consumer;
+pub use slow_consumer::{SlowConsumerStats, SlowConsumerThresholds};
 
 mod subscriptions;
-pub use subscriptions::{UpdateSubscriptionError, UpdateSubscriptionHandle};
+pub use subscriptions::{SubscriptionGroup, UnsolicitedPublicationPolicy, UpdateSubscriptionError, UpdateSubscriptionHandle};
+
+mod tap;
+pub use tap::{PacketTap, TappedPacket};
+
+#[cfg(test)]
+mod test_server;
+
+mod topic_limits;
+pub use topic_limits::{TopicLimitExceeded, TopicLimits};
+
+mod topic_rewrite;
+pub use topic_rewrite::TopicRewriter;
 
 /// An MQTT v3.1.1 client.
 ///
@@ -20,8 +115,8 @@ pub use subscriptions::{UpdateSubscriptionError, UpdateSubscriptionHandle};
 /// Subscribe to and unsubscribe from topics using the handle returned by [`Client::update_subscription_handle`].
 ///
 /// The [`Stream`] only ends (returns `Ready(None)`) when the client is told to shut down gracefully using the handle
-/// returned by [`Client::shutdown_handle`]. The `Client` becomes unusable after it has returned `None`
-/// and should be dropped.
+/// returned by [`Client::shutdown_handle`]. Polling the `Client` again after it has returned `None` returns
+/// [`Error::PolledAfterShutDown`], unless it is started again with [`Client::restart`].
 #[derive(Debug)]
 pub struct Client<C>(ClientState<C>)
 where
@@ -35,8 +130,11 @@ where
     ///
     /// * `client_id`
     ///
-    ///     If set, this ID will be used to start a new clean session with the server. On subsequent re-connects, the ID will be re-used.
-    ///     Otherwise, the client will use a server-generated ID for each new connection.
+    ///     If set, this ID will be used to start a new clean session with the server. On subsequent re-connects, the ID will be re-used to resume the session,
+    ///   unless changed with [`Client::with_session_expiry`].
+    ///   Otherwise, the client will use a server-generated ID for each new connection, with a clean session every time.
+    ///   An empty ID is the same as no ID. If the server rejects the empty ID because it does not generate IDs,
+    ///   the client generates a random ID and keeps using it, still with a clean session on every connection.
     ///
     /// * `username`
     ///
@@ -45,14 +143,17 @@ where
     /// * `connector`
     ///
     ///     The MQTT protocol is layered onto the I/O object returned by this source.
+    ///   Use [`Client::with_transport`] instead to connect over any [`crate::transport::Transport`] that opens a connection of bytes.
     ///
     /// * `max_reconnect_back_off`
     ///
     ///     Every connection failure will double the back-off period, to a maximum of this value.
     ///
+    ///     The back-off periods are randomized according to [`ReconnectJitter::Equal`] unless changed with [`Client::with_reconnect_jitter`].
+    ///
     /// * `keep_alive`
     ///
-    ///     The keep-alive time advertised to the server. The client will ping the server at half this interval.
+    ///     The keep-alive time advertised to the server. The client will ping the server if it hasn't sent any other packet for half this interval, or hasn't received any packet for the whole interval, unless changed with [`Client::with_always_ping`].
     pub fn new(
         client_id: Option<crate::proto::ByteStr>,
         username: Option<crate::proto::ByteStr>,
@@ -62,11 +163,12 @@ where
         keep_alive: std::time::Duration,
     ) -> Self {
         let client_id = match client_id {
-            Some(id) => crate::proto::ClientId::IdWithCleanSession(id),
-            None => crate::proto::ClientId::ServerGenerated,
+            Some(id) if !id.is_empty() => crate::proto::ClientId::IdWithCleanSession(id),
+            _ => crate::proto::ClientId::ServerGenerated,
         };
 
         let (shutdown_send, shutdown_recv) = futures_channel::mpsc::channel(0);
+        let (config_send, config_recv) = futures_channel::mpsc::unbounded();
 
         // TODO: username / password / will can be too large and prevent a CONNECT packet from being encoded.
         //       `Client::new()` should detect that and return an error.
@@ -80,30 +182,483 @@ where
 
             shutdown_send,
             shutdown_recv,
+            config_send,
+            config_recv,
+            closed: Default::default(),
+            cancellation: Default::default(),
 
             packet_identifiers: Default::default(),
 
             connect: connect::Connect::new(connector, max_reconnect_back_off),
-            ping: ping::State::BeginWaitingForNextPing,
+            ping: Default::default(),
             publish: Default::default(),
             subscriptions: Default::default(),
+            slow_consumer: Default::default(),
+            ack_deferral: Default::default(),
+            flush: Default::default(),
+            connection_stability: Default::default(),
+            presence: None,
+            inbound: Default::default(),
+            payload_sizes: Default::default(),
 
             packets_waiting_to_be_sent: Default::default(),
         })
     }
 
+    /// Sets how the back-off periods between reconnection attempts are randomized. Defaults to [`ReconnectJitter::Equal`].
+    #[must_use]
+    pub fn with_reconnect_jitter(mut self, reconnect_jitter: ReconnectJitter) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_reconnect_jitter(reconnect_jitter);
+        }
+        self
+    }
+
+    /// Sets whether the client connects to another server when the server redirects it there. Defaults to [`RedirectPolicy::Ignore`].
+    ///
+    /// Following redirects needs a connector that can connect to other servers, such as a [`RedirectableConnector`].
+    #[must_use]
+    pub fn with_redirect_policy(mut self, redirect_policy: RedirectPolicy) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_redirect_policy(redirect_policy);
+        }
+        self
+    }
+
+    /// Sets how long the server keeps the session after the connection is lost. Defaults to `None`, meaning the session never expires.
+    ///
+    /// With MQTT 5.0, this is sent as the Session Expiry Interval, in whole seconds. MQTT 3.1.1 sessions never expire, so there a session expiry
+    /// of zero makes the client start a clean session on every connection, and any other value keeps the session like `None` does.
+    ///
+    /// If the server did not keep the session by the time the client reconnects, the client resubscribes and starts the exactly-once flows
+    /// of unacked publications over, like it does after any clean session.
+    /// This has no effect without a client ID, since a server-generated ID starts a clean session on every connection.
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_session_expiry(mut self, session_expiry: Option<std::time::Duration>) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_session_expiry(session_expiry);
+        }
+        self
+    }
+
+    /// Sets how long the server waits after the connection is lost before it publishes the will that was passed to [`Client::new`].
+    /// Defaults to `None`, meaning the server publishes the will as soon as it notices that the connection was lost.
+    ///
+    /// With MQTT 5.0, this is sent as the Will Delay Interval, in whole seconds, and the will is not published at all
+    /// if the client reconnects to its session before the delay has passed. The server also publishes the will when the session expires,
+    /// if that happens first. MQTT 3.1.1 has no equivalent, so there the will is always published right away.
+    ///
+    /// The will itself can carry the user properties, content type and other properties of a [`crate::proto::Publication`],
+    /// which are likewise only sent to MQTT 5.0 servers.
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_will_delay(mut self, will_delay: Option<std::time::Duration>) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_will_delay(will_delay);
+        }
+        self
+    }
+
+    /// Sets the size in bytes of the largest packet that the client accepts from the server. Defaults to `None`, meaning no limit.
+    ///
+    /// With MQTT 5.0, this is sent as the Maximum Packet Size, and the server drops the publications that would not fit instead of sending them.
+    /// The other way around, a publication whose PUBLISH packet is larger than the Maximum Packet Size advertised by the server fails
+    /// with [`PublishError::PacketTooLarge`] instead of being sent, since the server would drop the connection.
+    /// MQTT 3.1.1 has no equivalent, so there neither side limits the size of packets.
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_maximum_packet_size(mut self, maximum_packet_size: Option<u32>) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_maximum_packet_size(maximum_packet_size);
+        }
+        self
+    }
+
+    /// Restricts the packet identifiers that the client assigns to its publications, subscriptions and unsubscriptions
+    /// to the given range. Defaults to every packet identifier.
+    ///
+    /// Packet identifiers are scoped to the session, so cooperating processes that take over the same session from each other,
+    /// such as during a handover, can give each client a disjoint range to never collide on the packet identifiers
+    /// of the flows that the previous process left unacked. With fewer packet identifiers, the client also runs out of them
+    /// with fewer flows in flight, and then returns [`Error::PacketIdentifiersExhausted`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is empty.
+    #[must_use]
+    pub fn with_packet_identifier_range(mut self, range: std::ops::RangeInclusive<crate::proto::PacketIdentifier>) -> Self {
+        assert!(!range.is_empty(), "packet identifier range must not be empty");
+
+        if let ClientState::Up { packet_identifiers, .. } = &mut self.0 {
+            packet_identifiers.set_range(range);
+        }
+        self
+    }
+
+    /// Sets how long the client waits for the server to respond to a CONNECT before it gives up on the connection and reconnects.
+    /// Defaults to `None`, meaning the client waits for as long as the connection stays open.
+    #[must_use]
+    pub fn with_conn_ack_timeout(mut self, conn_ack_timeout: Option<std::time::Duration>) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_conn_ack_timeout(conn_ack_timeout);
+        }
+        self
+    }
+
+    /// Sets how long the transport can take to send the CONNECT after the connector's future resolved, before the client gives up
+    /// on the connection and reconnects. Defaults to `None`, meaning the client waits for as long as the transport takes.
+    ///
+    /// The client always starts sending the CONNECT as soon as the connector's future resolves. Some servers close connections
+    /// that don't send a CONNECT within a deadline as short as a second, so with a transport that is slow to set up, such as one
+    /// that only performs its handshake when it's first written to, this should be set to within that deadline. The connection
+    /// attempt then fails fast and is retried, instead of waiting for the server to close it.
+    #[must_use]
+    pub fn with_connect_send_timeout(mut self, connect_send_timeout: Option<std::time::Duration>) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_connect_send_timeout(connect_send_timeout);
+        }
+        self
+    }
+
+    /// Sets the protocol level the client asks for in its CONNECT. Defaults to [`crate::PROTOCOL_LEVEL`], meaning MQTT 3.1.1.
+    ///
+    /// With [`crate::proto::v5::PROTOCOL_LEVEL`], the client asks for MQTT 5.0, and falls back to MQTT 3.1.1 for the rest of its life
+    /// if the server refuses the protocol level, unless changed with [`Client::with_protocol_fallback`]. The transport returned by the connector must then frame the packets of each connection
+    /// with the protocol level of its CONNECT, such as with [`crate::proto::v5::NegotiatedEncoder`] and [`crate::proto::v5::NegotiatedDecoder`].
+    /// An encoder created with [`crate::proto::v5::NegotiatedDecoder::encoder`] also sends publications with topic aliases if the server allows them.
+    #[cfg(feature = "v5")]
+    #[must_use]
+    pub fn with_protocol_level(mut self, protocol_level: u8) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_protocol_level(protocol_level);
+        }
+        self
+    }
+
+    /// Sets what the client does when the server refuses the protocol level set with [`Client::with_protocol_level`].
+    /// Defaults to [`ProtocolFallback::Sticky`].
+    #[cfg(feature = "v5")]
+    #[must_use]
+    pub fn with_protocol_fallback(mut self, protocol_fallback: ProtocolFallback) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_protocol_fallback(protocol_fallback);
+        }
+        self
+    }
+
+    /// Sets the [`Authenticator`] that performs enhanced authentication with the server. Its method and initial data are sent in every CONNECT,
+    /// and it answers the AUTH challenges of the server until the server sends CONNACK. A connection whose authentication fails is retried
+    /// after the usual back-off.
+    ///
+    /// Enhanced authentication is part of MQTT 5.0, so the client must also be set up with [`Client::with_protocol_level`].
+    #[allow(clippy::doc_markdown)]
+    #[cfg(feature = "v5")]
+    #[must_use]
+    pub fn with_authenticator(mut self, authenticator: impl Authenticator + Send + 'static) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_authenticator(Box::new(authenticator));
+        }
+        self
+    }
+
+    /// Sets the [`Clock`] that the client reads the time from for keep-alive pings, reconnection back-offs, lease refreshes and timeouts.
+    /// Defaults to [`TokioClock`].
+    ///
+    /// Handles returned by the client before this was called keep using the previous clock.
+    #[must_use]
+    pub fn with_clock(mut self, new_clock: impl Clock + 'static) -> Self {
+        if let ClientState::Up { connect, ping, publish, subscriptions, slow_consumer, ack_deferral, flush, connection_stability, .. } = &mut self.0 {
+            let new_clock = clock::SharedClock::new(new_clock);
+            connect.set_clock(new_clock.clone());
+            ping.set_clock(new_clock.clone());
+            publish.set_clock(new_clock.clone());
+            subscriptions.set_clock(new_clock.clone());
+            slow_consumer.set_clock(new_clock.clone());
+            ack_deferral.set_clock(new_clock.clone());
+            flush.set_clock(new_clock.clone());
+            connection_stability.set_clock(new_clock);
+        }
+        self
+    }
+
+    /// Enables [`Event::SlowConsumer`], which reports when the application polls the client too slowly to keep up with
+    /// the packets received from the server. Disabled by default.
+    #[must_use]
+    pub fn with_slow_consumer_thresholds(mut self, thresholds: SlowConsumerThresholds) -> Self {
+        if let ClientState::Up { slow_consumer, .. } = &mut self.0 {
+            slow_consumer.set_thresholds(Some(thresholds));
+        }
+        self
+    }
+
+    /// Lets the client hold back the PUBACKs of received QoS 1 publications for up to the given window, so that the PUBACKs of
+    /// many publications are written with a single flush. Disabled by default, meaning every PUBACK is flushed right away.
+    ///
+    /// This reduces the number of writes to the connection at very high inbound rates, at the cost of the server
+    /// keeping the publications in flight for a little longer.
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_ack_deferral(mut self, ack_deferral: AckDeferral) -> Self {
+        if let ClientState::Up { ack_deferral: state, .. } = &mut self.0 {
+            state.set_ack_deferral(Some(ack_deferral));
+        }
+        self
+    }
+
+    /// Sets when the client flushes the packets that it wrote to the connection. Defaults to [`FlushStrategy::Batch`].
+    #[must_use]
+    pub fn with_flush_strategy(mut self, strategy: FlushStrategy) -> Self {
+        if let ClientState::Up { flush, .. } = &mut self.0 {
+            flush.set_strategy(strategy);
+        }
+        self
+    }
+
+    /// Sets how long it takes for a reconnect, or for a period of being connected or disconnected, to count half as much
+    /// in the [`ConnectionStability`] of the client. Defaults to an hour.
+    #[must_use]
+    pub fn with_connection_stability_half_life(mut self, half_life: std::time::Duration) -> Self {
+        if let ClientState::Up { connection_stability, .. } = &mut self.0 {
+            connection_stability.set_half_life(half_life);
+        }
+        self
+    }
+
+    /// Sets whether new connections are reported with [`Event::Connected`], which carries the details of the CONNACK,
+    /// instead of [`Event::NewConnection`]. Defaults to `false`.
+    #[must_use]
+    pub fn with_connected_events(mut self, connected_events: bool) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_connected_events(connected_events);
+        }
+        self
+    }
+
+    /// Sets whether the client pings the server at half the keep-alive interval even if it has sent other packets in that time.
+    /// Defaults to `false`.
+    ///
+    /// By default, publications and other packets sent to the server take the place of pings, which saves waking up
+    /// a battery-powered device just to ping. The client still pings if it has not received anything from the server
+    /// for a whole keep-alive interval, so that the connection still carries traffic in both directions.
+    #[must_use]
+    pub fn with_always_ping(mut self, always_ping: bool) -> Self {
+        if let ClientState::Up { ping, .. } = &mut self.0 {
+            ping.set_always_ping(always_ping);
+        }
+        self
+    }
+
+    /// Sets how publications with QoS 1 or 2 are pipelined. Defaults to [`DeliveryMode::Pipelined`].
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_delivery_mode(mut self, delivery_mode: DeliveryMode) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_delivery_mode(delivery_mode);
+        }
+        self
+    }
+
+    /// Sets what happens to QoS 1 publications that were not acked when the connection was lost. Defaults to [`RetransmitPolicy::ResendAsDuplicate`].
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_retransmit_policy(mut self, retransmit_policy: RetransmitPolicy) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_retransmit_policy(retransmit_policy);
+        }
+        self
+    }
+
+    /// Sets what happens to QoS 1 publications received with the DUP flag set. Defaults to [`DuplicatePolicy::Deliver`].
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_duplicate_policy(mut self, duplicate_policy: DuplicatePolicy) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_duplicate_policy(duplicate_policy);
+        }
+        self
+    }
+
+    /// Sets what happens to publications received to topics that do not match any of the client's subscriptions.
+    /// Defaults to [`UnsolicitedPublicationPolicy::Deliver`].
+    #[must_use]
+    pub fn with_unsolicited_publication_policy(mut self, unsolicited_publication_policy: UnsolicitedPublicationPolicy) -> Self {
+        if let ClientState::Up { subscriptions, .. } = &mut self.0 {
+            subscriptions.set_unsolicited_publication_policy(unsolicited_publication_policy);
+        }
+        self
+    }
+
+    /// Adds middleware that received publications pass through before they are returned to the application.
+    ///
+    /// Middleware runs in the order it was added.
+    #[must_use]
+    pub fn with_inbound_middleware(mut self, middleware: impl InboundMiddleware + Send + 'static) -> Self {
+        if let ClientState::Up { inbound, .. } = &mut self.0 {
+            inbound.push(Box::new(middleware));
+        }
+        self
+    }
+
+    /// Sets the [`EventSink`] that is called with the connection events of the client, such as connection attempts and back-offs.
+    /// Replaces the sink set before, if any.
+    #[must_use]
+    pub fn with_event_sink(mut self, sink: impl EventSink + Send + 'static) -> Self {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.set_event_sink(Box::new(sink));
+        }
+        self
+    }
+
+    /// Shuts the client down when the given token is cancelled, as if its [`ShutdownHandle`] had been used.
+    ///
+    /// This lets the client take part in the graceful shutdown of the application. Unlike with the [`ShutdownHandle`],
+    /// publications that were not published yet are dropped, and their publish requests fail with [`PublishError::ClientClosed`].
+    /// If the client is started again with [`Client::restart`], it shuts down again immediately.
+    #[cfg(feature = "tokio-util")]
+    #[must_use]
+    pub fn with_cancellation_token(mut self, token: tokio_util::sync::CancellationToken) -> Self {
+        if let ClientState::Up { cancellation, .. } = &mut self.0 {
+            *cancellation = Cancellation::new(async move { token.cancelled().await });
+        }
+        self
+    }
+
+    /// Sets the maximum time that a publication can be queued before it is sent. Defaults to `None`, which means no limit.
+    ///
+    /// Publications that are still queued after this time, such as because the client was disconnected for a long time, are dropped,
+    /// and their publish requests fail with [`PublishError::Expired`]. This is useful for data like telemetry, where sending stale values
+    /// is worse than not sending them.
+    #[must_use]
+    pub fn with_max_message_age(mut self, max_message_age: Option<std::time::Duration>) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_max_message_age(max_message_age);
+        }
+        self
+    }
+
+    /// Sets how the publications that were queued while the client was disconnected are sent once it has connected.
+    /// Defaults to [`PublishPacing::Burst`].
+    ///
+    /// Brokers with rate limits may disconnect a client that sends a large backlog of publications right after connecting,
+    /// which [`PublishPacing::SpreadOverKeepAlive`] avoids.
+    #[must_use]
+    pub fn with_publish_pacing(mut self, publish_pacing: PublishPacing) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_publish_pacing(publish_pacing);
+        }
+        self
+    }
+
+    /// Sets the order of the publications that were queued while the client was disconnected, relative to the ones published
+    /// after it has connected again. Defaults to [`ReplayOrdering::QueuedFirst`].
+    #[must_use]
+    pub fn with_replay_ordering(mut self, replay_ordering: ReplayOrdering) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_replay_ordering(replay_ordering);
+        }
+        self
+    }
+
+    /// Sets what happens to publications requested before the client has connected for the first time.
+    /// Defaults to [`PublishBeforeFirstConnection::Queue`].
+    #[must_use]
+    pub fn with_publish_before_first_connection(mut self, publish_before_first_connection: PublishBeforeFirstConnection) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_publish_before_first_connection(publish_before_first_connection);
+        }
+        self
+    }
+
+    /// Sets whether received QoS 1 and QoS 2 publications are acked to the server automatically, or only once the application calls
+    /// [`ReceivedPublication::ack`]. Defaults to `false`.
+    ///
+    /// In manual ack mode, the server sends a publication again if the client reconnects without having acked it,
+    /// so publications are not lost if the application stops while processing them. QoS 2 publications are returned to the application
+    /// as soon as they are received, rather than once the server releases them.
+    ///
+    /// Acks for publications received before the session was reset are ignored. A publication whose [`ManualAck`] is dropped
+    /// without being acked is returned to the application again when the server sends it again.
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_manual_acks(mut self, manual_acks: bool) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_manual_acks(manual_acks);
+        }
+        self
+    }
+
+    /// Sets the rules for rewriting the topics of publications sent and received by the client.
+    #[must_use]
+    pub fn with_topic_rewriter(mut self, topic_rewriter: TopicRewriter) -> Self {
+        if let ClientState::Up { publish, .. } = &mut self.0 {
+            publish.set_topic_rewriter(topic_rewriter);
+        }
+        self
+    }
+
+    /// Sets the limits on the topics of publications and the topic filters of subscriptions. See [`TopicLimits`] for details.
+    ///
+    /// Update subscription handles created before this is called do not check the new limits.
+    #[must_use]
+    pub fn with_topic_limits(mut self, topic_limits: TopicLimits) -> Self {
+        if let ClientState::Up { publish, subscriptions, .. } = &mut self.0 {
+            publish.set_topic_limits(topic_limits);
+            subscriptions.set_topic_limits(topic_limits);
+        }
+        self
+    }
+
+    /// Makes the client announce whether it is online with retained messages on a presence topic. See [`Presence`] for details.
+    ///
+    /// This replaces the will passed to [`Client::new`] with [`Presence::offline_publication`].
+    #[must_use]
+    pub fn with_presence(mut self, new_presence: Presence) -> Self {
+        if let ClientState::Up { will, presence, .. } = &mut self.0 {
+            *will = Some(new_presence.offline_publication());
+            *presence = Some(presence::State::new(new_presence));
+        }
+        self
+    }
+
+    /// Sets the will that is registered with the server on subsequent connections.
+    ///
+    /// The will of the current connection, if any, is not affected. It is replaced when the client next reconnects.
+    /// If the client was configured with [`Client::with_presence`], setting a different will means the server no longer announces
+    /// that the client went offline.
+    pub fn set_will(&mut self, new_will: Option<crate::proto::Publication>) {
+        let up = match &mut self.0 {
+            up @ ClientState::Up { .. } => up,
+            ClientState::ShuttingDown { up, .. } | ClientState::ShutDown { up: Some(up), .. } => &mut **up,
+            ClientState::ShutDown { up: None, .. } => return,
+        };
+
+        if let ClientState::Up { will, .. } = up {
+            *will = new_will;
+        }
+    }
+
     /// Queues a message to be published to the server
     pub fn publish(
         &mut self,
         publication: crate::proto::Publication,
+    ) -> impl Future<Output = Result<(), PublishError>> {
+        self.publish_with_priority(publication, PublishPriority::default())
+    }
+
+    /// Queues a message to be published to the server, ahead of any queued messages of lower priority
+    pub fn publish_with_priority(
+        &mut self,
+        publication: crate::proto::Publication,
+        priority: PublishPriority,
     ) -> impl Future<Output = Result<(), PublishError>> {
         match &mut self.0 {
             ClientState::Up { publish, .. } => {
-                futures_util::future::Either::Left(publish.publish(publication))
+                futures_util::future::Either::Left(publish.publish(publication, priority))
             }
             ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => {
                 futures_util::future::Either::Right(futures_util::future::err(
-                    PublishError::ClientDoesNotExist,
+                    PublishError::ClientClosed,
                 ))
             }
         }
@@ -112,9 +667,9 @@ where
     /// Returns a handle that can be used to publish messages to the server
     pub fn publish_handle(&self) -> Result<PublishHandle, PublishError> {
         match &self.0 {
-            ClientState::Up { publish, .. } => Ok(publish.publish_handle()),
+            ClientState::Up { publish, closed, .. } => Ok(publish.publish_handle(closed.clone())),
             ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => {
-                Err(PublishError::ClientDoesNotExist)
+                Err(PublishError::ClientClosed)
             }
         }
     }
@@ -127,7 +682,52 @@ where
         match &mut self.0 {
             ClientState::Up { subscriptions, .. } => subscriptions.subscribe(subscribe_to),
             ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => {
-                Err(UpdateSubscriptionError::ClientDoesNotExist)
+                Err(UpdateSubscriptionError::ClientClosed)
+            }
+        }
+    }
+
+    /// Subscribes to a topic with the given parameters, and subscribes to it again every half of the given lease time,
+    /// for servers and gateways that expire subscriptions which are not renewed.
+    ///
+    /// Refreshes are sent while the client is connected, and do not generate [`Event::SubscriptionUpdates`] when they succeed.
+    /// If the server rejects a refresh, the client sends [`SubscriptionUpdateEvent::RefreshRejectedByServer`] and no longer considers itself
+    /// subscribed to the topic filter. Unsubscribing from the topic filter ends the lease.
+    pub fn subscribe_with_lease(
+        &mut self,
+        subscribe_to: crate::proto::SubscribeTo,
+        lease: std::time::Duration,
+    ) -> Result<(), UpdateSubscriptionError> {
+        match &mut self.0 {
+            ClientState::Up { subscriptions, .. } => subscriptions.subscribe_with_lease(subscribe_to, lease),
+            ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => {
+                Err(UpdateSubscriptionError::ClientClosed)
+            }
+        }
+    }
+
+    /// Changes the QoS of a subscription by subscribing to its topic filter again, which the server applies in place
+    /// without a gap in delivery.
+    ///
+    /// The QoS granted by the server is reported with [`SubscriptionUpdateEvent::QoSUpdated`], even if it is lower than the one requested.
+    #[allow(clippy::doc_markdown)]
+    pub fn update_subscription(
+        &mut self,
+        topic_filter: crate::proto::ByteStr,
+        qos: crate::proto::QoS,
+    ) -> Result<(), UpdateSubscriptionError> {
+        match &mut self.0 {
+            ClientState::Up { subscriptions, .. } => subscriptions.update_qos(crate::proto::SubscribeTo {
+                topic_filter,
+                qos,
+                user_properties: vec![],
+                subscription_identifier: None,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: Default::default(),
+            }),
+            ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => {
+                Err(UpdateSubscriptionError::ClientClosed)
             }
         }
     }
@@ -137,7 +737,7 @@ where
         match &mut self.0 {
             ClientState::Up { subscriptions, .. } => subscriptions.unsubscribe(unsubscribe_from),
             ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => {
-                Err(UpdateSubscriptionError::ClientDoesNotExist)
+                Err(UpdateSubscriptionError::ClientClosed)
             }
         }
     }
@@ -147,13 +747,115 @@ where
         &self,
     ) -> Result<UpdateSubscriptionHandle, UpdateSubscriptionError> {
         match &self.0 {
-            ClientState::Up { subscriptions, .. } => Ok(subscriptions.update_subscription_handle()),
+            ClientState::Up { subscriptions, closed, .. } => Ok(subscriptions.update_subscription_handle(closed.clone())),
             ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => {
-                Err(UpdateSubscriptionError::ClientDoesNotExist)
+                Err(UpdateSubscriptionError::ClientClosed)
             }
         }
     }
 
+    /// Returns the parameters in effect for the current connection, or `None` if the client is not currently connected.
+    ///
+    /// Applications can use this after [`Event::NewConnection`] or [`Event::Connected`] to adapt to the server's restrictions,
+    /// such as by not publishing retained messages when [`ConnectionParameters::retain_available`] is `false`.
+    pub fn connection_parameters(&self) -> Option<ConnectionParameters> {
+        match &self.0 {
+            ClientState::Up { connect, .. } => connect.connection_parameters(),
+            ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => None,
+        }
+    }
+
+    /// Returns a [`PacketTap`] that receives every packet sent and received by the client from now on, holding up to `capacity` packets.
+    ///
+    /// Only one tap can be active at a time. Requesting a new tap ends the previous one.
+    pub fn packet_tap(&mut self, capacity: usize) -> PacketTap {
+        match &mut self.0 {
+            ClientState::Up { connect, .. } => connect.packet_tap(capacity),
+            ClientState::ShuttingDown { up, .. } => match &mut **up {
+                ClientState::Up { connect, .. } => connect.packet_tap(capacity),
+                ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => unreachable!(),
+            },
+            ClientState::ShutDown { .. } => tap::Tap::default().new_tap(capacity),
+        }
+    }
+
+    /// Returns the histograms of the payload sizes of the publications sent and received by the client, which keep counting
+    /// as the client runs.
+    pub fn payload_sizes(&self) -> PayloadSizes {
+        match &self.0 {
+            ClientState::Up { payload_sizes, .. } => payload_sizes.clone(),
+            ClientState::ShuttingDown { up, .. } | ClientState::ShutDown { up: Some(up), .. } => match &**up {
+                ClientState::Up { payload_sizes, .. } => payload_sizes.clone(),
+                ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => unreachable!(),
+            },
+            ClientState::ShutDown { up: None, .. } => PayloadSizes::default(),
+        }
+    }
+
+    /// Returns the statistics of how stable the connection of the client is, which keep updating as the client runs,
+    /// such as for dashboards to rank unhealthy clients.
+    pub fn connection_stability(&self) -> ConnectionStability {
+        match &self.0 {
+            ClientState::Up { connection_stability, .. } => connection_stability.clone(),
+            ClientState::ShuttingDown { up, .. } | ClientState::ShutDown { up: Some(up), .. } => match &**up {
+                ClientState::Up { connection_stability, .. } => connection_stability.clone(),
+                ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => unreachable!(),
+            },
+            ClientState::ShutDown { up: None, .. } => ConnectionStability::default(),
+        }
+    }
+
+    /// Returns the number of publications received to topics that did not match any of the client's subscriptions.
+    /// Only counted if enabled with [`Client::with_unsolicited_publication_policy`].
+    pub fn unsolicited_publications(&self) -> u64 {
+        match &self.0 {
+            ClientState::Up { subscriptions, .. } => subscriptions.unsolicited_publications(),
+            ClientState::ShuttingDown { up, .. } | ClientState::ShutDown { up: Some(up), .. } => match &**up {
+                ClientState::Up { subscriptions, .. } => subscriptions.unsolicited_publications(),
+                ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => unreachable!(),
+            },
+            ClientState::ShutDown { up: None, .. } => 0,
+        }
+    }
+
+    /// Starts a re-authentication with the server, with the [`Authenticator`] set with [`Client::with_authenticator`], such as before the
+    /// credentials of the current authentication expire. The connection is kept while the server checks the new credentials.
+    ///
+    /// Nothing happens if the current connection does not use MQTT 5.0. A re-authentication requested while the client is not connected
+    /// is not started, since every connection authenticates anyway.
+    #[allow(clippy::doc_markdown)]
+    #[cfg(feature = "v5")]
+    pub fn reauthenticate(&mut self) {
+        if let ClientState::Up { connect, .. } = &mut self.0 {
+            connect.reauthenticate();
+        }
+    }
+
+    /// Starts a new session with the same configuration.
+    ///
+    /// The current connection, if any, is dropped, and the client connects again immediately with a clean session.
+    /// As whenever the server resets the session, the client subscribes again to its subscriptions and resends publications
+    /// that were not acked.
+    ///
+    /// This also starts a client that has shut down, or is shutting down, again. Handles that were returned by the client before
+    /// it shut down keep working.
+    pub fn restart(&mut self) {
+        let mut up = match std::mem::replace(&mut self.0, ClientState::ShutDown { up: None, reason: None, ended: false }) {
+            ClientState::ShuttingDown { up, .. } | ClientState::ShutDown { up: Some(up), .. } => *up,
+            state => state,
+        };
+
+        if let ClientState::Up { client_id, connect, closed, .. } = &mut up {
+            log::info!("Restarting with a new session...");
+
+            use_clean_session(client_id);
+            connect.restart();
+            closed.set(false);
+        }
+
+        self.0 = up;
+    }
+
     /// Returns a handle that can be used to signal the client to shut down
     pub fn shutdown_handle(&self) -> Result<ShutdownHandle, ShutdownError> {
         match &self.0 {
@@ -163,6 +865,49 @@ where
             }
         }
     }
+
+    /// Returns a handle that can be used to change some of the settings of the client while it runs, such as its keep-alive
+    /// and reconnection back-off, without restarting it. See [`ConfigUpdate`].
+    pub fn config_handle(&self) -> Result<ConfigHandle, ConfigUpdateError> {
+        match &self.0 {
+            ClientState::Up { config_send, .. } => Ok(ConfigHandle(config_send.clone())),
+            ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => {
+                Err(ConfigUpdateError::ClientDoesNotExist)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "transport-tokio")]
+impl<T> Client<crate::transport::TransportConnector<T>>
+where
+    T: crate::transport::Transport,
+{
+    /// Create a new client with the given parameters and a clean session, that connects to the server over the given
+    /// [`crate::transport::Transport`] instead of a [`crate::io::Connector`]. The client frames the connections that the transport opens.
+    ///
+    /// The parameters are the same as those of [`Client::new`], except for:
+    ///
+    /// * `password`
+    ///
+    ///     Optional password credential for the server, sent in every CONNECT.
+    ///
+    /// * `transport`
+    ///
+    ///     Opens the connections of bytes that the MQTT protocol is layered onto. Any [`FnMut`] that returns a future of a connection,
+    ///   such as `move || tokio::net::TcpStream::connect(address)`, is a transport.
+    pub fn with_transport(
+        client_id: Option<crate::proto::ByteStr>,
+        username: Option<crate::proto::ByteStr>,
+        password: Option<crate::proto::ByteStr>,
+        will: Option<crate::proto::Publication>,
+        transport: T,
+        max_reconnect_back_off: std::time::Duration,
+        keep_alive: std::time::Duration,
+    ) -> Self {
+        let connector = crate::transport::TransportConnector::new(transport).with_password(password);
+        Client::new(client_id, username, will, connector, max_reconnect_back_off, keep_alive)
+    }
 }
 
 impl<C> futures_core::Stream for Client<C>
@@ -182,7 +927,7 @@ where
     ) -> std::task::Poll<Option<Self::Item>> {
         use futures_sink::Sink;
 
-        let reason = loop {
+        let (reason, publish_will) = loop {
             match &mut self.0 {
                 ClientState::Up {
                     client_id,
@@ -191,6 +936,8 @@ where
                     keep_alive,
 
                     shutdown_recv,
+                    config_recv,
+                    cancellation,
 
                     packet_identifiers,
 
@@ -198,21 +945,46 @@ where
                     ping,
                     publish,
                     subscriptions,
+                    slow_consumer,
+                    ack_deferral,
+                    flush,
+                    connection_stability,
+                    presence,
+                    inbound,
+                    payload_sizes,
 
                     packets_waiting_to_be_sent,
                     ..
                 } => {
                     match std::pin::Pin::new(shutdown_recv).poll_next(cx) {
-                        std::task::Poll::Ready(Some(())) => break None,
+                        std::task::Poll::Ready(Some(request)) => break (None, request == ShutdownRequest::PublishWill),
 
                         std::task::Poll::Ready(None) | std::task::Poll::Pending => (),
                     }
 
+                    if cancellation.poll_cancelled(cx) {
+                        log::debug!("cancellation token was cancelled");
+                        publish.close(cx, packet_identifiers);
+                        break (None, false);
+                    }
+
+                    while let std::task::Poll::Ready(Some(update)) = std::pin::Pin::new(&mut *config_recv).poll_next(cx) {
+                        update.apply(keep_alive, connect, publish);
+                    }
+
+                    publish.poll_before_first_connection(cx);
+
+                    publish.poll_deadlines(cx);
+
                     let connect::Connected {
                         stream,
                         sink,
+                        tap,
+                        auth,
                         new_connection,
                         reset_session,
+                        connection_parameters,
+                        event: connected_event,
                     } = match connect.poll(
                         cx,
                         username.as_ref(),
@@ -227,32 +999,67 @@ where
                     if new_connection {
                         log::debug!("New connection established");
 
-                        *packets_waiting_to_be_sent = Default::default();
+                        packets_waiting_to_be_sent.clear();
 
                         ping.new_connection();
 
+                        slow_consumer.new_connection();
+
+                        ack_deferral.new_connection();
+
+                        flush.new_connection();
+
+                        connection_stability.new_connection();
+
                         packets_waiting_to_be_sent
-                            .extend(publish.new_connection(reset_session, packet_identifiers));
+                            .extend(publish.new_connection(
+                                reset_session,
+                                connection_parameters.keep_alive,
+                                connection_parameters.receive_maximum,
+                                connection_parameters.maximum_packet_size,
+                                connection_parameters.maximum_qos,
+                                packet_identifiers,
+                            ));
 
                         packets_waiting_to_be_sent.extend(
-                            subscriptions.new_connection(reset_session, packet_identifiers),
+                            subscriptions.new_connection(reset_session, connection_parameters.maximum_qos, packet_identifiers),
                         );
 
-                        return std::task::Poll::Ready(Some(Ok(Event::NewConnection {
-                            reset_session,
+                        if let Some(presence) = presence {
+                            presence.new_connection(cx, publish);
+                        }
+
+                        return std::task::Poll::Ready(Some(Ok(match connected_event {
+                            Some(connected_event) => Event::Connected(connected_event),
+                            None => Event::NewConnection { reset_session },
                         })));
                     }
 
+                    if let Some(publications) = publish.take_publications_not_acked() {
+                        return std::task::Poll::Ready(Some(Ok(Event::PublicationsNotAcked(publications))));
+                    }
+
+                    if let Some(stats) = slow_consumer.take_stats_to_report() {
+                        return std::task::Poll::Ready(Some(Ok(Event::SlowConsumer(stats))));
+                    }
+
                     match client_poll(
                         cx,
                         stream,
                         sink,
-                        *keep_alive,
+                        tap,
+                        auth,
+                        connection_parameters.keep_alive,
                         packets_waiting_to_be_sent,
                         packet_identifiers,
                         ping,
                         publish,
                         subscriptions,
+                        slow_consumer,
+                        ack_deferral,
+                        flush,
+                        inbound,
+                        payload_sizes,
                     ) {
                         std::task::Poll::Ready(Ok(event)) => {
                             return std::task::Poll::Ready(Some(Ok(event)))
@@ -260,25 +1067,22 @@ where
 
                         std::task::Poll::Ready(Err(err)) => {
                             if err.is_user_error() {
-                                break Some(err);
+                                break (Some(err), false);
                             }
                             log::warn!("client will reconnect because of error: {}", err);
+                            connect.record_event(&EventRecord::ConnectionLost(&err));
+                            connection_stability.connection_lost();
 
                             if !err.session_is_resumable() {
                                 // Ensure clean session if the error is such that the session is not resumable.
                                 //
                                 // DEVNOTE: subscriptions::State relies on the fact that the session is reset here.
                                 // Update that if this ever changes.
-                                *client_id = match std::mem::replace(
-                                    client_id,
-                                    crate::proto::ClientId::ServerGenerated,
-                                ) {
-                                    id @ crate::proto::ClientId::ServerGenerated
-                                    | id @ crate::proto::ClientId::IdWithCleanSession(_) => id,
-                                    crate::proto::ClientId::IdWithExistingSession(id) => {
-                                        crate::proto::ClientId::IdWithCleanSession(id)
-                                    }
-                                };
+                                use_clean_session(client_id);
+                            }
+
+                            if let Error::ServerDisconnected(Some(server_reference)) = &err {
+                                connect.redirect(server_reference);
                             }
 
                             connect.reconnect();
@@ -295,18 +1099,18 @@ where
                 }
 
                 ClientState::ShuttingDown {
-                    client_id,
-                    username,
-                    will,
-                    keep_alive,
-
-                    connect,
-
+                    up,
                     sent_disconnect,
-
-                    reason,
+                    publish_will,
+                    ..
                 } => {
-                    let connect::Connected { mut sink, .. } = match connect.poll(
+                    let (client_id, username, will, keep_alive, connect) = match &mut **up {
+                        ClientState::Up { client_id, username, will, keep_alive, connect, .. } =>
+                            (client_id, username, will, keep_alive, connect),
+                        ClientState::ShuttingDown { .. } | ClientState::ShutDown { .. } => unreachable!(),
+                    };
+
+                    let connect::Connected { mut sink, tap, connection_parameters, .. } = match connect.poll(
                         cx,
                         username.as_ref(),
                         will.as_ref(),
@@ -316,29 +1120,39 @@ where
                         std::task::Poll::Ready(connected) => connected,
                         std::task::Poll::Pending => {
                             // Already disconnected
-                            self.0 = ClientState::ShutDown {
-                                reason: reason.take(),
-                            };
+                            self.0.shut_down();
                             continue;
                         }
                     };
 
+                    // An MQTT 5.0 DISCONNECT can ask the server to publish the will. With MQTT 3.1.1, dropping the connection
+                    // without sending DISCONNECT makes the server publish it.
+                    let reason =
+                        if !*publish_will {
+                            crate::proto::DisconnectReason::Normal
+                        }
+                        else if connection_parameters.protocol_level == crate::PROTOCOL_LEVEL {
+                            log::debug!("closing connection without DISCONNECT so that the server publishes the will");
+                            connect.reconnect();
+                            self.0.shut_down();
+                            continue;
+                        }
+                        else {
+                            crate::proto::DisconnectReason::WithWill
+                        };
+
                     loop {
                         if *sent_disconnect {
                             match std::pin::Pin::new(&mut sink).poll_flush(cx) {
                                 std::task::Poll::Ready(Ok(())) => {
-                                    self.0 = ClientState::ShutDown {
-                                        reason: reason.take(),
-                                    };
+                                    self.0.shut_down();
                                     break;
                                 }
 
                                 std::task::Poll::Ready(Err(err)) => {
                                     let err = Error::EncodePacket(err);
                                     log::warn!("couldn't send DISCONNECT: {}", err);
-                                    self.0 = ClientState::ShutDown {
-                                        reason: reason.take(),
-                                    };
+                                    self.0.shut_down();
                                     break;
                                 }
 
@@ -347,16 +1161,17 @@ where
                         }
                         match std::pin::Pin::new(&mut sink).poll_ready(cx) {
                             std::task::Poll::Ready(Ok(())) => {
-                                let packet =
-                                    crate::proto::Packet::Disconnect(crate::proto::Disconnect);
+                                let packet = crate::proto::Packet::Disconnect(crate::proto::Disconnect {
+                                    reason,
+                                    server_reference: None,
+                                });
+                                tap.sent(&packet);
                                 match std::pin::Pin::new(&mut sink).start_send(packet) {
                                     Ok(()) => *sent_disconnect = true,
 
                                     Err(err) => {
                                         log::warn!("couldn't send DISCONNECT: {}", err);
-                                        self.0 = ClientState::ShutDown {
-                                            reason: reason.take(),
-                                        };
+                                        self.0.shut_down();
                                         break;
                                     }
                                 }
@@ -364,9 +1179,7 @@ where
 
                             std::task::Poll::Ready(Err(err)) => {
                                 log::warn!("couldn't send DISCONNECT: {}", err);
-                                self.0 = ClientState::ShutDown {
-                                    reason: reason.take(),
-                                };
+                                self.0.shut_down();
                                 break;
                             }
 
@@ -375,44 +1188,42 @@ where
                     }
                 }
 
-                ClientState::ShutDown { reason } => match reason.take() {
-                    Some(err) => return std::task::Poll::Ready(Some(Err(err))),
-                    None => return std::task::Poll::Ready(None),
-                },
+                ClientState::ShutDown { reason, ended, .. } => {
+                    if let Some(err) = reason.take() {
+                        return std::task::Poll::Ready(Some(Err(err)));
+                    }
+
+                    if *ended {
+                        return std::task::Poll::Ready(Some(Err(Error::PolledAfterShutDown)));
+                    }
+
+                    *ended = true;
+                    return std::task::Poll::Ready(None);
+                }
             }
         };
 
         // If we're here, then we're transitioning from Up to ShuttingDown
 
-        match std::mem::replace(&mut self.0, ClientState::ShutDown { reason: None }) {
-            ClientState::Up {
-                client_id,
-                username,
-                will,
-                keep_alive,
-
-                connect,
-                ..
-            } => {
-                log::warn!("Shutting down...");
-
-                self.0 = ClientState::ShuttingDown {
-                    client_id,
-                    username,
-                    will,
-                    keep_alive,
-
-                    connect,
-
-                    sent_disconnect: false,
+        log::warn!("Shutting down...");
 
-                    reason,
-                };
-                self.poll_next(cx)
-            }
-
-            _ => unreachable!(),
+        let up = std::mem::replace(&mut self.0, ClientState::ShutDown { up: None, reason: None, ended: false });
+        if let ClientState::Up { closed, .. } = &up {
+            closed.set(true);
         }
+
+        // The will of a client with a presence says that it is offline, so it is published even when the client shuts down cleanly
+        let publish_will = publish_will || matches!(
+            &up,
+            ClientState::Up { will: Some(will), presence: Some(presence), .. } if *will == presence.presence().offline_publication()
+        );
+        self.0 = ClientState::ShuttingDown {
+            up: Box::new(up),
+            sent_disconnect: false,
+            publish_will,
+            reason,
+        };
+        self.poll_next(cx)
     }
 }
 
@@ -425,13 +1236,107 @@ pub enum Event {
         reset_session: bool,
     },
 
+    /// The [`Client`] established a new connection to the server, with the details of the CONNACK that the server accepted it with.
+    /// Emitted instead of [`Event::NewConnection`] if enabled with [`Client::with_connected_events`].
+    Connected(ConnectedEvent),
+
     Disconnected(ConnectionError),
 
     /// A publication received from the server
     Publication(ReceivedPublication),
 
+    /// A publication received from the server to a topic that does not match any subscription.
+    /// Only emitted with [`UnsolicitedPublicationPolicy::Report`].
+    UnsolicitedPublication(ReceivedPublication),
+
     /// Subscription updates acked by the server
     SubscriptionUpdates(Vec<SubscriptionUpdateEvent>),
+
+    /// Publications that were not acked by the server before the connection was lost, and were not sent again
+    /// because of [`RetransmitPolicy::ReturnToApplication`]
+    PublicationsNotAcked(Vec<crate::proto::Publication>),
+
+    /// The application is polling the [`Client`] too slowly to keep up with the packets received from the server.
+    /// Only emitted if enabled with [`Client::with_slow_consumer_thresholds`].
+    SlowConsumer(SlowConsumerStats),
+
+    /// Publications that were sent with a lower quality of service than they were published with,
+    /// because the server accepts no higher one than [`ConnectionParameters::maximum_qos`]
+    PublicationsDowngraded(Vec<QoSDowngrade>),
+}
+
+/// The details of a new connection, reported with [`Event::Connected`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectedEvent {
+    /// Whether the session was reset as part of this new connection or not, like [`Event::NewConnection`].
+    /// The client then resubscribes to its subscriptions by itself.
+    pub reset_session: bool,
+
+    /// Whether the server still had the session of the client ID
+    pub session_present: bool,
+
+    /// The client ID that the server assigned to the client, if the client did not have one. Only sent by MQTT 5.0 servers.
+    #[allow(clippy::doc_markdown)]
+    pub assigned_client_id: Option<crate::proto::ByteStr>,
+
+    /// The keep-alive time that the server told the client to use instead of the one it asked for, if any. Only sent by MQTT 5.0 servers.
+    /// The client pings with it for the rest of the connection, as reflected in `connection_parameters`.
+    #[allow(clippy::doc_markdown)]
+    pub server_keep_alive: Option<std::time::Duration>,
+
+    /// The parameters in effect for the connection
+    pub connection_parameters: ConnectionParameters,
+
+    /// A human-readable explanation from the server, for diagnostics. Only sent by MQTT 5.0 servers.
+    #[allow(clippy::doc_markdown)]
+    pub reason_string: Option<crate::proto::ByteStr>,
+
+    /// Name and value pairs of metadata that the server sent about the connection. Only sent by MQTT 5.0 servers.
+    #[allow(clippy::doc_markdown)]
+    pub user_properties: Vec<(crate::proto::ByteStr, crate::proto::ByteStr)>,
+}
+
+/// The parameters in effect for a connection, as negotiated between the [`Client`] and the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectionParameters {
+    /// The keep-alive time in effect, which MQTT 5.0 servers can override. The client pings the server if it hasn't sent any other packet for half this interval.
+    #[allow(clippy::doc_markdown)]
+    pub keep_alive: std::time::Duration,
+
+    /// The highest quality of service that the server accepts for publications from the client.
+    /// The client sends publications with a higher quality of service with this one instead, reporting them with
+    /// [`Event::PublicationsDowngraded`], and accepts subscriptions that the server grants with this one instead of a higher one.
+    pub maximum_qos: crate::proto::QoS,
+
+    /// Whether the server supports retained messages
+    pub retain_available: bool,
+
+    /// The number of QoS 1 and QoS 2 publications that the server is willing to process concurrently.
+    /// The client queues any further publications until the server acks earlier ones.
+    #[allow(clippy::doc_markdown)]
+    pub receive_maximum: u16,
+
+    /// The size in bytes of the largest packet that the server accepts, if it limits them.
+    /// Publications whose PUBLISH packets are larger fail with [`PublishError::PacketTooLarge`].
+    pub maximum_packet_size: Option<u32>,
+
+    /// The protocol level of the connection, which is [`crate::PROTOCOL_LEVEL`] if the client fell back to MQTT 3.1.1
+    /// according to its [`ProtocolFallback`]
+    #[allow(clippy::doc_markdown)]
+    pub protocol_level: u8,
+}
+
+/// A publication that was sent with a lower quality of service than it was published with, reported with [`Event::PublicationsDowngraded`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QoSDowngrade {
+    /// The topic that the publication was sent to
+    pub topic_name: crate::proto::ByteStr,
+
+    /// The quality of service that the publication was published with
+    pub requested_qos: crate::proto::QoS,
+
+    /// The quality of service that the publication was sent with, which is the maximum that the server accepts
+    pub qos: crate::proto::QoS,
 }
 
 /// A subscription update event
@@ -440,20 +1345,84 @@ pub enum SubscriptionUpdateEvent {
     Subscribe(crate::proto::SubscribeTo),
     Unsubscribe(crate::proto::ByteStr),
     RejectedByServer(crate::proto::SubscribeTo),
+
+    /// A subscription that the server had acked before the session was reset was automatically resubscribed.
+    ///
+    /// Contains the quality of service granted by the server.
+    Resubscribe(crate::proto::SubscribeTo),
+
+    /// A subscription that the server had acked before the session was reset was rejected when it was automatically resubscribed.
+    ///
+    /// Contains the quality of service that was requested. The client no longer considers itself subscribed to this topic filter.
+    ResubscribeRejectedByServer(crate::proto::SubscribeTo),
+
+    /// The QoS of a subscription was changed with [`Client::update_subscription`].
+    ///
+    /// Contains the quality of service granted by the server, which may be lower than the one that was requested.
+    /// If the server rejects the change, [`SubscriptionUpdateEvent::RejectedByServer`] is sent instead.
+    #[allow(clippy::doc_markdown)]
+    QoSUpdated(crate::proto::SubscribeTo),
+
+    /// The server rejected the refresh of a subscription made with [`Client::subscribe_with_lease`].
+    ///
+    /// Contains the quality of service that was requested. The client no longer considers itself subscribed to this topic filter.
+    RefreshRejectedByServer(crate::proto::SubscribeTo),
 }
 
 /// A message that was received from the server
 #[derive(Debug, PartialEq, Eq)]
 pub struct ReceivedPublication {
     pub topic_name: crate::proto::ByteStr,
+
+    /// Whether the server marked the publication as possibly sent before. See [`DuplicatePolicy`].
     pub dup: bool,
+
     pub qos: crate::proto::QoS,
+
+    /// The packet identifier that the server assigned to the publication, or `None` for [`crate::proto::QoS::AtMostOnce`] publications.
+    ///
+    /// The server reuses packet identifiers once publications are acked, so this only identifies the publication
+    /// among the ones that are in flight on the same session. It is useful to correlate publications with server logs.
+    pub packet_identifier: Option<crate::proto::PacketIdentifier>,
+
     pub retain: bool,
     pub payload: bytes::Bytes,
+
+    /// The user properties of the publication. Always empty unless the client is connected to an MQTT 5.0 server.
+    /// See [`crate::proto::Publication::user_properties`].
+    pub user_properties: Vec<(crate::proto::ByteStr, crate::proto::ByteStr)>,
+
+    /// The identifiers of the subscriptions that the publication matched, one for every matching subscription that was made with
+    /// a [`crate::proto::SubscribeTo::subscription_identifier`]. Always empty unless the client is connected to an MQTT 5.0 server.
+    pub subscription_identifiers: Vec<u32>,
+
+    /// The topic that the publisher expects a response on, if the publication is a request. Always `None` unless the client is connected
+    /// to an MQTT 5.0 server. See [`crate::proto::Publication::response_topic`].
+    pub response_topic: Option<crate::proto::ByteStr>,
+
+    /// The data that the publisher expects back with the response. See [`crate::proto::Publication::correlation_data`].
+    pub correlation_data: Option<bytes::Bytes>,
+
+    /// Set if the client is in manual ack mode and the publication needs to be acked. See [`Client::with_manual_acks`].
+    pub manual_ack: Option<ManualAck>,
+}
+
+impl ReceivedPublication {
+    /// Decodes the payload of the publication with the given codec
+    pub fn decode<P>(&self, codec: &P) -> Result<P::Value, P::Error> where P: PayloadCodec {
+        codec.decode(&self.payload)
+    }
+
+    /// Acks the publication to the server, if the client is in manual ack mode. Does nothing if the publication was already acked.
+    pub fn ack(&mut self) {
+        if let Some(manual_ack) = self.manual_ack.take() {
+            manual_ack.ack();
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
-pub struct ShutdownHandle(futures_channel::mpsc::Sender<()>);
+pub struct ShutdownHandle(futures_channel::mpsc::Sender<ShutdownRequest>);
 
 impl ShutdownHandle {
     /// Signals the [`Client`] to shut down.
@@ -461,15 +1430,37 @@ impl ShutdownHandle {
     /// The returned `Future` resolves when the `Client` is guaranteed the notification,
     /// not necessarily when the `Client` has completed shutting down.
     pub async fn shutdown(&mut self) -> Result<(), ShutdownError> {
+        self.send(ShutdownRequest::Disconnect).await
+    }
+
+    /// Signals the [`Client`] to shut down so that the server publishes the will of the client.
+    ///
+    /// With MQTT 5.0, the client sends DISCONNECT with the reason code Disconnect with Will Message. MQTT 3.1.1 has no way
+    /// to disconnect gracefully and still have the will published, so the client closes the connection without DISCONNECT instead.
+    /// This is useful for orchestrated failovers, where the clients that watch the will must still be notified that this client went away.
+    ///
+    /// The returned `Future` resolves when the `Client` is guaranteed the notification, like for [`ShutdownHandle::shutdown`].
+    pub async fn shutdown_with_will(&mut self) -> Result<(), ShutdownError> {
+        self.send(ShutdownRequest::PublishWill).await
+    }
+
+    async fn send(&mut self, request: ShutdownRequest) -> Result<(), ShutdownError> {
         use futures_util::SinkExt;
 
-        match self.0.send(()).await {
+        match self.0.send(request).await {
             Ok(_) => Ok(()),
             Err(_) => Err(ShutdownError::ClientDoesNotExist),
         }
     }
 }
 
+/// How a [`ShutdownHandle`] asked the client to shut down
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ShutdownRequest {
+    Disconnect,
+    PublishWill,
+}
+
 #[derive(Debug)]
 enum ClientState<C>
 where
@@ -481,8 +1472,12 @@ where
         will: Option<crate::proto::Publication>,
         keep_alive: std::time::Duration,
 
-        shutdown_send: futures_channel::mpsc::Sender<()>,
-        shutdown_recv: futures_channel::mpsc::Receiver<()>,
+        shutdown_send: futures_channel::mpsc::Sender<ShutdownRequest>,
+        shutdown_recv: futures_channel::mpsc::Receiver<ShutdownRequest>,
+        config_send: futures_channel::mpsc::UnboundedSender<ConfigUpdate>,
+        config_recv: futures_channel::mpsc::UnboundedReceiver<ConfigUpdate>,
+        closed: Closed,
+        cancellation: Cancellation,
 
         packet_identifiers: PacketIdentifiers,
 
@@ -490,43 +1485,157 @@ where
         ping: ping::State,
         publish: publish::State,
         subscriptions: subscriptions::State,
+        slow_consumer: slow_consumer::Detector,
+        ack_deferral: ack_deferral::State,
+        flush: flush::State,
+        connection_stability: ConnectionStability,
+        presence: Option<presence::State>,
+        inbound: inbound::Pipeline,
+        payload_sizes: PayloadSizes,
 
         /// Packets waiting to be written to the underlying `PacketSink`
         packets_waiting_to_be_sent: std::collections::VecDeque<crate::proto::Packet>,
     },
 
     ShuttingDown {
-        client_id: crate::proto::ClientId,
-        username: Option<crate::proto::ByteStr>,
-        will: Option<crate::proto::Publication>,
-        keep_alive: std::time::Duration,
-
-        connect: connect::Connect<C>,
+        /// The state of the Client when it started shutting down. Always `ClientState::Up`.
+        /// Kept so that the Client can be restarted.
+        up: Box<ClientState<C>>,
 
         /// If the DISCONNECT packet has already been sent
         sent_disconnect: bool,
 
+        /// If the connection should be closed without sending DISCONNECT, so that the server publishes the will
+        publish_will: bool,
+
         /// The Error that caused the Client to transition away from Up, if any
         reason: Option<Error>,
     },
 
     ShutDown {
+        /// The state of the Client when it started shutting down, if any. Always `ClientState::Up`.
+        up: Option<Box<ClientState<C>>>,
+
         /// The Error that caused the Client to transition away from Up, if any
         reason: Option<Error>,
+
+        /// If the stream has already returned `None`
+        ended: bool,
     },
 }
 
+impl<C> ClientState<C>
+where
+    C: crate::io::Connector,
+{
+    /// Transitions from `ShuttingDown` to `ShutDown`
+    fn shut_down(&mut self) {
+        *self = match std::mem::replace(self, ClientState::ShutDown { up: None, reason: None, ended: false }) {
+            ClientState::ShuttingDown { up, reason, .. } => ClientState::ShutDown { up: Some(up), reason, ended: false },
+            state => state,
+        };
+    }
+}
+
+/// Whether the client has shut down, shared with the handles it returned so that they can fail fast
+#[derive(Clone, Debug, Default)]
+struct Closed(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+impl Closed {
+    fn is_closed(&self) -> bool {
+        self.0.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    fn set(&self, closed: bool) {
+        self.0.store(closed, std::sync::atomic::Ordering::Release);
+    }
+}
+
+/// Completes when the cancellation token of the client, if any, is cancelled
+#[derive(Default)]
+struct Cancellation {
+    cancelled: bool,
+    future: Option<std::pin::Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl Cancellation {
+    #[cfg(feature = "tokio-util")]
+    fn new(future: impl Future<Output = ()> + Send + 'static) -> Self {
+        Cancellation {
+            cancelled: false,
+            future: Some(Box::pin(future)),
+        }
+    }
+
+    /// Returns `true` if the token has been cancelled, now or before
+    fn poll_cancelled(&mut self, cx: &mut std::task::Context<'_>) -> bool {
+        if let Some(future) = &mut self.future {
+            if future.as_mut().poll(cx).is_ready() {
+                self.cancelled = true;
+                self.future = None;
+            }
+        }
+
+        self.cancelled
+    }
+}
+
+impl std::fmt::Debug for Cancellation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cancellation")
+            .field("cancelled", &self.cancelled)
+            .field("has_token", &self.future.is_some())
+            .finish()
+    }
+}
+
+/// Makes the next connection start a new session, if the client ID allows it
+fn use_clean_session(client_id: &mut crate::proto::ClientId) {
+    if let crate::proto::ClientId::IdWithExistingSession(id) = client_id {
+        *client_id = crate::proto::ClientId::IdWithCleanSession(id.clone());
+    }
+}
+
+/// Flushes the packets written to the connection, unless the flush strategy or the ack deferral holds them back
+fn poll_flush<PacketSink>(
+    cx: &mut std::task::Context<'_>,
+    sink: &mut PacketSink,
+    flush: &mut flush::State,
+    ack_deferral: &mut ack_deferral::State,
+) -> Result<(), Error>
+where
+    PacketSink: crate::io::PacketSink + Unpin,
+{
+    if flush.poll_flush(cx) && ack_deferral.poll_flush(cx) {
+        flush.flushed();
+
+        // We don't care whether this returns Poll::Ready or Poll::Pending.
+        let _: std::task::Poll<_> = std::pin::Pin::new(&mut *sink)
+            .poll_flush(cx)
+            .map_err(Error::EncodePacket)?;
+    }
+
+    Ok(())
+}
+
 fn client_poll<PacketStream, PacketSink>(
     cx: &mut std::task::Context<'_>,
 
     stream: &mut PacketStream,
     sink: &mut PacketSink,
+    tap: &mut tap::Tap,
+    auth: &mut auth::State,
     keep_alive: std::time::Duration,
     packets_waiting_to_be_sent: &mut std::collections::VecDeque<crate::proto::Packet>,
     packet_identifiers: &mut PacketIdentifiers,
     ping: &mut ping::State,
     publish: &mut publish::State,
     subscriptions: &mut subscriptions::State,
+    slow_consumer: &mut slow_consumer::Detector,
+    ack_deferral: &mut ack_deferral::State,
+    flush: &mut flush::State,
+    inbound: &mut inbound::Pipeline,
+    payload_sizes: &PayloadSizes,
 ) -> std::task::Poll<Result<Event, Error>>
 where
     PacketStream: crate::io::PacketStream + Unpin,
@@ -538,9 +1647,18 @@ where
             match std::pin::Pin::new(&mut *sink).poll_ready(cx) {
                 std::task::Poll::Ready(result) => {
                     let () = result.map_err(Error::EncodePacket)?;
+                    tap.sent(&packet);
+                    payload_sizes.packet_sent(&packet);
+                    ping.packet_sent();
+                    ack_deferral.packet_sent(&packet);
+                    flush.packet_sent(&packet);
                     let () = std::pin::Pin::new(&mut *sink)
                         .start_send(packet)
                         .map_err(Error::EncodePacket)?;
+
+                    if flush.per_packet() {
+                        poll_flush(cx, sink, flush, ack_deferral)?;
+                    }
                 }
 
                 std::task::Poll::Pending => {
@@ -550,18 +1668,21 @@ where
             }
         }
 
-        // Finish sending any packets waiting to be sent.
-        //
-        // We don't care whether this returns Poll::Ready or Poll::Pending.
-        let _: std::task::Poll<_> = std::pin::Pin::new(&mut *sink)
-            .poll_flush(cx)
-            .map_err(Error::EncodePacket)?;
+        // Finish sending any packets waiting to be sent, unless they are being coalesced with more packets,
+        // or they are only PUBACKs that are being deferred.
+        poll_flush(cx, sink, flush, ack_deferral)?;
 
         let mut continue_loop = false;
 
-        let mut packet = match std::pin::Pin::new(&mut *stream).poll_next(cx) {
+        let mut packet = match slow_consumer.poll_received(tap.poll_received(std::pin::Pin::new(&mut *stream).poll_next(cx))) {
             std::task::Poll::Ready(Some(packet)) => {
                 let packet = packet.map_err(Error::DecodePacket)?;
+                payload_sizes.packet_received(&packet);
+                ping.packet_received();
+
+                if let crate::proto::Packet::Disconnect(crate::proto::Disconnect { server_reference, .. }) = packet {
+                    return std::task::Poll::Ready(Err(Error::ServerDisconnected(server_reference)));
+                }
 
                 // May have more packets after this one, so keep looping
                 continue_loop = true;
@@ -573,16 +1694,25 @@ where
             std::task::Poll::Pending => None,
         };
 
-        let mut new_packets_to_be_sent = vec![];
+        // New packets are appended directly to `packets_waiting_to_be_sent` rather than collected into a temporary `Vec`,
+        // so that a steady stream of publishes does not allocate once the queue has grown to its working size.
+        let num_packets_waiting_to_be_sent = packets_waiting_to_be_sent.len();
+
+        // Enhanced authentication
+        auth.poll(&mut packet, packets_waiting_to_be_sent)?;
 
         // Ping
         let ping_packet = ping.poll(cx, &mut packet, keep_alive);
-        new_packets_to_be_sent.extend(ping_packet);
+        packets_waiting_to_be_sent.extend(ping_packet);
 
         // Publish
-        let (new_publish_packets, publication_received) =
-            publish.poll(cx, &mut packet, packet_identifiers)?;
-        new_packets_to_be_sent.extend(new_publish_packets);
+        let publication_received =
+            publish.poll(cx, &mut packet, packet_identifiers, packets_waiting_to_be_sent)?
+            .and_then(|publication_received| subscriptions.check_received(publication_received))
+            .and_then(|publication_received| match publication_received {
+                Ok(publication_received) => inbound.handle(publication_received).map(Event::Publication),
+                Err(unsolicited_publication) => Some(Event::UnsolicitedPublication(unsolicited_publication)),
+            });
 
         // Subscriptions
         let subscription_updates = if publication_received.is_some() {
@@ -590,28 +1720,28 @@ where
             // because they might generate their own responses.
             vec![]
         } else {
-            let (new_subscription_packets, subscription_updates) =
-                subscriptions.poll(cx, &mut packet, packet_identifiers)?;
-            new_packets_to_be_sent.extend(new_subscription_packets);
-            subscription_updates
+            subscriptions.poll(cx, &mut packet, packet_identifiers, packets_waiting_to_be_sent)?
         };
 
         assert!(packet.is_none(), "unconsumed packet");
 
-        if !new_packets_to_be_sent.is_empty() {
+        if packets_waiting_to_be_sent.len() > num_packets_waiting_to_be_sent {
             // Have new packets to send, so keep looping
             continue_loop = true;
-            packets_waiting_to_be_sent.extend(new_packets_to_be_sent);
         }
 
         if let Some(publication_received) = publication_received {
-            return std::task::Poll::Ready(Ok(Event::Publication(publication_received)));
+            return std::task::Poll::Ready(Ok(publication_received));
         }
 
         if !subscription_updates.is_empty() {
             return std::task::Poll::Ready(Ok(Event::SubscriptionUpdates(subscription_updates)));
         }
 
+        if let Some(qos_downgrades) = publish.take_qos_downgrades() {
+            return std::task::Poll::Ready(Ok(Event::PublicationsDowngraded(qos_downgrades)));
+        }
+
         if !continue_loop {
             return std::task::Poll::Pending;
         }
@@ -621,6 +1751,9 @@ where
 struct PacketIdentifiers {
     in_use: Box<[usize; PacketIdentifiers::SIZE]>,
     previous: crate::proto::PacketIdentifier,
+
+    /// The packet identifiers that this client may use
+    range: std::ops::RangeInclusive<crate::proto::PacketIdentifier>,
 }
 
 impl PacketIdentifiers {
@@ -636,10 +1769,13 @@ impl PacketIdentifiers {
     const SIZE: usize = (1 << 16) / (std::mem::size_of::<usize>() * 8);
 
     fn reserve(&mut self) -> Result<crate::proto::PacketIdentifier, Error> {
-        let start = self.previous;
-        let mut current = start;
-
-        current += 1;
+        let current =
+            if self.previous < *self.range.end() {
+                self.previous + 1
+            }
+            else {
+                *self.range.start()
+            };
 
         let (block, mask) = self.entry(current);
         if (*block & mask) != 0 {
@@ -651,6 +1787,13 @@ impl PacketIdentifiers {
         Ok(current)
     }
 
+    /// Restricts the packet identifiers handed out by `reserve` to the given range.
+    /// Packet identifiers that are already in use stay reserved until they are discarded.
+    fn set_range(&mut self, range: std::ops::RangeInclusive<crate::proto::PacketIdentifier>) {
+        self.previous = *range.end();
+        self.range = range;
+    }
+
     fn discard(&mut self, packet_identifier: crate::proto::PacketIdentifier) {
         let (block, mask) = self.entry(packet_identifier);
         *block &= !mask;
@@ -670,6 +1813,7 @@ impl std::fmt::Debug for PacketIdentifiers {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("PacketIdentifiers")
             .field("previous", &self.previous)
+            .field("range", &self.range)
             .finish()
     }
 }
@@ -679,17 +1823,24 @@ impl Default for PacketIdentifiers {
         PacketIdentifiers {
             in_use: Box::new([0; PacketIdentifiers::SIZE]),
             previous: crate::proto::PacketIdentifier::max_value(),
+            range: crate::proto::PacketIdentifier::new(1).expect("1 is a valid packet identifier")..=crate::proto::PacketIdentifier::max_value(),
         }
     }
 }
 
 #[derive(Debug)]
 pub enum Error {
+    AuthenticationFailed,
     DecodePacket(crate::proto::DecodeError),
     DuplicateExactlyOncePublishPacketNotMarkedDuplicate(crate::proto::PacketIdentifier),
     EncodePacket(crate::proto::EncodeError),
     PacketIdentifiersExhausted,
+    PolledAfterShutDown,
     ServerClosedConnection,
+
+    /// The server sent a DISCONNECT, with the server that it redirects the client to, if any
+    ServerDisconnected(Option<crate::proto::ByteStr>),
+
     SubAckDoesNotContainEnoughQoS(crate::proto::PacketIdentifier, usize, usize),
     SubscriptionDowngraded(crate::proto::ByteStr, crate::proto::QoS, crate::proto::QoS),
     UnexpectedSubAck(crate::proto::PacketIdentifier, UnexpectedSubUnsubAckReason),
@@ -721,7 +1872,7 @@ impl Error {
                 err.kind(),
                 std::io::ErrorKind::TimedOut | std::io::ErrorKind::WriteZero
             ),
-            Error::ServerClosedConnection => true,
+            Error::ServerClosedConnection | Error::ServerDisconnected(_) => true,
             _ => false,
         }
     }
@@ -732,6 +1883,7 @@ impl Error {
             Error::DecodePacket(crate::proto::DecodeError::Io(_))
                 | Error::EncodePacket(crate::proto::EncodeError::Io(_))
                 | Error::ServerClosedConnection
+                | Error::ServerDisconnected(_)
         )
     }
 }
@@ -739,6 +1891,9 @@ impl Error {
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Error::AuthenticationFailed =>
+                write!(f, "enhanced authentication with the server failed"),
+
             Error::DecodePacket(err) =>
                 write!(f, "could not decode packet: {}", err),
 
@@ -755,9 +1910,18 @@ impl std::fmt::Display for Error {
             Error::PacketIdentifiersExhausted =>
                 write!(f, "all packet identifiers exhausted"),
 
+            Error::PolledAfterShutDown =>
+                write!(f, "client was polled after it shut down; use Client::restart to start it again"),
+
             Error::ServerClosedConnection =>
                 write!(f, "connection closed by server"),
 
+            Error::ServerDisconnected(None) =>
+                write!(f, "server disconnected the client"),
+
+            Error::ServerDisconnected(Some(server_reference)) =>
+                write!(f, "server disconnected the client and redirected it to {}", server_reference),
+
             Error::SubAckDoesNotContainEnoughQoS(packet_identifier, expected, actual) =>
                 write!(f, "Expected SUBACK {} to contain {} QoS's but it actually contained {}", packet_identifier, expected, actual),
 
@@ -777,11 +1941,14 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         #[allow(clippy::match_same_arms)]
         match self {
+            Error::AuthenticationFailed => None,
             Error::DecodePacket(err) => Some(err),
             Error::DuplicateExactlyOncePublishPacketNotMarkedDuplicate(_) => None,
             Error::EncodePacket(err) => Some(err),
             Error::PacketIdentifiersExhausted => None,
+            Error::PolledAfterShutDown => None,
             Error::ServerClosedConnection => None,
+            Error::ServerDisconnected(_) => None,
             Error::SubAckDoesNotContainEnoughQoS(_, _, _) => None,
             Error::SubscriptionDowngraded(_, _, _) => None,
             Error::UnexpectedSubAck(_, _) => None,
@@ -943,4 +2110,561 @@ mod tests {
         }
         assert_eq!(packet_identifiers.in_use[..], expected[..]);
     }
+
+    #[test]
+    fn packet_identifier_range() {
+        let mut packet_identifiers: PacketIdentifiers = Default::default();
+        packet_identifiers.set_range(crate::proto::PacketIdentifier::new(100).unwrap()..=crate::proto::PacketIdentifier::new(102).unwrap());
+
+        assert_eq!(packet_identifiers.reserve().unwrap().get(), 100);
+        assert_eq!(packet_identifiers.reserve().unwrap().get(), 101);
+        assert_eq!(packet_identifiers.reserve().unwrap().get(), 102);
+        match packet_identifiers.reserve() {
+            Err(crate::client::Error::PacketIdentifiersExhausted) => (),
+            result => panic!("expected identifiers to be exhausted but got {:?}", result),
+        }
+
+        // Wraps around to the start of the range, not to 1
+        packet_identifiers.discard(crate::proto::PacketIdentifier::new(100).unwrap());
+        assert_eq!(packet_identifiers.reserve().unwrap().get(), 100);
+        packet_identifiers.discard(crate::proto::PacketIdentifier::new(101).unwrap());
+        assert_eq!(packet_identifiers.reserve().unwrap().get(), 101);
+    }
+
+    #[cfg(feature = "tokio-util")]
+    #[test]
+    fn cancellation_token() {
+        use futures_util::StreamExt;
+
+        // The server accepts the connection but never acks publications
+        let server = super::test_server::TestServer::accepting();
+
+        let token = tokio_util::sync::CancellationToken::new();
+        let mut client =
+            crate::Client::new(
+                Some("client".parse().unwrap()),
+                None,
+                None,
+                server.clone(),
+                std::time::Duration::from_secs(30),
+                std::time::Duration::from_secs(60),
+            )
+            .with_cancellation_token(token.clone());
+        let mut publish_handle = client.publish_handle().unwrap();
+
+        let publication = crate::proto::Publication {
+            topic_name: "a".parse().unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            retain: false,
+            payload: bytes::Bytes::from_static(b"1"),
+            user_properties: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            match client.next().await {
+                Some(Ok(crate::Event::NewConnection { .. })) => (),
+                event => panic!("expected new connection, got {:?}", event),
+            }
+
+            let (result, (), ()) = futures_util::future::join3(
+                publish_handle.clone().publish(publication.clone()),
+                async {
+                    while client.next().await.is_some() {}
+                },
+                async {
+                    // Cancel while the publication waits for its PUBACK
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    assert!(server.sent().iter().any(|packet| matches!(packet, crate::proto::Packet::Publish(_))));
+                    token.cancel();
+                },
+            ).await;
+
+            // The pending publication failed, the event stream ended, and later publications fail right away
+            assert!(matches!(result, Err(crate::PublishError::ClientClosed)), "{:?}", result);
+            assert!(matches!(publish_handle.publish(publication).await, Err(crate::PublishError::ClientClosed)));
+        });
+    }
+
+    #[test]
+    fn restart() {
+        use futures_util::StreamExt;
+
+        let server = super::test_server::TestServer::new(|packet| match packet {
+            crate::proto::Packet::Connect(_) => vec![crate::proto::Packet::ConnAck(super::test_server::conn_ack(crate::proto::ConnectReturnCode::Accepted))],
+            crate::proto::Packet::Subscribe(subscribe) => vec![crate::proto::Packet::SubAck(crate::proto::SubAck {
+                packet_identifier: subscribe.packet_identifier,
+                qos: subscribe.subscribe_to.iter().map(|subscribe_to| crate::proto::SubAckQos::Success(subscribe_to.qos)).collect(),
+            })],
+            crate::proto::Packet::Publish(crate::proto::Publish {
+                packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _),
+                ..
+            }) => vec![crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier: *packet_identifier })],
+            _ => vec![],
+        });
+
+        let mut client = crate::Client::new(
+            Some("client".parse().unwrap()),
+            None,
+            None,
+            server.clone(),
+            std::time::Duration::from_secs(30),
+            std::time::Duration::from_secs(60),
+        );
+        let mut publish_handle = client.publish_handle().unwrap();
+        let mut update_subscription_handle = client.update_subscription_handle().unwrap();
+        let mut shutdown_handle = client.shutdown_handle().unwrap();
+
+        let subscribe_to = |topic_filter: &str| crate::proto::SubscribeTo {
+            topic_filter: topic_filter.parse().unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            match client.next().await {
+                Some(Ok(crate::Event::NewConnection { reset_session: true })) => (),
+                event => panic!("expected new connection, got {:?}", event),
+            }
+
+            let (result, event) = futures_util::future::join(update_subscription_handle.subscribe(subscribe_to("a")), client.next()).await;
+            result.unwrap();
+            match event {
+                Some(Ok(crate::Event::SubscriptionUpdates(_))) => (),
+                event => panic!("expected subscription updates, got {:?}", event),
+            }
+
+            let (result, ()) = futures_util::future::join(shutdown_handle.shutdown(), async {
+                while client.next().await.is_some() {}
+            }).await;
+            result.unwrap();
+
+            // The event stream has ended, so polling it again is an error
+            assert!(matches!(client.next().await, Some(Err(super::Error::PolledAfterShutDown))));
+            assert!(publish_handle.is_closed());
+            assert!(update_subscription_handle.is_closed());
+
+            // Restarting connects again with a clean session, and subscribes again to the subscriptions of the client
+            client.restart();
+            assert!(!publish_handle.is_closed());
+            assert!(!update_subscription_handle.is_closed());
+            match client.next().await {
+                Some(Ok(crate::Event::NewConnection { reset_session: true })) => (),
+                event => panic!("expected new connection, got {:?}", event),
+            }
+
+            // The handles obtained before the client shut down work for the restarted client
+            let publication = crate::proto::Publication {
+                topic_name: "a".parse().unwrap(),
+                qos: crate::proto::QoS::AtLeastOnce,
+                retain: false,
+                payload: bytes::Bytes::from_static(b"1"),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            };
+            let (result, ()) = futures_util::future::join(publish_handle.publish(publication), async {
+                // Let the client send the publication and receive its PUBACK
+                let _ = tokio::time::timeout(std::time::Duration::from_millis(100), async {
+                    while client.next().await.is_some() {}
+                }).await;
+            }).await;
+            result.unwrap();
+
+            let (result, ()) = futures_util::future::join(update_subscription_handle.subscribe(subscribe_to("b")), async {
+                let _ = tokio::time::timeout(std::time::Duration::from_millis(100), async {
+                    while client.next().await.is_some() {}
+                }).await;
+            }).await;
+            result.unwrap();
+        });
+
+        let sent = server.sent();
+        let client_ids: Vec<_> = sent.iter()
+            .filter_map(|packet| match packet { crate::proto::Packet::Connect(connect) => Some(connect.client_id.clone()), _ => None })
+            .collect();
+        assert_eq!(client_ids, [
+            crate::proto::ClientId::IdWithCleanSession("client".parse().unwrap()),
+            crate::proto::ClientId::IdWithCleanSession("client".parse().unwrap()),
+        ]);
+
+        let topic_filters: Vec<_> = sent.iter()
+            .filter_map(|packet| match packet { crate::proto::Packet::Subscribe(subscribe) => Some(subscribe), _ => None })
+            .flat_map(|subscribe| subscribe.subscribe_to.iter().map(|subscribe_to| subscribe_to.topic_filter.to_string()))
+            .collect();
+        assert_eq!(topic_filters, ["a", "a", "b"]);
+
+        assert_eq!(sent.iter().filter(|packet| matches!(packet, crate::proto::Packet::Publish(_))).count(), 1);
+    }
+
+    #[test]
+    fn closed() {
+        use futures_util::StreamExt;
+
+        let server = super::test_server::TestServer::accepting();
+
+        let mut client = crate::Client::new(
+            Some("client".parse().unwrap()),
+            None,
+            None,
+            server,
+            std::time::Duration::from_secs(30),
+            std::time::Duration::from_secs(60),
+        );
+        let mut publish_handle = client.publish_handle().unwrap();
+        let mut update_subscription_handle = client.update_subscription_handle().unwrap();
+        let mut shutdown_handle = client.shutdown_handle().unwrap();
+        assert!(!publish_handle.is_closed());
+        assert!(!update_subscription_handle.is_closed());
+
+        let publication = crate::proto::Publication {
+            topic_name: "a".parse().unwrap(),
+            qos: crate::proto::QoS::AtMostOnce,
+            retain: false,
+            payload: bytes::Bytes::from_static(b"1"),
+            user_properties: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        };
+        let subscribe_to = crate::proto::SubscribeTo {
+            topic_filter: "a".parse().unwrap(),
+            qos: crate::proto::QoS::AtMostOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            let (result, ()) = futures_util::future::join(shutdown_handle.shutdown(), async {
+                while client.next().await.is_some() {}
+            }).await;
+            result.unwrap();
+
+            // Once the event stream has ended, the handles are closed and fail right away
+            assert!(publish_handle.is_closed());
+            assert!(update_subscription_handle.is_closed());
+            assert!(matches!(publish_handle.publish(publication.clone()).await, Err(crate::PublishError::ClientClosed)));
+            assert!(matches!(update_subscription_handle.subscribe(subscribe_to.clone()).await, Err(crate::UpdateSubscriptionError::ClientClosed)));
+            assert!(matches!(
+                update_subscription_handle.unsubscribe("a".parse().unwrap()).await,
+                Err(crate::UpdateSubscriptionError::ClientClosed),
+            ));
+
+            // ... and so does the client itself
+            assert!(matches!(client.publish_handle(), Err(crate::PublishError::ClientClosed)));
+            assert!(matches!(client.update_subscription_handle(), Err(crate::UpdateSubscriptionError::ClientClosed)));
+            assert!(matches!(client.subscribe(subscribe_to), Err(crate::UpdateSubscriptionError::ClientClosed)));
+            assert!(matches!(client.unsubscribe("a".parse().unwrap()), Err(crate::UpdateSubscriptionError::ClientClosed)));
+        });
+
+        // Handles of a client that was dropped are closed too
+        let client = crate::Client::new(
+            Some("client".parse().unwrap()),
+            None,
+            None,
+            super::test_server::TestServer::accepting(),
+            std::time::Duration::from_secs(30),
+            std::time::Duration::from_secs(60),
+        );
+        let publish_handle = client.publish_handle().unwrap();
+        let update_subscription_handle = client.update_subscription_handle().unwrap();
+        drop(client);
+        assert!(publish_handle.is_closed());
+        assert!(update_subscription_handle.is_closed());
+    }
+
+    #[test]
+    fn generated_client_id() {
+        use futures_util::StreamExt;
+
+        // The server does not assign client IDs, so it rejects empty ones
+        let server = super::test_server::TestServer::new(|packet| match packet {
+            crate::proto::Packet::Connect(connect) => {
+                let return_code = match connect.client_id {
+                    crate::proto::ClientId::ServerGenerated =>
+                        crate::proto::ConnectReturnCode::Refused(crate::proto::ConnectionRefusedReason::IdentifierRejected),
+                    _ => crate::proto::ConnectReturnCode::Accepted,
+                };
+                vec![crate::proto::Packet::ConnAck(super::test_server::conn_ack(return_code))]
+            },
+            _ => vec![],
+        });
+
+        // An empty client ID asks the server to assign one
+        let mut client = crate::Client::new(
+            Some("".parse().unwrap()),
+            None,
+            None,
+            server.clone(),
+            std::time::Duration::from_secs(30),
+            std::time::Duration::from_secs(60),
+        );
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            match client.next().await {
+                Some(Ok(crate::Event::NewConnection { reset_session: true })) => (),
+                event => panic!("expected new connection, got {:?}", event),
+            }
+        });
+
+        // The client connects again right away with a generated client ID that every server must accept, and a clean session
+        let sent = server.sent();
+        match &sent[..] {
+            [
+                crate::proto::Packet::Connect(crate::proto::Connect { client_id: crate::proto::ClientId::ServerGenerated, .. }),
+                crate::proto::Packet::Connect(crate::proto::Connect { client_id: crate::proto::ClientId::IdWithCleanSession(client_id), .. }),
+            ] => {
+                let client_id: &str = client_id.as_ref();
+                assert_eq!(client_id.len(), 21, "{}", client_id);
+                assert!(client_id.chars().all(|c| c.is_ascii_alphanumeric()), "{}", client_id);
+            },
+            sent => panic!("expected two CONNECTs, got {:?}", sent),
+        }
+    }
+
+    #[test]
+    fn publications_downgraded() {
+        use futures_util::StreamExt;
+
+        // The server only accepts publications up to QoS 1
+        let server = super::test_server::TestServer::new(|packet| match packet {
+            crate::proto::Packet::Connect(_) => vec![crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+                maximum_qos: Some(crate::proto::QoS::AtLeastOnce),
+                ..super::test_server::conn_ack(crate::proto::ConnectReturnCode::Accepted)
+            })],
+            crate::proto::Packet::Publish(crate::proto::Publish {
+                packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _),
+                ..
+            }) => vec![crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier: *packet_identifier })],
+            _ => vec![],
+        });
+
+        let mut client = crate::Client::new(
+            Some("client".parse().unwrap()),
+            None,
+            None,
+            server.clone(),
+            std::time::Duration::from_secs(30),
+            std::time::Duration::from_secs(60),
+        );
+        let mut publish_handle = client.publish_handle().unwrap();
+
+        let publication = |topic_name: &str, qos| crate::proto::Publication {
+            topic_name: topic_name.parse().unwrap(),
+            qos,
+            retain: false,
+            payload: Default::default(),
+            user_properties: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            match client.next().await {
+                Some(Ok(crate::Event::NewConnection { .. })) => (),
+                event => panic!("expected new connection, got {:?}", event),
+            }
+
+            let publishing = async {
+                (
+                    publish_handle.publish(publication("a", crate::proto::QoS::AtLeastOnce)).await,
+                    publish_handle.publish(publication("b", crate::proto::QoS::ExactlyOnce)).await,
+                )
+            };
+            let qos_downgrades = std::cell::RefCell::new(vec![]);
+            let events = async {
+                loop {
+                    match client.next().await {
+                        Some(Ok(crate::Event::PublicationsDowngraded(events))) => qos_downgrades.borrow_mut().extend(events),
+                        event => panic!("unexpected event {:?}", event),
+                    }
+                }
+            };
+            futures_util::pin_mut!(publishing, events);
+            let results = match futures_util::future::select(publishing, events).await {
+                futures_util::future::Either::Left((results, _)) => results,
+                futures_util::future::Either::Right(((), _)) => unreachable!(),
+            };
+            assert!(matches!(results, (Ok(()), Ok(()))), "{:?}", results);
+
+            // The application is told which publication was sent with a lower QoS than it asked for
+            assert_eq!(*qos_downgrades.borrow(), [super::QoSDowngrade {
+                topic_name: "b".parse().unwrap(),
+                requested_qos: crate::proto::QoS::ExactlyOnce,
+                qos: crate::proto::QoS::AtLeastOnce,
+            }]);
+        });
+
+        // The downgraded publication was sent with QoS 1
+        assert!(server.sent().iter().any(|packet| matches!(packet, crate::proto::Packet::Publish(crate::proto::Publish {
+            topic_name,
+            packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtLeastOnce(..),
+            ..
+        }) if *topic_name == "b")));
+    }
+
+    /// Connects a client with a will with the given protocol level, shuts it down with [`super::ShutdownHandle::shutdown_with_will`],
+    /// and returns the packets that it sent
+    fn shut_down_with_will(protocol_level: u8) -> Vec<crate::proto::Packet> {
+        use futures_util::StreamExt;
+
+        let server = super::test_server::TestServer::accepting();
+
+        let will = crate::proto::Publication {
+            topic_name: "will".parse().unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            retain: false,
+            payload: bytes::Bytes::from_static(b"gone"),
+            user_properties: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        };
+        let client = crate::Client::new(
+            Some("client".parse().unwrap()),
+            None,
+            Some(will),
+            server.clone(),
+            std::time::Duration::from_secs(30),
+            std::time::Duration::from_secs(60),
+        );
+        #[cfg(feature = "v5")]
+        let mut client = client.with_protocol_level(protocol_level);
+        #[cfg(not(feature = "v5"))]
+        let mut client = {
+            assert_eq!(protocol_level, crate::PROTOCOL_LEVEL);
+            client
+        };
+        let mut shutdown_handle = client.shutdown_handle().unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            match client.next().await {
+                Some(Ok(crate::Event::NewConnection { .. })) => (),
+                event => panic!("expected new connection, got {:?}", event),
+            }
+
+            let (result, ()) = futures_util::future::join(shutdown_handle.shutdown_with_will(), async {
+                while client.next().await.is_some() {}
+            }).await;
+            result.unwrap();
+        });
+
+        server.sent()
+    }
+
+    #[test]
+    fn shutdown_with_will() {
+        // An MQTT 3.1.1 client closes the connection without DISCONNECT, so that the server publishes the will
+        let sent = shut_down_with_will(crate::PROTOCOL_LEVEL);
+        assert!(matches!(&sent[..], [crate::proto::Packet::Connect(_)]), "{:?}", sent);
+
+        // An MQTT 5.0 client asks for the will to be published in its DISCONNECT
+        #[cfg(feature = "v5")]
+        {
+            let sent = shut_down_with_will(crate::proto::v5::PROTOCOL_LEVEL);
+            assert!(matches!(&sent[..], [
+                crate::proto::Packet::Connect(_),
+                crate::proto::Packet::Disconnect(crate::proto::Disconnect { reason: crate::proto::DisconnectReason::WithWill, .. }),
+            ]), "{:?}", sent);
+        }
+    }
+
+    #[test]
+    fn presence() {
+        use futures_util::StreamExt;
+
+        // The server disconnects the client as soon as it receives the first "online" publication, without acking it,
+        // and acks everything on the connections after that
+        let mut connections = 0;
+        let server = super::test_server::TestServer::new(move |packet| match packet {
+            crate::proto::Packet::Connect(_) => {
+                connections += 1;
+                vec![crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+                    session_present: connections > 1,
+                    ..super::test_server::conn_ack(crate::proto::ConnectReturnCode::Accepted)
+                })]
+            }
+            crate::proto::Packet::Publish(_) if connections == 1 =>
+                vec![crate::proto::Packet::Disconnect(crate::proto::Disconnect { reason: crate::proto::DisconnectReason::Normal, server_reference: None })],
+            crate::proto::Packet::Publish(crate::proto::Publish {
+                packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _),
+                ..
+            }) => vec![crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier: *packet_identifier })],
+            _ => vec![],
+        });
+
+        let mut client = crate::Client::new(
+            Some("client".parse().unwrap()),
+            None,
+            None,
+            server.clone(),
+            std::time::Duration::from_secs(30),
+            std::time::Duration::from_secs(60),
+        )
+        .with_presence(super::Presence::new("presence/client".parse().unwrap(), crate::proto::QoS::AtLeastOnce));
+        let mut shutdown_handle = client.shutdown_handle().unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(async {
+            let mut new_connections = 0;
+            while new_connections < 2 {
+                match client.next().await {
+                    Some(Ok(crate::Event::NewConnection { .. })) => new_connections += 1,
+                    Some(Ok(crate::Event::Disconnected(_))) => (),
+                    event => panic!("expected new connection, got {:?}", event),
+                }
+            }
+
+            // Let the client send its packets on the second connection
+            assert!(tokio::time::timeout(std::time::Duration::from_millis(100), client.next()).await.is_err());
+
+            let (result, ()) = futures_util::future::join(shutdown_handle.shutdown(), async {
+                while client.next().await.is_some() {}
+            }).await;
+            result.unwrap();
+        });
+
+        // The unacked "online" publication is sent again on the second connection instead of being published twice,
+        // and the client closes the connection without DISCONNECT when it shuts down, so that the server publishes the "offline" will
+        let sent = server.sent();
+        let publications: Vec<_> = sent.iter()
+            .filter_map(|packet| match packet {
+                crate::proto::Packet::Publish(publish) => Some((publish.payload.clone(), publish.packet_identifier_dup_qos)),
+                _ => None,
+            })
+            .collect();
+        let packet_identifier = crate::proto::PacketIdentifier::new(1).unwrap();
+        assert_eq!(publications, [
+            (bytes::Bytes::from_static(b"online"), crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, false)),
+            (bytes::Bytes::from_static(b"online"), crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, true)),
+        ]);
+        assert!(!sent.iter().any(|packet| matches!(packet, crate::proto::Packet::Disconnect(_))), "{:?}", sent);
+
+        match sent.iter().find_map(|packet| match packet { crate::proto::Packet::Connect(connect) => connect.will.as_ref(), _ => None }) {
+            Some(will) => assert_eq!((&will.payload[..], will.retain), (&b"offline"[..], true)),
+            None => panic!("expected the client to connect with a will"),
+        }
+    }
 }
@@ -0,0 +1,112 @@
+/// The number of buckets of a [`PayloadSizeHistogram`]. Payloads are always smaller than 2^28 bytes,
+/// the maximum remaining length of a packet.
+const NUM_BUCKETS: usize = 29;
+
+/// Histograms of the payload sizes of the publications sent and received by a [`Client`](crate::Client),
+/// returned by [`Client::payload_sizes`](crate::Client::payload_sizes).
+///
+/// Publications sent again after a reconnect are counted again. Received publications are counted before they pass
+/// through any [`super::InboundMiddleware`]. Clones share the same histograms.
+#[derive(Clone, Debug, Default)]
+pub struct PayloadSizes(std::sync::Arc<PayloadSizesInner>);
+
+#[derive(Debug, Default)]
+struct PayloadSizesInner {
+    sent: [std::sync::atomic::AtomicU64; NUM_BUCKETS],
+    received: [std::sync::atomic::AtomicU64; NUM_BUCKETS],
+}
+
+impl PayloadSizes {
+    /// The histogram of the payload sizes of the publications sent so far
+    pub fn sent(&self) -> PayloadSizeHistogram {
+        PayloadSizeHistogram::load(&self.0.sent)
+    }
+
+    /// The histogram of the payload sizes of the publications received so far
+    pub fn received(&self) -> PayloadSizeHistogram {
+        PayloadSizeHistogram::load(&self.0.received)
+    }
+
+    pub(super) fn packet_sent(&self, packet: &crate::proto::Packet) {
+        if let crate::proto::Packet::Publish(publish) = packet {
+            record(&self.0.sent, publish.payload.len());
+        }
+    }
+
+    pub(super) fn packet_received(&self, packet: &crate::proto::Packet) {
+        if let crate::proto::Packet::Publish(publish) = packet {
+            record(&self.0.received, publish.payload.len());
+        }
+    }
+}
+
+fn record(buckets: &[std::sync::atomic::AtomicU64; NUM_BUCKETS], payload_len: usize) {
+    let bucket = ((usize::BITS - payload_len.leading_zeros()) as usize).min(NUM_BUCKETS - 1);
+    buckets[bucket].fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// A snapshot of the payload sizes of publications, in buckets whose sizes grow exponentially.
+///
+/// The first bucket counts empty payloads. After that, each bucket counts the payloads from a power of two bytes
+/// up to the next one: 1 byte, 2 to 3 bytes, 4 to 7 bytes, and so on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadSizeHistogram([u64; NUM_BUCKETS]);
+
+impl PayloadSizeHistogram {
+    fn load(buckets: &[std::sync::atomic::AtomicU64; NUM_BUCKETS]) -> Self {
+        let mut histogram = [0; NUM_BUCKETS];
+        for (count, bucket) in histogram.iter_mut().zip(buckets) {
+            *count = bucket.load(std::sync::atomic::Ordering::Relaxed);
+        }
+        PayloadSizeHistogram(histogram)
+    }
+
+    /// The total number of publications counted
+    pub fn count(&self) -> u64 {
+        self.0.iter().sum()
+    }
+
+    /// The buckets that counted at least one publication, as the range of payload sizes in bytes of the bucket and its count
+    pub fn buckets(&self) -> impl Iterator<Item = (std::ops::RangeInclusive<usize>, u64)> + '_ {
+        self.0.iter().enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| {
+                let sizes = if bucket == 0 { 0..=0 } else { (1 << (bucket - 1))..=((1 << bucket) - 1) };
+                (sizes, *count)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn histogram_buckets() {
+        let payload_sizes = super::PayloadSizes::default();
+        for payload_len in &[0, 1, 2, 3, 4, 1000, 1023, 1024] {
+            payload_sizes.packet_sent(&crate::proto::Packet::Publish(crate::proto::Publish {
+                packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+                retain: false,
+                topic_name: "topic".parse().unwrap(),
+                payload: vec![0; *payload_len].into(),
+                user_properties: vec![],
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            }));
+        }
+
+        let sent = payload_sizes.sent();
+        assert_eq!(sent.count(), 8);
+        assert_eq!(sent.buckets().collect::<Vec<_>>(), [
+            (0..=0, 1),
+            (1..=1, 1),
+            (2..=3, 2),
+            (4..=7, 1),
+            (512..=1023, 2),
+            (1024..=2047, 1),
+        ]);
+        assert_eq!(payload_sizes.received().count(), 0);
+    }
+}
@@ -1,6 +1,20 @@
-pub(super) enum State {
+#[derive(Default)]
+pub(super) struct State {
+    always_ping: bool,
+    last_packet_sent: Option<std::time::Duration>,
+    last_packet_received: Option<std::time::Duration>,
+    timer: Timer,
+    clock: super::clock::SharedClock,
+}
+
+#[derive(Default)]
+enum Timer {
+    #[default]
     BeginWaitingForNextPing,
-    WaitingForNextPing(std::pin::Pin<Box<tokio::time::Sleep>>),
+    WaitingForNextPing {
+        deadline: std::time::Duration,
+        ping_timer: super::ClockSleep,
+    },
 }
 
 impl State {
@@ -14,29 +28,42 @@ impl State {
         if let Some(crate::proto::Packet::PingResp(crate::proto::PingResp)) = packet {
             let _ = packet.take();
 
-            match self {
-                State::BeginWaitingForNextPing => (),
-                State::WaitingForNextPing(ping_timer) => ping_timer
-                    .as_mut()
-                    .reset(deadline(tokio::time::Instant::now(), keep_alive)),
+            if let Timer::WaitingForNextPing { .. } = self.timer {
+                self.reset(deadline(self.clock.now(), keep_alive));
             }
         }
 
         loop {
             log::trace!("    {:?}", self);
 
-            match self {
-                State::BeginWaitingForNextPing => {
-                    let ping_timer = tokio::time::sleep(keep_alive);
-                    *self = State::WaitingForNextPing(Box::pin(ping_timer));
-                }
+            match &mut self.timer {
+                Timer::BeginWaitingForNextPing => self.reset(self.clock.now() + keep_alive),
 
-                State::WaitingForNextPing(ping_timer) => {
+                Timer::WaitingForNextPing { deadline: now, ping_timer } => {
                     use futures_util::FutureExt;
                     match ping_timer.poll_unpin(cx) {
                         std::task::Poll::Ready(()) => {
-                            let now = ping_timer.deadline();
-                            ping_timer.as_mut().reset(deadline(now, keep_alive));
+                            let now = *now;
+
+                            // Any packet sent to the server counts towards the keep-alive, so a ping is only needed
+                            // if nothing else was sent since the timer was last reset.
+                            //
+                            // But sending does not tell whether the server is still there, so a ping is still sent to get a PINGRESP back
+                            // if nothing was received from the server for a whole keep-alive interval.
+                            if !self.always_ping {
+                                if let Some(last_packet_sent) = self.last_packet_sent {
+                                    let mut next_ping = deadline(last_packet_sent, keep_alive);
+                                    if let Some(last_packet_received) = self.last_packet_received {
+                                        next_ping = next_ping.min(last_packet_received + keep_alive);
+                                    }
+                                    if next_ping > now {
+                                        self.reset(next_ping);
+                                        continue;
+                                    }
+                                }
+                            }
+
+                            self.reset(deadline(now, keep_alive));
                             return Some(crate::proto::Packet::PingReq(crate::proto::PingReq));
                         }
 
@@ -47,20 +74,86 @@ impl State {
         }
     }
 
+    pub(super) fn packet_sent(&mut self) {
+        if !self.always_ping {
+            self.last_packet_sent = Some(self.clock.now());
+        }
+    }
+
+    pub(super) fn packet_received(&mut self) {
+        if !self.always_ping {
+            self.last_packet_received = Some(self.clock.now());
+        }
+    }
+
     pub(super) fn new_connection(&mut self) {
-        *self = State::BeginWaitingForNextPing;
+        self.last_packet_sent = None;
+
+        // The CONNACK of the new connection was just received
+        self.last_packet_received = Some(self.clock.now());
+        self.timer = Timer::BeginWaitingForNextPing;
+    }
+
+    pub(super) fn set_always_ping(&mut self, always_ping: bool) {
+        self.always_ping = always_ping;
+    }
+
+    pub(super) fn set_clock(&mut self, clock: super::clock::SharedClock) {
+        self.clock = clock;
+    }
+
+    /// Waits for the next ping until the given time
+    fn reset(&mut self, deadline: std::time::Duration) {
+        self.timer = Timer::WaitingForNextPing {
+            deadline,
+            ping_timer: self.clock.sleep_until(deadline),
+        };
     }
 }
 
 impl std::fmt::Debug for State {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            State::BeginWaitingForNextPing => f.write_str("BeginWaitingForNextPing"),
-            State::WaitingForNextPing { .. } => f.write_str("WaitingForNextPing"),
+        match self.timer {
+            Timer::BeginWaitingForNextPing => f.write_str("BeginWaitingForNextPing"),
+            Timer::WaitingForNextPing { .. } => f.write_str("WaitingForNextPing"),
         }
     }
 }
 
-fn deadline(now: tokio::time::Instant, keep_alive: std::time::Duration) -> tokio::time::Instant {
+fn deadline(now: std::time::Duration, keep_alive: std::time::Duration) -> std::time::Duration {
     now + keep_alive / 2
 }
+
+#[cfg(test)]
+mod tests {
+    /// Returns the times at which the client pings, when it sends and receives a packet every second
+    fn pings(always_ping: bool) -> Vec<u64> {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let mut ping = super::State::default();
+        ping.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+        ping.set_always_ping(always_ping);
+        ping.new_connection();
+
+        (0..=30)
+            .filter(|&now| {
+                clock.advance_to(now);
+                ping.packet_sent();
+                ping.packet_received();
+                ping.poll(&mut cx, &mut None, std::time::Duration::from_secs(10)).is_some()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn other_packets_replace_pings() {
+        assert!(pings(false).is_empty());
+    }
+
+    #[test]
+    fn always_ping() {
+        assert_eq!(pings(true), [10, 15, 20, 25, 30]);
+    }
+}
@@ -0,0 +1,113 @@
+/// Announces whether a [`Client`](super::Client) is online with a retained message on a presence topic, set with
+/// [`Client::with_presence`](super::Client::with_presence).
+///
+/// The client registers a retained "offline" publication as its will, so the server publishes it if the client disconnects unexpectedly,
+/// and publishes a retained "online" publication every time it connects to the server. If the "online" publication of an earlier connection
+/// was not acked yet, the client only sends that one again instead of publishing another.
+///
+/// When the client is shut down, it asks the server to publish the will as if it had been shut down with
+/// [`ShutdownHandle::shutdown_with_will`](super::ShutdownHandle::shutdown_with_will), so that the presence topic does not keep saying
+/// that the client is online. This does not happen if the will was replaced with [`Client::set_will`](super::Client::set_will).
+#[derive(Clone, Debug)]
+pub struct Presence {
+    topic_name: crate::proto::ByteStr,
+    qos: crate::proto::QoS,
+    online_payload: bytes::Bytes,
+    offline_payload: bytes::Bytes,
+}
+
+impl Presence {
+    /// Creates a presence that publishes `online` and `offline` to the given topic with the given QoS.
+    #[allow(clippy::doc_markdown)]
+    pub fn new(topic_name: crate::proto::ByteStr, qos: crate::proto::QoS) -> Self {
+        Presence {
+            topic_name,
+            qos,
+            online_payload: bytes::Bytes::from_static(b"online"),
+            offline_payload: bytes::Bytes::from_static(b"offline"),
+        }
+    }
+
+    /// Sets the payload published when the client connects.
+    #[must_use]
+    pub fn with_online_payload(mut self, online_payload: impl Into<bytes::Bytes>) -> Self {
+        self.online_payload = online_payload.into();
+        self
+    }
+
+    /// Sets the payload published by the server when the client disconnects unexpectedly.
+    #[must_use]
+    pub fn with_offline_payload(mut self, offline_payload: impl Into<bytes::Bytes>) -> Self {
+        self.offline_payload = offline_payload.into();
+        self
+    }
+
+    /// The retained publication that announces the client is online
+    pub fn online_publication(&self) -> crate::proto::Publication {
+        self.publication(self.online_payload.clone())
+    }
+
+    /// The retained publication that announces the client is offline. This is also the client's will.
+    pub fn offline_publication(&self) -> crate::proto::Publication {
+        self.publication(self.offline_payload.clone())
+    }
+
+    fn publication(&self, payload: bytes::Bytes) -> crate::proto::Publication {
+        crate::proto::Publication {
+            topic_name: self.topic_name.clone(),
+            qos: self.qos,
+            retain: true,
+            payload,
+            user_properties: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        }
+    }
+}
+
+type OnlineAck = std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), super::PublishError>> + Send>>;
+
+/// Publishes the "online" publication of a [`Presence`] on every new connection
+pub(super) struct State {
+    presence: Presence,
+
+    /// Resolves when the "online" publication of an earlier connection has been acked or has failed
+    online_ack: Option<OnlineAck>,
+}
+
+impl State {
+    pub(super) fn new(presence: Presence) -> Self {
+        State {
+            presence,
+            online_ack: None,
+        }
+    }
+
+    pub(super) fn presence(&self) -> &Presence {
+        &self.presence
+    }
+
+    /// Publishes the "online" publication, unless the one published on an earlier connection is still waiting to be sent or acked.
+    /// The client sends that one again by itself, so publishing another would only retain the same message twice.
+    pub(super) fn new_connection(&mut self, cx: &mut std::task::Context<'_>, publish: &mut super::publish::State) {
+        if let Some(online_ack) = &mut self.online_ack {
+            if online_ack.as_mut().poll(cx).is_pending() {
+                log::debug!("not publishing presence again because the publication of an earlier connection has not been acked yet");
+                return;
+            }
+        }
+
+        self.online_ack = Some(Box::pin(publish.publish(self.presence.online_publication(), super::PublishPriority::High)));
+    }
+}
+
+impl std::fmt::Debug for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("State")
+            .field("presence", &self.presence)
+            .field("online_pending", &self.online_ack.is_some())
+            .finish()
+    }
+}
@@ -2,15 +2,84 @@ use std::future::Future;
 
 #[derive(Debug)]
 pub(super) struct State {
-    publish_request_send: futures_channel::mpsc::Sender<PublishRequest>,
-    publish_request_recv: futures_channel::mpsc::Receiver<PublishRequest>,
+    publish_request_send: super::queued::Sender<PublishRequest>,
+    publish_request_recv: super::queued::Receiver<PublishRequest>,
 
-    publish_requests_waiting_to_be_sent: std::collections::VecDeque<PublishRequest>,
+    publish_requests_waiting_to_be_sent: PublishRequestLanes,
+
+    delivery_mode: DeliveryMode,
+
+    retransmit_policy: RetransmitPolicy,
+
+    duplicate_policy: DuplicatePolicy,
+
+    publish_before_first_connection: PublishBeforeFirstConnection,
+
+    /// Publish requests that have been queued for longer than this are dropped instead of sent
+    max_message_age: Option<std::time::Duration>,
+
+    publish_pacing: PublishPacing,
+
+    /// In [`PublishPacing::SpreadOverKeepAlive`], the keep-alive of the new connection whose first publish requests have not been paced yet
+    pace_next_burst: Option<std::time::Duration>,
+
+    /// In [`PublishPacing::SpreadOverKeepAlive`], the publish requests that were queued when the connection was established, being paced
+    paced_burst: Option<PacedBurst>,
+
+    replay_ordering: ReplayOrdering,
+
+    /// In [`ReplayOrdering::QueuedFirst`], whether the publish requests queued for a new connection have not been made its backlog yet
+    mark_next_backlog: bool,
+
+    clock: super::clock::SharedClock,
+
+    /// The earliest deadline of the publish requests waiting to be sent, and the timer that fires at it
+    deadline_timer: Option<(std::time::Duration, super::clock::Timer)>,
+
+    topic_rewriter: super::TopicRewriter,
+
+    topic_limits: super::TopicLimits,
+
+    manual_acks: bool,
+    manual_ack_send: futures_channel::mpsc::UnboundedSender<ManualAckRequest>,
+    manual_ack_recv: futures_channel::mpsc::UnboundedReceiver<ManualAckRequest>,
+
+    /// Incremented every time the session is reset, so that manual acks for publications received in a previous session are ignored
+    session: u64,
+
+    /// In manual ack mode, holds the identifiers of exactly-once PUBLISH packets received by us that have been returned to the application,
+    /// waiting for the application to ack them
+    waiting_for_manual_ack: std::collections::BTreeSet<crate::proto::PacketIdentifier>,
+
+    /// In manual ack mode, holds the identifiers of PUBREC packets sent by us after the application acked the publication,
+    /// waiting for a corresponding PUBREL
+    waiting_to_be_released_after_manual_ack: std::collections::BTreeSet<crate::proto::PacketIdentifier>,
+
+    /// In [`DuplicatePolicy::SuppressSeen`], holds a fingerprint of the last at-least-once publication received by us with each packet identifier,
+    /// and whether it has been acked
+    at_least_once_received: std::collections::HashMap<crate::proto::PacketIdentifier, (u64, bool)>,
+
+    /// Whether the client has connected to the server at least once
+    connected_once: bool,
+
+    /// In [`RetransmitPolicy::ReturnToApplication`], holds the at-least-once publications that were not acked before the connection was lost,
+    /// waiting to be returned to the application
+    publications_not_acked: Vec<crate::proto::Publication>,
+
+    /// In [`DeliveryMode::OrderedPerTopic`], holds the topics that have a PUBLISH packet sent by us waiting to be acked,
+    /// and the publish requests to that topic waiting for it
+    topics_waiting_to_be_acked:
+        std::collections::HashMap<crate::proto::ByteStr, std::collections::VecDeque<PublishRequest>>,
+
+    #[allow(clippy::doc_markdown)]
+    /// The number of QoS 1 and QoS 2 PUBLISH packets sent by us so far. Each packet waiting for an ack is stored with the number it was sent as,
+    /// so that packets can be resent in the order they were first sent even after their packet identifiers wrapped around.
+    publications_sent: u64,
 
     /// Holds PUBLISH packets sent by us, waiting for a corresponding PUBACK or PUBREC
     waiting_to_be_acked: std::collections::BTreeMap<
         crate::proto::PacketIdentifier,
-        (futures_channel::oneshot::Sender<()>, crate::proto::Publish),
+        (u64, AckSender, crate::proto::Publish),
     >,
 
     /// Holds the identifiers of PUBREC packets sent by us, waiting for a corresponding PUBREL,
@@ -21,8 +90,26 @@ pub(super) struct State {
     /// Holds PUBLISH packets sent by us, waiting for a corresponding PUBCOMP
     waiting_to_be_completed: std::collections::BTreeMap<
         crate::proto::PacketIdentifier,
-        (futures_channel::oneshot::Sender<()>, crate::proto::Publish),
+        (u64, AckSender, crate::proto::Publish),
     >,
+
+    #[allow(clippy::doc_markdown)]
+    /// The number of QoS 1 and QoS 2 PUBLISH packets sent by us that the server allows to wait for acks at once on the current connection.
+    /// Publish requests beyond it wait in `publish_requests_waiting_to_be_sent` until earlier ones are acked.
+    receive_maximum: usize,
+
+    /// The size in bytes of the largest packet that the server accepts on the current connection, if it limits them.
+    /// Publish requests whose PUBLISH packets are larger fail instead of being sent.
+    maximum_packet_size: Option<u32>,
+
+    #[allow(clippy::doc_markdown)]
+    /// The highest QoS that the server accepts for publications on the current connection.
+    /// Publish requests with a higher QoS are sent with this one instead.
+    maximum_qos: crate::proto::QoS,
+
+    /// Holds the publish requests that were sent with a lower quality of service than requested because of `maximum_qos`,
+    /// waiting to be reported to the application
+    qos_downgrades: Vec<super::QoSDowngrade>,
 }
 
 impl State {
@@ -32,26 +119,74 @@ impl State {
 
         packet: &mut Option<crate::proto::Packet>,
         packet_identifiers: &mut super::PacketIdentifiers,
-    ) -> Result<
-        (
-            Vec<crate::proto::Packet>,
-            Option<crate::ReceivedPublication>,
-        ),
-        super::Error,
-    > {
+        packets_waiting_to_be_sent: &mut std::collections::VecDeque<crate::proto::Packet>,
+    ) -> Result<Option<crate::ReceivedPublication>, super::Error> {
         use futures_core::Stream;
 
-        let mut packets_waiting_to_be_sent = vec![];
+        // Apply the acks of the application before the packet, in case the packet is the server sending again a publication
+        // that the application dropped without acking
+        while let std::task::Poll::Ready(Some(ManualAckRequest { packet_identifier, qos, session, acked })) =
+            std::pin::Pin::new(&mut self.manual_ack_recv).poll_next(cx)
+        {
+            if session != self.session {
+                log::debug!("ignoring ack for a publication received in a previous session");
+                continue;
+            }
+
+            if !acked {
+                // The application dropped the publication without acking it. Forget that it was received,
+                // so that it is returned to the application again when the server sends it again.
+                log::debug!("publication {} was dropped without being acked", packet_identifier);
+                match qos {
+                    crate::proto::QoS::AtMostOnce => (),
+
+                    crate::proto::QoS::AtLeastOnce =>
+                        if matches!(self.at_least_once_received.get(&packet_identifier), Some((_, false))) {
+                            self.at_least_once_received.remove(&packet_identifier);
+                        },
+
+                    crate::proto::QoS::ExactlyOnce => {
+                        self.waiting_for_manual_ack.remove(&packet_identifier);
+                    },
+                }
+
+                continue;
+            }
+
+            match qos {
+                crate::proto::QoS::AtMostOnce => (),
+
+                crate::proto::QoS::AtLeastOnce => {
+                    if let Some((_, acked)) = self.at_least_once_received.get_mut(&packet_identifier) {
+                        *acked = true;
+                    }
+
+                    packets_waiting_to_be_sent.push_back(crate::proto::Packet::PubAck(
+                        crate::proto::PubAck { packet_identifier },
+                    ));
+                }
+
+                crate::proto::QoS::ExactlyOnce =>
+                    if self.waiting_for_manual_ack.remove(&packet_identifier) {
+                        self.waiting_to_be_released_after_manual_ack.insert(packet_identifier);
+                        packets_waiting_to_be_sent.push_back(crate::proto::Packet::PubRec(
+                            crate::proto::PubRec { packet_identifier },
+                        ));
+                    },
+            }
+        }
+
         let mut publication_received = None;
 
         match packet.take() {
             Some(crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier })) =>
-                if let Some((ack_sender, _)) = self.waiting_to_be_acked.remove(&packet_identifier) {
+                if let Some((_, ack_sender, packet)) = self.waiting_to_be_acked.remove(&packet_identifier) {
                     packet_identifiers.discard(packet_identifier);
+                    self.topic_acked(&packet.topic_name);
 
-                    match ack_sender.send(()) {
+                    match ack_sender.send(Ok(())) {
                         Ok(()) => (),
-                        Err(()) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+                        Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
                     }
                 }
                 else {
@@ -59,12 +194,13 @@ impl State {
                 },
 
             Some(crate::proto::Packet::PubComp(crate::proto::PubComp { packet_identifier })) =>
-                if let Some((ack_sender, _)) = self.waiting_to_be_completed.remove(&packet_identifier) {
+                if let Some((_, ack_sender, packet)) = self.waiting_to_be_completed.remove(&packet_identifier) {
                     packet_identifiers.discard(packet_identifier);
+                    self.topic_acked(&packet.topic_name);
 
-                    match ack_sender.send(()) {
+                    match ack_sender.send(Ok(())) {
                         Ok(()) => (),
-                        Err(()) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+                        Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
                     }
                 }
                 else {
@@ -76,32 +212,94 @@ impl State {
                 retain,
                 topic_name,
                 payload,
-            })) => match packet_identifier_dup_qos {
-                crate::proto::PacketIdentifierDupQoS::AtMostOnce => {
+                user_properties,
+                subscription_identifiers,
+                response_topic,
+                correlation_data,
+                message_expiry: _,
+                content_type: _,
+            })) => match (packet_identifier_dup_qos, self.topic_rewriter.rewrite_incoming(topic_name)) {
+                (crate::proto::PacketIdentifierDupQoS::AtMostOnce, topic_name) => {
                     publication_received = Some(crate::ReceivedPublication {
                         topic_name,
                         dup: false,
                         qos: crate::proto::QoS::AtMostOnce,
+                        packet_identifier: None,
                         retain,
                         payload,
+                        user_properties,
+                        subscription_identifiers,
+                        response_topic,
+                        correlation_data,
+                        manual_ack: None,
                     });
                 }
 
-                crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, dup) => {
-                    publication_received = Some(crate::ReceivedPublication {
-                        topic_name,
-                        dup,
-                        qos: crate::proto::QoS::AtLeastOnce,
-                        retain,
-                        payload,
-                    });
+                (crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, dup), topic_name) =>
+                    if let Some(original_acked) = self.at_least_once_received(packet_identifier, dup, &topic_name, &payload) {
+                        // The server sent this publication again, such as because it didn't receive our PUBACK before the connection was lost.
+                        // Ack it again, unless the application hasn't acked the original yet, in which case that ack covers this one too.
+                        log::debug!("suppressing duplicate PUBLISH {} with topic {:?}", packet_identifier, topic_name);
 
-                    packets_waiting_to_be_sent.push(crate::proto::Packet::PubAck(
-                        crate::proto::PubAck { packet_identifier },
-                    ));
+                        if original_acked {
+                            packets_waiting_to_be_sent.push_back(crate::proto::Packet::PubAck(
+                                crate::proto::PubAck { packet_identifier },
+                            ));
+                        }
+                    }
+                    else {
+                        publication_received = Some(crate::ReceivedPublication {
+                            topic_name,
+                            dup,
+                            qos: crate::proto::QoS::AtLeastOnce,
+                            packet_identifier: Some(packet_identifier),
+                            retain,
+                            payload,
+                            user_properties,
+                            subscription_identifiers,
+                            response_topic,
+                            correlation_data,
+                            manual_ack: self.manual_ack(packet_identifier, crate::proto::QoS::AtLeastOnce),
+                        });
+
+                        if !self.manual_acks {
+                            packets_waiting_to_be_sent.push_back(crate::proto::Packet::PubAck(
+                                crate::proto::PubAck { packet_identifier },
+                            ));
+                        }
+                    },
+
+                // In manual ack mode, ExactlyOnce publications are returned to the application as soon as they are received,
+                // and the PUBREC is only sent once the application acks them. So if the application never acks the publication,
+                // such as because it crashed while processing it, the server will send it again.
+                (crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, dup), topic_name) if self.manual_acks => {
+                    if self.waiting_to_be_released_after_manual_ack.contains(&packet_identifier) {
+                        // The application already acked this PUBLISH and a PUBREC was sent in response, but the server apparently didn't receive it.
+                        packets_waiting_to_be_sent.push_back(crate::proto::Packet::PubRec(
+                            crate::proto::PubRec { packet_identifier },
+                        ));
+                    }
+                    else if self.waiting_for_manual_ack.insert(packet_identifier) {
+                        publication_received = Some(crate::ReceivedPublication {
+                            topic_name,
+                            dup,
+                            qos: crate::proto::QoS::ExactlyOnce,
+                            packet_identifier: Some(packet_identifier),
+                            retain,
+                            payload,
+                            user_properties,
+                            subscription_identifiers,
+                            response_topic,
+                            correlation_data,
+                            manual_ack: self.manual_ack(packet_identifier, crate::proto::QoS::ExactlyOnce),
+                        });
+                    }
+                    else if !dup {
+                        return Err(super::Error::DuplicateExactlyOncePublishPacketNotMarkedDuplicate(packet_identifier));
+                    }
                 }
 
-                crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, dup) => {
+                (crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, dup), topic_name) => {
                     match self.waiting_to_be_released.entry(packet_identifier) {
                         std::collections::btree_map::Entry::Occupied(_) =>
                         // This PUBLISH was already received earlier and a PUBREC sent in response, but the server apparently didn't receive it.
@@ -119,28 +317,34 @@ impl State {
                                 topic_name,
                                 dup,
                                 qos: crate::proto::QoS::ExactlyOnce,
+                                packet_identifier: Some(packet_identifier),
                                 retain,
                                 payload,
+                                user_properties,
+                                subscription_identifiers,
+                                response_topic,
+                                correlation_data,
+                                manual_ack: None,
                             });
                         }
                     }
 
-                    packets_waiting_to_be_sent.push(crate::proto::Packet::PubRec(
+                    packets_waiting_to_be_sent.push_back(crate::proto::Packet::PubRec(
                         crate::proto::PubRec { packet_identifier },
                     ));
                 }
             },
 
             Some(crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier })) => {
-                if let Some((ack_sender, packet)) = self.waiting_to_be_acked.remove(&packet_identifier) {
+                if let Some(in_flight) = self.waiting_to_be_acked.remove(&packet_identifier) {
                     self.waiting_to_be_completed
-                        .insert(packet_identifier, (ack_sender, packet));
+                        .insert(packet_identifier, in_flight);
                 }
                 else {
                     log::warn!("ignoring PUBREC for a PUBLISH we never sent");
                 }
 
-                packets_waiting_to_be_sent.push(crate::proto::Packet::PubRel(
+                packets_waiting_to_be_sent.push_back(crate::proto::Packet::PubRel(
                     crate::proto::PubRel { packet_identifier },
                 ));
             }
@@ -149,11 +353,13 @@ impl State {
                 if let Some(publication) = self.waiting_to_be_released.remove(&packet_identifier) {
                     packet_identifiers.discard(packet_identifier);
                     publication_received = Some(publication);
+                } else if self.waiting_to_be_released_after_manual_ack.remove(&packet_identifier) {
+                    // Publication was already returned to the application when it was received
                 } else {
                     log::warn!("ignoring PUBREL for a PUBREC we never sent");
                 }
 
-                packets_waiting_to_be_sent.push(crate::proto::Packet::PubComp(
+                packets_waiting_to_be_sent.push_back(crate::proto::Packet::PubComp(
                     crate::proto::PubComp { packet_identifier },
                 ));
             }
@@ -161,33 +367,157 @@ impl State {
             other => *packet = other,
         }
 
-        while let std::task::Poll::Ready(Some(publish_request)) =
-            std::pin::Pin::new(&mut self.publish_request_recv).poll_next(cx)
-        {
-            self.publish_requests_waiting_to_be_sent
-                .push_back(publish_request);
+        self.receive_publish_requests(cx);
+
+        // Like the burst to pace, the backlog is made of the requests that were queued by the time the new connection is first polled,
+        // including the ones that were still in the channel.
+        if std::mem::take(&mut self.mark_next_backlog) {
+            self.publish_requests_waiting_to_be_sent.mark_backlog();
+            for publish_request in self.topics_waiting_to_be_acked.values_mut().flatten() {
+                publish_request.backlog = true;
+            }
         }
 
-        while let Some(PublishRequest {
-            publication,
-            ack_sender,
-        }) = self.publish_requests_waiting_to_be_sent.pop_front()
-        {
+        if let Some(interval) = self.pace_next_burst.take() {
+            let len = self.publish_requests_waiting_to_be_sent.len();
+            if len > 1 && interval > std::time::Duration::ZERO {
+                log::debug!("pacing {} queued publications over {:?}", len, interval);
+                self.paced_burst = Some(PacedBurst {
+                    started_at: self.clock.now(),
+                    interval,
+                    len,
+                    sent: 0,
+                    timer: None,
+                });
+            }
+        }
+
+        // Publish requests stay in their lanes until every packet queued earlier has been handed to the sink,
+        // so that requests which arrive while the connection is backed up can still be reordered by priority.
+        if !packets_waiting_to_be_sent.is_empty() {
+            return Ok(publication_received);
+        }
+
+        while !self.publish_requests_waiting_to_be_sent.is_empty() && self.within_receive_maximum() && self.poll_paced_burst(cx) {
+            let mut publish_request = self.publish_requests_waiting_to_be_sent.pop_front().expect("lanes are not empty");
+
+            let queued_for = self.clock.now().saturating_sub(publish_request.queued_at);
+
+            if let Some(max_message_age) = self.max_message_age {
+                if queued_for > max_message_age {
+                    log::debug!(
+                        "dropping publication to {:?} because it was queued for longer than {:?}",
+                        publish_request.publication.topic_name, max_message_age,
+                    );
+
+                    match publish_request.ack_sender.send(Err(PublishError::Expired)) {
+                        Ok(()) => (),
+                        Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+                    }
+
+                    continue;
+                }
+            }
+
+            if let Some(message_expiry) = publish_request.publication.message_expiry {
+                if queued_for >= message_expiry {
+                    log::debug!(
+                        "dropping publication to {:?} because it expired after {:?}",
+                        publish_request.publication.topic_name, message_expiry,
+                    );
+
+                    match publish_request.ack_sender.send(Err(PublishError::Expired)) {
+                        Ok(()) => (),
+                        Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+                    }
+
+                    continue;
+                }
+            }
+
+            if publish_request.deadline_exceeded(self.clock.now()) {
+                publish_request.fail_deadline_exceeded();
+                continue;
+            }
+
+            if let Some(maximum_packet_size) = self.maximum_packet_size {
+                let packet_size = packet_size(&publish_request.publication);
+                if packet_size > maximum_packet_size as usize {
+                    log::debug!(
+                        "dropping publication to {:?} because its packet of {} bytes is larger than the maximum packet size of {} bytes",
+                        publish_request.publication.topic_name, packet_size, maximum_packet_size,
+                    );
+
+                    let err = PublishError::PacketTooLarge(Box::new(publish_request.publication), packet_size, maximum_packet_size);
+                    match publish_request.ack_sender.send(Err(err)) {
+                        Ok(()) => (),
+                        Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+                    }
+
+                    continue;
+                }
+            }
+
+            if publish_request.publication.qos > self.maximum_qos {
+                log::warn!(
+                    "publishing to {:?} with {:?} instead of {:?} because that is the maximum QoS of the server",
+                    publish_request.publication.topic_name, self.maximum_qos, publish_request.publication.qos,
+                );
+                self.qos_downgrades.push(super::QoSDowngrade {
+                    topic_name: publish_request.publication.topic_name.clone(),
+                    requested_qos: publish_request.publication.qos,
+                    qos: self.maximum_qos,
+                });
+                publish_request.publication.qos = self.maximum_qos;
+            }
+
+            if let DeliveryMode::OrderedPerTopic = self.delivery_mode {
+                if let Some(publish_requests_waiting_for_topic) =
+                    self.topics_waiting_to_be_acked.get_mut(&publish_request.publication.topic_name)
+                {
+                    publish_requests_waiting_for_topic.push_back(publish_request);
+                    continue;
+                }
+
+                if publish_request.publication.qos != crate::proto::QoS::AtMostOnce {
+                    self.topics_waiting_to_be_acked
+                        .insert(publish_request.publication.topic_name.clone(), Default::default());
+                }
+            }
+
+            let PublishRequest {
+                publication,
+                priority,
+                ack_sender,
+                queued_at,
+                deadline,
+                backlog,
+            } = publish_request;
+
+            // The server is sent the time that remains
+            let message_expiry = publication.message_expiry.map(|message_expiry| message_expiry.saturating_sub(queued_for));
+
             match publication.qos {
                 crate::proto::QoS::AtMostOnce => {
-                    packets_waiting_to_be_sent.push(crate::proto::Packet::Publish(
+                    packets_waiting_to_be_sent.push_back(crate::proto::Packet::Publish(
                         crate::proto::Publish {
                             packet_identifier_dup_qos:
                                 crate::proto::PacketIdentifierDupQoS::AtMostOnce,
                             retain: publication.retain,
                             topic_name: publication.topic_name,
                             payload: publication.payload,
+                            user_properties: publication.user_properties,
+                            subscription_identifiers: vec![],
+                            response_topic: publication.response_topic,
+                            correlation_data: publication.correlation_data,
+                            message_expiry,
+                            content_type: publication.content_type,
                         },
                     ));
 
-                    match ack_sender.send(()) {
+                    match ack_sender.send(Ok(())) {
                         Ok(()) => (),
-                        Err(()) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+                        Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
                     }
                 }
 
@@ -195,10 +525,15 @@ impl State {
                     let packet_identifier = match packet_identifiers.reserve() {
                         Ok(packet_identifier) => packet_identifier,
                         Err(err) => {
+                            self.topics_waiting_to_be_acked.remove(&publication.topic_name);
                             self.publish_requests_waiting_to_be_sent
                                 .push_front(PublishRequest {
                                     publication,
+                                    priority,
                                     ack_sender,
+                                    queued_at,
+                                    deadline,
+                                    backlog,
                                 });
                             return Err(err);
                         }
@@ -213,11 +548,19 @@ impl State {
                         retain: publication.retain,
                         topic_name: publication.topic_name.clone(),
                         payload: publication.payload.clone(),
+                        user_properties: publication.user_properties.clone(),
+                        subscription_identifiers: vec![],
+                        response_topic: publication.response_topic.clone(),
+                        correlation_data: publication.correlation_data.clone(),
+                        message_expiry,
+                        content_type: publication.content_type.clone(),
                     });
 
+                    self.publications_sent += 1;
                     self.waiting_to_be_acked.insert(
                         packet_identifier,
                         (
+                            self.publications_sent,
                             ack_sender,
                             crate::proto::Publish {
                                 packet_identifier_dup_qos:
@@ -228,21 +571,32 @@ impl State {
                                 retain: publication.retain,
                                 topic_name: publication.topic_name,
                                 payload: publication.payload,
+                                user_properties: publication.user_properties,
+                                subscription_identifiers: vec![],
+                                response_topic: publication.response_topic,
+                                correlation_data: publication.correlation_data,
+                                message_expiry,
+                                content_type: publication.content_type,
                             },
                         ),
                     );
 
-                    packets_waiting_to_be_sent.push(packet);
+                    packets_waiting_to_be_sent.push_back(packet);
                 }
 
                 crate::proto::QoS::ExactlyOnce => {
                     let packet_identifier = match packet_identifiers.reserve() {
                         Ok(packet_identifier) => packet_identifier,
                         Err(err) => {
+                            self.topics_waiting_to_be_acked.remove(&publication.topic_name);
                             self.publish_requests_waiting_to_be_sent
                                 .push_front(PublishRequest {
                                     publication,
+                                    priority,
                                     ack_sender,
+                                    queued_at,
+                                    deadline,
+                                    backlog,
                                 });
                             return Err(err);
                         }
@@ -257,11 +611,19 @@ impl State {
                         retain: publication.retain,
                         topic_name: publication.topic_name.clone(),
                         payload: publication.payload.clone(),
+                        user_properties: publication.user_properties.clone(),
+                        subscription_identifiers: vec![],
+                        response_topic: publication.response_topic.clone(),
+                        correlation_data: publication.correlation_data.clone(),
+                        message_expiry,
+                        content_type: publication.content_type.clone(),
                     });
 
+                    self.publications_sent += 1;
                     self.waiting_to_be_acked.insert(
                         packet_identifier,
                         (
+                            self.publications_sent,
                             ack_sender,
                             crate::proto::Publish {
                                 packet_identifier_dup_qos:
@@ -272,23 +634,45 @@ impl State {
                                 retain: publication.retain,
                                 topic_name: publication.topic_name,
                                 payload: publication.payload,
+                                user_properties: publication.user_properties,
+                                subscription_identifiers: vec![],
+                                response_topic: publication.response_topic,
+                                correlation_data: publication.correlation_data,
+                                message_expiry,
+                                content_type: publication.content_type,
                             },
                         ),
                     );
 
-                    packets_waiting_to_be_sent.push(packet);
+                    packets_waiting_to_be_sent.push_back(packet);
                 }
             }
         }
 
-        Ok((packets_waiting_to_be_sent, publication_received))
+        Ok(publication_received)
     }
 
     pub(super) fn new_connection<'a>(
         &'a mut self,
         reset_session: bool,
+        keep_alive: std::time::Duration,
+        receive_maximum: u16,
+        maximum_packet_size: Option<u32>,
+        maximum_qos: crate::proto::QoS,
         packet_identifiers: &mut super::PacketIdentifiers,
     ) -> impl Iterator<Item = crate::proto::Packet> + 'a {
+        self.connected_once = true;
+        self.receive_maximum = receive_maximum.into();
+        self.maximum_packet_size = maximum_packet_size;
+        self.maximum_qos = maximum_qos;
+
+        self.paced_burst = None;
+        self.pace_next_burst = match self.publish_pacing {
+            PublishPacing::Burst => None,
+            PublishPacing::SpreadOverKeepAlive => Some(keep_alive),
+        };
+        self.mark_next_backlog = self.replay_ordering == ReplayOrdering::QueuedFirst;
+
         if reset_session {
             // Move all waiting_to_be_completed back to waiting_to_be_acked since we must restart the ExactlyOnce protocol flow
             self.waiting_to_be_acked
@@ -298,10 +682,74 @@ impl State {
             for (packet_identifier, _) in std::mem::take(&mut self.waiting_to_be_released) {
                 packet_identifiers.discard(packet_identifier);
             }
+
+            // Forget the publications that the application has not acked yet, and ignore its acks for them
+            self.session += 1;
+            self.waiting_for_manual_ack.clear();
+            self.waiting_to_be_released_after_manual_ack.clear();
+
+            self.at_least_once_received.clear();
+        }
+
+        if self.retransmit_policy != RetransmitPolicy::ResendAsDuplicate {
+            let packet_identifiers_at_least_once: Vec<_> =
+                in_send_order(&self.waiting_to_be_acked)
+                .filter_map(|(packet_identifier, packet)| match packet.packet_identifier_dup_qos {
+                    crate::proto::PacketIdentifierDupQoS::AtLeastOnce(..) => Some(packet_identifier),
+                    _ => None,
+                })
+                .collect();
+
+            let mut publications_not_acked = vec![];
+
+            // Iterate in reverse so that requeued publications end up in the order they were sent at the front of the queue
+            for packet_identifier in packet_identifiers_at_least_once.into_iter().rev() {
+                let (_, ack_sender, packet) = self.waiting_to_be_acked.remove(&packet_identifier).expect("packet identifier was just found");
+                packet_identifiers.discard(packet_identifier);
+
+                // Release any publications that were waiting for this one
+                self.topic_acked(&packet.topic_name);
+
+                let publication = crate::proto::Publication {
+                    topic_name: packet.topic_name,
+                    qos: crate::proto::QoS::AtLeastOnce,
+                    retain: packet.retain,
+                    payload: packet.payload,
+                    user_properties: packet.user_properties,
+                    response_topic: packet.response_topic,
+                    correlation_data: packet.correlation_data,
+                    message_expiry: packet.message_expiry,
+                    content_type: packet.content_type,
+                };
+
+                match self.retransmit_policy {
+                    RetransmitPolicy::ResendAsDuplicate => unreachable!(),
+
+                    RetransmitPolicy::Requeue =>
+                        self.publish_requests_waiting_to_be_sent.push_front(PublishRequest {
+                            publication,
+                            priority: PublishPriority::High,
+                            ack_sender,
+                            queued_at: self.clock.now(),
+                            deadline: None,
+                            backlog: false,
+                        }),
+
+                    RetransmitPolicy::ReturnToApplication => {
+                        match ack_sender.send(Err(PublishError::NotAcked)) {
+                            Ok(()) => (),
+                            Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+                        }
+
+                        publications_not_acked.push(publication);
+                    }
+                }
+            }
+
+            self.publications_not_acked.extend(publications_not_acked.into_iter().rev());
         }
 
-        self.waiting_to_be_acked
-            .values()
+        in_send_order(&self.waiting_to_be_acked)
             .map(|(_, packet)| crate::proto::Packet::Publish(packet.clone()))
             .chain(
                 self.waiting_to_be_released
@@ -311,146 +759,1989 @@ impl State {
                     }),
             )
             .chain(
-                self.waiting_to_be_completed
-                    .values()
+                self.waiting_to_be_released_after_manual_ack
+                    .iter()
+                    .map(|&packet_identifier| {
+                        crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier })
+                    }),
+            )
+            .chain(
+                in_send_order(&self.waiting_to_be_completed)
                     .map(|(_, packet)| crate::proto::Packet::Publish(packet.clone())),
             )
     }
 
-    pub(super) fn publish(
+    pub(super) fn set_delivery_mode(&mut self, delivery_mode: DeliveryMode) {
+        self.delivery_mode = delivery_mode;
+    }
+
+    pub(super) fn set_retransmit_policy(&mut self, retransmit_policy: RetransmitPolicy) {
+        self.retransmit_policy = retransmit_policy;
+    }
+
+    pub(super) fn set_duplicate_policy(&mut self, duplicate_policy: DuplicatePolicy) {
+        self.duplicate_policy = duplicate_policy;
+    }
+
+    /// In [`DuplicatePolicy::SuppressSeen`], records an at-least-once publication received by us. If it is a duplicate of the last publication
+    /// received with the same packet identifier, returns whether that publication was already acked.
+    fn at_least_once_received(
         &mut self,
-        publication: crate::proto::Publication,
-    ) -> impl Future<Output = Result<(), PublishError>> {
-        let (ack_sender, ack_receiver) = futures_channel::oneshot::channel();
-        match PublishRequest::new(publication, ack_sender) {
-            Ok(publish_request) => {
-                use futures_util::TryFutureExt;
+        packet_identifier: crate::proto::PacketIdentifier,
+        dup: bool,
+        topic_name: &crate::proto::ByteStr,
+        payload: &[u8],
+    ) -> Option<bool> {
+        use std::hash::{Hash, Hasher};
 
-                self.publish_requests_waiting_to_be_sent
-                    .push_back(publish_request);
-                futures_util::future::Either::Left(
-                    ack_receiver.map_err(|_| PublishError::ClientDoesNotExist),
-                )
+        if self.duplicate_policy != DuplicatePolicy::SuppressSeen {
+            return None;
+        }
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        AsRef::<str>::as_ref(topic_name).hash(&mut hasher);
+        payload.hash(&mut hasher);
+        let fingerprint = hasher.finish();
+
+        match self.at_least_once_received.entry(packet_identifier) {
+            std::collections::hash_map::Entry::Occupied(entry) if dup && entry.get().0 == fingerprint => Some(entry.get().1),
+
+            std::collections::hash_map::Entry::Occupied(mut entry) => {
+                entry.insert((fingerprint, !self.manual_acks));
+                None
             }
 
-            Err(err) => futures_util::future::Either::Right(futures_util::future::err(err)),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert((fingerprint, !self.manual_acks));
+                None
+            }
         }
     }
 
-    pub(super) fn publish_handle(&self) -> PublishHandle {
-        PublishHandle(self.publish_request_send.clone())
+    pub(super) fn set_max_message_age(&mut self, max_message_age: Option<std::time::Duration>) {
+        self.max_message_age = max_message_age;
     }
-}
 
-impl Default for State {
-    fn default() -> Self {
-        let (publish_request_send, publish_request_recv) = futures_channel::mpsc::channel(0);
+    pub(super) fn set_publish_pacing(&mut self, publish_pacing: PublishPacing) {
+        self.publish_pacing = publish_pacing;
+    }
 
-        State {
-            publish_request_send,
-            publish_request_recv,
+    pub(super) fn set_replay_ordering(&mut self, replay_ordering: ReplayOrdering) {
+        self.replay_ordering = replay_ordering;
+    }
 
-            publish_requests_waiting_to_be_sent: Default::default(),
-            waiting_to_be_acked: Default::default(),
-            waiting_to_be_released: Default::default(),
-            waiting_to_be_completed: Default::default(),
+    /// Returns whether the next publish request can be sent without exceeding the server's Receive Maximum.
+    /// At-most-once publish requests are never acked, so they do not count towards it.
+    #[allow(clippy::doc_markdown)]
+    fn within_receive_maximum(&self) -> bool {
+        match self.publish_requests_waiting_to_be_sent.front() {
+            Some(publish_request) if publish_request.publication.qos != crate::proto::QoS::AtMostOnce =>
+                self.waiting_to_be_acked.len() + self.waiting_to_be_completed.len() < self.receive_maximum,
+            _ => true,
         }
     }
-}
 
-/// Used to publish messages to the server
-#[derive(Clone, Debug)]
-pub struct PublishHandle(futures_channel::mpsc::Sender<PublishRequest>);
+    /// Returns whether the next publish request can be sent now. While a burst is being paced, only as many of its publish requests
+    /// as are due by now can be sent, and the task is woken up when the next one is due.
+    fn poll_paced_burst(&mut self, cx: &mut std::task::Context<'_>) -> bool {
+        let can_send = match &mut self.paced_burst {
+            Some(paced_burst) => paced_burst.poll_send(cx, &self.clock),
+            None => None,
+        };
 
-impl PublishHandle {
-    /// Publish the given message to the server
-    pub async fn publish(
-        &mut self,
-        publication: crate::proto::Publication,
-    ) -> Result<(), PublishError> {
-        use futures_util::SinkExt;
+        if can_send.is_none() {
+            self.paced_burst = None;
+        }
+        can_send.unwrap_or(true)
+    }
 
-        let (ack_sender, ack_receiver) = futures_channel::oneshot::channel();
+    pub(super) fn set_clock(&mut self, clock: super::clock::SharedClock) {
+        self.clock = clock;
+        self.deadline_timer = None;
+    }
 
-        let publish_request = PublishRequest::new(publication, ack_sender)?;
-        self.0
-            .send(publish_request)
-            .await
-            .map_err(|_| PublishError::ClientDoesNotExist)?;
-        ack_receiver
-            .await
-            .map_err(|_| PublishError::ClientDoesNotExist)?;
-        Ok(())
+    pub(super) fn set_publish_before_first_connection(&mut self, publish_before_first_connection: PublishBeforeFirstConnection) {
+        self.publish_before_first_connection = publish_before_first_connection;
     }
-}
 
-#[derive(Debug)]
-pub enum PublishError {
-    ClientDoesNotExist,
-    EncodePacket(crate::proto::Publication, crate::proto::EncodeError),
-}
+    /// Fails the publish requests that are still waiting to be sent when their deadline passes, even while the client is not connected.
+    /// See [`PublishHandle::publish_with_deadline`].
+    pub(super) fn poll_deadlines(&mut self, cx: &mut std::task::Context<'_>) {
+        use futures_util::FutureExt;
 
-impl std::fmt::Display for PublishError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            PublishError::ClientDoesNotExist => write!(f, "client does not exist"),
-            PublishError::EncodePacket(publication, err) => write!(
-                f,
-                "cannot encode PUBLISH packet with topic {:?}: {}",
-                publication.topic_name, err
-            ),
+        self.receive_publish_requests(cx);
+
+        loop {
+            let now = self.clock.now();
+            for publish_request in self.publish_requests_waiting_to_be_sent.take_deadline_exceeded(now) {
+                publish_request.fail_deadline_exceeded();
+            }
+            for publish_requests_waiting_for_topic in self.topics_waiting_to_be_acked.values_mut() {
+                for publish_request in take_deadline_exceeded(publish_requests_waiting_for_topic, now) {
+                    publish_request.fail_deadline_exceeded();
+                }
+            }
+
+            let next_deadline =
+                self.publish_requests_waiting_to_be_sent.iter()
+                .chain(self.topics_waiting_to_be_acked.values().flatten())
+                .filter_map(|publish_request| publish_request.deadline)
+                .min();
+            match next_deadline {
+                Some(next_deadline) if !matches!(&self.deadline_timer, Some((deadline, _)) if *deadline == next_deadline) =>
+                    self.deadline_timer = Some((next_deadline, super::clock::Timer(self.clock.sleep_until(next_deadline)))),
+                Some(_) => (),
+                None => self.deadline_timer = None,
+            }
+
+            let deadline_passed = match &mut self.deadline_timer {
+                Some((_, deadline_timer)) => deadline_timer.0.poll_unpin(cx).is_ready(),
+                None => false,
+            };
+            if !deadline_passed {
+                break;
+            }
+
+            self.deadline_timer = None;
         }
     }
-}
 
-impl std::error::Error for PublishError {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        match self {
-            PublishError::ClientDoesNotExist => None,
-            PublishError::EncodePacket(_, err) => Some(err),
+    /// Moves the publish requests made with [`PublishHandle`]s into the lanes of requests waiting to be sent
+    fn receive_publish_requests(&mut self, cx: &mut std::task::Context<'_>) {
+        use futures_core::Stream;
+
+        while let std::task::Poll::Ready(Some(PublishRequest { publication, priority, ack_sender, queued_at, deadline, backlog })) =
+            std::pin::Pin::new(&mut self.publish_request_recv).poll_next(cx)
+        {
+            match self.rewrite_publication(publication) {
+                Ok(publication) =>
+                    self.publish_requests_waiting_to_be_sent
+                        .push_back(PublishRequest { publication, priority, ack_sender, queued_at, deadline, backlog }),
+
+                Err(err) => match ack_sender.send(Err(err)) {
+                    Ok(()) => (),
+                    Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+                },
+            }
         }
     }
-}
 
-#[derive(Debug)]
-struct PublishRequest {
-    publication: crate::proto::Publication,
-    ack_sender: futures_channel::oneshot::Sender<()>,
-}
+    /// Fails publish requests received from [`PublishHandle`]s if the client has not connected yet and is configured with
+    /// [`PublishBeforeFirstConnection::Fail`]
+    pub(super) fn poll_before_first_connection(&mut self, cx: &mut std::task::Context<'_>) {
+        use futures_core::Stream;
 
-impl PublishRequest {
-    fn new(
-        publication: crate::proto::Publication,
-        ack_sender: futures_channel::oneshot::Sender<()>,
-    ) -> Result<PublishRequest, PublishError> {
-        use crate::proto::PacketMeta;
+        if self.connected_once || self.publish_before_first_connection != PublishBeforeFirstConnection::Fail {
+            return;
+        }
 
-        let packet = crate::proto::Publish {
-            packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
-            retain: publication.retain,
-            topic_name: publication.topic_name,
-            payload: publication.payload,
-        };
+        while let std::task::Poll::Ready(Some(PublishRequest { ack_sender, .. })) =
+            std::pin::Pin::new(&mut self.publish_request_recv).poll_next(cx)
+        {
+            match ack_sender.send(Err(PublishError::NotConnected)) {
+                Ok(()) => (),
+                Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+            }
+        }
+    }
 
-        let mut counter = crate::proto::ByteCounter::new();
-        let encode_result =
-            packet.clone()
-            .encode(&mut counter)
-            .and_then(|()| crate::proto::encode_remaining_length(counter.0, &mut counter));
-
-        let publication = crate::proto::Publication {
-            topic_name: packet.topic_name,
-            qos: publication.qos,
-            retain: publication.retain,
-            payload: packet.payload,
-        };
+    /// Fails all publish requests that have not been published yet with [`PublishError::ClientClosed`],
+    /// including the ones that were sent to the server but not acked.
+    pub(super) fn close(&mut self, cx: &mut std::task::Context<'_>, packet_identifiers: &mut super::PacketIdentifiers) {
+        use futures_core::Stream;
 
-        match encode_result {
-            Ok(_) => Ok(PublishRequest {
-                publication,
-                ack_sender,
-            }),
-            Err(err) => Err(PublishError::EncodePacket(publication, err)),
+        let mut ack_senders = vec![];
+
+        while let std::task::Poll::Ready(Some(PublishRequest { ack_sender, .. })) =
+            std::pin::Pin::new(&mut self.publish_request_recv).poll_next(cx)
+        {
+            ack_senders.push(ack_sender);
+        }
+
+        while let Some(PublishRequest { ack_sender, .. }) = self.publish_requests_waiting_to_be_sent.pop_front() {
+            ack_senders.push(ack_sender);
+        }
+
+        for (_, publish_requests) in self.topics_waiting_to_be_acked.drain() {
+            ack_senders.extend(publish_requests.into_iter().map(|PublishRequest { ack_sender, .. }| ack_sender));
         }
+
+        let in_flight = std::mem::take(&mut self.waiting_to_be_acked).into_iter().chain(std::mem::take(&mut self.waiting_to_be_completed));
+        for (packet_identifier, (_, ack_sender, _)) in in_flight {
+            packet_identifiers.discard(packet_identifier);
+            ack_senders.push(ack_sender);
+        }
+
+        for ack_sender in ack_senders {
+            match ack_sender.send(Err(PublishError::ClientClosed)) {
+                Ok(()) => (),
+                Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+            }
+        }
+    }
+
+    pub(super) fn set_manual_acks(&mut self, manual_acks: bool) {
+        self.manual_acks = manual_acks;
+    }
+
+    fn manual_ack(&self, packet_identifier: crate::proto::PacketIdentifier, qos: crate::proto::QoS) -> Option<ManualAck> {
+        if self.manual_acks {
+            Some(ManualAck {
+                request: Some(ManualAckRequest {
+                    packet_identifier,
+                    qos,
+                    session: self.session,
+                    acked: false,
+                }),
+                manual_ack_send: self.manual_ack_send.clone(),
+            })
+        }
+        else {
+            None
+        }
+    }
+
+    pub(super) fn set_topic_rewriter(&mut self, topic_rewriter: super::TopicRewriter) {
+        self.topic_rewriter = topic_rewriter;
+    }
+
+    pub(super) fn set_topic_limits(&mut self, topic_limits: super::TopicLimits) {
+        self.topic_limits = topic_limits;
+    }
+
+    /// Applies the outgoing topic rewrite rules to the given publication, and checks its rewritten topic against the topic limits
+    fn rewrite_publication(&self, publication: crate::proto::Publication) -> Result<crate::proto::Publication, PublishError> {
+        let publication = match self.topic_rewriter.rewrite_outgoing(&publication.topic_name) {
+            Some(Ok(topic_name)) => validate_publication(crate::proto::Publication { topic_name, ..publication })?,
+            Some(Err(err)) => return Err(PublishError::EncodePacket(Box::new(publication), err)),
+            None => publication,
+        };
+
+        match self.topic_limits.check(publication.topic_name.as_ref()) {
+            Ok(()) => Ok(publication),
+            Err(err) => Err(PublishError::TopicLimitExceeded(Box::new(publication), err)),
+        }
+    }
+
+    /// Returns the publications that were not acked before the connection was lost, if any, in the order they were sent
+    pub(super) fn take_publications_not_acked(&mut self) -> Option<Vec<crate::proto::Publication>> {
+        if self.publications_not_acked.is_empty() {
+            None
+        }
+        else {
+            Some(std::mem::take(&mut self.publications_not_acked))
+        }
+    }
+
+    /// Returns the publish requests that were sent with a lower quality of service than requested since the last call, if any
+    pub(super) fn take_qos_downgrades(&mut self) -> Option<Vec<super::QoSDowngrade>> {
+        if self.qos_downgrades.is_empty() {
+            None
+        }
+        else {
+            Some(std::mem::take(&mut self.qos_downgrades))
+        }
+    }
+
+    /// Called when a PUBLISH packet sent by us to the given topic has been acked.
+    ///
+    /// In [`DeliveryMode::OrderedPerTopic`], the publish requests that were waiting for it are sent next. They were already dequeued
+    /// once, so they go ahead of everything still waiting to be sent.
+    fn topic_acked(&mut self, topic_name: &crate::proto::ByteStr) {
+        if let Some(publish_requests_waiting_for_topic) = self.topics_waiting_to_be_acked.remove(topic_name) {
+            for mut publish_request in publish_requests_waiting_for_topic.into_iter().rev() {
+                publish_request.priority = PublishPriority::High;
+                self.publish_requests_waiting_to_be_sent.push_front(publish_request);
+            }
+        }
+    }
+
+    pub(super) fn publish(
+        &mut self,
+        publication: crate::proto::Publication,
+        priority: PublishPriority,
+    ) -> impl Future<Output = Result<(), PublishError>> {
+        if !self.connected_once && self.publish_before_first_connection == PublishBeforeFirstConnection::Fail {
+            return futures_util::future::Either::Right(futures_util::future::err(PublishError::NotConnected));
+        }
+
+        let (ack_sender, ack_receiver) = futures_channel::oneshot::channel();
+        match self.rewrite_publication(publication).and_then(|publication| PublishRequest::new(publication, priority, AckSender::Future(ack_sender), self.clock.now())) {
+            Ok(publish_request) => {
+                use futures_util::FutureExt;
+
+                self.publish_requests_waiting_to_be_sent
+                    .push_back(publish_request);
+                futures_util::future::Either::Left(
+                    ack_receiver.map(|ack| ack.unwrap_or_else(|_| Err(PublishError::ClientClosed))),
+                )
+            }
+
+            Err(err) => futures_util::future::Either::Right(futures_util::future::err(err)),
+        }
+    }
+
+    pub(super) fn publish_handle(&self, closed: super::Closed) -> PublishHandle {
+        PublishHandle(self.publish_request_send.clone(), closed, self.clock.clone())
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let (publish_request_send, publish_request_recv) = super::queued::channel(0);
+        let (manual_ack_send, manual_ack_recv) = futures_channel::mpsc::unbounded();
+
+        State {
+            publish_request_send,
+            publish_request_recv,
+
+            publish_requests_waiting_to_be_sent: Default::default(),
+            delivery_mode: Default::default(),
+            retransmit_policy: Default::default(),
+            duplicate_policy: Default::default(),
+            publish_before_first_connection: Default::default(),
+            max_message_age: None,
+            publish_pacing: Default::default(),
+            pace_next_burst: None,
+            paced_burst: None,
+            replay_ordering: Default::default(),
+            mark_next_backlog: false,
+            clock: Default::default(),
+            deadline_timer: None,
+            topic_rewriter: Default::default(),
+            topic_limits: Default::default(),
+            manual_acks: false,
+            manual_ack_send,
+            manual_ack_recv,
+            session: 0,
+            waiting_for_manual_ack: Default::default(),
+            waiting_to_be_released_after_manual_ack: Default::default(),
+            at_least_once_received: Default::default(),
+            connected_once: false,
+            publications_not_acked: Default::default(),
+            topics_waiting_to_be_acked: Default::default(),
+            publications_sent: 0,
+            waiting_to_be_acked: Default::default(),
+            waiting_to_be_released: Default::default(),
+            waiting_to_be_completed: Default::default(),
+            receive_maximum: u16::MAX.into(),
+            maximum_packet_size: None,
+            maximum_qos: crate::proto::QoS::ExactlyOnce,
+            qos_downgrades: Default::default(),
+        }
+    }
+}
+
+/// Used to publish messages to the server
+#[derive(Clone, Debug)]
+pub struct PublishHandle(super::queued::Sender<PublishRequest>, super::Closed, super::clock::SharedClock);
+
+impl PublishHandle {
+    /// Whether the client has shut down or was dropped, in which case publishing fails with [`PublishError::ClientClosed`].
+    ///
+    /// This becomes `false` again if the client is started again with [`Client::restart`](crate::Client::restart).
+    pub fn is_closed(&self) -> bool {
+        self.1.is_closed() || self.0.is_closed()
+    }
+
+    /// Publish the given message to the server
+    pub async fn publish(
+        &mut self,
+        publication: crate::proto::Publication,
+    ) -> Result<(), PublishError> {
+        self.publish_with_priority(publication, PublishPriority::default()).await
+    }
+
+    /// Publish the given message to the server, ahead of any queued messages of lower priority
+    pub async fn publish_with_priority(
+        &mut self,
+        publication: crate::proto::Publication,
+        priority: PublishPriority,
+    ) -> Result<(), PublishError> {
+        if self.is_closed() {
+            return Err(PublishError::ClientClosed);
+        }
+
+        let (ack_sender, ack_receiver) = futures_channel::oneshot::channel();
+
+        let publish_request = PublishRequest::new(publication, priority, AckSender::Future(ack_sender), self.2.now())?;
+        self.0
+            .send(publish_request)
+            .await
+            .map_err(|_| PublishError::ClientClosed)?;
+        ack_receiver
+            .await
+            .map_err(|_| PublishError::ClientClosed)?
+    }
+
+    /// Publish the given message to the server, unless it cannot be sent to the server by the given deadline, such as because
+    /// the client is not connected. Then the publication is dropped and this fails with [`PublishError::DeadlineExceeded`].
+    ///
+    /// This is for publications that are useless when they arrive late, such as control commands. A publication that was sent
+    /// before its deadline is not affected by it, even if it is acked after it.
+    pub async fn publish_with_deadline(
+        &mut self,
+        publication: crate::proto::Publication,
+        priority: PublishPriority,
+        deadline: std::time::Instant,
+    ) -> Result<(), PublishError> {
+        if self.is_closed() {
+            return Err(PublishError::ClientClosed);
+        }
+
+        let (ack_sender, ack_receiver) = futures_channel::oneshot::channel();
+
+        // The client measures time with its clock, which need not follow `Instant`
+        let now = self.2.now();
+        let mut publish_request = PublishRequest::new(publication, priority, AckSender::Future(ack_sender), now)?;
+        publish_request.deadline = Some(now + deadline.saturating_duration_since(std::time::Instant::now()));
+
+        self.0
+            .send(publish_request)
+            .await
+            .map_err(|_| PublishError::ClientClosed)?;
+        ack_receiver
+            .await
+            .map_err(|_| PublishError::ClientClosed)?
+    }
+
+    /// Publish the given message to the server without waiting, and call `callback` with the result instead.
+    ///
+    /// This is for applications that are built around callbacks rather than futures, such as bindings for other languages.
+    /// The callback is called exactly once, from the task that polls the [`Client`](crate::Client), when the publish would have completed;
+    /// or with [`PublishError::ClientClosed`] from wherever the client is dropped. It must not block, since it holds up the client.
+    ///
+    /// Errors that are known right away, such as an invalid topic name or a closed client, are returned instead, and then
+    /// the callback is not called.
+    pub fn publish_with_callback(
+        &self,
+        publication: crate::proto::Publication,
+        priority: PublishPriority,
+        callback: impl FnOnce(Result<(), PublishError>) + Send + 'static,
+    ) -> Result<(), PublishError> {
+        if self.is_closed() {
+            return Err(PublishError::ClientClosed);
+        }
+
+        let publish_request = PublishRequest::new(publication, priority, AckSender::Callback(CallbackAck(Some(Box::new(callback)))), self.2.now())?;
+
+        self.0
+            .try_send(publish_request)
+            .map_err(|mut publish_request| {
+                // Don't call the callback of the request that was not queued
+                if let AckSender::Callback(callback) = &mut publish_request.ack_sender {
+                    callback.0 = None;
+                }
+                PublishError::ClientClosed
+            })
+    }
+
+    /// Closes the handle once the client has received every publish request queued with it, such as the ones of
+    /// [`PublishHandle::publish_with_callback`], so that dropping the handle right after publishing does not race the client.
+    ///
+    /// The [`Future`] returned by this function does not wait for the publications to be sent or acked, only for the client to take them.
+    /// It also resolves if the client is dropped, since that fails the publications that were still queued.
+    /// The requests queued with clones of this handle are not waited for.
+    pub async fn close(self) {
+        self.0.close().await;
+    }
+
+    pub(super) fn clock(&self) -> &super::clock::SharedClock {
+        &self.2
+    }
+}
+
+/// Acks a publication received by a [`Client`](crate::Client) in manual ack mode. See [`crate::ReceivedPublication::ack`].
+///
+/// Dropping it without acking the publication makes the client forget the publication, so that it is returned to the application again
+/// when the server sends it again.
+#[derive(Debug)]
+pub struct ManualAck {
+    request: Option<ManualAckRequest>,
+    manual_ack_send: futures_channel::mpsc::UnboundedSender<ManualAckRequest>,
+}
+
+impl ManualAck {
+    /// Acks the publication to the server
+    pub fn ack(mut self) {
+        if let Some(request) = self.request.take() {
+            if self.manual_ack_send.unbounded_send(ManualAckRequest { acked: true, ..request }).is_err() {
+                log::debug!("could not ack publication because the client does not exist");
+            }
+        }
+    }
+}
+
+impl Drop for ManualAck {
+    fn drop(&mut self) {
+        if let Some(request) = self.request.take() {
+            // The client may not exist any more, in which case there is nothing to forget
+            let _ = self.manual_ack_send.unbounded_send(request);
+        }
+    }
+}
+
+impl PartialEq for ManualAck {
+    fn eq(&self, other: &Self) -> bool {
+        self.request == other.request
+    }
+}
+
+impl Eq for ManualAck {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ManualAckRequest {
+    packet_identifier: crate::proto::PacketIdentifier,
+    qos: crate::proto::QoS,
+    session: u64,
+
+    /// Whether the application acked the publication, or dropped it without acking it
+    acked: bool,
+}
+
+/// The priority of a publication relative to other publications queued on the same client.
+///
+/// Publications that are waiting to be sent because the connection is backed up or down are sent in order of priority,
+/// so that for example commands are not stuck behind bulk telemetry. Publications to the same topic are always sent
+/// in the order they were published: publishing with a higher priority also moves any queued publications to that topic
+/// ahead with it.
+///
+/// After a reconnect, the publications that were queued while the client was disconnected are sent before the newer ones
+/// unless configured otherwise with [`ReplayOrdering`].
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub enum PublishPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[allow(clippy::doc_markdown)]
+/// Controls how many PUBLISH packets with QoS 1 or 2 the client sends before earlier ones have been acked.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DeliveryMode {
+    /// Publications are sent as soon as possible, without waiting for earlier publications to be acked.
+    #[default]
+    Pipelined,
+
+    #[allow(clippy::doc_markdown)]
+    /// At most one publication with QoS 1 or 2 per topic is waiting to be acked at any time. Publications to the same topic
+    /// wait for it, even QoS 0 ones, so that a retransmission after a reconnect can never overtake a later publication to that topic.
+    /// Publications to other topics are not held up.
+    OrderedPerTopic,
+}
+
+#[allow(clippy::doc_markdown)]
+/// Controls what the client does with QoS 1 publications that were not acked when the connection was lost.
+///
+/// QoS 2 publications are always resent, since the protocol requires it for them to be delivered exactly once.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RetransmitPolicy {
+    /// Resend the publications on the new connection with the DUP flag set.
+    #[default]
+    ResendAsDuplicate,
+
+    /// Send the publications again as new publications, with new packet identifiers and without the DUP flag.
+    Requeue,
+
+    /// Don't send the publications again. Instead, fail their publish requests with [`PublishError::NotAcked`]
+    /// and return them in an [`crate::Event::PublicationsNotAcked`] so that the application can decide whether to publish them again.
+    ReturnToApplication,
+}
+
+#[allow(clippy::doc_markdown)]
+/// Controls what the client does with QoS 1 publications received with the DUP flag set.
+///
+/// The server sets the DUP flag when it sends a publication again, such as because it did not receive the client's PUBACK
+/// before the connection was lost. QoS 2 publications are never returned to the application twice, since the protocol prevents it for them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Return every publication to the application, with [`crate::ReceivedPublication::dup`] set for duplicates.
+    #[default]
+    Deliver,
+
+    /// Ack duplicates without returning them to the application, if the client already received a publication with the same packet identifier,
+    /// topic and payload in the current session. Duplicates whose original was not received are still returned to the application.
+    SuppressSeen,
+}
+
+/// Controls how the client sends the publications that were queued while it was disconnected, once it has connected.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PublishPacing {
+    /// Send all the publications at once.
+    #[default]
+    Burst,
+
+    /// Send the publications evenly over the keep-alive interval of the connection, so that the server does not receive them
+    /// and send their acks all at once. Publications requested in the meantime are sent after them.
+    ///
+    /// QoS 1 and QoS 2 publications that are resent with the DUP flag set are still sent right away.
+    #[allow(clippy::doc_markdown)]
+    SpreadOverKeepAlive,
+}
+
+/// Controls the order of the publications that were queued while the client was disconnected, relative to the ones published
+/// after it has connected again.
+///
+/// The queued publications are the ones published before the client polls the new connection for the first time,
+/// including the publications that [`RetransmitPolicy::Requeue`] puts back in the queue. QoS 1 and QoS 2 publications that are
+/// resent with the DUP flag set are always sent first.
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReplayOrdering {
+    /// Send all the queued publications before any publication published after the connection was established, whatever their
+    /// priorities, so that a stream of commands keeps its causal order across a reconnect. The queued publications are sent
+    /// among themselves in order of priority.
+    ///
+    /// In [`DeliveryMode::OrderedPerTopic`], queued publications that wait for the ack of an earlier publication to their topic
+    /// do not hold up the others.
+    #[default]
+    QueuedFirst,
+
+    /// Send the queued publications and the ones published after the connection was established together in order of priority,
+    /// so that a high priority publication can overtake the queued ones.
+    ByPriority,
+}
+
+/// Controls what the client does with publications requested before it has connected to the server for the first time.
+///
+/// Once the client has connected, publications requested while it is reconnecting are always queued.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PublishBeforeFirstConnection {
+    /// Queue the publications until the client has connected.
+    #[default]
+    Queue,
+
+    /// Fail the publish requests with [`PublishError::NotConnected`], so that they don't wait forever
+    /// if the client can never connect, such as because its credentials are invalid.
+    Fail,
+}
+
+#[derive(Debug)]
+pub enum PublishError {
+    ClientClosed,
+    DeadlineElapsed,
+
+    /// The publication was dropped without being sent, because it was still queued when its deadline passed.
+    /// See [`PublishHandle::publish_with_deadline`].
+    DeadlineExceeded,
+
+    EncodePacket(Box<crate::proto::Publication>, crate::proto::EncodeError),
+    Expired,
+    NotAcked,
+    NotConnected,
+
+    /// The PUBLISH packet of the publication, of the given size in bytes, is larger than the Maximum Packet Size of the given size
+    /// that the server advertised, so the server would have dropped the connection if the client had sent it
+    #[allow(clippy::doc_markdown)]
+    PacketTooLarge(Box<crate::proto::Publication>, usize, u32),
+
+    TopicLimitExceeded(Box<crate::proto::Publication>, super::TopicLimitExceeded),
+}
+
+impl std::fmt::Display for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublishError::ClientClosed => write!(f, "client has shut down or was dropped"),
+            PublishError::DeadlineElapsed => write!(f, "publication was not published before its deadline"),
+            PublishError::DeadlineExceeded => write!(f, "publication was dropped because it was not sent before its deadline"),
+            PublishError::EncodePacket(publication, err) => write!(
+                f,
+                "cannot encode PUBLISH packet with topic {:?}: {}",
+                publication.topic_name, err
+            ),
+            PublishError::Expired => write!(f, "publication was queued for longer than the maximum message age or its message expiry"),
+            PublishError::NotAcked => write!(f, "server did not ack the publication before the connection was lost"),
+            PublishError::NotConnected => write!(f, "client has not connected to the server yet"),
+            PublishError::PacketTooLarge(publication, packet_size, maximum_packet_size) => write!(
+                f,
+                "cannot publish to topic {:?}: packet of {} bytes is larger than the maximum packet size of {} bytes accepted by the server",
+                publication.topic_name, packet_size, maximum_packet_size,
+            ),
+            PublishError::TopicLimitExceeded(publication, err) => write!(
+                f,
+                "cannot publish to topic {:?}: {}",
+                publication.topic_name, err
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PublishError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PublishError::ClientClosed
+            | PublishError::DeadlineElapsed
+            | PublishError::DeadlineExceeded
+            | PublishError::Expired
+            | PublishError::NotAcked
+            | PublishError::NotConnected
+            | PublishError::PacketTooLarge(..) => None,
+            PublishError::EncodePacket(_, err) => Some(err),
+            PublishError::TopicLimitExceeded(_, err) => Some(err),
+        }
+    }
+}
+
+/// Reports the result of a publish request to whoever made it
+#[derive(Debug)]
+enum AckSender {
+    /// The result is awaited by the future returned by [`PublishHandle::publish`] or [`PublishHandle::publish_with_priority`]
+    Future(futures_channel::oneshot::Sender<Result<(), PublishError>>),
+
+    /// The result is passed to the callback given to [`PublishHandle::publish_with_callback`]
+    Callback(CallbackAck),
+}
+
+impl AckSender {
+    /// Reports the given result. Returns it back if nothing is waiting for it anymore.
+    fn send(self, result: Result<(), PublishError>) -> Result<(), Result<(), PublishError>> {
+        match self {
+            AckSender::Future(sender) => sender.send(result),
+            AckSender::Callback(mut callback) => {
+                if let Some(callback) = callback.0.take() {
+                    callback(result);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The callback of a publish request, which is called with [`PublishError::ClientClosed`] if the request is dropped
+/// without a result, like the future of a request fails then
+struct CallbackAck(Option<PublishCallback>);
+
+type PublishCallback = Box<dyn FnOnce(Result<(), PublishError>) + Send>;
+
+impl Drop for CallbackAck {
+    fn drop(&mut self) {
+        if let Some(callback) = self.0.take() {
+            callback(Err(PublishError::ClientClosed));
+        }
+    }
+}
+
+impl std::fmt::Debug for CallbackAck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CallbackAck").finish()
+    }
+}
+
+#[derive(Debug)]
+struct PublishRequest {
+    publication: crate::proto::Publication,
+    priority: PublishPriority,
+    ack_sender: AckSender,
+    /// The time of the [`super::Clock`] of the client when the request was queued
+    queued_at: std::time::Duration,
+    /// The time of the [`super::Clock`] of the client after which the request is dropped instead of sent, see [`PublishHandle::publish_with_deadline`]
+    deadline: Option<std::time::Duration>,
+    /// Whether the request was queued before the current connection was established, see [`ReplayOrdering::QueuedFirst`]
+    backlog: bool,
+}
+
+impl PublishRequest {
+    fn new(
+        publication: crate::proto::Publication,
+        priority: PublishPriority,
+        ack_sender: AckSender,
+        queued_at: std::time::Duration,
+    ) -> Result<PublishRequest, PublishError> {
+        let publication = validate_publication(publication)?;
+        Ok(PublishRequest {
+            publication,
+            priority,
+            ack_sender,
+            queued_at,
+            deadline: None,
+            backlog: false,
+        })
+    }
+
+    fn deadline_exceeded(&self, now: std::time::Duration) -> bool {
+        matches!(self.deadline, Some(deadline) if now >= deadline)
+    }
+
+    fn fail_deadline_exceeded(self) {
+        log::debug!("dropping publication to {:?} because it was not sent before its deadline", self.publication.topic_name);
+
+        match self.ack_sender.send(Err(PublishError::DeadlineExceeded)) {
+            Ok(()) => (),
+            Err(_) => log::debug!("could not send ack for publish request because ack receiver has been dropped"),
+        }
+    }
+}
+
+/// Checks that the given publication can be encoded into a PUBLISH packet
+fn validate_publication(publication: crate::proto::Publication) -> Result<crate::proto::Publication, PublishError> {
+    use crate::proto::PacketMeta;
+
+    let packet = crate::proto::Publish {
+        packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+        retain: publication.retain,
+        topic_name: publication.topic_name,
+        payload: publication.payload,
+        user_properties: publication.user_properties,
+        subscription_identifiers: vec![],
+        response_topic: publication.response_topic,
+        correlation_data: publication.correlation_data,
+        message_expiry: publication.message_expiry,
+        content_type: publication.content_type,
+    };
+
+    let encode_result =
+        packet.body_len()
+        .and_then(|body_len| crate::proto::encode_remaining_length(body_len, &mut crate::proto::ByteCounter::new()));
+
+    let publication = crate::proto::Publication {
+        topic_name: packet.topic_name,
+        qos: publication.qos,
+        retain: publication.retain,
+        payload: packet.payload,
+        user_properties: packet.user_properties,
+        response_topic: packet.response_topic,
+        correlation_data: packet.correlation_data,
+        message_expiry: packet.message_expiry,
+        content_type: packet.content_type,
+    };
+
+    if let Err(err) = encode_result {
+        return Err(PublishError::EncodePacket(Box::new(publication), err));
+    }
+
+    Ok(publication)
+}
+
+/// Returns the packet identifiers and PUBLISH packets waiting for acks in the order they were first sent.
+/// The map is ordered by packet identifier, which is not the same order once packet identifiers wrap around.
+fn in_send_order(
+    in_flight: &std::collections::BTreeMap<crate::proto::PacketIdentifier, (u64, AckSender, crate::proto::Publish)>,
+) -> impl Iterator<Item = (crate::proto::PacketIdentifier, &crate::proto::Publish)> {
+    let mut in_flight: Vec<_> = in_flight.iter().map(|(&packet_identifier, (sent, _, packet))| (*sent, packet_identifier, packet)).collect();
+    in_flight.sort_by_key(|&(sent, _, _)| sent);
+    in_flight.into_iter().map(|(_, packet_identifier, packet)| (packet_identifier, packet))
+}
+
+/// The size in bytes of the PUBLISH packet of the given publication
+fn packet_size(publication: &crate::proto::Publication) -> usize {
+    // The packet identifier is not assigned yet, but any one takes up the same room
+    let packet_identifier = crate::proto::PacketIdentifier::new(1).expect("1 is a valid packet identifier");
+    let packet = crate::proto::Packet::Publish(crate::proto::Publish {
+        packet_identifier_dup_qos: match publication.qos {
+            crate::proto::QoS::AtMostOnce => crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+            crate::proto::QoS::AtLeastOnce => crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, false),
+            crate::proto::QoS::ExactlyOnce => crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, false),
+        },
+        retain: publication.retain,
+        topic_name: publication.topic_name.clone(),
+        payload: publication.payload.clone(),
+        user_properties: publication.user_properties.clone(),
+        subscription_identifiers: vec![],
+        response_topic: publication.response_topic.clone(),
+        correlation_data: publication.correlation_data.clone(),
+        message_expiry: publication.message_expiry,
+        content_type: publication.content_type.clone(),
+    });
+
+    // The publication was validated when it was queued, so it can be encoded
+    crate::proto::encoded_len(packet).unwrap_or(usize::MAX)
+}
+
+/// The publish requests that were queued when a connection was established, being sent evenly over an interval
+#[derive(Debug)]
+struct PacedBurst {
+    started_at: std::time::Duration,
+    interval: std::time::Duration,
+    len: usize,
+    sent: usize,
+    timer: Option<super::clock::Timer>,
+}
+
+impl PacedBurst {
+    /// Returns whether the next publish request of the burst can be sent now, or `None` if the burst is over
+    fn poll_send(&mut self, cx: &mut std::task::Context<'_>, clock: &super::clock::SharedClock) -> Option<bool> {
+        loop {
+            let elapsed = clock.now().saturating_sub(self.started_at);
+            if self.sent >= self.len || elapsed >= self.interval {
+                return None;
+            }
+
+            // The first publish request is sent right away, and the rest are spread evenly over the interval
+            if self.sent <= self.due(elapsed) {
+                self.sent += 1;
+                self.timer = None;
+                return Some(true);
+            }
+
+            let next_due_at = self.started_at + self.next_due_after();
+            let timer = self.timer.get_or_insert_with(|| super::clock::Timer(clock.sleep_until(next_due_at)));
+            match timer.0.as_mut().poll(cx) {
+                std::task::Poll::Ready(()) => self.timer = None,
+                std::task::Poll::Pending => return Some(false),
+            }
+        }
+    }
+
+    /// The number of publish requests after the first one that are due after the given time has elapsed
+    fn due(&self, elapsed: std::time::Duration) -> usize {
+        let due = elapsed.as_nanos() * self.len as u128 / self.interval.as_nanos();
+        std::convert::TryInto::try_into(due).unwrap_or(usize::MAX)
+    }
+
+    /// The time after the start of the burst when the next publish request is due
+    fn next_due_after(&self) -> std::time::Duration {
+        let next_due_after = self.interval.as_nanos() * self.sent as u128 / self.len as u128;
+        std::time::Duration::from_nanos(std::convert::TryInto::try_into(next_due_after).unwrap_or(u64::MAX))
+    }
+}
+
+/// Publish requests waiting to be sent, with one FIFO lane per [`PublishPriority`]
+///
+/// All the requests for a given topic are always in the same lane, so draining the lanes from highest to lowest priority
+/// preserves the order of publications to any one topic. Requests of the backlog are drained before all the others.
+#[derive(Debug, Default)]
+struct PublishRequestLanes {
+    lanes: [std::collections::VecDeque<PublishRequest>; 3],
+
+    /// The number of requests in the lanes that are part of the backlog
+    backlog: usize,
+}
+
+impl PublishRequestLanes {
+    fn len(&self) -> usize {
+        self.lanes.iter().map(std::collections::VecDeque::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lanes.iter().all(std::collections::VecDeque::is_empty)
+    }
+
+    /// Makes all the requests in the lanes part of the backlog
+    fn mark_backlog(&mut self) {
+        for publish_request in self.lanes.iter_mut().flatten() {
+            publish_request.backlog = true;
+        }
+        self.backlog = self.len();
+    }
+
+    fn push_back(&mut self, publish_request: PublishRequest) {
+        if publish_request.backlog {
+            self.backlog += 1;
+        }
+
+        let (lower, rest) = self.lanes.split_at_mut(publish_request.priority as usize);
+        let lane = &mut rest[0];
+
+        // Promote any queued requests for the same topic, so that they are still sent before this one.
+        for lower_lane in lower {
+            let mut i = 0;
+            while i < lower_lane.len() {
+                if lower_lane[i].publication.topic_name == publish_request.publication.topic_name {
+                    let mut promoted = lower_lane.remove(i).expect("index is in range");
+                    promoted.priority = publish_request.priority;
+                    lane.push_back(promoted);
+                }
+                else {
+                    i += 1;
+                }
+            }
+        }
+
+        lane.push_back(publish_request);
+    }
+
+    fn push_front(&mut self, publish_request: PublishRequest) {
+        if publish_request.backlog {
+            self.backlog += 1;
+        }
+
+        self.lanes[publish_request.priority as usize].push_front(publish_request);
+    }
+
+    /// The request that the next [`PublishRequestLanes::pop_front`] returns
+    fn front(&self) -> Option<&PublishRequest> {
+        if self.backlog == 0 {
+            return self.lanes.iter().rev().find_map(std::collections::VecDeque::front);
+        }
+
+        self.lanes.iter().rev().find_map(|lane| lane.iter().find(|publish_request| publish_request.backlog))
+    }
+
+    fn pop_front(&mut self) -> Option<PublishRequest> {
+        if self.backlog == 0 {
+            return self.lanes.iter_mut().rev().find_map(std::collections::VecDeque::pop_front);
+        }
+
+        // Requests promoted into a higher lane can end up behind requests that are not part of the backlog
+        self.backlog -= 1;
+        Some(
+            self.lanes.iter_mut().rev()
+                .find_map(|lane| {
+                    let i = lane.iter().position(|publish_request| publish_request.backlog)?;
+                    lane.remove(i)
+                })
+                .expect("lanes have requests that are part of the backlog"),
+        )
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &PublishRequest> {
+        self.lanes.iter().flatten()
+    }
+
+    /// Removes the requests whose deadline has passed
+    fn take_deadline_exceeded(&mut self, now: std::time::Duration) -> Vec<PublishRequest> {
+        let mut deadline_exceeded = vec![];
+        for lane in &mut self.lanes {
+            deadline_exceeded.extend(take_deadline_exceeded(lane, now));
+        }
+
+        self.backlog -= deadline_exceeded.iter().filter(|publish_request| publish_request.backlog).count();
+        deadline_exceeded
+    }
+}
+
+/// Removes the requests whose deadline has passed from the given queue, keeping the order of the others
+fn take_deadline_exceeded(publish_requests: &mut std::collections::VecDeque<PublishRequest>, now: std::time::Duration) -> Vec<PublishRequest> {
+    if !publish_requests.iter().any(|publish_request| publish_request.deadline_exceeded(now)) {
+        return vec![];
+    }
+
+    let mut deadline_exceeded = vec![];
+    for publish_request in std::mem::take(publish_requests) {
+        if publish_request.deadline_exceeded(now) {
+            deadline_exceeded.push(publish_request);
+        }
+        else {
+            publish_requests.push_back(publish_request);
+        }
+    }
+    deadline_exceeded
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn publish_request_lanes() {
+        fn publish_request(topic_name: &str, priority: super::PublishPriority) -> super::PublishRequest {
+            super::PublishRequest {
+                publication: crate::proto::Publication {
+                    topic_name: topic_name.parse().unwrap(),
+                    qos: crate::proto::QoS::AtMostOnce,
+                    retain: false,
+                    payload: bytes::Bytes::copy_from_slice(format!("{:?}", priority).as_bytes()),
+                    user_properties: vec![],
+                    response_topic: None,
+                    correlation_data: None,
+                    message_expiry: None,
+                    content_type: None,
+                },
+                priority,
+                ack_sender: super::AckSender::Future(futures_channel::oneshot::channel().0),
+                queued_at: std::time::Duration::ZERO,
+                deadline: None,
+                backlog: false,
+            }
+        }
+
+        let mut lanes: super::PublishRequestLanes = Default::default();
+        lanes.push_back(publish_request("telemetry/a", super::PublishPriority::Low));
+        lanes.push_back(publish_request("telemetry/b", super::PublishPriority::Low));
+        lanes.push_back(publish_request("status", super::PublishPriority::Normal));
+        lanes.push_back(publish_request("commands", super::PublishPriority::High));
+        lanes.push_back(publish_request("telemetry/b", super::PublishPriority::High));
+        lanes.push_back(publish_request("telemetry/a", super::PublishPriority::Low));
+
+        let mut sent = vec![];
+        while let Some(publish_request) = lanes.pop_front() {
+            sent.push((
+                publish_request.publication.topic_name.to_string(),
+                std::str::from_utf8(&publish_request.publication.payload).unwrap().to_owned(),
+            ));
+        }
+
+        assert_eq!(sent, [
+            ("commands".to_owned(), "High".to_owned()),
+            ("telemetry/b".to_owned(), "Low".to_owned()),
+            ("telemetry/b".to_owned(), "High".to_owned()),
+            ("status".to_owned(), "Normal".to_owned()),
+            ("telemetry/a".to_owned(), "Low".to_owned()),
+            ("telemetry/a".to_owned(), "Low".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn paces_burst_after_new_connection() {
+        fn poll(publish: &mut super::State, packet_identifiers: &mut crate::client::PacketIdentifiers, clock: &crate::client::clock::tests::ManualClock, now: u64) -> usize {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            clock.advance_to(now);
+            let mut packets_waiting_to_be_sent = Default::default();
+            publish.poll(&mut cx, &mut None, packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            packets_waiting_to_be_sent.len()
+        }
+
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        publish.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+        publish.set_publish_pacing(super::PublishPacing::SpreadOverKeepAlive);
+
+        let publish_request = || super::PublishRequest {
+            publication: crate::proto::Publication {
+                topic_name: "telemetry".parse().unwrap(),
+                qos: crate::proto::QoS::AtMostOnce,
+                retain: false,
+                payload: bytes::Bytes::new(),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            },
+            priority: super::PublishPriority::Normal,
+            ack_sender: super::AckSender::Future(futures_channel::oneshot::channel().0),
+            queued_at: std::time::Duration::ZERO,
+            deadline: None,
+            backlog: false,
+        };
+
+        for _ in 0..4 {
+            publish.publish_requests_waiting_to_be_sent.push_back(publish_request());
+        }
+        assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+
+        // One publication is sent right away, and the rest every 2 seconds
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, &clock, 0), 1);
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, &clock, 1), 0);
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, &clock, 2), 1);
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, &clock, 5), 1);
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, &clock, 6), 1);
+
+        // Publications requested after the burst are not paced
+        publish.publish_requests_waiting_to_be_sent.push_back(publish_request());
+        publish.publish_requests_waiting_to_be_sent.push_back(publish_request());
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, &clock, 6), 2);
+    }
+
+    #[test]
+    fn replays_queued_publications_first() {
+        fn publish_request(publish: &mut super::State, topic_name: &str, priority: super::PublishPriority) {
+            let publication = crate::proto::Publication {
+                topic_name: topic_name.parse().unwrap(),
+                qos: crate::proto::QoS::AtMostOnce,
+                retain: false,
+                payload: bytes::Bytes::new(),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            };
+            drop(publish.publish(publication, priority));
+        }
+
+        fn sent(replay_ordering: super::ReplayOrdering) -> Vec<String> {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            let mut packet_identifiers = Default::default();
+            let mut publish = super::State::default();
+            publish.set_replay_ordering(replay_ordering);
+
+            // Queued while disconnected
+            publish_request(&mut publish, "telemetry", super::PublishPriority::Low);
+            publish_request(&mut publish, "commands/1", super::PublishPriority::Normal);
+            assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+
+            // The connection is backed up when it is first polled, so nothing is sent yet
+            let mut packets_waiting_to_be_sent = vec![crate::proto::Packet::PingReq(crate::proto::PingReq)].into();
+            publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            assert_eq!(packets_waiting_to_be_sent.len(), 1);
+
+            publish_request(&mut publish, "commands/2", super::PublishPriority::High);
+            publish_request(&mut publish, "status", super::PublishPriority::Normal);
+
+            let mut packets_waiting_to_be_sent = Default::default();
+            publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            packets_waiting_to_be_sent.into_iter()
+                .map(|packet| match packet {
+                    crate::proto::Packet::Publish(publish) => publish.topic_name.to_string(),
+                    packet => panic!("expected PUBLISH but got {:?}", packet),
+                })
+                .collect()
+        }
+
+        assert_eq!(sent(super::ReplayOrdering::QueuedFirst), ["commands/1", "telemetry", "commands/2", "status"]);
+        assert_eq!(sent(super::ReplayOrdering::ByPriority), ["commands/2", "commands/1", "status", "telemetry"]);
+    }
+
+    #[test]
+    fn receive_maximum() {
+        fn poll(publish: &mut super::State, packet_identifiers: &mut crate::client::PacketIdentifiers, packet: Option<crate::proto::Packet>) -> Vec<String> {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            let mut packet = packet;
+            let mut packets_waiting_to_be_sent = Default::default();
+            publish.poll(&mut cx, &mut packet, packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            packets_waiting_to_be_sent.into_iter()
+                .filter_map(|packet| match packet {
+                    crate::proto::Packet::Publish(publish) => Some(publish.topic_name.to_string()),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), 2, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+
+        for &(topic_name, qos) in &[
+            ("a", crate::proto::QoS::AtLeastOnce),
+            ("b", crate::proto::QoS::ExactlyOnce),
+            ("c", crate::proto::QoS::AtLeastOnce),
+            ("d", crate::proto::QoS::AtMostOnce),
+        ] {
+            let publication = crate::proto::Publication {
+                topic_name: topic_name.parse().unwrap(),
+                qos,
+                retain: false,
+                payload: bytes::Bytes::new(),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            };
+            drop(publish.publish(publication, super::PublishPriority::Normal));
+        }
+
+        // Only two publications can wait for acks at once, and the rest wait in order behind them
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, None), ["a", "b"]);
+        assert!(poll(&mut publish, &mut packet_identifiers, None).is_empty());
+
+        // Receiving PUBREC does not complete the exactly-once publication, so it still counts
+        let pub_rec = crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier: crate::proto::PacketIdentifier::new(2).unwrap() });
+        assert!(poll(&mut publish, &mut packet_identifiers, Some(pub_rec)).is_empty());
+
+        let pub_ack = crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap() });
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, Some(pub_ack)), ["c", "d"]);
+    }
+
+    #[test]
+    fn ordered_per_topic() {
+        fn poll(publish: &mut super::State, packet_identifiers: &mut crate::client::PacketIdentifiers, pub_ack: Option<u16>) -> Vec<String> {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            let mut packet = pub_ack.map(|packet_identifier| crate::proto::Packet::PubAck(crate::proto::PubAck {
+                packet_identifier: crate::proto::PacketIdentifier::new(packet_identifier).unwrap(),
+            }));
+            let mut packets_waiting_to_be_sent = Default::default();
+            publish.poll(&mut cx, &mut packet, packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            packets_waiting_to_be_sent.into_iter()
+                .filter_map(|packet| match packet {
+                    crate::proto::Packet::Publish(publish) => Some(format!("{}/{}", publish.topic_name, std::str::from_utf8(&publish.payload).unwrap())),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        publish.set_delivery_mode(super::DeliveryMode::OrderedPerTopic);
+        assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+
+        for &(topic_name, payload, qos) in &[
+            ("slow", "1", crate::proto::QoS::AtLeastOnce),
+            ("fast", "1", crate::proto::QoS::AtLeastOnce),
+            ("slow", "2", crate::proto::QoS::AtLeastOnce),
+            ("fast", "2", crate::proto::QoS::AtLeastOnce),
+            ("slow", "3", crate::proto::QoS::AtMostOnce),
+            ("fast", "3", crate::proto::QoS::AtLeastOnce),
+            ("other", "1", crate::proto::QoS::AtMostOnce),
+        ] {
+            let publication = crate::proto::Publication {
+                topic_name: topic_name.parse().unwrap(),
+                qos,
+                retain: false,
+                payload: bytes::Bytes::from_static(payload.as_bytes()),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            };
+            drop(publish.publish(publication, super::PublishPriority::Normal));
+        }
+
+        // Only the first publication to each topic is sent until it is acked
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, None), ["slow/1", "fast/1", "other/1"]);
+        assert!(poll(&mut publish, &mut packet_identifiers, None).is_empty());
+
+        // "slow" not being acked does not hold up "fast"
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, Some(2)), ["fast/2"]);
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, Some(3)), ["fast/3"]);
+        assert!(poll(&mut publish, &mut packet_identifiers, Some(4)).is_empty());
+
+        // Once "slow" is acked, the publications that waited for it are sent in order, one at a time,
+        // and the QoS 0 one waits for the QoS 1 one before it
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, Some(1)), ["slow/2"]);
+        assert!(poll(&mut publish, &mut packet_identifiers, None).is_empty());
+        assert_eq!(poll(&mut publish, &mut packet_identifiers, Some(5)), ["slow/3"]);
+    }
+
+    #[test]
+    fn close() {
+        use std::future::Future;
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let publication = |topic_name: &str| crate::proto::Publication {
+            topic_name: topic_name.parse().unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            retain: false,
+            payload: bytes::Bytes::new(),
+            user_properties: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        };
+
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), 1, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+
+        // "a" is sent and waits for its PUBACK, "b" waits to be sent behind it, and "c" is still in the channel from the publish handle
+        let mut in_flight = Box::pin(publish.publish(publication("a"), super::PublishPriority::Normal));
+        let mut waiting = Box::pin(publish.publish(publication("b"), super::PublishPriority::Normal));
+        let mut packets_waiting_to_be_sent = Default::default();
+        publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+        assert_eq!(packets_waiting_to_be_sent.len(), 1);
+
+        let mut publish_handle = publish.publish_handle(Default::default());
+        let mut queued = Box::pin(async move { publish_handle.publish(publication("c")).await });
+        assert!(queued.as_mut().poll(&mut cx).is_pending());
+
+        assert!(in_flight.as_mut().poll(&mut cx).is_pending());
+        assert!(waiting.as_mut().poll(&mut cx).is_pending());
+
+        publish.close(&mut cx, &mut packet_identifiers);
+
+        assert!(matches!(in_flight.as_mut().poll(&mut cx), std::task::Poll::Ready(Err(super::PublishError::ClientClosed))));
+        assert!(matches!(waiting.as_mut().poll(&mut cx), std::task::Poll::Ready(Err(super::PublishError::ClientClosed))));
+        assert!(matches!(queued.as_mut().poll(&mut cx), std::task::Poll::Ready(Err(super::PublishError::ClientClosed))));
+        assert!(publish.waiting_to_be_acked.is_empty());
+        assert!(publish.publish_requests_waiting_to_be_sent.is_empty());
+    }
+
+    #[test]
+    fn retransmit_policies() {
+        use std::future::Future;
+
+        fn sent(packets: impl IntoIterator<Item = crate::proto::Packet>) -> Vec<(String, Option<u16>, bool)> {
+            packets.into_iter()
+                .filter_map(|packet| match packet {
+                    crate::proto::Packet::Publish(publish) => Some(match publish.packet_identifier_dup_qos {
+                        crate::proto::PacketIdentifierDupQoS::AtMostOnce => (publish.topic_name.to_string(), None, false),
+                        crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, dup) |
+                        crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, dup) =>
+                            (publish.topic_name.to_string(), Some(packet_identifier.get()), dup),
+                    }),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        for &retransmit_policy in &[super::RetransmitPolicy::ResendAsDuplicate, super::RetransmitPolicy::Requeue, super::RetransmitPolicy::ReturnToApplication] {
+            let mut packet_identifiers: crate::client::PacketIdentifiers = Default::default();
+            let mut publish = super::State::default();
+            publish.set_retransmit_policy(retransmit_policy);
+            assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+
+            // The packet identifiers wrap around while the publications are in flight
+            packet_identifiers.previous = crate::proto::PacketIdentifier::new(65533).unwrap();
+
+            let mut acks: Vec<_> = ["a", "b", "c", "d"].iter()
+                .map(|topic_name| {
+                    let publication = crate::proto::Publication {
+                        topic_name: topic_name.parse().unwrap(),
+                        qos: crate::proto::QoS::AtLeastOnce,
+                        retain: false,
+                        payload: bytes::Bytes::new(),
+                        user_properties: vec![],
+                        response_topic: None,
+                        correlation_data: None,
+                        message_expiry: None,
+                        content_type: None,
+                    };
+                    Box::pin(publish.publish(publication, super::PublishPriority::Normal))
+                })
+                .collect();
+
+            let mut packets_waiting_to_be_sent = Default::default();
+            publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            assert_eq!(sent(packets_waiting_to_be_sent), [
+                ("a".to_owned(), Some(65534), false),
+                ("b".to_owned(), Some(65535), false),
+                ("c".to_owned(), Some(1), false),
+                ("d".to_owned(), Some(2), false),
+            ]);
+
+            let resent = sent(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers));
+            let mut packets_waiting_to_be_sent = Default::default();
+            publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            let sent_again = sent(packets_waiting_to_be_sent);
+
+            match retransmit_policy {
+                super::RetransmitPolicy::ResendAsDuplicate => {
+                    assert_eq!(resent, [
+                        ("a".to_owned(), Some(65534), true),
+                        ("b".to_owned(), Some(65535), true),
+                        ("c".to_owned(), Some(1), true),
+                        ("d".to_owned(), Some(2), true),
+                    ]);
+                    assert!(sent_again.is_empty());
+                    assert!(publish.take_publications_not_acked().is_none());
+                    assert!(acks.iter_mut().all(|ack| ack.as_mut().poll(&mut cx).is_pending()));
+                }
+
+                super::RetransmitPolicy::Requeue => {
+                    assert!(resent.is_empty());
+                    assert_eq!(sent_again, [
+                        ("a".to_owned(), Some(3), false),
+                        ("b".to_owned(), Some(4), false),
+                        ("c".to_owned(), Some(5), false),
+                        ("d".to_owned(), Some(6), false),
+                    ]);
+                    assert!(publish.take_publications_not_acked().is_none());
+                    assert!(acks.iter_mut().all(|ack| ack.as_mut().poll(&mut cx).is_pending()));
+                }
+
+                super::RetransmitPolicy::ReturnToApplication => {
+                    assert!(resent.is_empty());
+                    assert!(sent_again.is_empty());
+                    let publications_not_acked: Vec<_> =
+                        publish.take_publications_not_acked().unwrap().into_iter()
+                        .map(|publication| publication.topic_name.to_string())
+                        .collect();
+                    assert_eq!(publications_not_acked, ["a", "b", "c", "d"]);
+                    assert!(acks.iter_mut().all(|ack| matches!(ack.as_mut().poll(&mut cx), std::task::Poll::Ready(Err(super::PublishError::NotAcked)))));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn maximum_packet_size() {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, Some(64), crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+
+        let mut ack_receivers = vec![];
+        for &(topic_name, payload_len) in &[("a", 16), ("b", 64), ("c", 16)] {
+            let publication = crate::proto::Publication {
+                topic_name: topic_name.parse().unwrap(),
+                qos: crate::proto::QoS::AtLeastOnce,
+                retain: false,
+                payload: vec![0; payload_len].into(),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            };
+            let (ack_sender, ack_receiver) = futures_channel::oneshot::channel();
+            publish.publish_requests_waiting_to_be_sent.push_back(super::PublishRequest {
+                publication,
+                priority: super::PublishPriority::Normal,
+                ack_sender: super::AckSender::Future(ack_sender),
+                queued_at: std::time::Duration::ZERO,
+                deadline: None,
+                backlog: false,
+            });
+            ack_receivers.push(ack_receiver);
+        }
+
+        let mut packets_waiting_to_be_sent = Default::default();
+        publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+
+        // The publication that does not fit fails instead of being sent, and the others are sent as usual
+        let sent: Vec<_> = packets_waiting_to_be_sent.into_iter().filter_map(|packet| match packet {
+            crate::proto::Packet::Publish(publish) => Some(publish.topic_name.to_string()),
+            _ => None,
+        }).collect();
+        assert_eq!(sent, ["a", "c"]);
+
+        match ack_receivers[1].try_recv() {
+            Ok(Some(Err(super::PublishError::PacketTooLarge(publication, packet_size, 64)))) => {
+                assert_eq!(publication.topic_name.as_ref(), "b");
+                assert!(packet_size > 64);
+            },
+            result => panic!("expected publication to be too large but got {:?}", result),
+        }
+        assert!(matches!(ack_receivers[0].try_recv(), Ok(None)));
+    }
+
+    #[test]
+    fn maximum_qos() {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::AtLeastOnce, &mut packet_identifiers).count(), 0);
+
+        for &(topic_name, qos) in &[("a", crate::proto::QoS::AtMostOnce), ("b", crate::proto::QoS::ExactlyOnce)] {
+            let publication = crate::proto::Publication {
+                topic_name: topic_name.parse().unwrap(),
+                qos,
+                retain: false,
+                payload: bytes::Bytes::from_static(b"payload"),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            };
+            publish.publish_requests_waiting_to_be_sent.push_back(super::PublishRequest {
+                publication,
+                priority: super::PublishPriority::Normal,
+                ack_sender: super::AckSender::Future(futures_channel::oneshot::channel().0),
+                queued_at: std::time::Duration::ZERO,
+                deadline: None,
+                backlog: false,
+            });
+        }
+
+        let mut packets_waiting_to_be_sent = Default::default();
+        publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+
+        // The publication with a higher QoS than the server accepts is sent with the maximum QoS instead
+        let sent: Vec<_> = packets_waiting_to_be_sent.into_iter().map(|packet| match packet {
+            crate::proto::Packet::Publish(publish) => publish.packet_identifier_dup_qos,
+            packet => panic!("expected PUBLISH but got {:?}", packet),
+        }).collect();
+        assert_eq!(sent, [
+            crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+            crate::proto::PacketIdentifierDupQoS::AtLeastOnce(crate::proto::PacketIdentifier::new(1).unwrap(), false),
+        ]);
+    }
+
+    #[test]
+    fn message_expiry() {
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        publish.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+
+        let mut ack_receivers = vec![];
+        for &(topic_name, message_expiry) in &[("a", Some(5)), ("b", Some(20)), ("c", None)] {
+            let (ack_sender, ack_receiver) = futures_channel::oneshot::channel();
+            ack_receivers.push(ack_receiver);
+            publish.publish_requests_waiting_to_be_sent.push_back(super::PublishRequest {
+                publication: crate::proto::Publication {
+                    topic_name: topic_name.parse().unwrap(),
+                    qos: crate::proto::QoS::AtMostOnce,
+                    retain: false,
+                    payload: bytes::Bytes::new(),
+                    user_properties: vec![],
+                    response_topic: None,
+                    correlation_data: None,
+                    message_expiry: message_expiry.map(std::time::Duration::from_secs),
+                    content_type: None,
+                },
+                priority: super::PublishPriority::Normal,
+                ack_sender: super::AckSender::Future(ack_sender),
+                queued_at: std::time::Duration::ZERO,
+                deadline: None,
+                backlog: false,
+            });
+        }
+
+        // The client reconnects after the first publication expired, and the server is sent the time that remains of the second
+        clock.advance_to(10);
+        assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut packets_waiting_to_be_sent = Default::default();
+        publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+
+        let sent: Vec<_> =
+            packets_waiting_to_be_sent.into_iter()
+            .map(|packet| match packet {
+                crate::proto::Packet::Publish(publish) => (publish.topic_name.to_string(), publish.message_expiry),
+                packet => panic!("expected PUBLISH but got {:?}", packet),
+            })
+            .collect();
+        assert_eq!(sent, [("b".to_owned(), Some(std::time::Duration::from_secs(10))), ("c".to_owned(), None)]);
+
+        assert!(matches!(ack_receivers[0].try_recv(), Ok(Some(Err(super::PublishError::Expired)))));
+        assert!(matches!(ack_receivers[1].try_recv(), Ok(Some(Ok(())))));
+    }
+
+    #[test]
+    fn max_message_age() {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        publish.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+        publish.set_max_message_age(Some(std::time::Duration::from_secs(10)));
+
+        // The publications are requested at different times while the client is not connected
+        let mut results = vec![];
+        for &(topic_name, now) in &[("old", 0), ("exactly max age", 5), ("new", 8)] {
+            clock.advance_to(now);
+            let publication = crate::proto::Publication {
+                topic_name: topic_name.parse().unwrap(),
+                qos: crate::proto::QoS::AtMostOnce,
+                retain: false,
+                payload: bytes::Bytes::new(),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            };
+            results.push(Box::pin(publish.publish(publication, super::PublishPriority::Normal)));
+        }
+
+        // By the time the client connects, the first one was queued for longer than the maximum age
+        clock.advance_to(15);
+        publish.poll_deadlines(&mut cx);
+        assert_eq!(publish.new_connection(true, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+        let mut packets_waiting_to_be_sent = Default::default();
+        publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+
+        let sent: Vec<_> =
+            packets_waiting_to_be_sent.into_iter()
+            .map(|packet| match packet {
+                crate::proto::Packet::Publish(publish) => publish.topic_name.to_string(),
+                packet => panic!("expected PUBLISH but got {:?}", packet),
+            })
+            .collect();
+        assert_eq!(sent, ["exactly max age", "new"]);
+
+        let results: Vec<_> = results.iter_mut().map(|result| std::future::Future::poll(result.as_mut(), &mut cx)).collect();
+        assert!(matches!(
+            &results[..],
+            [std::task::Poll::Ready(Err(super::PublishError::Expired)), std::task::Poll::Ready(Ok(())), std::task::Poll::Ready(Ok(()))],
+        ), "{:?}", results);
+    }
+
+    #[test]
+    fn deadlines() {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        publish.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+
+        let mut ack_receivers = vec![];
+        for &(topic_name, deadline) in &[("a", Some(5)), ("b", Some(20)), ("c", None)] {
+            let (ack_sender, ack_receiver) = futures_channel::oneshot::channel();
+            ack_receivers.push(ack_receiver);
+            publish.publish_requests_waiting_to_be_sent.push_back(super::PublishRequest {
+                publication: crate::proto::Publication {
+                    topic_name: topic_name.parse().unwrap(),
+                    qos: crate::proto::QoS::AtMostOnce,
+                    retain: false,
+                    payload: bytes::Bytes::new(),
+                    user_properties: vec![],
+                    response_topic: None,
+                    correlation_data: None,
+                    message_expiry: None,
+                    content_type: None,
+                },
+                priority: super::PublishPriority::Normal,
+                ack_sender: super::AckSender::Future(ack_sender),
+                queued_at: std::time::Duration::ZERO,
+                deadline: deadline.map(std::time::Duration::from_secs),
+                backlog: false,
+            });
+        }
+
+        // The first deadline passes while the client is not connected
+        publish.poll_deadlines(&mut cx);
+        assert!(matches!(ack_receivers[0].try_recv(), Ok(None)));
+        clock.advance_to(10);
+        publish.poll_deadlines(&mut cx);
+        assert!(matches!(ack_receivers[0].try_recv(), Ok(Some(Err(super::PublishError::DeadlineExceeded)))));
+        assert!(matches!(ack_receivers[1].try_recv(), Ok(None)));
+
+        // ... and the second one by the time the client connects
+        clock.advance_to(20);
+        assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+        let mut packets_waiting_to_be_sent = Default::default();
+        publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+
+        let sent: Vec<_> =
+            packets_waiting_to_be_sent.into_iter()
+            .map(|packet| match packet {
+                crate::proto::Packet::Publish(publish) => publish.topic_name.to_string(),
+                packet => panic!("expected PUBLISH but got {:?}", packet),
+            })
+            .collect();
+        assert_eq!(sent, ["c"]);
+
+        assert!(matches!(ack_receivers[1].try_recv(), Ok(Some(Err(super::PublishError::DeadlineExceeded)))));
+        assert!(matches!(ack_receivers[2].try_recv(), Ok(Some(Ok(())))));
+    }
+
+    #[test]
+    fn suppress_seen_duplicates() {
+        fn receive(state: &mut super::State, topic_name: &str, dup: bool) -> (bool, Vec<crate::proto::Packet>) {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            let mut packet = Some(crate::proto::Packet::Publish(crate::proto::Publish {
+                packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtLeastOnce(
+                    crate::proto::PacketIdentifier::new(1).unwrap(),
+                    dup,
+                ),
+                retain: false,
+                topic_name: topic_name.parse().unwrap(),
+                payload: bytes::Bytes::from_static(b"payload"),
+                user_properties: vec![],
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            }));
+            let mut packets_waiting_to_be_sent = Default::default();
+            let publication = state.poll(&mut cx, &mut packet, &mut Default::default(), &mut packets_waiting_to_be_sent).unwrap();
+            (publication.is_some(), packets_waiting_to_be_sent.into_iter().collect())
+        }
+
+        let pub_ack = crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap() });
+
+        let mut state: super::State = Default::default();
+        state.set_duplicate_policy(super::DuplicatePolicy::SuppressSeen);
+
+        assert_eq!(receive(&mut state, "a", false), (true, vec![pub_ack.clone()]));
+        assert_eq!(receive(&mut state, "a", true), (false, vec![pub_ack.clone()]));
+
+        // Different publication with the same packet identifier
+        assert_eq!(receive(&mut state, "b", true), (true, vec![pub_ack.clone()]));
+
+        // New publication that happens to reuse the packet identifier
+        assert_eq!(receive(&mut state, "b", false), (true, vec![pub_ack.clone()]));
+
+        state.set_duplicate_policy(super::DuplicatePolicy::Deliver);
+        assert_eq!(receive(&mut state, "b", true), (true, vec![pub_ack]));
+    }
+
+    #[test]
+    fn manual_acks() {
+        fn publish(packet_identifier: u16, qos: crate::proto::QoS, dup: bool) -> crate::proto::Packet {
+            let packet_identifier = crate::proto::PacketIdentifier::new(packet_identifier).unwrap();
+            crate::proto::Packet::Publish(crate::proto::Publish {
+                packet_identifier_dup_qos: match qos {
+                    crate::proto::QoS::AtLeastOnce => crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, dup),
+                    _ => crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, dup),
+                },
+                retain: false,
+                topic_name: "a".parse().unwrap(),
+                payload: bytes::Bytes::from_static(b"payload"),
+                user_properties: vec![],
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            })
+        }
+
+        fn poll(state: &mut super::State, packet: Option<crate::proto::Packet>) -> (Option<crate::ReceivedPublication>, Vec<crate::proto::Packet>) {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            let mut packet = packet;
+            let mut packets_waiting_to_be_sent = Default::default();
+            let publication = state.poll(&mut cx, &mut packet, &mut Default::default(), &mut packets_waiting_to_be_sent).unwrap();
+            (publication, packets_waiting_to_be_sent.into_iter().collect())
+        }
+
+        fn identifier(packet_identifier: u16) -> crate::proto::PacketIdentifier {
+            crate::proto::PacketIdentifier::new(packet_identifier).unwrap()
+        }
+
+        let mut state: super::State = Default::default();
+        state.set_manual_acks(true);
+        state.set_duplicate_policy(super::DuplicatePolicy::SuppressSeen);
+
+        // Nothing is acked until the application acks the publication
+        let (publication, sent) = poll(&mut state, Some(publish(1, crate::proto::QoS::AtLeastOnce, false)));
+        let mut publication = publication.unwrap();
+        assert!(sent.is_empty(), "{:?}", sent);
+        assert_eq!(poll(&mut state, None).1, []);
+        publication.ack();
+        assert_eq!(poll(&mut state, None).1, [crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier: identifier(1) })]);
+
+        // QoS 2 publications are returned as soon as they are received, and the PUBREC is only sent once the application acks them
+        let (publication, sent) = poll(&mut state, Some(publish(2, crate::proto::QoS::ExactlyOnce, false)));
+        let mut publication = publication.unwrap();
+        assert!(sent.is_empty(), "{:?}", sent);
+        let (duplicate, sent) = poll(&mut state, Some(publish(2, crate::proto::QoS::ExactlyOnce, true)));
+        assert!(duplicate.is_none());
+        assert!(sent.is_empty(), "{:?}", sent);
+        publication.ack();
+        assert_eq!(poll(&mut state, None).1, [crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier: identifier(2) })]);
+        let (released, sent) = poll(&mut state, Some(crate::proto::Packet::PubRel(crate::proto::PubRel { packet_identifier: identifier(2) })));
+        assert!(released.is_none());
+        assert_eq!(sent, [crate::proto::Packet::PubComp(crate::proto::PubComp { packet_identifier: identifier(2) })]);
+
+        // A publication that is dropped without being acked is returned again when the server sends it again
+        for (packet_identifier, qos, ack) in std::array::IntoIter::new([
+            (3, crate::proto::QoS::AtLeastOnce, crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier: identifier(3) })),
+            (4, crate::proto::QoS::ExactlyOnce, crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier: identifier(4) })),
+        ]) {
+            let (publication, _) = poll(&mut state, Some(publish(packet_identifier, qos, false)));
+            drop(publication.unwrap());
+            let (publication, sent) = poll(&mut state, Some(publish(packet_identifier, qos, true)));
+            let mut publication = publication.unwrap();
+            assert!(publication.dup);
+            assert!(sent.is_empty(), "{:?}", sent);
+            publication.ack();
+            assert_eq!(poll(&mut state, None).1, [ack]);
+        }
+
+        // Acks for publications received before the session was reset are ignored
+        let (publication, _) = poll(&mut state, Some(publish(5, crate::proto::QoS::AtLeastOnce, false)));
+        let mut at_least_once = publication.unwrap();
+        let (publication, _) = poll(&mut state, Some(publish(6, crate::proto::QoS::ExactlyOnce, false)));
+        let mut exactly_once = publication.unwrap();
+        assert_eq!(state.new_connection(true, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut Default::default()).count(), 0);
+        at_least_once.ack();
+        exactly_once.ack();
+        assert_eq!(poll(&mut state, None).1, []);
+
+        // ... and the packet identifiers of the old session can be used for new publications
+        let (publication, sent) = poll(&mut state, Some(publish(6, crate::proto::QoS::ExactlyOnce, false)));
+        let mut publication = publication.unwrap();
+        assert!(sent.is_empty(), "{:?}", sent);
+        publication.ack();
+        assert_eq!(poll(&mut state, None).1, [crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier: identifier(6) })]);
+    }
+
+    #[test]
+    fn publish_before_first_connection() {
+        // Returns the results of the publications requested before the first connection, and the topics that were sent once connected
+        fn publish(publish_before_first_connection: super::PublishBeforeFirstConnection) -> (Vec<(&'static str, bool)>, Vec<String>) {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            let publication = |topic_name: &str| crate::proto::Publication {
+                topic_name: topic_name.parse().unwrap(),
+                qos: crate::proto::QoS::AtMostOnce,
+                retain: false,
+                payload: bytes::Bytes::from_static(b"payload"),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            };
+
+            let mut packet_identifiers = Default::default();
+            let mut publish = super::State::default();
+            publish.set_publish_before_first_connection(publish_before_first_connection);
+
+            let results = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+            let publish_handle = publish.publish_handle(Default::default());
+            publish_handle.publish_with_callback(publication("handle"), super::PublishPriority::Normal, {
+                let results = results.clone();
+                move |result| results.lock().unwrap().push(("handle", !matches!(result, Err(super::PublishError::NotConnected))))
+            }).unwrap();
+            let client = publish.publish(publication("client"), super::PublishPriority::Normal);
+            futures_util::pin_mut!(client);
+            if let std::task::Poll::Ready(result) = std::future::Future::poll(client, &mut cx) {
+                results.lock().unwrap().push(("client", !matches!(result, Err(super::PublishError::NotConnected))));
+            }
+
+            // Without a connection, the client only polls for the publications that fail before the first connection
+            publish.poll_before_first_connection(&mut cx);
+            publish.poll_deadlines(&mut cx);
+
+            assert_eq!(publish.new_connection(true, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+            let mut packets_waiting_to_be_sent = Default::default();
+            publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            let sent = packets_waiting_to_be_sent.into_iter()
+                .map(|packet| match packet {
+                    crate::proto::Packet::Publish(publish) => publish.topic_name.to_string(),
+                    packet => panic!("expected PUBLISH but got {:?}", packet),
+                })
+                .collect();
+
+            let results = results.lock().unwrap().clone();
+            (results, sent)
+        }
+
+        // Publications wait for the first connection by default
+        assert_eq!(publish(super::PublishBeforeFirstConnection::Queue), (vec![("handle", true)], vec!["client".to_owned(), "handle".to_owned()]));
+
+        // ... or fail right away without being sent later
+        assert_eq!(publish(super::PublishBeforeFirstConnection::Fail), (vec![("client", false), ("handle", false)], vec![]));
+    }
+
+    #[test]
+    fn publish_with_callback() {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let mut packet_identifiers = Default::default();
+        let mut publish = super::State::default();
+        assert_eq!(publish.new_connection(false, std::time::Duration::from_secs(8), u16::MAX, None, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).count(), 0);
+
+        let results = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let publish_handle = publish.publish_handle(Default::default());
+        for &topic_name in &["a", "b"] {
+            let publication = crate::proto::Publication {
+                topic_name: topic_name.parse().unwrap(),
+                qos: crate::proto::QoS::AtLeastOnce,
+                retain: false,
+                payload: bytes::Bytes::from_static(b"payload"),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            };
+            let results = results.clone();
+            publish_handle.publish_with_callback(publication, super::PublishPriority::Normal, move |result| {
+                results.lock().unwrap().push((topic_name, result.is_ok()));
+            }).unwrap();
+        }
+
+        let mut packets_waiting_to_be_sent = Default::default();
+        publish.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+        assert_eq!(packets_waiting_to_be_sent.len(), 2);
+        assert!(results.lock().unwrap().is_empty());
+
+        // The callback is called when the publication is acked
+        let mut pub_ack = Some(crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap() }));
+        publish.poll(&mut cx, &mut pub_ack, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+        assert_eq!(*results.lock().unwrap(), [("a", true)]);
+
+        // The callbacks of publications that did not complete are still called when the client is dropped
+        drop(publish);
+        assert_eq!(*results.lock().unwrap(), [("a", true), ("b", false)]);
     }
 }
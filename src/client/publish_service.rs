@@ -0,0 +1,317 @@
+use std::future::Future;
+
+/// The future returned by a [`PublishService`]
+pub type PublishFuture = std::pin::Pin<Box<dyn Future<Output = Result<(), super::PublishError>> + Send>>;
+
+/// A step of the path that publications take to the [`Client`](crate::Client).
+///
+/// [`super::PublishHandle`] is the innermost service. Other services wrap it to add behavior such as retries or deadlines,
+/// and are usually created by stacking [`PublishLayer`]s with a [`PublishServiceBuilder`].
+pub trait PublishService {
+    /// Publishes the given message. The returned future resolves when the innermost service has published it.
+    fn publish(&mut self, publication: crate::proto::Publication) -> PublishFuture;
+}
+
+impl PublishService for super::PublishHandle {
+    fn publish(&mut self, publication: crate::proto::Publication) -> PublishFuture {
+        let mut publish_handle = self.clone();
+        Box::pin(async move { publish_handle.publish(publication).await })
+    }
+}
+
+/// Wraps a [`PublishService`] in another one
+pub trait PublishLayer<S> {
+    type Service: PublishService;
+
+    fn layer(&self, inner: S) -> Self::Service;
+}
+
+/// Stacks [`PublishLayer`]s around a [`PublishService`].
+///
+/// The first layer that is added is the outermost one, so it sees publications first. For example, adding a [`LogLayer`]
+/// and then a [`MapPublicationLayer`] logs publications before they are changed.
+#[derive(Clone, Debug)]
+pub struct PublishServiceBuilder<L>(L);
+
+impl PublishServiceBuilder<IdentityLayer> {
+    pub fn new() -> Self {
+        PublishServiceBuilder(IdentityLayer)
+    }
+}
+
+impl Default for PublishServiceBuilder<IdentityLayer> {
+    fn default() -> Self {
+        PublishServiceBuilder::new()
+    }
+}
+
+impl<L> PublishServiceBuilder<L> {
+    /// Adds a layer inside the layers that were added before
+    #[must_use]
+    pub fn layer<T>(self, layer: T) -> PublishServiceBuilder<LayerStack<T, L>> {
+        PublishServiceBuilder(LayerStack { inner: layer, outer: self.0 })
+    }
+
+    /// Wraps the given service in the layers
+    pub fn service<S>(&self, service: S) -> L::Service where L: PublishLayer<S> {
+        self.0.layer(service)
+    }
+}
+
+/// A [`PublishLayer`] that does not wrap the service. Used by [`PublishServiceBuilder`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IdentityLayer;
+
+impl<S> PublishLayer<S> for IdentityLayer where S: PublishService {
+    type Service = S;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        inner
+    }
+}
+
+/// Two [`PublishLayer`]s, one inside the other. Used by [`PublishServiceBuilder`].
+#[derive(Clone, Debug)]
+pub struct LayerStack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<S, Inner, Outer> PublishLayer<S> for LayerStack<Inner, Outer>
+where
+    Inner: PublishLayer<S>,
+    Outer: PublishLayer<Inner::Service>,
+{
+    type Service = Outer::Service;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        self.outer.layer(self.inner.layer(inner))
+    }
+}
+
+/// A [`PublishLayer`] that changes publications, such as to add a topic prefix or compress the payload
+#[derive(Clone, Debug)]
+pub struct MapPublicationLayer<F>(F);
+
+impl<F> MapPublicationLayer<F> {
+    pub fn new(f: F) -> Self {
+        MapPublicationLayer(f)
+    }
+}
+
+impl<S, F> PublishLayer<S> for MapPublicationLayer<F>
+where
+    S: PublishService,
+    F: Fn(crate::proto::Publication) -> crate::proto::Publication + Clone,
+{
+    type Service = MapPublicationService<S, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MapPublicationService { inner, f: self.0.clone() }
+    }
+}
+
+/// The [`PublishService`] created by [`MapPublicationLayer`]
+#[derive(Clone, Debug)]
+pub struct MapPublicationService<S, F> {
+    inner: S,
+    f: F,
+}
+
+impl<S, F> PublishService for MapPublicationService<S, F>
+where
+    S: PublishService,
+    F: Fn(crate::proto::Publication) -> crate::proto::Publication,
+{
+    fn publish(&mut self, publication: crate::proto::Publication) -> PublishFuture {
+        self.inner.publish((self.f)(publication))
+    }
+}
+
+/// A [`PublishLayer`] that fails publications with [`super::PublishError::DeadlineElapsed`] if they are not published within the given time.
+///
+/// The publication stays queued on the client even if its deadline elapses, so it may still be published later.
+#[derive(Clone, Copy, Debug)]
+pub struct DeadlineLayer(std::time::Duration);
+
+impl DeadlineLayer {
+    pub fn new(deadline: std::time::Duration) -> Self {
+        DeadlineLayer(deadline)
+    }
+}
+
+impl<S> PublishLayer<S> for DeadlineLayer where S: PublishService {
+    type Service = DeadlineService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DeadlineService { inner, deadline: self.0 }
+    }
+}
+
+/// The [`PublishService`] created by [`DeadlineLayer`]
+#[derive(Clone, Debug)]
+pub struct DeadlineService<S> {
+    inner: S,
+    deadline: std::time::Duration,
+}
+
+impl<S> PublishService for DeadlineService<S> where S: PublishService {
+    fn publish(&mut self, publication: crate::proto::Publication) -> PublishFuture {
+        let published = self.inner.publish(publication);
+        let deadline = self.deadline;
+        Box::pin(async move {
+            match tokio::time::timeout(deadline, published).await {
+                Ok(result) => result,
+                Err(_) => Err(super::PublishError::DeadlineElapsed),
+            }
+        })
+    }
+}
+
+/// A [`PublishLayer`] that publishes again, after the given back-off, publications that failed with
+/// [`super::PublishError::NotAcked`] or [`super::PublishError::NotConnected`].
+///
+/// Other errors are not retried. In particular, a publication whose deadline elapsed may still be published, so retrying it could publish it twice.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryLayer {
+    max_retries: usize,
+    back_off: std::time::Duration,
+}
+
+impl RetryLayer {
+    pub fn new(max_retries: usize, back_off: std::time::Duration) -> Self {
+        RetryLayer { max_retries, back_off }
+    }
+}
+
+impl<S> PublishLayer<S> for RetryLayer where S: PublishService + Clone + Send + 'static {
+    type Service = RetryService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RetryService { inner, max_retries: self.max_retries, back_off: self.back_off }
+    }
+}
+
+/// The [`PublishService`] created by [`RetryLayer`]
+#[derive(Clone, Debug)]
+pub struct RetryService<S> {
+    inner: S,
+    max_retries: usize,
+    back_off: std::time::Duration,
+}
+
+impl<S> PublishService for RetryService<S> where S: PublishService + Clone + Send + 'static {
+    fn publish(&mut self, publication: crate::proto::Publication) -> PublishFuture {
+        let mut inner = self.inner.clone();
+        let max_retries = self.max_retries;
+        let back_off = self.back_off;
+
+        Box::pin(async move {
+            let mut retries = 0;
+
+            loop {
+                match inner.publish(publication.clone()).await {
+                    Err(super::PublishError::NotAcked | super::PublishError::NotConnected) if retries < max_retries => {
+                        retries += 1;
+                        log::debug!("retrying publication to {:?} ({}/{})", publication.topic_name, retries, max_retries);
+                        tokio::time::sleep(back_off).await;
+                    }
+
+                    result => return result,
+                }
+            }
+        })
+    }
+}
+
+/// A [`PublishLayer`] that logs every publication and whether it was published
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogLayer;
+
+impl<S> PublishLayer<S> for LogLayer where S: PublishService {
+    type Service = LogService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LogService { inner }
+    }
+}
+
+/// The [`PublishService`] created by [`LogLayer`]
+#[derive(Clone, Debug)]
+pub struct LogService<S> {
+    inner: S,
+}
+
+impl<S> PublishService for LogService<S> where S: PublishService {
+    fn publish(&mut self, publication: crate::proto::Publication) -> PublishFuture {
+        let topic_name = publication.topic_name.clone();
+        log::debug!("publishing to {:?} with QoS {:?}", topic_name, publication.qos);
+
+        let published = self.inner.publish(publication);
+        Box::pin(async move {
+            let result = published.await;
+            match &result {
+                Ok(()) => log::debug!("published to {:?}", topic_name),
+                Err(err) => log::warn!("could not publish to {:?}: {}", topic_name, err),
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PublishService;
+
+    /// Fails the first `failures` publications with `NotAcked`, and records the topics of all publications
+    #[derive(Clone)]
+    struct Flaky {
+        failures: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        topics: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl super::PublishService for Flaky {
+        fn publish(&mut self, publication: crate::proto::Publication) -> super::PublishFuture {
+            self.topics.lock().unwrap().push(publication.topic_name.to_string());
+
+            let failed = self.failures.fetch_update(std::sync::atomic::Ordering::SeqCst, std::sync::atomic::Ordering::SeqCst, |failures| failures.checked_sub(1)).is_ok();
+            Box::pin(async move { if failed { Err(crate::PublishError::NotAcked) } else { Ok(()) } })
+        }
+    }
+
+    #[test]
+    fn layers() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+
+        let flaky = Flaky { failures: Default::default(), topics: Default::default() };
+
+        let mut publisher =
+            super::PublishServiceBuilder::new()
+            .layer(super::LogLayer)
+            .layer(super::RetryLayer::new(2, std::time::Duration::from_millis(1)))
+            .layer(super::MapPublicationLayer::new(|publication: crate::proto::Publication| crate::proto::Publication {
+                topic_name: format!("prefix/{}", publication.topic_name).parse().unwrap(),
+                ..publication
+            }))
+            .service(flaky.clone());
+
+        let publication = crate::proto::Publication {
+            topic_name: "topic".parse().unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            retain: false,
+            payload: Default::default(),
+            user_properties: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        };
+
+        flaky.failures.store(2, std::sync::atomic::Ordering::SeqCst);
+        runtime.block_on(publisher.publish(publication.clone())).unwrap();
+        assert_eq!(*flaky.topics.lock().unwrap(), ["prefix/topic", "prefix/topic", "prefix/topic"]);
+
+        flaky.failures.store(3, std::sync::atomic::Ordering::SeqCst);
+        assert!(matches!(runtime.block_on(publisher.publish(publication)), Err(crate::PublishError::NotAcked)));
+    }
+}
@@ -0,0 +1,127 @@
+/// Creates a channel for the requests of a handle, such as a [`PublishHandle`](super::PublishHandle), whose sender can wait for
+/// the requests that it queued to be received.
+pub(super) fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    let (send, recv) = futures_channel::mpsc::channel(buffer);
+    (Sender { send, queued: Default::default() }, Receiver(recv))
+}
+
+/// Like a [`futures_channel::mpsc::Sender`], but counts the requests that it queued and that have not been received yet.
+///
+/// Every clone counts its own requests, so that closing one handle does not wait for the requests of the others.
+#[derive(Debug)]
+pub(super) struct Sender<T> {
+    send: futures_channel::mpsc::Sender<Queued<T>>,
+    queued: std::sync::Arc<QueuedRequests>,
+}
+
+impl<T> Sender<T> {
+    pub(super) fn is_closed(&self) -> bool {
+        self.send.is_closed()
+    }
+
+    pub(super) async fn send(&mut self, request: T) -> Result<(), futures_channel::mpsc::SendError> {
+        let request = self.queue(request);
+        futures_util::SinkExt::send(&mut self.send, request).await
+    }
+
+    /// Queues the request without waiting. Every sender can queue one request without waiting, so this only fails,
+    /// returning the request, if the receiver was dropped.
+    pub(super) fn try_send(&self, request: T) -> Result<(), T> {
+        let request = self.queue(request);
+        self.send.clone().try_send(request).map_err(|err| err.into_inner().request)
+    }
+
+    /// Waits until every request queued with this sender has been received, or dropped along with the receiver.
+    pub(super) async fn close(self) {
+        futures_util::future::poll_fn(|cx| self.queued.poll_empty(cx)).await;
+    }
+
+    fn queue(&self, request: T) -> Queued<T> {
+        self.queued.len.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+        Queued { request, _token: Token(self.queued.clone()) }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            send: self.send.clone(),
+            queued: Default::default(),
+        }
+    }
+}
+
+/// Like a [`futures_channel::mpsc::Receiver`], for the requests of a [`Sender`]
+#[derive(Debug)]
+pub(super) struct Receiver<T>(futures_channel::mpsc::Receiver<Queued<T>>);
+
+impl<T> futures_core::Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        std::pin::Pin::new(&mut self.0).poll_next(cx).map(|request| request.map(|Queued { request, .. }| request))
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueuedRequests {
+    len: std::sync::atomic::AtomicUsize,
+    waker: futures_util::task::AtomicWaker,
+}
+
+impl QueuedRequests {
+    fn poll_empty(&self, cx: &mut std::task::Context<'_>) -> std::task::Poll<()> {
+        self.waker.register(cx.waker());
+        if self.len.load(std::sync::atomic::Ordering::Acquire) == 0 {
+            std::task::Poll::Ready(())
+        }
+        else {
+            std::task::Poll::Pending
+        }
+    }
+}
+
+/// A request that counts as queued until it is received or dropped
+#[derive(Debug)]
+struct Queued<T> {
+    request: T,
+    _token: Token,
+}
+
+#[derive(Debug)]
+struct Token(std::sync::Arc<QueuedRequests>);
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        if self.0.len.fetch_sub(1, std::sync::atomic::Ordering::AcqRel) == 1 {
+            self.0.waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn close() {
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let (send, mut recv) = super::channel(0);
+        send.try_send(1).unwrap();
+        let other = send.clone();
+        other.try_send(2).unwrap();
+
+        // The request is still queued
+        let mut close = Box::pin(send.close());
+        assert!(futures_util::FutureExt::poll_unpin(&mut close, &mut cx).is_pending());
+
+        // ... until it is received. The requests of the clone are not waited for.
+        assert_eq!(futures_util::StreamExt::poll_next_unpin(&mut recv, &mut cx), std::task::Poll::Ready(Some(1)));
+        assert!(futures_util::FutureExt::poll_unpin(&mut close, &mut cx).is_ready());
+
+        // Requests that are dropped with the receiver are not waited for either
+        let mut close = Box::pin(other.close());
+        drop(recv);
+        assert!(futures_util::FutureExt::poll_unpin(&mut close, &mut cx).is_ready());
+    }
+}
@@ -0,0 +1,374 @@
+/// Publishes requests and waits for their responses, which responders publish to the requester's response topic.
+///
+/// With [`ReplyConvention::Properties`], each request carries the response topic and a correlation data that the responder copies
+/// into its response, as [`response`] does. MQTT 3.1.1 has no such properties, so with [`ReplyConvention::TopicSuffix`] the requester
+/// instead appends a correlation ID to the topic of each request, and a request to `{topic}/{correlation ID}` is answered
+/// on `{response topic}/{correlation ID}`.
+///
+/// The [`Requester::inbound_middleware`] of the requester must be added to the client, so that responses reach it.
+/// Clones share the same requests in flight.
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Debug)]
+pub struct Requester {
+    publish_handle: super::PublishHandle,
+    update_subscription_handle: super::UpdateSubscriptionHandle,
+    inner: std::sync::Arc<Inner>,
+    timeout: std::time::Duration,
+}
+
+#[derive(Debug)]
+struct Inner {
+    response_topic: crate::proto::ByteStr,
+    reply_convention: ReplyConvention,
+
+    /// Whether the requester has subscribed to its response topic
+    subscribed: std::sync::atomic::AtomicBool,
+
+    next_id: std::sync::atomic::AtomicU64,
+
+    /// The requests waiting for a response, by correlation ID
+    pending: std::sync::Mutex<std::collections::HashMap<String, futures_channel::oneshot::Sender<crate::proto::Publication>>>,
+}
+
+/// How requests of a [`Requester`] tell responders where to respond
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReplyConvention {
+    /// The request carries the response topic and correlation data properties. Requires an MQTT 5.0 server.
+    #[cfg(feature = "v5")]
+    #[default]
+    Properties,
+
+    /// The correlation ID is appended to the topic of the request, and the responder responds on `{response topic}/{correlation ID}`
+    #[cfg_attr(not(feature = "v5"), default)]
+    TopicSuffix,
+}
+
+impl Requester {
+    /// Creates a requester that receives responses on the given topic, which should be unique to the client
+    pub fn new(
+        publish_handle: super::PublishHandle,
+        update_subscription_handle: super::UpdateSubscriptionHandle,
+        response_topic: crate::proto::ByteStr,
+    ) -> Self {
+        Requester {
+            publish_handle,
+            update_subscription_handle,
+            inner: std::sync::Arc::new(Inner {
+                response_topic,
+                reply_convention: Default::default(),
+                subscribed: Default::default(),
+                next_id: Default::default(),
+                pending: Default::default(),
+            }),
+            timeout: std::time::Duration::from_secs(30),
+        }
+    }
+
+    /// Sets how requests tell responders where to respond. Defaults to [`ReplyConvention::Properties`] with the `v5` feature,
+    /// and to [`ReplyConvention::TopicSuffix`] otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the requester has been cloned, such as into its inbound middleware.
+    #[must_use]
+    pub fn with_reply_convention(mut self, reply_convention: ReplyConvention) -> Self {
+        std::sync::Arc::get_mut(&mut self.inner).expect("requester must not be cloned yet").reply_convention = reply_convention;
+        self
+    }
+
+    /// Sets how long to wait for the response to a request, from when the request is published. Defaults to 30 seconds.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The [`InboundMiddleware`](super::InboundMiddleware) that passes responses to the requester.
+    /// Add it to the client with [`Client::with_inbound_middleware`](crate::Client::with_inbound_middleware).
+    ///
+    /// It drops all publications on the response topic, including the responses to requests that timed out.
+    pub fn inbound_middleware(&self) -> RequesterMiddleware {
+        RequesterMiddleware(self.inner.clone())
+    }
+
+    /// Publishes the given request and returns its response.
+    #[allow(clippy::doc_markdown)]
+    ///
+    /// The requester subscribes to its response topic with the QoS of the first request. The subscription stays in place after the request,
+    /// and the client makes it again after reconnecting.
+    pub async fn request(&mut self, mut publication: crate::proto::Publication) -> Result<crate::proto::Publication, RequestError> {
+        use futures_util::FutureExt;
+
+        if !self.inner.subscribed.swap(true, std::sync::atomic::Ordering::AcqRel) {
+            let topic_filter = match self.inner.reply_convention {
+                #[cfg(feature = "v5")]
+                ReplyConvention::Properties => self.inner.response_topic.clone(),
+                ReplyConvention::TopicSuffix => {
+                    let topic_filter = format!("{}/+", self.inner.response_topic);
+                    if let Ok(topic_filter) = topic_filter.parse() {
+                        topic_filter
+                    }
+                    else {
+                        self.inner.subscribed.store(false, std::sync::atomic::Ordering::Release);
+                        return Err(RequestError::Subscribe(super::UpdateSubscriptionError::EncodePacket(
+                            self.inner.response_topic.clone(),
+                            crate::proto::EncodeError::StringTooLarge(topic_filter.len()),
+                        )));
+                    }
+                },
+            };
+
+            let subscribe_to = crate::proto::SubscribeTo {
+                topic_filter,
+                qos: publication.qos,
+                user_properties: vec![],
+                subscription_identifier: None,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: Default::default(),
+            };
+            if let Err(err) = self.update_subscription_handle.subscribe(subscribe_to).await {
+                self.inner.subscribed.store(false, std::sync::atomic::Ordering::Release);
+                return Err(RequestError::Subscribe(err));
+            }
+        }
+
+        let id = format!(
+            "{:016x}{:08x}",
+            super::connect::random(),
+            self.inner.next_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed) & 0xffff_ffff,
+        );
+
+        match self.inner.reply_convention {
+            #[cfg(feature = "v5")]
+            ReplyConvention::Properties => {
+                publication.response_topic = Some(self.inner.response_topic.clone());
+                publication.correlation_data = Some(id.clone().into());
+            },
+
+            ReplyConvention::TopicSuffix => {
+                let topic_name = format!("{}/{}", publication.topic_name, id);
+                if let Ok(topic_name) = topic_name.parse() {
+                    publication.topic_name = topic_name;
+                }
+                else {
+                    return Err(RequestError::Publish(super::PublishError::EncodePacket(
+                        Box::new(publication),
+                        crate::proto::EncodeError::StringTooLarge(topic_name.len()),
+                    )));
+                }
+            },
+        }
+
+        let (response_send, response_recv) = futures_channel::oneshot::channel();
+        self.inner.lock().insert(id.clone(), response_send);
+
+        if let Err(err) = self.publish_handle.publish(publication).await {
+            self.inner.lock().remove(&id);
+            return Err(RequestError::Publish(err));
+        }
+
+        let timer = self.publish_handle.clock().sleep(self.timeout);
+        match futures_util::future::select(response_recv, timer).await {
+            futures_util::future::Either::Left((Ok(response), _)) => Ok(response),
+            futures_util::future::Either::Left((Err(futures_channel::oneshot::Canceled), _)) => Err(RequestError::ClientClosed),
+            futures_util::future::Either::Right(((), mut response_recv)) => {
+                self.inner.lock().remove(&id);
+
+                // The response may have arrived just as the timer fired
+                match (&mut response_recv).now_or_never() {
+                    Some(Ok(response)) => Ok(response),
+                    Some(Err(_)) | None => Err(RequestError::TimedOut),
+                }
+            },
+        }
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> std::sync::MutexGuard<'_, std::collections::HashMap<String, futures_channel::oneshot::Sender<crate::proto::Publication>>> {
+        self.pending.lock().expect("pending requests lock is poisoned")
+    }
+
+    /// Returns the correlation ID of the given publication if it is on the response topic, or `None` if it is not a response
+    fn correlation_id(&self, publication: &crate::ReceivedPublication) -> Option<String> {
+        match self.reply_convention {
+            #[cfg(feature = "v5")]
+            ReplyConvention::Properties => {
+                if publication.topic_name != self.response_topic {
+                    return None;
+                }
+
+                let correlation_data = publication.correlation_data.as_deref().unwrap_or_default();
+                Some(String::from_utf8_lossy(correlation_data).into_owned())
+            },
+
+            ReplyConvention::TopicSuffix => {
+                let topic_name: &str = publication.topic_name.as_ref();
+                let id = topic_name.strip_prefix(self.response_topic.as_ref() as &str)?.strip_prefix('/')?;
+                Some(id.to_owned())
+            },
+        }
+    }
+}
+
+/// The [`InboundMiddleware`](super::InboundMiddleware) of a [`Requester`]. Returned by [`Requester::inbound_middleware`].
+#[derive(Debug)]
+pub struct RequesterMiddleware(std::sync::Arc<Inner>);
+
+impl super::InboundMiddleware for RequesterMiddleware {
+    fn handle(&mut self, publication: &mut crate::ReceivedPublication) -> bool {
+        let id = self.0.correlation_id(publication);
+        let response_send = match &id {
+            Some(id) => self.0.lock().remove(id),
+            None => return true,
+        };
+
+        match response_send {
+            Some(response_send) => {
+                let response = crate::proto::Publication {
+                    topic_name: publication.topic_name.clone(),
+                    qos: publication.qos,
+                    retain: publication.retain,
+                    payload: publication.payload.clone(),
+                    user_properties: std::mem::take(&mut publication.user_properties),
+                    response_topic: publication.response_topic.take(),
+                    correlation_data: publication.correlation_data.take(),
+                    message_expiry: None,
+                    content_type: None,
+                };
+                if response_send.send(response).is_err() {
+                    log::debug!("dropping response {:?} because the request was dropped", id);
+                }
+            },
+
+            None => log::debug!("dropping response {:?} to a request that timed out or was never made", id),
+        }
+
+        false
+    }
+}
+
+/// Returns the response to the given request with the given payload, for a responder of requests made with [`ReplyConvention::Properties`].
+///
+/// Returns `None` if the request has no response topic.
+#[cfg(feature = "v5")]
+#[allow(clippy::doc_markdown)]
+pub fn response(request: &crate::ReceivedPublication, qos: crate::proto::QoS, payload: bytes::Bytes) -> Option<crate::proto::Publication> {
+    Some(crate::proto::Publication {
+        topic_name: request.response_topic.clone()?,
+        qos,
+        retain: false,
+        payload,
+        user_properties: vec![],
+        response_topic: None,
+        correlation_data: request.correlation_data.clone(),
+        message_expiry: None,
+        content_type: None,
+    })
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+    ClientClosed,
+    Publish(super::PublishError),
+    Subscribe(super::UpdateSubscriptionError),
+    TimedOut,
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::ClientClosed => write!(f, "client has shut down or was dropped"),
+            RequestError::Publish(_) => write!(f, "could not publish request"),
+            RequestError::Subscribe(_) => write!(f, "could not subscribe to response topic"),
+            RequestError::TimedOut => write!(f, "timed out waiting for response"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RequestError::ClientClosed | RequestError::TimedOut => None,
+            RequestError::Publish(err) => Some(err),
+            RequestError::Subscribe(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn received(topic_name: &str, correlation_data: Option<&'static [u8]>) -> crate::ReceivedPublication {
+        crate::ReceivedPublication {
+            topic_name: topic_name.parse().unwrap(),
+            dup: false,
+            qos: crate::proto::QoS::AtMostOnce,
+            packet_identifier: None,
+            retain: false,
+            payload: bytes::Bytes::from_static(b"response"),
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: correlation_data.map(bytes::Bytes::from_static),
+            manual_ack: None,
+        }
+    }
+
+    fn inner(reply_convention: super::ReplyConvention) -> std::sync::Arc<super::Inner> {
+        std::sync::Arc::new(super::Inner {
+            response_topic: "replies/a".parse().unwrap(),
+            reply_convention,
+            subscribed: Default::default(),
+            next_id: Default::default(),
+            pending: Default::default(),
+        })
+    }
+
+    #[test]
+    fn topic_suffix() {
+        use crate::InboundMiddleware;
+
+        let inner = inner(super::ReplyConvention::TopicSuffix);
+        let (response_send, mut response_recv) = futures_channel::oneshot::channel();
+        inner.lock().insert("1".to_owned(), response_send);
+        let mut middleware = super::RequesterMiddleware(inner.clone());
+
+        // Publications on other topics pass through
+        assert!(middleware.handle(&mut received("replies/ab/1", None)));
+        assert!(middleware.handle(&mut received("replies", None)));
+
+        // Responses to unknown requests are dropped
+        assert!(!middleware.handle(&mut received("replies/a/2", None)));
+        assert_eq!(response_recv.try_recv().unwrap(), None);
+
+        assert!(!middleware.handle(&mut received("replies/a/1", None)));
+        assert_eq!(response_recv.try_recv().unwrap().unwrap().topic_name, "replies/a/1".parse::<crate::proto::ByteStr>().unwrap());
+        assert!(inner.lock().is_empty());
+    }
+
+    #[cfg(feature = "v5")]
+    #[test]
+    fn properties() {
+        use crate::InboundMiddleware;
+
+        let inner = inner(super::ReplyConvention::Properties);
+        let (response_send, mut response_recv) = futures_channel::oneshot::channel();
+        inner.lock().insert("1".to_owned(), response_send);
+        let mut middleware = super::RequesterMiddleware(inner);
+
+        assert!(middleware.handle(&mut received("replies/a/1", Some(b"1"))));
+        assert!(!middleware.handle(&mut received("replies/a", Some(b"2"))));
+        assert_eq!(response_recv.try_recv().unwrap(), None);
+
+        let mut request = received("requests", None);
+        request.response_topic = Some("replies/a".parse::<crate::proto::ByteStr>().unwrap());
+        request.correlation_data = Some(bytes::Bytes::from_static(b"1"));
+        let response = super::response(&request, crate::proto::QoS::AtMostOnce, bytes::Bytes::from_static(b"response")).unwrap();
+        assert_eq!(response.topic_name, "replies/a".parse::<crate::proto::ByteStr>().unwrap());
+        assert_eq!(response.correlation_data.as_deref(), Some(&b"1"[..]));
+
+        assert!(!middleware.handle(&mut received("replies/a", Some(b"1"))));
+        assert_eq!(response_recv.try_recv().unwrap().unwrap().correlation_data.as_deref(), Some(&b"1"[..]));
+    }
+}
@@ -0,0 +1,714 @@
+#[cfg(feature = "avro")]
+use std::convert::{TryFrom, TryInto};
+
+/// The first byte of payloads framed for a Confluent-style schema registry
+const MAGIC_BYTE: u8 = 0;
+
+/// A client for a Confluent-style schema registry, used by [`SchemaRegistryCodec`] to look up schemas. Requires the `schema-registry` feature.
+///
+/// Implement this with the HTTP client of your choice.
+pub trait SchemaRegistry {
+    type Error: std::error::Error;
+
+    type SchemaFuture: std::future::Future<Output = Result<String, Self::Error>>;
+
+    type LatestSchemaFuture: std::future::Future<Output = Result<(u32, String), Self::Error>>;
+
+    /// Returns the schema with the given ID
+    fn schema(&self, id: u32) -> Self::SchemaFuture;
+
+    /// Returns the ID and the schema of the latest version registered under the given subject
+    fn latest_schema(&self, subject: &str) -> Self::LatestSchemaFuture;
+}
+
+/// Encodes and decodes values with a schema, used by [`SchemaRegistryCodec`]. Requires the `schema-registry` feature.
+///
+/// With the `avro` feature, [`AvroCodec`] implements it for Avro. Implement it for other formats, such as JSON Schema, yourself.
+pub trait SchemaCodec {
+    /// A parsed schema
+    type Schema;
+
+    /// The type of the values that are encoded
+    type Value;
+
+    type Error: std::error::Error;
+
+    /// Parses a schema returned by the schema registry
+    fn parse_schema(&self, schema: &str) -> Result<Self::Schema, Self::Error>;
+
+    /// Encodes the given value with the given schema
+    fn encode(&self, schema: &Self::Schema, value: &Self::Value) -> Result<Vec<u8>, Self::Error>;
+
+    /// Decodes a value that was encoded with the given schema
+    fn decode(&self, writer_schema: &Self::Schema, data: &[u8]) -> Result<Self::Value, Self::Error>;
+}
+
+/// Encodes and decodes payloads with the framing used by Confluent-style schema registries: a zero byte,
+/// then the ID of the schema as a big-endian 32-bit integer, then the value encoded with that schema.
+///
+/// This lets payloads be bridged between MQTT and Kafka without being converted. Schemas are fetched from the [`SchemaRegistry`]
+/// the first time they are needed, and then cached until [`SchemaRegistryCodec::clear_cache`] is called.
+///
+/// Requires the `schema-registry` feature. Use it with [`AvroCodec`] for Avro payloads.
+pub struct SchemaRegistryCodec<R, C> where C: SchemaCodec {
+    registry: R,
+    codec: C,
+    schemas: std::sync::Mutex<std::collections::HashMap<u32, std::sync::Arc<C::Schema>>>,
+    subjects: std::sync::Mutex<std::collections::HashMap<String, u32>>,
+}
+
+impl<R, C> SchemaRegistryCodec<R, C>
+where
+    R: SchemaRegistry,
+    C: SchemaCodec,
+{
+    pub fn new(registry: R, codec: C) -> Self {
+        SchemaRegistryCodec {
+            registry,
+            codec,
+            schemas: Default::default(),
+            subjects: Default::default(),
+        }
+    }
+
+    /// Encodes the given value with the latest schema of the given subject
+    pub async fn encode(&self, subject: &str, value: &C::Value) -> Result<bytes::Bytes, SchemaRegistryCodecError<R::Error, C::Error>> {
+        let (id, schema) = self.latest_schema(subject).await?;
+
+        let mut payload = Vec::with_capacity(5);
+        payload.push(MAGIC_BYTE);
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend(self.codec.encode(&schema, value).map_err(SchemaRegistryCodecError::Codec)?);
+        Ok(payload.into())
+    }
+
+    /// Decodes the given payload with the schema whose ID it starts with
+    pub async fn decode(&self, payload: &[u8]) -> Result<C::Value, SchemaRegistryCodecError<R::Error, C::Error>> {
+        let (id, data) = match payload {
+            [MAGIC_BYTE, a, b, c, d, data @ ..] => (u32::from_be_bytes([*a, *b, *c, *d]), data),
+            _ => return Err(SchemaRegistryCodecError::NotFramed),
+        };
+
+        let schema = self.schema(id).await?;
+        self.codec.decode(&schema, data).map_err(SchemaRegistryCodecError::Codec)
+    }
+
+    /// Forgets all cached schemas, so that they are fetched from the registry again. This picks up new versions of subjects.
+    pub fn clear_cache(&self) {
+        self.schemas.lock().expect("schemas lock is poisoned").clear();
+        self.subjects.lock().expect("subjects lock is poisoned").clear();
+    }
+
+    async fn schema(&self, id: u32) -> Result<std::sync::Arc<C::Schema>, SchemaRegistryCodecError<R::Error, C::Error>> {
+        if let Some(schema) = self.schemas.lock().expect("schemas lock is poisoned").get(&id) {
+            return Ok(schema.clone());
+        }
+
+        let schema = self.registry.schema(id).await.map_err(SchemaRegistryCodecError::Registry)?;
+        let schema = std::sync::Arc::new(self.codec.parse_schema(&schema).map_err(SchemaRegistryCodecError::Codec)?);
+        self.schemas.lock().expect("schemas lock is poisoned").insert(id, schema.clone());
+        Ok(schema)
+    }
+
+    async fn latest_schema(&self, subject: &str) -> Result<(u32, std::sync::Arc<C::Schema>), SchemaRegistryCodecError<R::Error, C::Error>> {
+        let id = self.subjects.lock().expect("subjects lock is poisoned").get(subject).copied();
+        if let Some(id) = id {
+            return Ok((id, self.schema(id).await?));
+        }
+
+        let (id, schema) = self.registry.latest_schema(subject).await.map_err(SchemaRegistryCodecError::Registry)?;
+        let schema = std::sync::Arc::new(self.codec.parse_schema(&schema).map_err(SchemaRegistryCodecError::Codec)?);
+        self.schemas.lock().expect("schemas lock is poisoned").insert(id, schema.clone());
+        self.subjects.lock().expect("subjects lock is poisoned").insert(subject.to_owned(), id);
+        Ok((id, schema))
+    }
+}
+
+impl<R, C> std::fmt::Debug for SchemaRegistryCodec<R, C> where C: SchemaCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SchemaRegistryCodec").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub enum SchemaRegistryCodecError<R, C> {
+    NotFramed,
+    Registry(R),
+    Codec(C),
+}
+
+impl<R, C> std::fmt::Display for SchemaRegistryCodecError<R, C>
+where
+    R: std::fmt::Display,
+    C: std::fmt::Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaRegistryCodecError::NotFramed => write!(f, "payload does not start with a schema ID"),
+            SchemaRegistryCodecError::Registry(err) => write!(f, "could not get schema from schema registry: {}", err),
+            SchemaRegistryCodecError::Codec(err) => write!(f, "could not encode or decode value: {}", err),
+        }
+    }
+}
+
+impl<R, C> std::error::Error for SchemaRegistryCodecError<R, C>
+where
+    R: std::error::Error + 'static,
+    C: std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SchemaRegistryCodecError::NotFramed => None,
+            SchemaRegistryCodecError::Registry(err) => Some(err),
+            SchemaRegistryCodecError::Codec(err) => Some(err),
+        }
+    }
+}
+
+/// A value encoded with an Avro schema by [`AvroCodec`]. Requires the `avro` feature.
+///
+/// Logical types are represented by their underlying types, such as `Int` for `date`.
+#[cfg(feature = "avro")]
+#[derive(Clone, Debug, PartialEq)]
+pub enum AvroValue {
+    Null,
+    Boolean(bool),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Bytes(Vec<u8>),
+    String(String),
+
+    /// The fields of a record by name. They are encoded in the order of the schema, and decoded in that order.
+    Record(Vec<(String, AvroValue)>),
+
+    /// The symbol of an enum
+    Enum(String),
+
+    Array(Vec<AvroValue>),
+    Map(std::collections::BTreeMap<String, AvroValue>),
+
+    /// The index of the branch of a union within the union schema, and the value of that branch
+    Union(usize, Box<AvroValue>),
+
+    Fixed(Vec<u8>),
+}
+
+/// A [`SchemaCodec`] for the Avro binary encoding, for use with [`SchemaRegistryCodec`]. Requires the `avro` feature.
+///
+/// It implements the binary encoding only, with these limits:
+///
+/// - Schemas that refer to named types by name, rather than defining them inline, fail to parse with [`AvroCodecError::InvalidSchema`].
+///   So do recursive schemas, which need such references.
+/// - Values are decoded with the writer's schema, without schema resolution. An application whose own schema has evolved
+///   gets the fields of the writer's schema, and has to map them itself.
+/// - Arrays of items that take no bytes, such as nulls or records without fields, are decoded with at most
+///   1,048,576 items, so that a corrupt item count cannot make the decoder allocate without bound.
+/// - Arrays and maps are encoded as a single block.
+#[cfg(feature = "avro")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AvroCodec;
+
+#[cfg(feature = "avro")]
+impl SchemaCodec for AvroCodec {
+    type Schema = avro_schema::schema::Schema;
+    type Value = AvroValue;
+    type Error = AvroCodecError;
+
+    fn parse_schema(&self, schema: &str) -> Result<Self::Schema, Self::Error> {
+        serde_json::from_str(schema).map_err(AvroCodecError::InvalidSchema)
+    }
+
+    fn encode(&self, schema: &Self::Schema, value: &Self::Value) -> Result<Vec<u8>, Self::Error> {
+        let mut data = vec![];
+        encode_avro(schema, value, &mut data)?;
+        Ok(data)
+    }
+
+    fn decode(&self, writer_schema: &Self::Schema, mut data: &[u8]) -> Result<Self::Value, Self::Error> {
+        let value = decode_avro(writer_schema, &mut data)?;
+        if !data.is_empty() {
+            return Err(AvroCodecError::InvalidData);
+        }
+
+        Ok(value)
+    }
+}
+
+#[cfg(feature = "avro")]
+fn encode_avro(schema: &avro_schema::schema::Schema, value: &AvroValue, data: &mut Vec<u8>) -> Result<(), AvroCodecError> {
+    use avro_schema::schema::Schema;
+
+    match (schema, value) {
+        (Schema::Null, AvroValue::Null) => (),
+        (Schema::Boolean, AvroValue::Boolean(value)) => data.push(u8::from(*value)),
+        (Schema::Int(_), AvroValue::Int(value)) => encode_avro_long(i64::from(*value), data),
+        (Schema::Long(_), AvroValue::Long(value)) => encode_avro_long(*value, data),
+        (Schema::Float, AvroValue::Float(value)) => data.extend_from_slice(&value.to_le_bytes()),
+        (Schema::Double, AvroValue::Double(value)) => data.extend_from_slice(&value.to_le_bytes()),
+        (Schema::Bytes(_), AvroValue::Bytes(value)) => encode_avro_bytes(value, data),
+        (Schema::String(_), AvroValue::String(value)) => encode_avro_bytes(value.as_bytes(), data),
+
+        (Schema::Record(record), AvroValue::Record(fields)) =>
+            for field in &record.fields {
+                let value = fields.iter().find(|(name, _)| *name == field.name).ok_or(AvroCodecError::ValueDoesNotMatchSchema)?;
+                encode_avro(&field.schema, &value.1, data)?;
+            },
+
+        (Schema::Enum(schema), AvroValue::Enum(symbol)) => {
+            let index = schema.symbols.iter().position(|s| s == symbol).ok_or(AvroCodecError::ValueDoesNotMatchSchema)?;
+            encode_avro_len(index, data);
+        },
+
+        (Schema::Array(items), AvroValue::Array(values)) => {
+            // A single block of items, then the empty block that ends the array
+            if !values.is_empty() {
+                encode_avro_len(values.len(), data);
+                for value in values {
+                    encode_avro(items, value, data)?;
+                }
+            }
+            data.push(0);
+        },
+
+        (Schema::Map(values_schema), AvroValue::Map(values)) => {
+            if !values.is_empty() {
+                encode_avro_len(values.len(), data);
+                for (key, value) in values {
+                    encode_avro_bytes(key.as_bytes(), data);
+                    encode_avro(values_schema, value, data)?;
+                }
+            }
+            data.push(0);
+        },
+
+        (Schema::Union(schemas), AvroValue::Union(index, value)) => {
+            let schema = schemas.get(*index).ok_or(AvroCodecError::ValueDoesNotMatchSchema)?;
+            encode_avro_len(*index, data);
+            encode_avro(schema, value, data)?;
+        },
+
+        (Schema::Fixed(schema), AvroValue::Fixed(value)) if value.len() == schema.size => data.extend_from_slice(value),
+
+        _ => return Err(AvroCodecError::ValueDoesNotMatchSchema),
+    }
+
+    Ok(())
+}
+
+/// Encodes a long as a zigzag varint
+#[cfg(feature = "avro")]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn encode_avro_long(value: i64, data: &mut Vec<u8>) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        data.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+#[cfg(feature = "avro")]
+fn encode_avro_len(len: usize, data: &mut Vec<u8>) {
+    encode_avro_long(len.try_into().expect("length fits in i64"), data);
+}
+
+#[cfg(feature = "avro")]
+fn encode_avro_bytes(value: &[u8], data: &mut Vec<u8>) {
+    encode_avro_len(value.len(), data);
+    data.extend_from_slice(value);
+}
+
+#[cfg(feature = "avro")]
+fn decode_avro(schema: &avro_schema::schema::Schema, data: &mut &[u8]) -> Result<AvroValue, AvroCodecError> {
+    use avro_schema::schema::Schema;
+
+    Ok(match schema {
+        Schema::Null => AvroValue::Null,
+
+        Schema::Boolean => match decode_avro_fixed(data, 1)? {
+            [0] => AvroValue::Boolean(false),
+            [1] => AvroValue::Boolean(true),
+            _ => return Err(AvroCodecError::InvalidData),
+        },
+
+        Schema::Int(_) => AvroValue::Int(decode_avro_long(data)?.try_into().map_err(|_| AvroCodecError::InvalidData)?),
+        Schema::Long(_) => AvroValue::Long(decode_avro_long(data)?),
+        Schema::Float => AvroValue::Float(f32::from_le_bytes(decode_avro_fixed(data, 4)?.try_into().expect("4 bytes"))),
+        Schema::Double => AvroValue::Double(f64::from_le_bytes(decode_avro_fixed(data, 8)?.try_into().expect("8 bytes"))),
+        Schema::Bytes(_) => AvroValue::Bytes(decode_avro_bytes(data)?.to_owned()),
+        Schema::String(_) => AvroValue::String(decode_avro_string(data)?),
+
+        Schema::Record(record) => AvroValue::Record(
+            record.fields.iter()
+                .map(|field| Ok((field.name.clone(), decode_avro(&field.schema, data)?)))
+                .collect::<Result<_, AvroCodecError>>()?,
+        ),
+
+        Schema::Enum(schema) => {
+            let index = decode_avro_len(data)?;
+            AvroValue::Enum(schema.symbols.get(index).ok_or(AvroCodecError::InvalidData)?.clone())
+        },
+
+        Schema::Array(items) => {
+            let item_size = min_avro_size(items);
+            let mut values = vec![];
+            while let Some(count) = decode_avro_block(data, item_size)? {
+                if item_size == 0 && values.len() + count > MAX_EMPTY_AVRO_ITEMS {
+                    return Err(AvroCodecError::InvalidData);
+                }
+
+                for _ in 0..count {
+                    values.push(decode_avro(items, data)?);
+                }
+            }
+            AvroValue::Array(values)
+        },
+
+        Schema::Map(values_schema) => {
+            // Every entry starts with its key, which takes at least a byte for its length
+            let entry_size = min_avro_size(values_schema).saturating_add(1);
+            let mut values = std::collections::BTreeMap::new();
+            while let Some(count) = decode_avro_block(data, entry_size)? {
+                for _ in 0..count {
+                    let key = decode_avro_string(data)?;
+                    values.insert(key, decode_avro(values_schema, data)?);
+                }
+            }
+            AvroValue::Map(values)
+        },
+
+        Schema::Union(schemas) => {
+            let index = decode_avro_len(data)?;
+            let schema = schemas.get(index).ok_or(AvroCodecError::InvalidData)?;
+            AvroValue::Union(index, Box::new(decode_avro(schema, data)?))
+        },
+
+        Schema::Fixed(schema) => AvroValue::Fixed(decode_avro_fixed(data, schema.size)?.to_owned()),
+    })
+}
+
+/// Decodes a zigzag varint long
+#[cfg(feature = "avro")]
+#[allow(clippy::cast_possible_wrap)]
+fn decode_avro_long(data: &mut &[u8]) -> Result<i64, AvroCodecError> {
+    let mut value = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or(AvroCodecError::InvalidData)?;
+        *data = rest;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((value >> 1) as i64) ^ -((value & 1) as i64));
+        }
+    }
+
+    Err(AvroCodecError::InvalidData)
+}
+
+#[cfg(feature = "avro")]
+fn decode_avro_len(data: &mut &[u8]) -> Result<usize, AvroCodecError> {
+    decode_avro_long(data)?.try_into().map_err(|_| AvroCodecError::InvalidData)
+}
+
+#[cfg(feature = "avro")]
+fn decode_avro_fixed<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], AvroCodecError> {
+    if data.len() < len {
+        return Err(AvroCodecError::InvalidData);
+    }
+
+    let (value, rest) = data.split_at(len);
+    *data = rest;
+    Ok(value)
+}
+
+#[cfg(feature = "avro")]
+fn decode_avro_bytes<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], AvroCodecError> {
+    let len = decode_avro_len(data)?;
+    decode_avro_fixed(data, len)
+}
+
+#[cfg(feature = "avro")]
+fn decode_avro_string(data: &mut &[u8]) -> Result<String, AvroCodecError> {
+    let value = decode_avro_bytes(data)?;
+    Ok(std::str::from_utf8(value).map_err(|_| AvroCodecError::InvalidData)?.to_owned())
+}
+
+/// The most items that take no bytes that an array is decoded with. See [`AvroCodec`].
+#[cfg(feature = "avro")]
+const MAX_EMPTY_AVRO_ITEMS: usize = 1 << 20;
+
+/// Decodes the header of a block of array items or map entries, each of which takes at least `item_size` bytes.
+/// Returns the number of items in the block, or `None` at the end.
+#[cfg(feature = "avro")]
+fn decode_avro_block(data: &mut &[u8], item_size: usize) -> Result<Option<usize>, AvroCodecError> {
+    let count = decode_avro_long(data)?;
+    if count == 0 {
+        return Ok(None);
+    }
+
+    // A negative count is followed by the size of the block in bytes, which is not needed
+    if count < 0 {
+        decode_avro_long(data)?;
+    }
+
+    // This keeps a bad count from making the decoder allocate without bound. Items that take no bytes are limited by the caller instead.
+    let count = usize::try_from(count.unsigned_abs()).map_err(|_| AvroCodecError::InvalidData)?;
+    if count.saturating_mul(item_size) > data.len() {
+        return Err(AvroCodecError::InvalidData);
+    }
+
+    Ok(Some(count))
+}
+
+/// The fewest bytes that a value of the given schema is encoded with
+#[cfg(feature = "avro")]
+fn min_avro_size(schema: &avro_schema::schema::Schema) -> usize {
+    use avro_schema::schema::Schema;
+
+    match schema {
+        Schema::Null => 0,
+        Schema::Float => 4,
+        Schema::Double => 8,
+        Schema::Record(record) => record.fields.iter().fold(0, |size, field| size.saturating_add(min_avro_size(&field.schema))),
+        Schema::Fixed(schema) => schema.size,
+
+        // A varint, such as a length, an index or the count of the last block, takes at least a byte
+        Schema::Boolean
+        | Schema::Int(_)
+        | Schema::Long(_)
+        | Schema::Bytes(_)
+        | Schema::String(_)
+        | Schema::Enum(_)
+        | Schema::Array(_)
+        | Schema::Map(_)
+        | Schema::Union(_) => 1,
+    }
+}
+
+/// The error returned by [`AvroCodec`]. Requires the `avro` feature.
+#[cfg(feature = "avro")]
+#[derive(Debug)]
+pub enum AvroCodecError {
+    InvalidSchema(serde_json::Error),
+    ValueDoesNotMatchSchema,
+    InvalidData,
+}
+
+#[cfg(feature = "avro")]
+impl std::fmt::Display for AvroCodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AvroCodecError::InvalidSchema(err) => write!(f, "could not parse Avro schema: {}", err),
+            AvroCodecError::ValueDoesNotMatchSchema => f.write_str("value does not match Avro schema"),
+            AvroCodecError::InvalidData => f.write_str("data is not valid for Avro schema"),
+        }
+    }
+}
+
+#[cfg(feature = "avro")]
+impl std::error::Error for AvroCodecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AvroCodecError::InvalidSchema(err) => Some(err),
+            AvroCodecError::ValueDoesNotMatchSchema | AvroCodecError::InvalidData => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+
+    #[derive(Default)]
+    struct Registry(std::cell::Cell<usize>);
+
+    impl super::SchemaRegistry for Registry {
+        type Error = std::io::Error;
+        type SchemaFuture = futures_util::future::Ready<Result<String, Self::Error>>;
+        type LatestSchemaFuture = futures_util::future::Ready<Result<(u32, String), Self::Error>>;
+
+        fn schema(&self, id: u32) -> Self::SchemaFuture {
+            self.0.set(self.0.get() + 1);
+            futures_util::future::ready(match id {
+                1 => Ok("lower".to_owned()),
+                2 => Ok("upper".to_owned()),
+                _ => Err(std::io::ErrorKind::NotFound.into()),
+            })
+        }
+
+        fn latest_schema(&self, _subject: &str) -> Self::LatestSchemaFuture {
+            self.0.set(self.0.get() + 1);
+            futures_util::future::ready(Ok((2, "upper".to_owned())))
+        }
+    }
+
+    /// Encodes strings in the case given by the schema
+    struct CaseCodec;
+
+    impl super::SchemaCodec for CaseCodec {
+        type Schema = bool;
+        type Value = String;
+        type Error = std::str::Utf8Error;
+
+        fn parse_schema(&self, schema: &str) -> Result<bool, Self::Error> {
+            Ok(schema == "upper")
+        }
+
+        fn encode(&self, upper: &bool, value: &String) -> Result<Vec<u8>, Self::Error> {
+            Ok(if *upper { value.to_uppercase() } else { value.to_lowercase() }.into_bytes())
+        }
+
+        fn decode(&self, _writer_schema: &bool, data: &[u8]) -> Result<String, Self::Error> {
+            Ok(std::str::from_utf8(data)?.to_owned())
+        }
+    }
+
+    #[test]
+    fn framing_and_cache() {
+        let codec = super::SchemaRegistryCodec::new(Registry::default(), CaseCodec);
+
+        let payload = codec.encode("greeting-value", &"Hello".to_owned()).now_or_never().unwrap().unwrap();
+        assert_eq!(&payload[..], b"\x00\x00\x00\x00\x02HELLO");
+        assert_eq!(codec.decode(&payload).now_or_never().unwrap().unwrap(), "HELLO");
+        assert_eq!(codec.decode(b"\x00\x00\x00\x00\x01hello").now_or_never().unwrap().unwrap(), "hello");
+        assert_eq!(codec.registry.0.get(), 2);
+
+        codec.encode("greeting-value", &"Hello".to_owned()).now_or_never().unwrap().unwrap();
+        assert_eq!(codec.registry.0.get(), 2);
+
+        assert!(matches!(codec.decode(b"HELLO").now_or_never().unwrap(), Err(super::SchemaRegistryCodecError::NotFramed)));
+        assert!(matches!(codec.decode(b"\x00\x00\x00\x00\x03").now_or_never().unwrap(), Err(super::SchemaRegistryCodecError::Registry(_))));
+    }
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn avro() {
+        use super::SchemaCodec;
+
+        let codec = super::AvroCodec;
+        let schema = codec.parse_schema(r#"{
+            "type": "record",
+            "name": "Reading",
+            "fields": [
+                { "name": "sensor", "type": "string" },
+                { "name": "value", "type": "double" },
+                { "name": "offset", "type": "long" },
+                { "name": "unit", "type": ["null", "string"] },
+                { "name": "tags", "type": { "type": "array", "items": "string" } }
+            ]
+        }"#).unwrap();
+
+        let value = super::AvroValue::Record(vec![
+            ("sensor".to_owned(), super::AvroValue::String("t1".to_owned())),
+            ("value".to_owned(), super::AvroValue::Double(21.5)),
+            ("offset".to_owned(), super::AvroValue::Long(-65)),
+            ("unit".to_owned(), super::AvroValue::Union(1, Box::new(super::AvroValue::String("C".to_owned())))),
+            ("tags".to_owned(), super::AvroValue::Array(vec![super::AvroValue::String("a".to_owned())])),
+        ]);
+        let data = codec.encode(&schema, &value).unwrap();
+        assert_eq!(data, [
+            0x04, b't', b'1',
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x35, 0x40,
+            0x81, 0x01,
+            0x02, 0x02, b'C',
+            0x02, 0x02, b'a', 0x00,
+        ]);
+        assert_eq!(codec.decode(&schema, &data).unwrap(), value);
+
+        // Fields are encoded in the order of the schema
+        let mut fields = match value {
+            super::AvroValue::Record(fields) => fields,
+            _ => unreachable!(),
+        };
+        fields.reverse();
+        assert_eq!(codec.encode(&schema, &super::AvroValue::Record(fields)).unwrap(), data);
+
+        assert!(matches!(codec.encode(&schema, &super::AvroValue::Null), Err(super::AvroCodecError::ValueDoesNotMatchSchema)));
+        assert!(matches!(codec.decode(&schema, &data[..data.len() - 1]), Err(super::AvroCodecError::InvalidData)));
+        assert!(matches!(codec.decode(&schema, &[&data[..], &[0]].concat()), Err(super::AvroCodecError::InvalidData)));
+        assert!(matches!(codec.parse_schema("{}"), Err(super::AvroCodecError::InvalidSchema(_))));
+    }
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn avro_items_without_bytes() {
+        use super::SchemaCodec;
+
+        let codec = super::AvroCodec;
+
+        // Arrays of nulls and of empty records take a byte for their count and one to end them, however many items they have
+        for schema in &[
+            r#"{ "type": "array", "items": "null" }"#,
+            r#"{ "type": "array", "items": { "type": "record", "name": "Empty", "fields": [] } }"#,
+        ] {
+            let schema = codec.parse_schema(schema).unwrap();
+            let item = match &schema {
+                avro_schema::schema::Schema::Array(items) if **items == avro_schema::schema::Schema::Null => super::AvroValue::Null,
+                _ => super::AvroValue::Record(vec![]),
+            };
+
+            let value = super::AvroValue::Array(vec![item.clone(); 100]);
+            let data = codec.encode(&schema, &value).unwrap();
+            assert_eq!(data, [0xC8, 0x01, 0x00]);
+            assert_eq!(codec.decode(&schema, &data).unwrap(), value);
+
+            // A count of items that would take more bytes than are left is fine for items that take none, up to a limit
+            let mut data = vec![];
+            super::encode_avro_len(super::MAX_EMPTY_AVRO_ITEMS, &mut data);
+            data.push(0);
+            assert_eq!(codec.decode(&schema, &data).unwrap(), super::AvroValue::Array(vec![item; super::MAX_EMPTY_AVRO_ITEMS]));
+
+            let mut data = vec![];
+            super::encode_avro_len(super::MAX_EMPTY_AVRO_ITEMS + 1, &mut data);
+            data.push(0);
+            assert!(matches!(codec.decode(&schema, &data), Err(super::AvroCodecError::InvalidData)));
+        }
+
+        // Items that take bytes are still checked against the bytes that are left
+        let schema = codec.parse_schema(r#"{ "type": "array", "items": "double" }"#).unwrap();
+        assert!(matches!(codec.decode(&schema, &[0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Err(super::AvroCodecError::InvalidData)));
+
+        // Named types can only be defined inline
+        assert!(matches!(
+            codec.parse_schema(r#"{ "type": "record", "name": "Node", "fields": [{ "name": "next", "type": ["null", "Node"] }] }"#),
+            Err(super::AvroCodecError::InvalidSchema(_)),
+        ));
+    }
+
+    #[cfg(feature = "avro")]
+    #[test]
+    fn avro_unions() {
+        use super::SchemaCodec;
+
+        let codec = super::AvroCodec;
+        let schema = codec.parse_schema(r#"{
+            "type": "map",
+            "values": { "type": "array", "items": ["null", "long", { "type": "record", "name": "Point", "fields": [{ "name": "x", "type": "int" }] }] }
+        }"#).unwrap();
+
+        let value = super::AvroValue::Map(vec![
+            ("a".to_owned(), super::AvroValue::Array(vec![
+                super::AvroValue::Union(0, Box::new(super::AvroValue::Null)),
+                super::AvroValue::Union(1, Box::new(super::AvroValue::Long(-1))),
+                super::AvroValue::Union(2, Box::new(super::AvroValue::Record(vec![("x".to_owned(), super::AvroValue::Int(3))]))),
+            ])),
+            ("b".to_owned(), super::AvroValue::Array(vec![])),
+        ].into_iter().collect());
+        let data = codec.encode(&schema, &value).unwrap();
+        assert_eq!(data, [
+            0x04,
+            0x02, b'a', 0x06, 0x00, 0x02, 0x01, 0x04, 0x06, 0x00,
+            0x02, b'b', 0x00,
+            0x00,
+        ]);
+        assert_eq!(codec.decode(&schema, &data).unwrap(), value);
+
+        // The branch of a union must exist in the schema
+        let value = super::AvroValue::Map(vec![
+            ("a".to_owned(), super::AvroValue::Array(vec![super::AvroValue::Union(3, Box::new(super::AvroValue::Null))])),
+        ].into_iter().collect());
+        assert!(matches!(codec.encode(&schema, &value), Err(super::AvroCodecError::ValueDoesNotMatchSchema)));
+        assert!(matches!(codec.decode(&schema, &[0x02, 0x02, b'a', 0x02, 0x06, 0x00, 0x00]), Err(super::AvroCodecError::InvalidData)));
+    }
+}
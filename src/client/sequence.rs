@@ -0,0 +1,236 @@
+/// The length of the envelope that [`SequenceLayer`] puts in front of payloads
+const ENVELOPE_LEN: usize = 16;
+
+/// The position of a publication in the stream of publications to its topic, added by [`SequenceLayer`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sequence {
+    /// Identifies the run of the publisher. Sequence numbers restart from zero when it changes.
+    pub epoch: u64,
+
+    /// Increases by one with every publication to the topic
+    pub number: u64,
+}
+
+impl Sequence {
+    /// Splits the envelope added by [`SequenceLayer`] off the given payload.
+    /// Returns `None` if the payload is too short to have one.
+    pub fn split(payload: &bytes::Bytes) -> Option<(Self, bytes::Bytes)> {
+        if payload.len() < ENVELOPE_LEN {
+            return None;
+        }
+
+        let mut epoch = [0_u8; 8];
+        epoch.copy_from_slice(&payload[..8]);
+        let mut number = [0_u8; 8];
+        number.copy_from_slice(&payload[8..ENVELOPE_LEN]);
+
+        let sequence = Sequence { epoch: u64::from_be_bytes(epoch), number: u64::from_be_bytes(number) };
+        Some((sequence, payload.slice(ENVELOPE_LEN..)))
+    }
+}
+
+/// A [`super::PublishLayer`] that stamps every publication with its [`Sequence`], so that subscribers can use a [`GapDetector`]
+/// to notice publications that were lost, such as QoS 0 publications sent while the connection was dropping.
+///
+/// MQTT 3.1.1 publications do not have properties, so the sequence is put in an envelope in front of the payload:
+/// the epoch and then the number, both as big-endian 64-bit integers. Every topic has its own sequence numbers.
+///
+/// The epoch should be different every time the publisher starts, such as the time it started at, so that subscribers
+/// do not mistake its sequence numbers restarting from zero for lost publications.
+/// Clones share the same sequence numbers.
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Debug)]
+pub struct SequenceLayer {
+    epoch: u64,
+    next: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<crate::proto::ByteStr, u64>>>,
+}
+
+impl SequenceLayer {
+    pub fn new(epoch: u64) -> Self {
+        SequenceLayer {
+            epoch,
+            next: Default::default(),
+        }
+    }
+}
+
+impl<S> super::PublishLayer<S> for SequenceLayer where S: super::PublishService {
+    type Service = SequenceService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SequenceService { inner, layer: self.clone() }
+    }
+}
+
+/// The [`super::PublishService`] created by [`SequenceLayer`]
+#[derive(Clone, Debug)]
+pub struct SequenceService<S> {
+    inner: S,
+    layer: SequenceLayer,
+}
+
+impl<S> super::PublishService for SequenceService<S> where S: super::PublishService {
+    fn publish(&mut self, mut publication: crate::proto::Publication) -> super::PublishFuture {
+        let number = {
+            let mut next = self.layer.next.lock().expect("sequence numbers lock is poisoned");
+            let next = next.entry(publication.topic_name.clone()).or_default();
+            let number = *next;
+            *next += 1;
+            number
+        };
+
+        let mut payload = Vec::with_capacity(ENVELOPE_LEN + publication.payload.len());
+        payload.extend_from_slice(&self.layer.epoch.to_be_bytes());
+        payload.extend_from_slice(&number.to_be_bytes());
+        payload.extend_from_slice(&publication.payload);
+        publication.payload = payload.into();
+
+        self.inner.publish(publication)
+    }
+}
+
+/// An [`super::InboundMiddleware`] that removes the envelope added by [`SequenceLayer`] from publications,
+/// and counts the publications that were lost between the ones that were received.
+///
+/// Publications whose payloads are too short to have an envelope are dropped.
+/// Clones share the same counts, so a clone can be added to the client and the original used to read them.
+#[derive(Clone, Debug, Default)]
+pub struct GapDetector(std::sync::Arc<std::sync::Mutex<GapDetectorInner>>);
+
+#[derive(Debug, Default)]
+struct GapDetectorInner {
+    last: std::collections::HashMap<crate::proto::ByteStr, Sequence>,
+    gaps: u64,
+    missed: u64,
+    out_of_order: u64,
+}
+
+impl GapDetector {
+    /// The number of times publications were lost
+    pub fn gaps(&self) -> u64 {
+        self.0.lock().expect("gap detector lock is poisoned").gaps
+    }
+
+    /// The total number of publications that were lost
+    pub fn missed(&self) -> u64 {
+        self.0.lock().expect("gap detector lock is poisoned").missed
+    }
+
+    /// The number of publications that were received after a later one, such as redelivered duplicates
+    pub fn out_of_order(&self) -> u64 {
+        self.0.lock().expect("gap detector lock is poisoned").out_of_order
+    }
+
+    /// The sequence of the last publication received on the given topic
+    pub fn last(&self, topic_name: &crate::proto::ByteStr) -> Option<Sequence> {
+        self.0.lock().expect("gap detector lock is poisoned").last.get(topic_name).copied()
+    }
+}
+
+impl super::InboundMiddleware for GapDetector {
+    fn handle(&mut self, publication: &mut crate::ReceivedPublication) -> bool {
+        if let Some((sequence, payload)) = Sequence::split(&publication.payload) {
+            publication.payload = payload;
+            self.0.lock().expect("gap detector lock is poisoned").received(&publication.topic_name, sequence);
+            true
+        }
+        else {
+            log::warn!("publication to {:?} does not have a sequence number", publication.topic_name);
+            false
+        }
+    }
+}
+
+impl GapDetectorInner {
+    fn received(&mut self, topic_name: &crate::proto::ByteStr, sequence: Sequence) {
+        match self.last.get_mut(topic_name) {
+            Some(last) if last.epoch == sequence.epoch => {
+                if sequence.number <= last.number {
+                    self.out_of_order += 1;
+                    return;
+                }
+
+                let missed = sequence.number - last.number - 1;
+                if missed > 0 {
+                    log::warn!("lost {} publications to {:?} before sequence number {}", missed, topic_name, sequence.number);
+                    self.gaps += 1;
+                    self.missed += missed;
+                }
+
+                *last = sequence;
+            },
+
+            Some(last) => *last = sequence,
+
+            None => { self.last.insert(topic_name.clone(), sequence); },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{InboundMiddleware, PublishService};
+
+    /// Records the payloads of all publications
+    #[derive(Clone, Default)]
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<bytes::Bytes>>>);
+
+    impl PublishService for Recorder {
+        fn publish(&mut self, publication: crate::proto::Publication) -> crate::client::PublishFuture {
+            self.0.lock().unwrap().push(publication.payload);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn stamp_and_detect_gaps() {
+        let recorder = Recorder::default();
+        let mut publisher = crate::client::PublishServiceBuilder::new().layer(super::SequenceLayer::new(7)).service(recorder.clone());
+
+        for _ in 0..5 {
+            let _ = publisher.publish(crate::proto::Publication {
+                topic_name: "topic".parse().unwrap(),
+                qos: crate::proto::QoS::AtMostOnce,
+                retain: false,
+                payload: bytes::Bytes::from_static(b"payload"),
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            });
+        }
+
+        let payloads = std::mem::take(&mut *recorder.0.lock().unwrap());
+        assert_eq!(&payloads[1][..], b"\x00\x00\x00\x00\x00\x00\x00\x07\x00\x00\x00\x00\x00\x00\x00\x01payload");
+
+        let mut detector = super::GapDetector::default();
+        let mut receive = |payload: &bytes::Bytes| {
+            let mut publication = crate::ReceivedPublication {
+                topic_name: "topic".parse().unwrap(),
+                dup: false,
+                qos: crate::proto::QoS::AtMostOnce,
+                packet_identifier: None,
+                retain: false,
+                payload: payload.clone(),
+                user_properties: vec![],
+                manual_ack: None,
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+            };
+            assert!(detector.handle(&mut publication));
+            assert_eq!(&publication.payload[..], b"payload");
+        };
+
+        receive(&payloads[0]);
+        receive(&payloads[3]);
+        receive(&payloads[2]);
+        receive(&payloads[4]);
+
+        assert_eq!(detector.gaps(), 1);
+        assert_eq!(detector.missed(), 2);
+        assert_eq!(detector.out_of_order(), 1);
+        assert_eq!(detector.last(&"topic".parse().unwrap()), Some(super::Sequence { epoch: 7, number: 4 }));
+    }
+}
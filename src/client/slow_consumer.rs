@@ -0,0 +1,124 @@
+/// Thresholds at which a [`Client`](super::Client) reports that the application is not keeping up with the packets received from the server.
+///
+/// The client only reads a packet from the connection when it is polled, so packets that arrive faster than the application
+/// polls the client queue up in the connection. A backlog starts when the client reads a packet and ends when the client
+/// finds the connection empty. [`Event::SlowConsumer`](super::Event::SlowConsumer) is emitted once per backlog,
+/// as soon as either threshold is reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlowConsumerThresholds {
+    /// The number of packets read during a single backlog
+    pub backlog_depth: usize,
+
+    /// How long a single backlog lasts
+    pub backlog_age: std::time::Duration,
+}
+
+/// The backlog of received packets that caused an [`Event::SlowConsumer`](super::Event::SlowConsumer)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlowConsumerStats {
+    /// The number of packets read so far during the backlog
+    pub backlog_depth: usize,
+
+    /// How long the backlog has lasted so far
+    pub backlog_age: std::time::Duration,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Detector {
+    thresholds: Option<SlowConsumerThresholds>,
+    backlog: Option<Backlog>,
+    stats_to_report: Option<SlowConsumerStats>,
+    clock: super::clock::SharedClock,
+}
+
+#[derive(Debug)]
+struct Backlog {
+    started: std::time::Duration,
+    depth: usize,
+    reported: bool,
+}
+
+impl Detector {
+    pub(super) fn set_thresholds(&mut self, thresholds: Option<SlowConsumerThresholds>) {
+        self.thresholds = thresholds;
+        self.new_connection();
+    }
+
+    pub(super) fn set_clock(&mut self, clock: super::clock::SharedClock) {
+        self.clock = clock;
+    }
+
+    /// Tracks the backlog using the result of polling a [`crate::io::PacketStream`]
+    pub(super) fn poll_received<T>(&mut self, poll: std::task::Poll<Option<T>>) -> std::task::Poll<Option<T>> {
+        if let Some(thresholds) = self.thresholds {
+            if let std::task::Poll::Ready(Some(_)) = &poll {
+                self.packet_received(thresholds);
+            } else {
+                self.backlog = None;
+            }
+        }
+
+        poll
+    }
+
+    fn packet_received(&mut self, thresholds: SlowConsumerThresholds) {
+        let now = self.clock.now();
+        let backlog = self.backlog.get_or_insert(Backlog {
+            started: now,
+            depth: 0,
+            reported: false,
+        });
+        backlog.depth += 1;
+
+        let backlog_age = now.saturating_sub(backlog.started);
+        if !backlog.reported && (backlog.depth >= thresholds.backlog_depth || backlog_age >= thresholds.backlog_age) {
+            log::warn!(
+                "application is not keeping up with received packets: {} packets read over {:?} without the connection running dry",
+                backlog.depth,
+                backlog_age,
+            );
+            backlog.reported = true;
+            self.stats_to_report = Some(SlowConsumerStats {
+                backlog_depth: backlog.depth,
+                backlog_age,
+            });
+        }
+    }
+
+    pub(super) fn take_stats_to_report(&mut self) -> Option<SlowConsumerStats> {
+        self.stats_to_report.take()
+    }
+
+    pub(super) fn new_connection(&mut self) {
+        self.backlog = None;
+        self.stats_to_report = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn reports_once_per_backlog() {
+        let mut detector = super::Detector::default();
+        detector.set_thresholds(Some(super::SlowConsumerThresholds {
+            backlog_depth: 3,
+            backlog_age: std::time::Duration::from_secs(60),
+        }));
+
+        let mut receive = |poll| {
+            let _ = detector.poll_received(poll);
+            detector.take_stats_to_report().map(|stats| stats.backlog_depth)
+        };
+
+        assert_eq!(receive(std::task::Poll::Ready(Some(()))), None);
+        assert_eq!(receive(std::task::Poll::Ready(Some(()))), None);
+        assert_eq!(receive(std::task::Poll::Ready(Some(()))), Some(3));
+        assert_eq!(receive(std::task::Poll::Ready(Some(()))), None);
+
+        // The connection running dry ends the backlog
+        assert_eq!(receive(std::task::Poll::Pending), None);
+        assert_eq!(receive(std::task::Poll::Ready(Some(()))), None);
+        assert_eq!(receive(std::task::Poll::Ready(Some(()))), None);
+        assert_eq!(receive(std::task::Poll::Ready(Some(()))), Some(3));
+    }
+}
@@ -2,12 +2,55 @@
 pub(super) struct State {
     subscriptions: std::collections::BTreeMap<crate::proto::ByteStr, crate::proto::QoS>,
 
-    subscriptions_updated_send: futures_channel::mpsc::Sender<SubscriptionUpdate>,
-    subscriptions_updated_recv: futures_channel::mpsc::Receiver<SubscriptionUpdate>,
+    /// Topic filters with a SUBSCRIBE in flight that was requested with [`super::Client::update_subscription`]
+    qos_updates: std::collections::BTreeSet<crate::proto::ByteStr>,
+
+    /// Subscriptions that were requested with [`super::Client::subscribe_with_lease`]
+    leases: std::collections::BTreeMap<crate::proto::ByteStr, Lease>,
+
+    /// Topic filters with a SUBSCRIBE in flight that refreshes their lease
+    refreshes: std::collections::BTreeSet<crate::proto::ByteStr>,
+
+    /// Fires at the time of the earliest lease refresh
+    refresh_timer: Option<(std::time::Duration, super::clock::Timer)>,
+
+    /// The user properties and subscription identifiers that subscriptions were requested with, sent again with every SUBSCRIBE for the same topic filter
+    options: std::collections::BTreeMap<crate::proto::ByteStr, SubscribeOptions>,
+
+    clock: super::clock::SharedClock,
+
+    topic_limits: super::TopicLimits,
+
+    subscriptions_updated_send: super::queued::Sender<SubscriptionUpdate>,
+    subscriptions_updated_recv: super::queued::Receiver<SubscriptionUpdate>,
 
     subscription_updates_waiting_to_be_sent: std::collections::VecDeque<SubscriptionUpdate>,
     subscription_updates_waiting_to_be_acked:
         std::collections::VecDeque<(crate::proto::PacketIdentifier, BatchedSubscriptionUpdate)>,
+
+    unsolicited_publication_policy: UnsolicitedPublicationPolicy,
+    unsolicited_publications: u64,
+
+    #[allow(clippy::doc_markdown)]
+    /// The highest QoS that the server accepts on the current connection. Subscriptions granted with it are not downgrades.
+    maximum_qos: crate::proto::QoS,
+}
+
+#[derive(Clone, Debug, Default)]
+struct SubscribeOptions {
+    user_properties: Vec<(crate::proto::ByteStr, crate::proto::ByteStr)>,
+    subscription_identifier: Option<u32>,
+    no_local: bool,
+    retain_as_published: bool,
+    retain_handling: crate::proto::RetainHandling,
+}
+
+#[derive(Debug)]
+struct Lease {
+    lease: std::time::Duration,
+
+    /// When to refresh the subscription next, or `None` if the server has not acked it since it was last refreshed
+    refresh_at: Option<std::time::Duration>,
 }
 
 impl State {
@@ -17,13 +60,8 @@ impl State {
 
         packet: &mut Option<crate::proto::Packet>,
         packet_identifiers: &mut super::PacketIdentifiers,
-    ) -> Result<
-        (
-            Vec<crate::proto::Packet>,
-            Vec<super::SubscriptionUpdateEvent>,
-        ),
-        super::Error,
-    > {
+        packets_waiting_to_be_sent: &mut std::collections::VecDeque<crate::proto::Packet>,
+    ) -> Result<Vec<super::SubscriptionUpdateEvent>, super::Error> {
         use futures_core::Stream;
 
         let mut subscription_updates = vec![];
@@ -36,12 +74,12 @@ impl State {
                 match self.subscription_updates_waiting_to_be_acked.pop_front() {
                     Some((
                         packet_identifier_waiting_to_be_acked,
-                        BatchedSubscriptionUpdate::Subscribe(subscribe_to),
+                        BatchedSubscriptionUpdate::Subscribe { subscribe_to, resubscription },
                     )) => {
                         if packet_identifier != packet_identifier_waiting_to_be_acked {
                             self.subscription_updates_waiting_to_be_acked.push_front((
                                 packet_identifier_waiting_to_be_acked,
-                                BatchedSubscriptionUpdate::Subscribe(subscribe_to),
+                                BatchedSubscriptionUpdate::Subscribe { subscribe_to, resubscription },
                             ));
                             return Err(super::Error::UnexpectedSubAck(
                                 packet_identifier,
@@ -55,7 +93,7 @@ impl State {
                             let expected = subscribe_to.len();
                             self.subscription_updates_waiting_to_be_acked.push_front((
                                 packet_identifier_waiting_to_be_acked,
-                                BatchedSubscriptionUpdate::Subscribe(subscribe_to),
+                                BatchedSubscriptionUpdate::Subscribe { subscribe_to, resubscription },
                             ));
                             return Err(super::Error::SubAckDoesNotContainEnoughQoS(
                                 packet_identifier,
@@ -76,27 +114,69 @@ impl State {
                             crate::proto::SubscribeTo {
                                 topic_filter,
                                 qos: expected_qos,
+                                user_properties,
+                                subscription_identifier,
+                                no_local,
+                                retain_as_published,
+                                retain_handling,
                             },
                             qos,
                         ) in subscribe_to.into_iter().zip(qos)
                         {
+                            // The server may grant any QoS for a QoS update, so a downgrade is reported rather than treated as an error.
+                            let qos_update = !resubscription && self.qos_updates.remove(&topic_filter);
+                            let refresh = !resubscription && self.refreshes.remove(&topic_filter);
+
                             match qos {
                                 crate::proto::SubAckQos::Success(actual_qos) => {
-                                    if actual_qos >= expected_qos {
-                                        log::debug!(
-                                            "Subscribed to {} with qos {:?}",
-                                            topic_filter,
-                                            actual_qos
-                                        );
-                                        self.subscriptions.insert(topic_filter.clone(), actual_qos);
-                                        subscription_updates.push(
-                                            super::SubscriptionUpdateEvent::Subscribe(
-                                                crate::proto::SubscribeTo {
+                                    // The server cannot grant more than its maximum QoS, so that is not treated as a downgrade.
+                                    // The expected QoS is kept, to ask for it again from servers that allow it.
+                                    let capped_by_maximum_qos = actual_qos < expected_qos && actual_qos >= self.maximum_qos;
+
+                                    if actual_qos >= expected_qos || qos_update || capped_by_maximum_qos {
+                                        let subscribed_qos =
+                                            if capped_by_maximum_qos {
+                                                log::warn!(
+                                                    "Subscribed to {} with qos {:?} instead of {:?} because that is the maximum QoS of the server",
+                                                    topic_filter,
+                                                    actual_qos,
+                                                    expected_qos
+                                                );
+                                                expected_qos
+                                            }
+                                            else {
+                                                log::debug!(
+                                                    "Subscribed to {} with qos {:?}",
                                                     topic_filter,
-                                                    qos: actual_qos,
-                                                },
-                                            ),
-                                        );
+                                                    actual_qos
+                                                );
+                                                actual_qos
+                                            };
+                                        self.subscriptions.insert(topic_filter.clone(), subscribed_qos);
+                                        if let Some(lease) = self.leases.get_mut(&topic_filter) {
+                                            lease.refresh_at = Some(self.clock.now() + lease.lease / 2);
+                                        }
+
+                                        let subscribe_to = crate::proto::SubscribeTo {
+                                            topic_filter,
+                                            qos: actual_qos,
+                                            user_properties,
+                                            subscription_identifier,
+                                            no_local,
+                                            retain_as_published,
+                                            retain_handling,
+                                        };
+                                        if refresh {
+                                            log::debug!("Refreshed lease of subscription to {}", subscribe_to.topic_filter);
+                                        } else {
+                                            subscription_updates.push(if resubscription {
+                                                super::SubscriptionUpdateEvent::Resubscribe(subscribe_to)
+                                            } else if qos_update {
+                                                super::SubscriptionUpdateEvent::QoSUpdated(subscribe_to)
+                                            } else {
+                                                super::SubscriptionUpdateEvent::Subscribe(subscribe_to)
+                                            });
+                                        }
                                     } else {
                                         if err.is_none() {
                                             err = Some(super::Error::SubscriptionDowngraded(
@@ -112,14 +192,28 @@ impl State {
 
                                 crate::proto::SubAckQos::Failure => {
                                     // Return an event for rejected subscription instead of retrying to send the subscription
-                                    subscription_updates.push(
-                                        super::SubscriptionUpdateEvent::RejectedByServer(
-                                            crate::proto::SubscribeTo {
-                                                topic_filter,
-                                                qos: expected_qos,
-                                            },
-                                        ),
-                                    );
+                                    self.leases.remove(&topic_filter);
+                                    self.options.remove(&topic_filter);
+                                    if refresh {
+                                        self.subscriptions.remove(&topic_filter);
+                                    }
+
+                                    let subscribe_to = crate::proto::SubscribeTo {
+                                        topic_filter,
+                                        qos: expected_qos,
+                                        user_properties,
+                                        subscription_identifier,
+                                        no_local,
+                                        retain_as_published,
+                                        retain_handling,
+                                    };
+                                    subscription_updates.push(if refresh {
+                                        super::SubscriptionUpdateEvent::RefreshRejectedByServer(subscribe_to)
+                                    } else if resubscription {
+                                        super::SubscriptionUpdateEvent::ResubscribeRejectedByServer(subscribe_to)
+                                    } else {
+                                        super::SubscriptionUpdateEvent::RejectedByServer(subscribe_to)
+                                    });
                                 }
                             }
                         }
@@ -152,7 +246,7 @@ impl State {
                 }
             }
 
-            Some(crate::proto::Packet::UnsubAck(crate::proto::UnsubAck { packet_identifier })) => {
+            Some(crate::proto::Packet::UnsubAck(crate::proto::UnsubAck { packet_identifier, .. })) => {
                 match self.subscription_updates_waiting_to_be_acked.pop_front() {
                     Some((
                         packet_identifier_waiting_to_be_acked,
@@ -183,7 +277,7 @@ impl State {
 
                     Some((
                         packet_identifier_waiting_to_be_acked,
-                        subscribe @ BatchedSubscriptionUpdate::Subscribe(_),
+                        subscribe @ BatchedSubscriptionUpdate::Subscribe { .. },
                     )) => {
                         self.subscription_updates_waiting_to_be_acked
                             .push_front((packet_identifier_waiting_to_be_acked, subscribe));
@@ -214,7 +308,9 @@ impl State {
                 .push_back(subscription_to_update);
         }
 
-        let mut packets_waiting_to_be_sent = vec![];
+        self.poll_leases(cx);
+
+        let num_packets_waiting_to_be_sent = packets_waiting_to_be_sent.len();
 
         if !self.subscription_updates_waiting_to_be_sent.is_empty() {
             // Rather than send individual SUBSCRIBE and UNSUBSCRIBE packets for each update, we can send multiple updates in the same packet.
@@ -239,13 +335,54 @@ impl State {
             {
                 match subscription_update {
                     SubscriptionUpdate::Subscribe(subscribe_to) => {
+                        self.refreshes.remove(&subscribe_to.topic_filter);
+                        self.options.insert(subscribe_to.topic_filter.clone(), SubscribeOptions {
+                            user_properties: subscribe_to.user_properties,
+                            subscription_identifier: subscribe_to.subscription_identifier,
+                            no_local: subscribe_to.no_local,
+                            retain_as_published: subscribe_to.retain_as_published,
+                            retain_handling: subscribe_to.retain_handling,
+                        });
+                        target_unsubscriptions.remove(&subscribe_to.topic_filter);
+                        target_subscriptions.insert(
+                            subscribe_to.topic_filter.clone(),
+                            (subscribe_to.qos, false),
+                        );
+                    }
+                    SubscriptionUpdate::SubscribeWithLease(subscribe_to, lease) => {
+                        self.refreshes.remove(&subscribe_to.topic_filter);
+                        self.leases.insert(subscribe_to.topic_filter.clone(), Lease { lease, refresh_at: None });
+                        self.options.insert(subscribe_to.topic_filter.clone(), SubscribeOptions {
+                            user_properties: subscribe_to.user_properties,
+                            subscription_identifier: subscribe_to.subscription_identifier,
+                            no_local: subscribe_to.no_local,
+                            retain_as_published: subscribe_to.retain_as_published,
+                            retain_handling: subscribe_to.retain_handling,
+                        });
+                        target_unsubscriptions.remove(&subscribe_to.topic_filter);
+                        target_subscriptions.insert(
+                            subscribe_to.topic_filter.clone(),
+                            (subscribe_to.qos, false),
+                        );
+                    }
+                    SubscriptionUpdate::UpdateQoS(subscribe_to) => {
+                        self.refreshes.remove(&subscribe_to.topic_filter);
                         target_unsubscriptions.remove(&subscribe_to.topic_filter);
                         target_subscriptions.insert(
                             subscribe_to.topic_filter.clone(),
-                            subscribe_to.qos,
+                            (subscribe_to.qos, true),
                         );
                     }
+                    SubscriptionUpdate::Refresh(subscribe_to) => {
+                        // Any other update to the subscription in the same batch refreshes it too
+                        if let std::collections::btree_map::Entry::Vacant(entry) = target_subscriptions.entry(subscribe_to.topic_filter.clone()) {
+                            self.refreshes.insert(subscribe_to.topic_filter);
+                            entry.insert((subscribe_to.qos, false));
+                        }
+                    }
                     SubscriptionUpdate::Unsubscribe(unsubscribe_from) => {
+                        self.leases.remove(&unsubscribe_from);
+                        self.options.remove(&unsubscribe_from);
                         if target_subscriptions.remove(&unsubscribe_from).is_none() {
                             target_unsubscriptions.insert(unsubscribe_from, true);
                         }
@@ -253,14 +390,18 @@ impl State {
                 };
             }
 
-            let mut pending_subscriptions: std::collections::VecDeque<_> = Default::default();
-            for (topic_filter, &qos) in &target_subscriptions {
-                pending_subscriptions.push_back(crate::proto::SubscribeTo {
-                    topic_filter: topic_filter.clone(),
-                    qos,
-                });
+            let mut pending_subscriptions = vec![];
+            for (topic_filter, &(qos, qos_update)) in &target_subscriptions {
+                if qos_update {
+                    self.qos_updates.insert(topic_filter.clone());
+                }
+                pending_subscriptions.push(subscribe_to(&self.options, topic_filter.clone(), qos));
             }
 
+            // Group the subscriptions by subscription identifier, since a SUBSCRIBE can only carry one
+            pending_subscriptions.sort_by_key(|subscribe_to| subscribe_to.subscription_identifier);
+            let mut pending_subscriptions: std::collections::VecDeque<_> = pending_subscriptions.into();
+
             let mut pending_unsubscriptions: std::collections::VecDeque<_> = Default::default();
             for topic_filter in target_unsubscriptions.keys() {
                 pending_unsubscriptions.push_back(topic_filter.clone());
@@ -281,6 +422,14 @@ impl State {
                         };
 
                         while let Some(subscribe_to) = pending_subscriptions.pop_front() {
+                            if matches!(
+                                packet.subscribe_to.first(),
+                                Some(first) if first.subscription_identifier != subscribe_to.subscription_identifier
+                            ) {
+                                pending_subscriptions.push_front(subscribe_to);
+                                break;
+                            }
+
                             match try_append_subscription(&mut packet, subscribe_to) {
                                 Ok(()) => (),
                                 Err((subscribe_to, _)) => {
@@ -302,10 +451,13 @@ impl State {
 
                         self.subscription_updates_waiting_to_be_acked.push_back((
                             packet_identifier,
-                            BatchedSubscriptionUpdate::Subscribe(packet.subscribe_to.clone()),
+                            BatchedSubscriptionUpdate::Subscribe {
+                                subscribe_to: packet.subscribe_to.clone(),
+                                resubscription: false,
+                            },
                         ));
 
-                        packets_waiting_to_be_sent.push(crate::proto::Packet::Subscribe(packet));
+                        packets_waiting_to_be_sent.push_back(crate::proto::Packet::Subscribe(packet));
                     }
 
                     Err(err_) => {
@@ -352,7 +504,7 @@ impl State {
                             BatchedSubscriptionUpdate::Unsubscribe(packet.unsubscribe_from.clone()),
                         ));
 
-                        packets_waiting_to_be_sent.push(crate::proto::Packet::Unsubscribe(packet));
+                        packets_waiting_to_be_sent.push_back(crate::proto::Packet::Unsubscribe(packet));
                     }
 
                     Err(err_) => {
@@ -367,26 +519,34 @@ impl State {
                 };
             }
 
-            if packets_waiting_to_be_sent.is_empty() {
+            if packets_waiting_to_be_sent.len() == num_packets_waiting_to_be_sent {
                 if let Some(err) = err {
                     return Err(err);
                 }
             }
         }
 
-        Ok((packets_waiting_to_be_sent, subscription_updates))
+        Ok(subscription_updates)
     }
 
     pub(super) fn new_connection(
         &mut self,
         reset_session: bool,
+        maximum_qos: crate::proto::QoS,
         packet_identifiers: &mut super::PacketIdentifiers,
     ) -> impl Iterator<Item = crate::proto::Packet> {
+        self.maximum_qos = maximum_qos;
+
         if reset_session {
+            // The subscriptions that the server had acked are resubscribed in their own SUBSCRIBE packet,
+            // so that their results can be reported as resubscriptions rather than as new subscriptions.
+            #[allow(clippy::mutable_key_type)]
+            let mut resubscriptions = std::mem::take(&mut self.subscriptions);
             #[allow(clippy::mutable_key_type)]
-            let mut subscriptions = std::mem::take(&mut self.subscriptions);
+            let mut subscriptions = std::collections::BTreeMap::new();
             let subscription_updates_waiting_to_be_acked =
                 std::mem::take(&mut self.subscription_updates_waiting_to_be_acked);
+            self.refreshes.clear();
 
             // Apply all pending (ie unacked) changes to the set of subscriptions, in order that they were original requested
             for (packet_identifier, subscription_update_waiting_to_be_acked) in
@@ -395,47 +555,64 @@ impl State {
                 packet_identifiers.discard(packet_identifier);
 
                 match subscription_update_waiting_to_be_acked {
-                    BatchedSubscriptionUpdate::Subscribe(subscribe_to) => {
-                        for crate::proto::SubscribeTo { topic_filter, qos } in subscribe_to {
-                            subscriptions.insert(topic_filter, qos);
+                    BatchedSubscriptionUpdate::Subscribe { subscribe_to, resubscription } => {
+                        for crate::proto::SubscribeTo { topic_filter, qos, .. } in subscribe_to {
+                            if resubscription {
+                                resubscriptions.insert(topic_filter, qos);
+                            }
+                            else {
+                                resubscriptions.remove(&topic_filter);
+                                subscriptions.insert(topic_filter, qos);
+                            }
                         }
                     }
 
                     BatchedSubscriptionUpdate::Unsubscribe(unsubscribe_from) => {
                         for topic_filter in unsubscribe_from {
+                            resubscriptions.remove(&topic_filter);
                             subscriptions.remove(&topic_filter);
                         }
                     }
                 }
             }
 
-            // Generate a SUBSCRIBE packet for the final set of subscriptions
-            let mut subscriptions_waiting_to_be_acked: Vec<_> = subscriptions
-                .into_iter()
-                .map(|(topic_filter, qos)| crate::proto::SubscribeTo { topic_filter, qos })
-                .collect();
-            subscriptions_waiting_to_be_acked.sort_by(|subscribe_to1, subscribe_to2| {
-                subscribe_to1.topic_filter.cmp(&subscribe_to2.topic_filter)
-            });
+            // Generate a SUBSCRIBE packet each for the final sets of resubscriptions and subscriptions,
+            // split further by subscription identifier since a SUBSCRIBE can only carry one
+            let mut packets = vec![];
+            for (subscriptions, resubscription) in std::iter::once((resubscriptions, true)).chain(std::iter::once((subscriptions, false))) {
+                // BTreeMap iterates in order of topic filter, and the sort is stable, so each packet's subscriptions are sorted
+                let mut pending_subscriptions: Vec<_> = subscriptions
+                    .into_iter()
+                    .map(|(topic_filter, qos)| subscribe_to(&self.options, topic_filter, qos))
+                    .collect();
+                pending_subscriptions.sort_by_key(|subscribe_to| subscribe_to.subscription_identifier);
+
+                while let Some(first) = pending_subscriptions.first() {
+                    let subscription_identifier = first.subscription_identifier;
+                    let len = pending_subscriptions.iter()
+                        .take_while(|subscribe_to| subscribe_to.subscription_identifier == subscription_identifier)
+                        .count();
+                    let subscribe_to: Vec<_> = pending_subscriptions.drain(..len).collect();
+
+                    let packet_identifier = packet_identifiers
+                        .reserve()
+                        .expect("reset session should have available packet identifiers");
+                    self.subscription_updates_waiting_to_be_acked.push_back((
+                        packet_identifier,
+                        BatchedSubscriptionUpdate::Subscribe {
+                            subscribe_to: subscribe_to.clone(),
+                            resubscription,
+                        },
+                    ));
 
-            if subscriptions_waiting_to_be_acked.is_empty() {
-                NewConnectionIter::Empty
-            } else {
-                let packet_identifier = packet_identifiers
-                    .reserve()
-                    .expect("reset session should have available packet identifiers");
-                self.subscription_updates_waiting_to_be_acked.push_back((
-                    packet_identifier,
-                    BatchedSubscriptionUpdate::Subscribe(subscriptions_waiting_to_be_acked.clone()),
-                ));
-
-                NewConnectionIter::Single(std::iter::once(crate::proto::Packet::Subscribe(
-                    crate::proto::Subscribe {
+                    packets.push(crate::proto::Packet::Subscribe(crate::proto::Subscribe {
                         packet_identifier,
-                        subscribe_to: subscriptions_waiting_to_be_acked,
-                    },
-                )))
+                        subscribe_to,
+                    }));
+                }
             }
+
+            packets.into_iter()
         } else {
             // Re-create all pending (ie unacked) changes to the set of subscriptions
             let unacked_packets: Vec<_> = self
@@ -443,7 +620,7 @@ impl State {
                 .iter()
                 .map(
                     |(packet_identifier, subscription_update)| match subscription_update {
-                        BatchedSubscriptionUpdate::Subscribe(subscribe_to) => {
+                        BatchedSubscriptionUpdate::Subscribe { subscribe_to, .. } => {
                             crate::proto::Packet::Subscribe(crate::proto::Subscribe {
                                 packet_identifier: *packet_identifier,
                                 subscribe_to: subscribe_to.clone(),
@@ -460,7 +637,7 @@ impl State {
                 )
                 .collect();
 
-            NewConnectionIter::Multiple(unacked_packets.into_iter())
+            unacked_packets.into_iter()
         }
     }
 
@@ -468,7 +645,28 @@ impl State {
         &mut self,
         subscribe_to: crate::proto::SubscribeTo,
     ) -> Result<(), UpdateSubscriptionError> {
-        let subscription_update = SubscriptionUpdate::subscribe(subscribe_to)?;
+        let subscription_update = SubscriptionUpdate::subscribe(subscribe_to, self.topic_limits)?;
+        self.subscription_updates_waiting_to_be_sent
+            .push_back(subscription_update);
+        Ok(())
+    }
+
+    pub(super) fn subscribe_with_lease(
+        &mut self,
+        subscribe_to: crate::proto::SubscribeTo,
+        lease: std::time::Duration,
+    ) -> Result<(), UpdateSubscriptionError> {
+        let subscription_update = SubscriptionUpdate::subscribe_with_lease(subscribe_to, lease, self.topic_limits)?;
+        self.subscription_updates_waiting_to_be_sent
+            .push_back(subscription_update);
+        Ok(())
+    }
+
+    pub(super) fn update_qos(
+        &mut self,
+        subscribe_to: crate::proto::SubscribeTo,
+    ) -> Result<(), UpdateSubscriptionError> {
+        let subscription_update = SubscriptionUpdate::update_qos(subscribe_to, self.topic_limits)?;
         self.subscription_updates_waiting_to_be_sent
             .push_back(subscription_update);
         Ok(())
@@ -484,24 +682,153 @@ impl State {
         Ok(())
     }
 
-    pub(super) fn update_subscription_handle(&self) -> UpdateSubscriptionHandle {
-        UpdateSubscriptionHandle(self.subscriptions_updated_send.clone())
+    pub(super) fn update_subscription_handle(&self, closed: super::Closed) -> UpdateSubscriptionHandle {
+        UpdateSubscriptionHandle(self.subscriptions_updated_send.clone(), closed, self.topic_limits)
+    }
+
+    pub(super) fn set_topic_limits(&mut self, topic_limits: super::TopicLimits) {
+        self.topic_limits = topic_limits;
+    }
+
+    pub(super) fn set_clock(&mut self, clock: super::clock::SharedClock) {
+        self.clock = clock;
+        self.refresh_timer = None;
+    }
+
+    /// Queues a refresh for the leased subscriptions that are due for one
+    fn poll_leases(&mut self, cx: &mut std::task::Context<'_>) {
+        use futures_util::FutureExt;
+
+        match self.leases.values().filter_map(|lease| lease.refresh_at).min() {
+            Some(next_refresh) if !matches!(&self.refresh_timer, Some((deadline, _)) if *deadline == next_refresh) =>
+                self.refresh_timer = Some((next_refresh, super::clock::Timer(self.clock.sleep_until(next_refresh)))),
+            Some(_) => (),
+            None => self.refresh_timer = None,
+        }
+
+        let refresh_due = match &mut self.refresh_timer {
+            Some((_, refresh_timer)) => refresh_timer.0.poll_unpin(cx).is_ready(),
+            None => false,
+        };
+        if !refresh_due {
+            return;
+        }
+
+        self.refresh_timer = None;
+
+        let now = self.clock.now();
+        for (topic_filter, lease) in &mut self.leases {
+            if matches!(lease.refresh_at, Some(refresh_at) if refresh_at <= now) {
+                lease.refresh_at = None;
+
+                if let Some(&qos) = self.subscriptions.get(topic_filter) {
+                    log::debug!("Refreshing lease of subscription to {}", topic_filter);
+                    self.subscription_updates_waiting_to_be_sent.push_back(SubscriptionUpdate::Refresh(
+                        subscribe_to(&self.options, topic_filter.clone(), qos),
+                    ));
+                }
+            }
+        }
+    }
+
+    pub(super) fn set_unsolicited_publication_policy(&mut self, unsolicited_publication_policy: UnsolicitedPublicationPolicy) {
+        self.unsolicited_publication_policy = unsolicited_publication_policy;
+    }
+
+    pub(super) fn unsolicited_publications(&self) -> u64 {
+        self.unsolicited_publications
+    }
+
+    /// Checks the given received publication against the subscriptions, according to the [`UnsolicitedPublicationPolicy`].
+    ///
+    /// Returns `Ok` if the publication should be returned to the application as usual, `Err` if it should be reported as unsolicited,
+    /// or `None` if it was dropped.
+    pub(super) fn check_received(
+        &mut self,
+        mut publication: crate::ReceivedPublication,
+    ) -> Option<Result<crate::ReceivedPublication, crate::ReceivedPublication>> {
+        if self.unsolicited_publication_policy == UnsolicitedPublicationPolicy::Deliver || self.is_subscribed(publication.topic_name.as_ref()) {
+            return Some(Ok(publication));
+        }
+
+        log::warn!("received publication to {:?} which does not match any subscription", publication.topic_name);
+        self.unsolicited_publications += 1;
+
+        match self.unsolicited_publication_policy {
+            UnsolicitedPublicationPolicy::Deliver => unreachable!(),
+
+            UnsolicitedPublicationPolicy::Drop => {
+                publication.ack();
+                None
+            },
+
+            UnsolicitedPublicationPolicy::Report => Some(Err(publication)),
+        }
+    }
+
+    /// Whether the given topic matches a subscription. Subscriptions that have not been acked yet count,
+    /// since the server can send publications for them before it acks them, and so do subscriptions that are being unsubscribed from.
+    fn is_subscribed(&self, topic_name: &str) -> bool {
+        let subscriptions = self.subscriptions.keys();
+
+        let subscriptions_waiting_to_be_acked =
+            self.subscription_updates_waiting_to_be_acked.iter()
+            .flat_map(|(_, subscription_update)| -> Box<dyn Iterator<Item = &crate::proto::ByteStr>> {
+                match subscription_update {
+                    BatchedSubscriptionUpdate::Subscribe { subscribe_to, .. } =>
+                        Box::new(subscribe_to.iter().map(|subscribe_to| &subscribe_to.topic_filter)),
+                    BatchedSubscriptionUpdate::Unsubscribe(unsubscribe_from) => Box::new(unsubscribe_from.iter()),
+                }
+            });
+
+        subscriptions.chain(subscriptions_waiting_to_be_acked)
+            .any(|topic_filter| crate::proto::topic_filter_matches(topic_filter.as_ref(), topic_name))
+    }
+}
+
+/// Builds the subscription to a topic filter with the user properties, subscription identifier and subscription options it was requested with
+fn subscribe_to(
+    options: &std::collections::BTreeMap<crate::proto::ByteStr, SubscribeOptions>,
+    topic_filter: crate::proto::ByteStr,
+    qos: crate::proto::QoS,
+) -> crate::proto::SubscribeTo {
+    let SubscribeOptions { user_properties, subscription_identifier, no_local, retain_as_published, retain_handling } =
+        options.get(&topic_filter).cloned().unwrap_or_default();
+    crate::proto::SubscribeTo {
+        topic_filter,
+        qos,
+        user_properties,
+        subscription_identifier,
+        no_local,
+        retain_as_published,
+        retain_handling,
     }
 }
 
 impl Default for State {
     fn default() -> Self {
         let (subscriptions_updated_send, subscriptions_updated_recv) =
-            futures_channel::mpsc::channel(0);
+            super::queued::channel(0);
 
         State {
             subscriptions: Default::default(),
+            qos_updates: Default::default(),
+            leases: Default::default(),
+            refreshes: Default::default(),
+            refresh_timer: None,
+            options: Default::default(),
+            clock: Default::default(),
+            topic_limits: Default::default(),
 
             subscriptions_updated_send,
             subscriptions_updated_recv,
 
             subscription_updates_waiting_to_be_sent: Default::default(),
             subscription_updates_waiting_to_be_acked: Default::default(),
+
+            unsolicited_publication_policy: Default::default(),
+            unsolicited_publications: 0,
+            maximum_qos: crate::proto::QoS::ExactlyOnce,
         }
     }
 }
@@ -509,33 +836,33 @@ impl Default for State {
 #[derive(Clone, Debug)]
 pub(super) enum SubscriptionUpdate {
     Subscribe(crate::proto::SubscribeTo),
+    SubscribeWithLease(crate::proto::SubscribeTo, std::time::Duration),
+    UpdateQoS(crate::proto::SubscribeTo),
+    Refresh(crate::proto::SubscribeTo),
     Unsubscribe(crate::proto::ByteStr),
 }
 
 impl SubscriptionUpdate {
     pub(super) fn subscribe(
         subscribe_to: crate::proto::SubscribeTo,
+        topic_limits: super::TopicLimits,
     ) -> Result<Self, UpdateSubscriptionError> {
-        let mut packet = crate::proto::Subscribe {
-            packet_identifier: crate::proto::PacketIdentifier::max_value(),
-            subscribe_to: vec![],
-        };
+        Ok(SubscriptionUpdate::Subscribe(validate_subscription(subscribe_to, topic_limits)?))
+    }
 
-        let subscribe_to = match try_append_subscription(&mut packet, subscribe_to) {
-            Ok(()) => packet
-                .subscribe_to
-                .into_iter()
-                .next()
-                .expect("just inserted element above, so it must exist"),
-            Err((subscribe_to, err)) => {
-                return Err(UpdateSubscriptionError::EncodePacket(
-                    subscribe_to.topic_filter,
-                    err,
-                ))
-            }
-        };
+    pub(super) fn subscribe_with_lease(
+        subscribe_to: crate::proto::SubscribeTo,
+        lease: std::time::Duration,
+        topic_limits: super::TopicLimits,
+    ) -> Result<Self, UpdateSubscriptionError> {
+        Ok(SubscriptionUpdate::SubscribeWithLease(validate_subscription(subscribe_to, topic_limits)?, lease))
+    }
 
-        Ok(SubscriptionUpdate::Subscribe(subscribe_to))
+    pub(super) fn update_qos(
+        subscribe_to: crate::proto::SubscribeTo,
+        topic_limits: super::TopicLimits,
+    ) -> Result<Self, UpdateSubscriptionError> {
+        Ok(SubscriptionUpdate::UpdateQoS(validate_subscription(subscribe_to, topic_limits)?))
     }
 
     pub(super) fn unsubscribe(unsubscribe_from: crate::proto::ByteStr) -> Result<Self, UpdateSubscriptionError> {
@@ -559,36 +886,74 @@ impl SubscriptionUpdate {
     }
 }
 
-#[derive(Debug)]
-enum BatchedSubscriptionUpdate {
-    Subscribe(Vec<crate::proto::SubscribeTo>),
-    Unsubscribe(Vec<crate::proto::ByteStr>),
-}
+/// Checks that the given subscription is a well-formed shared subscription if it is one, has a valid subscription identifier if any,
+/// is within the topic limits, and fits in a SUBSCRIBE packet by itself
+fn validate_subscription(
+    subscribe_to: crate::proto::SubscribeTo,
+    topic_limits: super::TopicLimits,
+) -> Result<crate::proto::SubscribeTo, UpdateSubscriptionError> {
+    if let Some((share_name, topic_filter)) = crate::proto::split_shared_subscription(subscribe_to.topic_filter.as_ref()) {
+        if share_name.is_empty() || share_name.contains(['+', '#']) || topic_filter.is_empty() {
+            return Err(UpdateSubscriptionError::InvalidSharedSubscription(subscribe_to.topic_filter));
+        }
+    }
 
-#[derive(Debug)]
-enum NewConnectionIter {
-    Empty,
-    Single(std::iter::Once<crate::proto::Packet>),
-    Multiple(std::vec::IntoIter<crate::proto::Packet>),
-}
+    // Subscription identifiers are variable byte integers, and 0 is not allowed
+    if let Some(subscription_identifier) = subscribe_to.subscription_identifier {
+        if !(1..=268_435_455).contains(&subscription_identifier) {
+            return Err(UpdateSubscriptionError::InvalidSubscriptionIdentifier(subscribe_to.topic_filter, subscription_identifier));
+        }
+    }
 
-impl Iterator for NewConnectionIter {
-    type Item = crate::proto::Packet;
+    if let Err(err) = topic_limits.check(subscribe_to.topic_filter.as_ref()) {
+        return Err(UpdateSubscriptionError::TopicLimitExceeded(subscribe_to.topic_filter, err));
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self {
-            NewConnectionIter::Empty => None,
-            NewConnectionIter::Single(packet) => packet.next(),
-            NewConnectionIter::Multiple(packets) => packets.next(),
+    let mut packet = crate::proto::Subscribe {
+        packet_identifier: crate::proto::PacketIdentifier::max_value(),
+        subscribe_to: vec![],
+    };
+
+    let subscribe_to = match try_append_subscription(&mut packet, subscribe_to) {
+        Ok(()) => packet
+            .subscribe_to
+            .into_iter()
+            .next()
+            .expect("just inserted element above, so it must exist"),
+        Err((subscribe_to, err)) => {
+            return Err(UpdateSubscriptionError::EncodePacket(
+                subscribe_to.topic_filter,
+                err,
+            ))
         }
-    }
+    };
+
+    Ok(subscribe_to)
+}
+
+#[derive(Debug)]
+enum BatchedSubscriptionUpdate {
+    Subscribe {
+        subscribe_to: Vec<crate::proto::SubscribeTo>,
+
+        /// Whether these are subscriptions the server had already acked, being resubscribed after the session was reset
+        resubscription: bool,
+    },
+    Unsubscribe(Vec<crate::proto::ByteStr>),
 }
 
 /// Used to update subscriptions
 #[derive(Clone, Debug)]
-pub struct UpdateSubscriptionHandle(futures_channel::mpsc::Sender<SubscriptionUpdate>);
+pub struct UpdateSubscriptionHandle(super::queued::Sender<SubscriptionUpdate>, super::Closed, super::TopicLimits);
 
 impl UpdateSubscriptionHandle {
+    /// Whether the client has shut down or was dropped, in which case updating subscriptions fails with [`UpdateSubscriptionError::ClientClosed`].
+    ///
+    /// This becomes `false` again if the client is started again with [`Client::restart`](crate::Client::restart).
+    pub fn is_closed(&self) -> bool {
+        self.1.is_closed() || self.0.is_closed()
+    }
+
     #[allow(clippy::doc_markdown)]
     /// Subscribe to a topic with the given parameters.
     ///
@@ -606,17 +971,90 @@ impl UpdateSubscriptionHandle {
     /// To know when the server has acked the subscription update, wait for the client to send an [`mqtt3::Event::SubscriptionUpdate::Subscribe`] value
     /// that contains a `mqtt3::proto::SubscribeTo` value with the same topic filter.
     /// Be careful about using `==` to determine this, since the QoS in the event may be higher than the one requested here.
+    ///
+    /// Every time the client resubscribes after the session was reset, it sends an [`mqtt3::Event::SubscriptionUpdate::Resubscribe`]
+    /// or [`mqtt3::Event::SubscriptionUpdate::ResubscribeRejectedByServer`] value for this topic filter.
+    ///
+    /// A topic filter of the form `$share/{share name}/{topic filter}` is a shared subscription, if the server supports them.
+    /// The server delivers each publication that matches the shared topic filter to only one of the clients subscribed with the same share name,
+    /// which load-balances the publications between them. Publications received for a shared subscription have the topic names
+    /// that the shared topic filter matches. A malformed shared subscription fails with [`UpdateSubscriptionError::InvalidSharedSubscription`].
+    ///
+    /// If the subscription has a [`crate::proto::SubscribeTo::subscription_identifier`], an MQTT 5.0 server attaches it to the publications
+    /// that match the subscription, in [`crate::ReceivedPublication::subscription_identifiers`]. A subscription identifier outside 1 to 268,435,455
+    /// fails with [`UpdateSubscriptionError::InvalidSubscriptionIdentifier`].
+    ///
+    /// The subscription options of the subscription, such as [`crate::proto::SubscribeTo::no_local`] to not receive the publications
+    /// of this client back from an MQTT 5.0 server, are sent again every time the client resubscribes.
     pub async fn subscribe(
         &mut self,
         subscribe_to: crate::proto::SubscribeTo,
     ) -> Result<(), UpdateSubscriptionError> {
-        use futures_util::SinkExt;
+        let subscription_update = SubscriptionUpdate::subscribe(subscribe_to, self.2)?;
+        if self.is_closed() {
+            return Err(UpdateSubscriptionError::ClientClosed);
+        }
+
+        self.0
+            .send(subscription_update)
+            .await
+            .map_err(|_| UpdateSubscriptionError::ClientClosed)?;
+        Ok(())
+    }
+
+    /// Subscribe to a topic with the given parameters, and subscribe to it again every half of the given lease time,
+    /// for servers that expire subscriptions which are not renewed.
+    ///
+    /// The [`Future`] returned by this function resolves when the subscription update is received by the client,
+    /// like for [`UpdateSubscriptionHandle::subscribe`]. See [`Client::subscribe_with_lease`](crate::Client::subscribe_with_lease) for details.
+    pub async fn subscribe_with_lease(
+        &mut self,
+        subscribe_to: crate::proto::SubscribeTo,
+        lease: std::time::Duration,
+    ) -> Result<(), UpdateSubscriptionError> {
+        let subscription_update = SubscriptionUpdate::subscribe_with_lease(subscribe_to, lease, self.2)?;
+        if self.is_closed() {
+            return Err(UpdateSubscriptionError::ClientClosed);
+        }
 
-        let subscription_update = SubscriptionUpdate::subscribe(subscribe_to)?;
         self.0
             .send(subscription_update)
             .await
-            .map_err(|_| UpdateSubscriptionError::ClientDoesNotExist)?;
+            .map_err(|_| UpdateSubscriptionError::ClientClosed)?;
+        Ok(())
+    }
+
+    #[allow(clippy::doc_markdown)]
+    /// Change the QoS of a subscription by subscribing to its topic filter again, which the server applies in place
+    /// without a gap in delivery.
+    ///
+    /// The [`Future`] returned by this function resolves when the subscription update is received by the client,
+    /// like for [`UpdateSubscriptionHandle::subscribe`].
+    ///
+    /// To know the QoS that the server granted, wait for the client to send an [`mqtt3::Event::SubscriptionUpdate::QoSUpdated`] value
+    /// for this topic filter. The granted QoS may be lower than the one requested here.
+    pub async fn update_subscription(
+        &mut self,
+        topic_filter: crate::proto::ByteStr,
+        qos: crate::proto::QoS,
+    ) -> Result<(), UpdateSubscriptionError> {
+        let subscription_update = SubscriptionUpdate::update_qos(crate::proto::SubscribeTo {
+            topic_filter,
+            qos,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        }, self.2)?;
+        if self.is_closed() {
+            return Err(UpdateSubscriptionError::ClientClosed);
+        }
+
+        self.0
+            .send(subscription_update)
+            .await
+            .map_err(|_| UpdateSubscriptionError::ClientClosed)?;
         Ok(())
     }
 
@@ -639,15 +1077,99 @@ impl UpdateSubscriptionHandle {
         &mut self,
         unsubscribe_from: crate::proto::ByteStr,
     ) -> Result<(), UpdateSubscriptionError> {
-        use futures_util::SinkExt;
-
         let subscription_update = SubscriptionUpdate::unsubscribe(unsubscribe_from)?;
+        if self.is_closed() {
+            return Err(UpdateSubscriptionError::ClientClosed);
+        }
+
         self.0
             .send(subscription_update)
             .await
-            .map_err(|_| UpdateSubscriptionError::ClientDoesNotExist)?;
+            .map_err(|_| UpdateSubscriptionError::ClientClosed)?;
         Ok(())
     }
+
+    /// Closes the handle once the client has received every subscription update queued with it, so that dropping the handle
+    /// right after updating subscriptions does not race the client.
+    ///
+    /// The [`Future`] returned by this function also resolves if the client is dropped. The updates queued with clones of this handle
+    /// are not waited for.
+    pub async fn close(self) {
+        self.0.close().await;
+    }
+
+    /// Unsubscribes from a topic without waiting for the client to receive the subscription update, such as when a [`SubscriptionGroup`] is dropped
+    fn unsubscribe_now(&self, unsubscribe_from: crate::proto::ByteStr) -> Result<(), UpdateSubscriptionError> {
+        let subscription_update = SubscriptionUpdate::unsubscribe(unsubscribe_from)?;
+        if self.is_closed() {
+            return Err(UpdateSubscriptionError::ClientClosed);
+        }
+
+        self.0
+            .try_send(subscription_update)
+            .map_err(|_| UpdateSubscriptionError::ClientClosed)
+    }
+}
+
+/// A set of subscriptions that are all unsubscribed when the group is dropped, so that a part of the application that subscribes
+/// to topics for a while, such as a plugin, cannot leave its subscriptions behind on the server.
+///
+/// The unsubscriptions are queued without waiting when the group is dropped, so the client sends them together
+/// in as few UNSUBSCRIBE packets as it can. Use [`SubscriptionGroup::close`] to wait for the client to receive them instead.
+#[derive(Debug)]
+pub struct SubscriptionGroup {
+    update_subscription_handle: UpdateSubscriptionHandle,
+    topic_filters: Vec<crate::proto::ByteStr>,
+}
+
+impl SubscriptionGroup {
+    pub fn new(update_subscription_handle: UpdateSubscriptionHandle) -> Self {
+        SubscriptionGroup {
+            update_subscription_handle,
+            topic_filters: vec![],
+        }
+    }
+
+    /// The topic filters of the subscriptions of the group
+    pub fn topic_filters(&self) -> &[crate::proto::ByteStr] {
+        &self.topic_filters
+    }
+
+    /// Subscribes to a topic and adds the subscription to the group. See [`UpdateSubscriptionHandle::subscribe`].
+    pub async fn subscribe(&mut self, subscribe_to: crate::proto::SubscribeTo) -> Result<(), UpdateSubscriptionError> {
+        let topic_filter = subscribe_to.topic_filter.clone();
+        self.update_subscription_handle.subscribe(subscribe_to).await?;
+        if !self.topic_filters.contains(&topic_filter) {
+            self.topic_filters.push(topic_filter);
+        }
+        Ok(())
+    }
+
+    /// Unsubscribes from a topic and removes the subscription from the group. See [`UpdateSubscriptionHandle::unsubscribe`].
+    pub async fn unsubscribe(&mut self, unsubscribe_from: crate::proto::ByteStr) -> Result<(), UpdateSubscriptionError> {
+        self.topic_filters.retain(|topic_filter| *topic_filter != unsubscribe_from);
+        self.update_subscription_handle.unsubscribe(unsubscribe_from).await
+    }
+
+    /// Unsubscribes from all the topics of the group. The [`Future`] returned by this function resolves when the client received
+    /// all the subscription updates, like for [`UpdateSubscriptionHandle::unsubscribe`].
+    pub async fn close(mut self) -> Result<(), UpdateSubscriptionError> {
+        for topic_filter in std::mem::take(&mut self.topic_filters) {
+            self.update_subscription_handle.unsubscribe(topic_filter).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SubscriptionGroup {
+    fn drop(&mut self) {
+        for topic_filter in self.topic_filters.drain(..) {
+            log::debug!("unsubscribing from {} because its subscription group was dropped", topic_filter);
+            if let Err(err) = self.update_subscription_handle.unsubscribe_now(topic_filter) {
+                log::warn!("could not unsubscribe when subscription group was dropped: {}", err);
+            }
+        }
+    }
 }
 
 /// Tries to append the given subscription to the given SUBSCRIBE packet. If appending `subscribe_to` would cause encoding
@@ -660,10 +1182,9 @@ fn try_append_subscription(
     use crate::proto::PacketMeta;
 
     packet.subscribe_to.push(subscribe_to);
-    let mut counter = crate::proto::ByteCounter::new();
-    match packet.clone()
-        .encode(&mut counter)
-        .and_then(|()| crate::proto::encode_remaining_length(counter.0, &mut counter))
+    match packet
+        .body_len()
+        .and_then(|body_len| crate::proto::encode_remaining_length(body_len, &mut crate::proto::ByteCounter::new()))
     {
         Ok(_) => Ok(()),
         Err(err) => {
@@ -686,10 +1207,9 @@ fn try_append_unsubscription(
     use crate::proto::PacketMeta;
 
     packet.unsubscribe_from.push(unsubscribe_from);
-    let mut counter = crate::proto::ByteCounter::new();
-    match packet.clone()
-        .encode(&mut counter)
-        .and_then(|()| crate::proto::encode_remaining_length(counter.0, &mut counter))
+    match packet
+        .body_len()
+        .and_then(|body_len| crate::proto::encode_remaining_length(body_len, &mut crate::proto::ByteCounter::new()))
     {
         Ok(_) => Ok(()),
         Err(err) => {
@@ -702,21 +1222,61 @@ fn try_append_unsubscription(
     }
 }
 
+/// Controls what the client does with publications to topics that do not match any of its subscriptions.
+///
+/// A well-behaved server never sends these, but a misbehaving server, or one that injects publications into the session, might.
+/// The client only knows about the subscriptions it made itself, so publications for subscriptions that were made in a previous run
+/// of the application and kept by the server in its session count as unsolicited.
+///
+/// Unsolicited publications are counted in [`Client::unsolicited_publications`](crate::Client::unsolicited_publications)
+/// unless the policy is [`UnsolicitedPublicationPolicy::Deliver`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum UnsolicitedPublicationPolicy {
+    /// Return unsolicited publications to the application like any other publication, without checking for them.
+    #[default]
+    Deliver,
+
+    /// Ack unsolicited publications without returning them to the application.
+    Drop,
+
+    /// Return unsolicited publications to the application as [`crate::Event::UnsolicitedPublication`] instead of
+    /// [`crate::Event::Publication`], without passing them through the inbound middleware. In manual ack mode, the application must ack them.
+    Report,
+}
+
 #[derive(Debug)]
 pub enum UpdateSubscriptionError {
-    ClientDoesNotExist,
+    ClientClosed,
     EncodePacket(crate::proto::ByteStr, crate::proto::EncodeError),
+    InvalidSharedSubscription(crate::proto::ByteStr),
+    InvalidSubscriptionIdentifier(crate::proto::ByteStr, u32),
+    TopicLimitExceeded(crate::proto::ByteStr, super::TopicLimitExceeded),
 }
 
 impl std::fmt::Display for UpdateSubscriptionError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            UpdateSubscriptionError::ClientDoesNotExist => write!(f, "client does not exist"),
+            UpdateSubscriptionError::ClientClosed => write!(f, "client has shut down or was dropped"),
             UpdateSubscriptionError::EncodePacket(topic_filter, err) => write!(
                 f,
                 "cannot encode SUBSCRIBE / UNSUBSCRIBE packet that contains topic filter {:?}: {}",
                 topic_filter, err
             ),
+            UpdateSubscriptionError::InvalidSharedSubscription(topic_filter) => write!(
+                f,
+                "shared subscription topic filter {:?} is not of the form $share/{{share name}}/{{topic filter}}, with a share name without wildcards",
+                topic_filter
+            ),
+            UpdateSubscriptionError::InvalidSubscriptionIdentifier(topic_filter, subscription_identifier) => write!(
+                f,
+                "subscription identifier {} of topic filter {:?} is not between 1 and 268435455",
+                subscription_identifier, topic_filter
+            ),
+            UpdateSubscriptionError::TopicLimitExceeded(topic_filter, err) => write!(
+                f,
+                "cannot subscribe to topic filter {:?}: {}",
+                topic_filter, err
+            ),
         }
     }
 }
@@ -724,8 +1284,260 @@ impl std::fmt::Display for UpdateSubscriptionError {
 impl std::error::Error for UpdateSubscriptionError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            UpdateSubscriptionError::ClientDoesNotExist => None,
+            UpdateSubscriptionError::ClientClosed |
+            UpdateSubscriptionError::InvalidSharedSubscription(_) |
+            UpdateSubscriptionError::InvalidSubscriptionIdentifier(_, _) => None,
             UpdateSubscriptionError::EncodePacket(_, err) => Some(err),
+            UpdateSubscriptionError::TopicLimitExceeded(_, err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn shared_subscription_validation() {
+        for &(topic_filter, valid) in &[
+            ("$share/group/sport/+", true),
+            ("$share/group/#", true),
+            ("$shared/group", true),
+            ("$share/group", false),
+            ("$share/group/", false),
+            ("$share//sport", false),
+            ("$share/gr+oup/sport", false),
+            ("$share/#/sport", false),
+        ] {
+            let subscribe_to = crate::proto::SubscribeTo {
+                topic_filter: topic_filter.parse().unwrap(),
+                qos: crate::proto::QoS::AtLeastOnce,
+                user_properties: vec![],
+                subscription_identifier: None,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: Default::default(),
+            };
+            match super::SubscriptionUpdate::subscribe(subscribe_to, Default::default()) {
+                Ok(_) => assert!(valid, "{}", topic_filter),
+                Err(super::UpdateSubscriptionError::InvalidSharedSubscription(_)) => assert!(!valid, "{}", topic_filter),
+                Err(err) => panic!("unexpected error for {}: {}", topic_filter, err),
+            }
+        }
+    }
+
+    #[test]
+    fn subscription_identifiers() {
+        let subscribe_to = |topic_filter: &str, subscription_identifier| crate::proto::SubscribeTo {
+            topic_filter: topic_filter.parse().unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        };
+
+        let mut state = super::State::default();
+        assert!(matches!(
+            state.subscribe(subscribe_to("a", Some(0))),
+            Err(super::UpdateSubscriptionError::InvalidSubscriptionIdentifier(_, 0))
+        ));
+        for subscribe_to in vec![subscribe_to("a", Some(2)), subscribe_to("b", Some(1)), subscribe_to("c", None), subscribe_to("d", Some(2))] {
+            state.subscribe(subscribe_to).unwrap();
+        }
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut packet_identifiers = Default::default();
+        let mut packets_waiting_to_be_sent = Default::default();
+        state.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+
+        // A SUBSCRIBE can only carry one subscription identifier, so there is one packet for each
+        let packets: Vec<_> = packets_waiting_to_be_sent.into_iter().map(|packet| match packet {
+            crate::proto::Packet::Subscribe(subscribe) => subscribe.subscribe_to,
+            packet => panic!("expected SUBSCRIBE but got {:?}", packet),
+        }).collect();
+        assert_eq!(packets, [
+            vec![subscribe_to("c", None)],
+            vec![subscribe_to("b", Some(1))],
+            vec![subscribe_to("a", Some(2)), subscribe_to("d", Some(2))],
+        ]);
+
+        // and so do the resubscriptions after the session is reset
+        let packets: Vec<_> = state.new_connection(true, crate::proto::QoS::ExactlyOnce, &mut packet_identifiers).map(|packet| match packet {
+            crate::proto::Packet::Subscribe(subscribe) => subscribe.subscribe_to,
+            packet => panic!("expected SUBSCRIBE but got {:?}", packet),
+        }).collect();
+        assert_eq!(packets.len(), 3);
+    }
+
+    #[test]
+    fn lease_refresh() {
+        fn poll(
+            state: &mut super::State,
+            clock: &crate::client::clock::tests::ManualClock,
+            now: u64,
+            packet: Option<crate::proto::Packet>,
+            packet_identifiers: &mut crate::client::PacketIdentifiers,
+        ) -> (Vec<crate::client::SubscriptionUpdateEvent>, Vec<crate::proto::Packet>) {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            clock.advance_to(now);
+            let mut packet = packet;
+            let mut packets_waiting_to_be_sent = Default::default();
+            let subscription_updates = state.poll(&mut cx, &mut packet, packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            (subscription_updates, packets_waiting_to_be_sent.into_iter().collect())
+        }
+
+        fn sub_ack(packet: &crate::proto::Packet, qos: crate::proto::SubAckQos) -> crate::proto::Packet {
+            match packet {
+                crate::proto::Packet::Subscribe(subscribe) => crate::proto::Packet::SubAck(crate::proto::SubAck {
+                    packet_identifier: subscribe.packet_identifier,
+                    qos: vec![qos],
+                }),
+                packet => panic!("expected SUBSCRIBE but got {:?}", packet),
+            }
+        }
+
+        let subscribe_to = crate::proto::SubscribeTo {
+            topic_filter: "topic".parse().unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            user_properties: vec![("tenant".parse().unwrap(), "a".parse().unwrap())],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        };
+        let granted = crate::proto::SubAckQos::Success(crate::proto::QoS::AtLeastOnce);
+
+        let clock = crate::client::clock::tests::ManualClock::default();
+        let mut packet_identifiers = Default::default();
+        let mut state = super::State::default();
+        state.set_clock(crate::client::clock::SharedClock::new(clock.clone()));
+        state.subscribe_with_lease(subscribe_to.clone(), std::time::Duration::from_secs(10)).unwrap();
+
+        let (_, packets) = poll(&mut state, &clock, 0, None, &mut packet_identifiers);
+        let (events, _) = poll(&mut state, &clock, 0, Some(sub_ack(&packets[0], granted)), &mut packet_identifiers);
+        assert_eq!(events, [crate::client::SubscriptionUpdateEvent::Subscribe(subscribe_to.clone())]);
+
+        // Refreshed at half the lease time, without an event when the server acks the refresh
+        assert!(poll(&mut state, &clock, 4, None, &mut packet_identifiers).1.is_empty());
+        let (_, packets) = poll(&mut state, &clock, 5, None, &mut packet_identifiers);
+        assert!(matches!(&packets[..], [crate::proto::Packet::Subscribe(subscribe)] if subscribe.subscribe_to == [subscribe_to.clone()]));
+        let (events, _) = poll(&mut state, &clock, 5, Some(sub_ack(&packets[0], granted)), &mut packet_identifiers);
+        assert!(events.is_empty());
+
+        let (_, packets) = poll(&mut state, &clock, 10, None, &mut packet_identifiers);
+        let (events, _) = poll(&mut state, &clock, 10, Some(sub_ack(&packets[0], crate::proto::SubAckQos::Failure)), &mut packet_identifiers);
+        assert_eq!(events, [crate::client::SubscriptionUpdateEvent::RefreshRejectedByServer(subscribe_to)]);
+
+        // The lease ended with the rejected refresh
+        assert!(poll(&mut state, &clock, 20, None, &mut packet_identifiers).1.is_empty());
+    }
+
+    #[test]
+    fn update_qos() {
+        use std::future::Future;
+
+        fn poll(
+            state: &mut super::State,
+            packet: Option<crate::proto::Packet>,
+            packet_identifiers: &mut crate::client::PacketIdentifiers,
+        ) -> (Vec<crate::client::SubscriptionUpdateEvent>, Vec<crate::proto::Packet>) {
+            let waker = futures_util::task::noop_waker();
+            let mut cx = std::task::Context::from_waker(&waker);
+
+            let mut packet = packet;
+            let mut packets_waiting_to_be_sent = Default::default();
+            let subscription_updates = state.poll(&mut cx, &mut packet, packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            (subscription_updates, packets_waiting_to_be_sent.into_iter().collect())
+        }
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let sub_ack = |packet: &crate::proto::Packet, qos: crate::proto::QoS| match packet {
+            crate::proto::Packet::Subscribe(subscribe) => crate::proto::Packet::SubAck(crate::proto::SubAck {
+                packet_identifier: subscribe.packet_identifier,
+                qos: vec![crate::proto::SubAckQos::Success(qos)],
+            }),
+            packet => panic!("expected SUBSCRIBE but got {:?}", packet),
+        };
+
+        let subscribe_to = |qos: crate::proto::QoS| crate::proto::SubscribeTo {
+            topic_filter: "topic".parse().unwrap(),
+            qos,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        };
+
+        let mut packet_identifiers = Default::default();
+        let mut state = super::State::default();
+        state.subscribe(subscribe_to(crate::proto::QoS::AtMostOnce)).unwrap();
+        let (_, packets) = poll(&mut state, None, &mut packet_identifiers);
+        let (events, _) = poll(&mut state, Some(sub_ack(&packets[0], crate::proto::QoS::AtMostOnce)), &mut packet_identifiers);
+        assert_eq!(events, [crate::client::SubscriptionUpdateEvent::Subscribe(subscribe_to(crate::proto::QoS::AtMostOnce))]);
+
+        let mut update_subscription_handle = state.update_subscription_handle(Default::default());
+        let mut update = Box::pin(update_subscription_handle.update_subscription("topic".parse().unwrap(), crate::proto::QoS::ExactlyOnce));
+        let mut packets = vec![];
+        while update.as_mut().poll(&mut cx).is_pending() {
+            packets.extend(poll(&mut state, None, &mut packet_identifiers).1);
+        }
+        packets.extend(poll(&mut state, None, &mut packet_identifiers).1);
+
+        // The subscription is updated with a single SUBSCRIBE, without unsubscribing first
+        assert!(
+            matches!(&packets[..], [crate::proto::Packet::Subscribe(subscribe)] if subscribe.subscribe_to == [subscribe_to(crate::proto::QoS::ExactlyOnce)]),
+            "{:?}", packets,
+        );
+
+        // The granted QoS is reported even though it is lower than the one requested
+        let (events, packets) = poll(&mut state, Some(sub_ack(&packets[0], crate::proto::QoS::AtLeastOnce)), &mut packet_identifiers);
+        assert_eq!(events, [crate::client::SubscriptionUpdateEvent::QoSUpdated(subscribe_to(crate::proto::QoS::AtLeastOnce))]);
+        assert!(packets.is_empty(), "{:?}", packets);
+    }
+
+    #[test]
+    fn subscription_group() {
+        use std::future::Future;
+
+        let subscribe_to = |topic_filter: &str| crate::proto::SubscribeTo {
+            topic_filter: topic_filter.parse().unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        };
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut packet_identifiers = Default::default();
+        let mut packets_waiting_to_be_sent = std::collections::VecDeque::new();
+        let mut state = super::State::default();
+
+        let mut group = super::SubscriptionGroup::new(state.update_subscription_handle(Default::default()));
+        for &topic_filter in &["a", "b", "a"] {
+            let mut subscribe = Box::pin(group.subscribe(subscribe_to(topic_filter)));
+            while subscribe.as_mut().poll(&mut cx).is_pending() {
+                state.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+            }
         }
+        let topic_filters: Vec<crate::proto::ByteStr> = vec!["a".parse().unwrap(), "b".parse().unwrap()];
+        assert_eq!(group.topic_filters(), &topic_filters[..]);
+        packets_waiting_to_be_sent.clear();
+
+        // Both subscriptions are unsubscribed with a single UNSUBSCRIBE
+        drop(group);
+        state.poll(&mut cx, &mut None, &mut packet_identifiers, &mut packets_waiting_to_be_sent).unwrap();
+        assert!(matches!(
+            packets_waiting_to_be_sent.make_contiguous(),
+            [crate::proto::Packet::Unsubscribe(unsubscribe)] if unsubscribe.unsubscribe_from == topic_filters
+        ));
     }
 }
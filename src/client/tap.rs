@@ -0,0 +1,70 @@
+/// A [`Stream`] of every packet sent and received by a [`Client`], returned by [`Client::packet_tap`].
+///
+/// The password of the CONNECT packet is removed before it is added to the tap.
+///
+/// The client never waits for the tap to be read. If the tap is full, packets are dropped from it until there is room again.
+/// The stream ends when a new tap is requested from the client or when the client is dropped.
+#[derive(Debug)]
+pub struct PacketTap(futures_channel::mpsc::Receiver<TappedPacket>);
+
+impl futures_core::Stream for PacketTap {
+    type Item = TappedPacket;
+
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        std::pin::Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+/// A packet observed by a [`PacketTap`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TappedPacket {
+    /// A packet sent by the client to the server
+    Sent(crate::proto::Packet),
+
+    /// A packet received by the client from the server
+    Received(crate::proto::Packet),
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Tap(Option<futures_channel::mpsc::Sender<TappedPacket>>);
+
+impl Tap {
+    pub(super) fn new_tap(&mut self, capacity: usize) -> PacketTap {
+        let (tap_send, tap_recv) = futures_channel::mpsc::channel(capacity);
+        self.0 = Some(tap_send);
+        PacketTap(tap_recv)
+    }
+
+    pub(super) fn sent(&mut self, packet: &crate::proto::Packet) {
+        self.tap(|| match packet {
+            // Don't leak the password to the tap
+            crate::proto::Packet::Connect(connect) => TappedPacket::Sent(crate::proto::Packet::Connect(crate::proto::Connect {
+                password: None,
+                ..connect.clone()
+            })),
+
+            packet => TappedPacket::Sent(packet.clone()),
+        });
+    }
+
+    /// Taps the packet in the result of polling a [`crate::io::PacketStream`], if any
+    pub(super) fn poll_received<E>(
+        &mut self,
+        poll: std::task::Poll<Option<Result<crate::proto::Packet, E>>>,
+    ) -> std::task::Poll<Option<Result<crate::proto::Packet, E>>> {
+        if let std::task::Poll::Ready(Some(Ok(packet))) = &poll {
+            self.tap(|| TappedPacket::Received(packet.clone()));
+        }
+        poll
+    }
+
+    fn tap(&mut self, tapped_packet: impl FnOnce() -> TappedPacket) {
+        if let Some(tap_send) = &mut self.0 {
+            match tap_send.try_send(tapped_packet()) {
+                Ok(()) => (),
+                Err(err) if err.is_full() => log::debug!("dropping packet from packet tap because it is full"),
+                Err(_) => self.0 = None,
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+/// Returns a CONNACK with the given return code and none of the optional fields
+pub(in crate::client) fn conn_ack(return_code: crate::proto::ConnectReturnCode) -> crate::proto::ConnAck {
+    crate::proto::ConnAck {
+        session_present: false,
+        return_code,
+        authentication_data: None,
+        receive_maximum: None,
+        maximum_packet_size: None,
+        server_reference: None,
+        assigned_client_id: None,
+        server_keep_alive: None,
+        maximum_qos: None,
+        retain_available: None,
+        reason_string: None,
+        user_properties: vec![],
+    }
+}
+
+type Respond = dyn FnMut(&crate::proto::Packet) -> Vec<crate::proto::Packet> + Send;
+
+/// An in-memory server for the tests of the client. It is also the [`crate::io::Connector`] that connects the client to it.
+///
+/// Every packet that the client sends is recorded, and passed to the function that the server was created with.
+/// The packets that the function returns are sent back to the client on the same connection.
+///
+/// Clones share the same server.
+#[derive(Clone)]
+pub(in crate::client) struct TestServer {
+    respond: std::sync::Arc<std::sync::Mutex<Box<Respond>>>,
+    sent: std::sync::Arc<std::sync::Mutex<Vec<crate::proto::Packet>>>,
+    stall: Option<Stall>,
+}
+
+/// Where the sinks of the connections to a [`TestServer`] stop making progress
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(in crate::client) enum Stall {
+    /// The sink is never ready to send a packet
+    Ready,
+
+    /// The sink accepts packets but never finishes flushing them
+    Flush,
+}
+
+impl TestServer {
+    pub(in crate::client) fn new(respond: impl FnMut(&crate::proto::Packet) -> Vec<crate::proto::Packet> + Send + 'static) -> Self {
+        TestServer {
+            respond: std::sync::Arc::new(std::sync::Mutex::new(Box::new(respond))),
+            sent: Default::default(),
+            stall: None,
+        }
+    }
+
+    /// A server that would accept every connection, but whose connections stall before the client's CONNECT reaches it
+    pub(in crate::client) fn stalled(stall: Stall) -> Self {
+        TestServer {
+            stall: Some(stall),
+            ..TestServer::accepting()
+        }
+    }
+
+    /// A server that accepts every connection, and sends nothing but the CONNACK
+    pub(in crate::client) fn accepting() -> Self {
+        TestServer::new(|packet| match packet {
+            crate::proto::Packet::Connect(_) => vec![crate::proto::Packet::ConnAck(conn_ack(crate::proto::ConnectReturnCode::Accepted))],
+            _ => vec![],
+        })
+    }
+
+    /// A server that accepts every connection, and sends every publication that the client publishes back to it
+    pub(in crate::client) fn echo() -> Self {
+        TestServer::new(|packet| match packet {
+            crate::proto::Packet::Connect(_) => vec![crate::proto::Packet::ConnAck(conn_ack(crate::proto::ConnectReturnCode::Accepted))],
+            crate::proto::Packet::Publish(publish) => vec![crate::proto::Packet::Publish(publish.clone())],
+            _ => vec![],
+        })
+    }
+
+    /// The packets that the client sent, over all its connections
+    pub(in crate::client) fn sent(&self) -> Vec<crate::proto::Packet> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+impl crate::io::Connector for TestServer {
+    type PacketStream = futures_channel::mpsc::UnboundedReceiver<Result<crate::proto::Packet, crate::proto::DecodeError>>;
+    type PacketSink = TestSink;
+    type Error = std::io::Error;
+    type Future = futures_util::future::Ready<Result<(Self::PacketStream, Self::PacketSink, Option<crate::proto::ByteStr>), Self::Error>>;
+
+    fn connect(&mut self) -> Self::Future {
+        let (responses, stream) = futures_channel::mpsc::unbounded();
+        futures_util::future::ready(Ok((stream, TestSink { server: self.clone(), responses }, None)))
+    }
+}
+
+/// The sink of a connection to a [`TestServer`]
+pub(in crate::client) struct TestSink {
+    server: TestServer,
+    responses: futures_channel::mpsc::UnboundedSender<Result<crate::proto::Packet, crate::proto::DecodeError>>,
+}
+
+impl futures_sink::Sink<crate::proto::Packet> for TestSink {
+    type Error = crate::proto::EncodeError;
+
+    fn poll_ready(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        if self.server.stall == Some(Stall::Ready) {
+            return std::task::Poll::Pending;
+        }
+
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: std::pin::Pin<&mut Self>, packet: crate::proto::Packet) -> Result<(), Self::Error> {
+        let responses = (self.server.respond.lock().unwrap())(&packet);
+        self.server.sent.lock().unwrap().push(packet);
+        for response in responses {
+            // The client may have dropped the connection already
+            let _ = self.responses.unbounded_send(Ok(response));
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        if self.server.stall == Some(Stall::Flush) {
+            return std::task::Poll::Pending;
+        }
+
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: std::pin::Pin<&mut Self>, _cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        self.responses.close_channel();
+        std::task::Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,87 @@
+/// Limits on the topics of publications sent by a [`Client`](crate::Client) and the topic filters it subscribes to,
+/// set with [`Client::with_topic_limits`](crate::Client::with_topic_limits).
+///
+/// Servers often enforce limits like these by dropping the connection. Checking them in the client instead fails only the offending
+/// publish or subscribe request, with [`PublishError::TopicLimitExceeded`](crate::PublishError::TopicLimitExceeded) or
+/// [`UpdateSubscriptionError::TopicLimitExceeded`](crate::UpdateSubscriptionError::TopicLimitExceeded).
+///
+/// Publication topics are checked after they are rewritten by the [`super::TopicRewriter`]. Unsubscriptions are not checked.
+/// There are no limits by default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TopicLimits {
+    max_levels: Option<usize>,
+    max_len: Option<usize>,
+}
+
+impl TopicLimits {
+    /// Sets the maximum number of levels of a topic or topic filter, where `a/b/c` has three levels.
+    #[must_use]
+    pub fn with_max_levels(mut self, max_levels: usize) -> Self {
+        self.max_levels = Some(max_levels);
+        self
+    }
+
+    /// Sets the maximum length of a topic or topic filter in bytes.
+    #[must_use]
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Checks the given topic or topic filter against the limits
+    pub(super) fn check(self, topic: &str) -> Result<(), TopicLimitExceeded> {
+        if let Some(max_len) = self.max_len {
+            let len = topic.len();
+            if len > max_len {
+                return Err(TopicLimitExceeded::Len { len, max_len });
+            }
+        }
+
+        if let Some(max_levels) = self.max_levels {
+            let levels = topic.split('/').count();
+            if levels > max_levels {
+                return Err(TopicLimitExceeded::Levels { levels, max_levels });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The limit of a [`TopicLimits`] that a topic or topic filter exceeds
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TopicLimitExceeded {
+    /// The topic has more levels than allowed
+    Levels { levels: usize, max_levels: usize },
+
+    /// The topic is longer than allowed
+    Len { len: usize, max_len: usize },
+}
+
+impl std::fmt::Display for TopicLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TopicLimitExceeded::Levels { levels, max_levels } => write!(f, "topic has {} levels, more than the maximum of {}", levels, max_levels),
+            TopicLimitExceeded::Len { len, max_len } => write!(f, "topic is {} bytes long, more than the maximum of {}", len, max_len),
+        }
+    }
+}
+
+impl std::error::Error for TopicLimitExceeded {}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn check() {
+        let topic_limits = super::TopicLimits::default();
+        assert_eq!(topic_limits.check("a/b/c/d/e/f"), Ok(()));
+
+        let topic_limits = topic_limits.with_max_levels(3).with_max_len(8);
+        assert_eq!(topic_limits.check("a/b/c"), Ok(()));
+        assert_eq!(topic_limits.check("a/+/#"), Ok(()));
+        assert_eq!(topic_limits.check("a/b/c/d"), Err(super::TopicLimitExceeded::Levels { levels: 4, max_levels: 3 }));
+        assert_eq!(topic_limits.check("a//"), Ok(()));
+        assert_eq!(topic_limits.check("a///"), Err(super::TopicLimitExceeded::Levels { levels: 4, max_levels: 3 }));
+        assert_eq!(topic_limits.check("abc/defgh"), Err(super::TopicLimitExceeded::Len { len: 9, max_len: 8 }));
+    }
+}
@@ -0,0 +1,110 @@
+use std::convert::TryFrom;
+
+/// Rules for rewriting the topics of publications sent and received by a [`Client`], set with [`Client::with_topic_rewriter`].
+///
+/// Each rule replaces a topic prefix with another. Rules are tried in the order they were added, and only the first matching rule is applied.
+/// A prefix only matches whole topic levels, so a rule for `a` rewrites `a` and `a/x` but not `abc/x`. A prefix that ends with `/`,
+/// such as `a/`, matches the topics that have more levels after it.
+/// This is useful when migrating to a new topic hierarchy, without having to change every place that publishes or handles publications.
+///
+/// Topic filters of subscriptions are not rewritten.
+#[derive(Clone, Debug, Default)]
+pub struct TopicRewriter {
+    outgoing: Vec<(String, String)>,
+    incoming: Vec<(String, String)>,
+}
+
+impl TopicRewriter {
+    /// Adds a rule that replaces the prefix `from` with `to` in the topics of publications sent by the client.
+    #[must_use]
+    pub fn outgoing_prefix(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.outgoing.push((from.into(), to.into()));
+        self
+    }
+
+    /// Adds a rule that replaces the prefix `from` with `to` in the topics of publications received by the client.
+    #[must_use]
+    pub fn incoming_prefix(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.incoming.push((from.into(), to.into()));
+        self
+    }
+
+    /// Returns the rewritten topic for a publication sent by the client, or `None` if no rule matches it.
+    pub(super) fn rewrite_outgoing(&self, topic_name: &crate::proto::ByteStr) -> Option<Result<crate::proto::ByteStr, crate::proto::EncodeError>> {
+        rewrite(&self.outgoing, topic_name)
+    }
+
+    /// Returns the rewritten topic for a publication received by the client.
+    ///
+    /// If the rewritten topic would be too large, the original topic is returned.
+    pub(super) fn rewrite_incoming(&self, topic_name: crate::proto::ByteStr) -> crate::proto::ByteStr {
+        match rewrite(&self.incoming, &topic_name) {
+            Some(Ok(rewritten_topic_name)) => rewritten_topic_name,
+            Some(Err(err)) => {
+                log::warn!("could not rewrite topic of received publication {}: {}", crate::proto::EscapedStr(topic_name.as_ref()), err);
+                topic_name
+            },
+            None => topic_name,
+        }
+    }
+}
+
+fn rewrite(rules: &[(String, String)], topic_name: &crate::proto::ByteStr) -> Option<Result<crate::proto::ByteStr, crate::proto::EncodeError>> {
+    let topic_name: &str = topic_name.as_ref();
+
+    rules.iter().find_map(|(from, to)| {
+        let rest = topic_name.strip_prefix(from.as_str())?;
+        if !(rest.is_empty() || rest.starts_with('/') || from.ends_with('/')) {
+            return None;
+        }
+
+        let rewritten_topic_name = format!("{}{}", to, rest);
+        let len = rewritten_topic_name.len();
+        Some(crate::proto::ByteStr::try_from(rewritten_topic_name).map_err(|_| crate::proto::EncodeError::StringTooLarge(len)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    fn rewrite(rules: &[(&str, &str)], topic_name: &str) -> Option<Result<String, crate::proto::EncodeError>> {
+        let rules: Vec<_> = rules.iter().map(|&(from, to)| (from.to_owned(), to.to_owned())).collect();
+        super::rewrite(&rules, &topic_name.parse().unwrap()).map(|result| result.map(|topic_name| topic_name.to_string()))
+    }
+
+    #[test]
+    fn rewrite_whole_levels() {
+        let rules = [("a", "b")];
+        assert_eq!(rewrite(&rules, "a").unwrap().unwrap(), "b");
+        assert_eq!(rewrite(&rules, "a/x").unwrap().unwrap(), "b/x");
+
+        // A prefix that ends inside a level does not match
+        assert!(rewrite(&rules, "abc/x").is_none());
+        assert!(rewrite(&rules, "x/a").is_none());
+
+        // A prefix that ends with a separator matches the topics below it
+        let rules = [("legacy/", "v2/")];
+        assert_eq!(rewrite(&rules, "legacy/x").unwrap().unwrap(), "v2/x");
+        assert!(rewrite(&rules, "legacy").is_none());
+        assert!(rewrite(&rules, "legacyx/y").is_none());
+    }
+
+    #[test]
+    fn rewrite_first_matching_rule() {
+        let rules = [("a/b", "c"), ("a", "d"), ("a/b", "e")];
+        assert_eq!(rewrite(&rules, "a/b/x").unwrap().unwrap(), "c/x");
+        assert_eq!(rewrite(&rules, "a/bb").unwrap().unwrap(), "d/bb");
+    }
+
+    #[test]
+    fn rewrite_too_long() {
+        let long_prefix = "x".repeat(usize::from(u16::MAX));
+        match rewrite(&[("a", &long_prefix)], "a/b") {
+            Some(Err(crate::proto::EncodeError::StringTooLarge(len))) => assert_eq!(len, usize::from(u16::MAX) + 2),
+            result => panic!("expected the rewritten topic to be too long, got {:?}", result),
+        }
+
+        // A received publication whose topic can't be rewritten keeps its topic
+        let topic_rewriter = super::TopicRewriter::default().incoming_prefix("a", long_prefix);
+        assert_eq!(topic_rewriter.rewrite_incoming("a/b".parse().unwrap()), "a/b");
+    }
+}
@@ -23,6 +23,15 @@ pub trait Connector {
 
     /// Attempts the connection and returns a [`Future`] that resolves when the connection succeeds.
     fn connect(&mut self) -> Self::Future;
+
+    /// Makes the next connections go to the given server instead, or back to the usual server if `None`,
+    /// when the [`Client`] follows a redirect of the server. See [`crate::RedirectPolicy`].
+    ///
+    /// The server reference is as the server sent it, usually a host name with an optional port.
+    /// Returns whether the connector can connect to the given server. The default implementation does not follow redirects.
+    fn redirect(&mut self, _server_reference: Option<&str>) -> bool {
+        false
+    }
 }
 
 #[cfg(feature = "client")]
@@ -49,6 +58,130 @@ pub trait Listener {
     type PacketSink: PacketSink;
 
     fn poll_accept(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink)>>;
+
+    /// Like [`Listener::poll_accept`], but also returns the limits that the server applies to the accepted client.
+    ///
+    /// The default implementation returns no limits of its own, so the client gets the ones of the server's [`crate::server::Config`].
+    /// [`Listeners`] returns the limits that each of its listeners was added with.
+    #[allow(clippy::type_complexity)]
+    fn poll_accept_with_limits(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink, crate::server::ListenerLimits)>> {
+        self.poll_accept(cx).map_ok(|(stream, sink)| (stream, sink, Default::default()))
+    }
+}
+
+/// A [`PacketStream`] of any type, such as one accepted by [`Listeners`]
+#[cfg(feature = "server")]
+pub type BoxPacketStream = std::pin::Pin<Box<dyn PacketStream>>;
+
+/// A [`PacketSink`] of any type, such as one accepted by [`Listeners`]
+#[cfg(feature = "server")]
+pub type BoxPacketSink = std::pin::Pin<Box<dyn PacketSink>>;
+
+#[cfg(feature = "server")]
+type BoxListener = Box<dyn Listener<PacketStream = BoxPacketStream, PacketSink = BoxPacketSink>>;
+
+/// A [`Listener`] that accepts clients from several listeners at once, such as a TCP listener, a TLS listener and a WebSocket listener,
+/// so that a single server accepts clients on all of them. The clients of every listener share the server's sessions, subscriptions
+/// and retained messages, but each listener can limit the publications in flight and queued for its clients differently.
+#[cfg(feature = "server")]
+#[derive(Default)]
+pub struct Listeners {
+    /// The listeners, and the limits that they were added with, if any
+    listeners: Vec<(BoxListener, Option<crate::server::ListenerLimits>)>,
+
+    /// The listener that is polled first by the next accept, so that a busy listener does not starve the others
+    next: usize,
+}
+
+#[cfg(feature = "server")]
+impl Listeners {
+    /// Adds a listener to accept clients from
+    #[must_use]
+    pub fn with_listener<L>(mut self, listener: L) -> Self
+    where
+        L: Listener + 'static,
+        <L as Listener>::PacketStream: 'static,
+        <L as Listener>::PacketSink: 'static,
+    {
+        self.listeners.push((Box::new(BoxedListener(listener)), None));
+        self
+    }
+
+    /// Adds a listener to accept clients from, whose clients get the given limits instead of the ones of the server's [`crate::server::Config`]
+    #[must_use]
+    pub fn with_listener_limits<L>(mut self, listener: L, limits: crate::server::ListenerLimits) -> Self
+    where
+        L: Listener + 'static,
+        <L as Listener>::PacketStream: 'static,
+        <L as Listener>::PacketSink: 'static,
+    {
+        self.listeners.push((Box::new(BoxedListener(listener)), Some(limits)));
+        self
+    }
+}
+
+#[cfg(feature = "server")]
+impl Listener for Listeners {
+    type PacketStream = BoxPacketStream;
+    type PacketSink = BoxPacketSink;
+
+    fn poll_accept(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink)>> {
+        self.poll_accept_with_limits(cx).map_ok(|(stream, sink, _)| (stream, sink))
+    }
+
+    fn poll_accept_with_limits(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink, crate::server::ListenerLimits)>> {
+        let num_listeners = self.listeners.len();
+        for i in 0..num_listeners {
+            let index = (self.next + i) % num_listeners;
+            let (listener, limits) = &mut self.listeners[index];
+            if let std::task::Poll::Ready(result) = listener.poll_accept_with_limits(cx) {
+                self.next = (index + 1) % num_listeners;
+                return std::task::Poll::Ready(result.map(|(stream, sink, listener_limits)| (stream, sink, limits.unwrap_or(listener_limits))));
+            }
+        }
+
+        std::task::Poll::Pending
+    }
+}
+
+#[cfg(feature = "server")]
+impl std::fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listeners")
+            .field("len", &self.listeners.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "server")]
+struct BoxedListener<L>(L);
+
+#[cfg(feature = "server")]
+impl<L> Listener for BoxedListener<L>
+where
+    L: Listener,
+    <L as Listener>::PacketStream: 'static,
+    <L as Listener>::PacketSink: 'static,
+{
+    type PacketStream = BoxPacketStream;
+    type PacketSink = BoxPacketSink;
+
+    fn poll_accept(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink)>> {
+        self.0.poll_accept(cx).map_ok(|(stream, sink)| (Box::pin(stream) as BoxPacketStream, Box::pin(sink) as BoxPacketSink))
+    }
+
+    fn poll_accept_with_limits(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink, crate::server::ListenerLimits)>> {
+        self.0.poll_accept_with_limits(cx).map_ok(|(stream, sink, limits)| (Box::pin(stream) as BoxPacketStream, Box::pin(sink) as BoxPacketSink, limits))
+    }
 }
 
 pub fn logging<St, Si>(stream: St, sink: Si) -> (LoggingStream<St>, LoggingSink<Si>)
@@ -109,3 +242,76 @@ impl<S> futures_sink::Sink<crate::proto::Packet> for LoggingSink<S> where S: Pac
         self.project().0.poll_close(cx)
     }
 }
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    /// Accepts the given number of clients, then none
+    struct TestListener(usize);
+
+    impl super::Listener for TestListener {
+        type PacketStream = futures_util::stream::Empty<Result<crate::proto::Packet, crate::proto::DecodeError>>;
+        type PacketSink = futures_util::sink::SinkMapErr<futures_util::sink::Drain<crate::proto::Packet>, fn(std::convert::Infallible) -> crate::proto::EncodeError>;
+
+        fn poll_accept(&mut self, _cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink)>> {
+            use futures_util::SinkExt;
+
+            if self.0 == 0 {
+                return std::task::Poll::Pending;
+            }
+
+            self.0 -= 1;
+            let sink = futures_util::sink::drain().sink_map_err((|err| match err {}) as fn(std::convert::Infallible) -> crate::proto::EncodeError);
+            std::task::Poll::Ready(Ok((futures_util::stream::empty(), sink)))
+        }
+    }
+
+    #[test]
+    fn listeners() {
+        use super::Listener;
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        let mut listeners = super::Listeners::default();
+        assert!(listeners.poll_accept(&mut cx).is_pending());
+
+        let mut listeners = listeners.with_listener(TestListener(3)).with_listener(TestListener(1));
+        for _ in 0..4 {
+            assert!(listeners.poll_accept(&mut cx).is_ready());
+        }
+        assert!(listeners.poll_accept(&mut cx).is_pending());
+
+        // The second listener's client is accepted second, not after all of the first listener's clients
+        let mut listeners = super::Listeners::default().with_listener(TestListener(3)).with_listener(TestListener(1));
+        let _ = listeners.poll_accept(&mut cx);
+        assert_eq!(listeners.next, 1);
+        let _ = listeners.poll_accept(&mut cx);
+        assert_eq!(listeners.next, 0);
+    }
+
+    #[test]
+    fn listener_limits() {
+        use super::Listener;
+
+        fn accept(listeners: &mut impl Listener, cx: &mut std::task::Context<'_>) -> crate::server::ListenerLimits {
+            match listeners.poll_accept_with_limits(cx) {
+                std::task::Poll::Ready(Ok((_, _, limits))) => limits,
+                _ => panic!("expected a client to be accepted"),
+            }
+        }
+
+        let waker = futures_util::task::noop_waker();
+        let mut cx = std::task::Context::from_waker(&waker);
+
+        // The clients of each listener get the limits that the listener was added with, including the listeners of nested listeners
+        let limits = crate::server::ListenerLimits::default().with_max_inflight_messages(1).with_max_queued_messages(2);
+        let nested = super::Listeners::default().with_listener_limits(TestListener(1), limits);
+        let mut listeners = super::Listeners::default()
+            .with_listener_limits(TestListener(1), limits)
+            .with_listener(TestListener(1))
+            .with_listener(nested);
+        assert_eq!(accept(&mut listeners, &mut cx), limits);
+        assert_eq!(accept(&mut listeners, &mut cx), Default::default());
+        assert_eq!(accept(&mut listeners, &mut cx), limits);
+    }
+}
@@ -27,11 +27,41 @@ pub const PROTOCOL_LEVEL: u8 = 0x04;
 mod client;
 #[cfg(feature = "client")]
 pub use client::{
-    Client, ConnectionError, Error, Event, PublishError, PublishHandle,
-    ReceivedPublication, ShutdownError, ShutdownHandle, SubscriptionUpdateEvent,
-    UpdateSubscriptionError, UpdateSubscriptionHandle,
+    AckDeferral, AuthStep, Authenticator, Client, Clock, ClockSleep, CompressionCodec,
+    CompressionConnector, CompressionConnectorFuture, CompressionSink, CompressionStream,
+    ConfigHandle, ConfigUpdate, ConfigUpdateError, Conflated, Conflation, ConnectedEvent,
+    ConnectionError, ConnectionParameters, ConnectionStability, ConnectionStabilityStats, Consumer,
+    DeadlineLayer, DeadlineService, DecodedPublications, DeliveryMode, DuplicatePolicy,
+    EndpointFuture, Error, Event, EventRecord, EventSink, Failover, FailoverError, FailoverEvent,
+    FailoverPublishMode, FailoverSide, FlushStrategy, GapDetector, IdentityLayer, InboundCounters,
+    InboundMiddleware, LayerStack, LogLayer, LogService, LoopGuard, LoopGuardService, ManualAck,
+    MapPublicationLayer, MapPublicationService, MemoryProcessedStore, PacketTap, PayloadCodec,
+    PayloadFilter, PayloadSizeHistogram, PayloadSizes, Presence, ProcessedStore, ProtocolFallback,
+    PublishBeforeFirstConnection, PublishError, PublishFuture, PublishHandle, PublishLayer,
+    PublishPacing, PublishPriority, PublishService, PublishServiceBuilder, QoSDowngrade,
+    ReceivedPublication, ReconnectJitter, RedirectPolicy, RedirectableConnector, ReplayOrdering,
+    RetransmitPolicy, RetryLayer, RetryService, Sequence, SequenceLayer, SequenceService,
+    ShutdownError, ShutdownHandle, SlowConsumerStats, SlowConsumerThresholds, SubscriptionGroup,
+    SubscriptionUpdateEvent, TappedPacket, TokioClock, TopicCompression, TopicCompressionError,
+    TopicLimitExceeded, TopicLimits, TopicRewriter, UnsolicitedPublicationPolicy,
+    UpdateSubscriptionError, UpdateSubscriptionHandle, WeightedConnector,
 };
 
+#[cfg(all(feature = "client", feature = "flate2"))]
+pub use client::Deflate;
+
+#[cfg(all(feature = "client", feature = "prost"))]
+pub use client::ProstCodec;
+
+#[cfg(all(feature = "client", feature = "schema-registry"))]
+pub use client::{SchemaCodec, SchemaRegistry, SchemaRegistryCodec, SchemaRegistryCodecError};
+
+#[cfg(all(feature = "client", feature = "avro"))]
+pub use client::{AvroCodec, AvroCodecError, AvroValue};
+
+#[cfg(feature = "client")]
+pub use client::rpc;
+
 #[cfg(any(
     feature = "client",
     feature = "server",
@@ -42,3 +72,11 @@ pub mod proto;
 
 #[cfg(feature = "server")]
 pub mod server;
+
+#[cfg(all(any(feature = "client", feature = "server"), feature = "transport-tokio"))]
+pub mod transport;
+
+#[cfg(all(feature = "client", feature = "transport-tokio"))]
+pub mod testing;
+
+pub mod util;
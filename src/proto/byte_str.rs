@@ -22,6 +22,11 @@ impl ByteStr {
         u16::from_be_bytes(self.0[..std::mem::size_of::<u16>()].try_into().unwrap()).into()
     }
 
+    /// The number of bytes this string occupies when encoded, including its length prefix.
+    pub fn encoded_len(&self) -> usize {
+        self.0.len()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.0 == b"\x00\x00"[..]
     }
@@ -0,0 +1,91 @@
+/// Displays a string, such as a topic name, with its control characters escaped, so that a topic name sent by a peer
+/// cannot garble a log line or a terminal. Backslashes are escaped too, so that the escaped string is unambiguous.
+///
+/// ```
+/// assert_eq!(mqtt3::proto::EscapedStr("a/\u{1b}[2J\\b").to_string(), r"a/\u{1b}[2J\\b");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct EscapedStr<'a>(pub &'a str);
+
+impl std::fmt::Display for EscapedStr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        escape_str(self.0, f)
+    }
+}
+
+/// Displays bytes, such as a payload, as text. Control characters and backslashes are escaped like [`EscapedStr`],
+/// and bytes that are not valid UTF-8 are escaped as `\xNN`, so that the bytes are displayed without losing any of them.
+///
+/// ```
+/// assert_eq!(mqtt3::proto::EscapedBytes(b"21.5\xff\n").to_string(), r"21.5\xff\n");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct EscapedBytes<'a>(pub &'a [u8]);
+
+impl std::fmt::Display for EscapedBytes<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut bytes = self.0;
+
+        loop {
+            match std::str::from_utf8(bytes) {
+                Ok(s) => return escape_str(s, f),
+
+                Err(err) => {
+                    let (valid, rest) = bytes.split_at(err.valid_up_to());
+                    // The valid prefix was just checked to be UTF-8
+                    escape_str(std::str::from_utf8(valid).expect("valid prefix is UTF-8"), f)?;
+
+                    // A truncated character at the end of the bytes has no error length
+                    let (invalid, rest) = rest.split_at(err.error_len().unwrap_or(rest.len()));
+                    for b in invalid {
+                        write!(f, "\\x{:02x}", b)?;
+                    }
+
+                    bytes = rest;
+                },
+            }
+        }
+    }
+}
+
+fn escape_str(s: &str, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for c in s.chars() {
+        if c == '\\' || c.is_control() {
+            std::fmt::Display::fmt(&c.escape_default(), f)?;
+        }
+        else {
+            std::fmt::Write::write_char(f, c)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn escaped_str() {
+        for &(s, expected) in &[
+            ("sport/tennis/player1", "sport/tennis/player1"),
+            ("température/😀", "température/😀"),
+            ("a\tb\r\nc\0", r"a\tb\r\nc\u{0}"),
+            ("\u{1b}[31mred\u{7f}", r"\u{1b}[31mred\u{7f}"),
+            (r"C:\topic", r"C:\\topic"),
+        ] {
+            assert_eq!(super::EscapedStr(s).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn escaped_bytes() {
+        for &(bytes, expected) in &[
+            (&b""[..], ""),
+            (&b"{\"temperature\":21.5}"[..], "{\"temperature\":21.5}"),
+            (&b"\x00\x01\xfe\xff"[..], r"\u{0}\u{1}\xfe\xff"),
+            // Invalid bytes between valid text, and a character truncated at the end
+            (&b"caf\xc3\xa9 \xc3( \xe2\x82"[..], r"café \xc3( \xe2\x82"),
+        ] {
+            assert_eq!(super::EscapedBytes(bytes).to_string(), expected);
+        }
+    }
+}
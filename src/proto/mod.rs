@@ -9,17 +9,25 @@ pub use byte_str::{
     ByteStr,
 };
 
+mod escape;
+pub use escape::{
+    EscapedBytes, EscapedStr,
+};
+
 mod packet;
 pub use packet::{
-    ConnAck, Connect, Disconnect, Packet, PacketDecoder, PacketIdentifierDupQoS, PingReq, PingResp,
-    PubAck, PubComp, PubRec, PubRel, Publication, Publish, QoS, SubAck, SubAckQos, Subscribe,
-    SubscribeTo, UnsubAck, Unsubscribe,
+    Auth, AuthReason, ConnAck, Connect, Disconnect, DisconnectReason, Packet, PacketDecoder, PacketIdentifierDupQoS, PingReq, PingResp,
+    PubAck, PubComp, PubRec, PubRel, Publication, Publish, QoS, RetainHandling, SubAck, SubAckQos, Subscribe,
+    SubscribeTo, UnsubAck, UnsubAckResult, Unsubscribe,
     decode, encode,
 };
 
 #[cfg(feature = "client")]
 pub(crate) use packet::PacketMeta;
 
+#[cfg(feature = "v5")]
+pub mod v5;
+
 /// The client ID
 ///
 /// Refs:
@@ -128,6 +136,23 @@ fn decode_remaining_length(decoder: &mut RemainingLengthDecoder, src: &mut bytes
     }
 }
 
+/// The size in bytes of the given packet once it is encoded, as an MQTT 5.0 packet without topic aliases if the `v5` feature is enabled.
+///
+/// Only MQTT 5.0 servers advertise a Maximum Packet Size, so this is the size that is compared against it.
+#[allow(clippy::doc_markdown)]
+#[cfg(feature = "client")]
+pub(crate) fn encoded_len(packet: Packet) -> Result<usize, EncodeError> {
+    let mut counter = ByteCounter::new();
+
+    #[cfg(feature = "v5")]
+    v5::encode(packet.into(), &mut counter)?;
+
+    #[cfg(not(feature = "v5"))]
+    packet::encode(packet, &mut counter)?;
+
+    Ok(counter.0)
+}
+
 pub(crate) fn encode_remaining_length<B>(mut item: usize, dst: &mut B) -> Result<(), EncodeError>
 where
     B: ByteBuf,
@@ -207,6 +232,41 @@ impl std::ops::AddAssign<u16> for PacketIdentifier {
     }
 }
 
+/// Whether the given topic name matches the given topic filter, with its `+` and `#` wildcards.
+/// A shared subscription matches the same topic names as the topic filter it shares.
+#[cfg(any(feature = "client", feature = "server"))]
+pub(crate) fn topic_filter_matches(topic_filter: &str, topic_name: &str) -> bool {
+    let topic_filter = split_shared_subscription(topic_filter).map_or(topic_filter, |(_, topic_filter)| topic_filter);
+
+    // Wildcards at the start of a topic filter do not match topic names that start with $, such as $SYS
+    if topic_name.starts_with('$') && (topic_filter.starts_with('+') || topic_filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut topic_filter_levels = topic_filter.split('/');
+    let mut topic_name_levels = topic_name.split('/');
+    loop {
+        match (topic_filter_levels.next(), topic_name_levels.next()) {
+            (Some("#"), _) | (None, None) => return true,
+            (Some("+"), Some(_)) => (),
+            (Some(topic_filter_level), Some(topic_name_level)) if topic_filter_level == topic_name_level => (),
+            _ => return false,
+        }
+    }
+}
+
+/// Splits a shared subscription topic filter `$share/{share name}/{topic filter}` into its share name and the topic filter it shares,
+/// or returns `None` if the topic filter is not a shared subscription.
+///
+/// Shared subscriptions are defined by MQTT 5.0, and many MQTT 3.1.1 servers support them too.
+///
+/// Ref: 4.8.2 Shared Subscriptions (MQTT 5.0)
+#[cfg(any(feature = "client", feature = "server"))]
+pub(crate) fn split_shared_subscription(topic_filter: &str) -> Option<(&str, &str)> {
+    let topic_filter = topic_filter.strip_prefix("$share/")?;
+    Some(topic_filter.split_once('/').unwrap_or((topic_filter, "")))
+}
+
 #[derive(Debug)]
 pub enum DecodeError {
     ConnectReservedSet,
@@ -225,7 +285,9 @@ pub enum DecodeError {
     },
     UnrecognizedProtocolLevel(u8),
     UnrecognizedProtocolName(ByteStr),
+    UnrecognizedProperty(u8),
     UnrecognizedQoS(u8),
+    UnrecognizedSubscriptionOptions(u8),
     ZeroPacketIdentifier,
 }
 
@@ -269,7 +331,13 @@ impl std::fmt::Display for DecodeError {
             DecodeError::UnrecognizedProtocolName(name) => {
                 write!(f, "unexpected protocol name {:?}", name)
             }
+            DecodeError::UnrecognizedProperty(identifier) => {
+                write!(f, "could not parse property with identifier 0x{:02X}", identifier)
+            }
             DecodeError::UnrecognizedQoS(qos) => write!(f, "could not parse QoS 0x{:02X}", qos),
+            DecodeError::UnrecognizedSubscriptionOptions(options) => {
+                write!(f, "could not parse subscription options 0x{:02X}", options)
+            }
             DecodeError::ZeroPacketIdentifier => write!(f, "packet identifier is 0"),
         }
     }
@@ -291,7 +359,9 @@ impl std::error::Error for DecodeError {
             DecodeError::UnrecognizedPacket { .. } => None,
             DecodeError::UnrecognizedProtocolLevel(_) => None,
             DecodeError::UnrecognizedProtocolName(_) => None,
+            DecodeError::UnrecognizedProperty(_) => None,
             DecodeError::UnrecognizedQoS(_) => None,
+            DecodeError::UnrecognizedSubscriptionOptions(_) => None,
             DecodeError::ZeroPacketIdentifier => None,
         }
     }
@@ -305,6 +375,8 @@ impl From<std::io::Error> for DecodeError {
 
 #[derive(Debug)]
 pub enum EncodeError {
+    AuthNotSupported,
+    BinaryDataTooLarge(usize),
     Io(std::io::Error),
     KeepAliveTooHigh(std::time::Duration),
     RemainingLengthTooHigh(usize),
@@ -316,6 +388,8 @@ impl EncodeError {
     pub fn is_user_error(&self) -> bool {
         #[allow(clippy::match_same_arms)]
         match self {
+            EncodeError::AuthNotSupported => false,
+            EncodeError::BinaryDataTooLarge(_) => true,
             EncodeError::Io(_) => false,
             EncodeError::KeepAliveTooHigh(_) => true,
             EncodeError::RemainingLengthTooHigh(_) => true,
@@ -328,6 +402,10 @@ impl EncodeError {
 impl std::fmt::Display for EncodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            EncodeError::AuthNotSupported => f.write_str("AUTH packets cannot be encoded with MQTT 3.1.1"),
+            EncodeError::BinaryDataTooLarge(len) => {
+                write!(f, "binary data of length {} is too large to be encoded", len)
+            }
             EncodeError::Io(err) => write!(f, "I/O error: {}", err),
             EncodeError::KeepAliveTooHigh(keep_alive) => {
                 write!(f, "keep-alive {:?} is too high", keep_alive)
@@ -351,6 +429,8 @@ impl std::error::Error for EncodeError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         #[allow(clippy::match_same_arms)]
         match self {
+            EncodeError::AuthNotSupported => None,
+            EncodeError::BinaryDataTooLarge(_) => None,
             EncodeError::Io(err) => Some(err),
             EncodeError::KeepAliveTooHigh(_) => None,
             EncodeError::RemainingLengthTooHigh(_) => None,
@@ -422,6 +502,8 @@ trait BufMutExt {
 
     fn try_get_u8(&mut self) -> Result<u8, DecodeError>;
     fn try_get_u16_be(&mut self) -> Result<u16, DecodeError>;
+    #[cfg(feature = "v5")]
+    fn try_get_u32_be(&mut self) -> Result<u32, DecodeError>;
     fn try_get_packet_identifier(&mut self) -> Result<PacketIdentifier, DecodeError>;
 }
 
@@ -447,6 +529,15 @@ impl BufMutExt for bytes::BytesMut {
         Ok(self.get_u16())
     }
 
+    #[cfg(feature = "v5")]
+    fn try_get_u32_be(&mut self) -> Result<u32, DecodeError> {
+        if self.len() < std::mem::size_of::<u32>() {
+            return Err(DecodeError::IncompletePacket);
+        }
+
+        Ok(self.get_u32())
+    }
+
     fn try_get_packet_identifier(&mut self) -> Result<PacketIdentifier, DecodeError> {
         if self.len() < std::mem::size_of::<u16>() {
             return Err(DecodeError::IncompletePacket);
@@ -458,6 +549,32 @@ impl BufMutExt for bytes::BytesMut {
 
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn topic_filter_matches() {
+        for &(topic_filter, topic_name, matches) in &[
+            ("sport/tennis/player1", "sport/tennis/player1", true),
+            ("sport/tennis/player1", "sport/tennis/player2", false),
+            ("sport/tennis/player1", "sport/tennis", false),
+            ("sport/tennis/+", "sport/tennis/player1", true),
+            ("sport/tennis/+", "sport/tennis/player1/ranking", false),
+            ("sport/+/player1", "sport/tennis/player1", true),
+            ("+/+", "/finance", true),
+            ("+", "/finance", false),
+            ("sport/#", "sport", true),
+            ("sport/#", "sport/tennis/player1", true),
+            ("sports/#", "sport/tennis", false),
+            ("#", "sport/tennis", true),
+            ("#", "$SYS/uptime", false),
+            ("+/uptime", "$SYS/uptime", false),
+            ("$SYS/#", "$SYS/uptime", true),
+            ("$share/group/sport/+", "sport/tennis", true),
+            ("$share/group/sport/+", "$share/group/sport/tennis", false),
+            ("$share/group/#", "$SYS/uptime", false),
+        ] {
+            assert_eq!(super::topic_filter_matches(topic_filter, topic_name), matches, "{} {}", topic_filter, topic_name);
+        }
+    }
+
     #[test]
     fn remaining_length_encode() {
         remaining_length_encode_inner_ok(0x00, &[0x00]);
@@ -551,6 +668,119 @@ mod tests {
         }
     }
 
+    #[test]
+    fn body_len() {
+        use std::str::FromStr;
+
+        let topic = super::ByteStr::from_str("foo/bar").unwrap();
+        let packet_identifier = super::PacketIdentifier::new(5).unwrap();
+
+        body_len_inner(super::Publish {
+            packet_identifier_dup_qos: super::PacketIdentifierDupQoS::AtMostOnce,
+            retain: false,
+            topic_name: topic.clone(),
+            payload: bytes::Bytes::from_static(b"hello"),
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        });
+        body_len_inner(super::Publish {
+            packet_identifier_dup_qos: super::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, true),
+            retain: true,
+            topic_name: topic.clone(),
+            payload: bytes::Bytes::new(),
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        });
+        body_len_inner(super::SubAck {
+            packet_identifier,
+            qos: vec![super::SubAckQos::Success(super::QoS::AtLeastOnce), super::SubAckQos::Failure],
+        });
+        body_len_inner(super::Subscribe {
+            packet_identifier,
+            subscribe_to: vec![
+                super::SubscribeTo {
+                    topic_filter: topic.clone(),
+                    qos: super::QoS::AtMostOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
+                },
+                super::SubscribeTo {
+                    topic_filter: super::ByteStr::from_str("#").unwrap(),
+                    qos: super::QoS::ExactlyOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
+                },
+            ],
+        });
+        body_len_inner(super::Unsubscribe {
+            packet_identifier,
+            unsubscribe_from: vec![topic, super::ByteStr::from_str("a/+/b").unwrap()],
+        });
+    }
+
+    #[test]
+    fn connect_will() {
+        use std::str::FromStr;
+
+        for &(qos, retain) in &[
+            (super::QoS::AtMostOnce, false),
+            (super::QoS::AtLeastOnce, true),
+            (super::QoS::ExactlyOnce, false),
+            (super::QoS::ExactlyOnce, true),
+        ] {
+            let packet = super::Packet::Connect(super::Connect {
+                username: None,
+                password: None,
+                will: Some(super::Publication {
+                    topic_name: super::ByteStr::from_str("devices/foo/status").unwrap(),
+                    qos,
+                    retain,
+                    payload: bytes::Bytes::from_static(b"offline"),
+                    user_properties: vec![],
+                    response_topic: None,
+                    correlation_data: None,
+                    message_expiry: None,
+                    content_type: None,
+                }),
+                client_id: super::ClientId::IdWithCleanSession(super::ByteStr::from_str("foo").unwrap()),
+                keep_alive: std::time::Duration::from_secs(30),
+                protocol_name: crate::PROTOCOL_NAME,
+                protocol_level: crate::PROTOCOL_LEVEL,
+                session_expiry: None,
+                will_delay: None,
+                maximum_packet_size: None,
+                authentication_method: None,
+                authentication_data: None,
+            });
+
+            let mut bytes = bytes::BytesMut::new();
+            super::encode(packet.clone(), &mut bytes).unwrap();
+            let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+            assert_eq!(decoded, packet);
+            assert!(bytes.is_empty());
+        }
+    }
+
+    fn body_len_inner<P>(packet: P) where P: super::packet::PacketMeta + std::fmt::Debug {
+        let mut counter = super::ByteCounter::new();
+        packet.clone().encode(&mut counter).unwrap();
+        assert_eq!(packet.body_len().unwrap(), counter.0, "{:?}", packet);
+    }
+
     fn remaining_length_decode_inner_incomplete_packet(bytes: &[u8]) {
         use tokio_util::codec::Decoder;
 
@@ -7,6 +7,9 @@ use crate::proto::{BufMutExt, ByteBuf};
 /// An MQTT packet
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Packet {
+    /// Ref: MQTT 5.0 3.15 AUTH – Authentication exchange
+    Auth(Auth),
+
     /// Ref: 3.2 CONNACK – Acknowledge connection request
     ConnAck(ConnAck),
 
@@ -64,6 +67,41 @@ pub trait PacketMeta: Clone + Sized {
     fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
     where
         B: ByteBuf;
+
+    /// The length of the variable header and payload that [`PacketMeta::encode`] would write.
+    ///
+    /// The default implementation encodes a clone of the packet into a [`super::ByteCounter`].
+    /// Packets that are encoded on hot paths override this to compute the length without cloning.
+    fn body_len(&self) -> Result<usize, super::EncodeError> {
+        let mut counter = super::ByteCounter::new();
+        self.clone().encode(&mut counter)?;
+        Ok(counter.0)
+    }
+}
+
+/// Ref: MQTT 5.0 3.15 AUTH – Authentication exchange
+///
+/// The packet of the enhanced authentication of MQTT 5.0. MQTT 3.1.1 has no equivalent, so it cannot be encoded or decoded
+/// with MQTT 3.1.1, only with the codecs of [`crate::proto::v5`].
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Auth {
+    pub reason: AuthReason,
+    pub authentication_method: crate::proto::ByteStr,
+    pub authentication_data: Option<bytes::Bytes>,
+}
+
+/// Why an [`Auth`] packet was sent
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuthReason {
+    /// The server accepted a re-authentication
+    Success,
+
+    /// Either side sent the next step of the exchange
+    ContinueAuthentication,
+
+    /// The client starts a re-authentication on a connection that was already authenticated
+    ReAuthenticate,
 }
 
 /// Ref: 3.2 CONNACK – Acknowledge connection request
@@ -71,6 +109,40 @@ pub trait PacketMeta: Clone + Sized {
 pub struct ConnAck {
     pub session_present: bool,
     pub return_code: super::ConnectReturnCode,
+
+    /// The last data that the server sent in an enhanced authentication exchange, if any. See [`Connect::authentication_method`].
+    pub authentication_data: Option<bytes::Bytes>,
+
+    #[allow(clippy::doc_markdown)]
+    /// The number of QoS 1 and QoS 2 publications that the server is willing to process concurrently, if it limits them.
+    /// Only MQTT 5.0 servers can send this.
+    pub receive_maximum: Option<u16>,
+
+    /// The size in bytes of the largest packet that the server accepts, if it limits them. Only MQTT 5.0 servers can send this.
+    pub maximum_packet_size: Option<u32>,
+
+    /// Another server that the client should connect to instead, such as when this one refuses the connection because it is moving.
+    /// Only MQTT 5.0 servers can send this.
+    pub server_reference: Option<super::ByteStr>,
+
+    /// The client ID that the server assigned to the client, when the client did not send one. Only MQTT 5.0 servers can send this.
+    pub assigned_client_id: Option<super::ByteStr>,
+
+    /// The keep-alive time that the client must use instead of the one it asked for, in whole seconds. Only MQTT 5.0 servers can send this.
+    pub server_keep_alive: Option<std::time::Duration>,
+
+    #[allow(clippy::doc_markdown)]
+    /// The highest quality of service that the server accepts for publications, if it is lower than QoS 2. Only MQTT 5.0 servers can send this.
+    pub maximum_qos: Option<super::QoS>,
+
+    /// Whether the server supports retained messages, if it says so. Only MQTT 5.0 servers can send this.
+    pub retain_available: Option<bool>,
+
+    /// A human-readable explanation of the result of the connection, for diagnostics. Only MQTT 5.0 servers can send this.
+    pub reason_string: Option<super::ByteStr>,
+
+    /// Name and value pairs of metadata about the connection. Only MQTT 5.0 servers can send this.
+    pub user_properties: Vec<(super::ByteStr, super::ByteStr)>,
 }
 
 impl PacketMeta for ConnAck {
@@ -99,6 +171,16 @@ impl PacketMeta for ConnAck {
         Ok(ConnAck {
             session_present,
             return_code,
+            authentication_data: None,
+            receive_maximum: None,
+            maximum_packet_size: None,
+            server_reference: None,
+            assigned_client_id: None,
+            server_keep_alive: None,
+            maximum_qos: None,
+            retain_available: None,
+            reason_string: None,
+            user_properties: vec![],
         })
     }
 
@@ -109,6 +191,16 @@ impl PacketMeta for ConnAck {
         let ConnAck {
             session_present,
             return_code,
+            authentication_data: _,
+            receive_maximum: _,
+            maximum_packet_size: _,
+            server_reference: _,
+            assigned_client_id: _,
+            server_keep_alive: _,
+            maximum_qos: _,
+            retain_available: _,
+            reason_string: _,
+            user_properties: _,
         } = self;
         if session_present {
             dst.put_u8_bytes(0x01);
@@ -132,6 +224,36 @@ pub struct Connect {
     pub keep_alive: std::time::Duration,
     pub protocol_name: crate::proto::ByteStr,
     pub protocol_level: u8,
+
+    /// How long the server keeps the session after the connection is closed, in whole seconds, or `None` if it never expires.
+    ///
+    /// This is the Session Expiry Interval of MQTT 5.0. MQTT 3.1.1 has no equivalent, so it is not encoded and always decodes as `None`;
+    /// the client ID alone decides whether the session is kept.
+    pub session_expiry: Option<std::time::Duration>,
+
+    /// How long the server waits after the connection is lost before it publishes the will, or `None` to publish it right away.
+    /// If the client reconnects to the same session before then, the will is not published at all.
+    ///
+    /// This is the Will Delay Interval of MQTT 5.0, in whole seconds, and is only encoded when there is a will.
+    /// MQTT 3.1.1 has no equivalent, so it is not encoded and always decodes as `None`.
+    #[allow(clippy::doc_markdown)]
+    pub will_delay: Option<std::time::Duration>,
+
+    /// The size in bytes of the largest packet that the client accepts, if it limits them.
+    /// The server does not send larger packets, and drops the publications that would not fit instead.
+    ///
+    /// This is the Maximum Packet Size of MQTT 5.0. MQTT 3.1.1 has no equivalent, so it is not encoded and always decodes as `None`.
+    #[allow(clippy::doc_markdown)]
+    pub maximum_packet_size: Option<u32>,
+
+    /// The method of the enhanced authentication of MQTT 5.0 that the client asks for, such as `SCRAM-SHA-256`, if any.
+    /// The server continues the exchange with [`Packet::Auth`] packets until it accepts or refuses the connection.
+    /// MQTT 3.1.1 has no equivalent, so it is not encoded and always decodes as `None`, like `authentication_data`.
+    #[allow(clippy::doc_markdown)]
+    pub authentication_method: Option<crate::proto::ByteStr>,
+
+    /// The data of the first step of the enhanced authentication exchange, if the method has any
+    pub authentication_data: Option<bytes::Bytes>,
 }
 
 impl std::fmt::Debug for Connect {
@@ -141,6 +263,10 @@ impl std::fmt::Debug for Connect {
             .field("will", &self.will)
             .field("client_id", &self.client_id)
             .field("keep_alive", &self.keep_alive)
+            .field("session_expiry", &self.session_expiry)
+            .field("will_delay", &self.will_delay)
+            .field("maximum_packet_size", &self.maximum_packet_size)
+            .field("authentication_method", &self.authentication_method)
             .finish()
     }
 }
@@ -165,7 +291,11 @@ impl PacketMeta for Connect {
             return Err(super::DecodeError::UnrecognizedProtocolName(protocol_name));
         }
 
+        // The rest of the packet can't be decoded if it's from another version of the protocol, such as the properties of an MQTT 5 CONNECT
         let protocol_level = src.try_get_u8()?;
+        if protocol_level != crate::PROTOCOL_LEVEL {
+            return Err(super::DecodeError::UnrecognizedProtocolLevel(protocol_level));
+        }
 
         let connect_flags = src.try_get_u8()?;
         if connect_flags & 0x01 != 0 {
@@ -215,6 +345,11 @@ impl PacketMeta for Connect {
                 qos,
                 retain,
                 payload,
+                user_properties: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
             })
         };
 
@@ -244,6 +379,11 @@ impl PacketMeta for Connect {
             keep_alive,
             protocol_name,
             protocol_level,
+            session_expiry: None,
+            will_delay: None,
+            maximum_packet_size: None,
+            authentication_method: None,
+            authentication_data: None,
         })
     }
 
@@ -259,6 +399,11 @@ impl PacketMeta for Connect {
             keep_alive,
             protocol_name,
             protocol_level,
+            session_expiry: _,
+            will_delay: _,
+            maximum_packet_size: _,
+            authentication_method: _,
+            authentication_data: _,
         } = self;
 
         protocol_name.encode(dst);
@@ -333,8 +478,32 @@ impl PacketMeta for Connect {
 }
 
 /// Ref: 3.14 DISCONNECT - Disconnect notification
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Disconnect;
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Disconnect {
+    /// Why the connection is being closed. Only encoded and decoded for MQTT 5.0, so it is always [`DisconnectReason::Normal`] for MQTT 3.1.1.
+    pub reason: DisconnectReason,
+
+    /// Another server that the client should connect to instead, when the server disconnects the client because it is moving.
+    /// Only MQTT 5.0 servers can send this.
+    pub server_reference: Option<super::ByteStr>,
+}
+
+/// Why a [`Disconnect`] packet was sent
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DisconnectReason {
+    /// The connection is closed normally, and the server discards the will of the client
+    #[default]
+    Normal,
+
+    /// The client closes the connection, and wants the server to publish its will anyway
+    WithWill,
+
+    /// The server closes the connection because it is shutting down
+    ServerShuttingDown,
+
+    /// Any other reason code of MQTT 5.0, such as for an error
+    Other(u8),
+}
 
 impl PacketMeta for Disconnect {
     const PACKET_TYPE: u8 = 0xE0;
@@ -348,7 +517,10 @@ impl PacketMeta for Disconnect {
             });
         }
 
-        Ok(Disconnect)
+        Ok(Disconnect {
+            reason: DisconnectReason::Normal,
+            server_reference: None,
+        })
     }
 
     fn encode<B>(self, _: &mut B) -> Result<(), super::EncodeError>
@@ -487,6 +659,25 @@ pub struct Publish {
     pub retain: bool,
     pub topic_name: crate::proto::ByteStr,
     pub payload: bytes::Bytes,
+
+    /// Only encoded for MQTT 5.0. See [`Publication::user_properties`].
+    pub user_properties: Vec<(crate::proto::ByteStr, crate::proto::ByteStr)>,
+
+    /// Only decoded for MQTT 5.0. The subscription identifiers of the subscriptions that this publication matched,
+    /// see [`SubscribeTo::subscription_identifier`].
+    pub subscription_identifiers: Vec<u32>,
+
+    /// Only encoded and decoded for MQTT 5.0. See [`Publication::response_topic`].
+    pub response_topic: Option<crate::proto::ByteStr>,
+
+    /// Only encoded and decoded for MQTT 5.0. See [`Publication::correlation_data`].
+    pub correlation_data: Option<bytes::Bytes>,
+
+    /// Only encoded and decoded for MQTT 5.0. See [`Publication::message_expiry`].
+    pub message_expiry: Option<std::time::Duration>,
+
+    /// Only encoded and decoded for MQTT 5.0. See [`Publication::content_type`].
+    pub content_type: Option<crate::proto::ByteStr>,
 }
 
 impl PacketMeta for Publish {
@@ -525,6 +716,12 @@ impl PacketMeta for Publish {
             retain,
             topic_name,
             payload,
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
         })
     }
 
@@ -538,6 +735,12 @@ impl PacketMeta for Publish {
             retain: _,
             topic_name,
             payload,
+            user_properties: _,
+            subscription_identifiers: _,
+            response_topic: _,
+            correlation_data: _,
+            message_expiry: _,
+            content_type: _,
         } = self;
 
         topic_name.encode(dst);
@@ -554,6 +757,16 @@ impl PacketMeta for Publish {
 
         Ok(())
     }
+
+    fn body_len(&self) -> Result<usize, super::EncodeError> {
+        let packet_identifier_len = match self.packet_identifier_dup_qos {
+            PacketIdentifierDupQoS::AtMostOnce => 0,
+            PacketIdentifierDupQoS::AtLeastOnce(_, _)
+            | PacketIdentifierDupQoS::ExactlyOnce(_, _) => std::mem::size_of::<u16>(),
+        };
+
+        Ok(self.topic_name.encoded_len() + packet_identifier_len + self.payload.len())
+    }
 }
 
 #[allow(clippy::doc_markdown)]
@@ -684,6 +897,10 @@ impl PacketMeta for SubAck {
 
         Ok(())
     }
+
+    fn body_len(&self) -> Result<usize, super::EncodeError> {
+        Ok(std::mem::size_of::<u16>() + self.qos.len() * std::mem::size_of::<u8>())
+    }
 }
 
 /// Ref: 3.8 SUBSCRIBE - Subscribe to topics
@@ -719,7 +936,15 @@ impl PacketMeta for Subscribe {
                 0x02 => QoS::ExactlyOnce,
                 qos => return Err(super::DecodeError::UnrecognizedQoS(qos)),
             };
-            subscribe_to.push(SubscribeTo { topic_filter, qos });
+            subscribe_to.push(SubscribeTo {
+                topic_filter,
+                qos,
+                user_properties: vec![],
+                subscription_identifier: None,
+                no_local: false,
+                retain_as_published: false,
+                retain_handling: Default::default(),
+            });
         }
 
         if subscribe_to.is_empty() {
@@ -743,19 +968,29 @@ impl PacketMeta for Subscribe {
 
         dst.put_packet_identifier_bytes(packet_identifier);
 
-        for SubscribeTo { topic_filter, qos } in subscribe_to {
+        for SubscribeTo { topic_filter, qos, .. } in subscribe_to {
             topic_filter.encode(dst);
             dst.put_u8_bytes(qos.into());
         }
 
         Ok(())
     }
+
+    fn body_len(&self) -> Result<usize, super::EncodeError> {
+        Ok(self.subscribe_to.iter().fold(std::mem::size_of::<u16>(), |len, subscribe_to| {
+            len + subscribe_to.topic_filter.encoded_len() + std::mem::size_of::<u8>()
+        }))
+    }
 }
 
 /// Ref: 3.11 UNSUBACK – Unsubscribe acknowledgement
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct UnsubAck {
     pub packet_identifier: super::PacketIdentifier,
+
+    /// The result of unsubscribing from each topic filter of the UNSUBSCRIBE, in order. Only MQTT 5.0 servers can send this,
+    /// so it is empty for MQTT 3.1.1.
+    pub results: Vec<UnsubAckResult>,
 }
 
 impl PacketMeta for UnsubAck {
@@ -772,14 +1007,17 @@ impl PacketMeta for UnsubAck {
 
         let packet_identifier = src.get_packet_identifier()?;
 
-        Ok(UnsubAck { packet_identifier })
+        Ok(UnsubAck {
+            packet_identifier,
+            results: vec![],
+        })
     }
 
     fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
     where
         B: ByteBuf,
     {
-        let UnsubAck { packet_identifier } = self;
+        let UnsubAck { packet_identifier, .. } = self;
         dst.put_packet_identifier_bytes(packet_identifier);
         Ok(())
     }
@@ -842,6 +1080,12 @@ impl PacketMeta for Unsubscribe {
 
         Ok(())
     }
+
+    fn body_len(&self) -> Result<usize, super::EncodeError> {
+        Ok(self.unsubscribe_from.iter().fold(std::mem::size_of::<u16>(), |len, unsubscribe_from| {
+            len + unsubscribe_from.encoded_len()
+        }))
+    }
 }
 
 #[allow(clippy::doc_markdown)]
@@ -859,6 +1103,41 @@ pub enum PacketIdentifierDupQoS {
 pub struct SubscribeTo {
     pub topic_filter: crate::proto::ByteStr,
     pub qos: QoS,
+
+    /// User properties sent with the SUBSCRIBE packet of this subscription to an MQTT 5.0 server, such as for routing or authorization.
+    /// They are not sent to MQTT 3.1.1 servers.
+    pub user_properties: Vec<(crate::proto::ByteStr, crate::proto::ByteStr)>,
+
+    /// An identifier between 1 and 268,435,455 that an MQTT 5.0 server attaches to every publication that matches this subscription,
+    /// so that the application can route the publication by its subscription identifiers without matching its topic name
+    /// against topic filters again. It is not sent to MQTT 3.1.1 servers.
+    pub subscription_identifier: Option<u32>,
+
+    /// Whether an MQTT 5.0 server should not send the client's own publications that match this subscription back to it,
+    /// such as to keep a bridge from looping publications between two servers. It is not sent to MQTT 3.1.1 servers.
+    pub no_local: bool,
+
+    /// Whether an MQTT 5.0 server should keep the retain flag of the publications it forwards for this subscription as they were published,
+    /// rather than clearing it. It is not sent to MQTT 3.1.1 servers.
+    pub retain_as_published: bool,
+
+    /// Whether an MQTT 5.0 server should send the retained publications that match this subscription when it is made.
+    /// It is not sent to MQTT 3.1.1 servers, which always send them.
+    pub retain_handling: RetainHandling,
+}
+
+/// Whether the server sends retained publications when a subscription is made
+///
+/// Ref: 3.8.3.1 Subscription Options
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RetainHandling {
+    #[default]
+    SendOnSubscribe,
+
+    /// Only send retained publications if the subscription did not exist before
+    SendOnNewSubscribe,
+
+    DoNotSend,
 }
 
 /// The level of reliability for a publication
@@ -904,6 +1183,17 @@ impl From<SubAckQos> for u8 {
     }
 }
 
+/// The result of unsubscribing from one topic filter, returned in an UNSUBACK packet by MQTT 5.0 servers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnsubAckResult {
+    Success,
+
+    /// The client was not subscribed to the topic filter.
+    NoSubscriptionExisted,
+
+    Failure,
+}
+
 /// A message that can be published to the server
 //  but not yet assigned a packet identifier.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -912,6 +1202,28 @@ pub struct Publication {
     pub qos: crate::proto::QoS,
     pub retain: bool,
     pub payload: bytes::Bytes,
+
+    /// Name and value pairs of metadata that travel with the publication, for routing without looking into the payload.
+    /// They are sent to and received from MQTT 5.0 servers, and are not sent to MQTT 3.1.1 servers.
+    pub user_properties: Vec<(crate::proto::ByteStr, crate::proto::ByteStr)>,
+
+    /// For a request, the topic that the receiver should publish its response to. Like user properties, it is only sent to MQTT 5.0 servers.
+    pub response_topic: Option<crate::proto::ByteStr>,
+
+    /// For a request, data that the receiver sends back with its response, so that the requester can tell which request it responds to.
+    /// For a response, the data of the request. Like user properties, it is only sent to MQTT 5.0 servers.
+    pub correlation_data: Option<bytes::Bytes>,
+
+    /// How long the publication stays relevant, or `None` if it never expires.
+    ///
+    /// The client drops a publication that is still queued once it expires, such as while it is disconnected, and fails its publish request
+    /// with [`crate::PublishError::Expired`]. MQTT 5.0 servers are sent the time that remains, in whole seconds, and drop the publication
+    /// themselves once that has passed instead of delivering it to subscribers that were offline.
+    #[allow(clippy::doc_markdown)]
+    pub message_expiry: Option<std::time::Duration>,
+
+    /// The MIME type of the payload, such as `application/json`, for the receiver to interpret it. Like user properties, it is only sent to MQTT 5.0 servers.
+    pub content_type: Option<crate::proto::ByteStr>,
 }
 
 /// A decoder for MQTT packets.
@@ -942,7 +1254,15 @@ impl Default for PacketDecoderState {
 }
 
 pub fn decode(decoder: &mut PacketDecoder, src: &mut bytes::BytesMut) -> Result<Option<Packet>, super::DecodeError> {
-    let (first_byte, src) = loop {
+    match decode_fixed_header(decoder, src)? {
+        Some((first_byte, src)) => decode_body(first_byte, src).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Reads the fixed header of the next packet, and returns its first byte and its body once the whole packet has been read
+pub(super) fn decode_fixed_header(decoder: &mut PacketDecoder, src: &mut bytes::BytesMut) -> Result<Option<(u8, bytes::BytesMut)>, super::DecodeError> {
+    loop {
         match &mut decoder.decoder_state {
             PacketDecoderState::Empty => {
                 let first_byte = match src.try_get_u8() {
@@ -979,31 +1299,34 @@ pub fn decode(decoder: &mut PacketDecoder, src: &mut bytes::BytesMut) -> Result<
                 let first_byte = *first_byte;
                 let src = src.split_to(*remaining_length);
                 decoder.decoder_state = PacketDecoderState::Empty;
-                break (first_byte, src);
+                return Ok(Some((first_byte, src)));
             }
         }
-    };
+    }
+}
 
+/// Decodes the body of a packet whose fixed header starts with the given byte
+pub(super) fn decode_body(first_byte: u8, src: bytes::BytesMut) -> Result<Packet, super::DecodeError> {
     let packet_type = first_byte & 0xF0;
     let flags = first_byte & 0x0F;
     match packet_type {
-        ConnAck::PACKET_TYPE => Ok(Some(Packet::ConnAck(ConnAck::decode(flags, src)?))),
-        Connect::PACKET_TYPE => Ok(Some(Packet::Connect(Connect::decode(flags, src)?))),
+        ConnAck::PACKET_TYPE => Ok(Packet::ConnAck(ConnAck::decode(flags, src)?)),
+        Connect::PACKET_TYPE => Ok(Packet::Connect(Connect::decode(flags, src)?)),
         Disconnect::PACKET_TYPE => {
-            Ok(Some(Packet::Disconnect(Disconnect::decode(flags, src)?)))
+            Ok(Packet::Disconnect(Disconnect::decode(flags, src)?))
         }
-        PingReq::PACKET_TYPE => Ok(Some(Packet::PingReq(PingReq::decode(flags, src)?))),
-        PingResp::PACKET_TYPE => Ok(Some(Packet::PingResp(PingResp::decode(flags, src)?))),
-        PubAck::PACKET_TYPE => Ok(Some(Packet::PubAck(PubAck::decode(flags, src)?))),
-        PubComp::PACKET_TYPE => Ok(Some(Packet::PubComp(PubComp::decode(flags, src)?))),
-        Publish::PACKET_TYPE => Ok(Some(Packet::Publish(Publish::decode(flags, src)?))),
-        PubRec::PACKET_TYPE => Ok(Some(Packet::PubRec(PubRec::decode(flags, src)?))),
-        PubRel::PACKET_TYPE => Ok(Some(Packet::PubRel(PubRel::decode(flags, src)?))),
-        SubAck::PACKET_TYPE => Ok(Some(Packet::SubAck(SubAck::decode(flags, src)?))),
-        Subscribe::PACKET_TYPE => Ok(Some(Packet::Subscribe(Subscribe::decode(flags, src)?))),
-        UnsubAck::PACKET_TYPE => Ok(Some(Packet::UnsubAck(UnsubAck::decode(flags, src)?))),
+        PingReq::PACKET_TYPE => Ok(Packet::PingReq(PingReq::decode(flags, src)?)),
+        PingResp::PACKET_TYPE => Ok(Packet::PingResp(PingResp::decode(flags, src)?)),
+        PubAck::PACKET_TYPE => Ok(Packet::PubAck(PubAck::decode(flags, src)?)),
+        PubComp::PACKET_TYPE => Ok(Packet::PubComp(PubComp::decode(flags, src)?)),
+        Publish::PACKET_TYPE => Ok(Packet::Publish(Publish::decode(flags, src)?)),
+        PubRec::PACKET_TYPE => Ok(Packet::PubRec(PubRec::decode(flags, src)?)),
+        PubRel::PACKET_TYPE => Ok(Packet::PubRel(PubRel::decode(flags, src)?)),
+        SubAck::PACKET_TYPE => Ok(Packet::SubAck(SubAck::decode(flags, src)?)),
+        Subscribe::PACKET_TYPE => Ok(Packet::Subscribe(Subscribe::decode(flags, src)?)),
+        UnsubAck::PACKET_TYPE => Ok(Packet::UnsubAck(UnsubAck::decode(flags, src)?)),
         Unsubscribe::PACKET_TYPE => {
-            Ok(Some(Packet::Unsubscribe(Unsubscribe::decode(flags, src)?)))
+            Ok(Packet::Unsubscribe(Unsubscribe::decode(flags, src)?))
         }
         packet_type => Err(super::DecodeError::UnrecognizedPacket {
             packet_type,
@@ -1014,27 +1337,8 @@ pub fn decode(decoder: &mut PacketDecoder, src: &mut bytes::BytesMut) -> Result<
 }
 
 pub fn encode<B>(item: Packet, dst: &mut B) -> Result<(), super::EncodeError> where B: ByteBuf {
-    fn encode_inner<P, B>(
-        packet: P,
-        flags: u8,
-        dst: &mut B,
-    ) -> Result<(), super::EncodeError>
-    where
-        P: PacketMeta,
-        B: ByteBuf,
-    {
-        let mut counter = super::ByteCounter::new();
-        packet.clone().encode(&mut counter)?;
-        let body_len = counter.0;
-
-        dst.put_u8_bytes(<P as PacketMeta>::PACKET_TYPE | flags);
-        super::encode_remaining_length(body_len, dst)?;
-        packet.encode(dst)?;
-
-        Ok(())
-    }
-
     match item {
+        Packet::Auth(_) => Err(super::EncodeError::AuthNotSupported),
         Packet::ConnAck(packet) => encode_inner(packet, 0, dst),
         Packet::Connect(packet) => encode_inner(packet, 0, dst),
         Packet::Disconnect(packet) => encode_inner(packet, 0, dst),
@@ -1063,3 +1367,22 @@ pub fn encode<B>(item: Packet, dst: &mut B) -> Result<(), super::EncodeError> wh
         Packet::Unsubscribe(packet) => encode_inner(packet, 0x02, dst),
     }
 }
+
+/// Encodes the fixed header, variable header and payload of the given packet
+pub(super) fn encode_inner<P, B>(
+    packet: P,
+    flags: u8,
+    dst: &mut B,
+) -> Result<(), super::EncodeError>
+where
+    P: PacketMeta,
+    B: ByteBuf,
+{
+    let body_len = packet.body_len()?;
+
+    dst.put_u8_bytes(<P as PacketMeta>::PACKET_TYPE | flags);
+    super::encode_remaining_length(body_len, dst)?;
+    packet.encode(dst)?;
+
+    Ok(())
+}
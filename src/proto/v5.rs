@@ -0,0 +1,2731 @@
+/*!
+ * MQTT 5.0 protocol types.
+ *
+ * The packets of MQTT 5.0 carry properties, and their acknowledgements carry reason codes. [`NegotiatedDecoder`] and [`NegotiatedEncoder`]
+ * let a transport speak either version of the protocol while exchanging the MQTT 3.1.1 packets of [`crate::proto`]
+ * with a [`Client`](crate::Client) or server.
+ */
+
+use std::convert::{TryFrom, TryInto};
+
+use super::packet::PacketMeta;
+use super::{BufMutExt, ByteBuf};
+
+/// The protocol level of MQTT 5.0, sent in the CONNECT packet
+#[allow(clippy::doc_markdown)]
+pub const PROTOCOL_LEVEL: u8 = 0x05;
+
+/// An MQTT 5.0 packet
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Packet {
+    /// Ref: 3.15 AUTH – Authentication exchange
+    Auth(Auth),
+
+    /// Ref: 3.2 CONNACK – Connect acknowledgement
+    ConnAck(ConnAck),
+
+    /// Ref: 3.1 CONNECT – Connection Request
+    Connect(Connect),
+
+    /// Ref: 3.14 DISCONNECT – Disconnect notification
+    Disconnect(Disconnect),
+
+    /// Ref: 3.12 PINGREQ – PING request
+    PingReq(super::PingReq),
+
+    /// Ref: 3.13 PINGRESP – PING response
+    PingResp(super::PingResp),
+
+    /// Ref: 3.4 PUBACK – Publish acknowledgement
+    PubAck(PubAck),
+
+    /// Ref: 3.7 PUBCOMP – Publish complete (QoS 2 delivery part 3)
+    PubComp(PubComp),
+
+    /// Ref: 3.3 PUBLISH – Publish message
+    Publish(Publish),
+
+    /// Ref: 3.5 PUBREC – Publish received (QoS 2 delivery part 1)
+    PubRec(PubRec),
+
+    /// Ref: 3.6 PUBREL – Publish release (QoS 2 delivery part 2)
+    PubRel(PubRel),
+
+    /// Ref: 3.9 SUBACK – Subscribe acknowledgement
+    SubAck(SubAck),
+
+    /// Ref: 3.8 SUBSCRIBE - Subscribe request
+    Subscribe(Subscribe),
+
+    /// Ref: 3.11 UNSUBACK – Unsubscribe acknowledgement
+    UnsubAck(UnsubAck),
+
+    /// Ref: 3.10 UNSUBSCRIBE – Unsubscribe request
+    Unsubscribe(Unsubscribe),
+}
+
+/// The result of an operation, carried by CONNACK, the acknowledgements, DISCONNECT and AUTH.
+/// Values below 0x80 indicate success, the others indicate failure.
+///
+/// Ref: 2.4 Reason Code
+#[allow(clippy::doc_markdown)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ReasonCode(pub u8);
+
+impl ReasonCode {
+    pub const SUCCESS: Self = ReasonCode(0x00);
+    pub const NORMAL_DISCONNECTION: Self = ReasonCode(0x00);
+    pub const GRANTED_QOS_0: Self = ReasonCode(0x00);
+    pub const GRANTED_QOS_1: Self = ReasonCode(0x01);
+    pub const GRANTED_QOS_2: Self = ReasonCode(0x02);
+    pub const DISCONNECT_WITH_WILL_MESSAGE: Self = ReasonCode(0x04);
+    pub const NO_MATCHING_SUBSCRIBERS: Self = ReasonCode(0x10);
+    pub const NO_SUBSCRIPTION_EXISTED: Self = ReasonCode(0x11);
+    pub const CONTINUE_AUTHENTICATION: Self = ReasonCode(0x18);
+    pub const REAUTHENTICATE: Self = ReasonCode(0x19);
+    pub const UNSPECIFIED_ERROR: Self = ReasonCode(0x80);
+    pub const MALFORMED_PACKET: Self = ReasonCode(0x81);
+    pub const PROTOCOL_ERROR: Self = ReasonCode(0x82);
+    pub const IMPLEMENTATION_SPECIFIC_ERROR: Self = ReasonCode(0x83);
+    pub const UNSUPPORTED_PROTOCOL_VERSION: Self = ReasonCode(0x84);
+    pub const CLIENT_IDENTIFIER_NOT_VALID: Self = ReasonCode(0x85);
+    pub const BAD_USER_NAME_OR_PASSWORD: Self = ReasonCode(0x86);
+    pub const NOT_AUTHORIZED: Self = ReasonCode(0x87);
+    pub const SERVER_UNAVAILABLE: Self = ReasonCode(0x88);
+    pub const SERVER_BUSY: Self = ReasonCode(0x89);
+    pub const BANNED: Self = ReasonCode(0x8A);
+    pub const SERVER_SHUTTING_DOWN: Self = ReasonCode(0x8B);
+    pub const BAD_AUTHENTICATION_METHOD: Self = ReasonCode(0x8C);
+    pub const KEEP_ALIVE_TIMEOUT: Self = ReasonCode(0x8D);
+    pub const SESSION_TAKEN_OVER: Self = ReasonCode(0x8E);
+    pub const TOPIC_FILTER_INVALID: Self = ReasonCode(0x8F);
+    pub const TOPIC_NAME_INVALID: Self = ReasonCode(0x90);
+    pub const PACKET_IDENTIFIER_IN_USE: Self = ReasonCode(0x91);
+    pub const PACKET_IDENTIFIER_NOT_FOUND: Self = ReasonCode(0x92);
+    pub const RECEIVE_MAXIMUM_EXCEEDED: Self = ReasonCode(0x93);
+    pub const TOPIC_ALIAS_INVALID: Self = ReasonCode(0x94);
+    pub const PACKET_TOO_LARGE: Self = ReasonCode(0x95);
+    pub const MESSAGE_RATE_TOO_HIGH: Self = ReasonCode(0x96);
+    pub const QUOTA_EXCEEDED: Self = ReasonCode(0x97);
+    pub const ADMINISTRATIVE_ACTION: Self = ReasonCode(0x98);
+    pub const PAYLOAD_FORMAT_INVALID: Self = ReasonCode(0x99);
+    pub const RETAIN_NOT_SUPPORTED: Self = ReasonCode(0x9A);
+    pub const QOS_NOT_SUPPORTED: Self = ReasonCode(0x9B);
+    pub const USE_ANOTHER_SERVER: Self = ReasonCode(0x9C);
+    pub const SERVER_MOVED: Self = ReasonCode(0x9D);
+    pub const SHARED_SUBSCRIPTIONS_NOT_SUPPORTED: Self = ReasonCode(0x9E);
+    pub const CONNECTION_RATE_EXCEEDED: Self = ReasonCode(0x9F);
+    pub const MAXIMUM_CONNECT_TIME: Self = ReasonCode(0xA0);
+    pub const SUBSCRIPTION_IDENTIFIERS_NOT_SUPPORTED: Self = ReasonCode(0xA1);
+    pub const WILDCARD_SUBSCRIPTIONS_NOT_SUPPORTED: Self = ReasonCode(0xA2);
+
+    /// Whether this reason code indicates success
+    pub fn is_success(self) -> bool {
+        self.0 < 0x80
+    }
+}
+
+/// A property of a packet
+///
+/// Ref: 2.2.2 Properties
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Property {
+    PayloadFormatIndicator(u8),
+    MessageExpiryInterval(u32),
+    ContentType(super::ByteStr),
+    ResponseTopic(super::ByteStr),
+    CorrelationData(bytes::Bytes),
+    SubscriptionIdentifier(u32),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(super::ByteStr),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(super::ByteStr),
+    AuthenticationData(bytes::Bytes),
+    RequestProblemInformation(u8),
+    WillDelayInterval(u32),
+    RequestResponseInformation(u8),
+    ResponseInformation(super::ByteStr),
+    ServerReference(super::ByteStr),
+    ReasonString(super::ByteStr),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    MaximumQoS(u8),
+    RetainAvailable(u8),
+    UserProperty(super::ByteStr, super::ByteStr),
+    MaximumPacketSize(u32),
+    WildcardSubscriptionAvailable(u8),
+    SubscriptionIdentifierAvailable(u8),
+    SharedSubscriptionAvailable(u8),
+}
+
+impl Property {
+    fn decode(src: &mut bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        let property = match src.try_get_u8()? {
+            0x01 => Property::PayloadFormatIndicator(src.try_get_u8()?),
+            0x02 => Property::MessageExpiryInterval(src.try_get_u32_be()?),
+            0x03 => Property::ContentType(decode_byte_str(src)?),
+            0x08 => Property::ResponseTopic(decode_byte_str(src)?),
+            0x09 => Property::CorrelationData(decode_binary_data(src)?),
+            0x0B => Property::SubscriptionIdentifier(decode_variable_byte_integer(src)?),
+            0x11 => Property::SessionExpiryInterval(src.try_get_u32_be()?),
+            0x12 => Property::AssignedClientIdentifier(decode_byte_str(src)?),
+            0x13 => Property::ServerKeepAlive(src.try_get_u16_be()?),
+            0x15 => Property::AuthenticationMethod(decode_byte_str(src)?),
+            0x16 => Property::AuthenticationData(decode_binary_data(src)?),
+            0x17 => Property::RequestProblemInformation(src.try_get_u8()?),
+            0x18 => Property::WillDelayInterval(src.try_get_u32_be()?),
+            0x19 => Property::RequestResponseInformation(src.try_get_u8()?),
+            0x1A => Property::ResponseInformation(decode_byte_str(src)?),
+            0x1C => Property::ServerReference(decode_byte_str(src)?),
+            0x1F => Property::ReasonString(decode_byte_str(src)?),
+            0x21 => Property::ReceiveMaximum(src.try_get_u16_be()?),
+            0x22 => Property::TopicAliasMaximum(src.try_get_u16_be()?),
+            0x23 => Property::TopicAlias(src.try_get_u16_be()?),
+            0x24 => Property::MaximumQoS(src.try_get_u8()?),
+            0x25 => Property::RetainAvailable(src.try_get_u8()?),
+            0x26 => Property::UserProperty(decode_byte_str(src)?, decode_byte_str(src)?),
+            0x27 => Property::MaximumPacketSize(src.try_get_u32_be()?),
+            0x28 => Property::WildcardSubscriptionAvailable(src.try_get_u8()?),
+            0x29 => Property::SubscriptionIdentifierAvailable(src.try_get_u8()?),
+            0x2A => Property::SharedSubscriptionAvailable(src.try_get_u8()?),
+            identifier => return Err(super::DecodeError::UnrecognizedProperty(identifier)),
+        };
+        Ok(property)
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        match self {
+            Property::PayloadFormatIndicator(value) => {
+                dst.put_u8_bytes(0x01);
+                dst.put_u8_bytes(value);
+            }
+            Property::MessageExpiryInterval(value) => {
+                dst.put_u8_bytes(0x02);
+                put_u32_bytes(dst, value);
+            }
+            Property::ContentType(value) => {
+                dst.put_u8_bytes(0x03);
+                value.encode(dst);
+            }
+            Property::ResponseTopic(value) => {
+                dst.put_u8_bytes(0x08);
+                value.encode(dst);
+            }
+            Property::CorrelationData(value) => {
+                dst.put_u8_bytes(0x09);
+                put_binary_data_bytes(dst, value)?;
+            }
+            Property::SubscriptionIdentifier(value) => {
+                dst.put_u8_bytes(0x0B);
+                super::encode_remaining_length(value as usize, dst)?;
+            }
+            Property::SessionExpiryInterval(value) => {
+                dst.put_u8_bytes(0x11);
+                put_u32_bytes(dst, value);
+            }
+            Property::AssignedClientIdentifier(value) => {
+                dst.put_u8_bytes(0x12);
+                value.encode(dst);
+            }
+            Property::ServerKeepAlive(value) => {
+                dst.put_u8_bytes(0x13);
+                dst.put_u16_bytes(value);
+            }
+            Property::AuthenticationMethod(value) => {
+                dst.put_u8_bytes(0x15);
+                value.encode(dst);
+            }
+            Property::AuthenticationData(value) => {
+                dst.put_u8_bytes(0x16);
+                put_binary_data_bytes(dst, value)?;
+            }
+            Property::RequestProblemInformation(value) => {
+                dst.put_u8_bytes(0x17);
+                dst.put_u8_bytes(value);
+            }
+            Property::WillDelayInterval(value) => {
+                dst.put_u8_bytes(0x18);
+                put_u32_bytes(dst, value);
+            }
+            Property::RequestResponseInformation(value) => {
+                dst.put_u8_bytes(0x19);
+                dst.put_u8_bytes(value);
+            }
+            Property::ResponseInformation(value) => {
+                dst.put_u8_bytes(0x1A);
+                value.encode(dst);
+            }
+            Property::ServerReference(value) => {
+                dst.put_u8_bytes(0x1C);
+                value.encode(dst);
+            }
+            Property::ReasonString(value) => {
+                dst.put_u8_bytes(0x1F);
+                value.encode(dst);
+            }
+            Property::ReceiveMaximum(value) => {
+                dst.put_u8_bytes(0x21);
+                dst.put_u16_bytes(value);
+            }
+            Property::TopicAliasMaximum(value) => {
+                dst.put_u8_bytes(0x22);
+                dst.put_u16_bytes(value);
+            }
+            Property::TopicAlias(value) => {
+                dst.put_u8_bytes(0x23);
+                dst.put_u16_bytes(value);
+            }
+            Property::MaximumQoS(value) => {
+                dst.put_u8_bytes(0x24);
+                dst.put_u8_bytes(value);
+            }
+            Property::RetainAvailable(value) => {
+                dst.put_u8_bytes(0x25);
+                dst.put_u8_bytes(value);
+            }
+            Property::UserProperty(name, value) => {
+                dst.put_u8_bytes(0x26);
+                name.encode(dst);
+                value.encode(dst);
+            }
+            Property::MaximumPacketSize(value) => {
+                dst.put_u8_bytes(0x27);
+                put_u32_bytes(dst, value);
+            }
+            Property::WildcardSubscriptionAvailable(value) => {
+                dst.put_u8_bytes(0x28);
+                dst.put_u8_bytes(value);
+            }
+            Property::SubscriptionIdentifierAvailable(value) => {
+                dst.put_u8_bytes(0x29);
+                dst.put_u8_bytes(value);
+            }
+            Property::SharedSubscriptionAvailable(value) => {
+                dst.put_u8_bytes(0x2A);
+                dst.put_u8_bytes(value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Ref: 3.15 AUTH – Authentication exchange
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Auth {
+    pub reason_code: ReasonCode,
+    pub properties: Vec<Property>,
+}
+
+impl PacketMeta for Auth {
+    const PACKET_TYPE: u8 = 0xF0;
+
+    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        if flags != 0 {
+            return Err(super::DecodeError::UnrecognizedPacket {
+                packet_type: Self::PACKET_TYPE,
+                flags,
+                remaining_length: src.len(),
+            });
+        }
+
+        let (reason_code, properties) = decode_optional_reason_code(&mut src)?;
+
+        Ok(Auth {
+            reason_code,
+            properties,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let Auth {
+            reason_code,
+            properties,
+        } = self;
+        encode_optional_reason_code(reason_code, properties, dst)
+    }
+}
+
+/// Ref: 3.2 CONNACK – Connect acknowledgement
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConnAck {
+    pub session_present: bool,
+    pub reason_code: ReasonCode,
+    pub properties: Vec<Property>,
+}
+
+impl PacketMeta for ConnAck {
+    const PACKET_TYPE: u8 = 0x20;
+
+    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        if flags != 0 || src.len() < std::mem::size_of::<u8>() + std::mem::size_of::<u8>() {
+            return Err(super::DecodeError::UnrecognizedPacket {
+                packet_type: Self::PACKET_TYPE,
+                flags,
+                remaining_length: src.len(),
+            });
+        }
+
+        let connack_flags = src.try_get_u8()?;
+        let session_present = match connack_flags {
+            0x00 => false,
+            0x01 => true,
+            connack_flags => {
+                return Err(super::DecodeError::UnrecognizedConnAckFlags(connack_flags));
+            }
+        };
+
+        let reason_code = ReasonCode(src.try_get_u8()?);
+
+        // A server that does not support MQTT 5.0 refuses the connection with a CONNACK that has no properties
+        let properties = if src.is_empty() { vec![] } else { decode_properties(&mut src)? };
+
+        Ok(ConnAck {
+            session_present,
+            reason_code,
+            properties,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let ConnAck {
+            session_present,
+            reason_code,
+            properties,
+        } = self;
+
+        dst.put_u8_bytes(u8::from(session_present));
+        dst.put_u8_bytes(reason_code.0);
+        encode_properties(properties, dst)
+    }
+}
+
+/// Ref: 3.1 CONNECT – Connection Request
+#[derive(Clone, Eq, PartialEq)]
+pub struct Connect {
+    pub username: Option<super::ByteStr>,
+    pub password: Option<super::ByteStr>,
+    pub will: Option<Will>,
+    pub client_id: super::ClientId,
+    pub keep_alive: std::time::Duration,
+    pub properties: Vec<Property>,
+}
+
+impl std::fmt::Debug for Connect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connect")
+            .field("username", &self.username)
+            .field("will", &self.will)
+            .field("client_id", &self.client_id)
+            .field("keep_alive", &self.keep_alive)
+            .field("properties", &self.properties)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The will of a [`Connect`], with its properties. User properties are encoded from `properties`, not from those of the publication.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Will {
+    pub publication: super::Publication,
+    pub properties: Vec<Property>,
+}
+
+impl PacketMeta for Connect {
+    const PACKET_TYPE: u8 = 0x10;
+
+    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        if flags != 0 {
+            return Err(super::DecodeError::UnrecognizedPacket {
+                packet_type: Self::PACKET_TYPE,
+                flags,
+                remaining_length: src.len(),
+            });
+        }
+
+        let protocol_name = decode_byte_str(&mut src)?;
+        #[allow(clippy::borrow_interior_mutable_const)]
+        if protocol_name != crate::PROTOCOL_NAME {
+            return Err(super::DecodeError::UnrecognizedProtocolName(protocol_name));
+        }
+
+        let protocol_level = src.try_get_u8()?;
+        if protocol_level != PROTOCOL_LEVEL {
+            return Err(super::DecodeError::UnrecognizedProtocolLevel(protocol_level));
+        }
+
+        let connect_flags = src.try_get_u8()?;
+        if connect_flags & 0x01 != 0 {
+            return Err(super::DecodeError::ConnectReservedSet);
+        }
+
+        let keep_alive = std::time::Duration::from_secs(u64::from(src.try_get_u16_be()?));
+
+        let properties = decode_properties(&mut src)?;
+
+        let client_id = decode_byte_str(&mut src)?;
+        let client_id = if client_id.is_empty() {
+            super::ClientId::ServerGenerated
+        } else if connect_flags & 0x02 == 0 {
+            super::ClientId::IdWithExistingSession(client_id)
+        } else {
+            super::ClientId::IdWithCleanSession(client_id)
+        };
+
+        let will = if connect_flags & 0x04 == 0 {
+            None
+        } else {
+            let properties = decode_properties(&mut src)?;
+
+            let topic_name = decode_byte_str(&mut src)?;
+
+            let qos = match connect_flags & 0x18 {
+                0x00 => super::QoS::AtMostOnce,
+                0x08 => super::QoS::AtLeastOnce,
+                0x10 => super::QoS::ExactlyOnce,
+                qos => return Err(super::DecodeError::UnrecognizedQoS(qos >> 3)),
+            };
+
+            let retain = connect_flags & 0x20 != 0;
+
+            let payload = decode_binary_data(&mut src)?;
+
+            Some(Will {
+                publication: super::Publication {
+                    topic_name,
+                    qos,
+                    retain,
+                    payload,
+                    user_properties: vec![],
+                    response_topic: None,
+                    correlation_data: None,
+                    message_expiry: None,
+                    content_type: None,
+                },
+                properties,
+            })
+        };
+
+        let username = if connect_flags & 0x80 == 0 {
+            None
+        } else {
+            Some(decode_byte_str(&mut src)?)
+        };
+
+        let password = if connect_flags & 0x40 == 0 {
+            None
+        } else {
+            Some(decode_byte_str(&mut src)?)
+        };
+
+        Ok(Connect {
+            username,
+            password,
+            will,
+            client_id,
+            keep_alive,
+            properties,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let Connect {
+            username,
+            password,
+            will,
+            client_id,
+            keep_alive,
+            properties,
+        } = self;
+
+        crate::PROTOCOL_NAME.encode(dst);
+
+        dst.put_u8_bytes(PROTOCOL_LEVEL);
+
+        {
+            let mut connect_flags = 0x00_u8;
+            if username.is_some() {
+                connect_flags |= 0x80;
+            }
+            if password.is_some() {
+                connect_flags |= 0x40;
+            }
+            if let Some(will) = &will {
+                if will.publication.retain {
+                    connect_flags |= 0x20;
+                }
+                connect_flags |= match will.publication.qos {
+                    super::QoS::AtMostOnce => 0x00,
+                    super::QoS::AtLeastOnce => 0x08,
+                    super::QoS::ExactlyOnce => 0x10,
+                };
+                connect_flags |= 0x04;
+            }
+            match client_id {
+                super::ClientId::ServerGenerated | super::ClientId::IdWithCleanSession(_) => {
+                    connect_flags |= 0x02;
+                }
+                super::ClientId::IdWithExistingSession(_) => (),
+            }
+            dst.put_u8_bytes(connect_flags);
+        }
+
+        dst.put_u16_bytes(
+            keep_alive
+                .as_secs()
+                .try_into()
+                .map_err(|_| super::EncodeError::KeepAliveTooHigh(keep_alive))?,
+        );
+
+        encode_properties(properties, dst)?;
+
+        match client_id {
+            #[allow(clippy::borrow_interior_mutable_const)]
+            super::ClientId::ServerGenerated => super::ByteStr::EMPTY.encode(dst),
+            super::ClientId::IdWithCleanSession(id)
+            | super::ClientId::IdWithExistingSession(id) => id.encode(dst),
+        }
+
+        if let Some(Will { publication, properties }) = will {
+            encode_properties(properties, dst)?;
+
+            publication.topic_name.encode(dst);
+
+            let will_len = publication.payload.len();
+            dst.put_u16_bytes(
+                will_len
+                    .try_into()
+                    .map_err(|_| super::EncodeError::WillTooLarge(will_len))?,
+            );
+            dst.put_bytes(publication.payload);
+        }
+
+        if let Some(username) = username {
+            username.encode(dst);
+        }
+
+        if let Some(password) = password {
+            password.encode(dst);
+        }
+
+        Ok(())
+    }
+}
+
+/// Ref: 3.14 DISCONNECT – Disconnect notification
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Disconnect {
+    pub reason_code: ReasonCode,
+    pub properties: Vec<Property>,
+}
+
+impl PacketMeta for Disconnect {
+    const PACKET_TYPE: u8 = 0xE0;
+
+    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        if flags != 0 {
+            return Err(super::DecodeError::UnrecognizedPacket {
+                packet_type: Self::PACKET_TYPE,
+                flags,
+                remaining_length: src.len(),
+            });
+        }
+
+        let (reason_code, properties) = decode_optional_reason_code(&mut src)?;
+
+        Ok(Disconnect {
+            reason_code,
+            properties,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let Disconnect {
+            reason_code,
+            properties,
+        } = self;
+        encode_optional_reason_code(reason_code, properties, dst)
+    }
+}
+
+/// Ref: 3.4 PUBACK – Publish acknowledgement
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PubAck {
+    pub packet_identifier: super::PacketIdentifier,
+    pub reason_code: ReasonCode,
+    pub properties: Vec<Property>,
+}
+
+impl PacketMeta for PubAck {
+    const PACKET_TYPE: u8 = 0x40;
+
+    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, reason_code, properties) = decode_publish_ack(Self::PACKET_TYPE, flags, 0, src)?;
+        Ok(PubAck {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let PubAck {
+            packet_identifier,
+            reason_code,
+            properties,
+        } = self;
+        encode_publish_ack(packet_identifier, reason_code, properties, dst)
+    }
+}
+
+#[allow(clippy::doc_markdown)]
+/// Ref: 3.7 PUBCOMP – Publish complete (QoS 2 delivery part 3)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PubComp {
+    pub packet_identifier: super::PacketIdentifier,
+    pub reason_code: ReasonCode,
+    pub properties: Vec<Property>,
+}
+
+impl PacketMeta for PubComp {
+    const PACKET_TYPE: u8 = 0x70;
+
+    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, reason_code, properties) = decode_publish_ack(Self::PACKET_TYPE, flags, 0, src)?;
+        Ok(PubComp {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let PubComp {
+            packet_identifier,
+            reason_code,
+            properties,
+        } = self;
+        encode_publish_ack(packet_identifier, reason_code, properties, dst)
+    }
+}
+
+/// Ref: 3.3 PUBLISH – Publish message
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Publish {
+    pub packet_identifier_dup_qos: super::PacketIdentifierDupQoS,
+    pub retain: bool,
+    pub topic_name: super::ByteStr,
+    pub properties: Vec<Property>,
+    pub payload: bytes::Bytes,
+}
+
+impl PacketMeta for Publish {
+    const PACKET_TYPE: u8 = 0x30;
+
+    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        let dup = (flags & 0x08) != 0;
+        let retain = (flags & 0x01) != 0;
+
+        let topic_name = decode_byte_str(&mut src)?;
+
+        let packet_identifier_dup_qos = match (flags & 0x06) >> 1 {
+            0x00 if dup => return Err(super::DecodeError::PublishDupAtMostOnce),
+
+            0x00 => super::PacketIdentifierDupQoS::AtMostOnce,
+
+            0x01 => {
+                let packet_identifier = src.try_get_packet_identifier()?;
+                super::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, dup)
+            }
+
+            0x02 => {
+                let packet_identifier = src.try_get_packet_identifier()?;
+                super::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, dup)
+            }
+
+            qos => return Err(super::DecodeError::UnrecognizedQoS(qos)),
+        };
+
+        let properties = decode_properties(&mut src)?;
+
+        let payload = src.freeze();
+
+        Ok(Publish {
+            packet_identifier_dup_qos,
+            retain,
+            topic_name,
+            properties,
+            payload,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        #[allow(clippy::unneeded_field_pattern)]
+        let Publish {
+            packet_identifier_dup_qos,
+            retain: _,
+            topic_name,
+            properties,
+            payload,
+        } = self;
+
+        topic_name.encode(dst);
+
+        match packet_identifier_dup_qos {
+            super::PacketIdentifierDupQoS::AtMostOnce => (),
+            super::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _)
+            | super::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, _) => {
+                dst.put_packet_identifier_bytes(packet_identifier);
+            }
+        }
+
+        encode_properties(properties, dst)?;
+
+        dst.put_bytes(payload);
+
+        Ok(())
+    }
+}
+
+#[allow(clippy::doc_markdown)]
+/// Ref: 3.5 PUBREC – Publish received (QoS 2 delivery part 1)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PubRec {
+    pub packet_identifier: super::PacketIdentifier,
+    pub reason_code: ReasonCode,
+    pub properties: Vec<Property>,
+}
+
+impl PacketMeta for PubRec {
+    const PACKET_TYPE: u8 = 0x50;
+
+    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, reason_code, properties) = decode_publish_ack(Self::PACKET_TYPE, flags, 0, src)?;
+        Ok(PubRec {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let PubRec {
+            packet_identifier,
+            reason_code,
+            properties,
+        } = self;
+        encode_publish_ack(packet_identifier, reason_code, properties, dst)
+    }
+}
+
+#[allow(clippy::doc_markdown)]
+/// Ref: 3.6 PUBREL – Publish release (QoS 2 delivery part 2)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PubRel {
+    pub packet_identifier: super::PacketIdentifier,
+    pub reason_code: ReasonCode,
+    pub properties: Vec<Property>,
+}
+
+impl PacketMeta for PubRel {
+    const PACKET_TYPE: u8 = 0x60;
+
+    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, reason_code, properties) = decode_publish_ack(Self::PACKET_TYPE, flags, 2, src)?;
+        Ok(PubRel {
+            packet_identifier,
+            reason_code,
+            properties,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let PubRel {
+            packet_identifier,
+            reason_code,
+            properties,
+        } = self;
+        encode_publish_ack(packet_identifier, reason_code, properties, dst)
+    }
+}
+
+/// Ref: 3.9 SUBACK – Subscribe acknowledgement
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SubAck {
+    pub packet_identifier: super::PacketIdentifier,
+    pub properties: Vec<Property>,
+    pub reason_codes: Vec<ReasonCode>,
+}
+
+impl PacketMeta for SubAck {
+    const PACKET_TYPE: u8 = 0x90;
+
+    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, properties, reason_codes) = decode_topic_acks(Self::PACKET_TYPE, flags, src)?;
+        Ok(SubAck {
+            packet_identifier,
+            properties,
+            reason_codes,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let SubAck {
+            packet_identifier,
+            properties,
+            reason_codes,
+        } = self;
+        encode_topic_acks(packet_identifier, properties, reason_codes, dst)
+    }
+}
+
+/// Ref: 3.8 SUBSCRIBE - Subscribe request
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Subscribe {
+    pub packet_identifier: super::PacketIdentifier,
+    pub properties: Vec<Property>,
+    pub subscribe_to: Vec<SubscribeTo>,
+}
+
+impl PacketMeta for Subscribe {
+    const PACKET_TYPE: u8 = 0x80;
+
+    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        if flags != 2 || src.len() < std::mem::size_of::<u16>() {
+            return Err(super::DecodeError::UnrecognizedPacket {
+                packet_type: Self::PACKET_TYPE,
+                flags,
+                remaining_length: src.len(),
+            });
+        }
+
+        let packet_identifier = src.get_packet_identifier()?;
+
+        let properties = decode_properties(&mut src)?;
+
+        let mut subscribe_to = vec![];
+
+        while !src.is_empty() {
+            let topic_filter = decode_byte_str(&mut src)?;
+            let options = src.try_get_u8()?;
+            if options & 0xC0 != 0 {
+                return Err(super::DecodeError::UnrecognizedSubscriptionOptions(options));
+            }
+            let qos = match options & 0x03 {
+                0x00 => super::QoS::AtMostOnce,
+                0x01 => super::QoS::AtLeastOnce,
+                0x02 => super::QoS::ExactlyOnce,
+                qos => return Err(super::DecodeError::UnrecognizedQoS(qos)),
+            };
+            let retain_handling = match (options & 0x30) >> 4 {
+                0x00 => RetainHandling::SendOnSubscribe,
+                0x01 => RetainHandling::SendOnNewSubscribe,
+                0x02 => RetainHandling::DoNotSend,
+                _ => return Err(super::DecodeError::UnrecognizedSubscriptionOptions(options)),
+            };
+            subscribe_to.push(SubscribeTo {
+                topic_filter,
+                qos,
+                no_local: options & 0x04 != 0,
+                retain_as_published: options & 0x08 != 0,
+                retain_handling,
+            });
+        }
+
+        if subscribe_to.is_empty() {
+            return Err(super::DecodeError::NoTopics);
+        }
+
+        Ok(Subscribe {
+            packet_identifier,
+            properties,
+            subscribe_to,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let Subscribe {
+            packet_identifier,
+            properties,
+            subscribe_to,
+        } = self;
+
+        dst.put_packet_identifier_bytes(packet_identifier);
+
+        encode_properties(properties, dst)?;
+
+        for SubscribeTo { topic_filter, qos, no_local, retain_as_published, retain_handling } in subscribe_to {
+            topic_filter.encode(dst);
+
+            let mut options = u8::from(qos);
+            if no_local {
+                options |= 0x04;
+            }
+            if retain_as_published {
+                options |= 0x08;
+            }
+            options |= match retain_handling {
+                RetainHandling::SendOnSubscribe => 0x00,
+                RetainHandling::SendOnNewSubscribe => 0x10,
+                RetainHandling::DoNotSend => 0x20,
+            };
+            dst.put_u8_bytes(options);
+        }
+
+        Ok(())
+    }
+}
+
+/// A subscription request, with its subscription options.
+///
+/// Ref: 3.8.3.1 Subscription Options
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SubscribeTo {
+    pub topic_filter: super::ByteStr,
+    pub qos: super::QoS,
+    pub no_local: bool,
+    pub retain_as_published: bool,
+    pub retain_handling: RetainHandling,
+}
+
+pub use super::RetainHandling;
+
+/// Ref: 3.11 UNSUBACK – Unsubscribe acknowledgement
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnsubAck {
+    pub packet_identifier: super::PacketIdentifier,
+    pub properties: Vec<Property>,
+    pub reason_codes: Vec<ReasonCode>,
+}
+
+impl PacketMeta for UnsubAck {
+    const PACKET_TYPE: u8 = 0xB0;
+
+    fn decode(flags: u8, src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        let (packet_identifier, properties, reason_codes) = decode_topic_acks(Self::PACKET_TYPE, flags, src)?;
+        Ok(UnsubAck {
+            packet_identifier,
+            properties,
+            reason_codes,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let UnsubAck {
+            packet_identifier,
+            properties,
+            reason_codes,
+        } = self;
+        encode_topic_acks(packet_identifier, properties, reason_codes, dst)
+    }
+}
+
+/// Ref: 3.10 UNSUBSCRIBE – Unsubscribe request
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Unsubscribe {
+    pub packet_identifier: super::PacketIdentifier,
+    pub properties: Vec<Property>,
+    pub unsubscribe_from: Vec<super::ByteStr>,
+}
+
+impl PacketMeta for Unsubscribe {
+    const PACKET_TYPE: u8 = 0xA0;
+
+    fn decode(flags: u8, mut src: bytes::BytesMut) -> Result<Self, super::DecodeError> {
+        if flags != 2 || src.len() < std::mem::size_of::<u16>() {
+            return Err(super::DecodeError::UnrecognizedPacket {
+                packet_type: Self::PACKET_TYPE,
+                flags,
+                remaining_length: src.len(),
+            });
+        }
+
+        let packet_identifier = src.get_packet_identifier()?;
+
+        let properties = decode_properties(&mut src)?;
+
+        let mut unsubscribe_from = vec![];
+
+        while !src.is_empty() {
+            unsubscribe_from.push(decode_byte_str(&mut src)?);
+        }
+
+        if unsubscribe_from.is_empty() {
+            return Err(super::DecodeError::NoTopics);
+        }
+
+        Ok(Unsubscribe {
+            packet_identifier,
+            properties,
+            unsubscribe_from,
+        })
+    }
+
+    fn encode<B>(self, dst: &mut B) -> Result<(), super::EncodeError>
+    where
+        B: ByteBuf,
+    {
+        let Unsubscribe {
+            packet_identifier,
+            properties,
+            unsubscribe_from,
+        } = self;
+
+        dst.put_packet_identifier_bytes(packet_identifier);
+
+        encode_properties(properties, dst)?;
+
+        for unsubscribe_from in unsubscribe_from {
+            unsubscribe_from.encode(dst);
+        }
+
+        Ok(())
+    }
+}
+
+pub fn decode(decoder: &mut super::PacketDecoder, src: &mut bytes::BytesMut) -> Result<Option<Packet>, super::DecodeError> {
+    match super::packet::decode_fixed_header(decoder, src)? {
+        Some((first_byte, src)) => decode_body(first_byte, src).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn decode_body(first_byte: u8, src: bytes::BytesMut) -> Result<Packet, super::DecodeError> {
+    let packet_type = first_byte & 0xF0;
+    let flags = first_byte & 0x0F;
+    match packet_type {
+        Auth::PACKET_TYPE => Ok(Packet::Auth(Auth::decode(flags, src)?)),
+        ConnAck::PACKET_TYPE => Ok(Packet::ConnAck(ConnAck::decode(flags, src)?)),
+        Connect::PACKET_TYPE => Ok(Packet::Connect(Connect::decode(flags, src)?)),
+        Disconnect::PACKET_TYPE => Ok(Packet::Disconnect(Disconnect::decode(flags, src)?)),
+        super::PingReq::PACKET_TYPE => Ok(Packet::PingReq(super::PingReq::decode(flags, src)?)),
+        super::PingResp::PACKET_TYPE => Ok(Packet::PingResp(super::PingResp::decode(flags, src)?)),
+        PubAck::PACKET_TYPE => Ok(Packet::PubAck(PubAck::decode(flags, src)?)),
+        PubComp::PACKET_TYPE => Ok(Packet::PubComp(PubComp::decode(flags, src)?)),
+        Publish::PACKET_TYPE => Ok(Packet::Publish(Publish::decode(flags, src)?)),
+        PubRec::PACKET_TYPE => Ok(Packet::PubRec(PubRec::decode(flags, src)?)),
+        PubRel::PACKET_TYPE => Ok(Packet::PubRel(PubRel::decode(flags, src)?)),
+        SubAck::PACKET_TYPE => Ok(Packet::SubAck(SubAck::decode(flags, src)?)),
+        Subscribe::PACKET_TYPE => Ok(Packet::Subscribe(Subscribe::decode(flags, src)?)),
+        UnsubAck::PACKET_TYPE => Ok(Packet::UnsubAck(UnsubAck::decode(flags, src)?)),
+        Unsubscribe::PACKET_TYPE => Ok(Packet::Unsubscribe(Unsubscribe::decode(flags, src)?)),
+        packet_type => Err(super::DecodeError::UnrecognizedPacket {
+            packet_type,
+            flags,
+            remaining_length: src.len(),
+        }),
+    }
+}
+
+pub fn encode<B>(item: Packet, dst: &mut B) -> Result<(), super::EncodeError> where B: ByteBuf {
+    match item {
+        Packet::Auth(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::ConnAck(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::Connect(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::Disconnect(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::PingReq(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::PingResp(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::PubAck(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::PubComp(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::Publish(packet) => {
+            let mut flags = match packet.packet_identifier_dup_qos {
+                super::PacketIdentifierDupQoS::AtMostOnce => 0x00,
+                super::PacketIdentifierDupQoS::AtLeastOnce(_, true) => 0x0A,
+                super::PacketIdentifierDupQoS::AtLeastOnce(_, false) => 0x02,
+                super::PacketIdentifierDupQoS::ExactlyOnce(_, true) => 0x0C,
+                super::PacketIdentifierDupQoS::ExactlyOnce(_, false) => 0x04,
+            };
+            if packet.retain {
+                flags |= 0x01;
+            }
+            super::packet::encode_inner(packet, flags, dst)
+        }
+        Packet::PubRec(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::PubRel(packet) => super::packet::encode_inner(packet, 0x02, dst),
+        Packet::SubAck(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::Subscribe(packet) => super::packet::encode_inner(packet, 0x02, dst),
+        Packet::UnsubAck(packet) => super::packet::encode_inner(packet, 0, dst),
+        Packet::Unsubscribe(packet) => super::packet::encode_inner(packet, 0x02, dst),
+    }
+}
+
+/// A decoder that decodes packets of either MQTT 3.1.1 or MQTT 5.0 into MQTT 3.1.1 packets.
+///
+/// The protocol level is taken from the CONNECT or CONNACK that starts each connection, so a server can decode the packets of
+/// clients of either version, and a client can decode the packets of a server that accepted or refused its MQTT 5.0 CONNECT.
+/// MQTT 5.0 packets are converted with [`TryFrom`], so their properties are dropped.
+#[derive(Debug)]
+pub struct NegotiatedDecoder {
+    decoder: super::PacketDecoder,
+    protocol_level: u8,
+
+    /// The Topic Alias Maximum of the CONNECT or CONNACK that started the connection, shared with the encoders of the connection
+    topic_alias_maximum: std::sync::Arc<std::sync::atomic::AtomicU16>,
+
+    /// The protocol level of the CONNECT that started the connection, shared with the encoders of the connection
+    connect_protocol_level: std::sync::Arc<std::sync::atomic::AtomicU8>,
+}
+
+impl Default for NegotiatedDecoder {
+    fn default() -> Self {
+        NegotiatedDecoder {
+            decoder: Default::default(),
+            protocol_level: crate::PROTOCOL_LEVEL,
+            topic_alias_maximum: Default::default(),
+            connect_protocol_level: std::sync::Arc::new(std::sync::atomic::AtomicU8::new(crate::PROTOCOL_LEVEL)),
+        }
+    }
+}
+
+impl NegotiatedDecoder {
+    /// The protocol level of the packets being decoded
+    pub fn protocol_level(&self) -> u8 {
+        self.protocol_level
+    }
+
+    /// Returns an encoder for the other direction of the same connection.
+    ///
+    /// Unlike an encoder created on its own, the encoder sends PUBLISH packets with topic aliases, as many as the other side allows
+    /// with the Topic Alias Maximum of the CONNECT or CONNACK decoded by this decoder. It also encodes the CONNACK that answers
+    /// a CONNECT decoded by this decoder, and the packets after it, with the protocol level of that CONNECT, so a server answers
+    /// each client in the version it connected with.
+    #[allow(clippy::doc_markdown)]
+    pub fn encoder(&self) -> NegotiatedEncoder {
+        NegotiatedEncoder {
+            topic_aliases: TopicAliases {
+                maximum: self.topic_alias_maximum.clone(),
+                ..Default::default()
+            },
+            connect_protocol_level: Some(self.connect_protocol_level.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<super::Packet>, super::DecodeError> {
+        match super::packet::decode_fixed_header(&mut self.decoder, src)? {
+            Some((first_byte, src)) => self.decode_body(first_byte, src).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn decode_body(&mut self, first_byte: u8, src: bytes::BytesMut) -> Result<super::Packet, super::DecodeError> {
+        match first_byte & 0xF0 {
+            // The protocol level follows the protocol name
+            Connect::PACKET_TYPE => {
+                #[allow(clippy::borrow_interior_mutable_const)]
+                if let Some(&protocol_level) = src.get(crate::PROTOCOL_NAME.encoded_len()) {
+                    self.protocol_level = protocol_level;
+                    self.connect_protocol_level.store(protocol_level, std::sync::atomic::Ordering::Relaxed);
+                }
+            }
+
+            // A CONNACK of MQTT 3.1.1 has no properties
+            ConnAck::PACKET_TYPE => {
+                self.protocol_level =
+                    if src.len() == std::mem::size_of::<u8>() + std::mem::size_of::<u8>() { crate::PROTOCOL_LEVEL } else { PROTOCOL_LEVEL };
+            }
+
+            _ => (),
+        }
+
+        if self.protocol_level == PROTOCOL_LEVEL {
+            let packet = decode_body(first_byte, src)?;
+            if let Packet::Connect(Connect { properties, .. }) | Packet::ConnAck(ConnAck { properties, .. }) = &packet {
+                let topic_alias_maximum = properties.iter().find_map(|property| match property {
+                    Property::TopicAliasMaximum(topic_alias_maximum) => Some(*topic_alias_maximum),
+                    _ => None,
+                });
+                self.topic_alias_maximum.store(topic_alias_maximum.unwrap_or_default(), std::sync::atomic::Ordering::Relaxed);
+            }
+            packet.try_into()
+        }
+        else {
+            if let Connect::PACKET_TYPE | ConnAck::PACKET_TYPE = first_byte & 0xF0 {
+                self.topic_alias_maximum.store(0, std::sync::atomic::Ordering::Relaxed);
+            }
+            super::packet::decode_body(first_byte, src)
+        }
+    }
+}
+
+/// An encoder that encodes MQTT 3.1.1 packets as packets of either MQTT 3.1.1 or MQTT 5.0.
+///
+/// The protocol level is taken from the CONNECT that starts each connection, so a client's packets are encoded with the protocol level
+/// it asked for. A server either encodes with an encoder returned by the [`NegotiatedDecoder::encoder`] of the connection, which takes
+/// the protocol level of the CONNECT that the decoder decoded, or sets it with [`NegotiatedEncoder::set_protocol_level`].
+/// Packets are converted to MQTT 5.0 packets with [`From`]. An encoder returned by [`NegotiatedDecoder::encoder`] also uses topic aliases.
+#[derive(Debug)]
+pub struct NegotiatedEncoder {
+    protocol_level: u8,
+    topic_aliases: TopicAliases,
+
+    /// The protocol level of the CONNECT decoded by the decoder that this encoder was returned by, if any
+    connect_protocol_level: Option<std::sync::Arc<std::sync::atomic::AtomicU8>>,
+}
+
+impl Default for NegotiatedEncoder {
+    fn default() -> Self {
+        NegotiatedEncoder {
+            protocol_level: crate::PROTOCOL_LEVEL,
+            topic_aliases: Default::default(),
+            connect_protocol_level: None,
+        }
+    }
+}
+
+impl NegotiatedEncoder {
+    /// The protocol level of the packets being encoded
+    pub fn protocol_level(&self) -> u8 {
+        self.protocol_level
+    }
+
+    pub fn set_protocol_level(&mut self, protocol_level: u8) {
+        self.protocol_level = protocol_level;
+    }
+
+    pub fn encode<B>(&mut self, item: super::Packet, dst: &mut B) -> Result<(), super::EncodeError> where B: ByteBuf {
+        match &item {
+            super::Packet::Connect(connect) => {
+                self.protocol_level = connect.protocol_level;
+                self.topic_aliases.clear();
+            }
+
+            super::Packet::ConnAck(_) => {
+                if let Some(connect_protocol_level) = &self.connect_protocol_level {
+                    self.protocol_level = connect_protocol_level.load(std::sync::atomic::Ordering::Relaxed);
+                }
+                self.topic_aliases.clear();
+            }
+
+            _ => (),
+        }
+
+        if self.protocol_level == PROTOCOL_LEVEL {
+            let mut packet = item.into();
+            if let Packet::Publish(publish) = &mut packet {
+                self.topic_aliases.apply(publish);
+            }
+            encode(packet, dst)
+        }
+        else {
+            super::encode(item, dst)
+        }
+    }
+}
+
+/// The topic aliases of the PUBLISH packets sent on a connection.
+///
+/// Every topic gets an alias the first time it is published to, which is sent along with the topic name. Later PUBLISH packets
+/// to the topic are sent with only the alias. Once all the aliases that the other side allows are in use, the alias of the topic
+/// that was published to the least recently is given to the next new topic, so the frequently used topics keep theirs.
+#[derive(Debug, Default)]
+struct TopicAliases {
+    /// The Topic Alias Maximum of the other side of the connection. Aliases are not used while it is 0.
+    maximum: std::sync::Arc<std::sync::atomic::AtomicU16>,
+
+    /// The alias of each topic, and the value of `uses` when the alias was last used
+    aliases: std::collections::HashMap<super::ByteStr, (u16, u64)>,
+
+    uses: u64,
+}
+
+impl TopicAliases {
+    /// Forgets the aliases of the previous connection
+    fn clear(&mut self) {
+        self.aliases.clear();
+    }
+
+    fn apply(&mut self, publish: &mut Publish) {
+        let maximum = self.maximum.load(std::sync::atomic::Ordering::Relaxed);
+        if maximum == 0 {
+            return;
+        }
+
+        self.uses += 1;
+
+        if let Some((alias, last_used)) = self.aliases.get_mut(&publish.topic_name) {
+            *last_used = self.uses;
+            publish.topic_name = Default::default();
+            publish.properties.push(Property::TopicAlias(*alias));
+            return;
+        }
+
+        // Aliases are handed out in order starting from 1, and only reused after that, so they are always the first ones
+        let alias = match u16::try_from(self.aliases.len() + 1) {
+            Ok(alias) if alias <= maximum => alias,
+            _ => {
+                let least_recently_used = self.aliases.iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(topic_name, _)| topic_name.clone())
+                    .expect("all aliases are in use");
+                self.aliases.remove(&least_recently_used).expect("topic was just found").0
+            }
+        };
+
+        self.aliases.insert(publish.topic_name.clone(), (alias, self.uses));
+        publish.properties.push(Property::TopicAlias(alias));
+    }
+}
+
+/// Converts an MQTT 3.1.1 packet into the MQTT 5.0 packet with the same meaning.
+///
+/// Packets have no properties, except for the session expiry of a CONNECT, where `None` asks for the session to never expire
+/// like the sessions of MQTT 3.1.1 do, the user properties of publications, wills and subscriptions, and the subscription identifiers
+/// of subscriptions and publications. The user properties of all the subscriptions of a SUBSCRIBE are sent together as the properties of the packet,
+/// along with the subscription identifier of the first subscription that has one, since a SUBSCRIBE can only carry one.
+/// The authentication method and data of CONNECT, CONNACK and AUTH are sent as properties too.
+/// Acknowledgements that MQTT 3.1.1 cannot fail get [`ReasonCode::SUCCESS`], and an UNSUBACK gets the reason codes of its results.
+/// A DISCONNECT gets the reason code of its [`super::DisconnectReason`], or [`ReasonCode::USE_ANOTHER_SERVER`] for a normal one
+/// with a server reference.
+impl From<super::Packet> for Packet {
+    fn from(packet: super::Packet) -> Self {
+        match packet {
+            super::Packet::Auth(super::Auth { reason, authentication_method, authentication_data }) => Packet::Auth(Auth {
+                reason_code: match reason {
+                    super::AuthReason::Success => ReasonCode::SUCCESS,
+                    super::AuthReason::ContinueAuthentication => ReasonCode::CONTINUE_AUTHENTICATION,
+                    super::AuthReason::ReAuthenticate => ReasonCode::REAUTHENTICATE,
+                },
+                properties: into_authentication_properties(Some(authentication_method), authentication_data),
+            }),
+
+            super::Packet::ConnAck(super::ConnAck {
+                session_present,
+                return_code,
+                authentication_data,
+                receive_maximum,
+                maximum_packet_size,
+                server_reference,
+                assigned_client_id,
+                server_keep_alive,
+                maximum_qos,
+                retain_available,
+                reason_string,
+                user_properties,
+            }) => Packet::ConnAck(ConnAck {
+                session_present,
+                reason_code: match return_code {
+                    super::ConnectReturnCode::Accepted => ReasonCode::SUCCESS,
+                    super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::UnacceptableProtocolVersion) =>
+                        ReasonCode::UNSUPPORTED_PROTOCOL_VERSION,
+                    super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::IdentifierRejected) =>
+                        ReasonCode::CLIENT_IDENTIFIER_NOT_VALID,
+                    super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::ServerUnavailable) =>
+                        ReasonCode::SERVER_UNAVAILABLE,
+                    super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::BadUserNameOrPassword) =>
+                        ReasonCode::BAD_USER_NAME_OR_PASSWORD,
+                    super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::NotAuthorized) =>
+                        ReasonCode::NOT_AUTHORIZED,
+                    super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::Other(code)) if code >= 0x80 =>
+                        ReasonCode(code),
+                    super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::Other(_)) =>
+                        ReasonCode::UNSPECIFIED_ERROR,
+                },
+                properties:
+                    receive_maximum.map(Property::ReceiveMaximum).into_iter()
+                    .chain(maximum_packet_size.map(Property::MaximumPacketSize))
+                    .chain(into_authentication_properties(None, authentication_data))
+                    .chain(server_reference.map(Property::ServerReference))
+                    .chain(assigned_client_id.map(Property::AssignedClientIdentifier))
+                    .chain(server_keep_alive.map(|server_keep_alive| Property::ServerKeepAlive(server_keep_alive.as_secs().try_into().unwrap_or(u16::MAX))))
+                    .chain(maximum_qos.and_then(|maximum_qos| match maximum_qos {
+                        super::QoS::AtMostOnce => Some(Property::MaximumQoS(0)),
+                        super::QoS::AtLeastOnce => Some(Property::MaximumQoS(1)),
+                        super::QoS::ExactlyOnce => None,
+                    }))
+                    .chain(retain_available.map(|retain_available| Property::RetainAvailable(retain_available.into())))
+                    .chain(reason_string.map(Property::ReasonString))
+                    .chain(into_user_properties(user_properties))
+                    .collect(),
+            }),
+
+            super::Packet::Connect(super::Connect {
+                username,
+                password,
+                will,
+                client_id,
+                keep_alive,
+                session_expiry,
+                will_delay,
+                maximum_packet_size,
+                authentication_method,
+                authentication_data,
+                ..
+            }) => {
+                // A Session Expiry Interval of 0 is the default, and u32::MAX means the session never expires
+                let mut properties = match session_expiry.map(|session_expiry| session_expiry.as_secs()) {
+                    Some(0) => vec![],
+                    Some(session_expiry) => vec![Property::SessionExpiryInterval(session_expiry.try_into().unwrap_or(u32::MAX))],
+                    None => vec![Property::SessionExpiryInterval(u32::MAX)],
+                };
+                properties.extend(maximum_packet_size.map(Property::MaximumPacketSize));
+                properties.extend(into_authentication_properties(authentication_method, authentication_data));
+
+                Packet::Connect(Connect {
+                    username,
+                    password,
+                    will: will.map(|mut publication| Will {
+                        properties:
+                            into_user_properties(std::mem::take(&mut publication.user_properties)).into_iter()
+                            .chain(into_request_properties(publication.response_topic.take(), publication.correlation_data.take()))
+                            .chain(publication.message_expiry.take().map(into_message_expiry_property))
+                            .chain(publication.content_type.take().map(Property::ContentType))
+                            .chain(will_delay.map(|will_delay| Property::WillDelayInterval(will_delay.as_secs().try_into().unwrap_or(u32::MAX))))
+                            .collect(),
+                        publication,
+                    }),
+                    client_id,
+                    keep_alive,
+                    properties,
+                })
+            }
+
+            super::Packet::Disconnect(super::Disconnect { reason, server_reference }) => Packet::Disconnect(Disconnect {
+                reason_code: match reason {
+                    super::DisconnectReason::Normal if server_reference.is_some() => ReasonCode::USE_ANOTHER_SERVER,
+                    super::DisconnectReason::Normal => ReasonCode::NORMAL_DISCONNECTION,
+                    super::DisconnectReason::WithWill => ReasonCode::DISCONNECT_WITH_WILL_MESSAGE,
+                    super::DisconnectReason::ServerShuttingDown => ReasonCode::SERVER_SHUTTING_DOWN,
+                    super::DisconnectReason::Other(reason_code) => ReasonCode(reason_code),
+                },
+                properties: server_reference.map(Property::ServerReference).into_iter().collect(),
+            }),
+
+            super::Packet::PingReq(packet) => Packet::PingReq(packet),
+
+            super::Packet::PingResp(packet) => Packet::PingResp(packet),
+
+            super::Packet::PubAck(super::PubAck { packet_identifier }) => Packet::PubAck(PubAck {
+                packet_identifier,
+                reason_code: ReasonCode::SUCCESS,
+                properties: vec![],
+            }),
+
+            super::Packet::PubComp(super::PubComp { packet_identifier }) => Packet::PubComp(PubComp {
+                packet_identifier,
+                reason_code: ReasonCode::SUCCESS,
+                properties: vec![],
+            }),
+
+            super::Packet::Publish(super::Publish {
+                packet_identifier_dup_qos,
+                retain,
+                topic_name,
+                payload,
+                user_properties,
+                subscription_identifiers,
+                response_topic,
+                correlation_data,
+                message_expiry,
+                content_type,
+            }) => {
+                let mut properties = into_user_properties(user_properties);
+                properties.extend(subscription_identifiers.into_iter().map(Property::SubscriptionIdentifier));
+                properties.extend(into_request_properties(response_topic, correlation_data));
+                properties.extend(message_expiry.map(into_message_expiry_property));
+                properties.extend(content_type.map(Property::ContentType));
+
+                Packet::Publish(Publish {
+                    packet_identifier_dup_qos,
+                    retain,
+                    topic_name,
+                    properties,
+                    payload,
+                })
+            }
+
+            super::Packet::PubRec(super::PubRec { packet_identifier }) => Packet::PubRec(PubRec {
+                packet_identifier,
+                reason_code: ReasonCode::SUCCESS,
+                properties: vec![],
+            }),
+
+            super::Packet::PubRel(super::PubRel { packet_identifier }) => Packet::PubRel(PubRel {
+                packet_identifier,
+                reason_code: ReasonCode::SUCCESS,
+                properties: vec![],
+            }),
+
+            super::Packet::SubAck(super::SubAck { packet_identifier, qos }) => Packet::SubAck(SubAck {
+                packet_identifier,
+                properties: vec![],
+                reason_codes: qos.into_iter().map(|qos| ReasonCode(qos.into())).collect(),
+            }),
+
+            super::Packet::Subscribe(super::Subscribe { packet_identifier, subscribe_to }) => {
+                let mut user_properties = vec![];
+                let mut subscription_identifier = None;
+                let subscribe_to = subscribe_to.into_iter().map(|super::SubscribeTo {
+                    topic_filter,
+                    qos,
+                    user_properties: subscription_user_properties,
+                    subscription_identifier: subscription_subscription_identifier,
+                    no_local,
+                    retain_as_published,
+                    retain_handling,
+                }| {
+                    for user_property in subscription_user_properties {
+                        if !user_properties.contains(&user_property) {
+                            user_properties.push(user_property);
+                        }
+                    }
+
+                    subscription_identifier = subscription_identifier.or(subscription_subscription_identifier);
+
+                    SubscribeTo {
+                        topic_filter,
+                        qos,
+                        no_local,
+                        retain_as_published,
+                        retain_handling,
+                    }
+                }).collect();
+
+                let mut properties = into_user_properties(user_properties);
+                properties.extend(subscription_identifier.map(Property::SubscriptionIdentifier));
+
+                Packet::Subscribe(Subscribe {
+                    packet_identifier,
+                    properties,
+                    subscribe_to,
+                })
+            }
+
+            super::Packet::UnsubAck(super::UnsubAck { packet_identifier, results }) => Packet::UnsubAck(UnsubAck {
+                packet_identifier,
+                properties: vec![],
+                reason_codes: results.into_iter().map(|result| match result {
+                    super::UnsubAckResult::Success => ReasonCode::SUCCESS,
+                    super::UnsubAckResult::NoSubscriptionExisted => ReasonCode::NO_SUBSCRIPTION_EXISTED,
+                    super::UnsubAckResult::Failure => ReasonCode::UNSPECIFIED_ERROR,
+                }).collect(),
+            }),
+
+            super::Packet::Unsubscribe(super::Unsubscribe { packet_identifier, unsubscribe_from }) => Packet::Unsubscribe(Unsubscribe {
+                packet_identifier,
+                properties: vec![],
+                unsubscribe_from,
+            }),
+        }
+    }
+}
+
+/// Converts an MQTT 5.0 packet into the MQTT 3.1.1 packet with the closest meaning.
+///
+/// Properties are dropped, except for user properties, subscription identifiers and the authentication method and data. The user properties and subscription identifier
+/// of a SUBSCRIBE are given to each of its subscriptions. A CONNECT keeps [`PROTOCOL_LEVEL`] as its protocol level. The reason code of a CONNACK is mapped to the
+/// closest return code, failed subscriptions in a SUBACK become [`super::SubAckQos::Failure`], and the reason codes of an UNSUBACK
+/// become [`super::UnsubAckResult`]s, and the reason code of a DISCONNECT becomes its [`super::DisconnectReason`]. The reason codes of
+/// the other acknowledgements have no equivalent and are dropped. An AUTH fails to convert if it has no authentication method,
+/// or a reason code that AUTH cannot have.
+#[allow(clippy::doc_markdown)]
+impl TryFrom<Packet> for super::Packet {
+    type Error = super::DecodeError;
+
+    fn try_from(packet: Packet) -> Result<Self, Self::Error> {
+        let packet = match packet {
+            Packet::Auth(Auth { reason_code, properties }) => {
+                let reason = match reason_code {
+                    ReasonCode::SUCCESS => Some(super::AuthReason::Success),
+                    ReasonCode::CONTINUE_AUTHENTICATION => Some(super::AuthReason::ContinueAuthentication),
+                    ReasonCode::REAUTHENTICATE => Some(super::AuthReason::ReAuthenticate),
+                    _ => None,
+                };
+
+                match (reason, from_authentication_properties(&properties)) {
+                    (Some(reason), (Some(authentication_method), authentication_data)) => super::Packet::Auth(super::Auth {
+                        reason,
+                        authentication_method,
+                        authentication_data,
+                    }),
+
+                    _ => return Err(super::DecodeError::UnrecognizedPacket {
+                        packet_type: Auth::PACKET_TYPE,
+                        flags: 0,
+                        remaining_length: properties.len(),
+                    }),
+                }
+            }
+
+            Packet::ConnAck(ConnAck { session_present, reason_code, properties }) => super::Packet::ConnAck(super::ConnAck {
+                session_present,
+                return_code: match reason_code.0 {
+                    0x00 => super::ConnectReturnCode::Accepted,
+                    0x01 | 0x84 => super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::UnacceptableProtocolVersion),
+                    0x02 | 0x85 => super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::IdentifierRejected),
+                    0x03 | 0x88 | 0x89 => super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::ServerUnavailable),
+                    0x04 | 0x86 => super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::BadUserNameOrPassword),
+                    0x05 | 0x87 => super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::NotAuthorized),
+                    code => super::ConnectReturnCode::Refused(super::ConnectionRefusedReason::Other(code)),
+                },
+                authentication_data: from_authentication_properties(&properties).1,
+                // A Receive Maximum of zero is a protocol error, so treat it like an absent one
+                receive_maximum: properties.iter().find_map(|property| match property {
+                    Property::ReceiveMaximum(receive_maximum) if *receive_maximum > 0 => Some(*receive_maximum),
+                    _ => None,
+                }),
+                maximum_packet_size: maximum_packet_size(&properties),
+                server_reference: server_reference(&properties),
+                assigned_client_id: properties.iter().find_map(|property| match property {
+                    Property::AssignedClientIdentifier(assigned_client_id) => Some(assigned_client_id.clone()),
+                    _ => None,
+                }),
+                server_keep_alive: properties.iter().find_map(|property| match property {
+                    Property::ServerKeepAlive(server_keep_alive) => Some(std::time::Duration::from_secs((*server_keep_alive).into())),
+                    _ => None,
+                }),
+                maximum_qos: properties.iter().find_map(|property| match property {
+                    Property::MaximumQoS(0) => Some(super::QoS::AtMostOnce),
+                    Property::MaximumQoS(1) => Some(super::QoS::AtLeastOnce),
+                    _ => None,
+                }),
+                retain_available: properties.iter().find_map(|property| match property {
+                    Property::RetainAvailable(retain_available) => Some(*retain_available != 0),
+                    _ => None,
+                }),
+                reason_string: properties.iter().find_map(|property| match property {
+                    Property::ReasonString(reason_string) => Some(reason_string.clone()),
+                    _ => None,
+                }),
+                user_properties: from_user_properties(properties),
+            }),
+
+            Packet::Connect(Connect { username, password, will, client_id, keep_alive, properties }) => {
+                let session_expiry = properties.iter().find_map(|property| match property {
+                    Property::SessionExpiryInterval(session_expiry) => Some(*session_expiry),
+                    _ => None,
+                });
+                let (authentication_method, authentication_data) = from_authentication_properties(&properties);
+                let will_delay = will.as_ref().and_then(|will| will.properties.iter().find_map(|property| match property {
+                    Property::WillDelayInterval(will_delay) => Some(std::time::Duration::from_secs((*will_delay).into())),
+                    _ => None,
+                }));
+
+                super::Packet::Connect(super::Connect {
+                    username,
+                    password,
+                    will: will.map(|Will { mut publication, properties }| {
+                        let (response_topic, correlation_data) = from_request_properties(&properties);
+                        publication.response_topic = response_topic;
+                        publication.correlation_data = correlation_data;
+                        publication.message_expiry = message_expiry(&properties);
+                        publication.content_type = content_type(&properties);
+                        publication.user_properties = from_user_properties(properties);
+                        publication
+                    }),
+                    client_id,
+                    keep_alive,
+                    protocol_name: crate::PROTOCOL_NAME,
+                    protocol_level: PROTOCOL_LEVEL,
+                    session_expiry: match session_expiry {
+                        Some(u32::MAX) => None,
+                        session_expiry => Some(std::time::Duration::from_secs(session_expiry.unwrap_or_default().into())),
+                    },
+                    will_delay,
+                    maximum_packet_size: maximum_packet_size(&properties),
+                    authentication_method,
+                    authentication_data,
+                })
+            }
+
+            Packet::Disconnect(Disconnect { reason_code, properties }) => super::Packet::Disconnect(super::Disconnect {
+                reason: match reason_code {
+                    ReasonCode::NORMAL_DISCONNECTION => super::DisconnectReason::Normal,
+                    ReasonCode::DISCONNECT_WITH_WILL_MESSAGE => super::DisconnectReason::WithWill,
+                    ReasonCode::SERVER_SHUTTING_DOWN => super::DisconnectReason::ServerShuttingDown,
+                    ReasonCode(reason_code) => super::DisconnectReason::Other(reason_code),
+                },
+                server_reference: server_reference(&properties),
+            }),
+
+            Packet::PingReq(packet) => super::Packet::PingReq(packet),
+
+            Packet::PingResp(packet) => super::Packet::PingResp(packet),
+
+            Packet::PubAck(PubAck { packet_identifier, .. }) => super::Packet::PubAck(super::PubAck { packet_identifier }),
+
+            Packet::PubComp(PubComp { packet_identifier, .. }) => super::Packet::PubComp(super::PubComp { packet_identifier }),
+
+            Packet::Publish(Publish { packet_identifier_dup_qos, retain, topic_name, properties, payload }) => {
+                let (response_topic, correlation_data) = from_request_properties(&properties);
+                super::Packet::Publish(super::Publish {
+                    packet_identifier_dup_qos,
+                    retain,
+                    topic_name,
+                    payload,
+                    subscription_identifiers: subscription_identifiers(&properties).collect(),
+                    response_topic,
+                    correlation_data,
+                    message_expiry: message_expiry(&properties),
+                    content_type: content_type(&properties),
+                    user_properties: from_user_properties(properties),
+                })
+            }
+
+            Packet::PubRec(PubRec { packet_identifier, .. }) => super::Packet::PubRec(super::PubRec { packet_identifier }),
+
+            Packet::PubRel(PubRel { packet_identifier, .. }) => super::Packet::PubRel(super::PubRel { packet_identifier }),
+
+            Packet::SubAck(SubAck { packet_identifier, reason_codes, .. }) => super::Packet::SubAck(super::SubAck {
+                packet_identifier,
+                qos: reason_codes.into_iter().map(|reason_code| match reason_code {
+                    ReasonCode::GRANTED_QOS_0 => super::SubAckQos::Success(super::QoS::AtMostOnce),
+                    ReasonCode::GRANTED_QOS_1 => super::SubAckQos::Success(super::QoS::AtLeastOnce),
+                    ReasonCode::GRANTED_QOS_2 => super::SubAckQos::Success(super::QoS::ExactlyOnce),
+                    _ => super::SubAckQos::Failure,
+                }).collect(),
+            }),
+
+            Packet::Subscribe(Subscribe { packet_identifier, properties, subscribe_to }) => {
+                let subscription_identifier = subscription_identifiers(&properties).next();
+                let user_properties = from_user_properties(properties);
+                super::Packet::Subscribe(super::Subscribe {
+                    packet_identifier,
+                    subscribe_to: subscribe_to.into_iter().map(|SubscribeTo { topic_filter, qos, no_local, retain_as_published, retain_handling }| super::SubscribeTo {
+                        topic_filter,
+                        qos,
+                        user_properties: user_properties.clone(),
+                        subscription_identifier,
+                        no_local,
+                        retain_as_published,
+                        retain_handling,
+                    }).collect(),
+                })
+            }
+
+            Packet::UnsubAck(UnsubAck { packet_identifier, reason_codes, .. }) => super::Packet::UnsubAck(super::UnsubAck {
+                packet_identifier,
+                results: reason_codes.into_iter().map(|reason_code| match reason_code {
+                    ReasonCode::SUCCESS => super::UnsubAckResult::Success,
+                    ReasonCode::NO_SUBSCRIPTION_EXISTED => super::UnsubAckResult::NoSubscriptionExisted,
+                    _ => super::UnsubAckResult::Failure,
+                }).collect(),
+            }),
+
+            Packet::Unsubscribe(Unsubscribe { packet_identifier, unsubscribe_from, .. }) => super::Packet::Unsubscribe(super::Unsubscribe {
+                packet_identifier,
+                unsubscribe_from,
+            }),
+        };
+        Ok(packet)
+    }
+}
+
+fn into_user_properties(user_properties: Vec<(super::ByteStr, super::ByteStr)>) -> Vec<Property> {
+    user_properties.into_iter().map(|(name, value)| Property::UserProperty(name, value)).collect()
+}
+
+fn from_user_properties(properties: Vec<Property>) -> Vec<(super::ByteStr, super::ByteStr)> {
+    properties.into_iter().filter_map(|property| match property {
+        Property::UserProperty(name, value) => Some((name, value)),
+        _ => None,
+    }).collect()
+}
+
+fn into_request_properties(response_topic: Option<super::ByteStr>, correlation_data: Option<bytes::Bytes>) -> impl Iterator<Item = Property> {
+    response_topic.map(Property::ResponseTopic).into_iter()
+        .chain(correlation_data.map(Property::CorrelationData))
+}
+
+fn from_request_properties(properties: &[Property]) -> (Option<super::ByteStr>, Option<bytes::Bytes>) {
+    let mut response_topic = None;
+    let mut correlation_data = None;
+    for property in properties {
+        match property {
+            Property::ResponseTopic(value) => response_topic = Some(value.clone()),
+            Property::CorrelationData(value) => correlation_data = Some(value.clone()),
+            _ => (),
+        }
+    }
+    (response_topic, correlation_data)
+}
+
+/// Encodes the message expiry in whole seconds, rounded up so that a publication that has not expired yet is not sent as already expired
+fn into_message_expiry_property(message_expiry: std::time::Duration) -> Property {
+    let message_expiry = message_expiry.as_secs() + u64::from(message_expiry.subsec_nanos() > 0);
+    Property::MessageExpiryInterval(message_expiry.try_into().unwrap_or(u32::MAX))
+}
+
+fn message_expiry(properties: &[Property]) -> Option<std::time::Duration> {
+    properties.iter().find_map(|property| match property {
+        Property::MessageExpiryInterval(message_expiry) => Some(std::time::Duration::from_secs((*message_expiry).into())),
+        _ => None,
+    })
+}
+
+fn content_type(properties: &[Property]) -> Option<super::ByteStr> {
+    properties.iter().find_map(|property| match property {
+        Property::ContentType(content_type) => Some(content_type.clone()),
+        _ => None,
+    })
+}
+
+fn into_authentication_properties(authentication_method: Option<super::ByteStr>, authentication_data: Option<bytes::Bytes>) -> Vec<Property> {
+    authentication_method.map(Property::AuthenticationMethod).into_iter()
+        .chain(authentication_data.map(Property::AuthenticationData))
+        .collect()
+}
+
+fn from_authentication_properties(properties: &[Property]) -> (Option<super::ByteStr>, Option<bytes::Bytes>) {
+    let mut authentication_method = None;
+    let mut authentication_data = None;
+    for property in properties {
+        match property {
+            Property::AuthenticationMethod(value) => authentication_method = Some(value.clone()),
+            Property::AuthenticationData(value) => authentication_data = Some(value.clone()),
+            _ => (),
+        }
+    }
+    (authentication_method, authentication_data)
+}
+
+/// A Maximum Packet Size of zero is a protocol error, so it is treated like an absent one
+fn maximum_packet_size(properties: &[Property]) -> Option<u32> {
+    properties.iter().find_map(|property| match property {
+        Property::MaximumPacketSize(maximum_packet_size) if *maximum_packet_size > 0 => Some(*maximum_packet_size),
+        _ => None,
+    })
+}
+
+fn server_reference(properties: &[Property]) -> Option<super::ByteStr> {
+    properties.iter().find_map(|property| match property {
+        Property::ServerReference(server_reference) => Some(server_reference.clone()),
+        _ => None,
+    })
+}
+
+fn subscription_identifiers(properties: &[Property]) -> impl Iterator<Item = u32> + '_ {
+    properties.iter().filter_map(|property| match property {
+        Property::SubscriptionIdentifier(subscription_identifier) => Some(*subscription_identifier),
+        _ => None,
+    })
+}
+
+/// Decodes the body of a PUBACK, PUBREC, PUBREL or PUBCOMP. The reason code and properties are omitted when they are the defaults.
+fn decode_publish_ack(
+    packet_type: u8,
+    flags: u8,
+    expected_flags: u8,
+    mut src: bytes::BytesMut,
+) -> Result<(super::PacketIdentifier, ReasonCode, Vec<Property>), super::DecodeError> {
+    if flags != expected_flags || src.len() < std::mem::size_of::<u16>() {
+        return Err(super::DecodeError::UnrecognizedPacket {
+            packet_type,
+            flags,
+            remaining_length: src.len(),
+        });
+    }
+
+    let packet_identifier = src.get_packet_identifier()?;
+    let (reason_code, properties) = decode_optional_reason_code(&mut src)?;
+    Ok((packet_identifier, reason_code, properties))
+}
+
+fn encode_publish_ack<B>(
+    packet_identifier: super::PacketIdentifier,
+    reason_code: ReasonCode,
+    properties: Vec<Property>,
+    dst: &mut B,
+) -> Result<(), super::EncodeError>
+where
+    B: ByteBuf,
+{
+    dst.put_packet_identifier_bytes(packet_identifier);
+    encode_optional_reason_code(reason_code, properties, dst)
+}
+
+/// Decodes the body of a SUBACK or UNSUBACK, which have a reason code for each topic filter of the request
+fn decode_topic_acks(
+    packet_type: u8,
+    flags: u8,
+    mut src: bytes::BytesMut,
+) -> Result<(super::PacketIdentifier, Vec<Property>, Vec<ReasonCode>), super::DecodeError> {
+    if flags != 0 || src.len() < std::mem::size_of::<u16>() {
+        return Err(super::DecodeError::UnrecognizedPacket {
+            packet_type,
+            flags,
+            remaining_length: src.len(),
+        });
+    }
+
+    let packet_identifier = src.get_packet_identifier()?;
+    let properties = decode_properties(&mut src)?;
+    let reason_codes: Vec<_> = src.iter().map(|&reason_code| ReasonCode(reason_code)).collect();
+
+    if reason_codes.is_empty() {
+        return Err(super::DecodeError::NoTopics);
+    }
+
+    Ok((packet_identifier, properties, reason_codes))
+}
+
+fn encode_topic_acks<B>(
+    packet_identifier: super::PacketIdentifier,
+    properties: Vec<Property>,
+    reason_codes: Vec<ReasonCode>,
+    dst: &mut B,
+) -> Result<(), super::EncodeError>
+where
+    B: ByteBuf,
+{
+    dst.put_packet_identifier_bytes(packet_identifier);
+    encode_properties(properties, dst)?;
+    for reason_code in reason_codes {
+        dst.put_u8_bytes(reason_code.0);
+    }
+    Ok(())
+}
+
+/// Decodes a reason code and properties that are omitted from the packet when they are [`ReasonCode::SUCCESS`] and empty
+fn decode_optional_reason_code(src: &mut bytes::BytesMut) -> Result<(ReasonCode, Vec<Property>), super::DecodeError> {
+    if src.is_empty() {
+        return Ok((ReasonCode::SUCCESS, vec![]));
+    }
+
+    let reason_code = ReasonCode(src.try_get_u8()?);
+    let properties = if src.is_empty() { vec![] } else { decode_properties(src)? };
+    Ok((reason_code, properties))
+}
+
+fn encode_optional_reason_code<B>(reason_code: ReasonCode, properties: Vec<Property>, dst: &mut B) -> Result<(), super::EncodeError>
+where
+    B: ByteBuf,
+{
+    if reason_code == ReasonCode::SUCCESS && properties.is_empty() {
+        return Ok(());
+    }
+
+    dst.put_u8_bytes(reason_code.0);
+    if !properties.is_empty() {
+        encode_properties(properties, dst)?;
+    }
+    Ok(())
+}
+
+/// Decodes properties prefixed with their length
+fn decode_properties(src: &mut bytes::BytesMut) -> Result<Vec<Property>, super::DecodeError> {
+    let len = decode_variable_byte_integer(src)? as usize;
+    if src.len() < len {
+        return Err(super::DecodeError::IncompletePacket);
+    }
+
+    let mut src = src.split_to(len);
+    let mut properties = vec![];
+    while !src.is_empty() {
+        properties.push(Property::decode(&mut src)?);
+    }
+    Ok(properties)
+}
+
+/// Encodes properties prefixed with their length
+fn encode_properties<B>(properties: Vec<Property>, dst: &mut B) -> Result<(), super::EncodeError>
+where
+    B: ByteBuf,
+{
+    let mut counter = super::ByteCounter::new();
+    for property in &properties {
+        property.clone().encode(&mut counter)?;
+    }
+    super::encode_remaining_length(counter.0, dst)?;
+
+    for property in properties {
+        property.encode(dst)?;
+    }
+    Ok(())
+}
+
+/// Ref: 1.5.5 Variable Byte Integer
+fn decode_variable_byte_integer(src: &mut bytes::BytesMut) -> Result<u32, super::DecodeError> {
+    let value = super::decode_remaining_length(&mut Default::default(), src)?.ok_or(super::DecodeError::IncompletePacket)?;
+    value.try_into().map_err(|_| super::DecodeError::RemainingLengthTooHigh)
+}
+
+fn decode_byte_str(src: &mut bytes::BytesMut) -> Result<super::ByteStr, super::DecodeError> {
+    super::ByteStr::decode(src)?.ok_or(super::DecodeError::IncompletePacket)
+}
+
+/// Ref: 1.5.6 Binary Data
+fn decode_binary_data(src: &mut bytes::BytesMut) -> Result<bytes::Bytes, super::DecodeError> {
+    let len = usize::from(src.try_get_u16_be()?);
+    if src.len() < len {
+        return Err(super::DecodeError::IncompletePacket);
+    }
+    Ok(src.split_to(len).freeze())
+}
+
+fn put_binary_data_bytes<B>(dst: &mut B, data: bytes::Bytes) -> Result<(), super::EncodeError>
+where
+    B: ByteBuf,
+{
+    let len = data.len();
+    dst.put_u16_bytes(len.try_into().map_err(|_| super::EncodeError::BinaryDataTooLarge(len))?);
+    dst.put_bytes(data);
+    Ok(())
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn put_u32_bytes<B>(dst: &mut B, n: u32)
+where
+    B: ByteBuf,
+{
+    dst.put_u16_bytes((n >> 16) as u16);
+    dst.put_u16_bytes(n as u16);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    #[test]
+    fn round_trip() {
+        use std::str::FromStr;
+
+        let packet_identifier = crate::proto::PacketIdentifier::new(5).unwrap();
+
+        let packets = vec![
+            super::Packet::Connect(super::Connect {
+                username: Some(crate::proto::ByteStr::from_str("user").unwrap()),
+                password: None,
+                will: Some(super::Will {
+                    publication: crate::proto::Publication {
+                        topic_name: crate::proto::ByteStr::from_str("devices/foo/status").unwrap(),
+                        qos: crate::proto::QoS::AtLeastOnce,
+                        retain: true,
+                        payload: bytes::Bytes::from_static(b"offline"),
+                        user_properties: vec![],
+                        response_topic: None,
+                        correlation_data: None,
+                        message_expiry: None,
+                        content_type: None,
+                    },
+                    properties: vec![super::Property::WillDelayInterval(30)],
+                }),
+                client_id: crate::proto::ClientId::IdWithExistingSession(crate::proto::ByteStr::from_str("foo").unwrap()),
+                keep_alive: std::time::Duration::from_secs(30),
+                properties: vec![
+                    super::Property::SessionExpiryInterval(u32::MAX),
+                    super::Property::UserProperty(crate::proto::ByteStr::from_str("a").unwrap(), crate::proto::ByteStr::from_str("b").unwrap()),
+                ],
+            }),
+            super::Packet::ConnAck(super::ConnAck {
+                session_present: true,
+                reason_code: super::ReasonCode::SUCCESS,
+                properties: vec![super::Property::ReceiveMaximum(10), super::Property::MaximumQoS(1)],
+            }),
+            super::Packet::Publish(super::Publish {
+                packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, true),
+                retain: false,
+                topic_name: crate::proto::ByteStr::from_str("foo/bar").unwrap(),
+                properties: vec![
+                    super::Property::CorrelationData(bytes::Bytes::from_static(b"\x01\x02")),
+                    super::Property::SubscriptionIdentifier(200_000),
+                ],
+                payload: bytes::Bytes::from_static(b"hello"),
+            }),
+            super::Packet::PubAck(super::PubAck {
+                packet_identifier,
+                reason_code: super::ReasonCode::SUCCESS,
+                properties: vec![],
+            }),
+            super::Packet::PubRec(super::PubRec {
+                packet_identifier,
+                reason_code: super::ReasonCode::NO_MATCHING_SUBSCRIBERS,
+                properties: vec![],
+            }),
+            super::Packet::PubRel(super::PubRel {
+                packet_identifier,
+                reason_code: super::ReasonCode::PACKET_IDENTIFIER_NOT_FOUND,
+                properties: vec![super::Property::ReasonString(crate::proto::ByteStr::from_str("gone").unwrap())],
+            }),
+            super::Packet::Subscribe(super::Subscribe {
+                packet_identifier,
+                properties: vec![super::Property::SubscriptionIdentifier(1)],
+                subscribe_to: vec![super::SubscribeTo {
+                    topic_filter: crate::proto::ByteStr::from_str("foo/#").unwrap(),
+                    qos: crate::proto::QoS::AtLeastOnce,
+                    no_local: true,
+                    retain_as_published: false,
+                    retain_handling: super::RetainHandling::DoNotSend,
+                }],
+            }),
+            super::Packet::SubAck(super::SubAck {
+                packet_identifier,
+                properties: vec![],
+                reason_codes: vec![super::ReasonCode::GRANTED_QOS_1, super::ReasonCode::NOT_AUTHORIZED],
+            }),
+            super::Packet::Disconnect(super::Disconnect {
+                reason_code: super::ReasonCode::NORMAL_DISCONNECTION,
+                properties: vec![],
+            }),
+            super::Packet::Disconnect(super::Disconnect {
+                reason_code: super::ReasonCode::DISCONNECT_WITH_WILL_MESSAGE,
+                properties: vec![],
+            }),
+            super::Packet::Auth(super::Auth {
+                reason_code: super::ReasonCode::CONTINUE_AUTHENTICATION,
+                properties: vec![super::Property::AuthenticationMethod(crate::proto::ByteStr::from_str("SCRAM-SHA-1").unwrap())],
+            }),
+        ];
+
+        for packet in packets {
+            let mut bytes = bytes::BytesMut::new();
+            super::encode(packet.clone(), &mut bytes).unwrap();
+            let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+            assert_eq!(decoded, packet);
+            assert!(bytes.is_empty());
+        }
+    }
+
+    #[test]
+    fn negotiate_protocol_level() {
+        use std::str::FromStr;
+
+        let connect = |protocol_level| crate::proto::Packet::Connect(crate::proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id: crate::proto::ClientId::IdWithCleanSession(crate::proto::ByteStr::from_str("foo").unwrap()),
+            keep_alive: std::time::Duration::from_secs(30),
+            protocol_name: crate::PROTOCOL_NAME,
+            protocol_level,
+            session_expiry: None,
+            will_delay: None,
+            maximum_packet_size: None,
+            authentication_method: None,
+            authentication_data: None,
+        });
+
+        // A client that asks for MQTT 5.0 encodes its packets as MQTT 5.0, and a server decodes them as such
+        let mut encoder = super::NegotiatedEncoder::default();
+        let mut decoder = super::NegotiatedDecoder::default();
+        let mut bytes = bytes::BytesMut::new();
+        encoder.encode(connect(super::PROTOCOL_LEVEL), &mut bytes).unwrap();
+        assert_eq!(bytes[8], super::PROTOCOL_LEVEL);
+        assert_eq!(decoder.decode(&mut bytes).unwrap().unwrap(), connect(super::PROTOCOL_LEVEL));
+        assert_eq!(decoder.protocol_level(), super::PROTOCOL_LEVEL);
+
+        // A server that does not support MQTT 5.0 refuses the connection with an MQTT 3.1.1 CONNACK
+        let mut client_decoder = super::NegotiatedDecoder::default();
+        let refused = crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+            session_present: false,
+            return_code: crate::proto::ConnectReturnCode::Refused(crate::proto::ConnectionRefusedReason::UnacceptableProtocolVersion),
+            authentication_data: None,
+            receive_maximum: None,
+            maximum_packet_size: None,
+            server_reference: None,
+            assigned_client_id: None,
+            server_keep_alive: None,
+            maximum_qos: None,
+            retain_available: None,
+            reason_string: None,
+            user_properties: vec![],
+        });
+        crate::proto::encode(refused.clone(), &mut bytes).unwrap();
+        assert_eq!(client_decoder.decode(&mut bytes).unwrap().unwrap(), refused);
+        assert_eq!(client_decoder.protocol_level(), crate::PROTOCOL_LEVEL);
+
+        // A server that supports it accepts with an MQTT 5.0 CONNACK, since the encoder of its decoder follows the CONNECT,
+        // and the reason codes of later packets are kept where they can be
+        let mut server_encoder = decoder.encoder();
+        let accepted = crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+            session_present: false,
+            return_code: crate::proto::ConnectReturnCode::Accepted,
+            authentication_data: None,
+            receive_maximum: Some(10),
+            maximum_packet_size: Some(1024),
+            server_reference: None,
+            assigned_client_id: None,
+            server_keep_alive: None,
+            maximum_qos: None,
+            retain_available: None,
+            reason_string: None,
+            user_properties: vec![],
+        });
+        server_encoder.encode(accepted.clone(), &mut bytes).unwrap();
+        assert_eq!(client_decoder.decode(&mut bytes).unwrap().unwrap(), accepted);
+        assert_eq!(client_decoder.protocol_level(), super::PROTOCOL_LEVEL);
+
+        let sub_ack = super::Packet::SubAck(super::SubAck {
+            packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap(),
+            properties: vec![],
+            reason_codes: vec![super::ReasonCode::GRANTED_QOS_2, super::ReasonCode::TOPIC_FILTER_INVALID],
+        });
+        assert_eq!(crate::proto::Packet::try_from(sub_ack).unwrap(), crate::proto::Packet::SubAck(crate::proto::SubAck {
+            packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap(),
+            qos: vec![crate::proto::SubAckQos::Success(crate::proto::QoS::ExactlyOnce), crate::proto::SubAckQos::Failure],
+        }));
+
+        let unsub_ack = crate::proto::Packet::UnsubAck(crate::proto::UnsubAck {
+            packet_identifier: crate::proto::PacketIdentifier::new(2).unwrap(),
+            results: vec![crate::proto::UnsubAckResult::Success, crate::proto::UnsubAckResult::NoSubscriptionExisted],
+        });
+        server_encoder.encode(unsub_ack.clone(), &mut bytes).unwrap();
+        assert_eq!(client_decoder.decode(&mut bytes).unwrap().unwrap(), unsub_ack);
+
+        // A server answers an MQTT 3.1.1 CONNECT with an MQTT 3.1.1 CONNACK
+        let mut decoder = super::NegotiatedDecoder::default();
+        let mut server_encoder = decoder.encoder();
+        encoder.encode(connect(crate::PROTOCOL_LEVEL), &mut bytes).unwrap();
+        assert_eq!(decoder.decode(&mut bytes).unwrap().unwrap(), connect(crate::PROTOCOL_LEVEL));
+        server_encoder.encode(refused, &mut bytes).unwrap();
+        assert_eq!(&bytes[..], &[0x20, 0x02, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn user_properties() {
+        use std::str::FromStr;
+
+        let user_property = |name, value| (crate::proto::ByteStr::from_str(name).unwrap(), crate::proto::ByteStr::from_str(value).unwrap());
+
+        let publish = crate::proto::Packet::Publish(crate::proto::Publish {
+            packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+            retain: false,
+            topic_name: crate::proto::ByteStr::from_str("foo/bar").unwrap(),
+            payload: bytes::Bytes::from_static(b"hello"),
+            user_properties: vec![user_property("tenant", "a"), user_property("trace", "1")],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        });
+        let subscribe = crate::proto::Packet::Subscribe(crate::proto::Subscribe {
+            packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap(),
+            subscribe_to: vec![
+                crate::proto::SubscribeTo {
+                    topic_filter: crate::proto::ByteStr::from_str("foo/#").unwrap(),
+                    qos: crate::proto::QoS::AtLeastOnce,
+                    user_properties: vec![user_property("tenant", "a")],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
+                },
+                crate::proto::SubscribeTo {
+                    topic_filter: crate::proto::ByteStr::from_str("bar").unwrap(),
+                    qos: crate::proto::QoS::AtMostOnce,
+                    user_properties: vec![user_property("tenant", "a")],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
+                },
+            ],
+        });
+
+        for packet in vec![publish.clone(), subscribe] {
+            let mut bytes = bytes::BytesMut::new();
+            super::encode(super::Packet::from(packet.clone()), &mut bytes).unwrap();
+            let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+            assert_eq!(crate::proto::Packet::try_from(decoded).unwrap(), packet);
+        }
+
+        // MQTT 3.1.1 has nowhere to put them
+        let mut bytes = bytes::BytesMut::new();
+        crate::proto::encode(publish, &mut bytes).unwrap();
+        match crate::proto::decode(&mut Default::default(), &mut bytes).unwrap() {
+            Some(crate::proto::Packet::Publish(publish)) => assert!(publish.user_properties.is_empty()),
+            packet => panic!("expected PUBLISH but got {:?}", packet),
+        }
+    }
+
+    #[test]
+    fn session_expiry() {
+        use std::str::FromStr;
+
+        for &(session_expiry, ref properties) in &[
+            (None, vec![super::Property::SessionExpiryInterval(u32::MAX)]),
+            (Some(std::time::Duration::from_secs(0)), vec![]),
+            (Some(std::time::Duration::from_secs(300)), vec![super::Property::SessionExpiryInterval(300)]),
+        ] {
+            let connect = crate::proto::Packet::Connect(crate::proto::Connect {
+                username: None,
+                password: None,
+                will: None,
+                client_id: crate::proto::ClientId::IdWithCleanSession(crate::proto::ByteStr::from_str("foo").unwrap()),
+                keep_alive: std::time::Duration::from_secs(30),
+                protocol_name: crate::PROTOCOL_NAME,
+                protocol_level: super::PROTOCOL_LEVEL,
+                session_expiry,
+                will_delay: None,
+                maximum_packet_size: None,
+                authentication_method: None,
+                authentication_data: None,
+            });
+
+            let packet = super::Packet::from(connect.clone());
+            match &packet {
+                super::Packet::Connect(packet) => assert_eq!(&packet.properties, properties),
+                packet => panic!("expected CONNECT but got {:?}", packet),
+            }
+            assert_eq!(crate::proto::Packet::try_from(packet).unwrap(), connect);
+        }
+    }
+
+    #[test]
+    fn subscription_identifiers() {
+        use std::str::FromStr;
+
+        let subscribe_to = |topic_filter, subscription_identifier| crate::proto::SubscribeTo {
+            topic_filter: crate::proto::ByteStr::from_str(topic_filter).unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        };
+
+        // A SUBSCRIBE carries a single subscription identifier, which is given back to every subscription
+        let subscribe = crate::proto::Packet::Subscribe(crate::proto::Subscribe {
+            packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap(),
+            subscribe_to: vec![subscribe_to("foo/#", Some(200_000)), subscribe_to("bar", Some(200_000))],
+        });
+        match super::Packet::from(subscribe.clone()) {
+            super::Packet::Subscribe(super::Subscribe { properties, .. }) =>
+                assert_eq!(properties, [super::Property::SubscriptionIdentifier(200_000)]),
+            packet => panic!("expected SUBSCRIBE but got {:?}", packet),
+        }
+
+        let publish = crate::proto::Packet::Publish(crate::proto::Publish {
+            packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+            retain: false,
+            topic_name: crate::proto::ByteStr::from_str("foo/bar").unwrap(),
+            payload: bytes::Bytes::from_static(b"hello"),
+            user_properties: vec![],
+            subscription_identifiers: vec![1, 200_000],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        });
+
+        for packet in vec![subscribe, publish] {
+            let mut bytes = bytes::BytesMut::new();
+            super::encode(super::Packet::from(packet.clone()), &mut bytes).unwrap();
+            let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+            assert_eq!(crate::proto::Packet::try_from(decoded).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn subscription_options() {
+        use std::str::FromStr;
+
+        let subscribe = crate::proto::Packet::Subscribe(crate::proto::Subscribe {
+            packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap(),
+            subscribe_to: vec![crate::proto::SubscribeTo {
+                topic_filter: crate::proto::ByteStr::from_str("foo/#").unwrap(),
+                qos: crate::proto::QoS::AtLeastOnce,
+                user_properties: vec![],
+                subscription_identifier: None,
+                no_local: true,
+                retain_as_published: true,
+                retain_handling: super::RetainHandling::SendOnNewSubscribe,
+            }],
+        });
+
+        let mut bytes = bytes::BytesMut::new();
+        super::encode(super::Packet::from(subscribe.clone()), &mut bytes).unwrap();
+        assert_eq!(bytes[bytes.len() - 1], 0x1D);
+        let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+        assert_eq!(crate::proto::Packet::try_from(decoded).unwrap(), subscribe);
+    }
+
+    #[test]
+    fn server_reference() {
+        use std::str::FromStr;
+
+        for packet in vec![
+            crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+                session_present: false,
+                return_code: crate::proto::ConnectReturnCode::Refused(crate::proto::ConnectionRefusedReason::Other(0x9C)),
+                authentication_data: None,
+                receive_maximum: None,
+                maximum_packet_size: None,
+                server_reference: Some(crate::proto::ByteStr::from_str("other:1883").unwrap()),
+                assigned_client_id: None,
+                server_keep_alive: None,
+                maximum_qos: None,
+                retain_available: None,
+                reason_string: None,
+                user_properties: vec![],
+            }),
+            crate::proto::Packet::Disconnect(crate::proto::Disconnect {
+                reason: crate::proto::DisconnectReason::Other(0x9C),
+                server_reference: Some(crate::proto::ByteStr::from_str("other:1883").unwrap()),
+            }),
+        ] {
+            let mut bytes = bytes::BytesMut::new();
+            super::encode(super::Packet::from(packet.clone()), &mut bytes).unwrap();
+            let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+            assert_eq!(crate::proto::Packet::try_from(decoded).unwrap(), packet);
+        }
+    }
+
+    #[test]
+    fn conn_ack_properties() {
+        use std::str::FromStr;
+
+        let conn_ack = crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+            session_present: true,
+            return_code: crate::proto::ConnectReturnCode::Accepted,
+            authentication_data: None,
+            receive_maximum: None,
+            maximum_packet_size: None,
+            server_reference: None,
+            assigned_client_id: Some(crate::proto::ByteStr::from_str("assigned").unwrap()),
+            server_keep_alive: Some(std::time::Duration::from_secs(10)),
+            maximum_qos: Some(crate::proto::QoS::AtMostOnce),
+            retain_available: Some(false),
+            reason_string: Some(crate::proto::ByteStr::from_str("welcome").unwrap()),
+            user_properties: vec![(crate::proto::ByteStr::from_str("region").unwrap(), crate::proto::ByteStr::from_str("eu").unwrap())],
+        });
+
+        let mut bytes = bytes::BytesMut::new();
+        super::encode(super::Packet::from(conn_ack.clone()), &mut bytes).unwrap();
+        let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+        assert_eq!(crate::proto::Packet::try_from(decoded).unwrap(), conn_ack);
+    }
+
+    #[test]
+    fn publication_properties() {
+        use std::str::FromStr;
+
+        let publish = crate::proto::Packet::Publish(crate::proto::Publish {
+            packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+            retain: false,
+            topic_name: crate::proto::ByteStr::from_str("requests").unwrap(),
+            payload: bytes::Bytes::from_static(b"hello"),
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: Some(crate::proto::ByteStr::from_str("replies/a").unwrap()),
+            correlation_data: Some(bytes::Bytes::from_static(b"1")),
+            message_expiry: Some(std::time::Duration::from_secs(30)),
+            content_type: None,
+        });
+        match super::Packet::from(publish.clone()) {
+            super::Packet::Publish(super::Publish { properties, .. }) => assert_eq!(properties, [
+                super::Property::ResponseTopic(crate::proto::ByteStr::from_str("replies/a").unwrap()),
+                super::Property::CorrelationData(bytes::Bytes::from_static(b"1")),
+                super::Property::MessageExpiryInterval(30),
+            ]),
+            packet => panic!("expected PUBLISH but got {:?}", packet),
+        }
+
+        let mut bytes = bytes::BytesMut::new();
+        super::encode(super::Packet::from(publish.clone()), &mut bytes).unwrap();
+        let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+        assert_eq!(crate::proto::Packet::try_from(decoded).unwrap(), publish);
+    }
+
+    #[test]
+    fn will_properties() {
+        use std::str::FromStr;
+
+        let connect = crate::proto::Packet::Connect(crate::proto::Connect {
+            username: None,
+            password: None,
+            will: Some(crate::proto::Publication {
+                topic_name: crate::proto::ByteStr::from_str("devices/foo/status").unwrap(),
+                qos: crate::proto::QoS::AtLeastOnce,
+                retain: true,
+                payload: bytes::Bytes::from_static(b"{\"online\":false}"),
+                user_properties: vec![(crate::proto::ByteStr::from_str("a").unwrap(), crate::proto::ByteStr::from_str("b").unwrap())],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: Some(crate::proto::ByteStr::from_str("application/json").unwrap()),
+            }),
+            client_id: crate::proto::ClientId::IdWithExistingSession(crate::proto::ByteStr::from_str("foo").unwrap()),
+            keep_alive: std::time::Duration::from_secs(30),
+            protocol_name: crate::PROTOCOL_NAME,
+            protocol_level: super::PROTOCOL_LEVEL,
+            session_expiry: None,
+            will_delay: Some(std::time::Duration::from_secs(10)),
+            maximum_packet_size: None,
+            authentication_method: None,
+            authentication_data: None,
+        });
+        match super::Packet::from(connect.clone()) {
+            super::Packet::Connect(super::Connect { will: Some(super::Will { properties, .. }), .. }) => assert_eq!(properties, [
+                super::Property::UserProperty(crate::proto::ByteStr::from_str("a").unwrap(), crate::proto::ByteStr::from_str("b").unwrap()),
+                super::Property::ContentType(crate::proto::ByteStr::from_str("application/json").unwrap()),
+                super::Property::WillDelayInterval(10),
+            ]),
+            packet => panic!("expected CONNECT with a will but got {:?}", packet),
+        }
+
+        let mut bytes = bytes::BytesMut::new();
+        super::encode(super::Packet::from(connect.clone()), &mut bytes).unwrap();
+        let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+        assert_eq!(crate::proto::Packet::try_from(decoded).unwrap(), connect);
+    }
+
+    #[test]
+    fn enhanced_authentication() {
+        use std::str::FromStr;
+
+        let method = crate::proto::ByteStr::from_str("SCRAM-SHA-256").unwrap();
+
+        let connect = crate::proto::Packet::Connect(crate::proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id: crate::proto::ClientId::IdWithCleanSession(crate::proto::ByteStr::from_str("foo").unwrap()),
+            keep_alive: std::time::Duration::from_secs(30),
+            protocol_name: crate::PROTOCOL_NAME,
+            protocol_level: super::PROTOCOL_LEVEL,
+            session_expiry: None,
+            will_delay: None,
+            maximum_packet_size: None,
+            authentication_method: Some(method.clone()),
+            authentication_data: Some(bytes::Bytes::from_static(b"client-first")),
+        });
+        match super::Packet::from(connect.clone()) {
+            super::Packet::Connect(super::Connect { properties, .. }) => assert_eq!(properties, [
+                super::Property::SessionExpiryInterval(u32::MAX),
+                super::Property::AuthenticationMethod(method.clone()),
+                super::Property::AuthenticationData(bytes::Bytes::from_static(b"client-first")),
+            ]),
+            packet => panic!("expected CONNECT but got {:?}", packet),
+        }
+
+        let auth = |reason, authentication_data| crate::proto::Packet::Auth(crate::proto::Auth {
+            reason,
+            authentication_method: method.clone(),
+            authentication_data,
+        });
+
+        for packet in vec![
+            connect,
+            crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+                session_present: false,
+                return_code: crate::proto::ConnectReturnCode::Accepted,
+                authentication_data: Some(bytes::Bytes::from_static(b"server-final")),
+                receive_maximum: None,
+                maximum_packet_size: None,
+                server_reference: None,
+                assigned_client_id: None,
+                server_keep_alive: None,
+                maximum_qos: None,
+                retain_available: None,
+                reason_string: None,
+                user_properties: vec![],
+            }),
+            auth(crate::proto::AuthReason::ContinueAuthentication, Some(bytes::Bytes::from_static(b"server-first"))),
+            auth(crate::proto::AuthReason::ReAuthenticate, None),
+            auth(crate::proto::AuthReason::Success, None),
+        ] {
+            let mut bytes = bytes::BytesMut::new();
+            super::encode(super::Packet::from(packet.clone()), &mut bytes).unwrap();
+            let decoded = super::decode(&mut Default::default(), &mut bytes).unwrap().unwrap();
+            assert_eq!(crate::proto::Packet::try_from(decoded).unwrap(), packet);
+        }
+
+        // AUTH packets only exist in MQTT 5.0
+        let mut bytes = bytes::BytesMut::new();
+        let err = crate::proto::encode(auth(crate::proto::AuthReason::Success, None), &mut bytes).unwrap_err();
+        assert!(matches!(err, crate::proto::EncodeError::AuthNotSupported));
+    }
+
+    #[test]
+    fn topic_aliases() {
+        use std::str::FromStr;
+
+        let mut decoder = super::NegotiatedDecoder::default();
+        let mut encoder = decoder.encoder();
+        let mut bytes = bytes::BytesMut::new();
+
+        encoder.encode(crate::proto::Packet::Connect(crate::proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id: crate::proto::ClientId::IdWithCleanSession(crate::proto::ByteStr::from_str("foo").unwrap()),
+            keep_alive: std::time::Duration::from_secs(30),
+            protocol_name: crate::PROTOCOL_NAME,
+            protocol_level: super::PROTOCOL_LEVEL,
+            session_expiry: None,
+            will_delay: None,
+            maximum_packet_size: None,
+            authentication_method: None,
+            authentication_data: None,
+        }), &mut bytes).unwrap();
+        bytes.clear();
+
+        super::encode(super::Packet::ConnAck(super::ConnAck {
+            session_present: false,
+            reason_code: super::ReasonCode::SUCCESS,
+            properties: vec![super::Property::TopicAliasMaximum(2)],
+        }), &mut bytes).unwrap();
+        decoder.decode(&mut bytes).unwrap().unwrap();
+
+        let mut publish = |topic_name| {
+            encoder.encode(crate::proto::Packet::Publish(crate::proto::Publish {
+                packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+                retain: false,
+                topic_name: crate::proto::ByteStr::from_str(topic_name).unwrap(),
+                payload: bytes::Bytes::new(),
+                user_properties: vec![],
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
+                message_expiry: None,
+                content_type: None,
+            }), &mut bytes).unwrap();
+            match super::decode(&mut Default::default(), &mut bytes).unwrap() {
+                Some(super::Packet::Publish(super::Publish { topic_name, properties, .. })) => (topic_name.to_string(), properties),
+                packet => panic!("expected PUBLISH but got {:?}", packet),
+            }
+        };
+
+        assert_eq!(publish("devices/a"), ("devices/a".to_owned(), vec![super::Property::TopicAlias(1)]));
+        assert_eq!(publish("devices/a"), (String::new(), vec![super::Property::TopicAlias(1)]));
+        assert_eq!(publish("devices/b"), ("devices/b".to_owned(), vec![super::Property::TopicAlias(2)]));
+        assert_eq!(publish("devices/a"), (String::new(), vec![super::Property::TopicAlias(1)]));
+
+        // The least recently used alias is reused for a new topic
+        assert_eq!(publish("devices/c"), ("devices/c".to_owned(), vec![super::Property::TopicAlias(2)]));
+        assert_eq!(publish("devices/b"), ("devices/b".to_owned(), vec![super::Property::TopicAlias(1)]));
+    }
+}
@@ -0,0 +1,187 @@
+/// Builds a server that listens on several addresses at once, over plain TCP, TLS and WebSocket, with a single [`Config`](super::Config).
+///
+/// The clients of every listener share the server's sessions, subscriptions and retained messages, so a client that connects over
+/// WebSocket receives the publications of a client that connects over TLS. Each listener can limit the publications in flight and queued
+/// for its clients differently, with the `_limits` variant of the method that adds it. Listeners that the builder does not bind itself,
+/// such as a [`crate::transport::UnixListener`], are added with [`BrokerBuilder::with_listener`].
+///
+/// ```no_run
+/// # async fn f() -> std::io::Result<()> {
+/// let broker =
+///     mqtt3::server::BrokerBuilder::default()
+///     .with_tcp("0.0.0.0:1883")
+///     .with_ws("0.0.0.0:8080")
+///     .bind().await?;
+/// broker.run().await
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct BrokerBuilder {
+    config: super::Config,
+    addresses: Vec<(Address, Option<super::ListenerLimits>)>,
+    listeners: crate::io::Listeners,
+}
+
+/// An address for the builder to bind a listener to
+#[derive(Debug)]
+enum Address {
+    Tcp(String),
+    #[cfg(feature = "tls-rustls")]
+    Tls(String, crate::transport::ServerCertificate),
+    #[cfg(feature = "ws")]
+    Ws(String),
+}
+
+impl BrokerBuilder {
+    /// Sets the configuration of the server. Defaults to [`Config::default`](super::Config::default).
+    #[must_use]
+    pub fn with_config(mut self, config: super::Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Listens for clients over plain TCP at the given address, such as `0.0.0.0:1883`
+    #[must_use]
+    pub fn with_tcp(self, addr: impl Into<String>) -> Self {
+        self.with_address(Address::Tcp(addr.into()), None)
+    }
+
+    /// Like [`BrokerBuilder::with_tcp`], but the clients of the listener get the given limits instead of the ones of the server's configuration
+    #[must_use]
+    pub fn with_tcp_limits(self, addr: impl Into<String>, limits: super::ListenerLimits) -> Self {
+        self.with_address(Address::Tcp(addr.into()), Some(limits))
+    }
+
+    /// Listens for clients over TLS at the given address, such as `0.0.0.0:8883`, with a [`crate::transport::TlsListener`]
+    /// that presents the given certificate
+    #[cfg(feature = "tls-rustls")]
+    #[must_use]
+    pub fn with_tls(self, addr: impl Into<String>, certificate: crate::transport::ServerCertificate) -> Self {
+        self.with_address(Address::Tls(addr.into(), certificate), None)
+    }
+
+    /// Like [`BrokerBuilder::with_tls`], but the clients of the listener get the given limits instead of the ones of the server's configuration
+    #[cfg(feature = "tls-rustls")]
+    #[must_use]
+    pub fn with_tls_limits(self, addr: impl Into<String>, certificate: crate::transport::ServerCertificate, limits: super::ListenerLimits) -> Self {
+        self.with_address(Address::Tls(addr.into(), certificate), Some(limits))
+    }
+
+    /// Listens for clients with MQTT over WebSocket at the given address, such as `0.0.0.0:8080`, with a [`crate::transport::WsListener`]
+    #[cfg(feature = "ws")]
+    #[must_use]
+    pub fn with_ws(self, addr: impl Into<String>) -> Self {
+        self.with_address(Address::Ws(addr.into()), None)
+    }
+
+    /// Like [`BrokerBuilder::with_ws`], but the clients of the listener get the given limits instead of the ones of the server's configuration
+    #[cfg(feature = "ws")]
+    #[must_use]
+    pub fn with_ws_limits(self, addr: impl Into<String>, limits: super::ListenerLimits) -> Self {
+        self.with_address(Address::Ws(addr.into()), Some(limits))
+    }
+
+    /// Accepts clients from the given listener too, which is already bound
+    #[must_use]
+    pub fn with_listener<L>(mut self, listener: L) -> Self
+    where
+        L: crate::io::Listener + 'static,
+        <L as crate::io::Listener>::PacketStream: 'static,
+        <L as crate::io::Listener>::PacketSink: 'static,
+    {
+        self.listeners = self.listeners.with_listener(listener);
+        self
+    }
+
+    /// Like [`BrokerBuilder::with_listener`], but the clients of the listener get the given limits instead of the ones of the server's configuration
+    #[must_use]
+    pub fn with_listener_limits<L>(mut self, listener: L, limits: super::ListenerLimits) -> Self
+    where
+        L: crate::io::Listener + 'static,
+        <L as crate::io::Listener>::PacketStream: 'static,
+        <L as crate::io::Listener>::PacketSink: 'static,
+    {
+        self.listeners = self.listeners.with_listener_limits(listener, limits);
+        self
+    }
+
+    /// Binds the listeners to their addresses, in the order that they were added. Fails with the error of the first address that
+    /// cannot be bound, in which case the listeners bound before it are closed.
+    pub async fn bind(self) -> std::io::Result<Broker> {
+        let BrokerBuilder { config, addresses, mut listeners } = self;
+
+        let mut local_addrs = Vec::with_capacity(addresses.len());
+
+        for (address, limits) in addresses {
+            match address {
+                Address::Tcp(addr) => {
+                    let listener = tokio::net::TcpListener::bind(addr).await?;
+                    local_addrs.push(listener.local_addr()?);
+                    listeners = add(listeners, listener, limits);
+                },
+
+                #[cfg(feature = "tls-rustls")]
+                Address::Tls(addr, certificate) => {
+                    let listener = crate::transport::TlsListener::bind(addr, certificate).await?;
+                    local_addrs.push(listener.local_addr()?);
+                    listeners = add(listeners, listener, limits);
+                },
+
+                #[cfg(feature = "ws")]
+                Address::Ws(addr) => {
+                    let listener = crate::transport::WsListener::bind(addr).await?;
+                    local_addrs.push(listener.local_addr()?);
+                    listeners = add(listeners, listener, limits);
+                },
+            }
+        }
+
+        Ok(Broker {
+            config,
+            listeners,
+            local_addrs,
+        })
+    }
+
+    fn with_address(mut self, address: Address, limits: Option<super::ListenerLimits>) -> Self {
+        self.addresses.push((address, limits));
+        self
+    }
+}
+
+/// Adds the listener to the listeners, with the limits that it was added to the builder with, if any
+fn add<L>(listeners: crate::io::Listeners, listener: L, limits: Option<super::ListenerLimits>) -> crate::io::Listeners
+where
+    L: crate::io::Listener + 'static,
+    <L as crate::io::Listener>::PacketStream: 'static,
+    <L as crate::io::Listener>::PacketSink: 'static,
+{
+    match limits {
+        Some(limits) => listeners.with_listener_limits(listener, limits),
+        None => listeners.with_listener(listener),
+    }
+}
+
+/// A server whose listeners are bound, returned by [`BrokerBuilder::bind`]
+#[derive(Debug)]
+pub struct Broker {
+    config: super::Config,
+    listeners: crate::io::Listeners,
+    local_addrs: Vec<std::net::SocketAddr>,
+}
+
+impl Broker {
+    /// The local addresses that the listeners are bound to, in the order that their addresses were added to the builder.
+    /// Listeners added with [`BrokerBuilder::with_listener`] are not included.
+    ///
+    /// This is how to find the port that the OS picked for an address with port 0.
+    pub fn local_addrs(&self) -> &[std::net::SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Runs the server. Like [`run_with_config`](super::run_with_config), the returned future resolves when the server is shut down
+    /// with the [`ShutdownHandle`](super::ShutdownHandle) of its configuration.
+    pub fn run(self) -> impl std::future::Future<Output = std::io::Result<()>> {
+        super::run_with_config(self.listeners, self.config)
+    }
+}
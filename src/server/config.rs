@@ -0,0 +1,231 @@
+/// Configuration of the server and the clients accepted by its listener, passed to [`run_with_config`](super::run_with_config)
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub(super) maximum_qos: crate::proto::QoS,
+    pub(super) retain_available: bool,
+    pub(super) max_retained_payload_size: Option<usize>,
+    pub(super) max_retained_messages: Option<usize>,
+    pub(super) max_inflight_messages: Option<usize>,
+    pub(super) max_queued_messages: Option<usize>,
+    pub(super) max_queued_message_age: Option<std::time::Duration>,
+    pub(super) queue_spillover: Option<(std::path::PathBuf, usize)>,
+    pub(super) delayed_publish: bool,
+    pub(super) topic_metrics_budget: Option<(usize, usize)>,
+    pub(super) counters: super::Counters,
+    pub(super) retained_messages: super::RetainedMessages,
+    pub(super) topic_metrics: super::TopicMetrics,
+    pub(super) shutdown_handle: super::ShutdownHandle,
+}
+
+impl Config {
+    /// Sets the highest quality of service that the server accepts for publications and grants for subscriptions.
+    /// Defaults to [`crate::proto::QoS::ExactlyOnce`].
+    ///
+    /// MQTT 5.0 clients are told the maximum in the CONNACK. Publications from clients that send a higher quality of service anyway,
+    /// such as MQTT 3.1.1 clients that cannot be told, are acked as usual, and forwarded to subscribers with the maximum.
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_maximum_qos(mut self, maximum_qos: crate::proto::QoS) -> Self {
+        self.maximum_qos = maximum_qos;
+        self
+    }
+
+    /// Sets whether the server stores retained messages. Defaults to `true`.
+    ///
+    /// If `false`, the retain flag of publications is ignored, and they are only delivered to current subscribers.
+    /// MQTT 5.0 clients are told so in the CONNACK.
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_retain_available(mut self, retain_available: bool) -> Self {
+        self.retain_available = retain_available;
+        self
+    }
+
+    /// Sets the largest payload, in bytes, of a retained message that the server stores. Defaults to no limit.
+    ///
+    /// Retained publications with larger payloads are only delivered to current subscribers,
+    /// and the message previously retained for their topic, if any, is kept.
+    #[must_use]
+    pub fn with_max_retained_payload_size(mut self, max_retained_payload_size: usize) -> Self {
+        self.max_retained_payload_size = Some(max_retained_payload_size);
+        self
+    }
+
+    /// Sets the number of topics that the server stores a retained message for. Defaults to no limit.
+    ///
+    /// Once the limit is reached, retained publications can still replace or clear the retained message of a topic that already has one,
+    /// but retained publications to any other topic are only delivered to current subscribers.
+    #[must_use]
+    pub fn with_max_retained_messages(mut self, max_retained_messages: usize) -> Self {
+        self.max_retained_messages = Some(max_retained_messages);
+        self
+    }
+
+    /// Sets the number of QoS 1 and 2 publications that the server sends to a client without waiting for the client to ack them.
+    /// Defaults to no limit, other than the number of available packet identifiers.
+    ///
+    /// Publications beyond this limit are queued, and sent as the client acks earlier ones. A limit of zero is treated as one.
+    /// This applies to the clients of listeners that do not set their own limit with [`ListenerLimits::with_max_inflight_messages`].
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_max_inflight_messages(mut self, max_inflight_messages: usize) -> Self {
+        self.max_inflight_messages = Some(max_inflight_messages);
+        self
+    }
+
+    /// Sets the number of QoS 1 and 2 publications that the server queues for a client, while the client has the maximum number of
+    /// in-flight publications, or while the client is disconnected from a persistent session. Defaults to no limit.
+    ///
+    /// Publications to a client whose queue is full are dropped, and counted in [`Counters::queued_messages_dropped`](super::Counters::queued_messages_dropped).
+    /// This applies to the clients of listeners that do not set their own limit with [`ListenerLimits::with_max_queued_messages`].
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_max_queued_messages(mut self, max_queued_messages: usize) -> Self {
+        self.max_queued_messages = Some(max_queued_messages);
+        self
+    }
+
+    /// Sets how long the server keeps QoS 1 and 2 publications queued for a client before dropping them. Defaults to no limit.
+    ///
+    /// This keeps a client that reconnects after a long time from receiving stale publications. Dropped publications are counted in
+    /// [`Counters::expired_messages_dropped`](super::Counters::expired_messages_dropped).
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_max_queued_message_age(mut self, max_queued_message_age: std::time::Duration) -> Self {
+        self.max_queued_message_age = Some(max_queued_message_age);
+        self
+    }
+
+    /// Sets the server to spill the QoS 1 and 2 publications that it queues for a client of a persistent session to a file in
+    /// the given directory, once the payloads of the ones queued in memory for the client add up to `memory_threshold` bytes.
+    /// Defaults to keeping all queued publications in memory.
+    ///
+    /// This keeps clients that stay offline for long from exhausting the memory of small devices, such as edge gateways.
+    /// The spilled publications are read back as the client has room for them. They are kept when the server shuts down,
+    /// and delivered when the client reconnects with a persistent session to the server after it restarts, without its subscriptions.
+    /// The publications that were still queued in memory are lost when the server shuts down.
+    ///
+    /// The directory must exist. If a publication cannot be spilled, it is dropped rather than kept in memory, so that it is not
+    /// delivered ahead of the ones spilled before it, and counted in [`super::Counters::queued_messages_dropped`].
+    /// The files are written from the task that runs the server, so the directory should be on local storage.
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_queue_spillover(mut self, directory: impl Into<std::path::PathBuf>, memory_threshold: usize) -> Self {
+        self.queue_spillover = Some((directory.into(), memory_threshold));
+        self
+    }
+
+    /// Sets whether the server holds publications to topics of the form `$delayed/{delay in seconds}/{topic}` for the given delay,
+    /// and then publishes them to the topic, like EMQX does. Defaults to `false`.
+    ///
+    /// This lets clients schedule publications without an external job system. Delayed publications are retained when they are published,
+    /// if they ask to be. They are acked when they are received, and are lost if the server shuts down before they are due.
+    /// Publications to a `$delayed/` topic that is not of this form are dropped.
+    ///
+    /// The delays are timed with tokio, so the server must run on a tokio runtime with the time driver enabled.
+    /// If `false`, `$delayed/` topics are ordinary topics.
+    #[must_use]
+    pub fn with_delayed_publish(mut self, delayed_publish: bool) -> Self {
+        self.delayed_publish = delayed_publish;
+        self
+    }
+
+    /// Sets the server to count the publications that clients publish, and their payload bytes, for each topic prefix made of
+    /// the first `prefix_levels` levels of the topic name. Defaults to not counting them.
+    ///
+    /// For example, with two levels, publications to `devices/a/temperature` and `devices/a/humidity` are both counted under `devices/a`,
+    /// which identifies noisy devices. At most `max_prefixes` prefixes are counted in each sample, so that clients that publish to
+    /// many distinct topics cannot make the server use unbounded memory. See [`Config::topic_metrics`].
+    #[must_use]
+    pub fn with_topic_metrics(mut self, prefix_levels: usize, max_prefixes: usize) -> Self {
+        self.topic_metrics_budget = Some((prefix_levels, max_prefixes));
+        self
+    }
+
+    /// Returns the counters of the server that runs with this configuration.
+    pub fn counters(&self) -> super::Counters {
+        self.counters.clone()
+    }
+
+    /// Returns the retained messages of the server that runs with this configuration.
+    pub fn retained_messages(&self) -> super::RetainedMessages {
+        self.retained_messages.clone()
+    }
+
+    /// Returns the topic metrics of the server that runs with this configuration.
+    pub fn topic_metrics(&self) -> super::TopicMetrics {
+        self.topic_metrics.clone()
+    }
+
+    /// Returns a handle that can be used to shut down the server that runs with this configuration.
+    pub fn shutdown_handle(&self) -> super::ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            maximum_qos: crate::proto::QoS::ExactlyOnce,
+            retain_available: true,
+            max_retained_payload_size: None,
+            max_retained_messages: None,
+            max_inflight_messages: None,
+            max_queued_messages: None,
+            max_queued_message_age: None,
+            queue_spillover: None,
+            delayed_publish: false,
+            topic_metrics_budget: None,
+            counters: Default::default(),
+            retained_messages: Default::default(),
+            topic_metrics: Default::default(),
+            shutdown_handle: Default::default(),
+        }
+    }
+}
+
+/// Limits on the publications that the server keeps for the clients of one listener, set with
+/// [`Listeners::with_listener_limits`](crate::io::Listeners::with_listener_limits)
+///
+/// Limits that are not set are the ones of the server's [`Config`]. A client that is disconnected from its persistent session keeps
+/// the limits of the listener that it last connected through.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ListenerLimits {
+    max_inflight_messages: Option<usize>,
+    max_queued_messages: Option<usize>,
+}
+
+impl ListenerLimits {
+    /// Sets the number of QoS 1 and 2 publications that the server sends to a client of this listener without waiting for the client to ack them.
+    /// See [`Config::with_max_inflight_messages`].
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_max_inflight_messages(mut self, max_inflight_messages: usize) -> Self {
+        self.max_inflight_messages = Some(max_inflight_messages);
+        self
+    }
+
+    /// Sets the number of QoS 1 and 2 publications that the server queues for a client of this listener. See [`Config::with_max_queued_messages`].
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_max_queued_messages(mut self, max_queued_messages: usize) -> Self {
+        self.max_queued_messages = Some(max_queued_messages);
+        self
+    }
+
+    /// Fills in the limits that are not set with the ones of the given configuration
+    pub(super) fn or_config(self, config: &Config) -> Self {
+        ListenerLimits {
+            max_inflight_messages: self.max_inflight_messages.or(config.max_inflight_messages),
+            max_queued_messages: self.max_queued_messages.or(config.max_queued_messages),
+        }
+    }
+
+    pub(super) fn max_inflight_messages(&self) -> usize {
+        self.max_inflight_messages.unwrap_or(usize::MAX).clamp(1, usize::from(u16::MAX))
+    }
+
+    pub(super) fn max_queued_messages(&self) -> Option<usize> {
+        self.max_queued_messages
+    }
+}
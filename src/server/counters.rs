@@ -0,0 +1,38 @@
+/// Counters of the messages that the server dropped, shared with the running server. Returned by [`Config::counters`](super::Config::counters).
+#[derive(Clone, Debug, Default)]
+pub struct Counters(std::sync::Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    queued_messages_dropped: std::sync::atomic::AtomicU64,
+    expired_messages_dropped: std::sync::atomic::AtomicU64,
+}
+
+impl Counters {
+    /// The number of QoS 1 and 2 publications that were not delivered to a client because its queue was full,
+    /// or because they could not be spilled to disk.
+    ///
+    /// See [`Config::with_max_queued_messages`](super::Config::with_max_queued_messages) and
+    /// [`Config::with_queue_spillover`](super::Config::with_queue_spillover).
+    #[allow(clippy::doc_markdown)]
+    pub fn queued_messages_dropped(&self) -> u64 {
+        self.0.queued_messages_dropped.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// The number of QoS 1 and 2 publications that were not delivered to a client because they were queued for too long,
+    /// or because their message expiry passed before they could be delivered.
+    ///
+    /// See [`Config::with_max_queued_message_age`](super::Config::with_max_queued_message_age).
+    #[allow(clippy::doc_markdown)]
+    pub fn expired_messages_dropped(&self) -> u64 {
+        self.0.expired_messages_dropped.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub(super) fn queued_message_dropped(&self) {
+        self.0.queued_messages_dropped.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub(super) fn expired_message_dropped(&self) {
+        self.0.expired_messages_dropped.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
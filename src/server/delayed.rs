@@ -0,0 +1,207 @@
+use std::future::Future;
+
+/// The prefix of the topics of delayed publications, `$delayed/{delay in seconds}/{topic}`
+const DELAYED_TOPIC_PREFIX: &str = "$delayed/";
+
+/// The number of slots of the timer wheel, each one second long. Publications delayed by longer than this go around the wheel
+/// more than once.
+const NUM_SLOTS: usize = 512;
+
+/// Publications to `$delayed/{delay in seconds}/{topic}` that are held by the server until they are due.
+/// See [`Config::with_delayed_publish`](super::Config::with_delayed_publish).
+pub(super) struct DelayedPublications {
+    /// The publications, and the client IDs of the clients that published them
+    wheel: TimerWheel<(crate::proto::ByteStr, crate::proto::Publication)>,
+    start: tokio::time::Instant,
+
+    /// Fires at the next tick of the wheel while it holds any publications
+    timer: Option<std::pin::Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl DelayedPublications {
+    /// Holds the given publication of the given client for the given delay
+    pub(super) fn insert(&mut self, delay: std::time::Duration, publisher_id: crate::proto::ByteStr, publication: crate::proto::Publication) {
+        self.wheel.insert(due_tick(self.start.elapsed(), delay), (publisher_id, publication));
+    }
+
+    /// Returns the publications that are due, and the client IDs of the clients that published them,
+    /// and arranges for the task to be woken up at the next tick of the wheel if it holds any more
+    pub(super) fn poll_due(&mut self, cx: &mut std::task::Context<'_>) -> Vec<(crate::proto::ByteStr, crate::proto::Publication)> {
+        let mut due = vec![];
+
+        loop {
+            due.extend(self.wheel.advance(self.start.elapsed().as_secs()));
+
+            if self.wheel.is_empty() {
+                self.timer = None;
+                return due;
+            }
+
+            let next_tick = self.start + std::time::Duration::from_secs(self.wheel.current_tick + 1);
+            let timer = self.timer.get_or_insert_with(|| Box::pin(tokio::time::sleep_until(next_tick)));
+            if timer.deadline() != next_tick {
+                timer.as_mut().reset(next_tick);
+            }
+
+            if timer.as_mut().poll(cx).is_pending() {
+                return due;
+            }
+        }
+    }
+}
+
+impl Default for DelayedPublications {
+    fn default() -> Self {
+        DelayedPublications {
+            wheel: Default::default(),
+            start: tokio::time::Instant::now(),
+            timer: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for DelayedPublications {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DelayedPublications")
+            .field("len", &self.wheel.len)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Splits the topic of a delayed publication into its delay and the topic to publish to once the delay has passed,
+/// or returns `None` if the topic is not that of a delayed publication.
+///
+/// Returns `Some(Err(()))` if the topic starts with `$delayed/` but is not of the form `$delayed/{delay in seconds}/{topic}`.
+pub(super) fn split_delayed_topic(topic_name: &str) -> Option<Result<(std::time::Duration, &str), ()>> {
+    let topic_name = topic_name.strip_prefix(DELAYED_TOPIC_PREFIX)?;
+    Some(match topic_name.split_once('/') {
+        Some((delay, topic_name)) if !topic_name.is_empty() => match delay.parse::<u32>() {
+            Ok(delay) => Ok((std::time::Duration::from_secs(delay.into()), topic_name)),
+            Err(_) => Err(()),
+        },
+        _ => Err(()),
+    })
+}
+
+/// Returns the first tick at or after the time that a publication received at `elapsed` is due, so that it is never published early.
+/// Ticks are whole seconds since the wheel started.
+fn due_tick(elapsed: std::time::Duration, delay: std::time::Duration) -> u64 {
+    let due = elapsed + delay;
+    due.as_secs() + u64::from(due.subsec_nanos() > 0)
+}
+
+/// A hashed timer wheel with one slot per tick. Each item is kept in the slot of the tick it is due at, modulo the number of slots.
+#[derive(Debug)]
+struct TimerWheel<T> {
+    slots: Vec<Vec<(u64, T)>>,
+
+    /// The last tick whose items have been returned
+    current_tick: u64,
+
+    len: usize,
+}
+
+impl<T> TimerWheel<T> {
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds an item that is due at the given tick. Items that are already due are returned by the next call to [`TimerWheel::advance`].
+    fn insert(&mut self, due_tick: u64, item: T) {
+        let due_tick = due_tick.max(self.current_tick + 1);
+        self.slots[slot(due_tick)].push((due_tick, item));
+        self.len += 1;
+    }
+
+    /// Moves the wheel forward to the given tick, and returns the items that are due by then, in the order of the ticks they were due at
+    fn advance(&mut self, now_tick: u64) -> Vec<T> {
+        if self.len == 0 {
+            self.current_tick = self.current_tick.max(now_tick);
+            return vec![];
+        }
+
+        let mut due = vec![];
+
+        // Every slot only needs to be visited once, however far the wheel moves
+        let ticks = now_tick.saturating_sub(self.current_tick).min(NUM_SLOTS as u64);
+        for tick in self.current_tick + 1..=self.current_tick + ticks {
+            let slot = &mut self.slots[slot(tick)];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].0 <= now_tick {
+                    due.push(slot.remove(i));
+                }
+                else {
+                    i += 1;
+                }
+            }
+        }
+
+        self.current_tick = self.current_tick.max(now_tick);
+        self.len -= due.len();
+
+        due.sort_by_key(|(due_tick, _)| *due_tick);
+        due.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+impl<T> Default for TimerWheel<T> {
+    fn default() -> Self {
+        TimerWheel {
+            slots: std::iter::repeat_with(Vec::new).take(NUM_SLOTS).collect(),
+            current_tick: 0,
+            len: 0,
+        }
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn slot(tick: u64) -> usize {
+    (tick % NUM_SLOTS as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn split_delayed_topic() {
+        assert_eq!(super::split_delayed_topic("devices/a"), None);
+        assert_eq!(super::split_delayed_topic("$delayed/10/devices/a"), Some(Ok((std::time::Duration::from_secs(10), "devices/a"))));
+        assert_eq!(super::split_delayed_topic("$delayed/10/"), Some(Err(())));
+        assert_eq!(super::split_delayed_topic("$delayed/ten/devices/a"), Some(Err(())));
+        assert_eq!(super::split_delayed_topic("$delayed/10"), Some(Err(())));
+    }
+
+    #[test]
+    fn due_tick() {
+        // A publication received just before a tick is not published at that tick, which would be almost a second early
+        assert_eq!(super::due_tick(std::time::Duration::from_millis(950), std::time::Duration::from_secs(1)), 2);
+        assert_eq!(super::due_tick(std::time::Duration::from_millis(1950), std::time::Duration::from_secs(3)), 5);
+
+        // A publication received right at a tick is due exactly its delay later
+        assert_eq!(super::due_tick(std::time::Duration::from_secs(2), std::time::Duration::from_secs(3)), 5);
+        assert_eq!(super::due_tick(std::time::Duration::from_millis(950), std::time::Duration::ZERO), 1);
+    }
+
+    #[test]
+    fn timer_wheel() {
+        let mut wheel = super::TimerWheel::default();
+        wheel.insert(3, "c");
+        wheel.insert(1, "a");
+        wheel.insert(2, "b");
+        wheel.insert(3 + super::NUM_SLOTS as u64, "d");
+        wheel.insert(10 * super::NUM_SLOTS as u64, "e");
+
+        assert!(wheel.advance(0).is_empty());
+        assert_eq!(wheel.advance(1), ["a"]);
+        assert_eq!(wheel.advance(5), ["b", "c"]);
+
+        // "d" shares a slot with "c" but is due one turn of the wheel later
+        assert!(wheel.advance(super::NUM_SLOTS as u64).is_empty());
+        assert_eq!(wheel.advance(3 + super::NUM_SLOTS as u64), ["d"]);
+
+        // Items that are already due are returned by the next advance
+        wheel.insert(0, "f");
+        assert_eq!(wheel.advance(100 * super::NUM_SLOTS as u64), ["f", "e"]);
+        assert!(wheel.is_empty());
+    }
+}
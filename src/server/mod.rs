@@ -3,12 +3,50 @@ use std::convert::TryInto;
 use futures_sink::Sink;
 use futures_util::{FutureExt, SinkExt, StreamExt, TryStreamExt};
 
+#[cfg(feature = "transport-tokio")]
+mod builder;
+#[cfg(feature = "transport-tokio")]
+pub use builder::{Broker, BrokerBuilder};
+
+mod config;
+pub use config::{Config, ListenerLimits};
+
+mod counters;
+pub use counters::Counters;
+
+mod delayed;
+
+mod retained;
+pub use retained::RetainedMessages;
+
+mod shutdown;
+pub use shutdown::ShutdownHandle;
+
+mod spill;
+
+mod topic_metrics;
+pub use topic_metrics::{TopicMetrics, TopicPrefixMetrics};
+
+type CloseClientFuture = std::pin::Pin<Box<dyn std::future::Future<Output = ()>>>;
+
 type AuthAcceptedClientFuture<L> = std::pin::Pin<Box<dyn std::future::Future<Output = Result<
-    (crate::proto::ClientId, <L as crate::io::Listener>::PacketStream, <L as crate::io::Listener>::PacketSink),
+    (crate::proto::Connect, <L as crate::io::Listener>::PacketStream, <L as crate::io::Listener>::PacketSink, ListenerLimits),
     ServerError,
 >>>>;
 
+/// Runs a server that accepts clients from the given listener. Use [`crate::io::Listeners`] to accept clients on several addresses,
+/// or over several transports, or [`BrokerBuilder`] to bind the listeners of several addresses and run the server with them.
 pub fn run<L>(listener: L) -> impl std::future::Future<Output = std::io::Result<()>>
+where
+    L: crate::io::Listener + Unpin,
+    <L as crate::io::Listener>::PacketStream: Unpin + 'static,
+    <L as crate::io::Listener>::PacketSink: Unpin + 'static,
+{
+    run_with_config(listener, Default::default())
+}
+
+/// Like [`run`], but with the given configuration instead of the default one
+pub fn run_with_config<L>(listener: L, config: Config) -> impl std::future::Future<Output = std::io::Result<()>>
 where
     L: crate::io::Listener + Unpin,
     <L as crate::io::Listener>::PacketStream: Unpin + 'static,
@@ -19,6 +57,8 @@ where
         events_accept: futures_util::stream::FuturesUnordered<RouterFutureAccept<L>>,
         events_recv: futures_util::stream::FuturesUnordered<RouterFutureRecv<L>>,
         events_send: futures_util::stream::FuturesUnordered<RouterFutureSend<L>>,
+        events_close: futures_util::stream::FuturesUnordered<CloseClientFuture>,
+        shutting_down: bool,
     }
 
     impl<L> std::future::Future for Run<L>
@@ -35,6 +75,35 @@ where
             loop {
                 let mut all_pending = true;
 
+                if !this.shutting_down && this.server_state.config.shutdown_handle.poll_requested(cx) {
+                    log::info!("Shutting down server...");
+                    this.shutting_down = true;
+
+                    // Stop accepting new clients and reading from connected ones
+                    this.events_accept = Default::default();
+                    this.events_recv = Default::default();
+
+                    // Close the connections of clients that aren't being written to right now.
+                    // The others are closed when their writes complete.
+                    for client in this.server_state.clients.values_mut() {
+                        if let Some((client_sink, mut pending_packets)) = client.client_sink_and_pending_packets.take() {
+                            pending_packets.extend(client.pending_packets.drain(..));
+                            pending_packets.extend(client.shutting_down_packet());
+                            this.events_close.push(close_client::<L>(client.client_id.clone(), client_sink, pending_packets));
+                        }
+                    }
+                }
+
+                while let std::task::Poll::Ready(Some(())) = this.events_close.poll_next_unpin(cx) {
+                    all_pending = false;
+                }
+
+                if this.shutting_down && this.events_send.is_empty() && this.events_close.is_empty() {
+                    this.server_state.sync_spills();
+                    log::info!("Server shut down");
+                    return std::task::Poll::Ready(Ok(()));
+                }
+
                 // Write as much as possible, then read once, then accept once.
 
                 while let std::task::Poll::Ready(Some(RouterEventSend { client_id, result })) = this.events_send.poll_next_unpin(cx) {
@@ -44,7 +113,12 @@ where
                     match result {
                         Ok((client_sink, mut pending_packets)) =>
                             if let Some(client) = this.server_state.get_client_mut(&client_id) {
-                                if client.pending_packets.is_empty() {
+                                if this.shutting_down {
+                                    pending_packets.extend(client.pending_packets.drain(..));
+                                    pending_packets.extend(client.shutting_down_packet());
+                                    this.events_close.push(close_client::<L>(client_id, client_sink, pending_packets));
+                                }
+                                else if client.pending_packets.is_empty() {
                                     client.client_sink_and_pending_packets = Some((client_sink, pending_packets))
                                 }
                                 else {
@@ -60,13 +134,24 @@ where
                     }
                 }
 
+                if !this.shutting_down {
+                    let due_publications = this.server_state.delayed.poll_due(cx);
+                    if !due_publications.is_empty() {
+                        all_pending = false;
+
+                        for (publisher_id, publication) in due_publications {
+                            this.server_state.route(&mut this.events_send, &publisher_id, &publication);
+                        }
+                    }
+                }
+
                 if let std::task::Poll::Ready(Some(item)) = this.events_accept.poll_next_unpin(cx) {
                     log::trace!("RouterEventAccept");
                     all_pending = false;
 
                     match item {
-                        RouterEventAccept::AcceptedClient(listener, Ok((new_client_stream, new_client_sink))) => {
-                            this.events_accept.push(auth_accepted_client(new_client_stream, new_client_sink));
+                        RouterEventAccept::AcceptedClient(listener, Ok((new_client_stream, new_client_sink, limits))) => {
+                            this.events_accept.push(auth_accepted_client(new_client_stream, new_client_sink, limits));
                             this.events_accept.push(RouterFutureAccept::Accepting { listener: Some(listener) });
                         },
 
@@ -75,8 +160,8 @@ where
                             this.events_accept.push(RouterFutureAccept::Accepting { listener: Some(listener) });
                         },
 
-                        RouterEventAccept::ClientReady(Ok((new_client_id, new_client_stream, new_client_sink))) => {
-                            let client_id = this.server_state.add_client(new_client_id, new_client_sink);
+                        RouterEventAccept::ClientReady(Ok((connect, new_client_stream, new_client_sink, limits))) => {
+                            let client_id = this.server_state.add_client(&mut this.events_send, connect, new_client_sink, limits);
 
                             this.events_recv.push(RouterFutureRecv(Some((client_id.clone(), new_client_stream))));
                         },
@@ -93,38 +178,126 @@ where
                     match result {
                         Ok((client_stream, packet)) => {
                             #[allow(clippy::mutable_key_type)]
-                            let mut response_packets: std::collections::BTreeMap<crate::proto::ByteStr, Vec<crate::proto::Packet>> = Default::default();
+                            let mut response_publications: std::collections::BTreeMap<crate::proto::ByteStr, Vec<crate::proto::Publication>> = Default::default();
 
                             if let Some(client) = this.server_state.get_client_mut(&client_id) {
                                 match packet {
                                     crate::proto::Packet::PingReq(crate::proto::PingReq) =>
                                         client.write(&mut this.events_send, crate::proto::Packet::PingResp(crate::proto::PingResp)),
 
+                                    // A QoS 2 publication was forwarded when it was first received, so a retransmission of it
+                                    // before the client releases its packet identifier is only acked again
+                                    crate::proto::Packet::Publish(crate::proto::Publish {
+                                        packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, _dup),
+                                        topic_name,
+                                        ..
+                                    }) if client.received_exactly_once.contains(&packet_identifier) => {
+                                        log::debug!(
+                                            "not forwarding publication to {} from client {} again, since it did not release packet identifier {} yet",
+                                            crate::proto::EscapedStr(topic_name.as_ref()), client_id, packet_identifier,
+                                        );
+                                        client.write(&mut this.events_send, crate::proto::Packet::PubRec(crate::proto::PubRec {
+                                            packet_identifier,
+                                        }));
+                                    },
+
                                     crate::proto::Packet::Publish(crate::proto::Publish {
                                         packet_identifier_dup_qos,
-                                        retain: _,
+                                        retain,
                                         topic_name,
                                         payload,
+                                        user_properties,
+                                        subscription_identifiers: _,
+                                        response_topic,
+                                        correlation_data,
+                                        message_expiry,
+                                        content_type,
                                     }) => {
-                                        match packet_identifier_dup_qos {
-                                            crate::proto::PacketIdentifierDupQoS::AtMostOnce => (),
+                                        let qos = match packet_identifier_dup_qos {
+                                            crate::proto::PacketIdentifierDupQoS::AtMostOnce => crate::proto::QoS::AtMostOnce,
                                             crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _dup) => {
                                                 client.write(&mut this.events_send, crate::proto::Packet::PubAck(crate::proto::PubAck {
                                                     packet_identifier,
                                                 }));
+                                                crate::proto::QoS::AtLeastOnce
                                             },
-                                            crate::proto::PacketIdentifierDupQoS::ExactlyOnce(_packet_identifier, _dup) => (),
-                                        }
-
-                                        if let Some(client_ids) = this.server_state.get_subscribers(&topic_name) {
-                                            for client_id in client_ids {
-                                                response_packets.entry(client_id.clone()).or_default().push(crate::proto::Packet::Publish(crate::proto::Publish {
-                                                    packet_identifier_dup_qos,
-                                                    retain: false,
-                                                    topic_name: topic_name.clone(),
-                                                    payload: payload.clone(),
+                                            crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, _dup) => {
+                                                client.received_exactly_once.insert(packet_identifier);
+                                                client.write(&mut this.events_send, crate::proto::Packet::PubRec(crate::proto::PubRec {
+                                                    packet_identifier,
                                                 }));
+                                                crate::proto::QoS::ExactlyOnce
+                                            },
+                                        };
+
+                                        let qos =
+                                            if qos > this.server_state.config.maximum_qos {
+                                                log::debug!(
+                                                    "forwarding publication to {} from client {} with {:?} instead of {:?}, the maximum QoS",
+                                                    topic_name, client_id, this.server_state.config.maximum_qos, qos,
+                                                );
+                                                this.server_state.config.maximum_qos
                                             }
+                                            else {
+                                                qos
+                                            };
+
+                                        if let Some((prefix_levels, max_prefixes)) = this.server_state.config.topic_metrics_budget {
+                                            this.server_state.config.topic_metrics.record(topic_name.as_ref(), payload.len(), prefix_levels, max_prefixes);
+                                        }
+
+                                        let delayed_topic = if this.server_state.config.delayed_publish {
+                                            delayed::split_delayed_topic(topic_name.as_ref())
+                                        }
+                                        else {
+                                            None
+                                        };
+
+                                        match delayed_topic {
+                                            // The message expiry of a delayed publication counts down while it waits
+                                            Some(Ok((delay, _))) if matches!(message_expiry, Some(message_expiry) if message_expiry <= delay) => {
+                                                log::info!("dropping delayed publication to {} because it expires before it is due", crate::proto::EscapedStr(topic_name.as_ref()));
+                                                this.server_state.config.counters.expired_message_dropped();
+                                            },
+
+                                            Some(Ok((delay, delayed_topic_name))) =>
+                                                this.server_state.delayed.insert(delay, client_id.clone(), crate::proto::Publication {
+                                                    topic_name: delayed_topic_name.parse().expect("part of a topic name is a valid topic name"),
+                                                    qos,
+                                                    retain,
+                                                    payload,
+                                                    user_properties,
+                                                    response_topic,
+                                                    correlation_data,
+                                                    message_expiry: message_expiry.and_then(|message_expiry| message_expiry.checked_sub(delay)),
+                                                    content_type,
+                                                }),
+
+                                            Some(Err(())) => log::info!("dropping delayed publication to malformed topic {}", crate::proto::EscapedStr(topic_name.as_ref())),
+
+                                            None => {
+                                                if retain {
+                                                    this.server_state.retain(&topic_name, &payload);
+                                                }
+
+                                                for (subscriber_id, subscription) in this.server_state.get_subscribers(&topic_name) {
+                                                    if subscription.no_local && subscriber_id == client_id {
+                                                        continue;
+                                                    }
+
+                                                    response_publications.entry(subscriber_id).or_default().push(crate::proto::Publication {
+                                                        topic_name: topic_name.clone(),
+                                                        qos: std::cmp::min(qos, subscription.qos),
+                                                        retain: retain && subscription.retain_as_published,
+                                                        payload: payload.clone(),
+                                                        user_properties: user_properties.clone(),
+                                                        response_topic: response_topic.clone(),
+                                                        correlation_data: correlation_data.clone(),
+                                                        message_expiry,
+                                                        content_type: content_type.clone(),
+                                                    });
+                                                }
+                                            },
                                         }
                                     },
 
@@ -136,16 +309,99 @@ where
                                             packet_identifier,
                                             qos: vec![],
                                         };
-                                        for crate::proto::SubscribeTo { topic_filter, qos } in subscribe_to {
-                                            let qos = match qos {
-                                                crate::proto::QoS::AtMostOnce | crate::proto::QoS::AtLeastOnce => qos,
-                                                crate::proto::QoS::ExactlyOnce => crate::proto::QoS::AtLeastOnce,
+                                        let mut retained_publications = vec![];
+                                        for crate::proto::SubscribeTo {
+                                            topic_filter,
+                                            qos,
+                                            user_properties: _,
+                                            subscription_identifier: _,
+                                            no_local,
+                                            retain_as_published,
+                                            retain_handling,
+                                        } in subscribe_to {
+                                            let shared = crate::proto::split_shared_subscription(topic_filter.as_ref());
+                                            if let Some((share_name, shared_topic_filter)) = shared {
+                                                if share_name.is_empty() || share_name.contains(&['+', '#'][..]) || shared_topic_filter.is_empty() {
+                                                    log::info!("refusing malformed shared subscription {} of client {}", crate::proto::EscapedStr(topic_filter.as_ref()), client_id);
+                                                    sub_ack.qos.push(crate::proto::SubAckQos::Failure);
+                                                    continue;
+                                                }
+
+                                                // Ref: 3.8.3.1 Subscription Options
+                                                if no_local {
+                                                    log::info!("refusing shared subscription {} of client {} with No Local", crate::proto::EscapedStr(topic_filter.as_ref()), client_id);
+                                                    sub_ack.qos.push(crate::proto::SubAckQos::Failure);
+                                                    continue;
+                                                }
+                                            }
+
+                                            let qos = std::cmp::min(qos, this.server_state.config.maximum_qos);
+                                            let existed = this.server_state.subscribe(client_id.clone(), topic_filter.clone(), Subscription { qos, no_local, retain_as_published });
+
+                                            // Retained messages are not sent to shared subscriptions, since they would go to every member of the group
+                                            let send_retained = shared.is_none() && match retain_handling {
+                                                crate::proto::RetainHandling::SendOnSubscribe => true,
+                                                crate::proto::RetainHandling::SendOnNewSubscribe => !existed,
+                                                crate::proto::RetainHandling::DoNotSend => false,
                                             };
-                                            this.server_state.subscribe(client_id.clone(), topic_filter.clone());
+                                            let retained = if send_retained { this.server_state.get_retained(&topic_filter) } else { vec![] };
+                                            for (topic_name, payload) in retained {
+                                                // Retained messages are sent with QoS 0 since the server does not track packet identifiers
+                                                // of the publications it sends.
+                                                retained_publications.push(crate::proto::Packet::Publish(crate::proto::Publish {
+                                                    packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+                                                    retain: true,
+                                                    topic_name,
+                                                    payload,
+                                                    user_properties: vec![],
+                                                    subscription_identifiers: vec![],
+                                                    response_topic: None,
+                                                    correlation_data: None,
+                                                    message_expiry: None,
+                                                    content_type: None,
+                                                }));
+                                            }
                                             sub_ack.qos.push(crate::proto::SubAckQos::Success(qos));
                                         }
                                         let client = this.server_state.get_client_mut(&client_id).expect("got this client successfully just before this");
                                         client.write(&mut this.events_send, crate::proto::Packet::SubAck(sub_ack));
+                                        for packet in retained_publications {
+                                            client.write(&mut this.events_send, packet);
+                                        }
+                                    },
+
+                                    crate::proto::Packet::Unsubscribe(crate::proto::Unsubscribe {
+                                        packet_identifier,
+                                        unsubscribe_from,
+                                    }) => {
+                                        let results = unsubscribe_from.iter()
+                                            .map(|topic_filter|
+                                                if this.server_state.unsubscribe(&client_id, topic_filter) {
+                                                    crate::proto::UnsubAckResult::Success
+                                                }
+                                                else {
+                                                    crate::proto::UnsubAckResult::NoSubscriptionExisted
+                                                })
+                                            .collect();
+                                        let client = this.server_state.get_client_mut(&client_id).expect("got this client successfully just before this");
+                                        client.write(&mut this.events_send, crate::proto::Packet::UnsubAck(crate::proto::UnsubAck {
+                                            packet_identifier,
+                                            results,
+                                        }));
+                                    },
+
+                                    crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier }) |
+                                    crate::proto::Packet::PubComp(crate::proto::PubComp { packet_identifier }) =>
+                                        this.server_state.acked(&mut this.events_send, &client_id, packet_identifier),
+
+                                    crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier }) =>
+                                        client.released(&mut this.events_send, packet_identifier),
+
+                                    crate::proto::Packet::PubRel(crate::proto::PubRel { packet_identifier }) => {
+                                        client.received_exactly_once.remove(&packet_identifier);
+                                        client.write(&mut this.events_send, crate::proto::Packet::PubComp(crate::proto::PubComp {
+                                            packet_identifier,
+                                        }));
                                     },
 
                                     _ => (),
@@ -154,11 +410,9 @@ where
 
                             this.events_recv.push(RouterFutureRecv(Some((client_id, client_stream))));
 
-                            for (client_id, packets) in response_packets {
-                                if let Some(client) = this.server_state.get_client_mut(&client_id) {
-                                    for packet in packets {
-                                        client.write(&mut this.events_send, packet);
-                                    }
+                            for (client_id, publications) in response_publications {
+                                for publication in publications {
+                                    this.server_state.publish(&mut this.events_send, &client_id, publication);
                                 }
                             }
                         },
@@ -180,13 +434,39 @@ where
     log::info!("Starting server...");
 
     Run {
-        server_state: Default::default(),
+        server_state: ServerState::new(config),
         events_accept: std::iter::once(RouterFutureAccept::Accepting { listener: Some(listener) }).collect(),
         events_recv: Default::default(),
         events_send: Default::default(),
+        events_close: Default::default(),
+        shutting_down: false,
     }
 }
 
+/// Sends the given packets to a client and then closes its connection
+fn close_client<L>(
+    client_id: crate::proto::ByteStr,
+    mut client_sink: <L as crate::io::Listener>::PacketSink,
+    pending_packets: std::collections::VecDeque<crate::proto::Packet>,
+) -> CloseClientFuture
+where
+    L: crate::io::Listener,
+    <L as crate::io::Listener>::PacketSink: Unpin + 'static,
+{
+    Box::pin(async move {
+        for packet in pending_packets {
+            if let Err(err) = client_sink.feed(packet).await {
+                log::info!("could not write to client {} while shutting down: {}", client_id, err);
+                return;
+            }
+        }
+
+        if let Err(err) = client_sink.close().await {
+            log::info!("could not close connection of client {} while shutting down: {}", client_id, err);
+        }
+    })
+}
+
 #[allow(clippy::unnecessary_wraps)]
 fn auth(connect: &crate::proto::Connect) -> Result<(), crate::proto::ConnectionRefusedReason> {
     log::info!("authorizing {:?}:{:?}:{:?}", connect.username, connect.password, connect.client_id);
@@ -194,41 +474,121 @@ fn auth(connect: &crate::proto::Connect) -> Result<(), crate::proto::ConnectionR
 }
 
 struct ServerState<L> where L: crate::io::Listener {
+    config: Config,
+
     next_server_generated_session_id: u64,
 
     #[allow(clippy::mutable_key_type)]
     clients: std::collections::BTreeMap<crate::proto::ByteStr, ClientState<L>>,
 
+    /// The topic filters that each client subscribed to, and the subscriptions that the server made for each of them
     #[allow(clippy::mutable_key_type)]
-    subscriptions_by_client_id: std::collections::BTreeMap<crate::proto::ByteStr, std::collections::BTreeSet<crate::proto::ByteStr>>,
+    subscriptions_by_client_id: std::collections::BTreeMap<crate::proto::ByteStr, std::collections::BTreeMap<crate::proto::ByteStr, Subscription>>,
 
     #[allow(clippy::mutable_key_type)]
     subscriptions_by_topic: std::collections::BTreeMap<crate::proto::ByteStr, std::collections::BTreeSet<crate::proto::ByteStr>>,
+
+    /// The shared subscriptions `$share/{share name}/{topic filter}` of each topic filter that they share, and the turn of each of them,
+    /// which picks the member of the group that the next publication is sent to
+    #[allow(clippy::mutable_key_type)]
+    shared_subscriptions: std::collections::BTreeMap<String, std::collections::BTreeMap<crate::proto::ByteStr, usize>>,
+
+    retained: RetainedMessages,
+
+    delayed: delayed::DelayedPublications,
 }
 
 impl<L> ServerState<L> where L: crate::io::Listener {
+    fn new(config: Config) -> Self {
+        ServerState {
+            retained: config.retained_messages(),
+            config,
+            next_server_generated_session_id: Default::default(),
+            clients: Default::default(),
+            subscriptions_by_client_id: Default::default(),
+            subscriptions_by_topic: Default::default(),
+            shared_subscriptions: Default::default(),
+            delayed: Default::default(),
+        }
+    }
+
+    /// Adds a newly connected client, resuming its session if it has one and asked for it, and sends it a CONNACK.
     fn add_client(
         &mut self,
-        client_id: crate::proto::ClientId,
+        events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>,
+        connect: crate::proto::Connect,
         client_sink: <L as crate::io::Listener>::PacketSink,
+        limits: ListenerLimits,
     ) -> crate::proto::ByteStr {
-        let client_id = match client_id {
+        let crate::proto::Connect { client_id, protocol_level, session_expiry, .. } = connect;
+
+        let (client_id, assigned_client_id, clean_start) = match client_id {
             crate::proto::ClientId::ServerGenerated => {
-                let client_id =
+                let client_id: crate::proto::ByteStr =
                     format!("server-generated-{}", self.next_server_generated_session_id)
                     .try_into().expect("this string will not be long enough to exceed u16::max_value() bytes");
                 self.next_server_generated_session_id += 1;
-                client_id
+                (client_id.clone(), Some(client_id), true)
             },
-            crate::proto::ClientId::IdWithCleanSession(client_id) |
-            crate::proto::ClientId::IdWithExistingSession(client_id) => client_id,
+            crate::proto::ClientId::IdWithCleanSession(client_id) => (client_id, None, true),
+            crate::proto::ClientId::IdWithExistingSession(client_id) => (client_id, None, false),
         };
 
-        self.clients.insert(client_id.clone(), ClientState {
+        // An MQTT 5.0 client asks for a clean start and for its session to be kept after it disconnects separately,
+        // with the latter being a session expiry that is not zero
+        let clean_session =
+            if protocol_level == crate::PROTOCOL_LEVEL {
+                clean_start
+            }
+            else {
+                session_expiry == Some(std::time::Duration::ZERO)
+            };
+
+        if clean_start {
+            self.remove_session(&client_id);
+
+            if let Some((directory, _)) = &self.config.queue_spillover {
+                if let Err(err) = spill::remove(directory, client_id.as_ref()) {
+                    log::warn!("could not remove spilled publications of client {}: {}", client_id, err);
+                }
+            }
+        }
+
+        let session_present = self.clients.contains_key(&client_id);
+
+        let limits = limits.or_config(&self.config);
+
+        // Recover the publications spilled for the session before the server restarted
+        let spill = match &self.config.queue_spillover {
+            Some((directory, _)) if !clean_start && !session_present => match spill::Spill::open(directory, client_id.as_ref()) {
+                Ok(spill) => Some(spill),
+                Err(err) => {
+                    log::warn!("could not recover spilled publications of client {}: {}", client_id, err);
+                    None
+                },
+            },
+            _ => None,
+        };
+
+        let client = self.clients.entry(client_id.clone()).or_insert_with(|| ClientState {
             client_id: client_id.clone(),
+            protocol_level,
+            clean_session,
+            online: false,
             pending_packets: Default::default(),
-            client_sink_and_pending_packets: Some((client_sink, Default::default())),
+            client_sink_and_pending_packets: None,
+            next_packet_identifier: crate::proto::PacketIdentifier::new(1).expect("1 is a valid packet identifier"),
+            inflight: Default::default(),
+            received_exactly_once: Default::default(),
+            queued: Default::default(),
+            queued_payload_bytes: 0,
+            spill,
+            limits,
         });
+        client.protocol_level = protocol_level;
+        client.clean_session = clean_session;
+        client.limits = limits;
+        client.connected(events, &self.config, client_sink, session_present, assigned_client_id);
 
         client_id
     }
@@ -238,46 +598,536 @@ impl<L> ServerState<L> where L: crate::io::Listener {
     }
 
     fn drop_client(&mut self, client_id: &crate::proto::ByteStr) {
+        match self.clients.get_mut(client_id) {
+            // Keep the subscriptions and undelivered publications of a persistent session until the client reconnects
+            Some(client) if !client.clean_session => client.disconnected(),
+            _ => self.remove_session(client_id),
+        }
+    }
+
+    fn remove_session(&mut self, client_id: &crate::proto::ByteStr) {
         self.clients.remove(&client_id);
         if let Some(subscriptions) = self.subscriptions_by_client_id.remove(&client_id) {
-            for topic in subscriptions {
-                if let Some(client_ids) = self.subscriptions_by_topic.get_mut(&topic) {
-                    client_ids.remove(&client_id);
-                }
+            for topic_filter in subscriptions.into_keys() {
+                self.remove_subscriber(&topic_filter, client_id);
             }
         }
     }
 
-    fn subscribe(&mut self, client_id: crate::proto::ByteStr, topic_filter: crate::proto::ByteStr) {
-        self.subscriptions_by_client_id.entry(client_id.clone()).or_default().insert(topic_filter.clone());
+    /// Adds or replaces the given subscription of the given client, and returns whether the client already had a subscription to the topic filter
+    fn subscribe(&mut self, client_id: crate::proto::ByteStr, topic_filter: crate::proto::ByteStr, subscription: Subscription) -> bool {
+        if let Some((_, shared_topic_filter)) = crate::proto::split_shared_subscription(topic_filter.as_ref()) {
+            self.shared_subscriptions.entry(shared_topic_filter.to_owned()).or_default().entry(topic_filter.clone()).or_default();
+        }
+
+        let existed = self.subscriptions_by_client_id.entry(client_id.clone()).or_default().insert(topic_filter.clone(), subscription).is_some();
         self.subscriptions_by_topic.entry(topic_filter).or_default().insert(client_id);
+        existed
+    }
+
+    /// Removes the given subscription of the given client, and returns whether the client had it
+    fn unsubscribe(&mut self, client_id: &crate::proto::ByteStr, topic_filter: &crate::proto::ByteStr) -> bool {
+        let subscribed = self.subscriptions_by_client_id.get_mut(client_id).and_then(|subscriptions| subscriptions.remove(topic_filter)).is_some();
+        self.remove_subscriber(topic_filter, client_id);
+        subscribed
     }
 
-    fn get_subscribers(&self, topic_name: &crate::proto::ByteStr) -> Option<&std::collections::BTreeSet<crate::proto::ByteStr>> {
+    /// Removes the given client from the subscribers of the given topic filter, and forgets the topic filter once it has none
+    fn remove_subscriber(&mut self, topic_filter: &crate::proto::ByteStr, client_id: &crate::proto::ByteStr) {
+        if let Some(client_ids) = self.subscriptions_by_topic.get_mut(topic_filter) {
+            client_ids.remove(client_id);
+            if client_ids.is_empty() {
+                self.subscriptions_by_topic.remove(topic_filter);
+            }
+        }
+
+        if self.subscriptions_by_topic.contains_key(topic_filter) {
+            return;
+        }
+
+        if let Some((_, shared_topic_filter)) = crate::proto::split_shared_subscription(topic_filter.as_ref()) {
+            if let Some(shared_subscriptions) = self.shared_subscriptions.get_mut(shared_topic_filter) {
+                shared_subscriptions.remove(topic_filter);
+                if shared_subscriptions.is_empty() {
+                    self.shared_subscriptions.remove(shared_topic_filter);
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::doc_markdown)]
+    /// Returns the clients subscribed to the given topic, and the subscription of each of them.
+    /// A publication is forwarded to each of them with the lower of its own QoS and the granted QoS.
+    ///
+    /// Each shared subscription of the topic adds one member of its group, taking turns between the members that are online,
+    /// or between all of them if none are.
+    fn get_subscribers(&mut self, topic_name: &crate::proto::ByteStr) -> Vec<(crate::proto::ByteStr, Subscription)> {
+        let subscriptions_by_client_id = &self.subscriptions_by_client_id;
+        let subscriber = |client_id: &crate::proto::ByteStr, topic_filter: &crate::proto::ByteStr| {
+            let subscription = *subscriptions_by_client_id.get(client_id)?.get(topic_filter)?;
+            Some((client_id.clone(), subscription))
+        };
+
         // TODO: wildcards
-        self.subscriptions_by_topic.get(topic_name)
+        let mut subscribers: Vec<_> = self.subscriptions_by_topic.get(topic_name).into_iter().flatten()
+            .filter_map(|client_id| subscriber(client_id, topic_name))
+            .collect();
+
+        for (topic_filter, turn) in self.shared_subscriptions.get_mut(topic_name.as_ref()).into_iter().flatten() {
+            let members: Vec<_> = self.subscriptions_by_topic.get(topic_filter).into_iter().flatten().collect();
+            if members.is_empty() {
+                continue;
+            }
+
+            let clients = &self.clients;
+            let index = (0..members.len())
+                .map(|i| (*turn + i) % members.len())
+                .find(|&index| matches!(clients.get(members[index]), Some(client) if client.online))
+                .unwrap_or(*turn % members.len());
+            *turn = index + 1;
+
+            subscribers.extend(subscriber(members[index], topic_filter));
+        }
+
+        subscribers
     }
-}
 
-impl<L> Default for ServerState<L> where L: crate::io::Listener {
-    fn default() -> Self {
-        ServerState {
-            next_server_generated_session_id: Default::default(),
-            clients: Default::default(),
-            subscriptions_by_client_id: Default::default(),
-            subscriptions_by_topic: Default::default(),
+    fn publish(
+        &mut self,
+        events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>,
+        client_id: &crate::proto::ByteStr,
+        publication: crate::proto::Publication,
+    ) {
+        if let Some(client) = self.clients.get_mut(client_id) {
+            client.publish(events, &self.config, publication);
+        }
+    }
+
+    /// Retains the given publication of the given client if it asks to be, and publishes it to the clients subscribed to its topic
+    fn route(
+        &mut self,
+        events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>,
+        publisher_id: &crate::proto::ByteStr,
+        publication: &crate::proto::Publication,
+    ) {
+        if publication.retain {
+            self.retain(&publication.topic_name, &publication.payload);
+        }
+
+        for (client_id, subscription) in self.get_subscribers(&publication.topic_name) {
+            if subscription.no_local && client_id == *publisher_id {
+                continue;
+            }
+
+            self.publish(events, &client_id, crate::proto::Publication {
+                qos: std::cmp::min(publication.qos, subscription.qos),
+                retain: publication.retain && subscription.retain_as_published,
+                ..publication.clone()
+            });
+        }
+    }
+
+    fn acked(
+        &mut self,
+        events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>,
+        client_id: &crate::proto::ByteStr,
+        packet_identifier: crate::proto::PacketIdentifier,
+    ) {
+        if let Some(client) = self.clients.get_mut(client_id) {
+            if client.inflight.remove(&packet_identifier).is_some() {
+                client.send_queued(events, &self.config);
+            }
         }
     }
+
+    fn retain(&mut self, topic_name: &crate::proto::ByteStr, payload: &bytes::Bytes) {
+        if !self.config.retain_available {
+            log::debug!("not retaining publication to {} because retained messages are not available", crate::proto::EscapedStr(topic_name.as_ref()));
+            return;
+        }
+
+        let mut retained = self.retained.lock();
+
+        if payload.is_empty() {
+            retained.remove(topic_name);
+            return;
+        }
+
+        if let Some(max_retained_payload_size) = self.config.max_retained_payload_size {
+            if payload.len() > max_retained_payload_size {
+                log::info!(
+                    "not retaining publication to {} because its payload of {} bytes is larger than {} bytes",
+                    topic_name, payload.len(), max_retained_payload_size,
+                );
+                return;
+            }
+        }
+
+        if let Some(max_retained_messages) = self.config.max_retained_messages {
+            if !retained.contains_key(topic_name) && retained.len() >= max_retained_messages {
+                log::info!(
+                    "not retaining publication to {} because {} topics already have retained messages",
+                    topic_name, max_retained_messages,
+                );
+                return;
+            }
+        }
+
+        retained.insert(topic_name.clone(), payload.clone());
+    }
+
+    /// Returns the topic names and payloads of the retained messages that match the given topic filter
+    fn get_retained(&self, topic_filter: &crate::proto::ByteStr) -> Vec<(crate::proto::ByteStr, bytes::Bytes)> {
+        self.retained.retained(topic_filter.as_ref())
+    }
+
+    /// Writes the spilled publications of all clients through to the disk, so that they survive the server shutting down
+    fn sync_spills(&self) {
+        for client in self.clients.values() {
+            if let Some(spill) = &client.spill {
+                if let Err(err) = spill.sync() {
+                    log::warn!("could not sync spilled publications of client {}: {}", client.client_id, err);
+                }
+            }
+        }
+    }
+}
+
+/// A subscription of a client to a topic filter
+#[derive(Clone, Copy, Debug)]
+struct Subscription {
+    #[allow(clippy::doc_markdown)]
+    /// The QoS that the server granted
+    qos: crate::proto::QoS,
+
+    /// Whether the client is not sent its own publications
+    no_local: bool,
+
+    /// Whether publications are forwarded with the retain flag that they were published with, instead of without it
+    retain_as_published: bool,
 }
 
 struct ClientState<L> where L: crate::io::Listener {
     client_id: crate::proto::ByteStr,
+
+    /// The protocol level of the CONNECT of the client's current or last connection
+    protocol_level: u8,
+
+    /// Whether the session ends when the client disconnects
+    clean_session: bool,
+
+    online: bool,
     pending_packets: std::collections::VecDeque<crate::proto::Packet>,
     client_sink_and_pending_packets: Option<(<L as crate::io::Listener>::PacketSink, std::collections::VecDeque<crate::proto::Packet>)>,
+
+    next_packet_identifier: crate::proto::PacketIdentifier,
+
+    /// Publications sent to the client that it has not acked yet.
+    /// `None` if the client has sent PUBREC for the publication and the server has released it.
+    inflight: std::collections::BTreeMap<crate::proto::PacketIdentifier, Option<crate::proto::Publish>>,
+
+    #[allow(clippy::doc_markdown)]
+    /// Packet identifiers of QoS 2 publications from the client that were forwarded, and that the client has not released with PUBREL yet
+    received_exactly_once: std::collections::BTreeSet<crate::proto::PacketIdentifier>,
+
+    /// Publications waiting for room in `inflight`, or for the client to reconnect to its persistent session, and when they were queued
+    queued: std::collections::VecDeque<(std::time::Instant, crate::proto::Publication)>,
+
+    /// The total size of the payloads of the publications in `queued`
+    queued_payload_bytes: usize,
+
+    /// Publications queued after the ones in `queued` that were spilled to disk. See [`Config::with_queue_spillover`].
+    spill: Option<spill::Spill>,
+
+    /// The limits of the listener that the client last connected through
+    limits: ListenerLimits,
 }
 
 impl<L> ClientState<L> where L: crate::io::Listener {
+    fn connected(
+        &mut self,
+        events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>,
+        config: &Config,
+        client_sink: <L as crate::io::Listener>::PacketSink,
+        session_present: bool,
+        assigned_client_id: Option<crate::proto::ByteStr>,
+    ) {
+        self.online = true;
+        self.pending_packets.clear();
+        self.client_sink_and_pending_packets = Some((client_sink, Default::default()));
+
+        self.write(events, crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+            session_present,
+            return_code: crate::proto::ConnectReturnCode::Accepted,
+            authentication_data: None,
+            receive_maximum: None,
+            maximum_packet_size: None,
+            server_reference: None,
+            assigned_client_id,
+            server_keep_alive: None,
+            maximum_qos: match config.maximum_qos {
+                crate::proto::QoS::ExactlyOnce => None,
+                maximum_qos => Some(maximum_qos),
+            },
+            retain_available: if config.retain_available { None } else { Some(false) },
+            reason_string: None,
+            user_properties: vec![],
+        }));
+
+        // Resend the publications that the client did not ack before it disconnected
+        let inflight: Vec<_> = self.inflight.iter().map(|(&packet_identifier, packet)| match packet {
+            Some(packet) => crate::proto::Packet::Publish(crate::proto::Publish {
+                packet_identifier_dup_qos: match packet.packet_identifier_dup_qos {
+                    crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, _) =>
+                        crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, true),
+                    crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, _) =>
+                        crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, true),
+                    crate::proto::PacketIdentifierDupQoS::AtMostOnce => unreachable!("QoS 0 publications are never in flight"),
+                },
+                ..packet.clone()
+            }),
+            None => crate::proto::Packet::PubRel(crate::proto::PubRel { packet_identifier }),
+        }).collect();
+        for packet in inflight {
+            self.write(events, packet);
+        }
+
+        self.send_queued(events, config);
+    }
+
+    /// The packet that tells the client that the server is shutting down, sent after the packets that were waiting to be sent to it.
+    /// MQTT 3.1.1 has no such packet.
+    fn shutting_down_packet(&self) -> Option<crate::proto::Packet> {
+        (self.protocol_level != crate::PROTOCOL_LEVEL).then(|| crate::proto::Packet::Disconnect(crate::proto::Disconnect {
+            reason: crate::proto::DisconnectReason::ServerShuttingDown,
+            server_reference: None,
+        }))
+    }
+
+    fn disconnected(&mut self) {
+        self.online = false;
+        self.pending_packets.clear();
+        self.client_sink_and_pending_packets = None;
+    }
+
+    fn publish(
+        &mut self,
+        events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>,
+        config: &Config,
+        publication: crate::proto::Publication,
+    ) {
+        if publication.qos == crate::proto::QoS::AtMostOnce {
+            // QoS 0 publications are not queued for clients that are offline
+            if self.online {
+                self.write(events, crate::proto::Packet::Publish(crate::proto::Publish {
+                    packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+                    retain: publication.retain,
+                    topic_name: publication.topic_name,
+                    payload: publication.payload,
+                    user_properties: publication.user_properties,
+                    subscription_identifiers: vec![],
+                    response_topic: publication.response_topic,
+                    correlation_data: publication.correlation_data,
+                    message_expiry: publication.message_expiry,
+                    content_type: publication.content_type,
+                }));
+            }
+            return;
+        }
+
+        self.drop_expired(config);
+
+        if let Some(max_queued_messages) = self.limits.max_queued_messages() {
+            if self.queued.len() + self.spilled_len() >= max_queued_messages {
+                log::info!("dropping publication to {} for client {} because its queue is full", crate::proto::EscapedStr(publication.topic_name.as_ref()), self.client_id);
+                config.counters.queued_message_dropped();
+                return;
+            }
+        }
+
+        self.queue(config, publication);
+        self.send_queued(events, config);
+    }
+
+    /// Queues the given publication in memory, or spills it to disk if the client has a persistent session and the publications
+    /// queued in memory reached the memory threshold.
+    ///
+    /// A publication that cannot be spilled is dropped, since keeping it in memory would deliver it before the ones spilled before it.
+    fn queue(&mut self, config: &Config, publication: crate::proto::Publication) {
+        if let Some((directory, memory_threshold)) = &config.queue_spillover {
+            // Once a publication is spilled, the ones after it are spilled too, so that they are read back in order
+            if !self.clean_session && (self.spilled_len() > 0 || self.queued_payload_bytes + publication.payload.len() > *memory_threshold) {
+                let result = match &mut self.spill {
+                    Some(spill) => spill.push(std::time::SystemTime::now(), &publication),
+                    None => spill::Spill::open(directory, self.client_id.as_ref()).and_then(|spill| {
+                        let spill = self.spill.insert(spill);
+                        spill.push(std::time::SystemTime::now(), &publication)
+                    }),
+                };
+
+                if let Err(err) = result {
+                    log::warn!(
+                        "dropping publication to {} for client {} because it could not be spilled: {}",
+                        crate::proto::EscapedStr(publication.topic_name.as_ref()), self.client_id, err,
+                    );
+                    config.counters.queued_message_dropped();
+                }
+
+                return;
+            }
+        }
+
+        self.queued_payload_bytes += publication.payload.len();
+        self.queued.push_back((std::time::Instant::now(), publication));
+    }
+
+    /// Reads back as many spilled publications as are needed to have `len` publications queued in memory
+    fn unspill(&mut self, len: usize) {
+        let spill = match &mut self.spill {
+            Some(spill) if self.queued.len() < len => spill,
+            _ => return,
+        };
+
+        match spill.pop(len - self.queued.len()) {
+            Ok(publications) =>
+                for (queued_at, publication) in publications {
+                    // The publication keeps its age across restarts of the server, for the maximum queued message age
+                    let queued_for = std::time::SystemTime::now().duration_since(queued_at).unwrap_or_default();
+                    let queued_at = std::time::Instant::now().checked_sub(queued_for).unwrap_or_else(std::time::Instant::now);
+
+                    self.queued_payload_bytes += publication.payload.len();
+                    self.queued.push_back((queued_at, publication));
+                },
+
+            Err(err) => {
+                log::warn!("dropping {} spilled publications for client {} because they could not be read: {}", spill.len(), self.client_id, err);
+                if let Err(err) = spill.clear() {
+                    log::warn!("could not remove spilled publications of client {}: {}", self.client_id, err);
+                }
+            },
+        }
+    }
+
+    fn spilled_len(&self) -> usize {
+        self.spill.as_ref().map_or(0, spill::Spill::len)
+    }
+
+    /// Drops queued publications that are older than the maximum queued message age, or whose message expiry passed.
+    ///
+    /// Publications are queued in order, so the ones older than the maximum age are at the front. Publications with a message expiry
+    /// can expire before the ones in front of them, and are dropped when they reach the front, or when they are sent.
+    fn drop_expired(&mut self, config: &Config) {
+        while let Some((queued_at, publication)) = self.queued.front() {
+            let queued_for = queued_at.elapsed();
+            if matches!(config.max_queued_message_age, Some(max_queued_message_age) if queued_for > max_queued_message_age) {
+                log::info!("dropping publication to {} for client {} because it was queued for too long", crate::proto::EscapedStr(publication.topic_name.as_ref()), self.client_id);
+            }
+            else if matches!(publication.message_expiry, Some(message_expiry) if queued_for >= message_expiry) {
+                log::info!("dropping publication to {} for client {} because it expired", crate::proto::EscapedStr(publication.topic_name.as_ref()), self.client_id);
+            }
+            else {
+                break;
+            }
+
+            config.counters.expired_message_dropped();
+            self.queued_payload_bytes -= publication.payload.len();
+            self.queued.pop_front();
+        }
+    }
+
+    /// Sends as many queued publications as the client has room in flight for
+    fn send_queued(&mut self, events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>, config: &Config) {
+        if !self.online {
+            return;
+        }
+
+        let mut available = self.limits.max_inflight_messages().saturating_sub(self.inflight.len());
+        while available > 0 {
+            loop {
+                self.unspill(available);
+                self.drop_expired(config);
+
+                // Publications that were read back but had expired leave room for more
+                if self.queued.len() >= available || self.spilled_len() == 0 {
+                    break;
+                }
+            }
+
+            if self.queued.is_empty() {
+                break;
+            }
+
+            let publications: Vec<_> = self.queued.drain(..available.min(self.queued.len())).collect();
+            for (queued_at, publication) in publications {
+                self.queued_payload_bytes -= publication.payload.len();
+
+                // The client is sent what is left of the message expiry, and publications that expired behind others are dropped
+                let message_expiry = publication.message_expiry.map(|message_expiry|
+                    message_expiry.checked_sub(queued_at.elapsed()).filter(|message_expiry| !message_expiry.is_zero()));
+                if message_expiry == Some(None) {
+                    log::info!("dropping publication to {} for client {} because it expired", crate::proto::EscapedStr(publication.topic_name.as_ref()), self.client_id);
+                    config.counters.expired_message_dropped();
+                    continue;
+                }
+
+                available -= 1;
+                self.send(events, publication, message_expiry.flatten());
+            }
+        }
+    }
+
+    /// Sends a queued publication with the given remaining message expiry, and keeps it in flight until the client acks it
+    fn send(
+        &mut self,
+        events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>,
+        publication: crate::proto::Publication,
+        message_expiry: Option<std::time::Duration>,
+    ) {
+        let packet_identifier = self.reserve_packet_identifier();
+        let packet = crate::proto::Publish {
+            packet_identifier_dup_qos: match publication.qos {
+                crate::proto::QoS::AtMostOnce => unreachable!("QoS 0 publications are never queued"),
+                crate::proto::QoS::AtLeastOnce => crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, false),
+                crate::proto::QoS::ExactlyOnce => crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, false),
+            },
+            retain: publication.retain,
+            topic_name: publication.topic_name,
+            payload: publication.payload,
+            user_properties: publication.user_properties,
+            subscription_identifiers: vec![],
+            response_topic: publication.response_topic,
+            correlation_data: publication.correlation_data,
+            message_expiry,
+            content_type: publication.content_type,
+        };
+        self.inflight.insert(packet_identifier, Some(packet.clone()));
+        self.write(events, crate::proto::Packet::Publish(packet));
+    }
+
+    /// Handles a PUBREC from the client
+    fn released(&mut self, events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>, packet_identifier: crate::proto::PacketIdentifier) {
+        if let Some(packet) = self.inflight.get_mut(&packet_identifier) {
+            *packet = None;
+            self.write(events, crate::proto::Packet::PubRel(crate::proto::PubRel { packet_identifier }));
+        }
+    }
+
+    fn reserve_packet_identifier(&mut self) -> crate::proto::PacketIdentifier {
+        // `send_queued` never has more than `u16::MAX` publications in flight, so there is always a free packet identifier
+        loop {
+            let packet_identifier = self.next_packet_identifier;
+            self.next_packet_identifier += 1;
+            if !self.inflight.contains_key(&packet_identifier) {
+                return packet_identifier;
+            }
+        }
+    }
+
     fn write(&mut self, events: &mut futures_util::stream::FuturesUnordered<RouterFutureSend<L>>, packet: crate::proto::Packet) {
+        if !self.online {
+            return;
+        }
+
         if let Some((client_sink, mut pending_packets)) = self.client_sink_and_pending_packets.take() {
             pending_packets.extend(self.pending_packets.drain(..));
             pending_packets.push_back(packet);
@@ -302,6 +1152,7 @@ enum RouterFutureAccept<L> where L: crate::io::Listener {
 fn auth_accepted_client<L>(
     mut stream: <L as crate::io::Listener>::PacketStream,
     mut sink: <L as crate::io::Listener>::PacketSink,
+    limits: ListenerLimits,
 ) -> RouterFutureAccept<L>
 where
     L: crate::io::Listener + Unpin,
@@ -310,7 +1161,31 @@ where
 {
     RouterFutureAccept::ConnectingClient {
         inner: Box::pin(async move {
-            let packet = stream.try_next().await?.ok_or(ServerError::ClientUnexpectedEof)?;
+            let packet = match stream.try_next().await {
+                Ok(packet) => packet.ok_or(ServerError::ClientUnexpectedEof)?,
+
+                // Clients of versions that the listener cannot decode, such as MQTT 5.0 clients without the `v5` feature,
+                // are told so instead of being disconnected without a response.
+                Err(crate::proto::DecodeError::UnrecognizedProtocolLevel(protocol_level)) => {
+                    sink.send(crate::proto::Packet::ConnAck(crate::proto::ConnAck {
+                        session_present: false,
+                        return_code: crate::proto::ConnectReturnCode::Refused(crate::proto::ConnectionRefusedReason::UnacceptableProtocolVersion),
+                        authentication_data: None,
+                        receive_maximum: None,
+                        maximum_packet_size: None,
+                        server_reference: None,
+                        assigned_client_id: None,
+                        server_keep_alive: None,
+                        maximum_qos: None,
+                        retain_available: None,
+                        reason_string: None,
+                        user_properties: vec![],
+                    })).await?;
+                    return Err(ServerError::ClientUnsupportedProtocolLevel(protocol_level));
+                },
+
+                Err(err) => return Err(err.into()),
+            };
             let connect =
                 if let crate::proto::Packet::Connect(connect) = packet {
                     connect
@@ -322,16 +1197,22 @@ where
                 sink.send(crate::proto::Packet::ConnAck(crate::proto::ConnAck {
                     session_present: false,
                     return_code: crate::proto::ConnectReturnCode::Refused(reason),
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 })).await?;
                 return Err(ServerError::ClientAuthFailed);
             }
 
-            sink.send(crate::proto::Packet::ConnAck(crate::proto::ConnAck {
-                session_present: false,
-                return_code: crate::proto::ConnectReturnCode::Accepted,
-            })).await?;
-
-            Ok((connect.client_id, stream, sink))
+            // The CONNACK for an accepted client is sent by `ServerState::add_client`, since it depends on whether the client has a session.
+            Ok((connect, stream, sink, limits))
         }),
     }
 }
@@ -348,7 +1229,7 @@ where
         std::task::Poll::Ready(match &mut *self {
             RouterFutureAccept::Accepting { listener } => {
                 let mut listener = listener.take().expect("polled after completion");
-                match listener.poll_accept(cx) {
+                match listener.poll_accept_with_limits(cx) {
                     std::task::Poll::Ready(new_client) => RouterEventAccept::AcceptedClient(listener, new_client.map_err(ServerError::ClientAcceptFailed)),
                     std::task::Poll::Pending => {
                         self.set(RouterFutureAccept::Accepting {
@@ -368,9 +1249,9 @@ where
 }
 
 enum RouterEventAccept<L> where L: crate::io::Listener {
-    AcceptedClient(L, Result<(<L as crate::io::Listener>::PacketStream, <L as crate::io::Listener>::PacketSink), ServerError>),
+    AcceptedClient(L, Result<(<L as crate::io::Listener>::PacketStream, <L as crate::io::Listener>::PacketSink, ListenerLimits), ServerError>),
 
-    ClientReady(Result<(crate::proto::ClientId, <L as crate::io::Listener>::PacketStream, <L as crate::io::Listener>::PacketSink), ServerError>),
+    ClientReady(Result<(crate::proto::Connect, <L as crate::io::Listener>::PacketStream, <L as crate::io::Listener>::PacketSink, ListenerLimits), ServerError>),
 }
 
 struct RouterFutureRecv<L>(Option<(crate::proto::ByteStr, <L as crate::io::Listener>::PacketStream)>) where L: crate::io::Listener;
@@ -480,6 +1361,7 @@ enum ServerError {
     ClientMalformed(crate::proto::DecodeError),
     ClientUnexpected { expected: &'static str },
     ClientUnexpectedEof,
+    ClientUnsupportedProtocolLevel(u8),
     ServerMalformed(crate::proto::EncodeError),
 }
 
@@ -491,6 +1373,7 @@ impl std::fmt::Display for ServerError {
             ServerError::ClientMalformed(_) => f.write_str("client sent malformed packet"),
             ServerError::ClientUnexpected { expected } => write!(f, "client sent unexpected packet, expected {:?}", expected),
             ServerError::ClientUnexpectedEof => f.write_str("client disconnected unexpectedly"),
+            ServerError::ClientUnsupportedProtocolLevel(protocol_level) => write!(f, "client uses unsupported protocol level {}", protocol_level),
             ServerError::ServerMalformed(_) => f.write_str("server sent malformed packet"),
         }
     }
@@ -505,6 +1388,7 @@ impl std::error::Error for ServerError {
             ServerError::ClientMalformed(err) => Some(err),
             ServerError::ClientUnexpected { .. } => None,
             ServerError::ClientUnexpectedEof => None,
+            ServerError::ClientUnsupportedProtocolLevel(_) => None,
             ServerError::ServerMalformed(err) => Some(err),
         }
     }
@@ -521,3 +1405,630 @@ impl From<crate::proto::EncodeError> for ServerError {
         ServerError::ServerMalformed(err)
     }
 }
+
+#[cfg(all(test, feature = "transport-tokio"))]
+mod tests {
+    use futures_util::{SinkExt, StreamExt};
+
+    struct Listener(tokio::net::TcpListener);
+
+    impl crate::io::Listener for Listener {
+        type PacketStream = crate::transport::IoStream<tokio::io::ReadHalf<tokio::net::TcpStream>>;
+        type PacketSink = crate::transport::IoSink<tokio::io::WriteHalf<tokio::net::TcpStream>>;
+
+        fn poll_accept(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink)>> {
+            self.0.poll_accept(cx).map_ok(|(stream, _)| crate::transport::framed(stream))
+        }
+    }
+
+    type PacketStream = <Listener as crate::io::Listener>::PacketStream;
+    type PacketSink = <Listener as crate::io::Listener>::PacketSink;
+
+    /// Runs a server with the given configuration while the future returned by the given function runs
+    fn run<F>(config: super::Config, f: impl FnOnce(std::net::SocketAddr) -> F)
+    where
+        F: std::future::Future<Output = ()>,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&runtime, async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let _server = tokio::task::spawn_local(super::run_with_config(Listener(listener), config));
+            f(addr).await;
+        });
+    }
+
+    /// Connects a client with the given client ID and protocol level to the server, and returns its connection and the CONNACK
+    async fn connect(
+        addr: std::net::SocketAddr,
+        client_id: crate::proto::ClientId,
+        protocol_level: u8,
+    ) -> (PacketStream, PacketSink, crate::proto::ConnAck) {
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut stream, mut sink) = crate::transport::framed(stream);
+        sink.send(connect_packet(client_id, protocol_level)).await.unwrap();
+
+        match next(&mut stream).await {
+            crate::proto::Packet::ConnAck(conn_ack) => (stream, sink, conn_ack),
+            packet => panic!("expected CONNACK, got {:?}", packet),
+        }
+    }
+
+    fn connect_packet(client_id: crate::proto::ClientId, protocol_level: u8) -> crate::proto::Packet {
+        crate::proto::Packet::Connect(crate::proto::Connect {
+            username: None,
+            password: None,
+            will: None,
+            client_id,
+            keep_alive: std::time::Duration::from_secs(60),
+            protocol_name: crate::PROTOCOL_NAME,
+            protocol_level,
+            session_expiry: None,
+            will_delay: None,
+            maximum_packet_size: None,
+            authentication_method: None,
+            authentication_data: None,
+        })
+    }
+
+    async fn next(stream: &mut PacketStream) -> crate::proto::Packet {
+        tokio::time::timeout(std::time::Duration::from_secs(5), stream.next()).await
+            .expect("timed out waiting for a packet")
+            .expect("connection closed")
+            .unwrap()
+    }
+
+    /// Asserts that the server sends nothing for a while
+    async fn assert_no_packet(stream: &mut PacketStream) {
+        if let Ok(packet) = tokio::time::timeout(std::time::Duration::from_millis(100), stream.next()).await {
+            panic!("expected no packet, got {:?}", packet);
+        }
+    }
+
+    async fn subscribe(stream: &mut PacketStream, sink: &mut PacketSink, topic_filter: &str, qos: crate::proto::QoS) -> crate::proto::SubAck {
+        subscribe_to(stream, sink, subscription(topic_filter, qos)).await
+    }
+
+    fn subscription(topic_filter: &str, qos: crate::proto::QoS) -> crate::proto::SubscribeTo {
+        crate::proto::SubscribeTo {
+            topic_filter: topic_filter.parse().unwrap(),
+            qos,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        }
+    }
+
+    async fn subscribe_to(stream: &mut PacketStream, sink: &mut PacketSink, subscribe_to: crate::proto::SubscribeTo) -> crate::proto::SubAck {
+        sink.send(crate::proto::Packet::Subscribe(crate::proto::Subscribe {
+            packet_identifier: crate::proto::PacketIdentifier::new(1).unwrap(),
+            subscribe_to: vec![subscribe_to],
+        })).await.unwrap();
+
+        match next(stream).await {
+            crate::proto::Packet::SubAck(sub_ack) => sub_ack,
+            packet => panic!("expected SUBACK, got {:?}", packet),
+        }
+    }
+
+    fn publish(topic_name: &str, packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS, payload: &'static [u8]) -> crate::proto::Packet {
+        crate::proto::Packet::Publish(crate::proto::Publish {
+            packet_identifier_dup_qos,
+            retain: false,
+            topic_name: topic_name.parse().unwrap(),
+            payload: bytes::Bytes::from_static(payload),
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
+        })
+    }
+
+    fn client_id(client_id: &str) -> crate::proto::ClientId {
+        crate::proto::ClientId::IdWithCleanSession(client_id.parse().unwrap())
+    }
+
+    #[test]
+    fn granted_qos() {
+        run(Default::default(), |addr| async move {
+            let (mut subscriber_stream, mut subscriber_sink, _) = connect(addr, client_id("subscriber"), crate::PROTOCOL_LEVEL).await;
+            let sub_ack = subscribe(&mut subscriber_stream, &mut subscriber_sink, "a", crate::proto::QoS::AtLeastOnce).await;
+            assert_eq!(sub_ack.qos, [crate::proto::SubAckQos::Success(crate::proto::QoS::AtLeastOnce)]);
+
+            let (mut publisher_stream, mut publisher_sink, _) = connect(addr, client_id("publisher"), crate::PROTOCOL_LEVEL).await;
+            let packet_identifier = crate::proto::PacketIdentifier::new(1).unwrap();
+            publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, false), b"1")).await.unwrap();
+            assert_eq!(next(&mut publisher_stream).await, crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier }));
+
+            // The QoS 2 publication is forwarded with QoS 1, the QoS granted to the subscriber
+            match next(&mut subscriber_stream).await {
+                crate::proto::Packet::Publish(crate::proto::Publish {
+                    packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtLeastOnce(_, false),
+                    payload,
+                    ..
+                }) => assert_eq!(payload, b"1"[..]),
+                packet => panic!("expected QoS 1 PUBLISH, got {:?}", packet),
+            }
+
+            // A QoS 0 publication stays QoS 0
+            publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::AtMostOnce, b"2")).await.unwrap();
+            assert!(matches!(
+                next(&mut subscriber_stream).await,
+                crate::proto::Packet::Publish(crate::proto::Publish { packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce, .. }),
+            ));
+        });
+    }
+
+    #[test]
+    fn exactly_once() {
+        run(Default::default(), |addr| async move {
+            let (mut subscriber_stream, mut subscriber_sink, _) = connect(addr, client_id("subscriber"), crate::PROTOCOL_LEVEL).await;
+            subscribe(&mut subscriber_stream, &mut subscriber_sink, "a", crate::proto::QoS::AtMostOnce).await;
+
+            let (mut publisher_stream, mut publisher_sink, _) = connect(addr, client_id("publisher"), crate::PROTOCOL_LEVEL).await;
+            let packet_identifier = crate::proto::PacketIdentifier::new(1).unwrap();
+            publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, false), b"1")).await.unwrap();
+            assert_eq!(next(&mut publisher_stream).await, crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier }));
+            assert!(matches!(next(&mut subscriber_stream).await, crate::proto::Packet::Publish(_)));
+
+            // A retransmission before PUBREL is acked again, but not forwarded again
+            publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, true), b"1")).await.unwrap();
+            assert_eq!(next(&mut publisher_stream).await, crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier }));
+            assert_no_packet(&mut subscriber_stream).await;
+
+            publisher_sink.send(crate::proto::Packet::PubRel(crate::proto::PubRel { packet_identifier })).await.unwrap();
+            assert_eq!(next(&mut publisher_stream).await, crate::proto::Packet::PubComp(crate::proto::PubComp { packet_identifier }));
+
+            // Once released, the packet identifier can be used for a new publication
+            publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, false), b"2")).await.unwrap();
+            assert_eq!(next(&mut publisher_stream).await, crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier }));
+            match next(&mut subscriber_stream).await {
+                crate::proto::Packet::Publish(crate::proto::Publish { payload, .. }) => assert_eq!(payload, b"2"[..]),
+                packet => panic!("expected PUBLISH, got {:?}", packet),
+            }
+        });
+    }
+
+    #[test]
+    fn exactly_once_subscription() {
+        run(super::Config::default().with_max_inflight_messages(1), |addr| async move {
+            let (mut subscriber_stream, mut subscriber_sink, _) = connect(addr, client_id("subscriber"), crate::PROTOCOL_LEVEL).await;
+            let sub_ack = subscribe(&mut subscriber_stream, &mut subscriber_sink, "a", crate::proto::QoS::ExactlyOnce).await;
+            assert_eq!(sub_ack.qos, [crate::proto::SubAckQos::Success(crate::proto::QoS::ExactlyOnce)]);
+
+            let (mut publisher_stream, mut publisher_sink, _) = connect(addr, client_id("publisher"), crate::PROTOCOL_LEVEL).await;
+            for (packet_identifier, payload) in [(1, b"1"), (2, b"2")] {
+                let packet_identifier = crate::proto::PacketIdentifier::new(packet_identifier).unwrap();
+                publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, false), payload)).await.unwrap();
+                assert_eq!(next(&mut publisher_stream).await, crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier }));
+            }
+
+            // The subscriber gets the first publication with QoS 2, and the second one waits for room in flight
+            let packet_identifier = match next(&mut subscriber_stream).await {
+                crate::proto::Packet::Publish(crate::proto::Publish {
+                    packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::ExactlyOnce(packet_identifier, false),
+                    payload,
+                    ..
+                }) => {
+                    assert_eq!(payload, b"1"[..]);
+                    packet_identifier
+                },
+                packet => panic!("expected QoS 2 PUBLISH, got {:?}", packet),
+            };
+            assert_no_packet(&mut subscriber_stream).await;
+
+            // The publication is released, and only completing it makes room for the next one
+            subscriber_sink.send(crate::proto::Packet::PubRec(crate::proto::PubRec { packet_identifier })).await.unwrap();
+            assert_eq!(next(&mut subscriber_stream).await, crate::proto::Packet::PubRel(crate::proto::PubRel { packet_identifier }));
+            assert_no_packet(&mut subscriber_stream).await;
+
+            subscriber_sink.send(crate::proto::Packet::PubComp(crate::proto::PubComp { packet_identifier })).await.unwrap();
+            match next(&mut subscriber_stream).await {
+                crate::proto::Packet::Publish(crate::proto::Publish {
+                    packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::ExactlyOnce(_, false),
+                    payload,
+                    ..
+                }) => assert_eq!(payload, b"2"[..]),
+                packet => panic!("expected QoS 2 PUBLISH, got {:?}", packet),
+            }
+        });
+    }
+
+    #[test]
+    fn maximum_qos_subscription() {
+        run(super::Config::default().with_maximum_qos(crate::proto::QoS::AtLeastOnce), |addr| async move {
+            let (mut stream, mut sink, _) = connect(addr, client_id("subscriber"), crate::PROTOCOL_LEVEL).await;
+            let sub_ack = subscribe(&mut stream, &mut sink, "a", crate::proto::QoS::ExactlyOnce).await;
+            assert_eq!(sub_ack.qos, [crate::proto::SubAckQos::Success(crate::proto::QoS::AtLeastOnce)]);
+        });
+    }
+
+    #[test]
+    fn shared_subscriptions() {
+        run(Default::default(), |addr| async move {
+            let (mut subscriber_stream, mut subscriber_sink, _) = connect(addr, client_id("subscriber"), crate::PROTOCOL_LEVEL).await;
+            subscribe(&mut subscriber_stream, &mut subscriber_sink, "a", crate::proto::QoS::AtMostOnce).await;
+
+            let mut members = vec![];
+            for member in &["member1", "member2"] {
+                let (mut member_stream, mut member_sink, _) = connect(addr, client_id(member), crate::PROTOCOL_LEVEL).await;
+                let sub_ack = subscribe(&mut member_stream, &mut member_sink, "$share/group/a", crate::proto::QoS::AtMostOnce).await;
+                assert_eq!(sub_ack.qos, [crate::proto::SubAckQos::Success(crate::proto::QoS::AtMostOnce)]);
+                members.push((member_stream, member_sink));
+            }
+
+            let (mut publisher_stream, mut publisher_sink, _) = connect(addr, client_id("publisher"), crate::PROTOCOL_LEVEL).await;
+            let sub_ack = subscribe(&mut publisher_stream, &mut publisher_sink, "$share/group", crate::proto::QoS::AtMostOnce).await;
+            assert_eq!(sub_ack.qos, [crate::proto::SubAckQos::Failure]);
+
+            publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::AtMostOnce, b"1")).await.unwrap();
+            publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::AtMostOnce, b"2")).await.unwrap();
+
+            // The plain subscription gets every publication, and the members of the group take turns
+            for _ in 0..2 {
+                assert!(matches!(next(&mut subscriber_stream).await, crate::proto::Packet::Publish(_)));
+            }
+            for (member_stream, _) in &mut members {
+                assert!(matches!(next(member_stream).await, crate::proto::Packet::Publish(_)));
+                assert_no_packet(member_stream).await;
+            }
+        });
+    }
+
+    #[test]
+    fn retained() {
+        // Publications are routed when they are received, so once the PUBACK is received, the publication was retained
+        async fn retain(stream: &mut PacketStream, sink: &mut PacketSink, topic_name: &str, packet_identifier: u16, payload: &'static [u8]) {
+            let packet_identifier_dup_qos =
+                crate::proto::PacketIdentifierDupQoS::AtLeastOnce(crate::proto::PacketIdentifier::new(packet_identifier).unwrap(), false);
+            let packet = match publish(topic_name, packet_identifier_dup_qos, payload) {
+                crate::proto::Packet::Publish(publish) => crate::proto::Packet::Publish(crate::proto::Publish { retain: true, ..publish }),
+                _ => unreachable!(),
+            };
+            sink.send(packet).await.unwrap();
+            assert!(matches!(next(stream).await, crate::proto::Packet::PubAck(_)));
+        }
+
+        async fn assert_retained(stream: &mut PacketStream, expected: &[(&str, &[u8])]) {
+            for &(expected_topic_name, expected_payload) in expected {
+                match next(stream).await {
+                    crate::proto::Packet::Publish(crate::proto::Publish { retain: true, topic_name, payload, .. }) => {
+                        assert_eq!(topic_name.as_ref(), expected_topic_name);
+                        assert_eq!(payload, expected_payload);
+                    },
+                    packet => panic!("expected retained PUBLISH, got {:?}", packet),
+                }
+            }
+            assert_no_packet(stream).await;
+        }
+
+        let config = super::Config::default().with_max_retained_payload_size(1).with_max_retained_messages(2);
+
+        run(config, |addr| async move {
+            let (mut publisher_stream, mut publisher_sink, _) = connect(addr, client_id("publisher"), crate::PROTOCOL_LEVEL).await;
+            retain(&mut publisher_stream, &mut publisher_sink, "devices/a/status", 1, b"1").await;
+            retain(&mut publisher_stream, &mut publisher_sink, "devices/b/status", 2, b"2").await;
+
+            // Neither the payload larger than the maximum nor a third topic is retained, but the retained message of a topic
+            // that has one can be replaced
+            retain(&mut publisher_stream, &mut publisher_sink, "devices/a/config", 3, b"22").await;
+            retain(&mut publisher_stream, &mut publisher_sink, "devices/c/status", 4, b"3").await;
+            retain(&mut publisher_stream, &mut publisher_sink, "devices/b/status", 5, b"4").await;
+
+            let (mut subscriber_stream, mut subscriber_sink, _) = connect(addr, client_id("subscriber"), crate::PROTOCOL_LEVEL).await;
+            subscribe(&mut subscriber_stream, &mut subscriber_sink, "devices/+/status", crate::proto::QoS::AtMostOnce).await;
+            assert_retained(&mut subscriber_stream, &[("devices/a/status", b"1"), ("devices/b/status", b"4")]).await;
+
+            // An empty payload clears the retained message of its topic, which makes room for another topic
+            retain(&mut publisher_stream, &mut publisher_sink, "devices/a/status", 6, b"").await;
+            retain(&mut publisher_stream, &mut publisher_sink, "devices/c/status", 7, b"3").await;
+
+            let (mut subscriber_stream, mut subscriber_sink, _) = connect(addr, client_id("subscriber2"), crate::PROTOCOL_LEVEL).await;
+            subscribe(&mut subscriber_stream, &mut subscriber_sink, "devices/#", crate::proto::QoS::AtMostOnce).await;
+            assert_retained(&mut subscriber_stream, &[("devices/b/status", b"4"), ("devices/c/status", b"3")]).await;
+        });
+    }
+
+    #[cfg(all(feature = "client", feature = "ws"))]
+    #[test]
+    fn broker_builder() {
+        let config = super::Config::default();
+        let shutdown_handle = config.shutdown_handle();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&runtime, async {
+            let broker =
+                super::BrokerBuilder::default()
+                .with_config(config)
+                .with_tcp("127.0.0.1:0")
+                .with_ws("127.0.0.1:0")
+                .bind().await.unwrap();
+            let (tcp_addr, ws_addr) = (broker.local_addrs()[0], broker.local_addrs()[1]);
+            let server = tokio::task::spawn_local(broker.run());
+
+            let (mut subscriber_stream, mut subscriber_sink, _) = connect(tcp_addr, client_id("subscriber"), crate::PROTOCOL_LEVEL).await;
+            subscribe(&mut subscriber_stream, &mut subscriber_sink, "a", crate::proto::QoS::AtMostOnce).await;
+
+            // A client that connects over WebSocket publishes to the subscriber that connected over TCP
+            let mut connector = crate::transport::WsConnector::new(format!("ws://{}/mqtt", ws_addr));
+            let (mut publisher_stream, mut publisher_sink, _) = crate::io::Connector::connect(&mut connector).await.unwrap();
+            publisher_sink.send(connect_packet(client_id("publisher"), crate::PROTOCOL_LEVEL)).await.unwrap();
+            assert!(matches!(publisher_stream.next().await, Some(Ok(crate::proto::Packet::ConnAck(_)))));
+
+            publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::AtMostOnce, b"1")).await.unwrap();
+            match next(&mut subscriber_stream).await {
+                crate::proto::Packet::Publish(publish) => assert_eq!(publish.payload, &b"1"[..]),
+                packet => panic!("expected PUBLISH, got {:?}", packet),
+            }
+
+            shutdown_handle.shutdown();
+            server.await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn listener_limits() {
+        let config = super::Config::default();
+        let counters = config.counters();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let local = tokio::task::LocalSet::new();
+        local.block_on(&runtime, async {
+            let limited_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let limited_addr = limited_listener.local_addr().unwrap();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let listeners = crate::io::Listeners::default()
+                .with_listener_limits(Listener(limited_listener), super::ListenerLimits::default().with_max_inflight_messages(1).with_max_queued_messages(1))
+                .with_listener(Listener(listener));
+            let _server = tokio::task::spawn_local(super::run_with_config(listeners, config));
+
+            let (mut limited_stream, mut limited_sink, _) = connect(limited_addr, client_id("limited"), crate::PROTOCOL_LEVEL).await;
+            subscribe(&mut limited_stream, &mut limited_sink, "a", crate::proto::QoS::AtLeastOnce).await;
+            let (mut stream, mut sink, _) = connect(addr, client_id("unlimited"), crate::PROTOCOL_LEVEL).await;
+            subscribe(&mut stream, &mut sink, "a", crate::proto::QoS::AtLeastOnce).await;
+
+            let (mut publisher_stream, mut publisher_sink, _) = connect(addr, client_id("publisher"), crate::PROTOCOL_LEVEL).await;
+            for (packet_identifier, payload) in [(1, b"1"), (2, b"2"), (3, b"3")] {
+                let packet_identifier = crate::proto::PacketIdentifier::new(packet_identifier).unwrap();
+                publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, false), payload)).await.unwrap();
+                assert_eq!(next(&mut publisher_stream).await, crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier }));
+            }
+
+            // The client of the listener without limits gets every publication right away
+            for payload in [b"1", b"2", b"3"] {
+                match next(&mut stream).await {
+                    crate::proto::Packet::Publish(publish) => assert_eq!(publish.payload, &payload[..]),
+                    packet => panic!("expected PUBLISH, got {:?}", packet),
+                }
+            }
+
+            // The client of the limited listener gets one publication in flight and one queued behind it, and the third is dropped
+            let packet_identifier = match next(&mut limited_stream).await {
+                crate::proto::Packet::Publish(crate::proto::Publish {
+                    packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, false),
+                    payload,
+                    ..
+                }) if payload == b"1"[..] => packet_identifier,
+                packet => panic!("expected PUBLISH, got {:?}", packet),
+            };
+            assert_no_packet(&mut limited_stream).await;
+            assert_eq!(counters.queued_messages_dropped(), 1);
+
+            limited_sink.send(crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier })).await.unwrap();
+            match next(&mut limited_stream).await {
+                crate::proto::Packet::Publish(publish) => assert_eq!(publish.payload, &b"2"[..]),
+                packet => panic!("expected PUBLISH, got {:?}", packet),
+            }
+            assert_no_packet(&mut limited_stream).await;
+        });
+    }
+
+    #[test]
+    fn spill_failure() {
+        let directory = std::env::temp_dir().join(format!("mqtt3-server-spill-failure-{}", std::process::id()));
+        let config = super::Config::default().with_queue_spillover(directory, 0);
+        let counters = config.counters();
+
+        run(config, |addr| async move {
+            let subscriber_id = crate::proto::ClientId::IdWithExistingSession("subscriber".parse().unwrap());
+            let (mut subscriber_stream, mut subscriber_sink, _) = connect(addr, subscriber_id.clone(), crate::PROTOCOL_LEVEL).await;
+            subscribe(&mut subscriber_stream, &mut subscriber_sink, "a", crate::proto::QoS::AtLeastOnce).await;
+            drop((subscriber_stream, subscriber_sink));
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+            // The spill directory does not exist, so the publication is dropped instead of being kept in memory
+            let (mut publisher_stream, mut publisher_sink, _) = connect(addr, client_id("publisher"), crate::PROTOCOL_LEVEL).await;
+            let packet_identifier = crate::proto::PacketIdentifier::new(1).unwrap();
+            publisher_sink.send(publish("a", crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, false), b"1")).await.unwrap();
+            assert_eq!(next(&mut publisher_stream).await, crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier }));
+            assert_eq!(counters.queued_messages_dropped(), 1);
+
+            let (mut subscriber_stream, _subscriber_sink, _) = connect(addr, subscriber_id, crate::PROTOCOL_LEVEL).await;
+            assert_no_packet(&mut subscriber_stream).await;
+        });
+    }
+
+    #[test]
+    fn shutdown() {
+        let config = super::Config::default();
+        let shutdown_handle = config.shutdown_handle();
+
+        run(config, |addr| async move {
+            let (mut stream, _sink, _) = connect(addr, client_id("client"), crate::PROTOCOL_LEVEL).await;
+
+            #[cfg(feature = "v5")]
+            let (mut v5_stream, _v5_sink, _) = connect(addr, client_id("v5-client"), crate::proto::v5::PROTOCOL_LEVEL).await;
+
+            shutdown_handle.shutdown();
+
+            // MQTT 5.0 clients are told why they are disconnected, and MQTT 3.1.1 clients are disconnected without a packet
+            #[cfg(feature = "v5")]
+            {
+                assert_eq!(next(&mut v5_stream).await, crate::proto::Packet::Disconnect(crate::proto::Disconnect {
+                    reason: crate::proto::DisconnectReason::ServerShuttingDown,
+                    server_reference: None,
+                }));
+                assert!(tokio::time::timeout(std::time::Duration::from_secs(5), v5_stream.next()).await.unwrap().is_none());
+            }
+
+            assert!(tokio::time::timeout(std::time::Duration::from_secs(5), stream.next()).await.unwrap().is_none());
+        });
+    }
+
+    #[cfg(feature = "v5")]
+    #[test]
+    fn v5() {
+        use std::str::FromStr;
+
+        run(super::Config::default().with_retain_available(false), |addr| async move {
+            let (mut stream, mut sink, conn_ack) = connect(addr, crate::proto::ClientId::ServerGenerated, crate::proto::v5::PROTOCOL_LEVEL).await;
+            assert_eq!(conn_ack.assigned_client_id, Some(crate::proto::ByteStr::from_str("server-generated-0").unwrap()));
+            assert_eq!(conn_ack.retain_available, Some(false));
+
+            subscribe(&mut stream, &mut sink, "a", crate::proto::QoS::AtMostOnce).await;
+
+            let packet_identifier = crate::proto::PacketIdentifier::new(2).unwrap();
+            sink.send(crate::proto::Packet::Unsubscribe(crate::proto::Unsubscribe {
+                packet_identifier,
+                unsubscribe_from: vec!["a".parse().unwrap(), "b".parse().unwrap()],
+            })).await.unwrap();
+            assert_eq!(next(&mut stream).await, crate::proto::Packet::UnsubAck(crate::proto::UnsubAck {
+                packet_identifier,
+                results: vec![crate::proto::UnsubAckResult::Success, crate::proto::UnsubAckResult::NoSubscriptionExisted],
+            }));
+        });
+    }
+
+    #[cfg(feature = "v5")]
+    #[test]
+    fn subscription_options() {
+        async fn publish_retained(stream: &mut PacketStream, sink: &mut PacketSink, topic_name: &str, payload: &'static [u8]) {
+            let packet_identifier = crate::proto::PacketIdentifier::new(1).unwrap();
+            let mut packet = publish(topic_name, crate::proto::PacketIdentifierDupQoS::AtLeastOnce(packet_identifier, false), payload);
+            if let crate::proto::Packet::Publish(publish) = &mut packet {
+                publish.retain = true;
+            }
+            sink.send(packet).await.unwrap();
+            assert_eq!(next(stream).await, crate::proto::Packet::PubAck(crate::proto::PubAck { packet_identifier }));
+        }
+
+        async fn assert_published(stream: &mut PacketStream, expected_topic_name: &str, expected_retain: bool) {
+            match next(stream).await {
+                crate::proto::Packet::Publish(crate::proto::Publish { topic_name, retain, .. }) =>
+                    assert_eq!((topic_name.as_ref(), retain), (expected_topic_name, expected_retain)),
+                packet => panic!("expected PUBLISH, got {:?}", packet),
+            }
+        }
+
+        run(Default::default(), |addr| async move {
+            let (mut publisher_stream, mut publisher_sink, _) = connect(addr, client_id("publisher"), crate::PROTOCOL_LEVEL).await;
+            publish_retained(&mut publisher_stream, &mut publisher_sink, "r", b"1").await;
+
+            let (mut stream, mut sink, _) = connect(addr, client_id("subscriber"), crate::proto::v5::PROTOCOL_LEVEL).await;
+
+            // Retained messages are not sent when the subscription asks for none, or when it only asks for them on new subscriptions
+            // and already existed
+            let options = crate::proto::SubscribeTo { retain_handling: crate::proto::RetainHandling::DoNotSend, ..subscription("r", crate::proto::QoS::AtLeastOnce) };
+            subscribe_to(&mut stream, &mut sink, options).await;
+            assert_no_packet(&mut stream).await;
+
+            let options = crate::proto::SubscribeTo { retain_handling: crate::proto::RetainHandling::SendOnNewSubscribe, ..subscription("r", crate::proto::QoS::AtLeastOnce) };
+            subscribe_to(&mut stream, &mut sink, options).await;
+            assert_no_packet(&mut stream).await;
+
+            publish_retained(&mut publisher_stream, &mut publisher_sink, "q", b"1").await;
+            let options = crate::proto::SubscribeTo { retain_handling: crate::proto::RetainHandling::SendOnNewSubscribe, ..subscription("q", crate::proto::QoS::AtLeastOnce) };
+            subscribe_to(&mut stream, &mut sink, options).await;
+            assert_published(&mut stream, "q", true).await;
+
+            // The client is not sent its own publications with No Local, and is sent publications with the retain flag they were published with
+            // with Retain As Published
+            let options = crate::proto::SubscribeTo { no_local: true, retain_as_published: true, ..subscription("a", crate::proto::QoS::AtLeastOnce) };
+            subscribe_to(&mut stream, &mut sink, options).await;
+            let (mut other_stream, mut other_sink, _) = connect(addr, client_id("other"), crate::proto::v5::PROTOCOL_LEVEL).await;
+            subscribe(&mut other_stream, &mut other_sink, "a", crate::proto::QoS::AtLeastOnce).await;
+
+            publish_retained(&mut stream, &mut sink, "a", b"1").await;
+            assert_published(&mut other_stream, "a", false).await;
+            assert_no_packet(&mut stream).await;
+
+            publish_retained(&mut publisher_stream, &mut publisher_sink, "a", b"2").await;
+            assert_published(&mut stream, "a", true).await;
+            assert_published(&mut other_stream, "a", false).await;
+
+            // No Local is a protocol error for shared subscriptions
+            let options = crate::proto::SubscribeTo { no_local: true, ..subscription("$share/group/a", crate::proto::QoS::AtLeastOnce) };
+            let sub_ack = subscribe_to(&mut stream, &mut sink, options).await;
+            assert_eq!(sub_ack.qos, [crate::proto::SubAckQos::Failure]);
+        });
+    }
+
+    #[cfg(feature = "v5")]
+    #[test]
+    fn maximum_qos() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        run(super::Config::default().with_maximum_qos(crate::proto::QoS::AtLeastOnce), |addr| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+            // CONNECT with protocol level 5, clean start, keep alive 60s, no properties and client ID "a"
+            stream.write_all(&[0x10, 0x0E, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0x02, 0x00, 0x3C, 0x00, 0x00, 0x01, b'a']).await.unwrap();
+
+            // CONNACK with the Maximum QoS property
+            let mut conn_ack = [0; 7];
+            tokio::time::timeout(std::time::Duration::from_secs(5), stream.read_exact(&mut conn_ack)).await
+                .expect("timed out waiting for CONNACK")
+                .unwrap();
+            assert_eq!(conn_ack, [0x20, 0x05, 0x00, 0x00, 0x02, 0x24, 0x01]);
+        });
+    }
+
+    #[cfg(feature = "v5")]
+    #[test]
+    fn message_expiry() {
+        let publish = |packet_identifier, payload, message_expiry| match publish(
+            "a",
+            crate::proto::PacketIdentifierDupQoS::AtLeastOnce(crate::proto::PacketIdentifier::new(packet_identifier).unwrap(), false),
+            payload,
+        ) {
+            crate::proto::Packet::Publish(publish) => crate::proto::Packet::Publish(crate::proto::Publish {
+                message_expiry: Some(std::time::Duration::from_secs(message_expiry)),
+                ..publish
+            }),
+            _ => unreachable!(),
+        };
+
+        run(Default::default(), |addr| async move {
+            let subscriber_id = crate::proto::ClientId::IdWithExistingSession("subscriber".parse().unwrap());
+            let (mut subscriber_stream, mut subscriber_sink, _) = connect(addr, subscriber_id.clone(), crate::proto::v5::PROTOCOL_LEVEL).await;
+            subscribe(&mut subscriber_stream, &mut subscriber_sink, "a", crate::proto::QoS::AtLeastOnce).await;
+            drop((subscriber_stream, subscriber_sink));
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+            let (_publisher_stream, mut publisher_sink, _) = connect(addr, client_id("publisher"), crate::proto::v5::PROTOCOL_LEVEL).await;
+            publisher_sink.send(publish(1, b"1", 1)).await.unwrap();
+            publisher_sink.send(publish(2, b"2", 60)).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+
+            // Both publications were queued for the persistent session, and only the one that has not expired is delivered,
+            // with what is left of its message expiry
+            let (mut subscriber_stream, _subscriber_sink, conn_ack) = connect(addr, subscriber_id, crate::proto::v5::PROTOCOL_LEVEL).await;
+            assert!(conn_ack.session_present);
+            match next(&mut subscriber_stream).await {
+                crate::proto::Packet::Publish(crate::proto::Publish { payload, message_expiry: Some(message_expiry), .. }) => {
+                    assert_eq!(payload, b"2"[..]);
+                    assert!(message_expiry < std::time::Duration::from_secs(60));
+                },
+                packet => panic!("expected PUBLISH with message expiry, got {:?}", packet),
+            }
+            assert_no_packet(&mut subscriber_stream).await;
+        });
+    }
+}
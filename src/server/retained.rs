@@ -0,0 +1,46 @@
+/// The retained messages stored by the server, shared with the running server. Returned by [`Config::retained_messages`](super::Config::retained_messages).
+///
+/// This lets a management UI that runs alongside the server browse the retained messages, such as to display the current state of devices.
+#[derive(Clone, Debug, Default)]
+pub struct RetainedMessages(std::sync::Arc<std::sync::Mutex<RetainedMap>>);
+
+pub(super) type RetainedMap = std::collections::BTreeMap<crate::proto::ByteStr, bytes::Bytes>;
+
+impl RetainedMessages {
+    /// Returns the topic names and payloads of the retained messages whose topic names match the given topic filter, ordered by topic name.
+    ///
+    /// The topic filter can have `+` and `#` wildcards like the topic filter of a subscription, so `#` returns all the retained messages
+    /// except those to topics that start with `$`.
+    pub fn retained(&self, topic_filter: &str) -> Vec<(crate::proto::ByteStr, bytes::Bytes)> {
+        self.lock().iter()
+            .filter(|(topic_name, _)| crate::proto::topic_filter_matches(topic_filter, topic_name.as_ref()))
+            .map(|(topic_name, payload)| (topic_name.clone(), payload.clone()))
+            .collect()
+    }
+
+    pub(super) fn lock(&self) -> std::sync::MutexGuard<'_, RetainedMap> {
+        self.0.lock().expect("retained messages lock is poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn retained() {
+        use std::str::FromStr;
+
+        let retained_messages = super::RetainedMessages::default();
+        for topic_name in &["devices/a/status", "devices/b/status", "devices/b/config", "$SYS/uptime"] {
+            retained_messages.lock().insert(crate::proto::ByteStr::from_str(topic_name).unwrap(), bytes::Bytes::from_static(b"1"));
+        }
+
+        let topic_names = |topic_filter| -> Vec<String> {
+            retained_messages.retained(topic_filter).into_iter().map(|(topic_name, _)| topic_name.to_string()).collect()
+        };
+        assert_eq!(topic_names("devices/+/status"), ["devices/a/status", "devices/b/status"]);
+        assert_eq!(topic_names("devices/b/#"), ["devices/b/config", "devices/b/status"]);
+        assert_eq!(topic_names("#"), ["devices/a/status", "devices/b/config", "devices/b/status"]);
+        assert_eq!(topic_names("$SYS/uptime"), ["$SYS/uptime"]);
+        assert!(topic_names("devices/c/status").is_empty());
+    }
+}
@@ -0,0 +1,30 @@
+/// Used to shut down a running server. Returned by [`Config::shutdown_handle`](super::Config::shutdown_handle).
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle(std::sync::Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    requested: std::sync::atomic::AtomicBool,
+    waker: futures_util::task::AtomicWaker,
+}
+
+impl ShutdownHandle {
+    /// Starts shutting down the server.
+    ///
+    /// The server stops accepting new clients and reading from connected ones. It then sends each client the packets
+    /// that were already waiting to be sent to it, and closes its connection. MQTT 5.0 clients are sent a DISCONNECT with
+    /// the reason code Server shutting down before their connection is closed. MQTT 3.1.1 has no way for the server to tell
+    /// the client why it is being disconnected.
+    ///
+    /// The future returned by [`run`](super::run) or [`run_with_config`](super::run_with_config) resolves once every connection is closed,
+    /// and the publications spilled to disk, if any, are synced to it. See [`Config::with_queue_spillover`](super::Config::with_queue_spillover).
+    pub fn shutdown(&self) {
+        self.0.requested.store(true, std::sync::atomic::Ordering::Release);
+        self.0.waker.wake();
+    }
+
+    pub(super) fn poll_requested(&self, cx: &mut std::task::Context<'_>) -> bool {
+        self.0.waker.register(cx.waker());
+        self.0.requested.load(std::sync::atomic::Ordering::Acquire)
+    }
+}
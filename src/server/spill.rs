@@ -0,0 +1,433 @@
+use std::convert::TryFrom;
+
+use bytes::{Buf, BufMut};
+
+/// The extension of the files that the queued publications of sessions are spilled to
+const SPILL_FILE_EXTENSION: &str = "queue";
+
+/// The length of the header of a spill file, which holds the offset of the first publication that has not been read back yet,
+/// so that the publications that were read back before the server restarted are not recovered again
+const HEADER_LEN: u64 = 8;
+
+/// The publications queued for a client of a persistent session that were spilled to a file, because the ones queued in memory
+/// reached the memory threshold. See [`Config::with_queue_spillover`](super::Config::with_queue_spillover).
+///
+/// Publications are appended to the end of the file, and read back from the front once the client has room for them.
+/// The file is removed once all of them have been read back, and kept otherwise, such as when the server shuts down,
+/// so that the publications are recovered when the client reconnects to a server that restarted.
+#[derive(Debug)]
+pub(super) struct Spill {
+    path: std::path::PathBuf,
+
+    /// The file, kept open once a publication has been spilled to it or recovered from it
+    file: Option<std::fs::File>,
+
+    /// The offset in the file of the first publication that has not been read back yet
+    read_offset: u64,
+
+    /// The offset in the file just past the last publication that was completely written, where the next one is appended
+    write_offset: u64,
+
+    /// The number of publications in the file that have not been read back yet
+    len: usize,
+}
+
+impl Spill {
+    /// Opens the spill file of the given client in the given directory, recovering the publications that it holds, if it exists
+    pub(super) fn open(directory: &std::path::Path, client_id: &str) -> std::io::Result<Self> {
+        let path = directory.join(file_name(client_id));
+
+        let mut spill = Spill {
+            path,
+            file: None,
+            read_offset: HEADER_LEN,
+            write_offset: HEADER_LEN,
+            len: 0,
+        };
+
+        let mut file = match std::fs::OpenOptions::new().read(true).write(true).open(&spill.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(spill),
+            Err(err) => return Err(err),
+        };
+
+        let file_len = file.metadata()?.len();
+        if file_len < HEADER_LEN {
+            spill.clear()?;
+            return Ok(spill);
+        }
+
+        let mut header = [0_u8; 8];
+        std::io::Read::read_exact(&mut file, &mut header)?;
+        spill.read_offset = u64::from_be_bytes(header).clamp(HEADER_LEN, file_len);
+        std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(spill.read_offset))?;
+
+        // Count the publications in the file. A publication that was only partly written, such as because the server stopped
+        // while writing it, is cut off, so that the next one is appended after the last complete one.
+        let mut reader = std::io::BufReader::new(&file);
+        let mut complete_len = spill.read_offset;
+        while let Some(record_len) = read_record(&mut reader, file_len - complete_len)? {
+            complete_len += record_len;
+            spill.len += 1;
+        }
+        if complete_len < file_len {
+            log::warn!("discarding {} bytes of incomplete publication at the end of {}", file_len - complete_len, spill.path.display());
+            file.set_len(complete_len)?;
+        }
+
+        if spill.len == 0 {
+            spill.clear()?;
+        }
+        else {
+            log::info!("recovered {} spilled publications for client {}", spill.len, client_id);
+            spill.file = Some(file);
+            spill.write_offset = complete_len;
+        }
+
+        Ok(spill)
+    }
+
+    /// The number of publications in the file that have not been read back yet
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Appends the given publication, queued at the given time, to the file.
+    ///
+    /// If the publication cannot be written completely, the part of it that was written is cut off again, so that the file
+    /// only holds complete publications.
+    pub(super) fn push(&mut self, queued_at: std::time::SystemTime, publication: &crate::proto::Publication) -> std::io::Result<()> {
+        use std::io::{Seek, Write};
+
+        let record = encode(queued_at, publication)?;
+        let write_offset = self.write_offset;
+        let file = self.file()?;
+
+        if let Err(err) = file.seek(std::io::SeekFrom::Start(write_offset)).and_then(|_| file.write_all(&record)) {
+            // If the file cannot be cut off now, it is reopened and cut off before the next publication is appended
+            if file.set_len(write_offset).is_err() {
+                self.file = None;
+            }
+            return Err(err);
+        }
+
+        self.write_offset += record.len() as u64;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Reads back up to `max` publications from the front of the file, with the times they were queued at
+    pub(super) fn pop(&mut self, max: usize) -> std::io::Result<Vec<(std::time::SystemTime, crate::proto::Publication)>> {
+        use std::io::{Read, Seek, Write};
+
+        let mut publications = vec![];
+        if self.len == 0 || max == 0 {
+            return Ok(publications);
+        }
+
+        let mut read_offset = self.read_offset;
+        let mut remaining = self.len;
+        let file = self.file()?;
+        file.seek(std::io::SeekFrom::Start(read_offset))?;
+        let mut reader = std::io::BufReader::new(&*file);
+
+        while publications.len() < max && remaining > 0 {
+            let mut len = [0_u8; 4];
+            reader.read_exact(&mut len)?;
+            let mut record = vec![0_u8; u32::from_be_bytes(len) as usize];
+            reader.read_exact(&mut record)?;
+            publications.push(decode(bytes::Bytes::from(record)).ok_or_else(invalid_record)?);
+
+            read_offset += 4 + u64::from(u32::from_be_bytes(len));
+            remaining -= 1;
+        }
+
+        if remaining == 0 {
+            self.clear()?;
+        }
+        else {
+            file.seek(std::io::SeekFrom::Start(0))?;
+            file.write_all(&read_offset.to_be_bytes())?;
+            self.read_offset = read_offset;
+            self.len = remaining;
+        }
+
+        Ok(publications)
+    }
+
+    /// Writes the publications appended to the file through to the disk
+    pub(super) fn sync(&self) -> std::io::Result<()> {
+        match &self.file {
+            Some(file) => file.sync_all(),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the file and the publications in it
+    pub(super) fn clear(&mut self) -> std::io::Result<()> {
+        self.file = None;
+        self.read_offset = HEADER_LEN;
+        self.write_offset = HEADER_LEN;
+        self.len = 0;
+        remove_file(&self.path)
+    }
+
+    /// Returns the open file, opening or creating it first if it is not open.
+    /// Anything after the last complete publication, such as the part of one whose write failed, is cut off when it is opened.
+    fn file(&mut self) -> std::io::Result<&mut std::fs::File> {
+        use std::io::{Seek, Write};
+
+        if self.file.is_none() {
+            let mut file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&self.path)?;
+            file.set_len(self.write_offset)?;
+            file.seek(std::io::SeekFrom::Start(0))?;
+            file.write_all(&self.read_offset.to_be_bytes())?;
+            self.file = Some(file);
+        }
+
+        Ok(self.file.as_mut().expect("file was just opened"))
+    }
+}
+
+/// Removes the spill file of the given client in the given directory, if it exists, such as when the client starts a clean session
+pub(super) fn remove(directory: &std::path::Path, client_id: &str) -> std::io::Result<()> {
+    remove_file(&directory.join(file_name(client_id)))
+}
+
+fn remove_file(path: &std::path::Path) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// The name of the spill file of the given client. The client ID is hex-encoded, since it can contain any character.
+fn file_name(client_id: &str) -> String {
+    const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let mut file_name = String::with_capacity(client_id.len() * 2 + 1 + SPILL_FILE_EXTENSION.len());
+    for b in client_id.bytes() {
+        file_name.push(char::from(HEX_DIGITS[usize::from(b >> 4)]));
+        file_name.push(char::from(HEX_DIGITS[usize::from(b & 0x0f)]));
+    }
+    file_name.push('.');
+    file_name.push_str(SPILL_FILE_EXTENSION);
+    file_name
+}
+
+/// Skips over the next record of the reader, and returns its length including its length prefix, or `None` if there is no
+/// complete record left in the given number of remaining bytes
+fn read_record(reader: &mut impl std::io::BufRead, remaining: u64) -> std::io::Result<Option<u64>> {
+    use std::io::Read;
+
+    if remaining < 4 {
+        return Ok(None);
+    }
+
+    let mut len = [0_u8; 4];
+    reader.read_exact(&mut len)?;
+    let record_len = 4 + u64::from(u32::from_be_bytes(len));
+    if record_len > remaining {
+        return Ok(None);
+    }
+
+    std::io::copy(&mut reader.take(record_len - 4), &mut std::io::sink())?;
+    Ok(Some(record_len))
+}
+
+/// Encodes a publication as a record of the spill file, prefixed with the length of the rest of the record
+fn encode(queued_at: std::time::SystemTime, publication: &crate::proto::Publication) -> std::io::Result<Vec<u8>> {
+    fn put_bytes(dst: &mut Vec<u8>, value: &[u8]) -> std::io::Result<()> {
+        let len = u32::try_from(value.len()).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "publication is too large to spill"))?;
+        dst.put_u32(len);
+        dst.put_slice(value);
+        Ok(())
+    }
+
+    fn put_optional_bytes(dst: &mut Vec<u8>, value: Option<&[u8]>) -> std::io::Result<()> {
+        if let Some(value) = value {
+            dst.put_u8(1);
+            put_bytes(dst, value)
+        }
+        else {
+            dst.put_u8(0);
+            Ok(())
+        }
+    }
+
+    let mut record = vec![0; 4];
+
+    let queued_at = queued_at.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    record.put_u64(u64::try_from(queued_at.as_millis()).unwrap_or(u64::MAX));
+    record.put_u8(match publication.qos {
+        crate::proto::QoS::AtMostOnce => 0,
+        crate::proto::QoS::AtLeastOnce => 1,
+        crate::proto::QoS::ExactlyOnce => 2,
+    });
+    record.put_u8(publication.retain.into());
+    put_bytes(&mut record, publication.topic_name.as_bytes())?;
+    put_bytes(&mut record, &publication.payload)?;
+
+    record.put_u32(u32::try_from(publication.user_properties.len()).unwrap_or(u32::MAX));
+    for (name, value) in &publication.user_properties {
+        put_bytes(&mut record, name.as_bytes())?;
+        put_bytes(&mut record, value.as_bytes())?;
+    }
+
+    put_optional_bytes(&mut record, publication.response_topic.as_ref().map(crate::proto::ByteStr::as_bytes))?;
+    put_optional_bytes(&mut record, publication.correlation_data.as_deref())?;
+    match publication.message_expiry {
+        Some(message_expiry) => {
+            record.put_u8(1);
+            record.put_u64(u64::try_from(message_expiry.as_millis()).unwrap_or(u64::MAX));
+        },
+        None => record.put_u8(0),
+    }
+    put_optional_bytes(&mut record, publication.content_type.as_ref().map(crate::proto::ByteStr::as_bytes))?;
+
+    let len = u32::try_from(record.len() - 4).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "publication is too large to spill"))?;
+    record[..4].copy_from_slice(&len.to_be_bytes());
+    Ok(record)
+}
+
+/// Decodes a record of the spill file, without its length prefix, or returns `None` if it is malformed
+fn decode(mut src: bytes::Bytes) -> Option<(std::time::SystemTime, crate::proto::Publication)> {
+    fn get_u8(src: &mut bytes::Bytes) -> Option<u8> {
+        (src.remaining() >= 1).then(|| src.get_u8())
+    }
+
+    fn get_u32(src: &mut bytes::Bytes) -> Option<u32> {
+        (src.remaining() >= 4).then(|| src.get_u32())
+    }
+
+    fn get_u64(src: &mut bytes::Bytes) -> Option<u64> {
+        (src.remaining() >= 8).then(|| src.get_u64())
+    }
+
+    fn get_bytes(src: &mut bytes::Bytes) -> Option<bytes::Bytes> {
+        let len = get_u32(src)? as usize;
+        if src.remaining() < len {
+            return None;
+        }
+        Some(src.split_to(len))
+    }
+
+    fn get_byte_str(src: &mut bytes::Bytes) -> Option<crate::proto::ByteStr> {
+        let value = get_bytes(src)?;
+        std::str::from_utf8(&value).ok()?.parse().ok()
+    }
+
+    #[allow(clippy::option_option)]
+    fn get_optional<T>(src: &mut bytes::Bytes, get: impl FnOnce(&mut bytes::Bytes) -> Option<T>) -> Option<Option<T>> {
+        match get_u8(src)? {
+            0 => Some(None),
+            1 => get(src).map(Some),
+            _ => None,
+        }
+    }
+
+    let queued_at = std::time::UNIX_EPOCH + std::time::Duration::from_millis(get_u64(&mut src)?);
+    let qos = match get_u8(&mut src)? {
+        1 => crate::proto::QoS::AtLeastOnce,
+        2 => crate::proto::QoS::ExactlyOnce,
+        // QoS 0 publications are never queued
+        _ => return None,
+    };
+    let retain = get_u8(&mut src)? != 0;
+    let topic_name = get_byte_str(&mut src)?;
+    let payload = get_bytes(&mut src)?;
+
+    let num_user_properties = get_u32(&mut src)?;
+    let mut user_properties = vec![];
+    for _ in 0..num_user_properties {
+        user_properties.push((get_byte_str(&mut src)?, get_byte_str(&mut src)?));
+    }
+
+    let response_topic = get_optional(&mut src, get_byte_str)?;
+    let correlation_data = get_optional(&mut src, get_bytes)?;
+    let message_expiry = get_optional(&mut src, |src| get_u64(src).map(std::time::Duration::from_millis))?;
+    let content_type = get_optional(&mut src, get_byte_str)?;
+
+    if src.has_remaining() {
+        return None;
+    }
+
+    Some((queued_at, crate::proto::Publication {
+        topic_name,
+        qos,
+        retain,
+        payload,
+        user_properties,
+        response_topic,
+        correlation_data,
+        message_expiry,
+        content_type,
+    }))
+}
+
+fn invalid_record() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed publication in spill file")
+}
+
+#[cfg(test)]
+mod tests {
+    fn publication(i: u8) -> crate::proto::Publication {
+        crate::proto::Publication {
+            topic_name: format!("devices/{}", i).parse().unwrap(),
+            qos: crate::proto::QoS::AtLeastOnce,
+            retain: i % 2 == 0,
+            payload: bytes::Bytes::from(vec![i; usize::from(i) * 10]),
+            user_properties: vec![("device".parse().unwrap(), "a".parse().unwrap())],
+            response_topic: Some("responses".parse().unwrap()),
+            correlation_data: None,
+            message_expiry: Some(std::time::Duration::from_secs(60)),
+            content_type: None,
+        }
+    }
+
+    #[test]
+    fn spill() {
+        let directory = std::env::temp_dir().join(format!("mqtt3-spill-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let queued_at = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+
+        let mut spill = super::Spill::open(&directory, "client/1").unwrap();
+        assert_eq!(spill.len(), 0);
+        for i in 0..5 {
+            spill.push(queued_at, &publication(i)).unwrap();
+        }
+        assert_eq!(spill.len(), 5);
+        assert_eq!(spill.pop(2).unwrap(), vec![(queued_at, publication(0)), (queued_at, publication(1))]);
+
+        // The server restarts, and stopped while writing the last publication
+        let path = directory.join(super::file_name("client/1"));
+        let file_len = std::fs::metadata(&path).unwrap().len();
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(file_len - 1).unwrap();
+
+        // The publications that were not read back are recovered, other than the incomplete one
+        let mut spill = super::Spill::open(&directory, "client/1").unwrap();
+        assert_eq!(spill.len(), 2);
+
+        // A write failed, and the part of the publication that was written could not be cut off right away,
+        // so it is cut off when the file is reopened for the next publication
+        std::io::Write::write_all(&mut std::fs::OpenOptions::new().append(true).open(&path).unwrap(), b"torn").unwrap();
+        spill.file = None;
+        spill.push(queued_at, &publication(5)).unwrap();
+        assert_eq!(
+            spill.pop(10).unwrap(),
+            vec![(queued_at, publication(2)), (queued_at, publication(3)), (queued_at, publication(5))],
+        );
+
+        // The file is removed once it has been read back
+        assert_eq!(spill.len(), 0);
+        assert!(!path.exists());
+
+        std::fs::remove_dir(&directory).unwrap();
+    }
+
+    #[test]
+    fn file_name() {
+        assert_eq!(super::file_name("client/1"), "636c69656e742f31.queue");
+        assert_eq!(super::file_name(""), ".queue");
+    }
+}
@@ -0,0 +1,151 @@
+/// The number of publications and payload bytes that clients published to each topic prefix, shared with the running server.
+/// Returned by [`Config::topic_metrics`](super::Config::topic_metrics).
+///
+/// The server only records them if it runs with [`Config::with_topic_metrics`](super::Config::with_topic_metrics).
+#[derive(Clone, Debug, Default)]
+pub struct TopicMetrics(std::sync::Arc<std::sync::Mutex<Inner>>);
+
+#[derive(Debug)]
+struct Inner {
+    prefixes: std::collections::BTreeMap<String, Counts>,
+
+    /// Publications to prefixes that did not fit in the cardinality budget
+    other: Counts,
+
+    /// When the current sample started
+    since: std::time::Instant,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Counts {
+    messages: u64,
+    bytes: u64,
+}
+
+impl TopicMetrics {
+    /// Returns the publications to each topic prefix since the previous sample, or since the server started, and starts a new sample.
+    ///
+    /// The prefixes are ordered from the one with the most publications to the one with the fewest, so the noisiest devices come first.
+    /// Publications to prefixes beyond the cardinality budget are counted together, in an entry whose prefix is `None`.
+    pub fn sample(&self) -> Vec<TopicPrefixMetrics> {
+        let mut inner = self.lock();
+
+        let now = std::time::Instant::now();
+        let interval = now.duration_since(inner.since);
+        inner.since = now;
+
+        let other = std::mem::take(&mut inner.other);
+        let mut sample: Vec<_> =
+            std::mem::take(&mut inner.prefixes).into_iter()
+            .map(|(prefix, counts)| (Some(prefix), counts))
+            .chain(if other.messages == 0 { None } else { Some((None, other)) })
+            .map(|(prefix, Counts { messages, bytes })| TopicPrefixMetrics { prefix, messages, bytes, interval })
+            .collect();
+        sample.sort_by(|a, b| b.messages.cmp(&a.messages).then(b.bytes.cmp(&a.bytes)));
+        sample
+    }
+
+    /// Counts a publication to the given topic under the prefix made of its first `prefix_levels` levels.
+    /// A prefix that is not counted yet in this sample is counted under the `None` prefix instead if there are already `max_prefixes` of them.
+    pub(super) fn record(&self, topic_name: &str, payload_len: usize, prefix_levels: usize, max_prefixes: usize) {
+        let prefix = match topic_name.match_indices('/').nth(prefix_levels.max(1) - 1) {
+            Some((end, _)) => &topic_name[..end],
+            None => topic_name,
+        };
+
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let counts =
+            if inner.prefixes.contains_key(prefix) {
+                inner.prefixes.get_mut(prefix).expect("prefix was just found")
+            }
+            else if inner.prefixes.len() < max_prefixes {
+                inner.prefixes.entry(prefix.to_owned()).or_default()
+            }
+            else {
+                &mut inner.other
+            };
+        counts.messages += 1;
+        counts.bytes += payload_len as u64;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.0.lock().expect("topic metrics lock is poisoned")
+    }
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Inner {
+            prefixes: Default::default(),
+            other: Default::default(),
+            since: std::time::Instant::now(),
+        }
+    }
+}
+
+/// The publications to a topic prefix during a sample. Returned by [`TopicMetrics::sample`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct TopicPrefixMetrics {
+    /// The topic prefix, or `None` for the publications to the prefixes beyond the cardinality budget
+    pub prefix: Option<String>,
+
+    pub messages: u64,
+
+    /// The total size of the payloads of the publications
+    pub bytes: u64,
+
+    /// How long the sample lasted
+    pub interval: std::time::Duration,
+}
+
+impl TopicPrefixMetrics {
+    /// The number of publications per second during the sample
+    pub fn messages_per_sec(&self) -> f64 {
+        per_sec(self.messages, self.interval)
+    }
+
+    /// The number of payload bytes per second during the sample
+    pub fn bytes_per_sec(&self) -> f64 {
+        per_sec(self.bytes, self.interval)
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn per_sec(count: u64, interval: std::time::Duration) -> f64 {
+    let interval = interval.as_secs_f64();
+    if interval > 0. { count as f64 / interval } else { 0. }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn sample() {
+        let topic_metrics = super::TopicMetrics::default();
+        for &(topic_name, payload_len) in &[
+            ("devices/a/temperature", 10),
+            ("devices/b/temperature", 10),
+            ("devices/b/humidity", 20),
+            ("devices/b", 5),
+            ("devices/c/temperature", 100),
+            ("devices/a/humidity", 10),
+        ] {
+            topic_metrics.record(topic_name, payload_len, 2, 2);
+        }
+
+        let sample: Vec<_> =
+            topic_metrics.sample().into_iter()
+            .map(|metrics| (metrics.prefix, metrics.messages, metrics.bytes))
+            .collect();
+        assert_eq!(sample, [
+            (Some("devices/b".to_owned()), 3, 35),
+            (Some("devices/a".to_owned()), 2, 20),
+            (None, 1, 100),
+        ]);
+
+        // Every sample starts with an empty budget
+        assert!(topic_metrics.sample().is_empty());
+        topic_metrics.record("devices/c/temperature", 100, 2, 2);
+        assert_eq!(topic_metrics.sample()[0].prefix.as_deref(), Some("devices/c"));
+    }
+}
@@ -0,0 +1,623 @@
+/// A standard set of scenarios that a [`Client`](crate::Client) runs against a real server, to check that the server,
+/// and the network between it and the client, support what the client needs.
+///
+/// Every scenario connects its own clients with the given connector, and publishes and subscribes to topics under a prefix
+/// that is unique to the suite, so that scenarios do not see each other's publications. Scenarios clean up the retained publications
+/// and sessions they create.
+///
+/// ```no_run
+/// # async fn run() {
+/// let report = mqtt3::testing::InteropSuite::tcp("broker.example.com:1883").run().await;
+/// assert!(report.is_success(), "{}", report);
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct InteropSuite<C> {
+    connector: C,
+    username: Option<crate::proto::ByteStr>,
+    topic_prefix: String,
+    client_id_prefix: String,
+    timeout: std::time::Duration,
+}
+
+/// A scenario of an [`InteropSuite`]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InteropScenario {
+    /// Publications of every QoS reach a subscriber, with no higher QoS than they were published with.
+    ///
+    /// The subscription is QoS 1, since brokers such as the server of this crate may grant a lower QoS than QoS 2,
+    /// which the client treats as an error.
+    #[allow(clippy::doc_markdown)]
+    QoSFlows,
+
+    /// A retained publication is sent to a client that subscribes after it was published
+    Retained,
+
+    /// The will of a client that goes away without a DISCONNECT is published
+    Will,
+
+    /// A client that loses its connection resumes its session when it reconnects,
+    /// and receives the publications that were published while it was away
+    SessionResumption,
+}
+
+impl InteropScenario {
+    /// All scenarios, in the order that [`InteropSuite::run`] runs them
+    pub const ALL: [InteropScenario; 4] = [
+        InteropScenario::QoSFlows,
+        InteropScenario::Retained,
+        InteropScenario::Will,
+        InteropScenario::SessionResumption,
+    ];
+}
+
+/// The outcome of every scenario run by [`InteropSuite::run`]
+#[derive(Debug)]
+pub struct InteropReport {
+    pub results: Vec<(InteropScenario, Result<(), InteropError>)>,
+}
+
+impl InteropReport {
+    /// Whether every scenario succeeded
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+}
+
+/// The reason an [`InteropScenario`] failed
+#[derive(Debug)]
+pub enum InteropError {
+    Client(crate::Error),
+
+    /// The client ID or a topic made from the prefixes of the suite is too long
+    InvalidPrefix(String),
+
+    Publish(crate::PublishError),
+
+    /// A publication was received that the scenario did not expect
+    UnexpectedPublication(Box<crate::ReceivedPublication>),
+
+    /// The server did not keep the session of a client that reconnected
+    SessionNotResumed,
+
+    Shutdown(crate::ShutdownError),
+
+    /// The server rejected a subscription to the given topic filter
+    SubscriptionRejected(crate::proto::ByteStr),
+
+    /// The scenario timed out waiting for the given thing
+    TimedOut(&'static str),
+
+    UpdateSubscription(crate::UpdateSubscriptionError),
+}
+
+impl InteropSuite<crate::transport::TcpConnector> {
+    /// Creates a suite that connects to the server at the given address, `host:port`, over plain TCP
+    pub fn tcp(address: impl Into<String>) -> Self {
+        InteropSuite::new(crate::transport::TcpConnector::new(address))
+    }
+}
+
+impl<C> InteropSuite<C>
+where
+    C: crate::io::Connector + Clone + Unpin,
+    <C as crate::io::Connector>::PacketStream: Unpin,
+    <C as crate::io::Connector>::PacketSink: Unpin,
+    <C as crate::io::Connector>::Error: std::fmt::Display,
+    <C as crate::io::Connector>::Future: Unpin,
+{
+    /// Creates a suite whose clients connect to the server with clones of the given connector,
+    /// such as a [`crate::transport::TcpConnector`] or a TLS connector of [`crate::transport`]
+    pub fn new(connector: C) -> Self {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+        let run_id = now.subsec_nanos() ^ std::process::id().rotate_left(16);
+
+        InteropSuite {
+            connector,
+            username: None,
+            topic_prefix: format!("mqtt3-interop/{:08x}", run_id),
+            client_id_prefix: format!("mqtt3-{:08x}", run_id),
+            timeout: std::time::Duration::from_secs(10),
+        }
+    }
+
+    /// Sets the username that the clients connect with. Not set by default.
+    #[must_use]
+    pub fn with_username(mut self, username: Option<crate::proto::ByteStr>) -> Self {
+        self.username = username;
+        self
+    }
+
+    /// Sets the prefix of the topics of the scenarios, such as for a server that only authorizes the client to use some topics.
+    /// Defaults to `mqtt3-interop/` followed by an identifier of the suite.
+    #[must_use]
+    pub fn with_topic_prefix(mut self, topic_prefix: impl Into<String>) -> Self {
+        self.topic_prefix = topic_prefix.into();
+        self
+    }
+
+    /// Sets the prefix of the IDs of the clients of the scenarios, which are short enough for any MQTT 3.1.1 server by default.
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_client_id_prefix(mut self, client_id_prefix: impl Into<String>) -> Self {
+        self.client_id_prefix = client_id_prefix.into();
+        self
+    }
+
+    /// Sets how long each step of a scenario may take, such as connecting or receiving a publication. Defaults to 10 seconds.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Runs every scenario, one after the other
+    pub async fn run(&self) -> InteropReport {
+        let mut results = Vec::with_capacity(InteropScenario::ALL.len());
+        for &scenario in &InteropScenario::ALL {
+            let result = self.run_scenario(scenario).await;
+            match &result {
+                Ok(()) => log::info!("interop scenario {:?} succeeded", scenario),
+                Err(err) => log::warn!("interop scenario {:?} failed: {}", scenario, err),
+            }
+            results.push((scenario, result));
+        }
+
+        InteropReport { results }
+    }
+
+    /// Runs the given scenario
+    pub async fn run_scenario(&self, scenario: InteropScenario) -> Result<(), InteropError> {
+        match scenario {
+            InteropScenario::QoSFlows => self.qos_flows().await,
+            InteropScenario::Retained => self.retained().await,
+            InteropScenario::Will => self.will().await,
+            InteropScenario::SessionResumption => self.session_resumption().await,
+        }
+    }
+
+    async fn qos_flows(&self) -> Result<(), InteropError> {
+        let topic_name = self.topic_name("qos")?;
+
+        let mut subscriber = self.peer("qs", None, self.connector.clone())?;
+        subscriber.subscribe(&topic_name, crate::proto::QoS::AtLeastOnce).await?;
+
+        let mut publisher = self.peer("qp", None, self.connector.clone())?;
+        let qos_levels = [crate::proto::QoS::AtMostOnce, crate::proto::QoS::AtLeastOnce, crate::proto::QoS::ExactlyOnce];
+        for &qos in &qos_levels {
+            publisher.publish(publication(topic_name.clone(), qos, false, vec![qos.into()])).await?;
+        }
+
+        for &qos in &qos_levels {
+            let publication = subscriber.next_publication(&topic_name).await?;
+            if publication.qos > qos || publication.payload[..] != [u8::from(qos)] {
+                return Err(InteropError::UnexpectedPublication(Box::new(publication)));
+            }
+        }
+
+        publisher.shut_down().await?;
+        subscriber.shut_down().await
+    }
+
+    async fn retained(&self) -> Result<(), InteropError> {
+        let topic_name = self.topic_name("retained")?;
+
+        let mut publisher = self.peer("rp", None, self.connector.clone())?;
+        publisher.publish(publication(topic_name.clone(), crate::proto::QoS::AtLeastOnce, true, b"retained".to_vec())).await?;
+
+        let mut subscriber = self.peer("rs", None, self.connector.clone())?;
+        subscriber.subscribe(&topic_name, crate::proto::QoS::AtLeastOnce).await?;
+        let received = subscriber.next_publication(&topic_name).await;
+
+        // Clear the retained publication even if the scenario failed
+        publisher.publish(publication(topic_name.clone(), crate::proto::QoS::AtLeastOnce, true, vec![])).await?;
+
+        let received = received?;
+        if !received.retain || received.payload[..] != b"retained"[..] {
+            return Err(InteropError::UnexpectedPublication(Box::new(received)));
+        }
+
+        publisher.shut_down().await?;
+        subscriber.shut_down().await
+    }
+
+    async fn will(&self) -> Result<(), InteropError> {
+        let topic_name = self.topic_name("will")?;
+
+        let mut watcher = self.peer("ww", None, self.connector.clone())?;
+        watcher.subscribe(&topic_name, crate::proto::QoS::AtLeastOnce).await?;
+
+        let will = publication(topic_name.clone(), crate::proto::QoS::AtLeastOnce, false, b"gone".to_vec());
+        let mut client = self.peer("wc", Some(will), self.connector.clone())?;
+        client.connected().await?;
+
+        let mut shutdown_handle = client.client.shutdown_handle().map_err(InteropError::Shutdown)?;
+        client.ended_after(shutdown_handle.shutdown_with_will()).await?;
+
+        let will = watcher.next_publication(&topic_name).await?;
+        if will.payload[..] != b"gone"[..] {
+            return Err(InteropError::UnexpectedPublication(Box::new(will)));
+        }
+
+        watcher.shut_down().await
+    }
+
+    async fn session_resumption(&self) -> Result<(), InteropError> {
+        let topic_name = self.topic_name("session")?;
+
+        let sever: std::sync::Arc<std::sync::atomic::AtomicBool> = Default::default();
+        let mut subscriber = self.peer("ss", None, Severable { inner: self.connector.clone(), sever: sever.clone() })?;
+        subscriber.subscribe(&topic_name, crate::proto::QoS::AtLeastOnce).await?;
+
+        // The subscriber is not polled, so it does not reconnect, until the publication was published
+        sever.store(true, std::sync::atomic::Ordering::Relaxed);
+        subscriber.wait_for("the connection to drop", |event| matches!(event, crate::Event::Disconnected(_)).then_some(())).await?;
+
+        let mut publisher = self.peer("sp", None, self.connector.clone())?;
+        publisher.publish(publication(topic_name.clone(), crate::proto::QoS::AtLeastOnce, false, b"while away".to_vec())).await?;
+
+        let reset_session = subscriber.connected().await?;
+        let result =
+            if reset_session {
+                Err(InteropError::SessionNotResumed)
+            }
+            else {
+                match subscriber.next_publication(&topic_name).await {
+                    Ok(publication) if publication.payload[..] == b"while away"[..] => Ok(()),
+                    Ok(publication) => Err(InteropError::UnexpectedPublication(Box::new(publication))),
+                    Err(err) => Err(err),
+                }
+            };
+
+        // Connect once more with a clean session, so that the server does not keep the session around
+        subscriber.client.restart();
+        subscriber.connected().await?;
+
+        publisher.shut_down().await?;
+        subscriber.shut_down().await?;
+        result
+    }
+
+    fn topic_name(&self, name: &str) -> Result<crate::proto::ByteStr, InteropError> {
+        let topic_name = format!("{}/{}", self.topic_prefix, name);
+        topic_name.parse().map_err(|_| InteropError::InvalidPrefix(topic_name))
+    }
+
+    fn peer<P>(&self, name: &str, will: Option<crate::proto::Publication>, connector: P) -> Result<Peer<P>, InteropError>
+    where
+        P: crate::io::Connector,
+    {
+        let client_id = format!("{}-{}", self.client_id_prefix, name);
+        let client_id = client_id.parse().map_err(|_| InteropError::InvalidPrefix(client_id))?;
+
+        Ok(Peer {
+            client: crate::Client::new(
+                Some(client_id),
+                self.username.clone(),
+                will,
+                connector,
+                std::time::Duration::from_secs(1),
+                std::time::Duration::from_secs(30),
+            ),
+            received: Default::default(),
+            timeout: self.timeout,
+        })
+    }
+}
+
+/// A client of a scenario, with the publications it received while the scenario waited for something else
+struct Peer<C>
+where
+    C: crate::io::Connector,
+{
+    client: crate::Client<C>,
+    received: std::collections::VecDeque<crate::ReceivedPublication>,
+    timeout: std::time::Duration,
+}
+
+impl<C> Peer<C>
+where
+    C: crate::io::Connector + Unpin,
+    <C as crate::io::Connector>::PacketStream: Unpin,
+    <C as crate::io::Connector>::PacketSink: Unpin,
+    <C as crate::io::Connector>::Error: std::fmt::Display,
+    <C as crate::io::Connector>::Future: Unpin,
+{
+    /// Polls the client for its next event, or fails once the deadline has passed
+    async fn next_event(&mut self, deadline: tokio::time::Instant, waiting_for: &'static str) -> Result<crate::Event, InteropError> {
+        match tokio::time::timeout_at(deadline, futures_util::StreamExt::next(&mut self.client)).await {
+            Ok(Some(event)) => event.map_err(InteropError::Client),
+            Ok(None) => Err(InteropError::Client(crate::Error::PolledAfterShutDown)),
+            Err(_) => Err(InteropError::TimedOut(waiting_for)),
+        }
+    }
+
+    /// Polls the client until `f` returns a value for one of its events
+    async fn wait_for<T>(&mut self, waiting_for: &'static str, mut f: impl FnMut(crate::Event) -> Option<T>) -> Result<T, InteropError> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            match self.next_event(deadline, waiting_for).await? {
+                crate::Event::Publication(publication) => self.received.push_back(publication),
+                event => if let Some(value) = f(event) {
+                    return Ok(value);
+                },
+            }
+        }
+    }
+
+    /// Waits for the client to connect, and returns whether the server reset the session
+    async fn connected(&mut self) -> Result<bool, InteropError> {
+        self.wait_for("a CONNACK", |event| match event {
+            crate::Event::NewConnection { reset_session } => Some(reset_session),
+            crate::Event::Connected(connected) => Some(connected.reset_session),
+            _ => None,
+        }).await
+    }
+
+    async fn subscribe(&mut self, topic_filter: &crate::proto::ByteStr, qos: crate::proto::QoS) -> Result<(), InteropError> {
+        self.client.subscribe(crate::proto::SubscribeTo {
+            topic_filter: topic_filter.clone(),
+            qos,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
+        }).map_err(InteropError::UpdateSubscription)?;
+
+        self.wait_for("a SUBACK", |event| match event {
+            crate::Event::SubscriptionUpdates(updates) => updates.into_iter().find_map(|update| match update {
+                crate::SubscriptionUpdateEvent::Subscribe(subscribe_to) if subscribe_to.topic_filter == *topic_filter => Some(Ok(())),
+                crate::SubscriptionUpdateEvent::RejectedByServer(subscribe_to) if subscribe_to.topic_filter == *topic_filter =>
+                    Some(Err(InteropError::SubscriptionRejected(subscribe_to.topic_filter))),
+                _ => None,
+            }),
+            _ => None,
+        }).await?
+    }
+
+    /// Publishes the publication, and polls the client until the server acked it
+    async fn publish(&mut self, publication: crate::proto::Publication) -> Result<(), InteropError> {
+        let Peer { client, received, timeout } = self;
+
+        let mut acked = client.publish(publication);
+        let publish = async {
+            loop {
+                match futures_util::future::select(&mut acked, futures_util::StreamExt::next(&mut *client)).await {
+                    futures_util::future::Either::Left((result, _)) => return result.map_err(InteropError::Publish),
+                    futures_util::future::Either::Right((Some(Ok(crate::Event::Publication(publication))), _)) => received.push_back(publication),
+                    futures_util::future::Either::Right((Some(Ok(_)), _)) => (),
+                    futures_util::future::Either::Right((Some(Err(err)), _)) => return Err(InteropError::Client(err)),
+                    futures_util::future::Either::Right((None, _)) => return Err(InteropError::Client(crate::Error::PolledAfterShutDown)),
+                }
+            }
+        };
+
+        match tokio::time::timeout(*timeout, publish).await {
+            Ok(result) => result,
+            Err(_) => Err(InteropError::TimedOut("the publication to be acked")),
+        }
+    }
+
+    /// Returns the next publication to the given topic, skipping any other publications
+    async fn next_publication(&mut self, topic_name: &crate::proto::ByteStr) -> Result<crate::ReceivedPublication, InteropError> {
+        while let Some(publication) = self.received.pop_front() {
+            if publication.topic_name == *topic_name {
+                return Ok(publication);
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            if let crate::Event::Publication(publication) = self.next_event(deadline, "a publication").await? {
+                if publication.topic_name == *topic_name {
+                    return Ok(publication);
+                }
+            }
+        }
+    }
+
+    /// Polls the client until it has shut down
+    async fn ended(&mut self) -> Result<(), InteropError> {
+        let Peer { client, timeout, .. } = self;
+
+        let end = async {
+            while let Some(event) = futures_util::StreamExt::next(client).await {
+                let _: crate::Event = event.map_err(InteropError::Client)?;
+            }
+            Ok(())
+        };
+
+        match tokio::time::timeout(*timeout, end).await {
+            Ok(result) => result,
+            Err(_) => Err(InteropError::TimedOut("the client to shut down")),
+        }
+    }
+
+    async fn shut_down(&mut self) -> Result<(), InteropError> {
+        let mut shutdown_handle = self.client.shutdown_handle().map_err(InteropError::Shutdown)?;
+        self.ended_after(shutdown_handle.shutdown()).await
+    }
+
+    /// Polls the client until it has shut down because of the given shutdown request.
+    ///
+    /// The request is only sent once the client has received it, so the client is polled while it is sent.
+    async fn ended_after(&mut self, shutdown: impl std::future::Future<Output = Result<(), crate::ShutdownError>>) -> Result<(), InteropError> {
+        let shutdown = async { shutdown.await.map_err(InteropError::Shutdown) };
+        let ((), ()) = futures_util::future::try_join(shutdown, self.ended()).await?;
+        Ok(())
+    }
+}
+
+fn publication(topic_name: crate::proto::ByteStr, qos: crate::proto::QoS, retain: bool, payload: Vec<u8>) -> crate::proto::Publication {
+    crate::proto::Publication {
+        topic_name,
+        qos,
+        retain,
+        payload: payload.into(),
+        user_properties: vec![],
+        response_topic: None,
+        correlation_data: None,
+        message_expiry: None,
+        content_type: None,
+    }
+}
+
+/// A connector whose current connection can be dropped, as if the network failed
+struct Severable<C> {
+    inner: C,
+    sever: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl<C> crate::io::Connector for Severable<C>
+where
+    C: crate::io::Connector,
+    <C as crate::io::Connector>::Future: Unpin,
+{
+    type PacketStream = SeverableStream<<C as crate::io::Connector>::PacketStream>;
+    type PacketSink = <C as crate::io::Connector>::PacketSink;
+    type Error = <C as crate::io::Connector>::Error;
+    type Future = SeverableConnect<<C as crate::io::Connector>::Future>;
+
+    fn connect(&mut self) -> Self::Future {
+        SeverableConnect {
+            inner: self.inner.connect(),
+            sever: self.sever.clone(),
+        }
+    }
+
+    fn redirect(&mut self, server_reference: Option<&str>) -> bool {
+        self.inner.redirect(server_reference)
+    }
+}
+
+struct SeverableConnect<F> {
+    inner: F,
+    sever: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl<F, St, Si, E> std::future::Future for SeverableConnect<F>
+where
+    F: std::future::Future<Output = Result<(St, Si, Option<crate::proto::ByteStr>), E>> + Unpin,
+{
+    type Output = Result<(SeverableStream<St>, Si, Option<crate::proto::ByteStr>), E>;
+
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+        let (stream, sink, password) = match std::pin::Pin::new(&mut self.inner).poll(cx)? {
+            std::task::Poll::Ready(connection) => connection,
+            std::task::Poll::Pending => return std::task::Poll::Pending,
+        };
+
+        let stream = SeverableStream { inner: stream, sever: self.sever.clone() };
+        std::task::Poll::Ready(Ok((stream, sink, password)))
+    }
+}
+
+#[pin_project::pin_project]
+struct SeverableStream<S> {
+    #[pin] inner: S,
+    sever: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl<S> futures_core::Stream for SeverableStream<S> where S: futures_core::Stream {
+    type Item = S::Item;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        let this = self.project();
+
+        if this.sever.swap(false, std::sync::atomic::Ordering::Relaxed) {
+            return std::task::Poll::Ready(None);
+        }
+
+        this.inner.poll_next(cx)
+    }
+}
+
+impl std::fmt::Display for InteropReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (scenario, result) in &self.results {
+            match result {
+                Ok(()) => writeln!(f, "{:?}: ok", scenario)?,
+                Err(err) => writeln!(f, "{:?}: failed: {}", scenario, err)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for InteropError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InteropError::Client(err) => write!(f, "client failed: {}", err),
+            InteropError::InvalidPrefix(s) => write!(f, "{:?} is too long to be a client ID or topic name", s),
+            InteropError::Publish(err) => write!(f, "could not publish: {}", err),
+            InteropError::UnexpectedPublication(publication) => write!(
+                f,
+                "received unexpected publication to {:?} with QoS {:?}, retain {} and payload {:?}",
+                publication.topic_name, publication.qos, publication.retain, publication.payload,
+            ),
+            InteropError::SessionNotResumed => f.write_str("server did not keep the session of the client"),
+            InteropError::Shutdown(err) => write!(f, "could not shut down the client: {}", err),
+            InteropError::SubscriptionRejected(topic_filter) => write!(f, "server rejected the subscription to {:?}", topic_filter),
+            InteropError::TimedOut(waiting_for) => write!(f, "timed out waiting for {}", waiting_for),
+            InteropError::UpdateSubscription(err) => write!(f, "could not subscribe: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for InteropError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        #[allow(clippy::match_same_arms)]
+        match self {
+            InteropError::Client(err) => Some(err),
+            InteropError::Publish(err) => Some(err),
+            InteropError::Shutdown(err) => Some(err),
+            InteropError::UpdateSubscription(err) => Some(err),
+            InteropError::InvalidPrefix(_)
+            | InteropError::UnexpectedPublication(_)
+            | InteropError::SessionNotResumed
+            | InteropError::SubscriptionRejected(_)
+            | InteropError::TimedOut(_) => None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    struct Listener(tokio::net::TcpListener);
+
+    impl crate::io::Listener for Listener {
+        type PacketStream = crate::transport::IoStream<tokio::io::ReadHalf<tokio::net::TcpStream>>;
+        type PacketSink = crate::transport::IoSink<tokio::io::WriteHalf<tokio::net::TcpStream>>;
+
+        fn poll_accept(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink)>> {
+            self.0.poll_accept(cx).map_ok(|(stream, _)| crate::transport::framed(stream))
+        }
+    }
+
+    #[test]
+    fn interop_with_server() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            let server = Box::pin(crate::server::run(Listener(listener)));
+
+            let suite = super::InteropSuite::tcp(address).with_timeout(std::time::Duration::from_secs(5));
+
+            // The server of this crate publishes no wills and does not queue publications for disconnected clients
+            let scenarios = async move {
+                for &scenario in &[super::InteropScenario::QoSFlows, super::InteropScenario::Retained] {
+                    if let Err(err) = suite.run_scenario(scenario).await {
+                        panic!("scenario {:?} failed: {}", scenario, err);
+                    }
+                }
+            };
+
+            if let futures_util::future::Either::Left((result, _)) = futures_util::future::select(server, Box::pin(scenarios)).await {
+                panic!("server stopped: {:?}", result);
+            }
+        });
+    }
+}
@@ -0,0 +1,186 @@
+/// The port of an HTTP proxy whose address does not have one
+const DEFAULT_HTTP_PROXY_PORT: u16 = 8080;
+
+/// The most bytes of the response of the proxy to the CONNECT request that are read, so that a misbehaving proxy cannot
+/// make the client buffer without bound
+const MAX_RESPONSE_LEN: usize = 8 * 1024;
+
+/// An HTTP proxy that a connector tunnels its TCP connection to the server through with an HTTP CONNECT request, for clients
+/// whose network only lets connections out through a web proxy. Set with the `with_proxy` of [`super::TcpConnector`],
+/// and of the TLS and WebSocket connectors.
+///
+/// Like with a [`super::Socks5Proxy`], the host of the server is resolved by the proxy, and TLS and WebSocket handshakes happen
+/// through the tunnel, so that `mqtts` and `wss` connections go through the proxy unchanged.
+#[derive(Clone)]
+pub struct HttpProxy {
+    address: String,
+    credentials: Option<(String, String)>,
+}
+
+impl HttpProxy {
+    /// Creates a proxy at the given address, `host:port`, that does not require authentication.
+    /// The port defaults to 8080.
+    pub fn new(address: impl Into<String>) -> Self {
+        HttpProxy {
+            address: address.into(),
+            credentials: None,
+        }
+    }
+
+    /// Authenticates with the proxy with the given username and password, with HTTP basic authentication
+    #[must_use]
+    pub fn with_credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Connects to the given host and port through the proxy
+    pub(super) async fn connect(&self, host: &str, port: u16) -> std::io::Result<tokio::net::TcpStream> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (proxy_host, proxy_port) = super::split_host_port(&self.address, DEFAULT_HTTP_PROXY_PORT)?;
+        let mut stream = tokio::net::TcpStream::connect((proxy_host, proxy_port)).await?;
+
+        let authority = if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+        let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+        if let Some((username, password)) = &self.credentials {
+            request.push_str("Proxy-Authorization: Basic ");
+            request.push_str(&base64(format!("{}:{}", username, password).as_bytes()));
+            request.push_str("\r\n");
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // The response is read a byte at a time, so that none of the bytes that the server sends after it are read
+        let mut response = vec![];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() == MAX_RESPONSE_LEN {
+                return Err(invalid_response());
+            }
+            response.push(stream.read_u8().await?);
+        }
+
+        let status_line = response.split(|&b| b == b'\r').next().unwrap_or_default();
+        let status_line = std::str::from_utf8(status_line).map_err(|_| invalid_response())?;
+        let mut parts = status_line.splitn(3, ' ');
+        let status: u16 = match (parts.next(), parts.next()) {
+            (Some(version), Some(status)) if version.starts_with("HTTP/1.") => status.parse().map_err(|_| invalid_response())?,
+            _ => return Err(invalid_response()),
+        };
+
+        match status {
+            200..=299 => Ok(stream),
+
+            407 => Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                if self.credentials.is_some() { "HTTP proxy rejected the username and password" } else { "HTTP proxy requires authentication" },
+            )),
+
+            _ => Err(std::io::Error::other(format!("HTTP proxy could not connect to the server: {}", crate::proto::EscapedStr(status_line)))),
+        }
+    }
+}
+
+impl std::fmt::Debug for HttpProxy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The password is not printed
+        f.debug_struct("HttpProxy")
+            .field("address", &self.address)
+            .field("username", &self.credentials.as_ref().map(|(username, _)| username))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encodes the given bytes as padded base64, for the credentials of HTTP basic authentication
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], chunk.get(1).copied().unwrap_or_default(), chunk.get(2).copied().unwrap_or_default()];
+        let indices = [b[0] >> 2, (b[0] & 0x03) << 4 | b[1] >> 4, (b[1] & 0x0f) << 2 | b[2] >> 6, b[2] & 0x3f];
+        for (i, &index) in indices.iter().enumerate() {
+            encoded.push(if i <= chunk.len() { char::from(ALPHABET[usize::from(index)]) } else { '=' });
+        }
+    }
+    encoded
+}
+
+fn invalid_response() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid response from HTTP proxy")
+}
+
+#[cfg(test)]
+mod tests {
+    /// Accepts one client as an HTTP proxy, and returns the CONNECT request, after replying with the given response
+    async fn proxy(listener: tokio::net::TcpListener, response: &[u8]) -> (tokio::net::TcpStream, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut request = vec![];
+        while !request.ends_with(b"\r\n\r\n") {
+            request.push(stream.read_u8().await.unwrap());
+        }
+
+        stream.write_all(response).await.unwrap();
+        (stream, String::from_utf8(request).unwrap())
+    }
+
+    #[test]
+    fn base64() {
+        for &(bytes, expected) in &[
+            (&b""[..], ""),
+            (&b"f"[..], "Zg=="),
+            (&b"fo"[..], "Zm8="),
+            (&b"foo"[..], "Zm9v"),
+            (&b"user:pass"[..], "dXNlcjpwYXNz"),
+            (&b"\xff\xfe"[..], "//4="),
+        ] {
+            assert_eq!(super::base64(bytes), expected);
+        }
+    }
+
+    #[test]
+    fn connect() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = super::HttpProxy::new(listener.local_addr().unwrap().to_string()).with_credentials("user", "pass");
+            let mut connector = crate::transport::TcpConnector::new("broker.example.com").with_proxy(proxy);
+
+            let server = async {
+                let (stream, request) = self::proxy(listener, b"HTTP/1.1 200 Connection established\r\n\r\n").await;
+                assert_eq!(
+                    request,
+                    "CONNECT broker.example.com:1883 HTTP/1.1\r\nHost: broker.example.com:1883\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n",
+                );
+
+                // The MQTT connection goes through the tunnel
+                let (mut stream, _) = crate::transport::framed(stream);
+                futures_util::StreamExt::next(&mut stream).await.unwrap().unwrap()
+            };
+            let client = async {
+                let (_, mut sink, _) = crate::io::Connector::connect(&mut connector).await.unwrap();
+                futures_util::SinkExt::send(&mut sink, crate::proto::Packet::PingReq(crate::proto::PingReq)).await.unwrap();
+            };
+            let (packet, ()) = futures_util::future::join(server, client).await;
+            assert_eq!(packet, crate::proto::Packet::PingReq(crate::proto::PingReq));
+        });
+    }
+
+    #[test]
+    fn connect_rejected() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = super::HttpProxy::new(listener.local_addr().unwrap().to_string());
+
+            let server = self::proxy(listener, b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic\r\n\r\n");
+            let client = proxy.connect("::1", 8883);
+            let ((_, request), result) = futures_util::future::join(server, client).await;
+            assert_eq!(request, "CONNECT [::1]:8883 HTTP/1.1\r\nHost: [::1]:8883\r\n\r\n");
+            assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+        });
+    }
+}
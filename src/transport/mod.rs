@@ -0,0 +1,583 @@
+#[cfg(all(feature = "client", any(feature = "tls-native", feature = "tls-rustls")))]
+mod client_certificate;
+#[cfg(all(feature = "client", any(feature = "tls-native", feature = "tls-rustls")))]
+pub use client_certificate::ClientCertificate;
+
+#[cfg(feature = "client")]
+mod http_proxy;
+#[cfg(feature = "client")]
+pub use http_proxy::HttpProxy;
+
+#[cfg(all(feature = "client", feature = "tls-native"))]
+mod native_tls;
+#[cfg(all(feature = "client", feature = "tls-native"))]
+pub use self::native_tls::NativeTlsConnector;
+
+#[cfg(all(feature = "client", feature = "quic"))]
+mod quic;
+#[cfg(all(feature = "client", feature = "quic"))]
+pub use quic::{QuicConnector, QuicStream};
+
+#[cfg(feature = "client")]
+mod proxy;
+#[cfg(feature = "client")]
+pub use proxy::Proxy;
+
+#[cfg(feature = "client")]
+mod socks5;
+#[cfg(feature = "client")]
+pub use socks5::Socks5Proxy;
+
+#[cfg(feature = "client")]
+mod tcp;
+#[cfg(feature = "client")]
+pub use tcp::TcpConnector;
+
+#[cfg(all(feature = "client", feature = "tls-rustls"))]
+mod tls;
+#[cfg(all(feature = "client", feature = "tls-rustls"))]
+pub use tls::TlsConnector;
+
+#[cfg(all(feature = "server", feature = "tls-rustls"))]
+mod tls_listener;
+#[cfg(all(feature = "server", feature = "tls-rustls"))]
+pub use tls_listener::{ServerCertificate, TlsListener};
+
+#[cfg(all(feature = "client", unix))]
+mod unix;
+#[cfg(all(feature = "client", unix))]
+pub use unix::UnixConnector;
+
+#[cfg(all(feature = "server", unix))]
+mod unix_listener;
+#[cfg(all(feature = "server", unix))]
+pub use unix_listener::UnixListener;
+
+#[cfg(all(any(feature = "client", feature = "server"), feature = "ws"))]
+mod ws;
+#[cfg(all(feature = "client", feature = "ws"))]
+pub use ws::WsConnector;
+#[cfg(all(any(feature = "client", feature = "server"), feature = "ws"))]
+pub use ws::{WsSink, WsStream};
+
+#[cfg(all(feature = "server", feature = "ws"))]
+mod ws_listener;
+#[cfg(all(feature = "server", feature = "ws"))]
+pub use ws_listener::WsListener;
+
+/// A way to open a connection of bytes to the server, such as a TCP stream or a tunnel of a custom protocol, that a
+/// [`TransportConnector`] layers MQTT onto with [`framed`]. With [`crate::Client::with_transport`], a client connects over a transport
+/// that this crate does not provide without implementing [`crate::io::Connector`] and framing packets by hand.
+///
+/// The trait is implemented for every [`FnMut`] that returns a future of a connection, so a factory of connections is a transport too.
+#[cfg(feature = "client")]
+pub trait Transport {
+    /// A connection to the server
+    type Io: tokio::io::AsyncRead + tokio::io::AsyncWrite;
+
+    /// The future of a connection to the server
+    type Future: std::future::Future<Output = std::io::Result<Self::Io>>;
+
+    /// Opens a new connection to the server. Called again every time the client reconnects.
+    fn connect(&mut self) -> Self::Future;
+
+    /// Makes the next connections go to the given server instead, or back to the usual server if `None`.
+    /// See [`crate::io::Connector::redirect`]. The default implementation does not follow redirects.
+    fn redirect(&mut self, _server_reference: Option<&str>) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "client")]
+impl<F, A, Io> Transport for F
+where
+    F: FnMut() -> A,
+    A: std::future::Future<Output = std::io::Result<Io>>,
+    Io: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    type Io = Io;
+    type Future = A;
+
+    fn connect(&mut self) -> Self::Future {
+        (self)()
+    }
+}
+
+/// A [`crate::io::Connector`] that connects to the server over the given [`Transport`], framing its connections with [`framed`]
+#[cfg(feature = "client")]
+#[derive(Clone)]
+pub struct TransportConnector<T> {
+    transport: T,
+    password: Option<crate::proto::ByteStr>,
+}
+
+#[cfg(feature = "client")]
+impl<T> TransportConnector<T> {
+    /// Creates a connector to the server over the given transport
+    pub fn new(transport: T) -> Self {
+        TransportConnector {
+            transport,
+            password: None,
+        }
+    }
+
+    /// Sets the password of the CONNECT packet. Not set by default.
+    #[must_use]
+    pub fn with_password(mut self, password: Option<crate::proto::ByteStr>) -> Self {
+        self.password = password;
+        self
+    }
+}
+
+#[cfg(feature = "client")]
+impl<T> crate::io::Connector for TransportConnector<T> where T: Transport {
+    type PacketStream = IoStream<tokio::io::ReadHalf<T::Io>>;
+    type PacketSink = IoSink<tokio::io::WriteHalf<T::Io>>;
+    type Error = std::io::Error;
+    type Future = TransportFuture<T::Future>;
+
+    fn connect(&mut self) -> Self::Future {
+        TransportFuture {
+            inner: self.transport.connect(),
+            password: self.password.clone(),
+        }
+    }
+
+    fn redirect(&mut self, server_reference: Option<&str>) -> bool {
+        self.transport.redirect(server_reference)
+    }
+}
+
+#[cfg(feature = "client")]
+impl<T> std::fmt::Debug for TransportConnector<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransportConnector").finish_non_exhaustive()
+    }
+}
+
+/// The connection future of a [`TransportConnector`]
+#[cfg(feature = "client")]
+#[pin_project::pin_project]
+pub struct TransportFuture<F> {
+    #[pin] inner: F,
+    password: Option<crate::proto::ByteStr>,
+}
+
+#[cfg(feature = "client")]
+impl<F, Io> std::future::Future for TransportFuture<F>
+where
+    F: std::future::Future<Output = std::io::Result<Io>>,
+    Io: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    type Output = std::io::Result<(IoStream<tokio::io::ReadHalf<Io>>, IoSink<tokio::io::WriteHalf<Io>>, Option<crate::proto::ByteStr>)>;
+
+    fn poll(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Self::Output> {
+        let this = self.project();
+        let io = match this.inner.poll(cx)? {
+            std::task::Poll::Ready(io) => io,
+            std::task::Poll::Pending => return std::task::Poll::Pending,
+        };
+
+        let (stream, sink) = framed(io);
+        std::task::Poll::Ready(Ok((stream, sink, this.password.take())))
+    }
+}
+
+#[cfg(feature = "client")]
+impl<F> std::fmt::Debug for TransportFuture<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TransportFuture").finish_non_exhaustive()
+    }
+}
+
+/// The port that MQTT uses when the address of a server does not have one, such as the server reference of a redirection
+#[cfg(feature = "client")]
+const DEFAULT_TCP_PORT: u16 = 1883;
+
+/// The port that MQTT over TLS uses when the address of a server does not have one
+#[cfg(all(feature = "client", any(feature = "tls-native", feature = "tls-rustls")))]
+const DEFAULT_TLS_PORT: u16 = 8883;
+
+/// The size of the buffer that packets are read into, grown as needed for larger packets
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// The number of bytes of encoded packets that [`IoSink`] buffers before it has to be flushed
+const WRITE_BUFFER_SIZE: usize = 64 * 1024;
+
+#[cfg(feature = "v5")]
+type Decoder = crate::proto::v5::NegotiatedDecoder;
+#[cfg(not(feature = "v5"))]
+type Decoder = crate::proto::PacketDecoder;
+
+/// Splits a connection to the server, such as a TCP or TLS stream, into the packet stream and packet sink that a
+/// [`crate::io::Connector`] returns.
+///
+/// With the `v5` feature, packets are sent and received with the protocol level of the CONNECT that the client sends,
+/// so the client can connect with either MQTT 3.1.1 or MQTT 5.0.
+#[allow(clippy::doc_markdown)]
+pub fn framed<Io>(io: Io) -> (IoStream<tokio::io::ReadHalf<Io>>, IoSink<tokio::io::WriteHalf<Io>>)
+where
+    Io: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+{
+    let (read, write) = tokio::io::split(io);
+
+    let decoder = Decoder::default();
+
+    #[cfg(feature = "v5")]
+    let encoder = decoder.encoder();
+
+    let stream = IoStream {
+        io: read,
+        decoder,
+        buf: bytes::BytesMut::with_capacity(READ_BUFFER_SIZE),
+    };
+
+    let sink = IoSink {
+        io: write,
+        #[cfg(feature = "v5")]
+        encoder,
+        buf: bytes::BytesMut::new(),
+    };
+
+    (stream, sink)
+}
+
+/// The packets received on a connection returned by [`framed`]
+#[pin_project::pin_project]
+pub struct IoStream<Io> {
+    #[pin] io: Io,
+    decoder: Decoder,
+    buf: bytes::BytesMut,
+}
+
+impl<Io> futures_core::Stream for IoStream<Io> where Io: tokio::io::AsyncRead {
+    type Item = Result<crate::proto::Packet, crate::proto::DecodeError>;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        let mut this = self.project();
+
+        loop {
+            #[cfg(feature = "v5")]
+            let packet = this.decoder.decode(this.buf)?;
+            #[cfg(not(feature = "v5"))]
+            let packet = crate::proto::decode(this.decoder, this.buf)?;
+
+            if let Some(packet) = packet {
+                return std::task::Poll::Ready(Some(Ok(packet)));
+            }
+
+            let mut chunk = [0_u8; READ_BUFFER_SIZE];
+            let mut read_buf = tokio::io::ReadBuf::new(&mut chunk);
+            match this.io.as_mut().poll_read(cx, &mut read_buf)? {
+                std::task::Poll::Ready(()) => (),
+                std::task::Poll::Pending => return std::task::Poll::Pending,
+            }
+
+            let read = read_buf.filled();
+            if read.is_empty() {
+                if !this.buf.is_empty() {
+                    return std::task::Poll::Ready(Some(Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())));
+                }
+
+                return std::task::Poll::Ready(None);
+            }
+
+            this.buf.extend_from_slice(read);
+        }
+    }
+}
+
+impl<Io> std::fmt::Debug for IoStream<Io> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IoStream")
+            .field("buffered", &self.buf.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The packets sent on a connection returned by [`framed`].
+///
+/// Packets are buffered until the sink is flushed, or until the buffer is full.
+#[pin_project::pin_project]
+pub struct IoSink<Io> {
+    #[pin] io: Io,
+    #[cfg(feature = "v5")]
+    encoder: crate::proto::v5::NegotiatedEncoder,
+    buf: bytes::BytesMut,
+}
+
+impl<Io> futures_sink::Sink<crate::proto::Packet> for IoSink<Io> where Io: tokio::io::AsyncWrite {
+    type Error = crate::proto::EncodeError;
+
+    fn poll_ready(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        if self.buf.len() < WRITE_BUFFER_SIZE {
+            std::task::Poll::Ready(Ok(()))
+        }
+        else {
+            self.poll_flush(cx)
+        }
+    }
+
+    fn start_send(self: std::pin::Pin<&mut Self>, item: crate::proto::Packet) -> Result<(), Self::Error> {
+        let this = self.project();
+
+        #[cfg(feature = "v5")]
+        this.encoder.encode(item, this.buf)?;
+        #[cfg(not(feature = "v5"))]
+        crate::proto::encode(item, this.buf)?;
+
+        Ok(())
+    }
+
+    fn poll_flush(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        use bytes::Buf;
+
+        let mut this = self.project();
+
+        while !this.buf.is_empty() {
+            match this.io.as_mut().poll_write(cx, &this.buf[..])? {
+                std::task::Poll::Ready(0) => return std::task::Poll::Ready(Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into())),
+                std::task::Poll::Ready(written) => this.buf.advance(written),
+                std::task::Poll::Pending => return std::task::Poll::Pending,
+            }
+        }
+
+        match this.io.poll_flush(cx)? {
+            std::task::Poll::Ready(()) => std::task::Poll::Ready(Ok(())),
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+
+    fn poll_close(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        match self.as_mut().poll_flush(cx)? {
+            std::task::Poll::Ready(()) => (),
+            std::task::Poll::Pending => return std::task::Poll::Pending,
+        }
+
+        match self.project().io.poll_shutdown(cx)? {
+            std::task::Poll::Ready(()) => std::task::Poll::Ready(Ok(())),
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+}
+
+impl<Io> std::fmt::Debug for IoSink<Io> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IoSink")
+            .field("buffered", &self.buf.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Accepts clients over plain TCP, for a server. Pass the listener to the server directly,
+/// or alongside other listeners with [`crate::io::Listeners`].
+#[cfg(feature = "server")]
+impl crate::io::Listener for tokio::net::TcpListener {
+    type PacketStream = IoStream<tokio::io::ReadHalf<tokio::net::TcpStream>>;
+    type PacketSink = IoSink<tokio::io::WriteHalf<tokio::net::TcpStream>>;
+
+    fn poll_accept(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink)>> {
+        tokio::net::TcpListener::poll_accept(self, cx).map_ok(|(stream, _)| framed(stream))
+    }
+}
+
+/// Splits `host:port`, or `[ip]:port` for an IPv6 address, into the host and the port. The port is optional.
+#[cfg(feature = "client")]
+fn split_host_port(address: &str, default_port: u16) -> std::io::Result<(&str, u16)> {
+    let (host, port) =
+        if let Some(bracketed) = address.strip_prefix('[') {
+            match bracketed.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, Some(port.strip_prefix(':').ok_or_else(|| invalid_address(address))?)),
+                None => return Err(invalid_address(address)),
+            }
+        }
+        else {
+            match address.rsplit_once(':') {
+                // An IPv6 address without brackets has no port
+                Some((host, port)) if !host.contains(':') => (host, Some(port)),
+                _ => (address, None),
+            }
+        };
+
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid_address(address))?,
+        None => default_port,
+    };
+
+    Ok((host, port))
+}
+
+#[cfg(feature = "client")]
+fn invalid_address(address: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid server address {:?}", address))
+}
+
+/// Parses the PEM files of a certificate chain and its private key, such as the ones of the client certificate of a [`TlsConnector`]
+/// or of a [`ServerCertificate`], for rustls
+#[cfg(feature = "tls-rustls")]
+fn certificate_chain_and_key_from_pem(
+    certificate_chain: &[u8],
+    private_key: &[u8],
+    name: &str,
+) -> std::io::Result<(Vec<tokio_rustls::rustls::Certificate>, tokio_rustls::rustls::PrivateKey)> {
+    let certificate_chain: Vec<_> =
+        rustls_pemfile::certs(&mut &*certificate_chain)?
+        .into_iter()
+        .map(tokio_rustls::rustls::Certificate)
+        .collect();
+    if certificate_chain.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("PEM file of the {} does not contain any certificates", name)));
+    }
+
+    let mut private_key = private_key;
+    let private_key = loop {
+        match rustls_pemfile::read_one(&mut private_key)? {
+            Some(rustls_pemfile::Item::RSAKey(key) | rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::ECKey(key)) =>
+                break tokio_rustls::rustls::PrivateKey(key),
+            Some(_) => (),
+            None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "PEM file of the private key does not contain any private keys")),
+        }
+    };
+
+    Ok((certificate_chain, private_key))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn framed() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let (client, server) = tokio::io::duplex(16);
+            let (_, mut client_sink) = super::framed(client);
+            let (mut server_stream, _) = super::framed(server);
+
+            let packets = vec![
+                crate::proto::Packet::PingReq(crate::proto::PingReq),
+                crate::proto::Packet::Publish(crate::proto::Publish {
+                    packet_identifier_dup_qos: crate::proto::PacketIdentifierDupQoS::AtMostOnce,
+                    retain: false,
+                    topic_name: "topic".parse().unwrap(),
+                    payload: bytes::Bytes::from(vec![0x42; 100]),
+                    user_properties: vec![],
+                    subscription_identifiers: vec![],
+                    response_topic: None,
+                    correlation_data: None,
+                    message_expiry: None,
+                    content_type: None,
+                }),
+                crate::proto::Packet::PingReq(crate::proto::PingReq),
+            ];
+
+            // The packets are larger than the pipe, so they are sent and received concurrently
+            let send = async {
+                for packet in packets.clone() {
+                    futures_util::SinkExt::feed(&mut client_sink, packet).await.unwrap();
+                }
+                futures_util::SinkExt::close(&mut client_sink).await.unwrap();
+            };
+            let receive = async {
+                let mut received = vec![];
+                while let Some(packet) = futures_util::StreamExt::next(&mut server_stream).await {
+                    received.push(packet.unwrap());
+                }
+                received
+            };
+            let ((), received) = futures_util::future::join(send, receive).await;
+            assert_eq!(received, packets);
+        });
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn transport_connector() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let (client, server) = tokio::io::duplex(1024);
+
+            // A factory of connections is a transport, here one that can connect once
+            let mut client = Some(client);
+            let transport = move || futures_util::future::ready(client.take().ok_or_else(|| std::io::Error::from(std::io::ErrorKind::ConnectionRefused)));
+            let mut connector = super::TransportConnector::new(transport).with_password(Some("password".parse().unwrap()));
+            assert!(!crate::io::Connector::redirect(&mut connector, Some("other.example.com")));
+
+            let (_, mut sink, password) = crate::io::Connector::connect(&mut connector).await.unwrap();
+            assert_eq!(password, Some("password".parse().unwrap()));
+            futures_util::SinkExt::send(&mut sink, crate::proto::Packet::PingReq(crate::proto::PingReq)).await.unwrap();
+
+            let (mut server_stream, _) = super::framed(server);
+            let packet = futures_util::StreamExt::next(&mut server_stream).await.unwrap().unwrap();
+            assert_eq!(packet, crate::proto::Packet::PingReq(crate::proto::PingReq));
+
+            let err = crate::io::Connector::connect(&mut connector).await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::ConnectionRefused);
+        });
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn client_with_transport() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async {
+            let (client, server) = tokio::io::duplex(1024);
+
+            let mut client = Some(client);
+            let transport = move || futures_util::future::ready(client.take().ok_or_else(|| std::io::Error::from(std::io::ErrorKind::ConnectionRefused)));
+            let mut client = crate::Client::with_transport(
+                Some("client".parse().unwrap()),
+                Some("username".parse().unwrap()),
+                Some("password".parse().unwrap()),
+                None,
+                transport,
+                std::time::Duration::from_secs(1),
+                std::time::Duration::from_secs(30),
+            );
+
+            let server = async {
+                let (mut server_stream, mut server_sink) = super::framed(server);
+                match futures_util::StreamExt::next(&mut server_stream).await {
+                    Some(Ok(crate::proto::Packet::Connect(connect))) => {
+                        assert_eq!(connect.username, Some("username".parse().unwrap()));
+                        assert_eq!(connect.password, Some("password".parse().unwrap()));
+                    },
+                    packet => panic!("expected CONNECT, got {:?}", packet),
+                }
+
+                let conn_ack = crate::proto::ConnAck {
+                    session_present: false,
+                    return_code: crate::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
+                };
+                futures_util::SinkExt::send(&mut server_sink, crate::proto::Packet::ConnAck(conn_ack)).await.unwrap();
+                (server_stream, server_sink)
+            };
+
+            let (event, _server) = futures_util::future::join(futures_util::StreamExt::next(&mut client), server).await;
+            match event {
+                Some(Ok(crate::Event::NewConnection { reset_session: true })) => (),
+                event => panic!("expected new connection, got {:?}", event),
+            }
+        });
+    }
+
+    #[cfg(feature = "client")]
+    #[test]
+    fn split_host_port() {
+        assert_eq!(super::split_host_port("example.com:1883", 8883).unwrap(), ("example.com", 1883));
+        assert_eq!(super::split_host_port("example.com", 8883).unwrap(), ("example.com", 8883));
+        assert_eq!(super::split_host_port("[::1]:1883", 8883).unwrap(), ("::1", 1883));
+        assert_eq!(super::split_host_port("[::1]", 8883).unwrap(), ("::1", 8883));
+        assert_eq!(super::split_host_port("::1", 8883).unwrap(), ("::1", 8883));
+        assert!(super::split_host_port("example.com:mqtt", 8883).is_err());
+    }
+}
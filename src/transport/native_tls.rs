@@ -0,0 +1,185 @@
+/// A [`crate::io::Connector`] that connects to the server over TLS with the TLS implementation of the OS,
+/// which is SChannel on Windows, Security.framework on macOS and OpenSSL elsewhere.
+///
+/// Unlike [`super::TlsConnector`], the certificate of the server is verified against the certificate store of the OS,
+/// such as the corporate root certificates deployed to Windows and macOS machines. [`NativeTlsConnector::with_root_certificate_pem`]
+/// trusts more root certificates. The client authenticates with a certificate of its own, for servers that require mutual TLS,
+/// with [`NativeTlsConnector::with_client_certificate`] or [`NativeTlsConnector::with_client_certificate_source`].
+/// A [`NativeTlsConnector::with_connector`] replaces all of this.
+///
+/// The certificate of the server is verified against the host of the address unless [`NativeTlsConnector::with_server_name`] overrides it.
+/// Like [`super::TlsConnector`], the connector follows the redirections of the server.
+#[allow(clippy::doc_markdown)]
+#[derive(Clone)]
+pub struct NativeTlsConnector {
+    address: String,
+    server_name: Option<String>,
+    root_certificates: Vec<tokio_native_tls::native_tls::Certificate>,
+    client_certificate: Option<super::client_certificate::ClientCertificateSource>,
+    connector: Option<tokio_native_tls::TlsConnector>,
+    proxy: Option<super::Proxy>,
+    password: Option<crate::proto::ByteStr>,
+    server_reference: Option<String>,
+}
+
+impl NativeTlsConnector {
+    /// Creates a connector to the server at the given address, `host:port`, that trusts the root certificates of the OS
+    pub fn new(address: impl Into<String>) -> Self {
+        NativeTlsConnector {
+            address: address.into(),
+            server_name: None,
+            root_certificates: vec![],
+            client_certificate: None,
+            connector: None,
+            proxy: None,
+            password: None,
+            server_reference: None,
+        }
+    }
+
+    /// Also trusts the first certificate of the given PEM file, such as the CA certificate that signed the certificate of the server
+    pub fn with_root_certificate_pem(mut self, pem: &[u8]) -> std::io::Result<Self> {
+        let certificate = tokio_native_tls::native_tls::Certificate::from_pem(pem)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        self.root_certificates.push(certificate);
+        Ok(self)
+    }
+
+    /// Sets the name that the certificate of the server is verified against and that is sent with SNI.
+    /// Defaults to the host of the address.
+    #[must_use]
+    pub fn with_server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    /// Authenticates the client with the given certificate, either PEM files or a PKCS#12 archive
+    #[allow(clippy::doc_markdown)]
+    #[must_use]
+    pub fn with_client_certificate(self, client_certificate: super::ClientCertificate) -> Self {
+        self.with_client_certificate_source(move || Ok(client_certificate.clone()))
+    }
+
+    /// Authenticates the client with the certificate returned by `source`, which is called every time the connector connects.
+    ///
+    /// This lets a long-running client pick up a renewed certificate when it reconnects, such as with
+    /// `|| ClientCertificate::from_pkcs12_file("client.p12", password)`. If `source` fails, so does the connection,
+    /// and the client retries it like any other failed connection.
+    #[must_use]
+    pub fn with_client_certificate_source(
+        mut self,
+        source: impl Fn() -> std::io::Result<super::ClientCertificate> + Send + Sync + 'static,
+    ) -> Self {
+        self.client_certificate = Some(std::sync::Arc::new(source));
+        self
+    }
+
+    /// Sets the TLS connector of the client, instead of the one that trusts the root certificates of the OS
+    /// and authenticates with the certificate of the client
+    #[must_use]
+    pub fn with_connector(mut self, connector: tokio_native_tls::native_tls::TlsConnector) -> Self {
+        self.connector = Some(connector.into());
+        self
+    }
+
+    /// Tunnels the TCP connection to the server through the given proxy, a [`super::Socks5Proxy`] or an [`super::HttpProxy`].
+    /// Not set by default.
+    #[must_use]
+    pub fn with_proxy(mut self, proxy: impl Into<super::Proxy>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Sets the password of the CONNECT packet. Not set by default.
+    #[must_use]
+    pub fn with_password(mut self, password: Option<crate::proto::ByteStr>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// The server that the client was redirected to, if it is not connecting to the usual server
+    pub fn server_reference(&self) -> Option<&str> {
+        self.server_reference.as_deref()
+    }
+
+    /// Returns the TLS connector of the next connection. It is only built once, unless it has a certificate of the client
+    /// that may have been renewed since the last connection.
+    fn tls_connector(&mut self) -> std::io::Result<tokio_native_tls::TlsConnector> {
+        if let Some(connector) = &self.connector {
+            return Ok(connector.clone());
+        }
+
+        let mut builder = tokio_native_tls::native_tls::TlsConnector::builder();
+        for certificate in &self.root_certificates {
+            builder.add_root_certificate(certificate.clone());
+        }
+
+        if let Some(client_certificate) = &self.client_certificate {
+            builder.identity(identity(client_certificate()?)?);
+            let connector = builder.build().map_err(std::io::Error::other)?;
+            return Ok(connector.into());
+        }
+
+        let connector: tokio_native_tls::TlsConnector = builder.build().map_err(std::io::Error::other)?.into();
+        self.connector = Some(connector.clone());
+        Ok(connector)
+    }
+}
+
+impl crate::io::Connector for NativeTlsConnector {
+    type PacketStream = super::IoStream<tokio::io::ReadHalf<tokio_native_tls::TlsStream<tokio::net::TcpStream>>>;
+    type PacketSink = super::IoSink<tokio::io::WriteHalf<tokio_native_tls::TlsStream<tokio::net::TcpStream>>>;
+    type Error = std::io::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = std::io::Result<(Self::PacketStream, Self::PacketSink, Option<crate::proto::ByteStr>)>> + Send>>;
+
+    fn connect(&mut self) -> Self::Future {
+        let connector = self.tls_connector();
+
+        let (address, server_name) = match &self.server_reference {
+            Some(server_reference) => (server_reference.clone(), None),
+            None => (self.address.clone(), self.server_name.clone()),
+        };
+        let proxy = self.proxy.clone();
+        let password = self.password.clone();
+
+        Box::pin(async move {
+            let connector = connector?;
+            let (host, port) = super::split_host_port(&address, super::DEFAULT_TLS_PORT)?;
+            let server_name = server_name.as_deref().unwrap_or(host);
+
+            let stream = super::proxy::connect_tcp(proxy.as_ref(), host, port).await?;
+            let stream = connector.connect(server_name, stream).await
+                .map_err(std::io::Error::other)?;
+            let (stream, sink) = super::framed(stream);
+            Ok((stream, sink, password))
+        })
+    }
+
+    fn redirect(&mut self, server_reference: Option<&str>) -> bool {
+        self.server_reference = server_reference.map(ToOwned::to_owned);
+        true
+    }
+}
+
+impl std::fmt::Debug for NativeTlsConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NativeTlsConnector")
+            .field("address", &self.address)
+            .field("server_name", &self.server_name)
+            .field("root_certificates", &self.root_certificates.len())
+            .field("client_certificate", &self.client_certificate.is_some())
+            .field("server_reference", &self.server_reference)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Loads the certificate chain and private key of the client
+fn identity(client_certificate: super::ClientCertificate) -> std::io::Result<tokio_native_tls::native_tls::Identity> {
+    let identity = match client_certificate {
+        super::ClientCertificate::Pem { certificate_chain, private_key } =>
+            tokio_native_tls::native_tls::Identity::from_pkcs8(&certificate_chain, &private_key),
+        super::ClientCertificate::Pkcs12 { archive, password } =>
+            tokio_native_tls::native_tls::Identity::from_pkcs12(&archive, &password),
+    };
+    identity.map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
@@ -0,0 +1,39 @@
+/// A proxy that a connector tunnels its TCP connection to the server through, set with the `with_proxy` of [`super::TcpConnector`],
+/// and of the TLS and WebSocket connectors.
+///
+/// Either kind of proxy can be passed to `with_proxy` directly, since it converts into a `Proxy`.
+#[derive(Clone, Debug)]
+pub enum Proxy {
+    Socks5(super::Socks5Proxy),
+    Http(super::HttpProxy),
+}
+
+impl Proxy {
+    /// Connects to the given host and port through the proxy
+    async fn connect(&self, host: &str, port: u16) -> std::io::Result<tokio::net::TcpStream> {
+        match self {
+            Proxy::Socks5(proxy) => proxy.connect(host, port).await,
+            Proxy::Http(proxy) => proxy.connect(host, port).await,
+        }
+    }
+}
+
+impl From<super::Socks5Proxy> for Proxy {
+    fn from(proxy: super::Socks5Proxy) -> Self {
+        Proxy::Socks5(proxy)
+    }
+}
+
+impl From<super::HttpProxy> for Proxy {
+    fn from(proxy: super::HttpProxy) -> Self {
+        Proxy::Http(proxy)
+    }
+}
+
+/// Connects to the given host and port over TCP, through the proxy if there is one
+pub(super) async fn connect_tcp(proxy: Option<&Proxy>, host: &str, port: u16) -> std::io::Result<tokio::net::TcpStream> {
+    match proxy {
+        Some(proxy) => proxy.connect(host, port).await,
+        None => tokio::net::TcpStream::connect((host, port)).await,
+    }
+}
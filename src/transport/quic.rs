@@ -0,0 +1,194 @@
+/// The port that MQTT over QUIC uses when the address of a server does not have one, as there is no registered port for it
+const DEFAULT_QUIC_PORT: u16 = 14567;
+
+/// The ALPN protocol that the connector negotiates with the server
+const ALPN_PROTOCOL: &[u8] = b"mqtt";
+
+/// A [`crate::io::Connector`] that connects to the server with MQTT over a QUIC stream, with quinn.
+///
+/// Each connection is a QUIC connection with a single bidirectional stream that carries the packets. All the connections of a connector
+/// share one UDP socket, so reconnecting does not have to open a new socket, and the TLS session of the previous connection is resumed.
+/// Since a QUIC connection is identified by its connection IDs rather than by the addresses of its socket, it survives the address
+/// of the client changing, such as a mobile device moving between networks, without the client noticing a disconnection.
+///
+/// The certificate of the server is verified with the root certificates, client certificate and server name of the [`super::TlsConnector`]
+/// given with [`QuicConnector::with_tls`]. Its address is not used. The connector negotiates the `mqtt` ALPN protocol.
+///
+/// The connector follows the redirections of the server to the host and port of the server reference, like [`super::TlsConnector`].
+#[derive(Clone)]
+pub struct QuicConnector {
+    address: String,
+    tls: super::TlsConnector,
+    endpoint: std::sync::Arc<std::sync::Mutex<Option<quinn::Endpoint>>>,
+    password: Option<crate::proto::ByteStr>,
+    server_reference: Option<String>,
+}
+
+impl QuicConnector {
+    /// Creates a connector to the server at the given address, `host:port`, that trusts no root certificates yet
+    pub fn new(address: impl Into<String>) -> Self {
+        QuicConnector {
+            address: address.into(),
+            tls: super::TlsConnector::new(String::new()),
+            endpoint: Default::default(),
+            password: None,
+            server_reference: None,
+        }
+    }
+
+    /// Verifies the server, and authenticates the client, with the root certificates, client certificate and server name of the given connector
+    #[must_use]
+    pub fn with_tls(mut self, tls: super::TlsConnector) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Sets the password of the CONNECT packet. Not set by default.
+    #[must_use]
+    pub fn with_password(mut self, password: Option<crate::proto::ByteStr>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// The server that the client was redirected to, if it is not connecting to the usual server
+    pub fn server_reference(&self) -> Option<&str> {
+        self.server_reference.as_deref()
+    }
+
+    /// Returns the TLS configuration of the next connection, which is the one of the TLS connector with the ALPN protocol of MQTT
+    fn tls_config(&mut self) -> std::io::Result<tokio_rustls::rustls::ClientConfig> {
+        let mut config = (*self.tls.client_config()?).clone();
+        config.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        Ok(config)
+    }
+}
+
+impl crate::io::Connector for QuicConnector {
+    type PacketStream = super::IoStream<tokio::io::ReadHalf<QuicStream>>;
+    type PacketSink = super::IoSink<tokio::io::WriteHalf<QuicStream>>;
+    type Error = std::io::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = std::io::Result<(Self::PacketStream, Self::PacketSink, Option<crate::proto::ByteStr>)>> + Send>>;
+
+    fn connect(&mut self) -> Self::Future {
+        let config = self.tls_config().map(|config| quinn::ClientConfig::new(std::sync::Arc::new(config)));
+
+        let (address, server_name) = match &self.server_reference {
+            Some(server_reference) => (server_reference.clone(), None),
+            None => (self.address.clone(), self.tls.server_name().map(ToOwned::to_owned)),
+        };
+        let endpoint = self.endpoint.clone();
+        let password = self.password.clone();
+
+        Box::pin(async move {
+            let config = config?;
+            let (host, port) = super::split_host_port(&address, DEFAULT_QUIC_PORT)?;
+            let server_name = server_name.as_deref().unwrap_or(host);
+
+            let remote_address = tokio::net::lookup_host((host, port)).await?.next()
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{:?} did not resolve to any address", host)))?;
+
+            // The endpoint is created by the first connection, since it needs the runtime
+            let endpoint = {
+                let mut endpoint = endpoint.lock().expect("QUIC endpoint mutex is poisoned");
+                match &*endpoint {
+                    Some(endpoint) => endpoint.clone(),
+                    None => endpoint.insert(bind()?).clone(),
+                }
+            };
+
+            let connection =
+                endpoint.connect_with(config, remote_address, server_name)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?
+                .await
+                .map_err(std::io::Error::other)?;
+            let (send, recv) = connection.open_bi().await.map_err(std::io::Error::other)?;
+
+            let (stream, sink) = super::framed(QuicStream { send, recv });
+            Ok((stream, sink, password))
+        })
+    }
+
+    fn redirect(&mut self, server_reference: Option<&str>) -> bool {
+        self.server_reference = server_reference.map(ToOwned::to_owned);
+        true
+    }
+}
+
+impl std::fmt::Debug for QuicConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuicConnector")
+            .field("address", &self.address)
+            .field("tls", &self.tls)
+            .field("server_reference", &self.server_reference)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Binds the UDP socket of the connections, dual-stack if the host supports IPv6 so that it can connect to both IPv4 and IPv6 addresses
+fn bind() -> std::io::Result<quinn::Endpoint> {
+    quinn::Endpoint::client((std::net::Ipv6Addr::UNSPECIFIED, 0).into())
+        .or_else(|_| quinn::Endpoint::client((std::net::Ipv4Addr::UNSPECIFIED, 0).into()))
+}
+
+/// The bidirectional QUIC stream that a connection of a [`QuicConnector`] carries the packets on
+pub struct QuicStream {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+}
+
+impl tokio::io::AsyncRead for QuicStream {
+    fn poll_read(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.get_mut().recv).poll_read(cx, buf)
+    }
+}
+
+impl tokio::io::AsyncWrite for QuicStream {
+    fn poll_write(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<std::io::Result<usize>> {
+        std::pin::Pin::new(&mut self.get_mut().send).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.get_mut().send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        std::pin::Pin::new(&mut self.get_mut().send).poll_shutdown(cx)
+    }
+}
+
+impl std::fmt::Debug for QuicStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuicStream")
+            .field("id", &self.send.id())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn tls_config() {
+        let tls_config =
+            tokio_rustls::rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(tokio_rustls::rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        let mut connector =
+            super::QuicConnector::new("localhost")
+            .with_tls(crate::transport::TlsConnector::new("").with_client_config(std::sync::Arc::new(tls_config)));
+        assert_eq!(connector.tls_config().unwrap().alpn_protocols, vec![b"mqtt".to_vec()]);
+
+        // The configuration of the TLS connector is not changed
+        assert!(connector.tls.client_config().unwrap().alpn_protocols.is_empty());
+    }
+
+    #[test]
+    fn connect_unresolved() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+
+        let mut connector = super::QuicConnector::new("[::1]:mqtt");
+        let err = runtime.block_on(crate::io::Connector::connect(&mut connector)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(connector.endpoint.lock().unwrap().is_none());
+    }
+}
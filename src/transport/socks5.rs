@@ -0,0 +1,237 @@
+/// The port of a SOCKS5 proxy whose address does not have one
+const DEFAULT_SOCKS5_PORT: u16 = 1080;
+
+const VERSION: u8 = 0x05;
+const METHOD_NO_AUTHENTICATION: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xff;
+const USERNAME_PASSWORD_VERSION: u8 = 0x01;
+const COMMAND_CONNECT: u8 = 0x01;
+const ADDRESS_TYPE_IPV4: u8 = 0x01;
+const ADDRESS_TYPE_DOMAIN_NAME: u8 = 0x03;
+const ADDRESS_TYPE_IPV6: u8 = 0x04;
+
+/// A SOCKS5 proxy that a connector tunnels its TCP connection to the server through, for clients that can only reach the server
+/// through an egress proxy. Set with the `with_proxy` of [`super::TcpConnector`], and of the TLS and WebSocket connectors.
+///
+/// The host of the server is resolved by the proxy, so that the client does not need to resolve the names of outside hosts itself.
+/// TLS and WebSocket handshakes happen through the tunnel, after the proxy has connected to the server.
+#[derive(Clone)]
+pub struct Socks5Proxy {
+    address: String,
+    credentials: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    /// Creates a proxy at the given address, `host:port`, that does not require authentication.
+    /// The port defaults to 1080.
+    pub fn new(address: impl Into<String>) -> Self {
+        Socks5Proxy {
+            address: address.into(),
+            credentials: None,
+        }
+    }
+
+    /// Authenticates with the proxy with the given username and password
+    #[must_use]
+    pub fn with_credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Connects to the given host and port through the proxy
+    pub(super) async fn connect(&self, host: &str, port: u16) -> std::io::Result<tokio::net::TcpStream> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (proxy_host, proxy_port) = super::split_host_port(&self.address, DEFAULT_SOCKS5_PORT)?;
+        let mut stream = tokio::net::TcpStream::connect((proxy_host, proxy_port)).await?;
+
+        // Greeting
+        let methods: &[u8] =
+            if self.credentials.is_some() { &[METHOD_NO_AUTHENTICATION, METHOD_USERNAME_PASSWORD] } else { &[METHOD_NO_AUTHENTICATION] };
+        let mut greeting = vec![VERSION];
+        put_length_prefixed(&mut greeting, methods, "methods")?;
+        stream.write_all(&greeting).await?;
+
+        let mut reply = [0_u8; 2];
+        stream.read_exact(&mut reply).await?;
+        match reply {
+            [VERSION, METHOD_NO_AUTHENTICATION] => (),
+
+            [VERSION, METHOD_USERNAME_PASSWORD] => match &self.credentials {
+                Some((username, password)) => {
+                    let mut request = vec![USERNAME_PASSWORD_VERSION];
+                    put_length_prefixed(&mut request, username.as_bytes(), "username")?;
+                    put_length_prefixed(&mut request, password.as_bytes(), "password")?;
+                    stream.write_all(&request).await?;
+
+                    let mut reply = [0_u8; 2];
+                    stream.read_exact(&mut reply).await?;
+                    if reply[1] != 0x00 {
+                        return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "SOCKS5 proxy rejected the username and password"));
+                    }
+                },
+
+                None => return Err(invalid_reply()),
+            },
+
+            [VERSION, METHOD_NONE_ACCEPTABLE] =>
+                return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "SOCKS5 proxy requires an authentication method that is not supported")),
+
+            _ => return Err(invalid_reply()),
+        }
+
+        // Connect request
+        let mut request = vec![VERSION, COMMAND_CONNECT, 0x00];
+        match host.parse() {
+            Ok(std::net::IpAddr::V4(ip)) => {
+                request.push(ADDRESS_TYPE_IPV4);
+                request.extend_from_slice(&ip.octets());
+            },
+
+            Ok(std::net::IpAddr::V6(ip)) => {
+                request.push(ADDRESS_TYPE_IPV6);
+                request.extend_from_slice(&ip.octets());
+            },
+
+            Err(_) => {
+                request.push(ADDRESS_TYPE_DOMAIN_NAME);
+                put_length_prefixed(&mut request, host.as_bytes(), "host name")?;
+            },
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0_u8; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != VERSION {
+            return Err(invalid_reply());
+        }
+        if reply[1] != 0x00 {
+            return Err(connect_error(reply[1]));
+        }
+
+        // The address that the proxy bound for the connection, which the client does not need
+        let bound_address_len = match reply[3] {
+            ADDRESS_TYPE_IPV4 => 4,
+            ADDRESS_TYPE_IPV6 => 16,
+            ADDRESS_TYPE_DOMAIN_NAME => usize::from(stream.read_u8().await?),
+            _ => return Err(invalid_reply()),
+        };
+        let mut bound_address = vec![0_u8; bound_address_len + 2];
+        stream.read_exact(&mut bound_address).await?;
+
+        Ok(stream)
+    }
+}
+
+impl std::fmt::Debug for Socks5Proxy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The password is not printed
+        f.debug_struct("Socks5Proxy")
+            .field("address", &self.address)
+            .field("username", &self.credentials.as_ref().map(|(username, _)| username))
+            .finish_non_exhaustive()
+    }
+}
+
+fn put_length_prefixed(dst: &mut Vec<u8>, value: &[u8], name: &str) -> std::io::Result<()> {
+    let len = std::convert::TryFrom::try_from(value.len())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is too long for a SOCKS5 proxy", name)))?;
+    dst.push(len);
+    dst.extend_from_slice(value);
+    Ok(())
+}
+
+fn invalid_reply() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid reply from SOCKS5 proxy")
+}
+
+fn connect_error(reply: u8) -> std::io::Error {
+    let (kind, reason) = match reply {
+        0x02 => (std::io::ErrorKind::PermissionDenied, "connection not allowed by ruleset"),
+        0x03 => (std::io::ErrorKind::Other, "network unreachable"),
+        0x04 => (std::io::ErrorKind::Other, "host unreachable"),
+        0x05 => (std::io::ErrorKind::ConnectionRefused, "connection refused"),
+        0x06 => (std::io::ErrorKind::TimedOut, "TTL expired"),
+        0x07 => (std::io::ErrorKind::Unsupported, "command not supported"),
+        0x08 => (std::io::ErrorKind::Unsupported, "address type not supported"),
+        _ => (std::io::ErrorKind::Other, "general failure"),
+    };
+    std::io::Error::new(kind, format!("SOCKS5 proxy could not connect to the server: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    /// Accepts one client as a SOCKS5 proxy that requires the username and password `user` and `pass`,
+    /// and returns the connect request, after replying with the given reply code
+    async fn proxy(listener: tokio::net::TcpListener, reply: u8) -> (tokio::net::TcpStream, Vec<u8>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+
+        let mut greeting = [0_u8; 4];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [0x05, 0x02, 0x00, 0x02]);
+        stream.write_all(&[0x05, 0x02]).await.unwrap();
+
+        let mut authentication = [0_u8; 11];
+        stream.read_exact(&mut authentication).await.unwrap();
+        assert_eq!(&authentication, b"\x01\x04user\x04pass");
+        stream.write_all(&[0x01, 0x00]).await.unwrap();
+
+        let mut request = [0_u8; 5];
+        stream.read_exact(&mut request).await.unwrap();
+        let mut request = request.to_vec();
+        let rest = match request[3] {
+            0x01 => 3 + 2,
+            0x03 => usize::from(request[4]) + 2,
+            _ => 15 + 2,
+        };
+        request.resize(5 + rest, 0);
+        stream.read_exact(&mut request[5..]).await.unwrap();
+
+        stream.write_all(&[0x05, reply, 0x00, 0x01, 127, 0, 0, 1, 0x04, 0x38]).await.unwrap();
+        (stream, request)
+    }
+
+    #[test]
+    fn connect() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = super::Socks5Proxy::new(listener.local_addr().unwrap().to_string()).with_credentials("user", "pass");
+            let mut connector = crate::transport::TcpConnector::new("broker.example.com").with_proxy(proxy);
+
+            let server = async {
+                let (stream, request) = self::proxy(listener, 0x00).await;
+                assert_eq!(request, b"\x05\x01\x00\x03\x12broker.example.com\x07\x5b");
+
+                // The MQTT connection goes through the tunnel
+                let (mut stream, _) = crate::transport::framed(stream);
+                futures_util::StreamExt::next(&mut stream).await.unwrap().unwrap()
+            };
+            let client = async {
+                let (_, mut sink, _) = crate::io::Connector::connect(&mut connector).await.unwrap();
+                futures_util::SinkExt::send(&mut sink, crate::proto::Packet::PingReq(crate::proto::PingReq)).await.unwrap();
+            };
+            let (packet, ()) = futures_util::future::join(server, client).await;
+            assert_eq!(packet, crate::proto::Packet::PingReq(crate::proto::PingReq));
+        });
+    }
+
+    #[test]
+    fn connect_refused() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = super::Socks5Proxy::new(listener.local_addr().unwrap().to_string()).with_credentials("user", "pass");
+
+            let server = self::proxy(listener, 0x05);
+            let client = proxy.connect("10.0.0.1", 1883);
+            let ((_, request), result) = futures_util::future::join(server, client).await;
+            assert_eq!(request, b"\x05\x01\x00\x01\x0a\x00\x00\x01\x07\x5b");
+            assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::ConnectionRefused);
+        });
+    }
+}
@@ -0,0 +1,76 @@
+/// A [`crate::io::Connector`] that connects to the server over plain TCP.
+///
+/// The connector follows the redirections of the server to the host and port of the server reference, like [`super::TlsConnector`].
+#[derive(Clone)]
+pub struct TcpConnector {
+    address: String,
+    proxy: Option<super::Proxy>,
+    password: Option<crate::proto::ByteStr>,
+    server_reference: Option<String>,
+}
+
+impl TcpConnector {
+    /// Creates a connector to the server at the given address, `host:port`
+    pub fn new(address: impl Into<String>) -> Self {
+        TcpConnector {
+            address: address.into(),
+            proxy: None,
+            password: None,
+            server_reference: None,
+        }
+    }
+
+    /// Tunnels the TCP connection to the server through the given proxy, a [`super::Socks5Proxy`] or an [`super::HttpProxy`].
+    /// Not set by default.
+    #[must_use]
+    pub fn with_proxy(mut self, proxy: impl Into<super::Proxy>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Sets the password of the CONNECT packet. Not set by default.
+    #[must_use]
+    pub fn with_password(mut self, password: Option<crate::proto::ByteStr>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// The server that the client was redirected to, if it is not connecting to the usual server
+    pub fn server_reference(&self) -> Option<&str> {
+        self.server_reference.as_deref()
+    }
+}
+
+impl crate::io::Connector for TcpConnector {
+    type PacketStream = super::IoStream<tokio::io::ReadHalf<tokio::net::TcpStream>>;
+    type PacketSink = super::IoSink<tokio::io::WriteHalf<tokio::net::TcpStream>>;
+    type Error = std::io::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = std::io::Result<(Self::PacketStream, Self::PacketSink, Option<crate::proto::ByteStr>)>> + Send>>;
+
+    fn connect(&mut self) -> Self::Future {
+        let address = self.server_reference.clone().unwrap_or_else(|| self.address.clone());
+        let proxy = self.proxy.clone();
+        let password = self.password.clone();
+
+        Box::pin(async move {
+            let (host, port) = super::split_host_port(&address, super::DEFAULT_TCP_PORT)?;
+            let stream = super::proxy::connect_tcp(proxy.as_ref(), host, port).await?;
+            let (stream, sink) = super::framed(stream);
+            Ok((stream, sink, password))
+        })
+    }
+
+    fn redirect(&mut self, server_reference: Option<&str>) -> bool {
+        self.server_reference = server_reference.map(ToOwned::to_owned);
+        true
+    }
+}
+
+impl std::fmt::Debug for TcpConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TcpConnector")
+            .field("address", &self.address)
+            .field("server_reference", &self.server_reference)
+            .finish_non_exhaustive()
+    }
+}
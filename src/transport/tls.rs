@@ -0,0 +1,237 @@
+/// A [`crate::io::Connector`] that connects to the server over TLS, with rustls.
+///
+/// The certificate of the server is verified against the root certificates given with [`TlsConnector::with_root_certificates_pem`]
+/// or [`TlsConnector::with_root_certificates`], and against the host of the address unless [`TlsConnector::with_server_name`] overrides it.
+/// The client authenticates with a certificate of its own, for servers that require mutual TLS, with [`TlsConnector::with_client_certificate`]
+/// or [`TlsConnector::with_client_certificate_source`]. A [`TlsConnector::with_client_config`] replaces all of these.
+///
+/// The connector follows the redirections of the server to the host and port of the server reference. The server certificate of a redirected
+/// connection is verified against the host of the server reference.
+#[derive(Clone)]
+pub struct TlsConnector {
+    address: String,
+    server_name: Option<String>,
+    root_certificates: tokio_rustls::rustls::RootCertStore,
+    client_certificate: Option<super::client_certificate::ClientCertificateSource>,
+    config: Option<std::sync::Arc<tokio_rustls::rustls::ClientConfig>>,
+    proxy: Option<super::Proxy>,
+    password: Option<crate::proto::ByteStr>,
+    server_reference: Option<String>,
+}
+
+impl TlsConnector {
+    /// Creates a connector to the server at the given address, `host:port`, that trusts no root certificates yet
+    pub fn new(address: impl Into<String>) -> Self {
+        TlsConnector {
+            address: address.into(),
+            server_name: None,
+            root_certificates: tokio_rustls::rustls::RootCertStore::empty(),
+            client_certificate: None,
+            config: None,
+            proxy: None,
+            password: None,
+            server_reference: None,
+        }
+    }
+
+    /// Trusts the root certificates of the given PEM file, such as the CA certificate that signed the certificate of the server
+    pub fn with_root_certificates_pem(mut self, pem: &[u8]) -> std::io::Result<Self> {
+        let certificates = rustls_pemfile::certs(&mut &*pem)?;
+        if certificates.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "PEM file does not contain any certificates"));
+        }
+
+        for certificate in certificates {
+            self.root_certificates.add(&tokio_rustls::rustls::Certificate(certificate))
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+        }
+
+        Ok(self)
+    }
+
+    /// Trusts the root certificates of the given store, in addition to the ones already trusted
+    #[must_use]
+    pub fn with_root_certificates(mut self, root_certificates: tokio_rustls::rustls::RootCertStore) -> Self {
+        self.root_certificates.roots.extend(root_certificates.roots);
+        self
+    }
+
+    /// Sets the name that the certificate of the server is verified against and that is sent with SNI.
+    /// Defaults to the host of the address, which is not useful when the address is an IP address but the certificate is for a DNS name.
+    #[must_use]
+    pub fn with_server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.server_name = Some(server_name.into());
+        self
+    }
+
+    /// Authenticates the client with the given certificate. The certificate must be PEM files, since rustls does not support PKCS#12 archives.
+    #[must_use]
+    pub fn with_client_certificate(self, client_certificate: super::ClientCertificate) -> Self {
+        self.with_client_certificate_source(move || Ok(client_certificate.clone()))
+    }
+
+    /// Authenticates the client with the certificate returned by `source`, which is called every time the connector connects.
+    ///
+    /// This lets a long-running client pick up a renewed certificate when it reconnects, such as with
+    /// `|| ClientCertificate::from_pem_files("client.pem", "client.key")`. If `source` fails, so does the connection,
+    /// and the client retries it like any other failed connection.
+    #[must_use]
+    pub fn with_client_certificate_source(
+        mut self,
+        source: impl Fn() -> std::io::Result<super::ClientCertificate> + Send + Sync + 'static,
+    ) -> Self {
+        self.client_certificate = Some(std::sync::Arc::new(source));
+        self
+    }
+
+    /// Sets the TLS configuration of the client, instead of the one made of the root certificates and the certificate of the client
+    #[must_use]
+    pub fn with_client_config(mut self, config: std::sync::Arc<tokio_rustls::rustls::ClientConfig>) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Tunnels the TCP connection to the server through the given proxy, a [`super::Socks5Proxy`] or an [`super::HttpProxy`].
+    /// Not set by default.
+    #[must_use]
+    pub fn with_proxy(mut self, proxy: impl Into<super::Proxy>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Sets the password of the CONNECT packet. Not set by default.
+    #[must_use]
+    pub fn with_password(mut self, password: Option<crate::proto::ByteStr>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// The server that the client was redirected to, if it is not connecting to the usual server
+    pub fn server_reference(&self) -> Option<&str> {
+        self.server_reference.as_deref()
+    }
+
+    /// The name that the certificate of the server is verified against, if it is not the host of the address
+    #[cfg(any(feature = "quic", feature = "ws"))]
+    pub(super) fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Returns the TLS configuration of the next connection. It is only built once, unless it has a certificate of the client
+    /// that may have been renewed since the last connection.
+    pub(super) fn client_config(&mut self) -> std::io::Result<std::sync::Arc<tokio_rustls::rustls::ClientConfig>> {
+        if let Some(config) = &self.config {
+            return Ok(config.clone());
+        }
+
+        let builder = tokio_rustls::rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.root_certificates.clone());
+
+        if let Some(client_certificate) = &self.client_certificate {
+            let (certificate_chain, private_key) = client_auth(client_certificate()?)?;
+            let config = builder.with_client_auth_cert(certificate_chain, private_key)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+            return Ok(std::sync::Arc::new(config));
+        }
+
+        let config = std::sync::Arc::new(builder.with_no_client_auth());
+        self.config = Some(config.clone());
+        Ok(config)
+    }
+}
+
+impl crate::io::Connector for TlsConnector {
+    type PacketStream = super::IoStream<tokio::io::ReadHalf<tokio_rustls::client::TlsStream<tokio::net::TcpStream>>>;
+    type PacketSink = super::IoSink<tokio::io::WriteHalf<tokio_rustls::client::TlsStream<tokio::net::TcpStream>>>;
+    type Error = std::io::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = std::io::Result<(Self::PacketStream, Self::PacketSink, Option<crate::proto::ByteStr>)>> + Send>>;
+
+    fn connect(&mut self) -> Self::Future {
+        let config = self.client_config();
+
+        let (address, server_name) = match &self.server_reference {
+            Some(server_reference) => (server_reference.clone(), None),
+            None => (self.address.clone(), self.server_name.clone()),
+        };
+        let proxy = self.proxy.clone();
+        let password = self.password.clone();
+
+        Box::pin(async move {
+            let config = config?;
+            let (host, port) = super::split_host_port(&address, super::DEFAULT_TLS_PORT)?;
+            let server_name = server_name.as_deref().unwrap_or(host);
+            let server_name = std::convert::TryFrom::try_from(server_name)
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+
+            let stream = super::proxy::connect_tcp(proxy.as_ref(), host, port).await?;
+            let stream = tokio_rustls::TlsConnector::from(config).connect(server_name, stream).await?;
+            let (stream, sink) = super::framed(stream);
+            Ok((stream, sink, password))
+        })
+    }
+
+    fn redirect(&mut self, server_reference: Option<&str>) -> bool {
+        self.server_reference = server_reference.map(ToOwned::to_owned);
+        true
+    }
+}
+
+impl std::fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConnector")
+            .field("address", &self.address)
+            .field("server_name", &self.server_name)
+            .field("root_certificates", &self.root_certificates.len())
+            .field("client_certificate", &self.client_certificate.is_some())
+            .field("server_reference", &self.server_reference)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Parses the certificate chain and private key of the client
+fn client_auth(client_certificate: super::ClientCertificate) -> std::io::Result<(Vec<tokio_rustls::rustls::Certificate>, tokio_rustls::rustls::PrivateKey)> {
+    match client_certificate {
+        super::ClientCertificate::Pem { certificate_chain, private_key } =>
+            super::certificate_chain_and_key_from_pem(&certificate_chain, &private_key, "client certificate"),
+
+        super::ClientCertificate::Pkcs12 { .. } =>
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "rustls does not support PKCS#12 archives, use PEM files or the NativeTlsConnector instead")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn root_certificates_pem() {
+        assert_eq!(super::TlsConnector::new("localhost:8883").with_root_certificates_pem(b"").unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn client_certificate_source() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut connector = super::TlsConnector::new("localhost:8883").with_client_certificate_source({
+            let calls = calls.clone();
+            move || {
+                calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Ok(crate::transport::ClientCertificate::Pkcs12 { archive: vec![], password: String::new() })
+            }
+        });
+
+        // The source is called for every connection, and a certificate that rustls does not support fails the connection
+        for expected_calls in 1..=2 {
+            let err = runtime.block_on(crate::io::Connector::connect(&mut connector)).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), expected_calls);
+        }
+
+        let mut connector = super::TlsConnector::new("localhost:8883").with_client_certificate(crate::transport::ClientCertificate::Pem {
+            certificate_chain: vec![],
+            private_key: vec![],
+        });
+        let err = runtime.block_on(crate::io::Connector::connect(&mut connector)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,91 @@
+/// A [`crate::io::Connector`] that connects to a server on the same host over a Unix domain socket, such as a broker
+/// of an edge gateway that listens on `/run/mqtt/broker.sock`.
+///
+/// The connector does not follow the redirections of the server, since a server reference is the address of another host.
+#[derive(Clone)]
+pub struct UnixConnector {
+    path: std::path::PathBuf,
+    password: Option<crate::proto::ByteStr>,
+}
+
+impl UnixConnector {
+    /// Creates a connector to the server that listens on the socket at the given path
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        UnixConnector {
+            path: path.into(),
+            password: None,
+        }
+    }
+
+    /// Sets the password of the CONNECT packet. Not set by default.
+    #[must_use]
+    pub fn with_password(mut self, password: Option<crate::proto::ByteStr>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// The path of the socket that the connector connects to
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+impl crate::io::Connector for UnixConnector {
+    type PacketStream = super::IoStream<tokio::io::ReadHalf<tokio::net::UnixStream>>;
+    type PacketSink = super::IoSink<tokio::io::WriteHalf<tokio::net::UnixStream>>;
+    type Error = std::io::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = std::io::Result<(Self::PacketStream, Self::PacketSink, Option<crate::proto::ByteStr>)>> + Send>>;
+
+    fn connect(&mut self) -> Self::Future {
+        let path = self.path.clone();
+        let password = self.password.clone();
+
+        Box::pin(async move {
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            let (stream, sink) = super::framed(stream);
+            Ok((stream, sink, password))
+        })
+    }
+}
+
+impl std::fmt::Debug for UnixConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnixConnector")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn connect() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        runtime.block_on(async {
+            let path = std::env::temp_dir().join(format!("mqtt3-unix-connector-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+            let mut connector = super::UnixConnector::new(&path).with_password(Some("password".parse().unwrap()));
+            assert!(!crate::io::Connector::redirect(&mut connector, Some("other.example.com")));
+
+            let server = async {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (mut stream, _) = crate::transport::framed(stream);
+                futures_util::StreamExt::next(&mut stream).await.unwrap().unwrap()
+            };
+            let client = async {
+                let (_, mut sink, password) = crate::io::Connector::connect(&mut connector).await.unwrap();
+                assert_eq!(password, Some("password".parse().unwrap()));
+                futures_util::SinkExt::send(&mut sink, crate::proto::Packet::PingReq(crate::proto::PingReq)).await.unwrap();
+            };
+            let (packet, ()) = futures_util::future::join(server, client).await;
+            assert_eq!(packet, crate::proto::Packet::PingReq(crate::proto::PingReq));
+
+            std::fs::remove_file(&path).unwrap();
+
+            // The connection fails like any other once the server is gone
+            assert!(crate::io::Connector::connect(&mut connector).await.is_err());
+        });
+    }
+}
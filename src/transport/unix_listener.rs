@@ -0,0 +1,63 @@
+/// A [`crate::io::Listener`] that accepts clients on the same host over a Unix domain socket, for a server. Pass it to the server directly,
+/// or alongside other listeners with [`crate::io::Listeners`].
+///
+/// This lets an embedded broker serve sidecars and other local processes where listening on TCP is not allowed,
+/// such as on `/run/mqtt/broker.sock`. Clients of this crate connect to it with a `UnixConnector`.
+#[derive(Debug)]
+pub struct UnixListener(tokio::net::UnixListener);
+
+impl UnixListener {
+    /// Listens for clients on a socket at the given path.
+    ///
+    /// Fails if a file already exists at the path, such as the socket of a server that did not remove it when it stopped.
+    /// The socket is not removed when the listener is dropped.
+    pub fn bind(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let listener = tokio::net::UnixListener::bind(path)?;
+        Ok(UnixListener(listener))
+    }
+
+    /// The local address that the listener is bound to
+    pub fn local_addr(&self) -> std::io::Result<tokio::net::unix::SocketAddr> {
+        self.0.local_addr()
+    }
+}
+
+impl crate::io::Listener for UnixListener {
+    type PacketStream = super::IoStream<tokio::io::ReadHalf<tokio::net::UnixStream>>;
+    type PacketSink = super::IoSink<tokio::io::WriteHalf<tokio::net::UnixStream>>;
+
+    fn poll_accept(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink)>> {
+        self.0.poll_accept(cx).map_ok(|(stream, _)| super::framed(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn accept() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        runtime.block_on(async {
+            let path = std::env::temp_dir().join(format!("mqtt3-unix-listener-{}.sock", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            let mut listener = super::UnixListener::bind(&path).unwrap();
+            assert_eq!(listener.local_addr().unwrap().as_pathname(), Some(&*path));
+
+            let server = async {
+                let (mut stream, _) = futures_util::future::poll_fn(|cx| crate::io::Listener::poll_accept(&mut listener, cx)).await.unwrap();
+                futures_util::StreamExt::next(&mut stream).await.unwrap().unwrap()
+            };
+            let client = async {
+                let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+                let (_, mut sink) = crate::transport::framed(stream);
+                futures_util::SinkExt::send(&mut sink, crate::proto::Packet::PingReq(crate::proto::PingReq)).await.unwrap();
+            };
+            let (packet, ()) = futures_util::future::join(server, client).await;
+            assert_eq!(packet, crate::proto::Packet::PingReq(crate::proto::PingReq));
+
+            // The socket of a listener that is still there cannot be bound again
+            assert!(super::UnixListener::bind(&path).is_err());
+
+            std::fs::remove_file(&path).unwrap();
+        });
+    }
+}
@@ -0,0 +1,540 @@
+/// The port of `ws://` URLs without one
+#[cfg(feature = "client")]
+const DEFAULT_WS_PORT: u16 = 80;
+
+/// The port of `wss://` URLs without one
+#[cfg(all(feature = "client", feature = "tls-rustls"))]
+const DEFAULT_WSS_PORT: u16 = 443;
+
+/// A [`crate::io::Connector`] that connects to the server with MQTT over WebSocket, for servers that are only reachable
+/// through HTTP ports such as 80 and 443.
+///
+/// The connector connects to a `ws://` URL, such as `ws://broker.example.com/mqtt`, and asks for the `mqtt` subprotocol.
+/// With the `tls-rustls` feature, it also connects to `wss://` URLs over TLS with the root certificates, client certificate
+/// and server name of the [`super::TlsConnector`] given with [`WsConnector::with_tls`], or with the configuration given with
+/// [`WsConnector::with_client_config`].
+///
+/// Brokers that authenticate the client on the upgrade request, such as with an `Authorization: Bearer` header, are given
+/// the headers of [`WsConnector::with_header`] and [`WsConnector::with_header_source`].
+///
+/// Packets are sent in binary messages. Received binary messages are reassembled into packets regardless of how the server splits them.
+/// The connector follows the redirections of the server, either to another URL or to another `host:port` with the same path.
+#[cfg(feature = "client")]
+#[derive(Clone)]
+pub struct WsConnector {
+    url: String,
+    #[cfg(feature = "tls-rustls")]
+    tls: Option<super::TlsConnector>,
+    headers: Vec<(String, HeaderSource)>,
+    proxy: Option<super::Proxy>,
+    password: Option<crate::proto::ByteStr>,
+    server_reference: Option<String>,
+}
+
+#[cfg(feature = "client")]
+impl WsConnector {
+    /// Creates a connector to the server at the given `ws://` or `wss://` URL
+    pub fn new(url: impl Into<String>) -> Self {
+        WsConnector {
+            url: url.into(),
+            #[cfg(feature = "tls-rustls")]
+            tls: None,
+            headers: vec![],
+            proxy: None,
+            password: None,
+            server_reference: None,
+        }
+    }
+
+    /// Connects to `wss://` URLs with the root certificates, client certificate and server name of the given connector.
+    /// Its address is not used. Connecting to a `wss://` URL fails without either this or [`WsConnector::with_client_config`].
+    #[cfg(feature = "tls-rustls")]
+    #[must_use]
+    pub fn with_tls(mut self, tls: super::TlsConnector) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets the TLS configuration of `wss://` URLs, such as a configuration that trusts the root certificates of the server.
+    /// Connecting to a `wss://` URL fails without either this or [`WsConnector::with_tls`].
+    #[cfg(feature = "tls-rustls")]
+    #[must_use]
+    pub fn with_client_config(self, config: std::sync::Arc<tokio_rustls::rustls::ClientConfig>) -> Self {
+        self.with_tls(super::TlsConnector::new(String::new()).with_client_config(config))
+    }
+
+    /// Adds a header to the upgrade request, such as `Authorization` with `Bearer <token>`
+    #[must_use]
+    pub fn with_header(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let value = value.into();
+        self.with_header_source(name, move || Ok(value.clone()))
+    }
+
+    /// Adds a header to the upgrade request whose value is returned by `source`, which is called every time the connector connects.
+    ///
+    /// This lets a long-running client send a renewed token when it reconnects. If `source` fails, so does the connection,
+    /// and the client retries it like any other failed connection.
+    #[must_use]
+    pub fn with_header_source(
+        mut self,
+        name: impl Into<String>,
+        source: impl Fn() -> std::io::Result<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.headers.push((name.into(), std::sync::Arc::new(source)));
+        self
+    }
+
+    /// Tunnels the TCP connection to the server through the given proxy, a [`super::Socks5Proxy`] or an [`super::HttpProxy`].
+    /// Not set by default.
+    #[must_use]
+    pub fn with_proxy(mut self, proxy: impl Into<super::Proxy>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Sets the password of the CONNECT packet. Not set by default.
+    #[must_use]
+    pub fn with_password(mut self, password: Option<crate::proto::ByteStr>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// The server that the client was redirected to, if it is not connecting to the usual server
+    pub fn server_reference(&self) -> Option<&str> {
+        self.server_reference.as_deref()
+    }
+
+    /// Returns the URL of the next connection, which is the URL of the server reference if the client was redirected
+    fn next_url(&self) -> std::io::Result<String> {
+        match &self.server_reference {
+            Some(server_reference) if server_reference.starts_with("ws://") || server_reference.starts_with("wss://") => Ok(server_reference.clone()),
+
+            Some(server_reference) => {
+                let url = Url::parse(&self.url)?;
+                Ok(format!("{}://{}{}", url.scheme, server_reference, url.path))
+            },
+
+            None => Ok(self.url.clone()),
+        }
+    }
+
+    /// Returns the headers of the next upgrade request, with the current values of their sources
+    fn upgrade_headers(&self) -> std::io::Result<Vec<(tokio_tungstenite::tungstenite::http::HeaderName, tokio_tungstenite::tungstenite::http::HeaderValue)>> {
+        self.headers.iter()
+            .map(|(name, source)| {
+                let name = tokio_tungstenite::tungstenite::http::HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+                let mut value = tokio_tungstenite::tungstenite::http::HeaderValue::from_str(&source()?)
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+                // Header values are usually credentials, which should not be logged
+                value.set_sensitive(true);
+                Ok((name, value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "client")]
+impl crate::io::Connector for WsConnector {
+    type PacketStream = WsStream;
+    type PacketSink = WsSink;
+    type Error = std::io::Error;
+    type Future = std::pin::Pin<Box<dyn std::future::Future<Output = std::io::Result<(Self::PacketStream, Self::PacketSink, Option<crate::proto::ByteStr>)>> + Send>>;
+
+    fn connect(&mut self) -> Self::Future {
+        let url = self.next_url();
+        #[cfg(feature = "tls-rustls")]
+        let (config, server_name) = match &mut self.tls {
+            Some(tls) => (
+                Some(tls.client_config()),
+                if self.server_reference.is_none() { tls.server_name().map(ToOwned::to_owned) } else { None },
+            ),
+            None => (None, None),
+        };
+        let headers = self.upgrade_headers();
+        let proxy = self.proxy.clone();
+        let password = self.password.clone();
+
+        Box::pin(async move {
+            let url = url?;
+            let headers = headers?;
+            let Url { scheme, authority, .. } = Url::parse(&url)?;
+
+            let stream = match scheme {
+                "ws" => {
+                    let (host, port) = super::split_host_port(authority, DEFAULT_WS_PORT)?;
+                    Transport::Plain(super::proxy::connect_tcp(proxy.as_ref(), host, port).await?)
+                },
+
+                #[cfg(feature = "tls-rustls")]
+                "wss" => {
+                    let config = config.ok_or_else(||
+                        std::io::Error::new(std::io::ErrorKind::InvalidInput, "wss:// URLs need the TLS configuration of the client"))??;
+                    let (host, port) = super::split_host_port(authority, DEFAULT_WSS_PORT)?;
+                    let server_name = std::convert::TryFrom::try_from(server_name.as_deref().unwrap_or(host))
+                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+
+                    let stream = super::proxy::connect_tcp(proxy.as_ref(), host, port).await?;
+                    let stream = tokio_rustls::TlsConnector::from(config).connect(server_name, stream).await?;
+                    Transport::Rustls(Box::new(stream))
+                },
+
+                _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("unsupported URL scheme {:?}", scheme))),
+            };
+
+            let mut request = tokio_tungstenite::tungstenite::client::IntoClientRequest::into_client_request(&*url)
+                .map_err(std::io::Error::other)?;
+            request.headers_mut().insert(
+                tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL,
+                tokio_tungstenite::tungstenite::http::HeaderValue::from_static("mqtt"),
+            );
+            for (name, value) in headers {
+                request.headers_mut().append(name, value);
+            }
+
+            let (ws, _) = tokio_tungstenite::client_async(request, stream).await.map_err(std::io::Error::other)?;
+            let (stream, sink) = split(ws);
+
+            Ok((stream, sink, password))
+        })
+    }
+
+    fn redirect(&mut self, server_reference: Option<&str>) -> bool {
+        self.server_reference = server_reference.map(ToOwned::to_owned);
+        true
+    }
+}
+
+#[cfg(feature = "client")]
+impl std::fmt::Debug for WsConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WsConnector")
+            .field("url", &self.url)
+            .field("headers", &self.headers.iter().map(|(name, _)| name).collect::<Vec<_>>())
+            .field("server_reference", &self.server_reference)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the value of a header of the upgrade request every time the connector connects
+#[cfg(feature = "client")]
+type HeaderSource = std::sync::Arc<dyn Fn() -> std::io::Result<String> + Send + Sync>;
+
+/// The parts of a `ws://` or `wss://` URL that the connector needs
+#[cfg(feature = "client")]
+struct Url<'a> {
+    scheme: &'a str,
+    authority: &'a str,
+    path: &'a str,
+}
+
+#[cfg(feature = "client")]
+impl<'a> Url<'a> {
+    fn parse(url: &'a str) -> std::io::Result<Self> {
+        let (scheme, rest) = url.split_once("://")
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("invalid server URL {:?}", url)))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        Ok(Url { scheme, authority, path })
+    }
+}
+
+/// The connection under the WebSocket
+pub(super) enum Transport {
+    Plain(tokio::net::TcpStream),
+    #[cfg(all(feature = "client", feature = "tls-rustls"))]
+    Rustls(Box<tokio_rustls::client::TlsStream<tokio::net::TcpStream>>),
+}
+
+impl tokio::io::AsyncRead for Transport {
+    fn poll_read(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Transport::Plain(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+            #[cfg(all(feature = "client", feature = "tls-rustls"))]
+            Transport::Rustls(stream) => std::pin::Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl tokio::io::AsyncWrite for Transport {
+    fn poll_write(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Transport::Plain(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
+            #[cfg(all(feature = "client", feature = "tls-rustls"))]
+            Transport::Rustls(stream) => std::pin::Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Transport::Plain(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+            #[cfg(all(feature = "client", feature = "tls-rustls"))]
+            Transport::Rustls(stream) => std::pin::Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Transport::Plain(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+            #[cfg(all(feature = "client", feature = "tls-rustls"))]
+            Transport::Rustls(stream) => std::pin::Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+pub(super) type WebSocket = tokio_tungstenite::WebSocketStream<Transport>;
+
+/// Splits an upgraded connection into the packets received and sent on it
+pub(super) fn split(ws: WebSocket) -> (WsStream, WsSink) {
+    let (sink, stream) = futures_util::StreamExt::split(ws);
+
+    let decoder = super::Decoder::default();
+    #[cfg(feature = "v5")]
+    let encoder = decoder.encoder();
+
+    let stream = WsStream {
+        inner: stream,
+        decoder,
+        buf: bytes::BytesMut::with_capacity(super::READ_BUFFER_SIZE),
+    };
+    let sink = WsSink {
+        inner: sink,
+        #[cfg(feature = "v5")]
+        encoder,
+        buf: bytes::BytesMut::new(),
+    };
+
+    (stream, sink)
+}
+
+/// The packets received on a connection of a `WsConnector` or a `WsListener`
+pub struct WsStream {
+    inner: futures_util::stream::SplitStream<WebSocket>,
+    decoder: super::Decoder,
+    buf: bytes::BytesMut,
+}
+
+impl futures_core::Stream for WsStream {
+    type Item = Result<crate::proto::Packet, crate::proto::DecodeError>;
+
+    fn poll_next(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        loop {
+            #[cfg(feature = "v5")]
+            let packet = this.decoder.decode(&mut this.buf)?;
+            #[cfg(not(feature = "v5"))]
+            let packet = crate::proto::decode(&mut this.decoder, &mut this.buf)?;
+
+            if let Some(packet) = packet {
+                return std::task::Poll::Ready(Some(Ok(packet)));
+            }
+
+            let message = match std::pin::Pin::new(&mut this.inner).poll_next(cx) {
+                std::task::Poll::Ready(Some(Ok(message))) => Some(message),
+                std::task::Poll::Ready(Some(Err(err))) => return std::task::Poll::Ready(Some(Err(std::io::Error::other(err).into()))),
+                std::task::Poll::Ready(None) => None,
+                std::task::Poll::Pending => return std::task::Poll::Pending,
+            };
+
+            match message {
+                Some(tokio_tungstenite::tungstenite::Message::Binary(data)) => this.buf.extend_from_slice(&data),
+
+                Some(tokio_tungstenite::tungstenite::Message::Close(_)) | None => {
+                    if !this.buf.is_empty() {
+                        return std::task::Poll::Ready(Some(Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())));
+                    }
+
+                    return std::task::Poll::Ready(None);
+                },
+
+                Some(tokio_tungstenite::tungstenite::Message::Text(_)) =>
+                    return std::task::Poll::Ready(Some(Err(
+                        std::io::Error::new(std::io::ErrorKind::InvalidData, "received a text message instead of a binary message").into(),
+                    ))),
+
+                // Pings are answered by the WebSocket itself
+                Some(
+                    tokio_tungstenite::tungstenite::Message::Ping(_) |
+                    tokio_tungstenite::tungstenite::Message::Pong(_) |
+                    tokio_tungstenite::tungstenite::Message::Frame(_)
+                ) => (),
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for WsStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WsStream")
+            .field("buffered", &self.buf.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The packets sent on a connection of a `WsConnector` or a `WsListener`.
+///
+/// Packets are buffered until the sink is flushed, and then sent in a single binary message.
+pub struct WsSink {
+    inner: futures_util::stream::SplitSink<WebSocket, tokio_tungstenite::tungstenite::Message>,
+    #[cfg(feature = "v5")]
+    encoder: crate::proto::v5::NegotiatedEncoder,
+    buf: bytes::BytesMut,
+}
+
+impl futures_sink::Sink<crate::proto::Packet> for WsSink {
+    type Error = crate::proto::EncodeError;
+
+    fn poll_ready(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        if self.buf.len() < super::WRITE_BUFFER_SIZE {
+            std::task::Poll::Ready(Ok(()))
+        }
+        else {
+            self.poll_flush(cx)
+        }
+    }
+
+    fn start_send(mut self: std::pin::Pin<&mut Self>, item: crate::proto::Packet) -> Result<(), Self::Error> {
+        let this = &mut *self;
+
+        #[cfg(feature = "v5")]
+        this.encoder.encode(item, &mut this.buf)?;
+        #[cfg(not(feature = "v5"))]
+        crate::proto::encode(item, &mut this.buf)?;
+
+        Ok(())
+    }
+
+    fn poll_flush(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        let this = &mut *self;
+
+        if !this.buf.is_empty() {
+            match std::pin::Pin::new(&mut this.inner).poll_ready(cx).map_err(std::io::Error::other)? {
+                std::task::Poll::Ready(()) => (),
+                std::task::Poll::Pending => return std::task::Poll::Pending,
+            }
+
+            let message = tokio_tungstenite::tungstenite::Message::Binary(this.buf.split().to_vec());
+            std::pin::Pin::new(&mut this.inner).start_send(message).map_err(std::io::Error::other)?;
+        }
+
+        match std::pin::Pin::new(&mut this.inner).poll_flush(cx).map_err(std::io::Error::other)? {
+            std::task::Poll::Ready(()) => std::task::Poll::Ready(Ok(())),
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+
+    fn poll_close(mut self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
+        match self.as_mut().poll_flush(cx)? {
+            std::task::Poll::Ready(()) => (),
+            std::task::Poll::Pending => return std::task::Poll::Pending,
+        }
+
+        match std::pin::Pin::new(&mut self.inner).poll_close(cx).map_err(std::io::Error::other)? {
+            std::task::Poll::Ready(()) => std::task::Poll::Ready(Ok(())),
+            std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+}
+
+impl std::fmt::Debug for WsSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WsSink")
+            .field("buffered", &self.buf.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "client"))]
+mod tests {
+    #[test]
+    fn connect() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("ws://{}/mqtt", listener.local_addr().unwrap());
+
+            let server = async {
+                let (stream, _) = listener.accept().await.unwrap();
+                // The signature of the callback is given by tungstenite, whose error response is large
+                #[allow(clippy::result_large_err)]
+                let accept = |
+                    request: &tokio_tungstenite::tungstenite::handshake::server::Request,
+                    mut response: tokio_tungstenite::tungstenite::handshake::server::Response,
+                | {
+                    assert_eq!(request.uri().path(), "/mqtt");
+                    let protocol = request.headers()[tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL].clone();
+                    assert_eq!(protocol, "mqtt");
+                    assert_eq!(request.headers()[tokio_tungstenite::tungstenite::http::header::AUTHORIZATION], "Bearer token");
+                    response.headers_mut().insert(tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL, protocol);
+                    Ok(response)
+                };
+                let mut ws = tokio_tungstenite::accept_hdr_async(stream, accept).await.unwrap();
+
+                let message = futures_util::StreamExt::next(&mut ws).await.unwrap().unwrap();
+                assert_eq!(message, tokio_tungstenite::tungstenite::Message::Binary(vec![0xc0, 0x00]));
+
+                // Two PINGRESPs, split across messages regardless of packet boundaries
+                futures_util::SinkExt::send(&mut ws, tokio_tungstenite::tungstenite::Message::Binary(vec![0xd0])).await.unwrap();
+                futures_util::SinkExt::send(&mut ws, tokio_tungstenite::tungstenite::Message::Binary(vec![0x00, 0xd0, 0x00])).await.unwrap();
+                futures_util::SinkExt::close(&mut ws).await.unwrap();
+            };
+
+            let client = async {
+                let (mut stream, mut sink, _) = crate::io::Connector::connect(&mut super::WsConnector::new(url).with_header("Authorization", "Bearer token")).await.unwrap();
+                futures_util::SinkExt::send(&mut sink, crate::proto::Packet::PingReq(crate::proto::PingReq)).await.unwrap();
+
+                let mut received = vec![];
+                while let Some(packet) = futures_util::StreamExt::next(&mut stream).await {
+                    received.push(packet.unwrap());
+                }
+                assert_eq!(received, vec![crate::proto::Packet::PingResp(crate::proto::PingResp); 2]);
+            };
+
+            futures_util::future::join(server, client).await;
+        });
+    }
+
+    #[test]
+    fn next_url() {
+        let mut connector = super::WsConnector::new("wss://broker.example.com/mqtt");
+        assert_eq!(connector.next_url().unwrap(), "wss://broker.example.com/mqtt");
+
+        crate::io::Connector::redirect(&mut connector, Some("other.example.com:8443"));
+        assert_eq!(connector.next_url().unwrap(), "wss://other.example.com:8443/mqtt");
+
+        crate::io::Connector::redirect(&mut connector, Some("ws://other.example.com/ws"));
+        assert_eq!(connector.next_url().unwrap(), "ws://other.example.com/ws");
+    }
+
+    #[test]
+    fn upgrade_headers() {
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let connector = super::WsConnector::new("ws://broker.example.com/mqtt")
+            .with_header("X-Client", "device")
+            .with_header_source("Authorization", {
+                let calls = calls.clone();
+                move || Ok(format!("Bearer {}", calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst)))
+            });
+
+        // The source is called for every connection
+        for expected_token in 0..2 {
+            let headers = connector.upgrade_headers().unwrap();
+            assert_eq!(headers.len(), 2);
+            assert_eq!(headers[0].0, "x-client");
+            assert_eq!(headers[0].1, "device");
+            assert_eq!(headers[1].0, "authorization");
+            assert_eq!(headers[1].1, format!("Bearer {}", expected_token).as_str());
+            assert!(headers[1].1.is_sensitive());
+        }
+
+        let connector = super::WsConnector::new("ws://broker.example.com/mqtt").with_header("Invalid header", "");
+        assert_eq!(connector.upgrade_headers().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+        let connector = super::WsConnector::new("ws://broker.example.com/mqtt")
+            .with_header_source("Authorization", || Err(std::io::ErrorKind::NotFound.into()));
+        assert_eq!(connector.upgrade_headers().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,186 @@
+/// A [`crate::io::Listener`] that accepts clients with MQTT over WebSocket, for a server, such as browsers and clients that connect
+/// with a `WsConnector`. Pass it to the server directly, or alongside other listeners with [`crate::io::Listeners`] or
+/// [`crate::server::BrokerBuilder`].
+///
+/// Upgrade requests to any path are accepted if they ask for the `mqtt` subprotocol, and rejected with `400 Bad Request` otherwise.
+/// Packets are sent in binary messages, and received binary messages are reassembled into packets regardless of how the client splits them.
+///
+/// Like the TLS handshakes of a [`super::TlsListener`], upgrades happen concurrently, and a client whose upgrade fails or times out
+/// is returned as an error from the accept. While [`WsListener::with_max_handshakes`] upgrades are in progress,
+/// new clients wait in the backlog of the TCP listener.
+pub struct WsListener {
+    listener: tokio::net::TcpListener,
+    handshake_timeout: std::time::Duration,
+    max_handshakes: usize,
+    handshakes: futures_util::stream::FuturesUnordered<tokio::time::Timeout<Handshake>>,
+}
+
+type Handshake = std::pin::Pin<Box<dyn std::future::Future<Output = Result<super::ws::WebSocket, tokio_tungstenite::tungstenite::Error>>>>;
+
+impl WsListener {
+    /// Listens for clients at the given address
+    pub async fn bind(addr: impl tokio::net::ToSocketAddrs) -> std::io::Result<Self> {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+
+        Ok(WsListener {
+            listener,
+            handshake_timeout: std::time::Duration::from_secs(10),
+            max_handshakes: 64,
+            handshakes: Default::default(),
+        })
+    }
+
+    /// Sets how long a client has to finish its upgrade request. Defaults to 10 seconds.
+    #[must_use]
+    pub fn with_handshake_timeout(mut self, handshake_timeout: std::time::Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Sets how many upgrades can be in progress at once. Defaults to 64.
+    #[must_use]
+    pub fn with_max_handshakes(mut self, max_handshakes: usize) -> Self {
+        self.max_handshakes = max_handshakes;
+        self
+    }
+
+    /// The local address that the listener is bound to
+    pub fn local_addr(&self) -> std::io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+impl crate::io::Listener for WsListener {
+    type PacketStream = super::WsStream;
+    type PacketSink = super::WsSink;
+
+    fn poll_accept(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<(Self::PacketStream, Self::PacketSink)>> {
+        // The listener is not polled while there are too many upgrades. The upgrades wake the task when they finish.
+        while self.handshakes.len() < self.max_handshakes {
+            match self.listener.poll_accept(cx) {
+                std::task::Poll::Ready(Ok((stream, _))) => {
+                    let handshake: Handshake = Box::pin(tokio_tungstenite::accept_hdr_async(super::ws::Transport::Plain(stream), select_mqtt_protocol));
+                    self.handshakes.push(tokio::time::timeout(self.handshake_timeout, handshake));
+                },
+                std::task::Poll::Ready(Err(err)) => return std::task::Poll::Ready(Err(err)),
+                std::task::Poll::Pending => break,
+            }
+        }
+
+        match futures_util::StreamExt::poll_next_unpin(&mut self.handshakes, cx) {
+            std::task::Poll::Ready(Some(Ok(result))) => std::task::Poll::Ready(result.map(super::ws::split).map_err(std::io::Error::other)),
+            std::task::Poll::Ready(Some(Err(_))) =>
+                std::task::Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "WebSocket upgrade timed out"))),
+            std::task::Poll::Ready(None) | std::task::Poll::Pending => std::task::Poll::Pending,
+        }
+    }
+}
+
+impl std::fmt::Debug for WsListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WsListener")
+            .field("listener", &self.listener)
+            .field("handshake_timeout", &self.handshake_timeout)
+            .field("max_handshakes", &self.max_handshakes)
+            .field("handshakes", &self.handshakes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Accepts upgrade requests that ask for the `mqtt` subprotocol, and tells the client that it was selected
+#[allow(clippy::result_large_err)] // The error type is tungstenite's, which is an entire HTTP response
+fn select_mqtt_protocol(
+    request: &tokio_tungstenite::tungstenite::handshake::server::Request,
+    mut response: tokio_tungstenite::tungstenite::handshake::server::Response,
+) -> Result<tokio_tungstenite::tungstenite::handshake::server::Response, tokio_tungstenite::tungstenite::handshake::server::ErrorResponse> {
+    let asks_for_mqtt =
+        request.headers().get_all(tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == "mqtt");
+    if !asks_for_mqtt {
+        let mut response = tokio_tungstenite::tungstenite::handshake::server::ErrorResponse::new(Some("the mqtt subprotocol is required".to_owned()));
+        *response.status_mut() = tokio_tungstenite::tungstenite::http::StatusCode::BAD_REQUEST;
+        return Err(response);
+    }
+
+    response.headers_mut().insert(
+        tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL,
+        tokio_tungstenite::tungstenite::http::HeaderValue::from_static("mqtt"),
+    );
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    /// Connects to the listener and asks for the given subprotocols, if any
+    async fn connect(
+        addr: std::net::SocketAddr,
+        protocols: Option<&'static str>,
+    ) -> Result<tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>, tokio_tungstenite::tungstenite::Error> {
+        let mut request =
+            tokio_tungstenite::tungstenite::client::IntoClientRequest::into_client_request(format!("ws://{}/mqtt", addr)).unwrap();
+        if let Some(protocols) = protocols {
+            request.headers_mut().insert(
+                tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL,
+                tokio_tungstenite::tungstenite::http::HeaderValue::from_static(protocols),
+            );
+        }
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (ws, response) = tokio_tungstenite::client_async(request, stream).await?;
+        assert_eq!(response.headers()[tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL], "mqtt");
+        Ok(ws)
+    }
+
+    #[test]
+    fn accept() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let mut listener = super::WsListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+
+            let accept = futures_util::future::poll_fn(|cx| crate::io::Listener::poll_accept(&mut listener, cx));
+            let (client, accepted) = futures_util::future::join(connect(addr, Some("mqttv3.1, mqtt")), accept).await;
+            let mut client = client.unwrap();
+            let (mut server_stream, mut server_sink) = accepted.unwrap();
+
+            // A PINGREQ, split across messages regardless of packet boundaries
+            futures_util::SinkExt::send(&mut client, tokio_tungstenite::tungstenite::Message::Binary(vec![0xc0])).await.unwrap();
+            futures_util::SinkExt::send(&mut client, tokio_tungstenite::tungstenite::Message::Binary(vec![0x00])).await.unwrap();
+            let packet = futures_util::StreamExt::next(&mut server_stream).await.unwrap().unwrap();
+            assert_eq!(packet, crate::proto::Packet::PingReq(crate::proto::PingReq));
+
+            futures_util::SinkExt::send(&mut server_sink, crate::proto::Packet::PingResp(crate::proto::PingResp)).await.unwrap();
+            let message = futures_util::StreamExt::next(&mut client).await.unwrap().unwrap();
+            assert_eq!(message, tokio_tungstenite::tungstenite::Message::Binary(vec![0xd0, 0x00]));
+
+            // Clients that don't ask for the mqtt subprotocol are rejected
+            let accept = futures_util::future::poll_fn(|cx| crate::io::Listener::poll_accept(&mut listener, cx));
+            let (client, accepted) = futures_util::future::join(connect(addr, None), accept).await;
+            match client {
+                Err(tokio_tungstenite::tungstenite::Error::Http(response)) =>
+                    assert_eq!(response.status(), tokio_tungstenite::tungstenite::http::StatusCode::BAD_REQUEST),
+                result => panic!("expected the upgrade to be rejected, got {:?}", result.map(drop)),
+            }
+            assert!(accepted.is_err());
+        });
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let mut listener =
+                super::WsListener::bind("127.0.0.1:0").await.unwrap()
+                .with_handshake_timeout(std::time::Duration::from_millis(100));
+            let addr = listener.local_addr().unwrap();
+
+            // A client that never sends its upgrade request
+            let _stalled_client = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+            let accepted = futures_util::future::poll_fn(|cx| crate::io::Listener::poll_accept(&mut listener, cx)).await;
+            assert_eq!(accepted.err().unwrap().kind(), std::io::ErrorKind::TimedOut);
+        });
+    }
+}
@@ -0,0 +1,91 @@
+/*!
+ * Helpers for the command-line options and connections of binaries that use this crate, such as the examples.
+ *
+ * The parsers can be used with `structopt` and `clap` as `parse(try_from_str = mqtt3::util::qos_from_str)`.
+ */
+
+/// Parses a QoS from its number or its name, such as `1` or `AtLeastOnce`
+#[allow(clippy::doc_markdown)]
+pub fn qos_from_str(s: &str) -> Result<crate::proto::QoS, ParseError> {
+    match s {
+        "0" | "AtMostOnce" => Ok(crate::proto::QoS::AtMostOnce),
+        "1" | "AtLeastOnce" => Ok(crate::proto::QoS::AtLeastOnce),
+        "2" | "ExactlyOnce" => Ok(crate::proto::QoS::ExactlyOnce),
+        s => Err(ParseError {
+            input: s.to_owned(),
+            expected: "one of 0, 1, 2, AtMostOnce, AtLeastOnce, ExactlyOnce",
+        }),
+    }
+}
+
+/// Parses a duration from a whole number of seconds, such as `30`
+pub fn duration_from_secs_str(s: &str) -> Result<std::time::Duration, ParseError> {
+    let secs = s.parse().map_err(|_| ParseError { input: s.to_owned(), expected: "a whole number of seconds" })?;
+    Ok(std::time::Duration::from_secs(secs))
+}
+
+/// Parses a duration from a whole number of milliseconds, such as `500`
+pub fn duration_from_millis_str(s: &str) -> Result<std::time::Duration, ParseError> {
+    let millis = s.parse().map_err(|_| ParseError { input: s.to_owned(), expected: "a whole number of milliseconds" })?;
+    Ok(std::time::Duration::from_millis(millis))
+}
+
+/// Connects to the server at the given address over plain TCP, and logs the packets of the connection with [`crate::io::logging`].
+///
+/// This is the connection of a [`crate::Client`] that only needs TCP, such as the one of the examples.
+/// Use [`crate::transport::TcpConnector`] instead to follow the redirections of the server.
+#[cfg(all(feature = "client", feature = "transport-tokio"))]
+pub async fn connect(address: impl tokio::net::ToSocketAddrs) -> std::io::Result<(impl crate::io::PacketStream, impl crate::io::PacketSink)> {
+    let stream = tokio::net::TcpStream::connect(address).await?;
+    let (stream, sink) = crate::transport::framed(stream);
+    Ok(crate::io::logging(stream, sink))
+}
+
+/// The error of parsing a command-line option that is not valid
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    input: String,
+    expected: &'static str,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unrecognized value {:?}: must be {}", self.input, self.expected)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn qos_from_str() {
+        for &(s, expected) in &[
+            ("0", crate::proto::QoS::AtMostOnce),
+            ("AtMostOnce", crate::proto::QoS::AtMostOnce),
+            ("1", crate::proto::QoS::AtLeastOnce),
+            ("AtLeastOnce", crate::proto::QoS::AtLeastOnce),
+            ("2", crate::proto::QoS::ExactlyOnce),
+            ("ExactlyOnce", crate::proto::QoS::ExactlyOnce),
+        ] {
+            assert_eq!(super::qos_from_str(s).unwrap(), expected);
+        }
+
+        for s in &["", "3", "atleastonce", " 1"] {
+            let err = super::qos_from_str(s).unwrap_err();
+            assert_eq!(err.to_string(), format!("unrecognized value {:?}: must be one of 0, 1, 2, AtMostOnce, AtLeastOnce, ExactlyOnce", s));
+        }
+    }
+
+    #[test]
+    fn duration_from_str() {
+        assert_eq!(super::duration_from_secs_str("30").unwrap(), std::time::Duration::from_secs(30));
+        assert_eq!(super::duration_from_millis_str("500").unwrap(), std::time::Duration::from_millis(500));
+        assert_eq!(super::duration_from_secs_str("0").unwrap(), std::time::Duration::from_secs(0));
+
+        for s in &["", "-1", "1.5", "30s"] {
+            assert!(super::duration_from_secs_str(s).is_err());
+            assert!(super::duration_from_millis_str(s).is_err());
+        }
+    }
+}
@@ -14,11 +14,26 @@ async fn server_publishes_at_most_once() {
                 keep_alive: std::time::Duration::from_secs(4),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                session_expiry: Some(std::time::Duration::from_secs(0)),
+                will_delay: None,
+                maximum_packet_size: None,
+                authentication_method: None,
+                authentication_data: None,
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
             session_present: false,
             return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+            authentication_data: None,
+            receive_maximum: None,
+            maximum_packet_size: None,
+            server_reference: None,
+            assigned_client_id: None,
+            server_keep_alive: None,
+            maximum_qos: None,
+            retain_available: None,
+            reason_string: None,
+            user_properties: vec![],
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
             mqtt3::proto::Subscribe {
@@ -26,6 +41,11 @@ async fn server_publishes_at_most_once() {
                 subscribe_to: vec![mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_owned(),
                     qos: mqtt3::proto::QoS::AtMostOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }],
             },
         )),
@@ -42,6 +62,12 @@ async fn server_publishes_at_most_once() {
             retain: false,
             topic_name: "topic1".to_owned(),
             payload: [0x01, 0x02, 0x03][..].into(),
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(mqtt3::proto::PingReq)),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::PingResp(mqtt3::proto::PingResp)),
@@ -59,6 +85,11 @@ async fn server_publishes_at_most_once() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic1".to_owned(),
             qos: mqtt3::proto::QoS::AtMostOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
 
@@ -72,6 +103,11 @@ async fn server_publishes_at_most_once() {
                 mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_owned(),
                     qos: mqtt3::proto::QoS::AtMostOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 },
             )]),
             mqtt3::Event::Publication(mqtt3::ReceivedPublication {
@@ -80,6 +116,10 @@ async fn server_publishes_at_most_once() {
                 qos: mqtt3::proto::QoS::AtMostOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                user_properties: vec![],
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
             }),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::ServerClosedConnection),
         ],
@@ -101,11 +141,26 @@ async fn server_publishes_at_least_once() {
                 keep_alive: std::time::Duration::from_secs(4),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                session_expiry: Some(std::time::Duration::from_secs(0)),
+                will_delay: None,
+                maximum_packet_size: None,
+                authentication_method: None,
+                authentication_data: None,
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
             session_present: false,
             return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+            authentication_data: None,
+            receive_maximum: None,
+            maximum_packet_size: None,
+            server_reference: None,
+            assigned_client_id: None,
+            server_keep_alive: None,
+            maximum_qos: None,
+            retain_available: None,
+            reason_string: None,
+            user_properties: vec![],
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
             mqtt3::proto::Subscribe {
@@ -113,6 +168,11 @@ async fn server_publishes_at_least_once() {
                 subscribe_to: vec![mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_owned(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }],
             },
         )),
@@ -132,6 +192,12 @@ async fn server_publishes_at_least_once() {
             retain: false,
             topic_name: "topic1".to_owned(),
             payload: [0x01, 0x02, 0x03][..].into(),
+            user_properties: vec![],
+            subscription_identifiers: vec![],
+            response_topic: None,
+            correlation_data: None,
+            message_expiry: None,
+            content_type: None,
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PubAck(mqtt3::proto::PubAck {
             packet_identifier: mqtt3::proto::PacketIdentifier::new(2).unwrap(),
@@ -152,6 +218,11 @@ async fn server_publishes_at_least_once() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic1".to_owned(),
             qos: mqtt3::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
 
@@ -165,6 +236,11 @@ async fn server_publishes_at_least_once() {
                 mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_owned(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 },
             )]),
             mqtt3::Event::Publication(mqtt3::ReceivedPublication {
@@ -173,6 +249,10 @@ async fn server_publishes_at_least_once() {
                 qos: mqtt3::proto::QoS::AtLeastOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                user_properties: vec![],
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
             }),
         ],
     );
@@ -194,12 +274,27 @@ async fn server_publishes_at_least_once_with_reconnect_before_publish() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
         ],
@@ -215,12 +310,27 @@ async fn server_publishes_at_least_once_with_reconnect_before_publish() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -229,6 +339,11 @@ async fn server_publishes_at_least_once_with_reconnect_before_publish() {
                     subscribe_to: vec![mqtt3::proto::SubscribeTo {
                         topic_filter: "topic1".to_owned(),
                         qos: mqtt3::proto::QoS::AtLeastOnce,
+                        user_properties: vec![],
+                        subscription_identifier: None,
+                        no_local: false,
+                        retain_as_published: false,
+                        retain_handling: Default::default(),
                     }],
                 },
             )),
@@ -251,12 +366,27 @@ async fn server_publishes_at_least_once_with_reconnect_before_publish() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -274,6 +404,12 @@ async fn server_publishes_at_least_once_with_reconnect_before_publish() {
                     retain: false,
                     topic_name: "topic1".to_owned(),
                     payload: [0x01, 0x02, 0x03][..].into(),
+                    user_properties: vec![],
+                    subscription_identifiers: vec![],
+                    response_topic: None,
+                    correlation_data: None,
+                    message_expiry: None,
+                    content_type: None,
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PubAck(
@@ -302,6 +438,11 @@ async fn server_publishes_at_least_once_with_reconnect_before_publish() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic1".to_owned(),
             qos: mqtt3::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
 
@@ -319,6 +460,11 @@ async fn server_publishes_at_least_once_with_reconnect_before_publish() {
                 mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_owned(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 },
             )]),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::ServerClosedConnection),
@@ -331,6 +477,10 @@ async fn server_publishes_at_least_once_with_reconnect_before_publish() {
                 qos: mqtt3::proto::QoS::AtLeastOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                user_properties: vec![],
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
             }),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::ServerClosedConnection),
         ],
@@ -353,12 +503,27 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
         ],
@@ -374,12 +539,27 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -388,6 +568,11 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     subscribe_to: vec![mqtt3::proto::SubscribeTo {
                         topic_filter: "topic1".to_owned(),
                         qos: mqtt3::proto::QoS::AtLeastOnce,
+                        user_properties: vec![],
+                        subscription_identifier: None,
+                        no_local: false,
+                        retain_as_published: false,
+                        retain_handling: Default::default(),
                     }],
                 },
             )),
@@ -406,6 +591,12 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     retain: false,
                     topic_name: "topic1".to_owned(),
                     payload: [0x01, 0x02, 0x03][..].into(),
+                    user_properties: vec![],
+                    subscription_identifiers: vec![],
+                    response_topic: None,
+                    correlation_data: None,
+                    message_expiry: None,
+                    content_type: None,
                 },
             )),
         ],
@@ -421,12 +612,27 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -444,6 +650,12 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
                     retain: false,
                     topic_name: "topic1".to_owned(),
                     payload: [0x01, 0x02, 0x03][..].into(),
+                    user_properties: vec![],
+                    subscription_identifiers: vec![],
+                    response_topic: None,
+                    correlation_data: None,
+                    message_expiry: None,
+                    content_type: None,
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PubAck(
@@ -472,6 +684,11 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic1".to_owned(),
             qos: mqtt3::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
 
@@ -489,6 +706,11 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
                 mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_owned(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 },
             )]),
             mqtt3::Event::Publication(mqtt3::ReceivedPublication {
@@ -497,6 +719,10 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
                 qos: mqtt3::proto::QoS::AtLeastOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                user_properties: vec![],
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
             }),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::Io(std::io::Error::new(
                 std::io::ErrorKind::WriteZero,
@@ -511,6 +737,10 @@ async fn server_publishes_at_least_once_with_reconnect_before_ack() {
                 qos: mqtt3::proto::QoS::AtLeastOnce,
                 retain: false,
                 payload: [0x01, 0x02, 0x03][..].into(),
+                user_properties: vec![],
+                subscription_identifiers: vec![],
+                response_topic: None,
+                correlation_data: None,
             }),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::ServerClosedConnection),
         ],
@@ -532,11 +762,26 @@ async fn should_reject_invalid_publications() {
                 keep_alive: std::time::Duration::from_secs(4),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                session_expiry: Some(std::time::Duration::from_secs(0)),
+                will_delay: None,
+                maximum_packet_size: None,
+                authentication_method: None,
+                authentication_data: None,
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
             session_present: false,
             return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+            authentication_data: None,
+            receive_maximum: None,
+            maximum_packet_size: None,
+            server_reference: None,
+            assigned_client_id: None,
+            server_keep_alive: None,
+            maximum_qos: None,
+            retain_available: None,
+            reason_string: None,
+            user_properties: vec![],
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(mqtt3::proto::PingReq)),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::PingResp(mqtt3::proto::PingResp)),
@@ -560,6 +805,11 @@ async fn should_reject_invalid_publications() {
         qos: mqtt3::proto::QoS::AtMostOnce,
         retain: false,
         payload: Default::default(),
+        user_properties: vec![],
+        response_topic: None,
+        correlation_data: None,
+        message_expiry: None,
+        content_type: None,
     });
 
     common::verify_client_events(
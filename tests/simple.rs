@@ -13,12 +13,27 @@ async fn server_generated_id_can_connect_and_idle() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: Some(std::time::Duration::from_secs(0)),
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -44,12 +59,27 @@ async fn server_generated_id_can_connect_and_idle() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: Some(std::time::Duration::from_secs(0)),
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -109,12 +139,27 @@ async fn client_id_can_connect_and_idle() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -142,6 +187,11 @@ async fn client_id_can_connect_and_idle() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
@@ -151,6 +201,16 @@ async fn client_id_can_connect_and_idle() {
                     // So this second session will still have `session_present == false`
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -178,12 +238,27 @@ async fn client_id_can_connect_and_idle() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -13,12 +13,27 @@ async fn server_generated_id_must_always_resubscribe() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: Some(std::time::Duration::from_secs(0)),
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -28,14 +43,29 @@ async fn server_generated_id_must_always_resubscribe() {
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic1".to_string(),
                             qos: mqtt3::proto::QoS::AtMostOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic2".to_string(),
                             qos: mqtt3::proto::QoS::AtLeastOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic3".to_string(),
                             qos: mqtt3::proto::QoS::ExactlyOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                     ],
                 },
@@ -65,12 +95,27 @@ async fn server_generated_id_must_always_resubscribe() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: Some(std::time::Duration::from_secs(0)),
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -80,14 +125,29 @@ async fn server_generated_id_must_always_resubscribe() {
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic1".to_string(),
                             qos: mqtt3::proto::QoS::AtMostOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic2".to_string(),
                             qos: mqtt3::proto::QoS::AtLeastOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic3".to_string(),
                             qos: mqtt3::proto::QoS::ExactlyOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                     ],
                 },
@@ -121,18 +181,33 @@ async fn server_generated_id_must_always_resubscribe() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic1".to_string(),
             qos: mqtt3::proto::QoS::AtMostOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
     client
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic2".to_string(),
             qos: mqtt3::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
     client
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic3".to_string(),
             qos: mqtt3::proto::QoS::ExactlyOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
 
@@ -146,14 +221,29 @@ async fn server_generated_id_must_always_resubscribe() {
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_string(),
                     qos: mqtt3::proto::QoS::AtMostOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic2".to_string(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic3".to_string(),
                     qos: mqtt3::proto::QoS::ExactlyOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
             ]),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::ServerClosedConnection),
@@ -164,14 +254,29 @@ async fn server_generated_id_must_always_resubscribe() {
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_string(),
                     qos: mqtt3::proto::QoS::AtMostOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic2".to_string(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic3".to_string(),
                     qos: mqtt3::proto::QoS::ExactlyOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
             ]),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::ServerClosedConnection),
@@ -182,14 +287,29 @@ async fn server_generated_id_must_always_resubscribe() {
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_string(),
                     qos: mqtt3::proto::QoS::AtMostOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic2".to_string(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic3".to_string(),
                     qos: mqtt3::proto::QoS::ExactlyOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
             ]),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::ServerClosedConnection),
@@ -215,12 +335,27 @@ async fn client_id_should_not_resubscribe_when_session_is_present() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -230,14 +365,29 @@ async fn client_id_should_not_resubscribe_when_session_is_present() {
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic1".to_string(),
                             qos: mqtt3::proto::QoS::AtMostOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic2".to_string(),
                             qos: mqtt3::proto::QoS::AtLeastOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic3".to_string(),
                             qos: mqtt3::proto::QoS::ExactlyOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                     ],
                 },
@@ -269,6 +419,11 @@ async fn client_id_should_not_resubscribe_when_session_is_present() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
@@ -278,6 +433,16 @@ async fn client_id_should_not_resubscribe_when_session_is_present() {
                     // So this second session will still have `session_present == false`
                     session_present: false,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
@@ -287,14 +452,29 @@ async fn client_id_should_not_resubscribe_when_session_is_present() {
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic1".to_string(),
                             qos: mqtt3::proto::QoS::AtMostOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic2".to_string(),
                             qos: mqtt3::proto::QoS::AtLeastOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                         mqtt3::proto::SubscribeTo {
                             topic_filter: "topic3".to_string(),
                             qos: mqtt3::proto::QoS::ExactlyOnce,
+                            user_properties: vec![],
+                            subscription_identifier: None,
+                            no_local: false,
+                            retain_as_published: false,
+                            retain_handling: Default::default(),
                         },
                     ],
                 },
@@ -326,12 +506,27 @@ async fn client_id_should_not_resubscribe_when_session_is_present() {
                     keep_alive: std::time::Duration::from_secs(4),
                     protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                     protocol_level: mqtt3::PROTOCOL_LEVEL,
+                    session_expiry: None,
+                    will_delay: None,
+                    maximum_packet_size: None,
+                    authentication_method: None,
+                    authentication_data: None,
                 },
             )),
             common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(
                 mqtt3::proto::ConnAck {
                     session_present: true,
                     return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+                    authentication_data: None,
+                    receive_maximum: None,
+                    maximum_packet_size: None,
+                    server_reference: None,
+                    assigned_client_id: None,
+                    server_keep_alive: None,
+                    maximum_qos: None,
+                    retain_available: None,
+                    reason_string: None,
+                    user_properties: vec![],
                 },
             )),
             common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(
@@ -355,18 +550,33 @@ async fn client_id_should_not_resubscribe_when_session_is_present() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic1".to_string(),
             qos: mqtt3::proto::QoS::AtMostOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
     client
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic2".to_string(),
             qos: mqtt3::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
     client
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic3".to_string(),
             qos: mqtt3::proto::QoS::ExactlyOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
 
@@ -380,14 +590,29 @@ async fn client_id_should_not_resubscribe_when_session_is_present() {
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_string(),
                     qos: mqtt3::proto::QoS::AtMostOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic2".to_string(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic3".to_string(),
                     qos: mqtt3::proto::QoS::ExactlyOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
             ]),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::ServerClosedConnection),
@@ -398,14 +623,29 @@ async fn client_id_should_not_resubscribe_when_session_is_present() {
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_string(),
                     qos: mqtt3::proto::QoS::AtMostOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic2".to_string(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic3".to_string(),
                     qos: mqtt3::proto::QoS::ExactlyOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
             ]),
             mqtt3::Event::Disconnected(mqtt3::ConnectionError::ServerClosedConnection),
@@ -432,11 +672,26 @@ async fn should_combine_pending_subscription_updates() {
                 keep_alive: std::time::Duration::from_secs(4),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                session_expiry: Some(std::time::Duration::from_secs(0)),
+                will_delay: None,
+                maximum_packet_size: None,
+                authentication_method: None,
+                authentication_data: None,
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
             session_present: false,
             return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+            authentication_data: None,
+            receive_maximum: None,
+            maximum_packet_size: None,
+            server_reference: None,
+            assigned_client_id: None,
+            server_keep_alive: None,
+            maximum_qos: None,
+            retain_available: None,
+            reason_string: None,
+            user_properties: vec![],
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
             mqtt3::proto::Subscribe {
@@ -445,14 +700,29 @@ async fn should_combine_pending_subscription_updates() {
                     mqtt3::proto::SubscribeTo {
                         topic_filter: "topic1".to_string(),
                         qos: mqtt3::proto::QoS::AtLeastOnce,
+                        user_properties: vec![],
+                        subscription_identifier: None,
+                        no_local: false,
+                        retain_as_published: false,
+                        retain_handling: Default::default(),
                     },
                     mqtt3::proto::SubscribeTo {
                         topic_filter: "topic3".to_string(),
                         qos: mqtt3::proto::QoS::ExactlyOnce,
+                        user_properties: vec![],
+                        subscription_identifier: None,
+                        no_local: false,
+                        retain_as_published: false,
+                        retain_handling: Default::default(),
                     },
                     mqtt3::proto::SubscribeTo {
                         topic_filter: "topic4".to_string(),
                         qos: mqtt3::proto::QoS::ExactlyOnce,
+                        user_properties: vec![],
+                        subscription_identifier: None,
+                        no_local: false,
+                        retain_as_published: false,
+                        retain_handling: Default::default(),
                     },
                 ],
             },
@@ -473,6 +743,7 @@ async fn should_combine_pending_subscription_updates() {
         })),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::UnsubAck(mqtt3::proto::UnsubAck {
             packet_identifier: mqtt3::proto::PacketIdentifier::new(2).unwrap(),
+            results: vec![],
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::PingReq(mqtt3::proto::PingReq)),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::PingResp(mqtt3::proto::PingResp)),
@@ -490,12 +761,22 @@ async fn should_combine_pending_subscription_updates() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic1".to_string(),
             qos: mqtt3::proto::QoS::AtMostOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
     client
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic2".to_string(),
             qos: mqtt3::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
 
@@ -503,6 +784,11 @@ async fn should_combine_pending_subscription_updates() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic3".to_string(),
             qos: mqtt3::proto::QoS::ExactlyOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
     client.unsubscribe("topic4".to_string()).unwrap();
@@ -510,12 +796,22 @@ async fn should_combine_pending_subscription_updates() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic4".to_string(),
             qos: mqtt3::proto::QoS::ExactlyOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
     client
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic1".to_string(),
             qos: mqtt3::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
     client.unsubscribe("topic2".to_string()).unwrap();
@@ -531,14 +827,29 @@ async fn should_combine_pending_subscription_updates() {
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_string(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic3".to_string(),
                     qos: mqtt3::proto::QoS::ExactlyOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic4".to_string(),
                     qos: mqtt3::proto::QoS::ExactlyOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
             ]),
             mqtt3::Event::SubscriptionUpdates(vec![mqtt3::SubscriptionUpdateEvent::Unsubscribe(
@@ -564,11 +875,26 @@ async fn should_send_reject_event() {
                 keep_alive: std::time::Duration::from_secs(4),
                 protocol_name: mqtt3::PROTOCOL_NAME.to_string(),
                 protocol_level: mqtt3::PROTOCOL_LEVEL,
+                session_expiry: Some(std::time::Duration::from_secs(0)),
+                will_delay: None,
+                maximum_packet_size: None,
+                authentication_method: None,
+                authentication_data: None,
             },
         )),
         common::TestConnectionStep::Sends(mqtt3::proto::Packet::ConnAck(mqtt3::proto::ConnAck {
             session_present: false,
             return_code: mqtt3::proto::ConnectReturnCode::Accepted,
+            authentication_data: None,
+            receive_maximum: None,
+            maximum_packet_size: None,
+            server_reference: None,
+            assigned_client_id: None,
+            server_keep_alive: None,
+            maximum_qos: None,
+            retain_available: None,
+            reason_string: None,
+            user_properties: vec![],
         })),
         common::TestConnectionStep::Receives(mqtt3::proto::Packet::Subscribe(
             mqtt3::proto::Subscribe {
@@ -577,10 +903,20 @@ async fn should_send_reject_event() {
                     mqtt3::proto::SubscribeTo {
                         topic_filter: "topic1".to_string(),
                         qos: mqtt3::proto::QoS::AtLeastOnce,
+                        user_properties: vec![],
+                        subscription_identifier: None,
+                        no_local: false,
+                        retain_as_published: false,
+                        retain_handling: Default::default(),
                     },
                     mqtt3::proto::SubscribeTo {
                         topic_filter: "topic2".to_string(),
                         qos: mqtt3::proto::QoS::AtLeastOnce,
+                        user_properties: vec![],
+                        subscription_identifier: None,
+                        no_local: false,
+                        retain_as_published: false,
+                        retain_handling: Default::default(),
                     },
                 ],
             },
@@ -608,12 +944,22 @@ async fn should_send_reject_event() {
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic1".to_string(),
             qos: mqtt3::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
     client
         .subscribe(mqtt3::proto::SubscribeTo {
             topic_filter: "topic2".to_string(),
             qos: mqtt3::proto::QoS::AtLeastOnce,
+            user_properties: vec![],
+            subscription_identifier: None,
+            no_local: false,
+            retain_as_published: false,
+            retain_handling: Default::default(),
         })
         .unwrap();
 
@@ -627,10 +973,20 @@ async fn should_send_reject_event() {
                 mqtt3::SubscriptionUpdateEvent::Subscribe(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic1".to_string(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
                 mqtt3::SubscriptionUpdateEvent::RejectedByServer(mqtt3::proto::SubscribeTo {
                     topic_filter: "topic2".to_string(),
                     qos: mqtt3::proto::QoS::AtLeastOnce,
+                    user_properties: vec![],
+                    subscription_identifier: None,
+                    no_local: false,
+                    retain_as_published: false,
+                    retain_handling: Default::default(),
                 }),
             ]),
         ],
@@ -655,7 +1011,7 @@ fn should_reject_invalid_subscriptions() {
 
     let too_large_topic_filter = "a".repeat(usize::from(u16::max_value()) + 1);
 
-    match client.subscribe(mqtt3::proto::SubscribeTo { topic_filter: too_large_topic_filter.clone(), qos: mqtt3::proto::QoS::AtMostOnce }) {
+    match client.subscribe(mqtt3::proto::SubscribeTo { topic_filter: too_large_topic_filter.clone(), qos: mqtt3::proto::QoS::AtMostOnce, user_properties: vec![], subscription_identifier: None, no_local: false, retain_as_published: false, retain_handling: Default::default() }) {
         Err(mqtt3::UpdateSubscriptionError::EncodePacket(_, mqtt3::proto::EncodeError::StringTooLarge(_))) => (),
         result => panic!("expected client.subscribe() to fail with EncodePacket(StringTooLarge) but it returned {:?}", result),
     }